@@ -474,14 +474,8 @@ fn run_esp_idf_menuconfig<'a>(
     target: Option<&'a str>,
     environment: Option<&'a str>,
 ) -> Result<()> {
-    let args = if let Some(environment) = environment {
-        vec!["-t", "menuconfig", "-e", environment]
-    } else {
-        vec!["-t", "menuconfig"]
-    };
-
     if check_pio_first_project(&project) {
-        call_in_dir(project, move || pio.run_with_args(&args))
+        pio.exec_target(project, environment, "menuconfig", true)
     } else {
         let target = derive_target(project, target)?;
 
@@ -516,7 +510,7 @@ fn run_esp_idf_menuconfig<'a>(
             }
         }
 
-        call_in_dir(&project_path, move || pio.run_with_args(&args))?;
+        pio.exec_target(&project_path, environment, "menuconfig", true)?;
 
         for sdkconfig in sdkconfigs {
             let dest_sdkconfig = project_path.join(sdkconfig.file_name().unwrap());
@@ -594,8 +588,8 @@ fn run_esp_idf_monitor<'a>(
                 PathBuf::from("__platform__"),
             )
             .enable_scons_dump() // Just a trick to do an early termination of the build
-            .option(project::OPTION_TERMINATE_AFTER_DUMP, "true")
-            .option(project::OPTION_QUICK_DUMP, "true")
+            .option("env", project::OPTION_TERMINATE_AFTER_DUMP, "true")
+            .option("env", project::OPTION_QUICK_DUMP, "true")
             .generate(&resolution)?;
 
         let patch_dir = project_path.join("patches");
@@ -609,7 +603,10 @@ fn run_esp_idf_monitor<'a>(
         // For now, we need to build the project, as ther build is patching the esp32_exception_decoder filter
         // so that it supports the environment variables from below, and does proper stacktrace decoding for ESP32C3
         pio = pio.log_level(LogLevel::Quiet);
-        pio.build(&project_path, Some("release") == environment)?;
+        if let Err(err) = pio.build(&project_path, Some("release") == environment) {
+            // The build is only needed for its patching side effect
+            warn!("Building the patched project failed: {err:#}");
+        }
 
         // Need to re-generate the project again or else the filter fails with:
         // Esp32ExceptionDecoder: disabling, exception while looking for addr2line: Warning! Ignore unknown configuration option `patches` in section [env]
@@ -709,17 +706,15 @@ fn get_framework_scons_vars(
 
     builder
         .enable_scons_dump()
-        .option(project::OPTION_TERMINATE_AFTER_DUMP, "true");
+        .option("env", project::OPTION_TERMINATE_AFTER_DUMP, "true");
 
     if quick {
-        builder.option(project::OPTION_QUICK_DUMP, "true");
+        builder.option("env", project::OPTION_QUICK_DUMP, "true");
     }
 
     builder.generate(resolution)?;
 
-    pio.build(&project_path, release)?;
-
-    project::SconsVariables::from_dump(project_path)
+    project::SconsVariables::dump(pio, &project_path, release)
 }
 
 fn create_project<I, S>(
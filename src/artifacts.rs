@@ -0,0 +1,331 @@
+//! Locating the artifacts of a build from outside of build scripts.
+//!
+//! Build scripts using embuild record the artifacts they produce (like the bindings or
+//! the esp-idf) in an index file in their `OUT_DIR` (see [`record`]). Tools like xtask
+//! binaries or flashing wrappers can then find them from the cargo target directory
+//! alone with [`Artifacts::discover`], without re-implementing the path conventions of
+//! embuild.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// The name of the artifacts index file written to the `OUT_DIR` of build scripts.
+pub const ARTIFACTS_INDEX_FILE: &str = "embuild-artifacts.json";
+
+/// An artifact recorded with [`record`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ArtifactKind {
+    /// The generated (or pregenerated) rust bindings.
+    Bindings,
+    /// The directory of the esp-idf.
+    EspIdfDir,
+    /// The directory of the generated PlatformIO or the cmake project.
+    ProjectDir,
+    /// The build directory of the cmake project.
+    BuildDir,
+    /// The built firmware ELF.
+    Elf,
+    /// The built partition table.
+    PartitionTable,
+}
+
+impl ArtifactKind {
+    /// The build step recording this artifact.
+    fn producer(self) -> &'static str {
+        match self {
+            Self::Bindings => "`bindgen::run` or `bindgen::run_or_use_pregenerated`",
+            Self::EspIdfDir => "`espidf::Installer::install`",
+            Self::ProjectDir => "`pio::project::Builder::generate` or `cmake::Runner::configure`",
+            Self::BuildDir => "`cmake::Runner::configure`",
+            Self::Elf => "`pio::Pio::build` (or cargo for the crate binary)",
+            Self::PartitionTable => {
+                "`pio::Pio::build` or `cmake::Build::build` of an esp-idf project"
+            }
+        }
+    }
+}
+
+impl fmt::Display for ArtifactKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Bindings => "bindings file",
+            Self::EspIdfDir => "esp-idf directory",
+            Self::ProjectDir => "project directory",
+            Self::BuildDir => "build directory",
+            Self::Elf => "firmware ELF",
+            Self::PartitionTable => "partition table",
+        })
+    }
+}
+
+/// The contents of an [`ARTIFACTS_INDEX_FILE`].
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct Index {
+    /// The package whose build script wrote the index.
+    package: String,
+    artifacts: BTreeMap<ArtifactKind, PathBuf>,
+}
+
+/// Record `path` as the artifact `kind` of this build in the [`ARTIFACTS_INDEX_FILE`]
+/// of the `OUT_DIR`, replacing the artifact of the same kind recorded before.
+///
+/// Does nothing outside of build scripts (when `OUT_DIR` is not set).
+pub fn record(kind: ArtifactKind, path: impl AsRef<Path>) -> Result<()> {
+    let Some(out_dir) = env::var_os("OUT_DIR") else {
+        return Ok(());
+    };
+    let index_file = Path::new(&out_dir).join(ARTIFACTS_INDEX_FILE);
+
+    let mut index = read_index(&index_file).unwrap_or_default();
+    index.package = env::var("CARGO_PKG_NAME").unwrap_or_default();
+    let path = path.as_ref();
+    index.artifacts.insert(
+        kind,
+        fs::canonicalize(path).unwrap_or_else(|_| path.to_owned()),
+    );
+
+    crate::fs::write_if_changed(&index_file, serde_json::to_string_pretty(&index)?)?;
+    Ok(())
+}
+
+fn read_index(index_file: &Path) -> Result<Index> {
+    let content = fs::read(index_file)
+        .with_context(|| anyhow!("Could not read '{}'", index_file.display()))?;
+    serde_json::from_slice(&content)
+        .with_context(|| anyhow!("Invalid artifacts index '{}'", index_file.display()))
+}
+
+/// The artifacts of a build, found in a cargo target directory with
+/// [`Artifacts::discover`].
+#[derive(Clone, Debug)]
+pub struct Artifacts {
+    profile_dirs: Vec<PathBuf>,
+    crate_name: String,
+    index_files: Vec<PathBuf>,
+    artifacts: BTreeMap<ArtifactKind, PathBuf>,
+}
+
+impl Artifacts {
+    /// Find the artifacts of the build of `crate_name` with the cargo `profile` (e.g.
+    /// `debug`, `release` or a custom profile) in `target_dir`.
+    ///
+    /// The artifacts are read from the [`ARTIFACTS_INDEX_FILE`]s of all build scripts of
+    /// the host build (`<target_dir>/<profile>`) and of all cross builds
+    /// (`<target_dir>/<target>/<profile>`); if several build scripts recorded the same
+    /// kind of artifact, the most recently recorded one wins.
+    pub fn discover(target_dir: &Path, crate_name: &str, profile: &str) -> Result<Artifacts> {
+        let profile = match profile {
+            "dev" | "test" => "debug",
+            "bench" => "release",
+            profile => profile,
+        };
+
+        let mut profile_dirs = Vec::new();
+        if target_dir.join(profile).is_dir() {
+            profile_dirs.push(target_dir.join(profile));
+        }
+        for entry in fs::read_dir(target_dir).with_context(|| {
+            anyhow!(
+                "Could not read cargo target directory '{}'",
+                target_dir.display()
+            )
+        })? {
+            let dir = entry?.path().join(profile);
+            if dir.join("build").is_dir() && !profile_dirs.contains(&dir) {
+                profile_dirs.push(dir);
+            }
+        }
+        if profile_dirs.is_empty() {
+            bail!(
+                "No '{profile}' build found in the cargo target directory '{}' (expected '{0}/{profile}' or '{0}/<target>/{profile}'); build the crate first",
+                target_dir.display()
+            );
+        }
+
+        let mut index_files = Vec::new();
+        for profile_dir in &profile_dirs {
+            let Ok(entries) = fs::read_dir(profile_dir.join("build")) else {
+                continue;
+            };
+            for entry in entries {
+                let index_file = entry?.path().join("out").join(ARTIFACTS_INDEX_FILE);
+                if let Ok(modified) = fs::metadata(&index_file).and_then(|m| m.modified()) {
+                    index_files.push((modified, index_file));
+                }
+            }
+        }
+        index_files.sort();
+
+        let mut artifacts = BTreeMap::new();
+        for (_, index_file) in &index_files {
+            artifacts.extend(read_index(index_file)?.artifacts);
+        }
+
+        Ok(Self {
+            profile_dirs,
+            crate_name: crate_name.to_owned(),
+            index_files: index_files.into_iter().map(|(_, file)| file).collect(),
+            artifacts,
+        })
+    }
+
+    /// The artifacts index files found, from the oldest to the newest.
+    pub fn index_files(&self) -> &[PathBuf] {
+        &self.index_files
+    }
+
+    /// Get the recorded artifact `kind`.
+    ///
+    /// Fails if no build script recorded it, or if it doesn't exist anymore.
+    pub fn get(&self, kind: ArtifactKind) -> Result<&Path> {
+        let Some(path) = self.artifacts.get(&kind) else {
+            bail!(
+                "No {kind} was recorded in the embuild artifacts index files `{ARTIFACTS_INDEX_FILE}` ({} found in the `build/*/out` directories of {}); it is recorded by {} in a build script",
+                self.index_files.len(),
+                self.profile_dirs_display(),
+                kind.producer()
+            );
+        };
+        if !path.exists() {
+            bail!(
+                "The recorded {kind} '{}' does not exist anymore; rebuild the crate",
+                path.display()
+            );
+        }
+        Ok(path)
+    }
+
+    /// The generated rust bindings.
+    pub fn bindings_file(&self) -> Result<&Path> {
+        self.get(ArtifactKind::Bindings)
+    }
+
+    /// The directory of the esp-idf.
+    pub fn esp_idf_dir(&self) -> Result<&Path> {
+        self.get(ArtifactKind::EspIdfDir)
+    }
+
+    /// The directory of the generated PlatformIO or the cmake project.
+    pub fn project_dir(&self) -> Result<&Path> {
+        self.get(ArtifactKind::ProjectDir)
+    }
+
+    /// The built partition table.
+    pub fn partition_table(&self) -> Result<&Path> {
+        self.get(ArtifactKind::PartitionTable)
+    }
+
+    /// The firmware ELF: the recorded one (e.g. built by PlatformIO), or else the binary
+    /// of the crate built by cargo.
+    pub fn elf(&self) -> Result<PathBuf> {
+        if self.artifacts.contains_key(&ArtifactKind::Elf) {
+            return self.get(ArtifactKind::Elf).map(Path::to_owned);
+        }
+
+        let names = [
+            self.crate_name.clone(),
+            format!("{}{}", self.crate_name, env::consts::EXE_SUFFIX),
+        ];
+        self.profile_dirs
+            .iter()
+            .flat_map(|dir| names.iter().map(move |name| dir.join(name)))
+            .filter_map(|file| Some((fs::metadata(&file).ok()?.modified().ok()?, file)))
+            .max_by_key(|(modified, _): &(SystemTime, PathBuf)| *modified)
+            .map(|(_, file)| file)
+            .ok_or_else(|| {
+                anyhow!(
+                    "No firmware ELF was recorded and no binary '{}' was found in {}; it is produced by cargo when building the binary crate",
+                    self.crate_name,
+                    self.profile_dirs_display()
+                )
+            })
+    }
+
+    fn profile_dirs_display(&self) -> String {
+        self.profile_dirs
+            .iter()
+            .map(|dir| format!("'{}'", dir.display()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_discover() {
+        let tmp = tempfile::tempdir().unwrap();
+        let target_dir = tmp.path();
+        let build = |triple: &str, package: &str| {
+            let dir = target_dir.join(triple).join("debug/build").join(package);
+            fs::create_dir_all(dir.join("out")).unwrap();
+            dir.join("out")
+        };
+
+        let sys_out = build("xtensa-esp32-espidf", "esp-idf-sys-1234");
+        let app_out = build("", "app-5678");
+        fs::write(sys_out.join("bindings.rs"), "").unwrap();
+        let index = |artifacts: &[(ArtifactKind, &Path)]| Index {
+            package: "test".into(),
+            artifacts: artifacts
+                .iter()
+                .map(|(kind, path)| (*kind, path.to_path_buf()))
+                .collect(),
+        };
+        let write_index = |out: &Path, index: &Index| {
+            fs::write(
+                out.join(ARTIFACTS_INDEX_FILE),
+                serde_json::to_string(index).unwrap(),
+            )
+            .unwrap()
+        };
+        write_index(
+            &sys_out,
+            &index(&[
+                (ArtifactKind::Bindings, &sys_out.join("bindings.rs")),
+                (ArtifactKind::EspIdfDir, &sys_out),
+            ]),
+        );
+        write_index(&app_out, &index(&[(ArtifactKind::ProjectDir, &app_out)]));
+        fs::write(target_dir.join("xtensa-esp32-espidf/debug/app"), "").unwrap();
+
+        let artifacts = Artifacts::discover(target_dir, "app", "dev").unwrap();
+        assert_eq!(artifacts.index_files().len(), 2);
+        assert_eq!(
+            artifacts.bindings_file().unwrap(),
+            sys_out.join("bindings.rs")
+        );
+        assert_eq!(artifacts.esp_idf_dir().unwrap(), sys_out);
+        assert_eq!(artifacts.project_dir().unwrap(), app_out);
+        assert_eq!(
+            artifacts.elf().unwrap(),
+            target_dir.join("xtensa-esp32-espidf/debug/app")
+        );
+
+        let error = artifacts.partition_table().unwrap_err().to_string();
+        assert!(
+            error.starts_with("No partition table was recorded"),
+            "{error}"
+        );
+        assert!(error.contains("2 found"), "{error}");
+
+        fs::remove_file(sys_out.join("bindings.rs")).unwrap();
+        assert!(artifacts
+            .bindings_file()
+            .unwrap_err()
+            .to_string()
+            .contains("does not exist anymore"));
+
+        assert!(Artifacts::discover(target_dir, "app", "release").is_err());
+    }
+}
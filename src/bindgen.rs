@@ -2,20 +2,45 @@
 
 use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 use std::{env, fs};
 
 use anyhow::{anyhow, bail, Context, Error, Result};
 use serde::Deserialize;
 
+use crate::artifacts::{self, ArtifactKind};
 use crate::cargo::out_dir;
-use crate::utils::OsStrExt;
-use crate::{cargo, cmd};
+use crate::logging::{self, warning};
+use crate::utils::{path_prefix_regex, OsStrExt, PathExt, PathStyle};
+use crate::{cargo, cli, cmd};
+
+mod preflight;
+mod pregenerated;
+mod sort;
+pub use preflight::*;
+pub use pregenerated::*;
+pub use sort::*;
 
 /// The environment variable name containing the file path of the file that contains the
 /// generated bindings.
+///
+/// Build scripts of dependents can't read this variable, export the bindings file with
+/// [`DepVarsWriter::path`](crate::build::DepVarsWriter::path) and read it with
+/// [`DepVars::path`](crate::build::DepVars::path) instead.
 pub const VAR_BINDINGS_FILE: &str = "EMBUILD_GENERATED_BINDINGS_FILE";
 
-#[derive(Debug, Deserialize, Clone)]
+/// The environment variable enabling the diagnostics mode of [`run_for_file`] if set to
+/// `1`.
+///
+/// If generating the bindings fails in this mode, clang is rerun with the same args
+/// (with `-fsyntax-only`) so that its complete output is printed, and the args are
+/// written to the response file `OUT_DIR/bindgen-diag.rsp` for reproduction.
+pub const VAR_BINDGEN_DIAG: &str = "EMBUILD_BINDGEN_DIAG";
+
+/// The maximum number of clang errors included in the error of [`run_for_file`].
+const MAX_REPORTED_ERRORS: usize = 10;
+
+#[derive(Debug, Deserialize, Clone, Default)]
 pub struct Filter {
     #[serde(default)]
     pub allow_types: Option<Vec<String>>,
@@ -25,20 +50,38 @@ pub struct Filter {
 
     #[serde(default)]
     pub allow_vars: Option<Vec<String>>,
-    
+
     #[serde(default)]
     pub block_types: Option<Vec<String>>,
 
     #[serde(default)]
     pub block_functions: Option<Vec<String>>,
-    
+
     #[serde(default)]
     pub block_vars: Option<Vec<String>>,
 
     #[serde(default)]
-    pub block_files: Option<Vec<String>>
+    pub block_files: Option<Vec<String>>,
+
+    /// Generate bindings only for items from headers in these directories (or their
+    /// subdirectories), see [`Filter::block_dirs`].
+    #[serde(default)]
+    pub allow_dirs: Option<Vec<PathBuf>>,
+
+    /// Don't generate bindings for items from headers in these directories (or their
+    /// subdirectories).
+    ///
+    /// Relative directories are resolved against `CARGO_MANIFEST_DIR`, and a leading
+    /// [`SYSROOT_DIR`] component is replaced with the resolved sysroot (e.g.
+    /// `$SYSROOT/include`).
+    #[serde(default)]
+    pub block_dirs: Option<Vec<PathBuf>>,
 }
 
+/// The placeholder for the sysroot in [`Filter::allow_dirs`] and
+/// [`Filter::block_dirs`].
+pub const SYSROOT_DIR: &str = "$SYSROOT";
+
 /// A builder for creating a [`bindgen::Builder`].
 #[derive(Clone, Default, Debug)]
 #[must_use]
@@ -48,6 +91,75 @@ pub struct Factory {
     pub mcu: Option<String>,
     pub force_cpp: bool,
     pub sysroot: Option<PathBuf>,
+    pub target: ClangTarget,
+    pub layout: LayoutOptions,
+    pub preflight: Option<Preflight>,
+}
+
+/// Options for the layout of the generated unions, structs and enums, see
+/// [`Factory::with_layout`].
+///
+/// The defaults are those of bindgen.
+#[derive(Clone, Debug)]
+pub struct LayoutOptions {
+    /// How unions with non-`Copy` members are generated, see
+    /// [`bindgen::Builder::default_non_copy_union_style`].
+    pub union_style: bindgen::NonCopyUnionStyle,
+    /// Whether to generate Rust unions (instead of structs with `__BindgenUnionField`s
+    /// for all unions), see [`bindgen::Builder::disable_untagged_union`].
+    pub untagged_unions: bool,
+    /// Whether to add explicit padding fields to structs, see
+    /// [`bindgen::Builder::explicit_padding`].
+    pub explicit_padding: bool,
+    /// Regexes of the enums to generate as bitfields (newtypes implementing the bit
+    /// operators), see [`bindgen::Builder::bitfield_enum`].
+    pub bitfield_enums: Vec<String>,
+    /// Regexes of the types to generate as opaque blobs of their size and alignment,
+    /// see [`bindgen::Builder::opaque_type`].
+    pub opaque_types: Vec<String>,
+}
+
+impl Default for LayoutOptions {
+    fn default() -> Self {
+        Self {
+            union_style: bindgen::NonCopyUnionStyle::BindgenWrapper,
+            untagged_unions: true,
+            explicit_padding: false,
+            bitfield_enums: Vec::new(),
+            opaque_types: Vec::new(),
+        }
+    }
+}
+
+impl LayoutOptions {
+    fn apply(self, mut builder: bindgen::Builder) -> bindgen::Builder {
+        builder = builder
+            .default_non_copy_union_style(self.union_style)
+            .explicit_padding(self.explicit_padding);
+        if !self.untagged_unions {
+            builder = builder.disable_untagged_union();
+        }
+        for bitfield_enum in self.bitfield_enums {
+            builder = builder.bitfield_enum(bitfield_enum);
+        }
+        for opaque_type in self.opaque_types {
+            builder = builder.opaque_type(opaque_type);
+        }
+        builder
+    }
+}
+
+/// The target clang generates bindings for, see [`Factory::with_target`].
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+pub enum ClangTarget {
+    /// The target of the cargo build (the `TARGET` environment variable of build
+    /// scripts), if set.
+    #[default]
+    Cargo,
+    /// A Rust or clang target triple.
+    Triple(String),
+    /// The default target of libclang, usually the host.
+    Host,
 }
 
 impl Factory {
@@ -56,10 +168,15 @@ impl Factory {
     #[cfg(feature = "pio")]
     pub fn from_scons_vars(scons_vars: &crate::pio::project::SconsVariables) -> Result<Self> {
         use crate::cli;
-        let clang_args = cli::NativeCommandArgs::new(&scons_vars.incflags)
-            .chain(cli::NativeCommandArgs::new(
-                scons_vars.clangargs.as_deref().unwrap_or_default(),
-            ))
+        let clang_args = scons_vars
+            .defines()
+            .into_iter()
+            .map(|(name, value)| match value {
+                Some(value) => format!("-D{name}={value}"),
+                None => format!("-D{name}"),
+            })
+            .chain(cli::Syntax::NATIVE.split(&scons_vars.incflags))
+            .chain(cli::Syntax::NATIVE.split(scons_vars.clangargs.as_deref().unwrap_or_default()))
             .collect();
 
         Ok(Self {
@@ -68,9 +185,26 @@ impl Factory {
             mcu: Some(scons_vars.mcu.clone()),
             force_cpp: false,
             sysroot: None,
+            target: ClangTarget::Cargo,
+            layout: LayoutOptions::default(),
+            preflight: None,
         })
     }
 
+    /// Add the include directories of the PlatformIO library dependencies `deps` to the
+    /// clang args, unless already present.
+    #[cfg(feature = "pio")]
+    pub fn with_library_deps(mut self, deps: &crate::pio::project::LibraryDeps) -> Self {
+        for include_dir in deps.include_dirs() {
+            let arg = format!("-I{}", include_dir.display());
+            if !self.clang_args.contains(&arg) {
+                self.clang_args.push(arg);
+            }
+        }
+
+        self
+    }
+
     /// Create a new factory populating the clang args, force cpp, and sysroot from the
     /// cmake file-api compile group.
     #[cfg(feature = "cmake")]
@@ -93,6 +227,10 @@ impl Factory {
                     .iter()
                     .map(|i| format!("-I{}", &i.path)),
             )
+            .chain(compile_group.std_arg())
+            // The fragments come last so that they override the language standard (for
+            // example when they contain a `-std=gnu17` variant).
+            .chain(sanitize_clang_args(compile_group.compile_args()))
             .collect();
 
         Ok(Self {
@@ -101,6 +239,9 @@ impl Factory {
             force_cpp: compile_group.language == Language::Cpp,
             mcu: None,
             sysroot: compile_group.sysroot.as_ref().map(|s| s.path.clone()),
+            target: ClangTarget::Cargo,
+            layout: LayoutOptions::default(),
+            preflight: None,
         })
     }
 
@@ -130,6 +271,51 @@ impl Factory {
         self
     }
 
+    /// Set the target to generate bindings for, either a Rust target triple (like
+    /// `riscv32imc-esp-espidf`) or a clang target triple.
+    ///
+    /// By default the target of the cargo build is used (see [`ClangTarget::Cargo`]),
+    /// unless the clang args already contain a `--target`. Rust target triples are
+    /// mapped to the closest clang target (see [`clang_target_args`]).
+    pub fn with_target(mut self, triple: impl Into<String>) -> Self {
+        self.target = ClangTarget::Triple(triple.into());
+        self
+    }
+
+    /// Use the default target of libclang, e.g. to generate bindings for the host in
+    /// the build script of a cross-compiled crate.
+    pub fn with_host_target(mut self) -> Self {
+        self.target = ClangTarget::Host;
+        self
+    }
+
+    /// Set the options for the layout of the generated types.
+    pub fn with_layout(mut self, layout: LayoutOptions) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Run the `preflight` checks before creating the [`bindgen::Builder`], failing with
+    /// all problems found.
+    pub fn with_preflight(mut self, preflight: Preflight) -> Self {
+        self.preflight = Some(preflight);
+        self
+    }
+
+    /// Run the `preflight` checks for these settings (for C++ bindings if `cpp` is
+    /// `true`), also reporting if the sysroot cannot be determined.
+    pub fn run_preflight(&self, cpp: bool, preflight: &Preflight) -> PreflightReport {
+        let language = self.language(cpp);
+        match self.resolve_sysroot() {
+            Ok(sysroot) => preflight.check(Some(&sysroot), language),
+            Err(e) => {
+                let mut report = preflight.check(None, language);
+                report.problem(PreflightCheck::Sysroot, format!("{e:#}"));
+                report
+            }
+        }
+    }
+
     /// Create a [`bindgen::Builder`] with these settings.
     pub fn builder(self) -> Result<bindgen::Builder> {
         self.create_builder(false, None)
@@ -140,58 +326,16 @@ impl Factory {
         self.create_builder(true, None)
     }
 
-    pub fn create_builder(self, cpp: bool, filter: Option<Filter>) -> Result<bindgen::Builder> {
-        let cpp = self.force_cpp || cpp;
-        let sysroot = self
-            .sysroot
-            .clone()
-            .map_or_else(|| try_get_sysroot(&self.linker), Ok)?;
-
-        let sysroot_args = [
-            format!("--sysroot={}", sysroot.try_to_str()?),
-            format!("-I{}", sysroot.join("include").try_to_str()?),
-        ];
+    pub fn create_builder(mut self, cpp: bool, filter: Option<Filter>) -> Result<bindgen::Builder> {
+        if let Some(preflight) = self.preflight.take() {
+            let report = self.run_preflight(cpp, &preflight).into_result()?;
+            // Don't determine the sysroot from the linker again.
+            self.sysroot = report.sysroot;
+        }
 
-        let cpp_args = if cpp {
-            get_cpp_includes(&sysroot)?
-        } else {
-            vec![]
-        };
-
-        let mut builder = bindgen::Builder::default()
-            .use_core()
-            .layout_tests(false)
-            .formatter(bindgen::Formatter::None)
-            .derive_default(true)
-            .clang_arg("-D__bindgen")
-            // Include directories provided by the build system
-            // should be first on the search path (before sysroot includes),
-            // or else libc's <dirent.h> does not correctly override sysroot's <dirent.h>
-            .clang_args(&self.clang_args)
-            .clang_args(sysroot_args)
-            .clang_args(&["-x", if cpp { "c++" } else { "c" }])
-            .clang_args(cpp_args)
-            .clang_args([
-                "-DTF_LITE_STATIC_MEMORY",
-                "-DTF_LITE_DISABLE_X86_NEON",
-                "-O3",
-                "-Wstrict-aliasing",
-                "-Wno-unused-parameter",
-                "-Wall",
-                "-Wextra",
-                "-Wvla",
-                "-Wsign-compare",
-                "-Wdouble-promotion",
-                "-Wswitch",
-                "-Wunused-function",
-                "-Wmissing-field-initializers",
-                "-ffunction-sections",
-                "-fdata-sections",
-                "-Wshadow",
-                "-Wunused-variable",
-                "-fno-unwind-tables",
-                "-fmessage-length=0",
-            ]);
+        let layout = self.layout.clone();
+        let resolved = self.resolve(cpp)?;
+        let mut builder = layout.apply(resolved.builder());
 
         if let Some(filter) = filter {
             if let Some(allow_functions) = filter.allow_functions {
@@ -229,6 +373,20 @@ impl Factory {
                     builder = builder.blocklist_file(block_file);
                 }
             }
+            if let Some(allow_dirs) = filter.allow_dirs {
+                for allow_dir in allow_dirs {
+                    for regex in dir_regexes(&allow_dir, &resolved.sysroot)? {
+                        builder = builder.allowlist_file(regex);
+                    }
+                }
+            }
+            if let Some(block_dirs) = filter.block_dirs {
+                for block_dir in block_dirs {
+                    for regex in dir_regexes(&block_dir, &resolved.sysroot)? {
+                        builder = builder.blocklist_file(regex);
+                    }
+                }
+            }
         }
 
         log::debug!(
@@ -238,61 +396,324 @@ impl Factory {
 
         Ok(builder)
     }
+
+    /// Resolve the sysroot (from the linker if not set explicitly) and the complete
+    /// clang args of these settings, for creating C++ bindings if `cpp` is `true`.
+    ///
+    /// The result can be used to run clang (or a C compiler) with the same flags as
+    /// bindgen, or to create the [`bindgen::Builder`] with
+    /// [`ResolvedClangConfig::builder`].
+    pub fn resolve(self, cpp: bool) -> Result<ResolvedClangConfig> {
+        let language = self.language(cpp);
+        let sysroot = self.resolve_sysroot()?;
+
+        let sysroot_args = [
+            format!("--sysroot={}", sysroot.try_to_str_with_context("sysroot")?),
+            format!(
+                "-I{}",
+                sysroot
+                    .join("include")
+                    .try_to_str_with_context("sysroot include directory")?
+            ),
+        ];
+
+        let cpp_args = if language == Language::Cpp {
+            get_cpp_includes(&sysroot)?
+        } else {
+            vec![]
+        };
+
+        let target = match &self.target {
+            _ if self
+                .clang_args
+                .iter()
+                .any(|arg| arg.starts_with("--target") || arg == "-target") =>
+            {
+                None
+            }
+            ClangTarget::Cargo => env::var("TARGET").ok(),
+            ClangTarget::Triple(triple) => Some(triple.clone()),
+            ClangTarget::Host => None,
+        };
+        let target_args = target
+            .map(|target| target_args(&target, libclang_supports_xtensa()))
+            .unwrap_or_default();
+
+        let clang_args = ["-D__bindgen".to_owned()]
+            .into_iter()
+            // Include directories provided by the build system
+            // should be first on the search path (before sysroot includes),
+            // or else libc's <dirent.h> does not correctly override sysroot's <dirent.h>
+            .chain(self.clang_args)
+            .chain(sysroot_args)
+            .chain(["-x".to_owned(), language.clang_name().to_owned()])
+            .chain(cpp_args)
+            .chain(
+                [
+                    "-DTF_LITE_STATIC_MEMORY",
+                    "-DTF_LITE_DISABLE_X86_NEON",
+                    "-O3",
+                    "-Wstrict-aliasing",
+                    "-Wno-unused-parameter",
+                    "-Wall",
+                    "-Wextra",
+                    "-Wvla",
+                    "-Wsign-compare",
+                    "-Wdouble-promotion",
+                    "-Wswitch",
+                    "-Wunused-function",
+                    "-Wmissing-field-initializers",
+                    "-ffunction-sections",
+                    "-fdata-sections",
+                    "-Wshadow",
+                    "-Wunused-variable",
+                    "-fno-unwind-tables",
+                    "-fmessage-length=0",
+                ]
+                .map(String::from),
+            )
+            .chain(target_args)
+            .collect();
+
+        Ok(ResolvedClangConfig {
+            sysroot,
+            clang_args,
+            language,
+        })
+    }
+
+    fn language(&self, cpp: bool) -> Language {
+        if self.force_cpp || cpp {
+            Language::Cpp
+        } else {
+            Language::C
+        }
+    }
+
+    fn resolve_sysroot(&self) -> Result<PathBuf> {
+        self.sysroot
+            .clone()
+            .map_or_else(|| try_get_sysroot(&self.linker), Ok)
+    }
+}
+
+/// The language bindings are generated for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Language {
+    C,
+    Cpp,
+}
+
+impl Language {
+    /// Get the name of this language as passed to clang with `-x`.
+    pub fn clang_name(self) -> &'static str {
+        match self {
+            Self::C => "c",
+            Self::Cpp => "c++",
+        }
+    }
+}
+
+/// The settings of a [`Factory`] resolved with [`Factory::resolve`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResolvedClangConfig {
+    /// The sysroot of the toolchain.
+    pub sysroot: PathBuf,
+    /// All args passed to clang by bindgen, in order (including the sysroot, its
+    /// include directories and the language).
+    pub clang_args: Vec<String>,
+    /// The language of the bindings.
+    pub language: Language,
+}
+
+impl ResolvedClangConfig {
+    /// Create a [`bindgen::Builder`] with these settings.
+    pub fn builder(&self) -> bindgen::Builder {
+        bindgen::Builder::default()
+            .use_core()
+            .layout_tests(false)
+            .formatter(bindgen::Formatter::None)
+            .derive_default(true)
+            .clang_args(&self.clang_args)
+    }
+}
+
+impl crate::build::ManifestSource for bindgen::Bindings {
+    /// Bindgen (as reported in the header comment of the bindings, if present) and the
+    /// libclang it used.
+    fn manifest_entries(&self) -> Result<Vec<crate::build::ManifestEntry>> {
+        use crate::build::ManifestEntry;
+
+        let bindings = self.to_string();
+        let mut bindgen = ManifestEntry::new("bindgen");
+        if let Some(version) = bindings
+            .lines()
+            .next()
+            .and_then(|line| line.split("generated by rust-bindgen ").nth(1))
+        {
+            bindgen = bindgen.version(version.trim_end_matches("*/").trim());
+        }
+
+        Ok(vec![
+            bindgen,
+            ManifestEntry::new("libclang").version(bindgen::clang_version().full),
+        ])
+    }
 }
 
 /// Get the default filename for bindings and set the environment variable named
 /// [`VAR_BINDINGS_FILE`] that is available during crate compilation to that path.
 pub fn default_bindings_file() -> Result<PathBuf> {
     let bindings_file = out_dir().join("bindings.rs");
-    cargo::set_rustc_env(VAR_BINDINGS_FILE, bindings_file.try_to_str()?);
+    cargo::set_rustc_env(
+        VAR_BINDINGS_FILE,
+        bindings_file.try_to_str_with_context("bindings file")?,
+    );
     Ok(bindings_file)
 }
 
+/// A [`bindgen::Builder`] with the options of writing its bindings, accepted by
+/// [`run`], [`run_for_file`] and [`run_or_use_pregenerated`].
+///
+/// A [`bindgen::Builder`] converts into a `Generator` with the default options.
+#[derive(Clone, Debug, Default)]
+#[must_use]
+pub struct Generator {
+    builder: bindgen::Builder,
+    order: ItemOrder,
+}
+
+impl Generator {
+    /// Write the bindings of `builder` with the default options.
+    pub fn new(builder: bindgen::Builder) -> Self {
+        Self {
+            builder,
+            order: ItemOrder::default(),
+        }
+    }
+
+    /// Set the order of the items in the written bindings.
+    ///
+    /// With [`ItemOrder::Sorted`] the bindings don't change when only the order of the
+    /// items in the headers does, which keeps the diffs of committed bindings (see
+    /// [`run_or_use_pregenerated`]) reviewable.
+    pub fn item_order(mut self, order: ItemOrder) -> Self {
+        self.order = order;
+        self
+    }
+}
+
+impl From<bindgen::Builder> for Generator {
+    fn from(builder: bindgen::Builder) -> Self {
+        Self::new(builder)
+    }
+}
+
 /// Create rust bindings in [`default_bindings_file`] using [`run_for_file`].
-pub fn run(builder: bindgen::Builder) -> Result<PathBuf> {
+pub fn run(generator: impl Into<Generator>) -> Result<PathBuf> {
     let output_file = default_bindings_file()?;
-    run_for_file(builder, &output_file)?;
+    run_for_file(generator, &output_file)?;
     Ok(output_file)
 }
 
-/// Try to `cargo fmt` `file` using any of the current, `stable` and `nightly` toolchains.
-/// If all of them fail print a warning ([`cargo::print_warning`]).
-pub fn cargo_fmt_file(file: impl AsRef<Path>) {
-    let file = file.as_ref();
-    // Run rustfmt on the generated bindings separately, because custom toolchains often do not have rustfmt
-    // We try multiple rustfmt instances:
-    // - The one from the currently active toolchain
-    // - The one from stable
-    // - The one from nightly
-    if cmd!("rustfmt", file).run().is_err()
-        && cmd!("rustup", "run", "stable", "rustfmt", file)
-            .run()
-            .is_err()
-        && cmd!("rustup", "run", "nightly", "rustfmt", file)
-            .run()
-            .is_err()
-    {
-        cargo::print_warning(
-            "rustfmt not found in the current toolchain, nor in stable or nightly. \
-             The generated bindings will not be properly formatted.",
-        );
-    }
-}
+pub use crate::cargo::cargo_fmt_file;
 
 /// Create rust bindings in `output_file` and run `cargo fmt` over that file.
-pub fn run_for_file(builder: bindgen::Builder, output_file: impl AsRef<Path>) -> Result<()> {
+///
+/// `generator` is a [`bindgen::Builder`] or a [`Generator`] with the options of
+/// writing the bindings (e.g. their [`ItemOrder`]).
+pub fn run_for_file(generator: impl Into<Generator>, output_file: impl AsRef<Path>) -> Result<()> {
+    let Generator { builder, order } = generator.into();
     let output_file = output_file.as_ref();
 
-    eprintln!("Output: {output_file:?}");
-    eprintln!("Bindgen builder flags: {:?}", builder.command_line_flags());
+    let flags = builder.command_line_flags();
+    let step = logging::scoped_step(format!("Generating bindings '{}'", output_file.display()));
+    log::debug!("Bindgen builder flags: {flags:?}");
 
-    let bindings = builder
-        .generate()
-        .map_err(|_| Error::msg("Failed to generate bindings"))?;
+    let bindings = match builder.generate() {
+        Ok(bindings) => bindings,
+        Err(error) => {
+            let diagnostics = env::var(VAR_BINDGEN_DIAG).is_ok_and(|v| v == "1");
+            if diagnostics {
+                let dir = env::var_os("OUT_DIR").map_or_else(env::temp_dir, PathBuf::from);
+                if let Err(e) = run_clang_diagnostics(&flags, &dir.join("bindgen-diag.rsp")) {
+                    warning!("Could not rerun clang for diagnostics: {e:#}");
+                }
+            }
+            return Err(crate::diag::record_error(generate_error(
+                &error,
+                !diagnostics,
+            )));
+        }
+    };
 
-    bindings.write_to_file(output_file)?;
+    match order {
+        ItemOrder::Bindgen => bindings.write_to_file(output_file)?,
+        ItemOrder::Sorted => fs::write(output_file, sort_bindings(&bindings.to_string())?)?,
+    }
     cargo_fmt_file(output_file);
 
+    artifacts::record(ArtifactKind::Bindings, output_file)?;
+
+    step.finish();
+    Ok(())
+}
+
+/// Create the error of a failed [`bindgen::Builder::generate`], including the first
+/// [`MAX_REPORTED_ERRORS`] clang errors, and a hint at [`VAR_BINDGEN_DIAG`] if `hint`.
+fn generate_error(error: &bindgen::BindgenError, hint: bool) -> Error {
+    let bindgen::BindgenError::ClangDiagnostic(message) = error else {
+        return anyhow!("Failed to generate bindings: {error}");
+    };
+
+    let errors = message
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .collect::<Vec<_>>();
+    let mut msg = format!(
+        "Failed to generate bindings, clang reported {} error(s):",
+        errors.len()
+    );
+    for error in errors.iter().take(MAX_REPORTED_ERRORS) {
+        msg.push_str("\n  ");
+        msg.push_str(error);
+    }
+    if errors.len() > MAX_REPORTED_ERRORS {
+        msg.push_str(&format!(
+            "\n  ... and {} more",
+            errors.len() - MAX_REPORTED_ERRORS
+        ));
+    }
+    if hint {
+        msg.push_str(&format!(
+            "\n(set `{VAR_BINDGEN_DIAG}=1` to rerun clang with the same args for its complete output)"
+        ));
+    }
+    Error::msg(msg)
+}
+
+/// Rerun clang (`CLANG_PATH` or `clang`) with `-fsyntax-only` and the header and
+/// clang args of the bindgen command line `flags`, passed in the response file
+/// `rsp_file`.
+fn run_clang_diagnostics(flags: &[String], rsp_file: &Path) -> Result<()> {
+    // The main header is the first flag, the clang args follow `--`.
+    let header = flags.first().filter(|flag| !flag.starts_with('-'));
+    let clang_args = flags
+        .iter()
+        .skip_while(|flag| *flag != "--")
+        .skip(1)
+        .chain(header)
+        .collect::<Vec<_>>();
+
+    let args = cli::spill_response_file(&clang_args, rsp_file, 0, cli::Syntax::NATIVE)?;
+    let clang = env::var_os("CLANG_PATH").unwrap_or_else(|| "clang".into());
+    log::info!(
+        "Rerunning clang for diagnostics: {} -fsyntax-only {}",
+        clang.to_string_lossy(),
+        args.join(" ")
+    );
+
+    cmd!(clang, "-fsyntax-only"; args=(&args)).run()?;
     Ok(())
 }
 
@@ -305,12 +726,174 @@ pub trait BindgenExt: Sized {
 impl BindgenExt for bindgen::Builder {
     fn headers(mut self, headers: impl IntoIterator<Item = impl AsRef<Path>>) -> Result<Self> {
         for header in headers {
-            self = self.header(header.as_ref().try_to_str()?)
+            self = self.header(header.as_ref().try_to_str_with_context("header")?)
         }
         Ok(self)
     }
 }
 
+/// Compiler arguments with a separate value that are kept by [`sanitize_clang_args`].
+#[cfg(feature = "cmake")]
+const CLANG_ARGS_WITH_VALUE: &[&str] = &[
+    "-D",
+    "-U",
+    "-I",
+    "-isystem",
+    "-iquote",
+    "-idirafter",
+    "-include",
+    "-imacros",
+];
+
+/// Compiler flags that affect the ABI and are kept by [`sanitize_clang_args`].
+#[cfg(feature = "cmake")]
+const CLANG_ABI_FLAGS: &[&str] = &[
+    "-fshort-enums",
+    "-fno-short-enums",
+    "-fshort-wchar",
+    "-fno-short-wchar",
+    "-funsigned-char",
+    "-fsigned-char",
+    "-nostdinc",
+];
+
+/// Filter the compiler arguments `args` (of gcc for example) so that only arguments
+/// which are relevant for generating bindings and understood by clang remain.
+///
+/// These are defines, include directories and files, the language standard and flags
+/// affecting the ABI. All other arguments (warnings, optimization and target specific
+/// flags) are removed.
+#[cfg(feature = "cmake")]
+fn sanitize_clang_args(args: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        if CLANG_ARGS_WITH_VALUE.contains(&arg.as_str()) {
+            if let Some(value) = args.next() {
+                result.push(arg);
+                result.push(value);
+            }
+        } else if CLANG_ARGS_WITH_VALUE.iter().any(|a| arg.starts_with(a))
+            || arg.starts_with("-std=")
+            || CLANG_ABI_FLAGS.contains(&arg.as_str())
+        {
+            result.push(arg);
+        }
+    }
+
+    result
+}
+
+/// Get the regexes matching the files in the [`Filter`] directory `dir`.
+///
+/// These match both the directory as given and canonicalized, as clang reports the
+/// path of a header as it was found on the include path.
+fn dir_regexes(dir: &Path, sysroot: &Path) -> Result<Vec<String>> {
+    let dir = match dir.strip_prefix(SYSROOT_DIR) {
+        Ok(rest) if rest.as_os_str().is_empty() => sysroot.to_owned(),
+        Ok(rest) => sysroot.join(rest),
+        Err(_) => match env::var_os("CARGO_MANIFEST_DIR") {
+            Some(manifest_dir) => dir.abspath_relative_to(manifest_dir),
+            None => dir.abspath()?,
+        },
+    };
+    let canonical = dir
+        .canonicalize()
+        .with_context(|| anyhow!("Invalid filter directory '{}'", dir.display()))?;
+
+    let mut regexes = vec![path_prefix_regex(
+        canonical.try_to_str_with_context("filter directory")?,
+        PathStyle::native(),
+    )];
+    let regex = path_prefix_regex(
+        dir.try_to_str_with_context("filter directory")?,
+        PathStyle::native(),
+    );
+    if !regexes.contains(&regex) {
+        regexes.push(regex);
+    }
+    Ok(regexes)
+}
+
+/// The clang args used instead of those of an xtensa target (see
+/// [`clang_target_args`]) if libclang doesn't support xtensa.
+///
+/// This is a 32-bit little-endian target with an unsigned `char`, like xtensa, but
+/// with a different `va_list` layout.
+pub const XTENSA_FALLBACK_TARGET_ARGS: [&str; 3] =
+    ["--target=riscv32-esp-elf", "-march=rv32imac", "-mabi=ilp32"];
+
+/// Get the clang args selecting the clang target closest to the Rust target triple
+/// `target`.
+///
+/// - xtensa targets (e.g. `xtensa-esp32-espidf`) map to `xtensa-esp-elf` with the cpu
+///   (`-mcpu=esp32`).
+/// - riscv32 targets (e.g. `riscv32imc-esp-espidf`) map to `riscv32` with the
+///   extensions as `-march` (`-march=rv32imc`) and the matching `-mabi`.
+/// - The `espidf` os maps to `elf`, other targets are used as is.
+pub fn clang_target_args(target: &str) -> Vec<String> {
+    let (arch, rest) = target.split_once('-').unwrap_or((target, ""));
+    let rest = rest
+        .strip_suffix("-espidf")
+        .map_or_else(|| rest.to_owned(), |vendor| format!("{vendor}-elf"));
+
+    if arch == "xtensa" {
+        let cpu = rest.split('-').next().unwrap_or_default();
+        vec!["--target=xtensa-esp-elf".into(), format!("-mcpu={cpu}")]
+    } else if let Some(extensions) = arch.strip_prefix("riscv32") {
+        let abi = if extensions.contains('f') {
+            "ilp32f"
+        } else {
+            "ilp32"
+        };
+        vec![
+            format!("--target=riscv32-{rest}"),
+            format!("-march=rv32{extensions}"),
+            format!("-mabi={abi}"),
+        ]
+    } else if rest.is_empty() {
+        vec![format!("--target={arch}")]
+    } else {
+        vec![format!("--target={arch}-{rest}")]
+    }
+}
+
+/// Get the [`clang_target_args`] of `target`, or the [`XTENSA_FALLBACK_TARGET_ARGS`]
+/// (with a warning) for an xtensa target if libclang doesn't support `xtensa`.
+fn target_args(target: &str, xtensa: bool) -> Vec<String> {
+    let args = clang_target_args(target);
+    if !xtensa && args[0] == "--target=xtensa-esp-elf" {
+        warning!(
+            "libclang does not support the xtensa target '{target}', generating bindings for '{}' instead (set `LIBCLANG_PATH` to an esp-clang installation)",
+            XTENSA_FALLBACK_TARGET_ARGS.join(" ")
+        );
+        return XTENSA_FALLBACK_TARGET_ARGS.map(String::from).to_vec();
+    }
+    args
+}
+
+/// Whether the loaded libclang supports the xtensa target.
+///
+/// Upstream clang lacks the xtensa backend (which the Espressif fork `esp-clang` has),
+/// so this is probed once per process by parsing a trivial header for `xtensa-esp-elf`.
+/// If libclang can't be loaded at all, xtensa is assumed to be supported, so that
+/// generating the bindings fails with the actual error.
+fn libclang_supports_xtensa() -> bool {
+    static SUPPORTED: OnceLock<bool> = OnceLock::new();
+
+    *SUPPORTED.get_or_init(|| {
+        let probe = || {
+            bindgen::Builder::default()
+                .header_contents("embuild-xtensa-probe.h", "int embuild_xtensa_probe;")
+                .clang_arg("--target=xtensa-esp-elf")
+                .generate()
+                .is_ok()
+        };
+        std::panic::catch_unwind(probe).unwrap_or(true)
+    })
+}
+
 fn try_get_sysroot(linker: &Option<impl AsRef<Path>>) -> Result<PathBuf> {
     let linker = if let Some(ref linker) = linker {
         linker.as_ref().to_owned()
@@ -349,32 +932,41 @@ fn try_get_sysroot(linker: &Option<impl AsRef<Path>>) -> Result<PathBuf> {
         .map(PathBuf::from)
 }
 
+/// Get the include args of the newest C++ standard library in `sysroot`
+/// (`include/c++/<version>`).
 fn get_cpp_includes(sysroot: impl AsRef<Path>) -> Result<Vec<String>> {
     let sysroot = sysroot.as_ref();
     let cpp_includes_root = sysroot.join("include").join("c++");
 
+    // Compare the versions numerically, so that e.g. `12.2.0` is newer than `8.4.0`.
+    let version_key = |dir: &Path| {
+        let name = dir.file_name().unwrap_or_default().to_string_lossy();
+        let numbers = name
+            .split('.')
+            .map(|part| part.parse::<u32>().ok())
+            .collect::<Vec<_>>();
+        (numbers, name.into_owned())
+    };
     let cpp_version = fs::read_dir(cpp_includes_root)?
-        .map(|dir_entry_r| dir_entry_r.map(|dir_entry| dir_entry.path()))
-        .fold(None, |ao: Option<PathBuf>, sr: Result<PathBuf, _>| {
-            if let Some(a) = ao.as_ref() {
-                sr.ok()
-                    .map_or(ao.clone(), |s| if a >= &s { ao.clone() } else { Some(s) })
-            } else {
-                sr.ok()
-            }
-        });
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|dir| dir.is_dir())
+        .max_by_key(|dir| version_key(dir));
 
     if let Some(cpp_version) = cpp_version {
+        let include_arg = |dir: PathBuf| -> Result<String> {
+            Ok(format!(
+                "-I{}",
+                dir.try_to_str_with_context("C++ include directory")?
+            ))
+        };
+
         let mut cpp_include_paths = vec![
-            format!("-I{}", cpp_version.try_to_str()?),
-            format!("-I{}", cpp_version.join("backward").try_to_str()?),
+            include_arg(cpp_version.clone())?,
+            include_arg(cpp_version.join("backward"))?,
         ];
 
         if let Some(sysroot_last_segment) = fs::canonicalize(sysroot)?.file_name() {
-            cpp_include_paths.push(format!(
-                "-I{}",
-                cpp_version.join(sysroot_last_segment).try_to_str()?
-            ));
+            cpp_include_paths.push(include_arg(cpp_version.join(sysroot_last_segment))?);
         }
 
         Ok(cpp_include_paths)
@@ -382,3 +974,337 @@ fn get_cpp_includes(sysroot: impl AsRef<Path>) -> Result<Vec<String>> {
         Ok(Vec::new())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let tmp = tempfile::tempdir().unwrap();
+        let sysroot = tmp.path();
+        fs::create_dir_all(sysroot.join("include/c++/12.2.0")).unwrap();
+
+        let factory = Factory::new()
+            .with_sysroot(sysroot)
+            .with_clang_args(["-DFOO=1", "-I/sdk/include"]);
+
+        let resolved = factory.clone().resolve(false).unwrap();
+        assert_eq!(resolved.sysroot, sysroot);
+        assert_eq!(resolved.language, Language::C);
+        assert_eq!(
+            resolved.clang_args[..6],
+            [
+                "-D__bindgen".to_owned(),
+                "-DFOO=1".to_owned(),
+                "-I/sdk/include".to_owned(),
+                format!("--sysroot={}", sysroot.display()),
+                format!("-I{}", sysroot.join("include").display()),
+                "-x".to_owned(),
+            ]
+        );
+        assert_eq!(resolved.clang_args[6], "c");
+        assert_eq!(
+            resolved.builder().command_line_flags(),
+            factory.clone().builder().unwrap().command_line_flags()
+        );
+
+        let resolved = factory.clone().resolve(true).unwrap();
+        assert_eq!(resolved.language, Language::Cpp);
+        assert!(resolved.clang_args.contains(&format!(
+            "-I{}",
+            sysroot.join("include/c++/12.2.0/backward").display()
+        )));
+        assert_eq!(
+            resolved.builder().command_line_flags(),
+            factory.cpp_builder().unwrap().command_line_flags()
+        );
+    }
+
+    #[test]
+    fn test_clang_target_args() {
+        let table = [
+            (
+                "xtensa-esp32-espidf",
+                &["--target=xtensa-esp-elf", "-mcpu=esp32"][..],
+            ),
+            (
+                "xtensa-esp32s3-none-elf",
+                &["--target=xtensa-esp-elf", "-mcpu=esp32s3"],
+            ),
+            (
+                "riscv32imc-esp-espidf",
+                &["--target=riscv32-esp-elf", "-march=rv32imc", "-mabi=ilp32"],
+            ),
+            (
+                "riscv32imafc-esp-espidf",
+                &[
+                    "--target=riscv32-esp-elf",
+                    "-march=rv32imafc",
+                    "-mabi=ilp32f",
+                ],
+            ),
+            (
+                "riscv32imac-unknown-none-elf",
+                &[
+                    "--target=riscv32-unknown-none-elf",
+                    "-march=rv32imac",
+                    "-mabi=ilp32",
+                ],
+            ),
+            ("thumbv7em-none-eabihf", &["--target=thumbv7em-none-eabihf"]),
+            (
+                "x86_64-unknown-linux-gnu",
+                &["--target=x86_64-unknown-linux-gnu"],
+            ),
+        ];
+        for (target, args) in table {
+            assert_eq!(clang_target_args(target), args, "{target}");
+        }
+
+        assert_eq!(
+            target_args("xtensa-esp32-espidf", false),
+            XTENSA_FALLBACK_TARGET_ARGS
+        );
+        assert_eq!(
+            target_args("xtensa-esp32-espidf", true),
+            clang_target_args("xtensa-esp32-espidf")
+        );
+        assert_eq!(
+            target_args("riscv32imc-esp-espidf", false),
+            clang_target_args("riscv32imc-esp-espidf")
+        );
+    }
+
+    #[test]
+    fn test_target() {
+        let tmp = tempfile::tempdir().unwrap();
+        let sysroot = tmp.path();
+        let factory = Factory::new().with_sysroot(sysroot);
+        let targets = |factory: Factory| {
+            factory
+                .resolve(false)
+                .unwrap()
+                .clang_args
+                .into_iter()
+                .filter(|arg| arg.starts_with("--target"))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            targets(factory.clone().with_target("riscv32imc-esp-espidf")),
+            ["--target=riscv32-esp-elf"]
+        );
+        assert!(targets(
+            factory
+                .clone()
+                .with_target("riscv32imc-esp-espidf")
+                .with_host_target()
+        )
+        .is_empty());
+        assert_eq!(
+            targets(
+                factory
+                    .with_clang_args(["--target=armv7-none-eabi"])
+                    .with_target("riscv32imc-esp-espidf")
+            ),
+            ["--target=armv7-none-eabi"]
+        );
+    }
+
+    #[test]
+    fn test_generate_error() {
+        let message = (1..=12)
+            .map(|i| format!("main.h:{i}:1: error: unknown type name 't{i}'\n"))
+            .collect::<String>();
+        let error = generate_error(&bindgen::BindgenError::ClangDiagnostic(message), true);
+        let error = error.to_string();
+        let lines = error.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[0],
+            "Failed to generate bindings, clang reported 12 error(s):"
+        );
+        assert_eq!(lines[1], "  main.h:1:1: error: unknown type name 't1'");
+        assert_eq!(lines[10], "  main.h:10:1: error: unknown type name 't10'");
+        assert_eq!(lines[11], "  ... and 2 more");
+        assert!(lines[12].contains(VAR_BINDGEN_DIAG));
+
+        let error = generate_error(&bindgen::BindgenError::NotExist("main.h".into()), false);
+        assert_eq!(
+            error.to_string(),
+            "Failed to generate bindings: header 'main.h' does not exist."
+        );
+    }
+
+    #[test]
+    fn test_run_clang_diagnostics() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let header = dir.join("main.h");
+        fs::write(&header, "int f(void);\n").unwrap();
+        let rsp_file = dir.join("bindgen-diag.rsp");
+
+        let flags = bindgen::Builder::default()
+            .header(header.to_str().unwrap())
+            .clang_args(["-DFOO=1", "-I/sdk/include"])
+            .command_line_flags();
+        let result = run_clang_diagnostics(&flags, &rsp_file);
+
+        assert_eq!(
+            cli::expand_response_files(
+                [format!("@{}", rsp_file.display())],
+                dir,
+                cli::Syntax::NATIVE
+            )
+            .unwrap(),
+            ["-DFOO=1", "-I/sdk/include", header.to_str().unwrap()]
+        );
+        if cmd!("clang", "--version").stdout().is_ok() {
+            result.unwrap();
+        }
+    }
+
+    #[test]
+    fn test_layout() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let header = dir.join("layout.h");
+        fs::write(
+            &header,
+            r"
+            struct name { char *s; };
+            union value { int i; struct name n; };
+            struct __attribute__((packed)) flags { unsigned a : 3; unsigned b : 5; char c; };
+            struct padded { char a; int b; };
+            enum mode { MODE_A = 1, MODE_B = 2 };
+            ",
+        )
+        .unwrap();
+
+        let builder = |layout: LayoutOptions| {
+            Factory::new()
+                .with_sysroot(dir)
+                .with_layout(layout)
+                .builder()
+                .unwrap()
+                .header(header.to_str().unwrap())
+                .no_copy("name")
+        };
+        let defaults = builder(LayoutOptions::default());
+        assert_eq!(
+            defaults.command_line_flags(),
+            Factory::new()
+                .with_sysroot(dir)
+                .resolve(false)
+                .unwrap()
+                .builder()
+                .header(header.to_str().unwrap())
+                .no_copy("name")
+                .command_line_flags()
+        );
+
+        let changed = LayoutOptions {
+            union_style: bindgen::NonCopyUnionStyle::ManuallyDrop,
+            untagged_unions: true,
+            explicit_padding: true,
+            bitfield_enums: vec!["mode".into()],
+            opaque_types: vec!["flags".into()],
+        };
+        let flags = builder(changed.clone()).command_line_flags();
+        for flag in [
+            "--default-non-copy-union-style",
+            "--explicit-padding",
+            "--bitfield-enum",
+            "--opaque-type",
+        ] {
+            assert!(flags.iter().any(|f| f == flag), "{flag}");
+        }
+
+        // Generating the bindings requires libclang.
+        if std::panic::catch_unwind(bindgen::clang_version).is_err() {
+            eprintln!("Skipping test: libclang not found");
+            return;
+        }
+        let defaults = defaults.generate().unwrap().to_string();
+        let changed = builder(changed).generate().unwrap().to_string();
+
+        assert!(defaults.contains("__BindgenUnionField<name>"));
+        assert!(changed.contains("::core::mem::ManuallyDrop<name>"));
+        assert!(!defaults.contains("__bindgen_padding_0"));
+        assert!(changed.contains("__bindgen_padding_0"));
+        assert!(changed.contains("impl ::core::ops::BitOr<mode> for mode"));
+        assert!(defaults.contains("pub fn a(&self)"));
+        assert!(!changed.contains("pub fn a(&self)"));
+    }
+
+    #[test]
+    fn test_filter_dirs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let sysroot = dir.join("sysroot");
+        fs::create_dir_all(sysroot.join("include")).unwrap();
+        fs::create_dir_all(dir.join("sdk")).unwrap();
+        let sysroot = sysroot.canonicalize().unwrap();
+        let sdk = dir.join("sdk").canonicalize().unwrap();
+
+        let filter = Filter {
+            allow_dirs: Some(vec![sdk.clone()]),
+            block_dirs: Some(vec![
+                PathBuf::from(SYSROOT_DIR),
+                Path::new(SYSROOT_DIR).join("include"),
+            ]),
+            ..Default::default()
+        };
+        let flags = Factory::new()
+            .with_sysroot(&sysroot)
+            .create_builder(false, Some(filter))
+            .unwrap()
+            .command_line_flags();
+        let values = |flag: &str| {
+            flags
+                .windows(2)
+                .filter(|w| w[0] == flag)
+                .map(|w| w[1].clone())
+                .collect::<Vec<_>>()
+        };
+
+        let regex = |dir: &Path| path_prefix_regex(dir.to_str().unwrap(), PathStyle::native());
+        assert_eq!(values("--allowlist-file"), [regex(&sdk)]);
+        assert_eq!(
+            values("--blocklist-file"),
+            [regex(&sysroot), regex(&sysroot.join("include"))]
+        );
+
+        assert!(dir_regexes(Path::new("nonexistent"), &sysroot).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "cmake")]
+    fn test_sanitize_clang_args() {
+        let args = [
+            "-mlongcalls",
+            "-Wno-frame-address",
+            "-ffunction-sections",
+            "-Og",
+            "-std=gnu17",
+            "-DFOO=1",
+            "-include",
+            "sdkconfig.h",
+            "-I/include",
+            "-fshort-enums",
+            "-isystem",
+        ];
+
+        assert_eq!(
+            sanitize_clang_args(args.into_iter().map(Into::into)),
+            [
+                "-std=gnu17",
+                "-DFOO=1",
+                "-include",
+                "sdkconfig.h",
+                "-I/include",
+                "-fshort-enums"
+            ]
+        );
+    }
+}
@@ -0,0 +1,275 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+
+use super::Language;
+
+/// The minimum libclang version required by [`Preflight`] by default.
+pub const DEFAULT_MIN_CLANG_VERSION: (u32, u32) = (9, 0);
+
+/// A check of [`Preflight`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PreflightCheck {
+    /// libclang can be loaded and is at least the minimum version (see
+    /// [`Preflight::min_clang_version`]).
+    ClangVersion,
+    /// The sysroot can be determined and contains an `include` directory.
+    Sysroot,
+    /// The sysroot contains a `include/c++/<version>` directory (only for C++
+    /// bindings).
+    CppIncludes,
+    /// A check added on top of [`Preflight`] (see [`PreflightReport::problem`]).
+    Other(&'static str),
+}
+
+/// A problem found by a [`PreflightCheck`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PreflightProblem {
+    pub check: PreflightCheck,
+    pub message: String,
+}
+
+/// The result of the [`Preflight`] checks.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PreflightReport {
+    /// The full version of libclang (as reported by [`bindgen::clang_version`]), if it
+    /// could be loaded.
+    pub clang_version: Option<String>,
+    /// The major and minor version of libclang, if it could be parsed.
+    pub parsed_clang_version: Option<(u32, u32)>,
+    /// The checked sysroot, if it could be determined.
+    pub sysroot: Option<PathBuf>,
+    /// The language of the bindings.
+    pub language: Language,
+    /// The checks that were skipped.
+    pub skipped: Vec<PreflightCheck>,
+    /// All problems found.
+    pub problems: Vec<PreflightProblem>,
+}
+
+impl PreflightReport {
+    /// Add a problem found by `check`.
+    pub fn problem(&mut self, check: PreflightCheck, message: impl Into<String>) {
+        self.problems.push(PreflightProblem {
+            check,
+            message: message.into(),
+        });
+    }
+
+    /// Whether no problems were found.
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    /// Get this report, or an error listing all problems if there are any.
+    pub fn into_result(self) -> Result<Self> {
+        if self.is_ok() {
+            return Ok(self);
+        }
+
+        let mut message = format!(
+            "Bindgen preflight checks found {} problem(s):",
+            self.problems.len()
+        );
+        for problem in &self.problems {
+            let _ = write!(message, "\n- {}", problem.message);
+        }
+        bail!(message)
+    }
+}
+
+/// Checks of the environment of bindgen before generating bindings, see
+/// [`Factory::with_preflight`](super::Factory::with_preflight).
+///
+/// All problems are reported together in a [`PreflightReport`], instead of surfacing
+/// as confusing errors of the bindings (e.g. an old libclang misparsing newer C
+/// constructs).
+#[derive(Clone, Debug)]
+#[must_use]
+pub struct Preflight {
+    min_clang_version: (u32, u32),
+    skipped: Vec<PreflightCheck>,
+}
+
+impl Default for Preflight {
+    fn default() -> Self {
+        Self {
+            min_clang_version: DEFAULT_MIN_CLANG_VERSION,
+            skipped: Vec::new(),
+        }
+    }
+}
+
+impl Preflight {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// The minimum libclang version.
+    ///
+    /// Defaults to [`DEFAULT_MIN_CLANG_VERSION`].
+    pub fn min_clang_version(mut self, major: u32, minor: u32) -> Self {
+        self.min_clang_version = (major, minor);
+        self
+    }
+
+    /// Skip `check`, e.g. for toolchains with an unusual layout.
+    pub fn skip(mut self, check: PreflightCheck) -> Self {
+        if !self.skipped.contains(&check) {
+            self.skipped.push(check);
+        }
+        self
+    }
+
+    /// Run the checks for bindings in `language` with `sysroot` (the sysroot checks are
+    /// skipped if it is [`None`]).
+    pub fn check(&self, sysroot: Option<&Path>, language: Language) -> PreflightReport {
+        let mut report = PreflightReport {
+            clang_version: None,
+            parsed_clang_version: None,
+            sysroot: sysroot.map(Path::to_owned),
+            language,
+            skipped: self.skipped.clone(),
+            problems: Vec::new(),
+        };
+
+        if !self.skips(PreflightCheck::ClangVersion) {
+            // `bindgen::clang_version` panics if libclang cannot be loaded.
+            match std::panic::catch_unwind(bindgen::clang_version) {
+                Ok(version) => {
+                    let min = self.min_clang_version;
+                    if let Some(problem) = clang_version_problem(version.parsed, &version.full, min)
+                    {
+                        report.problem(PreflightCheck::ClangVersion, problem);
+                    }
+                    report.clang_version = Some(version.full);
+                    report.parsed_clang_version = version.parsed;
+                }
+                Err(_) => report.problem(
+                    PreflightCheck::ClangVersion,
+                    "libclang could not be loaded (set `LIBCLANG_PATH` to its directory)",
+                ),
+            }
+        }
+
+        if let Some(sysroot) = sysroot {
+            if !self.skips(PreflightCheck::Sysroot) && !sysroot.join("include").is_dir() {
+                report.problem(
+                    PreflightCheck::Sysroot,
+                    format!("sysroot '{}' has no `include` directory", sysroot.display()),
+                );
+            }
+            if language == Language::Cpp
+                && !self.skips(PreflightCheck::CppIncludes)
+                && !has_cpp_includes(sysroot)
+            {
+                report.problem(
+                    PreflightCheck::CppIncludes,
+                    format!(
+                        "sysroot '{}' has no C++ include directory `include/c++/<version>`",
+                        sysroot.display()
+                    ),
+                );
+            }
+        }
+
+        report
+    }
+
+    fn skips(&self, check: PreflightCheck) -> bool {
+        self.skipped.contains(&check)
+    }
+}
+
+/// Get the problem with the libclang version `parsed` (`full`), if it is unknown or
+/// older than `min`.
+fn clang_version_problem(
+    parsed: Option<(u32, u32)>,
+    full: &str,
+    min: (u32, u32),
+) -> Option<String> {
+    match parsed {
+        None => Some(format!(
+            "could not determine the version of libclang '{full}'"
+        )),
+        Some(version) if version < min => Some(format!(
+            "libclang {}.{} is older than the minimum version {}.{} ('{full}')",
+            version.0, version.1, min.0, min.1
+        )),
+        Some(_) => None,
+    }
+}
+
+/// Whether `sysroot` contains a `include/c++/<version>` directory.
+fn has_cpp_includes(sysroot: &Path) -> bool {
+    fs::read_dir(sysroot.join("include").join("c++")).is_ok_and(|mut entries| {
+        entries.any(|entry| entry.is_ok_and(|entry| entry.path().is_dir()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clang_version_problem() {
+        let min = DEFAULT_MIN_CLANG_VERSION;
+        assert_eq!(
+            clang_version_problem(Some((17, 0)), "clang 17.0.1", min),
+            None
+        );
+        assert_eq!(
+            clang_version_problem(Some((9, 0)), "clang 9.0.0", min),
+            None
+        );
+        assert_eq!(
+            clang_version_problem(Some((8, 1)), "clang 8.1.0", min).unwrap(),
+            "libclang 8.1 is older than the minimum version 9.0 ('clang 8.1.0')"
+        );
+        assert_eq!(
+            clang_version_problem(None, "unknown", min).unwrap(),
+            "could not determine the version of libclang 'unknown'"
+        );
+    }
+
+    #[test]
+    fn test_preflight() {
+        let tmp = tempfile::tempdir().unwrap();
+        let sysroot = tmp.path();
+        let preflight = Preflight::new().skip(PreflightCheck::ClangVersion);
+
+        let report = preflight.check(Some(sysroot), Language::Cpp);
+        assert_eq!(
+            report
+                .problems
+                .iter()
+                .map(|problem| problem.check)
+                .collect::<Vec<_>>(),
+            [PreflightCheck::Sysroot, PreflightCheck::CppIncludes]
+        );
+        let error = report.clone().into_result().unwrap_err().to_string();
+        assert!(error.starts_with("Bindgen preflight checks found 2 problem(s):\n- sysroot"));
+
+        fs::create_dir_all(sysroot.join("include")).unwrap();
+        assert!(preflight.check(Some(sysroot), Language::C).is_ok());
+        assert!(!preflight.check(Some(sysroot), Language::Cpp).is_ok());
+        assert!(preflight
+            .clone()
+            .skip(PreflightCheck::CppIncludes)
+            .check(Some(sysroot), Language::Cpp)
+            .is_ok());
+
+        fs::create_dir_all(sysroot.join("include/c++/12.2.0")).unwrap();
+        let mut report = preflight.check(Some(sysroot), Language::Cpp);
+        assert!(report.is_ok());
+        assert_eq!(report.skipped, [PreflightCheck::ClangVersion]);
+
+        report.problem(PreflightCheck::Other("newlib"), "newlib is missing");
+        assert_eq!(
+            report.into_result().unwrap_err().to_string(),
+            "Bindgen preflight checks found 1 problem(s):\n- newlib is missing"
+        );
+    }
+}
@@ -0,0 +1,327 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context, Error, Result};
+
+use super::{run, Generator, VAR_BINDINGS_FILE};
+use crate::artifacts::{self, ArtifactKind};
+use crate::cargo;
+use crate::utils::{OsStrExt, PathExt};
+
+/// The environment variable overriding the [`PregenMode`] of
+/// [`run_or_use_pregenerated`], one of `pregenerated`, `generate` or `verify`.
+pub const VAR_BINDINGS_MODE: &str = "EMBUILD_BINDINGS";
+
+/// The maximum number of differing hunks shown by [`verify_pregenerated`].
+const MAX_HUNKS: usize = 5;
+
+/// The maximum number of lines of each side of a hunk shown by [`verify_pregenerated`].
+const MAX_HUNK_LINES: usize = 8;
+
+/// The number of lines searched for the end of a differing hunk.
+const RESYNC_WINDOW: usize = 200;
+
+/// How [`run_or_use_pregenerated`] handles the pregenerated bindings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PregenMode {
+    /// Use the pregenerated bindings without generating them (`pregenerated`).
+    Use,
+    /// Generate the bindings, ignoring the pregenerated ones (`generate`).
+    Regenerate,
+    /// Generate the bindings and fail if they differ from the pregenerated ones
+    /// (`verify`).
+    Verify,
+}
+
+impl FromStr for PregenMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "pregenerated" | "use" => Ok(Self::Use),
+            "generate" | "regenerate" => Ok(Self::Regenerate),
+            "verify" => Ok(Self::Verify),
+            _ => bail!(
+                "Invalid bindings mode '{s}', expected one of 'pregenerated', 'generate' or 'verify'"
+            ),
+        }
+    }
+}
+
+impl PregenMode {
+    /// Get the mode set by the [`VAR_BINDINGS_MODE`] environment variable, or
+    /// `default` if it is not set.
+    pub fn from_env_or(default: PregenMode) -> Result<PregenMode> {
+        cargo::track_env_var(VAR_BINDINGS_MODE);
+        match env::var(VAR_BINDINGS_MODE) {
+            Ok(mode) if !mode.trim().is_empty() => mode
+                .parse()
+                .with_context(|| anyhow!("in environment variable `{VAR_BINDINGS_MODE}`")),
+            _ => Ok(default),
+        }
+    }
+}
+
+/// Create rust bindings with `generator` or use the `pregenerated` bindings (e.g.
+/// committed to the repository for docs.rs builds), depending on `mode`.
+///
+/// `mode` can be overridden with the [`VAR_BINDINGS_MODE`] environment variable:
+/// - [`PregenMode::Use`]: set [`VAR_BINDINGS_FILE`] to `pregenerated` without
+///   generating the bindings.
+/// - [`PregenMode::Regenerate`]: generate the bindings like [`run`].
+/// - [`PregenMode::Verify`]: generate the bindings like [`run`] and fail with a summary
+///   of the differences if they differ from `pregenerated` (ignoring whitespace).
+///
+/// The pregenerated bindings must have been generated with the same [`Generator`]
+/// options (e.g. the same [`ItemOrder`](super::ItemOrder)) to be verified successfully.
+///
+/// A relative `pregenerated` path is resolved against `CARGO_MANIFEST_DIR`. Returns the
+/// file [`VAR_BINDINGS_FILE`] is set to.
+pub fn run_or_use_pregenerated(
+    generator: impl Into<Generator>,
+    pregenerated: impl AsRef<Path>,
+    mode: PregenMode,
+) -> Result<PathBuf> {
+    let pregenerated = match env::var_os("CARGO_MANIFEST_DIR") {
+        Some(manifest_dir) => pregenerated.as_ref().abspath_relative_to(manifest_dir),
+        None => pregenerated.as_ref().to_owned(),
+    };
+
+    match PregenMode::from_env_or(mode)? {
+        PregenMode::Use => {
+            if !pregenerated.is_file() {
+                bail!(
+                    "Pregenerated bindings '{}' do not exist (set `{VAR_BINDINGS_MODE}=generate` to generate them)",
+                    pregenerated.display()
+                );
+            }
+            cargo::track_file(&pregenerated);
+            cargo::set_rustc_env(
+                VAR_BINDINGS_FILE,
+                pregenerated.try_to_str_with_context("pregenerated bindings file")?,
+            );
+            artifacts::record(ArtifactKind::Bindings, &pregenerated)?;
+            Ok(pregenerated)
+        }
+        PregenMode::Regenerate => run(generator),
+        PregenMode::Verify => {
+            let generated = run(generator)?;
+            cargo::track_file(&pregenerated);
+            verify_pregenerated(&generated, &pregenerated)?;
+            Ok(generated)
+        }
+    }
+}
+
+/// Check that the `generated` and `pregenerated` bindings are equal, ignoring
+/// whitespace and empty lines.
+///
+/// The error lists the number of lines missing in and added to the generated bindings
+/// and the first few differing hunks.
+pub fn verify_pregenerated(
+    generated: impl AsRef<Path>,
+    pregenerated: impl AsRef<Path>,
+) -> Result<()> {
+    let (generated, pregenerated) = (generated.as_ref(), pregenerated.as_ref());
+    let read = |path: &Path| {
+        fs::read_to_string(path)
+            .with_context(|| anyhow!("could not read bindings '{}'", path.display()))
+    };
+    let generated_lines = read(generated)?;
+    let pregenerated_lines = read(pregenerated)?;
+
+    let summary = diff_summary(
+        &normalized_lines(&pregenerated_lines),
+        &normalized_lines(&generated_lines),
+    );
+    match summary {
+        None => Ok(()),
+        Some(summary) => bail!(
+            "The generated bindings '{}' differ from the pregenerated bindings '{}' ({summary}\nUpdate the pregenerated bindings or set `{VAR_BINDINGS_MODE}=generate`.",
+            generated.display(),
+            pregenerated.display(),
+        ),
+    }
+}
+
+/// Get the non-empty lines of `text` with their line numbers, with whitespace
+/// sequences replaced by a single space.
+fn normalized_lines(text: &str) -> Vec<(usize, String)> {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.split_whitespace().collect::<Vec<_>>().join(" ")))
+        .filter(|(_, line)| !line.is_empty())
+        .collect()
+}
+
+/// Summarize the differences between the `old` and `new` lines, or get `None` if they
+/// are equal.
+fn diff_summary(old: &[(usize, String)], new: &[(usize, String)]) -> Option<String> {
+    fn line((_, line): &(usize, String)) -> &str {
+        line
+    }
+
+    if old.iter().map(line).eq(new.iter().map(line)) {
+        return None;
+    }
+
+    // Count the lines by content, which is exact regardless of how the lines moved.
+    let mut counts = HashMap::<&str, isize>::new();
+    for l in old {
+        *counts.entry(line(l)).or_default() += 1;
+    }
+    for l in new {
+        *counts.entry(line(l)).or_default() -= 1;
+    }
+    let removed: isize = counts.values().filter(|c| **c > 0).sum();
+    let added: isize = -counts.values().filter(|c| **c < 0).sum::<isize>();
+
+    let mut summary = format!("{removed} line(s) removed, {added} line(s) added):");
+    let (mut i, mut j, mut hunks) = (0, 0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i].1 == new[j].1 {
+            i += 1;
+            j += 1;
+            continue;
+        }
+        if hunks == MAX_HUNKS {
+            summary.push_str("\n...");
+            break;
+        }
+        hunks += 1;
+
+        let (end_i, end_j) = resync(old, new, i, j);
+        let line_no = |lines: &[(usize, String)], k: usize| {
+            lines
+                .get(k)
+                .map_or_else(|| "end".to_owned(), |(n, _)| n.to_string())
+        };
+        let _ = write!(
+            summary,
+            "\n@@ pregenerated line {}, generated line {} @@",
+            line_no(old, i),
+            line_no(new, j)
+        );
+        for (prefix, lines) in [("-", &old[i..end_i]), ("+", &new[j..end_j])] {
+            for (_, l) in lines.iter().take(MAX_HUNK_LINES) {
+                let _ = write!(summary, "\n{prefix} {l}");
+            }
+            if lines.len() > MAX_HUNK_LINES {
+                let _ = write!(
+                    summary,
+                    "\n{prefix} ... ({} more)",
+                    lines.len() - MAX_HUNK_LINES
+                );
+            }
+        }
+        (i, j) = (end_i, end_j);
+    }
+    Some(summary)
+}
+
+/// Find the nearest indices after the differing lines `old[i]` and `new[j]` where the
+/// lines are equal again (within [`RESYNC_WINDOW`] lines), or the indices
+/// [`RESYNC_WINDOW`] lines further.
+fn resync(old: &[(usize, String)], new: &[(usize, String)], i: usize, j: usize) -> (usize, usize) {
+    for distance in 1..=2 * RESYNC_WINDOW {
+        for x in distance.saturating_sub(RESYNC_WINDOW)..=distance.min(RESYNC_WINDOW) {
+            let (a, b) = (i + x, j + distance - x);
+            if a < old.len() && b < new.len() && old[a].1 == new[b].1 {
+                return (a, b);
+            }
+        }
+    }
+    (
+        (i + RESYNC_WINDOW).min(old.len()),
+        (j + RESYNC_WINDOW).min(new.len()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<(usize, String)> {
+        normalized_lines(text)
+    }
+
+    #[test]
+    fn test_pregen_mode() {
+        assert_eq!(
+            "pregenerated".parse::<PregenMode>().unwrap(),
+            PregenMode::Use
+        );
+        assert_eq!(
+            "Generate".parse::<PregenMode>().unwrap(),
+            PregenMode::Regenerate
+        );
+        assert_eq!(" verify".parse::<PregenMode>().unwrap(), PregenMode::Verify);
+        assert!("always".parse::<PregenMode>().is_err());
+    }
+
+    #[test]
+    fn test_diff_summary() {
+        let old = "pub type a = u32;\n\npub struct b {\n    pub x: u8,\n}\npub const C: u32 = 1;\n";
+        let same = "pub type a =  u32;\npub struct b {\n  pub x: u8,\n}\n\npub const C: u32 = 1;";
+        assert_eq!(diff_summary(&lines(old), &lines(same)), None);
+
+        let new = "pub type a = u32;\npub struct b {\n    pub x: u16,\n    pub y: u8,\n}\npub const C: u32 = 1;\npub const D: u32 = 2;\n";
+        assert_eq!(
+            diff_summary(&lines(old), &lines(new)).unwrap(),
+            "1 line(s) removed, 3 line(s) added):\n\
+             @@ pregenerated line 4, generated line 3 @@\n\
+             - pub x: u8,\n\
+             + pub x: u16,\n\
+             + pub y: u8,\n\
+             @@ pregenerated line end, generated line 7 @@\n\
+             + pub const D: u32 = 2;"
+        );
+    }
+
+    #[test]
+    fn test_diff_summary_truncated() {
+        let old = (0..100_000)
+            .map(|i| format!("pub const C{i}: u32 = {i};\n"))
+            .collect::<String>();
+        let new = old.replace("= 7", "= 8");
+
+        let summary = diff_summary(&lines(&old), &lines(&new)).unwrap();
+        let summary = summary.lines().collect::<Vec<_>>();
+        assert_eq!(summary[0], "11111 line(s) removed, 11111 line(s) added):");
+        assert_eq!(
+            summary[1..4],
+            [
+                "@@ pregenerated line 8, generated line 8 @@",
+                "- pub const C7: u32 = 7;",
+                "+ pub const C7: u32 = 8;"
+            ]
+        );
+        assert_eq!(
+            summary.iter().filter(|l| l.starts_with("@@")).count(),
+            MAX_HUNKS
+        );
+        assert_eq!(summary.last(), Some(&"..."));
+    }
+
+    #[test]
+    fn test_verify_pregenerated() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let (generated, pregenerated) = (dir.join("bindings.rs"), dir.join("pregenerated.rs"));
+
+        fs::write(&generated, "pub type a = u32;\n").unwrap();
+        fs::write(&pregenerated, "pub type a = u32;").unwrap();
+        verify_pregenerated(&generated, &pregenerated).unwrap();
+
+        fs::write(&pregenerated, "pub type a = u16;").unwrap();
+        let error = verify_pregenerated(&generated, &pregenerated)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("1 line(s) removed, 1 line(s) added"));
+        assert!(error.contains("- pub type a = u16;\n+ pub type a = u32;"));
+    }
+}
@@ -0,0 +1,386 @@
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use syn::{ForeignItem, Item, ItemForeignMod, Type};
+
+/// The order of the items in the written bindings, see [`Generator::item_order`].
+///
+/// [`Generator::item_order`]: super::Generator::item_order
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ItemOrder {
+    /// The order bindgen generates the items in, which follows the order in which the
+    /// headers are included.
+    #[default]
+    Bindgen,
+    /// Sorted by kind and name with [`sort_bindings`], so that changing the includes of
+    /// the headers doesn't reorder the bindings.
+    Sorted,
+}
+
+/// The kinds of items, in the order they are emitted by [`sort_bindings`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Group {
+    /// Items whose order may matter (e.g. `use`s and macros), kept in their order.
+    Unsorted,
+    Const,
+    Static,
+    TypeAlias,
+    Struct,
+    Union,
+    Enum,
+    Mod,
+    Extern,
+    Fn,
+}
+
+/// Sort the items of the generated bindings `code` deterministically.
+///
+/// The items are grouped by kind (consts, statics, type aliases, structs, unions, enums,
+/// modules, extern blocks and functions) and sorted by name within each group, with the
+/// `impl` blocks and layout tests of a type following the type. All extern blocks with
+/// the same ABI and attributes are merged into one, with their items sorted by name.
+/// `use`s, macros and other items whose order may matter are kept in their order before
+/// all other items, and the items of modules are sorted recursively.
+///
+/// The attributes (and doc comments) of all items are kept, as is a comment at the start
+/// of `code` (e.g. bindgen's header).
+pub fn sort_bindings(code: &str) -> Result<String> {
+    let mut file = syn::parse_file(code).context("Could not parse the bindings to sort them")?;
+    file.items = sort_items(std::mem::take(&mut file.items));
+
+    let header = code
+        .lines()
+        .take_while(|line| {
+            let line = line.trim_start();
+            (line.starts_with("/*") || line.starts_with("//"))
+                && !line.starts_with("///")
+                && !line.starts_with("//!")
+        })
+        .collect::<Vec<_>>();
+    let sorted = prettyplease::unparse(&file);
+
+    Ok(if header.is_empty() {
+        sorted
+    } else {
+        format!("{}\n\n{sorted}", header.join("\n"))
+    })
+}
+
+fn sort_items(items: Vec<Item>) -> Vec<Item> {
+    let mut sorted = Vec::new();
+    // The `impl` blocks and layout tests of the types, by type name.
+    let mut attached = HashMap::<String, Vec<Item>>::new();
+    let mut unattached = Vec::new();
+    let mut externs = Vec::<(ItemForeignMod, Vec<ForeignItem>)>::new();
+
+    for item in items {
+        let (group, name) = match &item {
+            Item::Const(i) if i.ident != "_" => (Group::Const, i.ident.to_string()),
+            Item::Static(i) => (Group::Static, i.ident.to_string()),
+            Item::Type(i) => (Group::TypeAlias, i.ident.to_string()),
+            Item::Struct(i) => (Group::Struct, i.ident.to_string()),
+            Item::Union(i) => (Group::Union, i.ident.to_string()),
+            Item::Enum(i) => (Group::Enum, i.ident.to_string()),
+            Item::Mod(i) => (Group::Mod, i.ident.to_string()),
+            Item::Fn(i) => {
+                let name = i.sig.ident.to_string();
+                match name.strip_prefix("bindgen_test_layout_") {
+                    Some(ty) => {
+                        attached.entry(ty.to_owned()).or_default().push(item);
+                        continue;
+                    }
+                    None => (Group::Fn, name),
+                }
+            }
+            Item::Impl(i) => {
+                match type_name(&i.self_ty) {
+                    Some(ty) => attached.entry(ty).or_default().push(item),
+                    None => unattached.push(item),
+                }
+                continue;
+            }
+            Item::ForeignMod(i) => {
+                let header = ItemForeignMod {
+                    items: Vec::new(),
+                    ..i.clone()
+                };
+                let Item::ForeignMod(i) = item else {
+                    unreachable!()
+                };
+                match externs.iter_mut().find(|(h, _)| *h == header) {
+                    Some((_, items)) => items.extend(i.items),
+                    None => externs.push((header, i.items)),
+                }
+                continue;
+            }
+            _ => (Group::Unsorted, String::new()),
+        };
+
+        let item = match item {
+            Item::Mod(mut module) => {
+                if let Some((_, items)) = &mut module.content {
+                    *items = sort_items(std::mem::take(items));
+                }
+                Item::Mod(module)
+            }
+            item => item,
+        };
+        sorted.push((group, name, item));
+    }
+
+    for (mut header, mut items) in externs {
+        items.sort_by_cached_key(foreign_item_name);
+        header.items = items;
+        let abi = header
+            .abi
+            .name
+            .as_ref()
+            .map(|name| name.value())
+            .unwrap_or_default();
+        sorted.push((Group::Extern, abi, Item::ForeignMod(header)));
+    }
+
+    // Stable, so that items with the same group and name keep their order.
+    sorted.sort_by(|(g1, n1, _), (g2, n2, _)| (g1, n1).cmp(&(g2, n2)));
+
+    let mut items = Vec::new();
+    for (group, name, item) in sorted {
+        items.push(item);
+        if matches!(
+            group,
+            Group::TypeAlias | Group::Struct | Group::Union | Group::Enum
+        ) {
+            items.extend(attached.remove(&name).unwrap_or_default());
+        }
+    }
+
+    // The `impl`s of types which are not part of the bindings, in their order.
+    let mut rest = attached.into_values().flatten().collect::<Vec<_>>();
+    rest.sort_by_cached_key(|item| {
+        let name = match item {
+            Item::Impl(i) => type_name(&i.self_ty),
+            Item::Fn(i) => Some(i.sig.ident.to_string()),
+            _ => None,
+        };
+        name.unwrap_or_default()
+    });
+    items.extend(rest);
+    items.extend(unattached);
+    items
+}
+
+/// The name of the type `ty` of an `impl` block, without its path and generics.
+fn type_name(ty: &Type) -> Option<String> {
+    match ty {
+        Type::Path(path) => path.path.segments.last().map(|s| s.ident.to_string()),
+        _ => None,
+    }
+}
+
+fn foreign_item_name(item: &ForeignItem) -> String {
+    match item {
+        ForeignItem::Fn(i) => i.sig.ident.to_string(),
+        ForeignItem::Static(i) => i.ident.to_string(),
+        ForeignItem::Type(i) => i.ident.to_string(),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::{env, fs};
+
+    use super::*;
+    use crate::cmd;
+
+    /// The bindings bindgen generates for a few headers, with items of all kinds in
+    /// include order.
+    const BINDINGS: &str = r#"/* automatically generated by rust-bindgen 0.69.4 */
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct __BindgenBitfieldUnit<Storage> {
+    storage: Storage,
+}
+impl<Storage> __BindgenBitfieldUnit<Storage> {
+    #[inline]
+    pub const fn new(storage: Storage) -> Self {
+        Self { storage }
+    }
+}
+pub const WIFI_MAX: u32 = 8;
+pub const ESP_OK: u32 = 0;
+pub type esp_err_t = ::core::ffi::c_int;
+#[doc = " A task handle"]
+pub type TaskHandle_t = *mut tskTaskControlBlock;
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct tskTaskControlBlock {
+    _unused: [u8; 0],
+}
+extern "C" {
+    #[doc = " Create a task"]
+    pub fn xTaskCreate(name: *const ::core::ffi::c_char, handle: *mut TaskHandle_t) -> esp_err_t;
+}
+extern "C" {
+    pub fn esp_restart();
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct wifi_config_t {
+    pub ssid: [u8; 32usize],
+    pub mode: wifi_mode_t::Type,
+    pub _bitfield_align_1: [u8; 0],
+    pub _bitfield_1: __BindgenBitfieldUnit<[u8; 1usize]>,
+}
+#[test]
+fn bindgen_test_layout_wifi_config_t() {
+    assert_eq!(::core::mem::size_of::<wifi_config_t>(), 40usize);
+}
+impl Default for wifi_config_t {
+    fn default() -> Self {
+        unsafe { ::core::mem::zeroed() }
+    }
+}
+impl wifi_config_t {
+    #[inline]
+    pub fn is_open(&self) -> bool {
+        self.ssid[0] == 0
+    }
+}
+pub mod wifi_mode_t {
+    pub type Type = ::core::ffi::c_uint;
+    pub const WIFI_MODE_STA: Type = 1;
+    pub const WIFI_MODE_NULL: Type = 0;
+}
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub union esp_ip_addr {
+    pub ip4: u32,
+    pub ip6: [u32; 4usize],
+}
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+pub enum esp_log_level_t {
+    ESP_LOG_NONE = 0,
+    ESP_LOG_ERROR = 1,
+}
+extern "C" {
+    pub static mut esp_log_level: esp_log_level_t;
+    pub fn esp_log_write(level: esp_log_level_t, tag: *const ::core::ffi::c_char, ...);
+}
+pub const ESP_FAIL: i32 = -1;
+"#;
+
+    #[test]
+    fn test_sort_bindings() {
+        let sorted = sort_bindings(BINDINGS).unwrap();
+        assert!(sorted.starts_with("/* automatically generated by rust-bindgen 0.69.4 */\n\n"));
+
+        let position = |s: &str| sorted.find(s).unwrap_or_else(|| panic!("{s}"));
+        let order = [
+            "pub const ESP_FAIL",
+            "pub const ESP_OK",
+            "pub const WIFI_MAX",
+            "pub type TaskHandle_t",
+            "pub type esp_err_t",
+            "pub struct __BindgenBitfieldUnit",
+            "impl<Storage> __BindgenBitfieldUnit<Storage>",
+            "pub struct tskTaskControlBlock",
+            "pub struct wifi_config_t",
+            "fn bindgen_test_layout_wifi_config_t",
+            "impl Default for wifi_config_t",
+            "impl wifi_config_t",
+            "pub union esp_ip_addr",
+            "pub enum esp_log_level_t",
+            "pub mod wifi_mode_t",
+            "pub const WIFI_MODE_NULL",
+            "pub const WIFI_MODE_STA",
+            "pub type Type",
+            "extern \"C\"",
+            "pub static mut esp_log_level",
+            "pub fn esp_log_write",
+            "pub fn esp_restart",
+            "pub fn xTaskCreate",
+        ];
+        for pair in order.windows(2) {
+            assert!(position(pair[0]) < position(pair[1]), "{pair:?}");
+        }
+        // The extern blocks are merged.
+        assert_eq!(sorted.matches("extern \"C\"").count(), 1);
+        // Doc comments are kept.
+        assert!(sorted.contains("A task handle"));
+        assert!(sorted.contains("Create a task"));
+
+        // Sorting is deterministic regardless of the original order.
+        assert_eq!(sort_bindings(&sorted).unwrap(), sorted);
+        let mut reordered = BINDINGS.lines().collect::<Vec<_>>();
+        let pos = reordered
+            .iter()
+            .position(|l| l.starts_with("pub const ESP_FAIL"))
+            .unwrap();
+        let line = reordered.remove(pos);
+        reordered.insert(2, line);
+        assert_eq!(sort_bindings(&reordered.join("\n")).unwrap(), sorted);
+    }
+
+    /// The headers in `tests/fixtures/bindgen`, which have items of all kinds.
+    const HEADERS: [&str; 4] = ["esp_err.h", "log.h", "task.h", "wifi.h"];
+
+    /// Generate the bindings of a header in `dir` including `headers` in this order.
+    fn generate(dir: &Path, headers: &[&str]) -> String {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/bindgen");
+        let header = dir.join(format!("{}.h", headers.join("-")));
+        let includes = headers
+            .iter()
+            .map(|h| format!("#include \"{}\"\n", fixtures.join(h).display()))
+            .collect::<String>();
+        fs::write(&header, includes).unwrap();
+
+        bindgen::Builder::default()
+            .header(header.to_str().unwrap())
+            .use_core()
+            .derive_default(true)
+            .constified_enum_module("wifi_mode_t")
+            .generate()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn test_sort_generated_bindings() {
+        if std::panic::catch_unwind(bindgen::clang_version).is_err() {
+            eprintln!("Skipping test_sort_generated_bindings: libclang is not available");
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+
+        let bindings = generate(dir.path(), &HEADERS);
+        let sorted = sort_bindings(&bindings).unwrap();
+        let mut reversed = HEADERS;
+        reversed.reverse();
+        assert_eq!(
+            sort_bindings(&generate(dir.path(), &reversed)).unwrap(),
+            sorted
+        );
+
+        // Both compile, and the layout tests of the sorted bindings pass.
+        let rustc = env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+        for (name, bindings) in [("original", bindings), ("sorted", sorted)] {
+            let file = dir.path().join(format!("{name}.rs"));
+            fs::write(
+                &file,
+                format!("#![allow(non_camel_case_types, non_upper_case_globals, non_snake_case, dead_code)]\n{bindings}"),
+            )
+            .unwrap();
+            let test = dir.path().join(name);
+            cmd!(&rustc, "--edition=2021", "--test", &file, "-o", &test)
+                .run()
+                .unwrap_or_else(|e| panic!("the {name} bindings don't compile: {e}"));
+            cmd!(&test)
+                .run()
+                .unwrap_or_else(|e| panic!("the layout tests of the {name} bindings fail: {e}"));
+        }
+    }
+}
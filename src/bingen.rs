@@ -1,20 +1,134 @@
+use std::fmt::Write as _;
 use std::fs::{self, File};
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::{cmp, env};
 
-use anyhow::{Error, Result};
+use anyhow::{bail, Context, Error, Result};
 use xmas_elf::ElfFile;
 
+use crate::{cargo, utils};
+
+mod manifest;
+
+pub use manifest::*;
+
 pub const VAR_BIN_FILE: &str = "EMBUILD_GENERATED_BIN_FILE";
+pub const VAR_BIN_RUST_FILE: &str = "EMBUILD_GENERATED_BIN_RUST_FILE";
+
+/// The format of the input file of [`Bingen`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InputFormat {
+    /// An ELF file, whose loadable segments are placed at their addresses (starting
+    /// from address 0).
+    #[default]
+    Elf,
+    /// An Intel HEX file, whose records are placed at their addresses (starting from
+    /// the lowest address).
+    IntelHex,
+    /// A raw binary file, which is used as is.
+    Raw,
+}
+
+/// How the data is embedded by the Rust code of [`Bingen::run_rust`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Embedding {
+    /// One array literal.
+    Array,
+    /// Array literals of at most `chunk_size` bytes, with a table of all chunks.
+    Chunked { chunk_size: usize },
+    /// The raw binary file next to the Rust file, included with `include_bytes!`. This
+    /// is much faster to compile than array literals.
+    IncludeBytes,
+}
+
+/// The configuration of the Rust code generated by [`Bingen::run_rust`].
+#[derive(Debug, Clone)]
+pub struct RustConfig {
+    name: String,
+    align: Option<usize>,
+    link_section: Option<String>,
+    embedding: Embedding,
+}
+
+impl RustConfig {
+    /// Embed the data as the static `name` (e.g. `FIRMWARE`) in one array literal.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            align: None,
+            link_section: None,
+            embedding: Embedding::Array,
+        }
+    }
+
+    /// Align the data (or every chunk) to `align` bytes, by wrapping it in a
+    /// `#[repr(align(N))]` struct.
+    pub fn align(mut self, align: usize) -> Self {
+        self.align = Some(align);
+        self
+    }
+
+    /// Place the data in the linker section `section` with `#[link_section]`.
+    pub fn link_section(mut self, section: impl Into<String>) -> Self {
+        self.link_section = Some(section.into());
+        self
+    }
+
+    /// Embed the data with `embedding`.
+    pub fn embedding(mut self, embedding: Embedding) -> Self {
+        self.embedding = embedding;
+        self
+    }
 
+    fn validate(&self) -> Result<()> {
+        if !is_identifier(&self.name) {
+            bail!("'{}' is not a valid identifier", self.name);
+        }
+        if let Some(align) = self.align {
+            if !align.is_power_of_two() || align > 1 << 29 {
+                bail!("alignment {align} is not a power of two below 2^29");
+            }
+        }
+        if self.embedding == (Embedding::Chunked { chunk_size: 0 }) {
+            bail!("the chunk size must not be 0");
+        }
+        Ok(())
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    name.starts_with(|c: char| c == '_' || c.is_ascii_alphabetic())
+        && name.chars().all(|c| c == '_' || c.is_ascii_alphanumeric())
+        && name != "_"
+}
+
+#[derive(Clone)]
 pub struct Bingen {
-    elf: PathBuf,
+    input: PathBuf,
+    format: InputFormat,
+    gap_fill: u8,
 }
 
 impl Bingen {
     pub fn new(elf: impl Into<PathBuf>) -> Self {
-        Self { elf: elf.into() }
+        Self {
+            input: elf.into(),
+            format: InputFormat::Elf,
+            gap_fill: 0,
+        }
+    }
+
+    /// Read the input file as `format`.
+    pub fn input_format(mut self, format: InputFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Fill the gaps between the segments or records with `byte` (`0` by default).
+    pub fn gap_fill(mut self, byte: u8) -> Self {
+        self.gap_fill = byte;
+        self
     }
 
     pub fn run(&self) -> Result<PathBuf> {
@@ -22,7 +136,7 @@ impl Bingen {
 
         self.run_for_file(&output_file)?;
 
-        println!("cargo:rustc-env={}={}", VAR_BIN_FILE, output_file.display());
+        cargo::set_rustc_env(VAR_BIN_FILE, output_file.display());
 
         Ok(output_file)
     }
@@ -30,23 +144,89 @@ impl Bingen {
     pub fn run_for_file(&self, output_file: impl AsRef<Path>) -> Result<()> {
         let output_file = output_file.as_ref();
 
-        eprintln!("Output: {output_file:?}");
+        log::info!("Output: {}", utils::shortest_display(output_file, "."));
 
         self.write(&mut File::create(output_file)?)
     }
 
     pub fn write(&self, output: &mut impl Write) -> Result<()> {
-        eprintln!("Input: {:?}", self.elf);
+        log::info!("Input: {}", utils::shortest_display(&self.input, "."));
+
+        let input = fs::read(&self.input)?;
+        match self.format {
+            InputFormat::Elf => self.write_elf(&input, output),
+            InputFormat::IntelHex => {
+                let hex =
+                    std::str::from_utf8(&input).context("Intel HEX file is not valid utf-8")?;
+                let records = ihex::parse(hex)
+                    .with_context(|| format!("could not parse '{}'", self.input.display()))?;
+                Ok(output.write_all(&ihex::flatten(records, self.gap_fill)?)?)
+            }
+            InputFormat::Raw => Ok(output.write_all(&input)?),
+        }
+    }
+
+    /// Generate a Rust file in `OUT_DIR` embedding the data as configured by `config`
+    /// (see [`Bingen::run_rust_for_file`]), and set the environment variable
+    /// [`VAR_BIN_RUST_FILE`] to its path.
+    pub fn run_rust(&self, config: &RustConfig) -> Result<PathBuf> {
+        let output_file = PathBuf::from(env::var("OUT_DIR")?).join("binary.rs");
+
+        self.run_rust_for_file(config, &output_file)?;
+
+        cargo::set_rustc_env(VAR_BIN_RUST_FILE, output_file.display());
+
+        Ok(output_file)
+    }
+
+    /// Generate the Rust file `output_file` embedding the data as configured by
+    /// `config`, to be included with `include!`.
+    ///
+    /// With [`Embedding::IncludeBytes`] the data is written to `output_file` with the
+    /// extension `bin`.
+    pub fn run_rust_for_file(
+        &self,
+        config: &RustConfig,
+        output_file: impl AsRef<Path>,
+    ) -> Result<()> {
+        config.validate()?;
+        let output_file = output_file.as_ref();
 
-        let elf_data = fs::read(&self.elf)?;
-        let elf = ElfFile::new(&elf_data).map_err(Error::msg)?;
+        log::info!("Output: {}", utils::shortest_display(output_file, "."));
+
+        let mut data = Vec::new();
+        self.write(&mut data)?;
+
+        let bin_file = if config.embedding == Embedding::IncludeBytes {
+            let bin_file = output_file.with_extension("bin");
+            fs::write(&bin_file, &data)?;
+            Some(bin_file.file_name().unwrap().to_string_lossy().into_owned())
+        } else {
+            None
+        };
+
+        let mut rust = String::from("// Generated by embuild's bingen, do not edit.\n");
+        if let Some(align) = config.align {
+            write!(
+                rust,
+                "\n#[repr(C, align({align}))]\npub struct Aligned<T: ?Sized>(pub T);\n"
+            )?;
+        }
+        rust_items(&mut rust, config, &data, bin_file.as_deref())?;
+
+        fs::write(output_file, rust)?;
+        Ok(())
+    }
+
+    fn write_elf(&self, elf_data: &[u8], output: &mut impl Write) -> Result<()> {
+        let elf = ElfFile::new(elf_data).map_err(Error::msg)?;
 
         let mut sorted = segments::segments(&elf).collect::<Vec<_>>();
         sorted.sort();
 
         let mut offset: u64 = 0;
         for segment in sorted {
-            let buf = [0_u8; 4096];
+            let buf = [self.gap_fill; 4096];
             while offset < segment.addr {
                 let delta = cmp::min(buf.len() as u64, segment.addr - offset) as usize;
 
@@ -63,6 +243,184 @@ impl Bingen {
     }
 }
 
+/// Write the items of the data with the name, alignment, section and embedding of
+/// `config` to `rust`.
+fn rust_items(
+    rust: &mut String,
+    config: &RustConfig,
+    data: &[u8],
+    bin_file: Option<&str>,
+) -> Result<()> {
+    let name = &config.name;
+    let link_section = config
+        .link_section
+        .as_ref()
+        .map(|section| format!("#[link_section = {section:?}]\n"))
+        .unwrap_or_default();
+    let aligned = |value: String| match config.align {
+        Some(_) => format!("Aligned({value})"),
+        None => value,
+    };
+    let array_type = |len: &str| match config.align {
+        Some(_) => format!("Aligned<[u8; {len}]>"),
+        None => format!("[u8; {len}]"),
+    };
+
+    write!(
+        rust,
+        "\n/// The length of `{name}` in bytes.\npub const {name}_LEN: usize = {};\n",
+        data.len()
+    )?;
+
+    match (config.embedding, bin_file) {
+        (Embedding::IncludeBytes, Some(bin_file)) => write!(
+            rust,
+            "\n{link_section}pub static {name}: {} = {};\n",
+            array_type(&format!("{name}_LEN")),
+            aligned(format!("*include_bytes!({bin_file:?})")),
+        )?,
+        (Embedding::Chunked { chunk_size }, _) => {
+            let chunks = data.chunks(chunk_size).collect::<Vec<_>>();
+            writeln!(
+                rust,
+                "\n/// The length of the chunks of `{name}` (except the last one) in bytes.\n\
+                 pub const {name}_CHUNK_SIZE: usize = {chunk_size};"
+            )?;
+            for (index, chunk) in chunks.iter().enumerate() {
+                write!(
+                    rust,
+                    "\n{link_section}static {name}_CHUNK_{index}: {} = {};\n",
+                    array_type(&chunk.len().to_string()),
+                    aligned(array_literal(chunk)),
+                )?;
+            }
+
+            let field = if config.align.is_some() { ".0" } else { "" };
+            write!(
+                rust,
+                "\n/// The chunks of `{name}` in order.\npub static {name}_CHUNKS: [&[u8]; {}] = [",
+                chunks.len()
+            )?;
+            for index in 0..chunks.len() {
+                write!(rust, "\n    &{name}_CHUNK_{index}{field},")?;
+            }
+            if !chunks.is_empty() {
+                rust.push('\n');
+            }
+            rust.push_str("];\n");
+        }
+        _ => write!(
+            rust,
+            "\n{link_section}pub static {name}: {} = {};\n",
+            array_type(&format!("{name}_LEN")),
+            aligned(array_literal(data)),
+        )?,
+    }
+
+    Ok(())
+}
+
+/// Format `data` as array literal with 16 bytes per line.
+fn array_literal(data: &[u8]) -> String {
+    let mut literal = String::from("[");
+    for line in data.chunks(16) {
+        literal.push_str("\n   ");
+        for byte in line {
+            write!(literal, " 0x{byte:02x},").unwrap();
+        }
+    }
+    if !data.is_empty() {
+        literal.push('\n');
+    }
+    literal.push(']');
+    literal
+}
+
+mod ihex {
+    use anyhow::{bail, Context, Result};
+
+    /// The data records of an Intel HEX file, as `(address, data)`.
+    pub fn parse(hex: &str) -> Result<Vec<(u64, Vec<u8>)>> {
+        let mut records = Vec::new();
+        let mut base = 0_u64;
+
+        for (index, line) in hex.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let record = parse_record(line)
+                .with_context(|| format!("invalid record on line {}", index + 1))?;
+            let (len, address, r#type, data) = (
+                record[0] as usize,
+                u16::from_be_bytes([record[1], record[2]]),
+                record[3],
+                &record[4..record.len() - 1],
+            );
+            if data.len() != len {
+                bail!(
+                    "record on line {} has length {len}, but {} data bytes",
+                    index + 1,
+                    data.len()
+                );
+            }
+
+            match r#type {
+                0x00 => records.push((base + u64::from(address), data.to_vec())),
+                0x01 => break,
+                0x02 if len == 2 => base = u64::from(u16::from_be_bytes([data[0], data[1]])) << 4,
+                0x04 if len == 2 => base = u64::from(u16::from_be_bytes([data[0], data[1]])) << 16,
+                0x03 | 0x05 => (),
+                _ => bail!(
+                    "unsupported record of type {type:02x} on line {}",
+                    index + 1
+                ),
+            }
+        }
+
+        Ok(records)
+    }
+
+    /// Decode and checksum a record (`:LLAAAATT<data>CC`).
+    fn parse_record(line: &str) -> Result<Vec<u8>> {
+        let Some(hex) = line.strip_prefix(':') else {
+            bail!("missing ':'");
+        };
+        if hex.len() % 2 != 0 || hex.len() < 10 || !hex.is_ascii() {
+            bail!("malformed record");
+        }
+
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<Vec<_>, _>>()
+            .context("malformed record")?;
+        if bytes.iter().fold(0_u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            bail!("wrong checksum");
+        }
+        Ok(bytes)
+    }
+
+    /// Place the data `records` at their address relative to the lowest address and
+    /// fill the gaps with `gap_fill`.
+    pub fn flatten(mut records: Vec<(u64, Vec<u8>)>, gap_fill: u8) -> Result<Vec<u8>> {
+        records.sort_by_key(|(address, _)| *address);
+
+        let start = records.first().map_or(0, |(address, _)| *address);
+        let mut flat = Vec::new();
+        for (address, data) in records {
+            let offset = (address - start) as usize;
+            if offset < flat.len() {
+                bail!("record at address {address:#x} overlaps the previous record");
+            }
+            flat.resize(offset, gap_fill);
+            flat.extend_from_slice(&data);
+        }
+        Ok(flat)
+    }
+}
+
 mod segments {
     use std::cmp::Ordering;
 
@@ -105,3 +463,118 @@ mod segments {
             })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod array {
+        include!("../tests/fixtures/bingen/array.rs");
+    }
+
+    #[cfg(target_os = "linux")]
+    mod aligned {
+        include!("../tests/fixtures/bingen/aligned.rs");
+    }
+
+    mod chunked {
+        include!("../tests/fixtures/bingen/chunked.rs");
+    }
+
+    mod include_bytes {
+        include!("../tests/fixtures/bingen/include_bytes.rs");
+    }
+
+    const DATA: [u8; 20] = [
+        0, 1, 2, 3, 4, 5, 6, 7, 0xff, 0xff, 0xff, 0xff, 0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16,
+        0x17,
+    ];
+
+    fn fixtures() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/bingen")
+    }
+
+    #[test]
+    fn test_intel_hex() {
+        let bingen = Bingen::new(fixtures().join("input.hex")).input_format(InputFormat::IntelHex);
+
+        let mut data = Vec::new();
+        bingen.clone().gap_fill(0xff).write(&mut data).unwrap();
+        assert_eq!(data, DATA);
+
+        data.clear();
+        bingen.write(&mut data).unwrap();
+        assert_eq!(data[8..12], [0; 4]);
+
+        assert!(ihex::parse(":0400000500000000F7").is_ok());
+        assert!(ihex::parse(":0400000500000000F6").is_err());
+        assert!(ihex::flatten(vec![(0, vec![0; 4]), (2, vec![0; 4])], 0).is_err());
+    }
+
+    #[test]
+    fn test_rust() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+
+        let bingen = Bingen::new(fixtures().join("input.hex"))
+            .input_format(InputFormat::IntelHex)
+            .gap_fill(0xff);
+        for (file, config) in [
+            ("array.rs", RustConfig::new("FIRMWARE")),
+            (
+                "aligned.rs",
+                RustConfig::new("FIRMWARE")
+                    .align(16)
+                    .link_section(".rodata.fw"),
+            ),
+            (
+                "chunked.rs",
+                RustConfig::new("FIRMWARE")
+                    .align(4)
+                    .embedding(Embedding::Chunked { chunk_size: 8 }),
+            ),
+            (
+                "include_bytes.rs",
+                RustConfig::new("FIRMWARE")
+                    .align(16)
+                    .embedding(Embedding::IncludeBytes),
+            ),
+        ] {
+            bingen.run_rust_for_file(&config, dir.join(file)).unwrap();
+            assert_eq!(
+                fs::read_to_string(dir.join(file)).unwrap(),
+                fs::read_to_string(fixtures().join(file)).unwrap(),
+                "{file}"
+            );
+        }
+        assert_eq!(fs::read(dir.join("include_bytes.bin")).unwrap(), DATA);
+
+        assert!(bingen
+            .run_rust_for_file(&RustConfig::new("1X"), dir.join("x.rs"))
+            .is_err());
+        assert!(bingen
+            .run_rust_for_file(&RustConfig::new("X").align(3), dir.join("x.rs"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_generated() {
+        assert_eq!(array::FIRMWARE, DATA);
+        #[cfg(target_os = "linux")]
+        {
+            assert_eq!(aligned::FIRMWARE.0, DATA);
+            assert_eq!(std::mem::align_of_val(&aligned::FIRMWARE), 16);
+        }
+        assert_eq!(chunked::FIRMWARE_CHUNKS.concat(), DATA);
+        assert_eq!(chunked::FIRMWARE_CHUNKS[2].len(), 4);
+        assert_eq!(
+            chunked::FIRMWARE_CHUNKS[0].len(),
+            chunked::FIRMWARE_CHUNK_SIZE
+        );
+        assert_eq!(chunked::FIRMWARE_LEN, DATA.len());
+        assert_eq!(chunked::FIRMWARE_CHUNKS[1].as_ptr() as usize % 4, 0);
+        assert_eq!(include_bytes::FIRMWARE.0, DATA);
+        assert_eq!(include_bytes::FIRMWARE_LEN, DATA.len());
+        assert_eq!(std::mem::align_of_val(&include_bytes::FIRMWARE), 16);
+    }
+}
@@ -0,0 +1,380 @@
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+use super::{rust_items, Bingen, Embedding, InputFormat, RustConfig};
+use crate::cargo;
+
+pub const VAR_BIN_MANIFEST_FILE: &str = "EMBUILD_GENERATED_BIN_MANIFEST_FILE";
+
+/// An entry of a [`Manifest`].
+#[derive(Clone)]
+struct Entry {
+    name: String,
+    bingen: Bingen,
+    #[cfg(feature = "gzip")]
+    compress: bool,
+}
+
+/// A set of named binaries, embedded in one generated Rust module together with a
+/// `MANIFEST` of all of them.
+///
+/// Every binary is embedded as a static named after the binary (converted to an
+/// uppercase identifier, e.g. `WEB_UI_GZ` for `web/ui.gz`), and the manifest
+/// `pub static MANIFEST: &[(&str, &[u8])]` maps the names to the contents, sorted by
+/// name.
+#[derive(Clone)]
+pub struct Manifest {
+    entries: Vec<Entry>,
+    align: Option<usize>,
+    link_section: Option<String>,
+    embedding: Embedding,
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+            align: None,
+            link_section: None,
+            embedding: Embedding::Array,
+        }
+    }
+}
+
+impl Manifest {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add the raw binary file `path` as `name`.
+    pub fn add(self, name: impl Into<String>, path: impl Into<PathBuf>) -> Self {
+        self.add_bingen(name, Bingen::new(path).input_format(InputFormat::Raw))
+    }
+
+    /// Add the output of `bingen` (e.g. of an ELF or Intel HEX file) as `name`.
+    pub fn add_bingen(mut self, name: impl Into<String>, bingen: Bingen) -> Self {
+        self.entries.push(Entry {
+            name: name.into(),
+            bingen,
+            #[cfg(feature = "gzip")]
+            compress: false,
+        });
+        self
+    }
+
+    /// Add the raw binary file `path` as `name`, compressed with gzip.
+    ///
+    /// The length of the uncompressed binary is recorded as `<NAME>_ORIGINAL_LEN`.
+    #[cfg(feature = "gzip")]
+    pub fn add_compressed(mut self, name: impl Into<String>, path: impl Into<PathBuf>) -> Self {
+        self = self.add(name, path);
+        self.entries.last_mut().unwrap().compress = true;
+        self
+    }
+
+    /// Add all files in `dir` (recursively) matching the glob `filter` (e.g. `**/*.gz`)
+    /// as raw binaries, named by their path relative to `dir` (with `/` separators).
+    #[cfg(feature = "glob")]
+    pub fn add_dir(mut self, dir: impl AsRef<Path>, filter: impl AsRef<str>) -> Result<Self> {
+        let dir = dir.as_ref();
+        let walker = globwalk::GlobWalkerBuilder::from_patterns(dir, &[filter])
+            .follow_links(true)
+            .file_type(globwalk::FileType::FILE)
+            .build()?;
+
+        for entry in walker {
+            let path = entry?.into_path();
+            let name = path
+                .strip_prefix(dir)?
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            self = self.add(name, path);
+        }
+
+        Ok(self)
+    }
+
+    /// Align every binary to `align` bytes (see [`RustConfig::align`]).
+    pub fn align(mut self, align: usize) -> Self {
+        self.align = Some(align);
+        self
+    }
+
+    /// Place every binary in the linker section `section` (see
+    /// [`RustConfig::link_section`]).
+    pub fn link_section(mut self, section: impl Into<String>) -> Self {
+        self.link_section = Some(section.into());
+        self
+    }
+
+    /// Embed every binary with `embedding`, which must not be [`Embedding::Chunked`].
+    pub fn embedding(mut self, embedding: Embedding) -> Self {
+        self.embedding = embedding;
+        self
+    }
+
+    /// Generate a Rust module in `OUT_DIR` embedding all binaries (see
+    /// [`Manifest::run_for_file`]), set the environment variable
+    /// [`VAR_BIN_MANIFEST_FILE`] to its path and rerun the build script if any input
+    /// changes.
+    pub fn run(&self) -> Result<PathBuf> {
+        let output_file = cargo::out_dir().join("binaries.rs");
+
+        self.run_for_file(&output_file)?;
+
+        for entry in &self.entries {
+            cargo::track_file(&entry.bingen.input);
+        }
+        cargo::set_rustc_env(VAR_BIN_MANIFEST_FILE, output_file.display());
+
+        Ok(output_file)
+    }
+
+    /// Generate the Rust file `output_file` embedding all binaries, to be included with
+    /// `include!`.
+    ///
+    /// With [`Embedding::IncludeBytes`] every binary is written next to `output_file`,
+    /// named `<output file stem>_<name>.bin`.
+    pub fn run_for_file(&self, output_file: impl AsRef<Path>) -> Result<()> {
+        let output_file = output_file.as_ref();
+        if matches!(self.embedding, Embedding::Chunked { .. }) {
+            bail!("binaries of a manifest cannot be chunked");
+        }
+
+        let mut entries = self.entries.iter().collect::<Vec<_>>();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut rust = String::from("// Generated by embuild's bingen, do not edit.\n");
+        if let Some(align) = self.align {
+            write!(
+                rust,
+                "\n#[repr(C, align({align}))]\npub struct Aligned<T: ?Sized>(pub T);\n"
+            )?;
+        }
+
+        let mut idents = HashSet::from(["MANIFEST".to_owned()]);
+        let mut manifest = Vec::new();
+        for entry in entries {
+            let ident = to_identifier(&entry.name);
+            for item in [
+                &ident,
+                &format!("{ident}_LEN"),
+                &format!("{ident}_ORIGINAL_LEN"),
+            ] {
+                if !idents.insert(item.clone()) {
+                    bail!(
+                        "binary '{}' would be embedded as `{ident}`, which collides with another \
+                         binary",
+                        entry.name
+                    );
+                }
+            }
+
+            let mut config = RustConfig::new(&ident).embedding(self.embedding);
+            config.align = self.align;
+            config.link_section.clone_from(&self.link_section);
+            config.validate()?;
+
+            let mut data = Vec::new();
+            entry
+                .bingen
+                .write(&mut data)
+                .with_context(|| format!("could not read binary '{}'", entry.name))?;
+            #[cfg(feature = "gzip")]
+            if entry.compress {
+                writeln!(
+                    rust,
+                    "\n/// The length of the uncompressed `{ident}` in bytes.\n\
+                     pub const {ident}_ORIGINAL_LEN: usize = {};",
+                    data.len()
+                )?;
+                data = gzip(&data)?;
+            }
+
+            let bin_file = if self.embedding == Embedding::IncludeBytes {
+                let stem = output_file
+                    .file_stem()
+                    .unwrap_or_default()
+                    .to_string_lossy();
+                let bin_file = format!("{stem}_{}.bin", ident.to_lowercase());
+                fs::write(output_file.with_file_name(&bin_file), &data)?;
+                Some(bin_file)
+            } else {
+                None
+            };
+            rust_items(&mut rust, &config, &data, bin_file.as_deref())?;
+
+            let field = if self.align.is_some() { ".0" } else { "" };
+            manifest.push(format!("    ({:?}, &{ident}{field}),\n", entry.name));
+        }
+
+        write!(
+            rust,
+            "\n/// The names and contents of all binaries, sorted by name.\n\
+             pub static MANIFEST: &[(&str, &[u8])] = &[\n{}];\n",
+            manifest.concat()
+        )?;
+
+        fs::write(output_file, rust)?;
+        Ok(())
+    }
+}
+
+/// Convert `name` to an uppercase identifier by replacing all characters not allowed
+/// in identifiers with `_`.
+fn to_identifier(name: &str) -> String {
+    let ident = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+
+    if ident.is_empty() || ident.starts_with(|c: char| c.is_ascii_digit()) || ident == "_" {
+        format!("_{ident}")
+    } else {
+        ident
+    }
+}
+
+#[cfg(feature = "gzip")]
+fn gzip(data: &[u8]) -> Result<Vec<u8>> {
+    use std::io::Write;
+
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod assets {
+        include!("../../tests/fixtures/bingen/manifest.rs");
+    }
+
+    fn fixtures() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/bingen")
+    }
+
+    #[test]
+    fn test_manifest() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::write(dir.join("index.html"), "<html></html>").unwrap();
+
+        let manifest = Manifest::new()
+            .add("web/index.html", dir.join("index.html"))
+            .add_bingen(
+                "1st-stage",
+                Bingen::new(fixtures().join("input.hex")).input_format(InputFormat::IntelHex),
+            )
+            .align(4);
+        manifest.run_for_file(dir.join("manifest.rs")).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("manifest.rs")).unwrap(),
+            fs::read_to_string(fixtures().join("manifest.rs")).unwrap()
+        );
+
+        let error = manifest
+            .clone()
+            .add("web-index.html", dir.join("index.html"))
+            .run_for_file(dir.join("manifest.rs"))
+            .unwrap_err();
+        assert!(format!("{error:#}").contains("WEB_INDEX_HTML"), "{error:#}");
+
+        let error = Manifest::new()
+            .add("a", dir.join("index.html"))
+            .add("a.len", dir.join("index.html"))
+            .run_for_file(dir.join("manifest.rs"))
+            .unwrap_err();
+        assert!(format!("{error:#}").contains("collides"), "{error:#}");
+    }
+
+    #[test]
+    fn test_generated() {
+        assert_eq!(
+            assets::MANIFEST
+                .iter()
+                .map(|(name, data)| (*name, data.len()))
+                .collect::<Vec<_>>(),
+            [("1st-stage", 20), ("web/index.html", 13)]
+        );
+        assert_eq!(assets::MANIFEST[1].1, b"<html></html>");
+        assert_eq!(assets::_1ST_STAGE_LEN, 20);
+        assert_eq!(assets::WEB_INDEX_HTML_LEN, 13);
+        assert_eq!(std::mem::align_of_val(&assets::WEB_INDEX_HTML), 4);
+    }
+
+    #[cfg(feature = "glob")]
+    #[test]
+    fn test_add_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::create_dir_all(dir.join("web/img")).unwrap();
+        for file in [
+            "web/index.html",
+            "web/img/logo.png",
+            "web/app.js",
+            "notes.txt",
+        ] {
+            fs::write(dir.join(file), file).unwrap();
+        }
+
+        Manifest::new()
+            .add_dir(dir.join("web"), "**/*.{html,png,js}")
+            .unwrap()
+            .run_for_file(dir.join("assets.rs"))
+            .unwrap();
+
+        let rust = fs::read_to_string(dir.join("assets.rs")).unwrap();
+        assert!(rust.contains(
+            "    (\"app.js\", &APP_JS),\n    (\"img/logo.png\", &IMG_LOGO_PNG),\n    (\"index.html\", &INDEX_HTML),\n];"
+        ), "{rust}");
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_compressed() {
+        use std::io::Read;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::write(dir.join("ui.js"), "x".repeat(1000)).unwrap();
+
+        Manifest::new()
+            .add_compressed("ui.js", dir.join("ui.js"))
+            .embedding(Embedding::IncludeBytes)
+            .run_for_file(dir.join("assets.rs"))
+            .unwrap();
+
+        let rust = fs::read_to_string(dir.join("assets.rs")).unwrap();
+        assert!(
+            rust.contains("pub const UI_JS_ORIGINAL_LEN: usize = 1000;"),
+            "{rust}"
+        );
+        assert!(
+            rust.contains(r#"include_bytes!("assets_ui_js.bin")"#),
+            "{rust}"
+        );
+
+        let compressed = fs::read(dir.join("assets_ui_js.bin")).unwrap();
+        assert!(compressed.len() < 100);
+        let mut data = String::new();
+        flate2::read::GzDecoder::new(&compressed[..])
+            .read_to_string(&mut data)
+            .unwrap();
+        assert_eq!(data, "x".repeat(1000));
+    }
+}
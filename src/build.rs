@@ -2,18 +2,28 @@
 
 #![allow(deprecated)] // TODO: For now
 
-use std::ffi::OsStr;
+use std::collections::{BTreeMap, HashSet};
+use std::ffi::{OsStr, OsString};
 use std::fmt::Display;
 use std::path::{Path, PathBuf};
 use std::{env, vec};
 
 use anyhow::{anyhow, Context, Result};
 
-use crate::cargo::{self, add_link_arg, print_warning, set_metadata, track_file};
+use crate::cargo::{self, add_link_arg, set_metadata, track_file};
 use crate::cli::{self, Arg, ArgDef};
+use crate::logging::warning;
 use crate::utils::OsStrExt;
 
-const C_INCLUDE_ARGS_VAR: &str = "EMBUILD_C_INCLUDE_ARGS";
+mod linker_scripts;
+#[cfg(feature = "serde_json")]
+mod manifest;
+pub use linker_scripts::*;
+#[cfg(feature = "serde_json")]
+pub use manifest::*;
+
+const C_INCLUDE_DIRS_VAR: &str = "EMBUILD_C_INCLUDE_DIRS";
+const C_DEFINES_VAR: &str = "EMBUILD_C_DEFINES";
 const LINK_ARGS_VAR: &str = "EMBUILD_LINK_ARGS";
 const CFG_ARGS_VAR: &str = "EMBUILD_CFG_ARGS";
 
@@ -26,6 +36,10 @@ pub const ENV_PATH_VAR: &str = "EMBUILD_ENV_PATH";
 /// path to the `esp-idf` that they've used.
 pub const ESP_IDF_PATH_VAR: &str = "EMBUILD_ESP_IDF_PATH";
 
+/// The name of a [`cargo::set_metadata`] variable where build scripts can store the
+/// directory the `esp-idf` tools they've used are installed in.
+pub const ESP_IDF_TOOLS_INSTALL_DIR_VAR: &str = "EMBUILD_ESP_IDF_TOOLS_INSTALL_DIR";
+
 const LINK_ARGS_FILE_NAME: &str = "linker_args.txt";
 
 /// The name of the ldproxy executable.
@@ -130,29 +144,170 @@ where
     Ok(items.into_iter())
 }
 
-/// C compiler defines and include arguments (i.e. `-D<define>`, and
-/// `-isystem<dir>`/`-I<dir>`).
-#[derive(Clone, Debug)]
+/// C compiler defines and include directories, which can be propagated to the build
+/// scripts of dependents (see [`CInclArgs::propagate`]).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CInclArgs {
-    // TODO: split into defines and includes, add a way to configure whether includes
-    // should be system headers `-isystem` or normal headers `-I`.
-    pub args: String,
+    /// The include directories, passed as system include directories
+    /// (`-isystem<dir>`).
+    pub include_dirs: Vec<PathBuf>,
+    /// The defines (`-D<name>[=<value>]`), as name and optional value.
+    pub defines: Vec<(String, Option<String>)>,
 }
 
 impl CInclArgs {
+    /// Collect the include directories (`-I`, `-isystem`) and defines (`-D`) of the
+    /// compiler arguments `args`, ignoring all other arguments.
+    pub fn from_args(args: impl IntoIterator<Item = impl AsRef<str>>) -> Self {
+        let mut result = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let arg = arg.as_ref();
+            let mut value = |prefix: &str| -> Option<String> {
+                let value = arg.strip_prefix(prefix)?;
+                if value.is_empty() {
+                    args.next().map(|value| value.as_ref().to_owned())
+                } else {
+                    Some(value.to_owned())
+                }
+            };
+
+            if let Some(dir) = value("-isystem").or_else(|| value("-I")) {
+                result.include_dirs.push(dir.into());
+            } else if let Some(define) = value("-D") {
+                result.defines.push(match define.split_once('=') {
+                    Some((name, value)) => (name.to_owned(), Some(value.to_owned())),
+                    None => (define, None),
+                });
+            }
+        }
+        result
+    }
+
+    /// Get the compiler arguments: all defines (`-D<name>[=<value>]`) followed by all
+    /// include directories (`-isystem<dir>`).
+    pub fn args(&self) -> Vec<String> {
+        self.defines
+            .iter()
+            .map(|(name, value)| match value {
+                Some(value) => format!("-D{name}={value}"),
+                None => format!("-D{name}"),
+            })
+            .chain(
+                self.include_dirs
+                    .iter()
+                    .map(|dir| format!("-isystem{}", dir.display())),
+            )
+            .collect()
+    }
+
+    /// Load the defines and include directories propagated by `lib_name` (see
+    /// [`CInclArgs::from_dep`]).
+    #[deprecated = "use `CInclArgs::from_dep`"]
     pub fn try_from_env(lib_name: impl Display) -> Result<Self> {
-        let args = env::var(format!("DEP_{lib_name}_{C_INCLUDE_ARGS_VAR}"))?;
+        Self::from_dep(lib_name.to_string())
+    }
 
-        Ok(Self { args })
+    /// Load the defines and include directories propagated by the dependency with the
+    /// `links` property value `links` using [`propagate`](CInclArgs::propagate).
+    pub fn from_dep(links: impl AsRef<str>) -> Result<Self> {
+        let vars = DepVars::from_links(links);
+        let defines = vars
+            .args(C_DEFINES_VAR)?
+            .into_iter()
+            .map(|define| match define.split_once('=') {
+                Some((name, value)) => (name.to_owned(), Some(value.to_owned())),
+                None => (define, None),
+            })
+            .collect();
+
+        Ok(Self {
+            include_dirs: vars.paths(C_INCLUDE_DIRS_VAR)?,
+            defines,
+        })
     }
 
-    pub fn propagate(&self) {
-        set_metadata(C_INCLUDE_ARGS_VAR, self.args.as_str());
+    /// Propagate the defines and include directories to all dependents of this crate,
+    /// which can load them with [`CInclArgs::from_dep`].
+    ///
+    /// The include directories are separated by the platform's path separator and the
+    /// defines are quoted, so that both may contain spaces.
+    pub fn propagate(&self) -> Result<()> {
+        let defines = self.defines.iter().map(|(name, value)| match value {
+            Some(value) => format!("{name}={value}"),
+            None => name.clone(),
+        });
+
+        DepVarsWriter::new()
+            .paths(C_INCLUDE_DIRS_VAR, &self.include_dirs)?
+            .args(C_DEFINES_VAR, defines);
+        Ok(())
+    }
+
+    /// Add the defines and include directories to the clang args of `factory`, so
+    /// that it generates bindings with the same flags.
+    #[cfg(feature = "bindgen")]
+    pub fn extend_factory(&self, factory: crate::bindgen::Factory) -> crate::bindgen::Factory {
+        factory.with_clang_args(self.args())
+    }
+}
+
+/// The kind of a linker, which determines how options only understood by the linker
+/// itself are passed to it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LinkerKind {
+    /// A compiler driver (e.g. `gcc` or `clang`, also when linking through `ldproxy`),
+    /// which takes linker options as `-Wl,<option>`.
+    #[default]
+    Gcc,
+    /// A linker that is invoked directly (e.g. `ld` or `ld.lld`), which takes linker
+    /// options as is.
+    Ld,
+}
+
+impl LinkerKind {
+    /// Detect the kind of the `linker` from its file name: `ld`, `*-ld`, `ld.*` and
+    /// `*lld` are [`LinkerKind::Ld`], everything else is [`LinkerKind::Gcc`].
+    pub fn detect(linker: impl AsRef<Path>) -> Self {
+        let name = linker
+            .as_ref()
+            .file_name()
+            .and_then(OsStr::to_str)
+            .unwrap_or_default();
+        let name = name.strip_suffix(".exe").unwrap_or(name);
+
+        if name == "ld" || name.ends_with("-ld") || name.starts_with("ld.") || name.ends_with("lld")
+        {
+            Self::Ld
+        } else {
+            Self::Gcc
+        }
+    }
+
+    /// Get the arguments passing the linker `option` to this kind of linker.
+    fn option(self, option: &str) -> String {
+        match self {
+            Self::Gcc => format!("-Wl,{option}"),
+            Self::Ld => option.to_owned(),
+        }
     }
 }
 
 /// A builder for outputing linker arguments in a build script.
 ///
+/// The arguments are output in a deterministic order: the library directories, the
+/// libraries and then the other flags, with the following adjustments:
+/// - duplicate libraries are removed if [`dedup_libs`](LinkArgsBuilder::dedup_libs) is
+///   set,
+/// - the [`link_group`](LinkArgsBuilder::link_group) libraries are wrapped in a link
+///   group,
+/// - the parent directories of all linker scripts (`-T <script>`) are added as library
+///   directories (`-L <dir>`) before the first linker script, so that the scripts they
+///   `INCLUDE` are found, as are the directories of the scripts these include (see
+///   [`LinkerScripts`]; includes that are not found are warned about),
+/// - `-Wl,<options>` arguments are unwrapped for a [`LinkerKind::Ld`] linker.
+///
 /// Can be constructed with:
 /// - `TryFrom<&`[`crate::cmake::file_api::codemodel::target::Link`]`>`
 /// - `TryFrom<&`[`crate::pio::project::SconsVariables`]`>`
@@ -168,6 +323,10 @@ pub struct LinkArgsBuilder {
     /// The working directory that should be set when linking.
     pub(crate) working_directory: Option<PathBuf>,
     pub(crate) dedup_libs: bool,
+    /// The kind of the linker, detected from the `linker` if not set.
+    pub(crate) linker_kind: Option<LinkerKind>,
+    /// The names of the libraries wrapped in a link group.
+    pub(crate) link_group: Vec<String>,
 }
 
 impl LinkArgsBuilder {
@@ -191,18 +350,67 @@ impl LinkArgsBuilder {
         self
     }
 
+    /// Whether duplicate libraries should be removed.
+    ///
+    /// Only the last occurrence of every library (`-l<name>` or a library file) is kept,
+    /// as a static library only resolves the undefined symbols of the libraries before
+    /// it. Libraries in a `--whole-archive` region are always kept. `ldproxy` is also
+    /// instructed to remove duplicates (see [`LDPROXY_DEDUP_LIBS_ARG`]).
     pub fn dedup_libs(mut self, dedup: bool) -> Self {
         self.dedup_libs = dedup;
         self
     }
 
+    /// The kind of the linker.
+    ///
+    /// If not set it is [detected](LinkerKind::detect) from the
+    /// [`linker`](LinkArgsBuilder::linker), or is [`LinkerKind::Gcc`] without a linker.
+    pub fn linker_kind(mut self, kind: LinkerKind) -> Self {
+        self.linker_kind = Some(kind);
+        self
+    }
+
+    /// Wrap the libraries named `libs` (e.g. `freertos` for `-lfreertos` and
+    /// `path/to/libfreertos.a`) in a link group (`--start-group`/`--end-group`), so that
+    /// circular dependencies between them are resolved.
+    ///
+    /// The group replaces the first of these libraries, all others are moved into it in
+    /// the order they appear in.
+    pub fn link_group(mut self, libs: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.link_group = libs.into_iter().map(Into::into).collect();
+        self
+    }
+
     pub fn build(self) -> Result<LinkArgs> {
-        let args: Vec<_> = self
+        let kind = self.linker_kind.unwrap_or_else(|| {
+            self.linker
+                .as_deref()
+                .map_or(LinkerKind::Gcc, LinkerKind::detect)
+        });
+
+        let mut args: Vec<_> = self
             .libdirflags
             .into_iter()
             .chain(self.libflags)
             .chain(self.linkflags)
             .collect();
+        if kind == LinkerKind::Ld {
+            args = unwrap_linker_options(args);
+        }
+        if self.dedup_libs {
+            args = dedup_libs(args);
+        }
+        if !self.link_group.is_empty() {
+            args = group_libs(args, &self.link_group, kind);
+        }
+        let scripts = LinkerScripts::discover(&args, self.working_directory.as_deref());
+        for (script, include) in &scripts.missing {
+            warning!(
+                "Linker script '{include}' included by '{}' was not found",
+                script.display()
+            );
+        }
+        let args = add_linker_script_dirs(args, &scripts);
 
         let detected_ldproxy = env::var("RUSTC_LINKER")
             .ok()
@@ -215,10 +423,10 @@ impl LinkArgsBuilder {
             .unwrap_or(false);
 
         if self.force_ldproxy && !detected_ldproxy {
-            print_warning(
+            warning!(
                 "The linker arguments force the usage of `ldproxy` but the linker used \
                  by cargo is different. Please set the linker to `ldproxy` in your cargo config \
-                 or set `force_ldproxy` to `false`.",
+                 or set `force_ldproxy` to `false`."
             );
         }
 
@@ -226,7 +434,9 @@ impl LinkArgsBuilder {
             let mut result = Vec::new();
 
             if let Some(linker) = &self.linker {
-                result.extend(LDPROXY_LINKER_ARG.format(Some(linker.try_to_str()?)));
+                result.extend(
+                    LDPROXY_LINKER_ARG.format(Some(linker.try_to_str_with_context("linker")?)),
+                );
             }
 
             if self.dedup_libs {
@@ -234,7 +444,9 @@ impl LinkArgsBuilder {
             }
 
             if let Some(cwd) = &self.working_directory {
-                result.extend(LDPROXY_WORKING_DIRECTORY_ARG.format(Some(cwd.try_to_str()?)))
+                result.extend(LDPROXY_WORKING_DIRECTORY_ARG.format(Some(
+                    cwd.try_to_str_with_context("linker working directory")?,
+                )))
             }
 
             // If `windows && gcc` we always use reponse files to circumvent the command-line
@@ -255,7 +467,7 @@ impl LinkArgsBuilder {
                     let link_args_file = cargo::out_dir().join(LINK_ARGS_FILE_NAME);
                     let args = cli::join_unix_args(args.iter().map(|s| s.as_str()));
 
-                    std::fs::write(&link_args_file, args).with_context(|| {
+                    crate::fs::write_if_changed(&link_args_file, args).with_context(|| {
                         anyhow!(
                             "could not write link args to file '{}'",
                             link_args_file.display()
@@ -279,6 +491,170 @@ impl LinkArgsBuilder {
     }
 }
 
+/// Split all `-Wl,<option>[,<option>...]` arguments into the options.
+fn unwrap_linker_options(args: Vec<String>) -> Vec<String> {
+    args.into_iter()
+        .flat_map(|arg| match arg.strip_prefix("-Wl,") {
+            Some(options) => options.split(',').map(str::to_owned).collect(),
+            None => vec![arg],
+        })
+        .collect()
+}
+
+/// Get the linker options of `arg`, which are either wrapped in `-Wl,` or the argument
+/// itself.
+fn linker_options(arg: &str) -> Vec<&str> {
+    match arg.strip_prefix("-Wl,") {
+        Some(options) => options.split(',').collect(),
+        None => vec![arg],
+    }
+}
+
+/// Whether `arg` is a library to link, i.e. `-l<name>` or the path of a library file.
+fn is_library(arg: &str) -> bool {
+    if let Some(name) = arg.strip_prefix("-l") {
+        return !name.is_empty();
+    }
+
+    !arg.starts_with('-')
+        && Path::new(arg)
+            .extension()
+            .and_then(OsStr::to_str)
+            .is_some_and(|ext| matches!(ext, "a" | "so" | "lib" | "dylib"))
+}
+
+/// Get the name of the library `arg`, e.g. `foo` for `-lfoo`, `-l:libfoo.a` and
+/// `path/to/libfoo.a`.
+fn library_name(arg: &str) -> Option<&str> {
+    if !is_library(arg) {
+        return None;
+    }
+
+    let file = match arg.strip_prefix("-l") {
+        Some(name) => match name.strip_prefix(':') {
+            Some(file) => file,
+            None => return Some(name),
+        },
+        None => arg.rsplit(['/', '\\']).next().unwrap_or(arg),
+    };
+    let file = file.strip_prefix("lib").unwrap_or(file);
+    Some(file.split('.').next().unwrap_or(file))
+}
+
+/// Remove all but the last occurrence of every library outside of `--whole-archive`
+/// regions.
+fn dedup_libs(args: Vec<String>) -> Vec<String> {
+    let mut whole_archive = false;
+    let in_whole_archive = args
+        .iter()
+        .map(|arg| {
+            let inside = whole_archive;
+            for option in linker_options(arg) {
+                match option {
+                    "--whole-archive" => whole_archive = true,
+                    "--no-whole-archive" => whole_archive = false,
+                    _ => (),
+                }
+            }
+            inside
+        })
+        .collect::<Vec<_>>();
+
+    let mut seen = HashSet::new();
+    let mut keep = args
+        .iter()
+        .zip(in_whole_archive)
+        .rev()
+        .map(|(arg, whole_archive)| whole_archive || !is_library(arg) || seen.insert(arg.clone()))
+        .collect::<Vec<_>>();
+    keep.reverse();
+
+    args.into_iter()
+        .zip(keep)
+        .filter_map(|(arg, keep)| keep.then_some(arg))
+        .collect()
+}
+
+/// Move all libraries named `group` into a link group at the position of the first
+/// of them.
+fn group_libs(args: Vec<String>, group: &[String], kind: LinkerKind) -> Vec<String> {
+    let in_group =
+        |arg: &str| library_name(arg).is_some_and(|name| group.iter().any(|g| g == name));
+
+    let Some(first) = args.iter().position(|arg| in_group(arg)) else {
+        return args;
+    };
+    let (libs, mut rest): (Vec<_>, Vec<_>) = args.into_iter().partition(|arg| in_group(arg));
+
+    let grouped = std::iter::once(kind.option("--start-group"))
+        .chain(libs)
+        .chain(std::iter::once(kind.option("--end-group")));
+    rest.splice(first..first, grouped);
+    rest
+}
+
+/// Get the linker scripts referenced by `args` (`-T <script>`, `-T<script>` or
+/// `--script=<script>`, optionally wrapped in `-Wl,`) with the index of their argument.
+fn linker_scripts(args: &[String]) -> Vec<(usize, &str)> {
+    let mut scripts = Vec::new();
+    let mut script_follows = false;
+    for (i, arg) in args.iter().enumerate() {
+        for option in linker_options(arg) {
+            if std::mem::take(&mut script_follows) {
+                scripts.push((i, option));
+            } else if option == "-T" || option == "--script" {
+                script_follows = true;
+            } else if let Some(script) = option.strip_prefix("--script=") {
+                scripts.push((i, script));
+            } else if let Some(script) = option.strip_prefix("-T") {
+                // Exclude `-Ttext=<address>` and similar section options.
+                let is_section = ["text", "data", "bss", "ldata"]
+                    .iter()
+                    .any(|section| script.starts_with(section));
+                if !is_section {
+                    scripts.push((i, script));
+                }
+            }
+        }
+    }
+    scripts
+}
+
+/// Add the parent directories of all linker scripts and of the scripts they include (in
+/// `closure`) as library directories before the first linker script, unless they are
+/// already.
+fn add_linker_script_dirs(mut args: Vec<String>, closure: &LinkerScripts) -> Vec<String> {
+    let scripts = linker_scripts(&args);
+    let Some(&(first, _)) = scripts.first() else {
+        return args;
+    };
+    // The start of the arguments of the first script (`-T <script>`).
+    let first = if first > 0 && linker_options(&args[first - 1]).last() == Some(&"-T") {
+        first - 1
+    } else {
+        first
+    };
+
+    let script_dirs = scripts
+        .iter()
+        .filter_map(|(_, script)| Path::new(script).parent())
+        .chain(closure.included.iter().filter_map(|script| script.parent()));
+
+    let mut dirs = Vec::new();
+    for dir in script_dirs {
+        let Some(dir) = dir.to_str() else {
+            continue;
+        };
+        let dir = format!("-L{dir}");
+        if dir != "-L" && !args.contains(&dir) && !dirs.contains(&dir) {
+            dirs.push(dir);
+        }
+    }
+
+    args.splice(first..first, dirs);
+    args
+}
+
 #[derive(Clone, Debug)]
 pub struct LinkArgs {
     pub args: Vec<String>,
@@ -397,3 +773,257 @@ impl CfgArgs {
         Self::try_from_env(lib_name).map(|args| args.output())
     }
 }
+
+/// The variables exported by a `links` dependency (using [`DepVarsWriter`] or
+/// [`cargo::set_metadata`]), available as `DEP_<links>_<key>` environment variables in
+/// the build scripts of its direct dependents.
+///
+/// `links` doesn't refer to a crate, library or package name, it refers to a
+/// dependency's `links` property value, which is specified in its package manifest
+/// (`Cargo.toml`).
+#[derive(Clone, Debug)]
+pub struct DepVars {
+    links: String,
+    vars: BTreeMap<String, OsString>,
+}
+
+impl DepVars {
+    /// Gather all variables exported by the dependency with the `links` property value
+    /// `links` from the environment.
+    pub fn from_links(links: impl AsRef<str>) -> Self {
+        Self::from_vars(links, env::vars_os())
+    }
+
+    /// Gather all variables exported by the dependency with the `links` property value
+    /// `links` from `vars`.
+    pub fn from_vars(
+        links: impl AsRef<str>,
+        vars: impl IntoIterator<Item = (OsString, OsString)>,
+    ) -> Self {
+        let links = links.as_ref().to_owned();
+        let prefix = format!("DEP_{}_", dep_var_name(&links));
+
+        let vars = vars
+            .into_iter()
+            .filter_map(|(key, value)| {
+                let key = key.into_string().ok()?;
+                Some((key.strip_prefix(&prefix)?.to_owned(), value))
+            })
+            .collect();
+
+        Self { links, vars }
+    }
+
+    /// The `links` property value of the dependency.
+    pub fn links(&self) -> &str {
+        &self.links
+    }
+
+    /// Get the (normalized) keys of all exported variables.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.vars.keys().map(String::as_str)
+    }
+
+    /// Get the raw value of the variable `key`, if exported.
+    pub fn get(&self, key: impl AsRef<str>) -> Option<&OsStr> {
+        self.vars
+            .get(&dep_var_name(key.as_ref()))
+            .map(OsString::as_os_str)
+    }
+
+    /// Get the variable `key` as a string.
+    pub fn string(&self, key: impl AsRef<str>) -> Result<String> {
+        let key = key.as_ref();
+        let value = self.value(key)?;
+        Ok(value
+            .try_to_str_with_context(self.var_context(key))?
+            .to_owned())
+    }
+
+    /// Get the variable `key` as a path.
+    pub fn path(&self, key: impl AsRef<str>) -> Result<PathBuf> {
+        Ok(PathBuf::from(self.value(key.as_ref())?))
+    }
+
+    /// Get the variable `key` as a list of paths (separated by the platform's path
+    /// separator, as written by [`DepVarsWriter::paths`]).
+    pub fn paths(&self, key: impl AsRef<str>) -> Result<Vec<PathBuf>> {
+        Ok(self.value(key.as_ref())?.split_paths_ext())
+    }
+
+    /// Get the variable `key` as a list of (compiler or linker) arguments, as written by
+    /// [`DepVarsWriter::args`].
+    pub fn args(&self, key: impl AsRef<str>) -> Result<Vec<String>> {
+        Ok(cli::UnixCommandArgs::new(&self.string(key)?).collect())
+    }
+
+    fn value(&self, key: &str) -> Result<&OsStr> {
+        self.get(key)
+            .ok_or_else(|| anyhow!("{} is not set", self.var_context(key)))
+    }
+
+    fn var_context(&self, key: &str) -> String {
+        format!(
+            "variable `DEP_{}_{}` (key `{key}` of links dependency `{}`)",
+            dep_var_name(&self.links),
+            dep_var_name(key),
+            self.links
+        )
+    }
+}
+
+/// A writer for variables exported to the build scripts of dependents, the counterpart
+/// of [`DepVars`].
+///
+/// Exporting variables requires the `links` property to be set in this crate's manifest
+/// (`Cargo.toml`).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DepVarsWriter;
+
+impl DepVarsWriter {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Export the string `value` as `key`.
+    pub fn string(&self, key: impl AsRef<str>, value: impl Display) -> &Self {
+        set_metadata(dep_var_name(key.as_ref()), value);
+        self
+    }
+
+    /// Export the `path` as `key`.
+    pub fn path(&self, key: impl AsRef<str>, path: impl AsRef<Path>) -> Result<&Self> {
+        Ok(self.string(key, path.as_ref().try_to_str_with_context("path")?))
+    }
+
+    /// Export the list of `paths` as `key`, separated by the platform's path separator.
+    pub fn paths(
+        &self,
+        key: impl AsRef<str>,
+        paths: impl IntoIterator<Item = impl AsRef<Path>>,
+    ) -> Result<&Self> {
+        let paths = env::join_paths(paths.into_iter().map(|p| p.as_ref().to_owned()))?;
+        Ok(self.string(key, paths.try_to_str_with_context("paths")?))
+    }
+
+    /// Export the list of (compiler or linker) arguments `args` as `key`.
+    pub fn args(
+        &self,
+        key: impl AsRef<str>,
+        args: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> &Self {
+        let args = args
+            .into_iter()
+            .map(|a| a.as_ref().to_owned())
+            .collect::<Vec<_>>();
+        self.string(key, cli::join_unix_args(args.iter().map(String::as_str)))
+    }
+}
+
+/// Normalize `name` like cargo does for the `DEP_<links>_<key>` variables.
+fn dep_var_name(name: &str) -> String {
+    name.to_uppercase().replace('-', "_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_c_incl_args() {
+        let args = CInclArgs::from_args([
+            "-DFOO",
+            "-D",
+            "BAR=a b",
+            "-I/sdk/include",
+            "-isystem",
+            "/sdk/port include",
+            "-O2",
+        ]);
+        assert_eq!(
+            args,
+            CInclArgs {
+                include_dirs: vec!["/sdk/include".into(), "/sdk/port include".into()],
+                defines: vec![("FOO".into(), None), ("BAR".into(), Some("a b".into()))],
+            }
+        );
+        assert_eq!(
+            args.args(),
+            [
+                "-DFOO",
+                "-DBAR=a b",
+                "-isystem/sdk/include",
+                "-isystem/sdk/port include"
+            ]
+        );
+        assert_eq!(CInclArgs::from_args(args.args()), args);
+    }
+
+    #[test]
+    fn test_link_args_ld() {
+        assert_eq!(LinkerKind::detect("xtensa-esp32-elf-ld"), LinkerKind::Ld);
+        assert_eq!(LinkerKind::detect("/usr/bin/ld.lld"), LinkerKind::Ld);
+        assert_eq!(LinkerKind::detect("rust-lld.exe"), LinkerKind::Ld);
+        assert_eq!(LinkerKind::detect("xtensa-esp32-elf-gcc"), LinkerKind::Gcc);
+        assert_eq!(LinkerKind::detect("ldproxy"), LinkerKind::Gcc);
+
+        let args = LinkArgsBuilder {
+            libflags: ["-lb", "-la", "-lb", "x/liba.a"].map(Into::into).to_vec(),
+            linkflags: ["-Wl,--gc-sections,-T,ld/memory.ld"]
+                .map(Into::into)
+                .to_vec(),
+            ..Default::default()
+        }
+        .linker("xtensa-esp32-elf-ld")
+        .dedup_libs(true)
+        .link_group(["a"])
+        .build()
+        .unwrap();
+        assert_eq!(
+            args.args,
+            [
+                "--start-group",
+                "-la",
+                "x/liba.a",
+                "--end-group",
+                "-lb",
+                "--gc-sections",
+                "-Lld",
+                "-T",
+                "ld/memory.ld"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dep_vars() {
+        let include_dirs = env::join_paths(["/sdk/include", "/sdk/port include"]).unwrap();
+        let vars = DepVars::from_vars(
+            "esp-idf",
+            [
+                ("DEP_ESP_IDF_ROOT".into(), "/sdk".into()),
+                ("DEP_ESP_IDF_INCLUDE_DIRS".into(), include_dirs),
+                ("DEP_ESP_IDF_CFLAGS".into(), "-DFOO=1 '-DBAR=a b'".into()),
+                ("DEP_OTHER_ROOT".into(), "/other".into()),
+            ],
+        );
+
+        assert_eq!(
+            vars.keys().collect::<Vec<_>>(),
+            ["CFLAGS", "INCLUDE_DIRS", "ROOT"]
+        );
+        assert_eq!(vars.path("root").unwrap(), PathBuf::from("/sdk"));
+        assert_eq!(
+            vars.paths("include-dirs").unwrap(),
+            [
+                PathBuf::from("/sdk/include"),
+                PathBuf::from("/sdk/port include")
+            ]
+        );
+        assert_eq!(vars.args("cflags").unwrap(), ["-DFOO=1", "-DBAR=a b"]);
+        assert_eq!(
+            vars.string("bindings_file").unwrap_err().to_string(),
+            "variable `DEP_ESP_IDF_BINDINGS_FILE` (key `bindings_file` of links dependency `esp-idf`) is not set"
+        );
+    }
+}
@@ -0,0 +1,331 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use super::{linker_options, linker_scripts};
+use crate::cargo;
+
+/// The linker scripts of a link: the scripts passed with `-T` and all scripts they
+/// (transitively) `INCLUDE`.
+///
+/// Scripts are searched for like the linker does: relative to the working directory of
+/// the linker, then in the library directories (`-L <dir>`) of the link arguments.
+/// Included scripts are additionally searched for in the directories of the scripts
+/// found before, as [`LinkArgsBuilder`](super::LinkArgsBuilder) adds these as library
+/// directories.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LinkerScripts {
+    /// The scripts passed with `-T` that exist, in the order of the arguments.
+    pub scripts: Vec<PathBuf>,
+    /// The scripts included by [`scripts`](Self::scripts) (directly or indirectly), in
+    /// the order they are included in.
+    pub included: Vec<PathBuf>,
+    /// The includes that were not found, with the script including them.
+    ///
+    /// These may be generated later in the build, so they are not an error.
+    pub missing: Vec<(PathBuf, String)>,
+}
+
+impl LinkerScripts {
+    /// Discover the linker scripts of the link arguments `args` of a linker run in
+    /// `working_dir` (or the current directory if [`None`]).
+    ///
+    /// Scripts passed with `-T` that don't exist are skipped.
+    pub fn discover(args: &[String], working_dir: Option<&Path>) -> Self {
+        let working_dir = working_dir.unwrap_or(Path::new(""));
+        let lib_dirs = library_dirs(args)
+            .into_iter()
+            .map(|dir| working_dir.join(dir))
+            .collect::<Vec<_>>();
+
+        let mut result = Self::default();
+        let mut seen = HashSet::new();
+        for (_, script) in linker_scripts(args) {
+            if let Some(path) = find_script(script, working_dir, &lib_dirs, &[]) {
+                if seen.insert(normalize(&path)) {
+                    result.scripts.push(path.clone());
+                    result.add_includes(&path, working_dir, &lib_dirs, &mut seen);
+                }
+            }
+        }
+        result
+    }
+
+    /// Add the scripts included by `script` depth-first.
+    fn add_includes(
+        &mut self,
+        script: &Path,
+        working_dir: &Path,
+        lib_dirs: &[PathBuf],
+        seen: &mut HashSet<PathBuf>,
+    ) {
+        let Ok(contents) = fs::read_to_string(script) else {
+            return;
+        };
+        for include in parse_includes(&contents) {
+            let found = find_script(&include, working_dir, lib_dirs, &self.dirs());
+            match found {
+                Some(path) => {
+                    if seen.insert(normalize(&path)) {
+                        self.included.push(path.clone());
+                        self.add_includes(&path, working_dir, lib_dirs, seen);
+                    }
+                }
+                None => self.missing.push((script.to_owned(), include)),
+            }
+        }
+    }
+
+    /// All scripts, the [`scripts`](Self::scripts) followed by the
+    /// [`included`](Self::included) ones.
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.scripts
+            .iter()
+            .chain(&self.included)
+            .map(PathBuf::as_path)
+    }
+
+    /// The directories of all scripts, without duplicates.
+    pub fn dirs(&self) -> Vec<&Path> {
+        let mut dirs = Vec::new();
+        for dir in self.paths().filter_map(Path::parent) {
+            if !dir.as_os_str().is_empty() && !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+        dirs
+    }
+
+    /// Rerun the build script if any of the scripts changes, and warn about the missing
+    /// includes.
+    pub fn output(&self, output: &mut cargo::Output) {
+        for script in self.paths() {
+            output.rerun_if_changed(script);
+        }
+        for (script, include) in &self.missing {
+            output.warning(format!(
+                "Linker script '{include}' included by '{}' was not found",
+                script.display()
+            ));
+        }
+    }
+
+    /// Rerun the build script if any of the scripts changes (see
+    /// [`output`](Self::output)).
+    pub fn emit(&self) -> Result<()> {
+        let mut output = cargo::Output::new();
+        self.output(&mut output);
+        output.emit()
+    }
+}
+
+/// Discover the linker scripts of the link arguments `args` (see
+/// [`LinkerScripts::discover`]) and rerun the build script if any of them changes.
+pub fn track_linker_scripts(args: &[String], working_dir: Option<&Path>) -> Result<LinkerScripts> {
+    let scripts = LinkerScripts::discover(args, working_dir);
+    scripts.emit()?;
+    Ok(scripts)
+}
+
+/// Get the library directories of `args` (`-L <dir>`, `-L<dir>` or
+/// `--library-path=<dir>`, optionally wrapped in `-Wl,`).
+fn library_dirs(args: &[String]) -> Vec<&str> {
+    let mut dirs = Vec::new();
+    let mut dir_follows = false;
+    for arg in args {
+        for option in linker_options(arg) {
+            if std::mem::take(&mut dir_follows) {
+                dirs.push(option);
+            } else if option == "-L" || option == "--library-path" {
+                dir_follows = true;
+            } else if let Some(dir) = option
+                .strip_prefix("--library-path=")
+                .or_else(|| option.strip_prefix("-L"))
+            {
+                dirs.push(dir);
+            }
+        }
+    }
+    dirs
+}
+
+/// Find the script `name` relative to `working_dir`, in `lib_dirs` or in `script_dirs`.
+fn find_script(
+    name: &str,
+    working_dir: &Path,
+    lib_dirs: &[PathBuf],
+    script_dirs: &[&Path],
+) -> Option<PathBuf> {
+    let name = Path::new(name);
+    if name.is_absolute() {
+        return name.is_file().then(|| name.to_owned());
+    }
+
+    std::iter::once(working_dir)
+        .chain(lib_dirs.iter().map(PathBuf::as_path))
+        .chain(script_dirs.iter().copied())
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// The path used to detect scripts found more than once (e.g. through different library
+/// directories).
+fn normalize(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_owned())
+}
+
+/// Get the files of all `INCLUDE` commands in the linker script `contents`.
+///
+/// The file may be quoted or not, and commands in comments are ignored.
+fn parse_includes(contents: &str) -> Vec<String> {
+    let mut includes = Vec::new();
+    let mut rest = contents;
+    // Whether the next token is the file of an `INCLUDE`.
+    let mut include_follows = false;
+
+    loop {
+        rest = rest.trim_start();
+        if let Some(comment) = rest.strip_prefix("/*") {
+            rest = comment.split_once("*/").map_or("", |(_, rest)| rest);
+            continue;
+        }
+
+        let token;
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let (string, after) = quoted.split_once('"').unwrap_or((quoted, ""));
+            token = string;
+            rest = after;
+        } else {
+            let end = rest
+                .char_indices()
+                .find(|&(i, c)| {
+                    c.is_whitespace() || "\";{}()=,".contains(c) || rest[i..].starts_with("/*")
+                })
+                .map_or(rest.len(), |(i, _)| i);
+            if end == 0 {
+                let Some(c) = rest.chars().next() else {
+                    break;
+                };
+                rest = &rest[c.len_utf8()..];
+                include_follows = false;
+                continue;
+            }
+            token = &rest[..end];
+            rest = &rest[end..];
+        }
+
+        if std::mem::take(&mut include_follows) {
+            includes.push(token.to_owned());
+        } else if token == "INCLUDE" {
+            include_follows = true;
+        }
+    }
+    includes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::LinkArgsBuilder;
+
+    #[test]
+    fn test_parse_includes() {
+        let script = r#"
+/* INCLUDE commented.ld */
+INCLUDE memory.ld
+INCLUDE "sections dir/sections.ld";
+SECTIONS
+{
+  .iram0.text : { INCLUDE iram.ld }
+  /* a comment
+     INCLUDE multiline.ld */
+}
+INCLUDE"last.ld"
+"#;
+        assert_eq!(
+            parse_includes(script),
+            [
+                "memory.ld",
+                "sections dir/sections.ld",
+                "iram.ld",
+                "last.ld"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_discover_linker_scripts() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let ld = dir.join("ld");
+        let generated = dir.join("generated");
+        let nested = ld.join("nested");
+        fs::create_dir_all(&nested).unwrap();
+        fs::create_dir_all(&generated).unwrap();
+
+        fs::write(
+            ld.join("memory.ld"),
+            "/* INCLUDE ignored.ld */\nINCLUDE \"nested/rom.ld\"\nINCLUDE sections.ld\n",
+        )
+        .unwrap();
+        // Found next to the including script, which includes the first script again.
+        fs::write(
+            nested.join("rom.ld"),
+            "INCLUDE rom.api.ld\nINCLUDE memory.ld\n",
+        )
+        .unwrap();
+        fs::write(nested.join("rom.api.ld"), "PROVIDE(foo = 0x40000000);\n").unwrap();
+        // Found in a library directory.
+        fs::write(generated.join("sections.ld"), "INCLUDE sections.ld.in;\n").unwrap();
+
+        let args = [
+            format!("-L{}", generated.display()),
+            "-Wl,-T,ld/memory.ld".into(),
+            "-Tmissing.ld".into(),
+        ];
+        let scripts = LinkerScripts::discover(&args, Some(dir));
+        assert_eq!(scripts.scripts, [dir.join("ld/memory.ld")]);
+        assert_eq!(
+            scripts.included,
+            [
+                nested.join("rom.ld"),
+                nested.join("rom.api.ld"),
+                generated.join("sections.ld")
+            ]
+        );
+        assert_eq!(
+            scripts.missing,
+            [(generated.join("sections.ld"), "sections.ld.in".into())]
+        );
+        assert_eq!(scripts.dirs(), [ld.as_path(), &nested, &generated]);
+
+        let mut output = cargo::Output::new();
+        scripts.output(&mut output);
+        let directives = output.directives().collect::<Vec<_>>();
+        assert_eq!(directives.len(), 5, "{directives:?}");
+        assert_eq!(
+            directives[0],
+            format!("rerun-if-changed={}", dir.join("ld/memory.ld").display())
+        );
+        assert_eq!(
+            directives[3],
+            format!(
+                "rerun-if-changed={}",
+                generated.join("sections.ld").display()
+            )
+        );
+        assert!(directives[4].starts_with("warning=Linker script 'sections.ld.in' included by"));
+
+        // The link args search the directories of the included scripts.
+        let link_args = LinkArgsBuilder {
+            linkflags: args.to_vec(),
+            ..Default::default()
+        }
+        .working_directory(dir)
+        .build()
+        .unwrap();
+        assert!(link_args.args.contains(&format!("-L{}", nested.display())));
+    }
+}
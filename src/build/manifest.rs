@@ -0,0 +1,213 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::cargo;
+use crate::utils::OsStrExt;
+
+/// The environment variable that is available during crate compilation with the path
+/// of the [`BuildManifest`] written by [`BuildManifest::write`].
+///
+/// The manifest can then be embedded with
+/// `include_str!(env!("EMBUILD_BUILD_MANIFEST_FILE"))`.
+pub const VAR_BUILD_MANIFEST_FILE: &str = "EMBUILD_BUILD_MANIFEST_FILE";
+
+/// A tool or component used by the build, as recorded in a [`BuildManifest`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[must_use]
+pub struct ManifestEntry {
+    /// The name of the tool or component, which identifies the entry.
+    pub name: String,
+    /// The version of the tool or component, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// The url it was obtained from, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// The git commit it was built from, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    /// The path it is installed at, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+}
+
+impl ManifestEntry {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn version(mut self, version: impl Into<String>) -> Self {
+        self.version = Some(version.into());
+        self
+    }
+
+    pub fn source(mut self, source: impl Into<String>) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+
+    pub fn commit(mut self, commit: impl Into<String>) -> Self {
+        self.commit = Some(commit.into());
+        self
+    }
+
+    pub fn path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+}
+
+/// Something that used tools or components during the build (e.g. an installed
+/// esp-idf or a cmake build) and can describe them for a [`BuildManifest`].
+pub trait ManifestSource {
+    /// Get the entries of all tools and components used.
+    fn manifest_entries(&self) -> Result<Vec<ManifestEntry>>;
+}
+
+/// A record of the tools and components (and their versions) that produced a build.
+///
+/// The manifest is written as JSON to `OUT_DIR/embuild-manifest.json` by
+/// [`BuildManifest::write`], where it is merged with the entries written earlier.
+///
+/// # Examples
+/// ```ignore
+/// let mut manifest = BuildManifest::new();
+/// manifest.add_from(&esp_idf)?.add_from(&cmake_build)?;
+/// manifest.write()?;
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildManifest {
+    /// All entries, with unique names.
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl BuildManifest {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add `entry`, replacing the entry with the same name.
+    pub fn add(&mut self, entry: ManifestEntry) -> &mut Self {
+        match self.entries.iter_mut().find(|e| e.name == entry.name) {
+            Some(existing) => *existing = entry,
+            None => self.entries.push(entry),
+        }
+        self
+    }
+
+    /// Add all entries of `source` (see [`BuildManifest::add`]).
+    pub fn add_from(&mut self, source: &(impl ManifestSource + ?Sized)) -> Result<&mut Self> {
+        for entry in source.manifest_entries()? {
+            self.add(entry);
+        }
+        Ok(self)
+    }
+
+    /// Get the entry named `name`.
+    pub fn get(&self, name: impl AsRef<str>) -> Option<&ManifestEntry> {
+        self.entries.iter().find(|e| e.name == name.as_ref())
+    }
+
+    /// Read a manifest from the JSON file `path`.
+    pub fn read_from(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = fs::File::open(path)
+            .with_context(|| anyhow!("could not open build manifest '{}'", path.display()))?;
+        serde_json::from_reader(std::io::BufReader::new(file))
+            .with_context(|| anyhow!("invalid build manifest '{}'", path.display()))
+    }
+
+    /// Write this manifest as JSON to `path`, merged into the manifest already there:
+    /// the entries of this manifest replace those with the same name.
+    pub fn merge_into(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let mut merged = if path.exists() {
+            Self::read_from(path)?
+        } else {
+            Self::new()
+        };
+        for entry in &self.entries {
+            merged.add(entry.clone());
+        }
+
+        fs::write(path, serde_json::to_string_pretty(&merged)?)
+            .with_context(|| anyhow!("could not write build manifest '{}'", path.display()))
+    }
+
+    /// Write this manifest to [`default_manifest_file`] (see
+    /// [`BuildManifest::merge_into`]) and set the [`VAR_BUILD_MANIFEST_FILE`]
+    /// environment variable to its path.
+    pub fn write(&self) -> Result<PathBuf> {
+        let file = default_manifest_file();
+        self.merge_into(&file)?;
+        cargo::set_rustc_env(
+            VAR_BUILD_MANIFEST_FILE,
+            file.try_to_str_with_context("build manifest file")?,
+        );
+        Ok(file)
+    }
+}
+
+/// Get the file [`BuildManifest::write`] writes to: `OUT_DIR/embuild-manifest.json`.
+pub fn default_manifest_file() -> PathBuf {
+    cargo::out_dir().join("embuild-manifest.json")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Tools;
+
+    impl ManifestSource for Tools {
+        fn manifest_entries(&self) -> Result<Vec<ManifestEntry>> {
+            Ok(vec![
+                ManifestEntry::new("cmake").version("3.28.1"),
+                ManifestEntry::new("esp-idf")
+                    .version("v5.2.1")
+                    .source("https://github.com/espressif/esp-idf.git")
+                    .commit("a322e6bdad4b6675d4597fb2722eea2851ba88cb"),
+            ])
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let file = dir.join("embuild-manifest.json");
+        let _ = fs::remove_file(&file);
+
+        let mut manifest = BuildManifest::new();
+        manifest
+            .add(ManifestEntry::new("cmake").version("3.20.0"))
+            .add(
+                ManifestEntry::new("clang")
+                    .version("17.0.1")
+                    .path("/usr/bin/clang"),
+            );
+        manifest.merge_into(&file).unwrap();
+
+        let mut manifest = BuildManifest::new();
+        manifest.add_from(&Tools).unwrap();
+        manifest.merge_into(&file).unwrap();
+
+        let merged = BuildManifest::read_from(&file).unwrap();
+        assert_eq!(
+            merged.entries.iter().map(|e| &e.name).collect::<Vec<_>>(),
+            ["cmake", "clang", "esp-idf"]
+        );
+        assert_eq!(
+            merged.get("cmake").unwrap().version.as_deref(),
+            Some("3.28.1")
+        );
+        assert_eq!(merged.get("esp-idf"), manifest.get("esp-idf"));
+        assert!(!fs::read_to_string(&file).unwrap().contains("null"));
+    }
+}
@@ -0,0 +1,567 @@
+//! A facade over the PlatformIO and the cmake build flows.
+//!
+//! Crates supporting both flows select one with [`BuildFlavor::from_env_or`] (the
+//! [`BUILD_FLAVOR_VAR`] environment variable) and run its [`FlavorBuilder`] with
+//! [`Flavors::run`], which prepares and builds the project and collects the link and
+//! include args, the [`bindgen::Factory`] and the environment of the build into a
+//! [`BuildOutput`]. The flavors are implemented by [`PioFlavor`] (with the `pio` feature)
+//! and [`CmakeFlavor`] (with the `cmake` feature).
+//!
+//! ```ignore
+//! let output = Flavors::new(BuildFlavor::Cmake)
+//!     .builder(|| Ok(PioFlavor::new(pio, project, resolution, release)))
+//!     .builder(|| Ok(CmakeFlavor::new(cmake::Runner::new(src, build), "app.elf")))
+//!     .run()?;
+//! output.propagate()?;
+//! ```
+
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context, Error, Result};
+
+use crate::bindgen;
+use crate::build::{CInclArgs, LinkArgs, ENV_PATH_VAR};
+use crate::cargo;
+
+/// The environment variable selecting the [`BuildFlavor`], one of `pio` or `cmake`.
+pub const BUILD_FLAVOR_VAR: &str = "EMBUILD_BUILD_FLAVOR";
+
+/// The build flow used to build the native project.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BuildFlavor {
+    /// A PlatformIO project (`pio`, needs the `pio` feature).
+    Pio,
+    /// A cmake project (`cmake`, needs the `cmake` feature).
+    Cmake,
+}
+
+impl FromStr for BuildFlavor {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "pio" | "platformio" => Ok(Self::Pio),
+            "cmake" | "native" => Ok(Self::Cmake),
+            _ => bail!("Invalid build flavor '{s}', expected one of 'pio' or 'cmake'"),
+        }
+    }
+}
+
+impl Display for BuildFlavor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Pio => "pio",
+            Self::Cmake => "cmake",
+        })
+    }
+}
+
+impl BuildFlavor {
+    /// Get the flavor set by the [`BUILD_FLAVOR_VAR`] environment variable, or
+    /// `default` if it is not set.
+    pub fn from_env_or(default: BuildFlavor) -> Result<BuildFlavor> {
+        cargo::track_env_var(BUILD_FLAVOR_VAR);
+        match env::var(BUILD_FLAVOR_VAR) {
+            Ok(flavor) if !flavor.trim().is_empty() => flavor
+                .parse()
+                .with_context(|| anyhow!("in environment variable `{BUILD_FLAVOR_VAR}`")),
+            _ => Ok(default),
+        }
+    }
+
+    /// The cargo feature of embuild this flavor needs.
+    pub fn feature(self) -> &'static str {
+        match self {
+            Self::Pio => "pio",
+            Self::Cmake => "cmake",
+        }
+    }
+
+    /// Whether the [`feature`](Self::feature) of this flavor is enabled.
+    pub fn is_enabled(self) -> bool {
+        match self {
+            Self::Pio => cfg!(feature = "pio"),
+            Self::Cmake => cfg!(feature = "cmake"),
+        }
+    }
+
+    /// Fail if the [`feature`](Self::feature) of this flavor is not enabled.
+    pub fn check_enabled(self) -> Result<()> {
+        if !self.is_enabled() {
+            bail!(
+                "The {self} build flavor needs the `{feature}` feature of embuild; add `features = [\"{feature}\"]` to the embuild dependency in Cargo.toml, or select another flavor with `{BUILD_FLAVOR_VAR}`",
+                feature = self.feature()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The result of a build, common to all flavors.
+#[derive(Clone, Debug)]
+pub struct BuildOutput {
+    /// The flavor which built the project.
+    pub flavor: BuildFlavor,
+    /// The args for linking the native project into the final binary.
+    pub link_args: LinkArgs,
+    /// The include directories and defines of the native project.
+    pub incl_args: CInclArgs,
+    /// A factory for generating bindings with the flags of the native project.
+    pub bindgen_factory: bindgen::Factory,
+    /// The environment variables of the build (e.g. the `PATH` with the toolchain).
+    pub env: BTreeMap<String, OsString>,
+}
+
+impl BuildOutput {
+    /// Output the [`link_args`](Self::link_args) for this crate, and propagate them, the
+    /// [`incl_args`](Self::incl_args) and the `PATH` of the [`env`](Self::env) (as
+    /// [`ENV_PATH_VAR`]) to the build scripts of dependents.
+    pub fn propagate(&self) -> Result<()> {
+        self.link_args.output();
+        self.link_args.propagate();
+        self.incl_args.propagate()?;
+        if let Some(path) = self.env.get("PATH") {
+            cargo::set_metadata(
+                ENV_PATH_VAR,
+                path.to_str()
+                    .ok_or_else(|| anyhow!("The `PATH` of the build is not valid UTF-8"))?,
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The operations of a build flow, see [`Flavors::run`].
+///
+/// The args and the factory are only available after [`build`](Self::build).
+pub trait FlavorBuilder {
+    /// The flavor of this build flow.
+    const FLAVOR: BuildFlavor;
+
+    /// Generate or configure the project.
+    fn prepare(&mut self) -> Result<()>;
+
+    /// Build the prepared project.
+    fn build(&mut self) -> Result<()>;
+
+    /// The args for linking the built project.
+    fn link_args(&self) -> Result<LinkArgs>;
+
+    /// The include directories and defines of the built project.
+    fn incl_args(&self) -> Result<CInclArgs>;
+
+    /// A factory for generating bindings with the flags of the built project.
+    fn bindgen_factory(&self) -> Result<bindgen::Factory>;
+
+    /// The environment variables of the build.
+    fn env(&self) -> Result<BTreeMap<String, OsString>>;
+
+    /// Prepare and build the project, and collect its [`BuildOutput`].
+    fn run(&mut self) -> Result<BuildOutput> {
+        self.prepare()?;
+        self.build()?;
+
+        Ok(BuildOutput {
+            flavor: Self::FLAVOR,
+            link_args: self.link_args()?,
+            incl_args: self.incl_args()?,
+            bindgen_factory: self.bindgen_factory()?,
+            env: self.env()?,
+        })
+    }
+}
+
+type CreateAndRun<'a> = Box<dyn FnOnce() -> Result<BuildOutput> + 'a>;
+
+/// The builders of the supported flavors, of which the selected one is run.
+#[must_use]
+pub struct Flavors<'a> {
+    default: BuildFlavor,
+    builders: BTreeMap<BuildFlavor, CreateAndRun<'a>>,
+}
+
+impl<'a> Flavors<'a> {
+    /// Select the `default` flavor unless [`BUILD_FLAVOR_VAR`] is set.
+    pub fn new(default: BuildFlavor) -> Self {
+        Self {
+            default,
+            builders: BTreeMap::new(),
+        }
+    }
+
+    /// Support the flavor of `B`, whose builder is created with `create` if selected.
+    pub fn builder<B: FlavorBuilder + 'a>(
+        mut self,
+        create: impl FnOnce() -> Result<B> + 'a,
+    ) -> Self {
+        self.builders
+            .insert(B::FLAVOR, Box::new(move || create()?.run()));
+        self
+    }
+
+    /// The selected flavor.
+    pub fn selected(&self) -> Result<BuildFlavor> {
+        BuildFlavor::from_env_or(self.default)
+    }
+
+    /// Run the builder of the selected flavor.
+    ///
+    /// Fails if the feature of the selected flavor is not enabled (see
+    /// [`BuildFlavor::check_enabled`]), or if it is not supported.
+    pub fn run(mut self) -> Result<BuildOutput> {
+        let flavor = self.selected()?;
+        flavor.check_enabled()?;
+
+        let run = self.builders.remove(&flavor).ok_or_else(|| {
+            let supported = self
+                .builders
+                .keys()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>();
+            anyhow!(
+                "The {flavor} build flavor is not supported by this crate (supported: {})",
+                supported.join(", ")
+            )
+        })?;
+        run().with_context(|| anyhow!("The {flavor} build failed"))
+    }
+}
+
+#[cfg(feature = "pio")]
+pub use self::pio::*;
+
+#[cfg(feature = "pio")]
+mod pio {
+    use std::collections::BTreeMap;
+    use std::convert::TryFrom;
+    use std::ffi::OsString;
+
+    use anyhow::{anyhow, Result};
+
+    use super::{BuildFlavor, FlavorBuilder};
+    use crate::bindgen;
+    use crate::build::{CInclArgs, LinkArgs, LinkArgsBuilder};
+    use crate::pio::project::{self, SconsVariables};
+    use crate::pio::{Pio, Resolution};
+
+    /// The PlatformIO build flow: generates the `project` for the `resolution` and
+    /// builds its debug or `release` environment with `pio`.
+    pub struct PioFlavor {
+        pio: Pio,
+        project: project::Builder,
+        resolution: Resolution,
+        release: bool,
+        vars: Option<SconsVariables>,
+    }
+
+    impl PioFlavor {
+        pub fn new(
+            pio: Pio,
+            project: project::Builder,
+            resolution: Resolution,
+            release: bool,
+        ) -> Self {
+            Self {
+                pio,
+                project,
+                resolution,
+                release,
+                vars: None,
+            }
+        }
+
+        /// The scons variables of the built project.
+        pub fn scons_vars(&self) -> Result<&SconsVariables> {
+            self.vars.as_ref().ok_or_else(|| {
+                anyhow!(
+                    "The PlatformIO project '{}' is not built yet",
+                    self.project.project_dir().display()
+                )
+            })
+        }
+    }
+
+    impl FlavorBuilder for PioFlavor {
+        const FLAVOR: BuildFlavor = BuildFlavor::Pio;
+
+        fn prepare(&mut self) -> Result<()> {
+            self.project.enable_scons_dump();
+            self.project.generate(&self.resolution)?;
+            Ok(())
+        }
+
+        fn build(&mut self) -> Result<()> {
+            self.vars = Some(SconsVariables::from_project(
+                &self.pio,
+                self.project.project_dir(),
+                self.release,
+            )?);
+            Ok(())
+        }
+
+        fn link_args(&self) -> Result<LinkArgs> {
+            let vars = self.scons_vars()?;
+            LinkArgsBuilder::try_from(vars)?
+                .linker(vars.full_path(&vars.link)?)
+                .working_directory(&vars.project_dir)
+                .build()
+        }
+
+        fn incl_args(&self) -> Result<CInclArgs> {
+            let vars = self.scons_vars()?;
+            let mut incl_args = CInclArgs::try_from(vars)?;
+            incl_args.defines = vars.defines();
+            Ok(incl_args)
+        }
+
+        fn bindgen_factory(&self) -> Result<bindgen::Factory> {
+            bindgen::Factory::from_scons_vars(self.scons_vars()?)
+        }
+
+        fn env(&self) -> Result<BTreeMap<String, OsString>> {
+            let vars = self.scons_vars()?;
+            Ok([("PATH".to_owned(), OsString::from(&vars.path))].into())
+        }
+    }
+}
+
+#[cfg(feature = "cmake")]
+pub use self::cmake::*;
+
+#[cfg(feature = "cmake")]
+mod cmake {
+    use std::collections::BTreeMap;
+    use std::convert::TryFrom;
+    use std::ffi::OsString;
+    use std::path::PathBuf;
+
+    use anyhow::{anyhow, Result};
+
+    use super::{BuildFlavor, FlavorBuilder};
+    use crate::bindgen;
+    use crate::build::{CInclArgs, LinkArgs, LinkArgsBuilder};
+    use crate::cmake::file_api::codemodel::target::{CompileGroup, Target};
+    use crate::cmake::file_api::codemodel::Language;
+    use crate::cmake::runner::{Build, Runner};
+
+    /// The cmake build flow: configures and builds the project of the `runner`, and
+    /// takes the link args from the cmake `target` and the include args and bindgen flags
+    /// from its first C compile group.
+    #[derive(Clone, Debug)]
+    pub struct CmakeFlavor {
+        runner: Option<Runner>,
+        target_name: String,
+        build: Option<Build>,
+        target: Option<Target>,
+        compiler: Option<PathBuf>,
+    }
+
+    impl CmakeFlavor {
+        pub fn new(runner: Runner, target: impl Into<String>) -> Self {
+            Self {
+                runner: Some(runner),
+                target_name: target.into(),
+                build: None,
+                target: None,
+                compiler: None,
+            }
+        }
+
+        /// The codemodel target of the built project.
+        pub fn target(&self) -> Result<&Target> {
+            self.target
+                .as_ref()
+                .ok_or_else(|| anyhow!("The cmake target '{}' is not built yet", self.target_name))
+        }
+
+        fn compile_group(&self) -> Result<&CompileGroup> {
+            let target = self.target()?;
+            target
+                .compile_groups
+                .iter()
+                .find(|g| g.language == Language::C)
+                .or_else(|| target.compile_groups.first())
+                .ok_or_else(|| anyhow!("The cmake target '{}' compiles no sources", target.name))
+        }
+    }
+
+    impl FlavorBuilder for CmakeFlavor {
+        const FLAVOR: BuildFlavor = BuildFlavor::Cmake;
+
+        fn prepare(&mut self) -> Result<()> {
+            if let Some(runner) = self.runner.take() {
+                self.build = Some(runner.configure()?);
+            }
+            Ok(())
+        }
+
+        fn build(&mut self) -> Result<()> {
+            self.prepare()?;
+            let build = self.build.as_ref().expect("prepared");
+            build.build()?;
+
+            let replies = build.replies()?;
+            let target = replies
+                .get_codemodel()?
+                .into_first_conf()
+                .get_target(&self.target_name)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "The cmake project '{}' has no target '{}'",
+                        build.source_dir().display(),
+                        self.target_name
+                    )
+                })??;
+            self.compiler = replies
+                .get_toolchains()
+                .ok()
+                .and_then(|mut toolchains| toolchains.take(Language::C)?.compiler.path);
+            self.target = Some(target);
+            Ok(())
+        }
+
+        fn link_args(&self) -> Result<LinkArgs> {
+            let target = self.target()?;
+            let link = target.link.as_ref().ok_or_else(|| {
+                anyhow!(
+                    "The cmake target '{}' is not linked (it must be an executable)",
+                    target.name
+                )
+            })?;
+
+            let mut builder = LinkArgsBuilder::try_from(link)?;
+            if let Some(compiler) = &self.compiler {
+                builder = builder.linker(compiler);
+            }
+            if let Some(build) = &self.build {
+                builder = builder.working_directory(build.build_dir());
+            }
+            builder.build()
+        }
+
+        fn incl_args(&self) -> Result<CInclArgs> {
+            CInclArgs::try_from(self.compile_group()?)
+        }
+
+        fn bindgen_factory(&self) -> Result<bindgen::Factory> {
+            let factory = bindgen::Factory::from_cmake(self.compile_group()?)?;
+            Ok(match &self.compiler {
+                Some(compiler) => factory.with_linker(compiler),
+                None => factory,
+            })
+        }
+
+        fn env(&self) -> Result<BTreeMap<String, OsString>> {
+            let Some(build) = &self.build else {
+                return Ok(BTreeMap::new());
+            };
+            build
+                .env()
+                .map(|(key, value)| {
+                    let key = key
+                        .to_str()
+                        .ok_or_else(|| anyhow!("Invalid environment variable name {key:?}"))?;
+                    Ok((key.to_owned(), value.to_owned()))
+                })
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FakeBuilder(Vec<&'static str>);
+
+    impl FlavorBuilder for FakeBuilder {
+        const FLAVOR: BuildFlavor = BuildFlavor::Cmake;
+
+        fn prepare(&mut self) -> Result<()> {
+            self.0.push("prepare");
+            Ok(())
+        }
+
+        fn build(&mut self) -> Result<()> {
+            if self.0 != ["prepare"] {
+                bail!("not prepared");
+            }
+            Ok(())
+        }
+
+        fn link_args(&self) -> Result<LinkArgs> {
+            Ok(LinkArgs {
+                args: vec!["-lfake".into()],
+            })
+        }
+
+        fn incl_args(&self) -> Result<CInclArgs> {
+            Ok(CInclArgs::from_args(["-Iinclude", "-DFAKE"]))
+        }
+
+        fn bindgen_factory(&self) -> Result<bindgen::Factory> {
+            Ok(bindgen::Factory::new().with_clang_args(["-DFAKE"]))
+        }
+
+        fn env(&self) -> Result<BTreeMap<String, OsString>> {
+            Ok([("PATH".to_owned(), "/fake/bin".into())].into())
+        }
+    }
+
+    #[test]
+    fn test_build_flavor() {
+        assert_eq!("PIO".parse::<BuildFlavor>().unwrap(), BuildFlavor::Pio);
+        assert_eq!(
+            " native".parse::<BuildFlavor>().unwrap(),
+            BuildFlavor::Cmake
+        );
+        assert!("make".parse::<BuildFlavor>().is_err());
+
+        for flavor in [BuildFlavor::Pio, BuildFlavor::Cmake] {
+            match flavor.check_enabled() {
+                Ok(()) => assert!(flavor.is_enabled()),
+                Err(e) => assert!(e
+                    .to_string()
+                    .contains(&format!("add `features = [\"{flavor}\"]`"))),
+            }
+        }
+    }
+
+    #[test]
+    fn test_flavors_run() {
+        let output = Flavors::new(BuildFlavor::Cmake)
+            .builder(|| Ok(FakeBuilder(Vec::new())))
+            .run();
+        if !cfg!(feature = "cmake") {
+            assert!(output.unwrap_err().to_string().contains("`cmake` feature"));
+            return;
+        }
+        let output = output.unwrap();
+        assert_eq!(output.flavor, BuildFlavor::Cmake);
+        assert_eq!(output.link_args.args, ["-lfake"]);
+        assert_eq!(output.incl_args.args(), ["-DFAKE", "-isysteminclude"]);
+        assert_eq!(output.bindgen_factory.clang_args, ["-DFAKE"]);
+        assert_eq!(output.env["PATH"], "/fake/bin");
+
+        let error = Flavors::new(BuildFlavor::Cmake)
+            .builder(|| -> Result<FakeBuilder> { bail!("no cmake") })
+            .run()
+            .unwrap_err();
+        assert_eq!(format!("{error:#}"), "The cmake build failed: no cmake");
+
+        if cfg!(feature = "pio") {
+            let error = Flavors::new(BuildFlavor::Pio)
+                .builder(|| Ok(FakeBuilder(Vec::new())))
+                .run()
+                .unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                "The pio build flavor is not supported by this crate (supported: cmake)"
+            );
+        }
+    }
+}
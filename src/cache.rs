@@ -0,0 +1,277 @@
+//! Version-stamped on-disk caches.
+//!
+//! Every directory (or file) embuild persists across builds has a sidecar
+//! [`CACHE_STAMP_FILE`] recording the version of embuild and of the relevant tools it was
+//! created with, and a schema key of its contents (see [`CacheKey`]). The cache is
+//! checked with [`validate_or_clean`] (or [`validate_or_remove`]) before it is used, and
+//! emptied if anything changed, so that upgrading embuild or a tool doesn't leave stale
+//! contents behind which fail the build in obscure ways.
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::{env, fs, io};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::logging::warning;
+
+/// The name of the stamp file in a cache directory.
+///
+/// The stamp of a cache file `<name>` is the file `<name>.embuild-cache.json` next to it.
+pub const CACHE_STAMP_FILE: &str = "embuild-cache.json";
+
+/// The environment variable which, if set to `1`, keeps stale caches instead of cleaning
+/// them (e.g. to debug their contents).
+pub const KEEP_STALE_CACHE_VAR: &str = "EMBUILD_KEEP_STALE_CACHE";
+
+/// The version of embuild recorded in the stamps.
+pub const EMBUILD_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// What the contents of a cache depend on, as recorded in its [`CACHE_STAMP_FILE`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CacheKey {
+    schema: String,
+    embuild_version: String,
+    #[serde(default)]
+    tools: BTreeMap<String, String>,
+    #[serde(skip)]
+    any_embuild_version: bool,
+}
+
+impl CacheKey {
+    /// Create a key for a cache whose contents have the layout `schema` (e.g.
+    /// `"cmake-build/1"`), created by this version of embuild.
+    ///
+    /// The schema should be changed whenever the contents of the cache become
+    /// incompatible.
+    pub fn new(schema: impl Into<String>) -> Self {
+        Self {
+            schema: schema.into(),
+            embuild_version: EMBUILD_VERSION.to_owned(),
+            tools: BTreeMap::new(),
+            any_embuild_version: false,
+        }
+    }
+
+    /// Make the contents of the cache also depend on the `version` of the tool `name`.
+    pub fn tool(mut self, name: impl Into<String>, version: impl Into<String>) -> Self {
+        self.tools.insert(name.into(), version.into());
+        self
+    }
+
+    /// Don't clean the cache if it was created by another version of embuild.
+    ///
+    /// This is meant for caches shared by builds which may use different versions of
+    /// embuild (e.g. in a global directory), which would otherwise clean each other's
+    /// caches; only the schema then decides whether the contents are compatible.
+    pub fn any_embuild_version(mut self) -> Self {
+        self.any_embuild_version = true;
+        self
+    }
+
+    /// Why a cache with the `stamp` doesn't match this key, if it doesn't.
+    fn mismatch(&self, stamp: &CacheKey) -> Option<String> {
+        if stamp.schema != self.schema {
+            return Some(format!(
+                "its schema changed from '{}' to '{}'",
+                stamp.schema, self.schema
+            ));
+        }
+        if !self.any_embuild_version && stamp.embuild_version != self.embuild_version {
+            return Some(format!(
+                "it was created by embuild {} (now {})",
+                stamp.embuild_version, self.embuild_version
+            ));
+        }
+        self.tools
+            .iter()
+            .find_map(|(name, version)| match stamp.tools.get(name) {
+                Some(old) if old == version => None,
+                Some(old) => Some(format!("{name} changed from {old} to {version}")),
+                None => Some(format!("it was created without {name} {version}")),
+            })
+    }
+}
+
+/// Check the stamp of the cache directory `dir` against the `expected` key, and remove
+/// all contents of the directory if they don't match.
+///
+/// The directory is created if it doesn't exist, and stamped with the `expected` key if
+/// it is empty or was cleaned. A non-empty directory without a stamp is considered stale.
+/// If [`KEEP_STALE_CACHE_VAR`] is set to `1`, a stale directory is kept as is (until
+/// the variable is unset).
+///
+/// Returns whether the directory was cleaned.
+pub fn validate_or_clean(dir: impl AsRef<Path>, expected: &CacheKey) -> Result<bool> {
+    validate_dir(dir.as_ref(), expected, keep_stale())
+}
+
+/// Check the stamp of the cache file `file` against the `expected` key, and remove the
+/// file if they don't match.
+///
+/// Like [`validate_or_clean`], but for a single file whose stamp is
+/// `<file>.embuild-cache.json`.
+///
+/// Returns whether the file was removed.
+pub fn validate_or_remove(file: impl AsRef<Path>, expected: &CacheKey) -> Result<bool> {
+    validate_file(file.as_ref(), expected, keep_stale())
+}
+
+fn keep_stale() -> bool {
+    env::var(KEEP_STALE_CACHE_VAR).as_deref() == Ok("1")
+}
+
+fn validate_dir(dir: &Path, expected: &CacheKey, keep_stale: bool) -> Result<bool> {
+    if !dir.exists() {
+        fs::create_dir_all(dir)
+            .with_context(|| anyhow!("Could not create cache directory '{}'", dir.display()))?;
+    }
+    let empty = fs::read_dir(dir)?.next().is_none();
+
+    validate(
+        dir,
+        &dir.join(CACHE_STAMP_FILE),
+        expected,
+        empty,
+        keep_stale,
+        || {
+            fs::remove_dir_all(dir)?;
+            fs::create_dir_all(dir)
+        },
+    )
+}
+
+fn validate_file(file: &Path, expected: &CacheKey, keep_stale: bool) -> Result<bool> {
+    validate(
+        file,
+        &file_stamp(file),
+        expected,
+        !file.exists(),
+        keep_stale,
+        || fs::remove_file(file),
+    )
+}
+
+/// The stamp file of the cache file `file`.
+fn file_stamp(file: &Path) -> PathBuf {
+    let mut stamp = file.file_name().map(OsString::from).unwrap_or_default();
+    stamp.push(".");
+    stamp.push(CACHE_STAMP_FILE);
+    file.with_file_name(stamp)
+}
+
+fn validate(
+    cache: &Path,
+    stamp_file: &Path,
+    expected: &CacheKey,
+    empty: bool,
+    keep_stale: bool,
+    clean: impl FnOnce() -> io::Result<()>,
+) -> Result<bool> {
+    let reason = match fs::read(stamp_file) {
+        Ok(stamp) => match serde_json::from_slice::<CacheKey>(&stamp) {
+            Ok(stamp) => expected.mismatch(&stamp),
+            Err(err) => Some(format!("its stamp is invalid ({err})")),
+        },
+        Err(err) if err.kind() == io::ErrorKind::NotFound && empty => None,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            Some("it has no stamp (it was created by an older version of embuild)".to_owned())
+        }
+        Err(err) => Some(format!("its stamp could not be read ({err})")),
+    };
+
+    let cleaned = match reason {
+        Some(reason) if keep_stale => {
+            warning!(
+                "Keeping the stale cache '{}' although {reason}, as `{KEEP_STALE_CACHE_VAR}` is set",
+                cache.display()
+            );
+            return Ok(false);
+        }
+        Some(reason) => {
+            warning!("Cleaning the cache '{}', as {reason}", cache.display());
+            clean().with_context(|| anyhow!("Could not clean cache '{}'", cache.display()))?;
+            true
+        }
+        None => false,
+    };
+
+    crate::fs::write_if_changed(stamp_file, serde_json::to_string_pretty(expected)?)?;
+    Ok(cleaned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn old_version(key: CacheKey) -> CacheKey {
+        CacheKey {
+            embuild_version: "0.1.0".into(),
+            ..key
+        }
+    }
+
+    #[test]
+    fn test_validate_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = dir.path().join("cache");
+        let old = old_version(CacheKey::new("test/1").tool("cmake", "3.24.0"));
+        let new = CacheKey::new("test/1").tool("cmake", "3.24.0");
+
+        assert!(!validate_dir(&cache, &old, false).unwrap());
+        assert!(cache.join(CACHE_STAMP_FILE).is_file());
+        fs::write(cache.join("contents"), "old").unwrap();
+        assert!(!validate_dir(&cache, &old, false).unwrap());
+        assert!(cache.join("contents").is_file());
+
+        // Kept while debugging.
+        assert!(!validate_dir(&cache, &new, true).unwrap());
+        assert!(cache.join("contents").is_file());
+
+        // Rebuilt exactly once after the version bump.
+        assert!(validate_dir(&cache, &new, false).unwrap());
+        assert!(!cache.join("contents").exists());
+        fs::write(cache.join("contents"), "new").unwrap();
+        assert!(!validate_dir(&cache, &new, false).unwrap());
+        assert!(cache.join("contents").is_file());
+
+        // Other embuild versions may be accepted, but not other tools or schemas.
+        assert!(!validate_dir(&cache, &old.clone().any_embuild_version(), false).unwrap());
+        assert!(validate_dir(&cache, &new.clone().tool("cmake", "3.28.1"), false).unwrap());
+        assert!(validate_dir(&cache, &CacheKey::new("test/2"), false).unwrap());
+
+        // Contents without a stamp are stale.
+        fs::remove_file(cache.join(CACHE_STAMP_FILE)).unwrap();
+        fs::write(cache.join("contents"), "unknown").unwrap();
+        assert!(validate_dir(&cache, &new, false).unwrap());
+        assert!(!cache.join("contents").exists());
+    }
+
+    #[test]
+    fn test_validate_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let file = dir.join("dump.json");
+        let stamp = dir.join("dump.json.embuild-cache.json");
+        let old = old_version(CacheKey::new("dump/1"));
+        let new = CacheKey::new("dump/1");
+
+        assert!(!validate_file(&file, &old, false).unwrap());
+        assert!(stamp.is_file());
+        fs::write(&file, "old").unwrap();
+        assert!(!validate_file(&file, &old, false).unwrap());
+        assert!(file.is_file());
+
+        assert!(validate_file(&file, &new, false).unwrap());
+        assert!(!file.exists());
+        fs::write(&file, "new").unwrap();
+        assert!(!validate_file(&file, &new, false).unwrap());
+        assert!(file.is_file());
+
+        fs::write(&stamp, "{").unwrap();
+        assert!(validate_file(&file, &new, false).unwrap());
+        assert!(!file.exists());
+    }
+}
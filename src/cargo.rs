@@ -3,6 +3,7 @@
 use std::ffi::OsStr;
 use std::fmt::{Display, Write};
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 use std::{env, fs};
 
 use anyhow::Result;
@@ -10,9 +11,17 @@ use anyhow::Result;
 use cargo_toml::{Manifest, Product};
 use log::*;
 
+use crate::fs::FileLock;
 use crate::utils::{OsStrExt, PathExt};
 use crate::{cargo, cmd};
 
+#[cfg(feature = "manifest")]
+pub mod metadata;
+mod output;
+
+use output::print_directive;
+pub use output::Output;
+
 /// Which cargo command to execute and whether the standard library should be built
 /// locally.
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
@@ -307,50 +316,112 @@ build-std-features = ["panic_immediate_abort"]
 /// All dependent packages of this crate can gets the metadata set here in their build
 /// script from an environment variable named `CARGO_DEP_<links value>_<key>`. The `<links
 /// value>` is the value of the `links` property in this crate's manifest.
+///
+/// This and the other functions printing build script directives print them
+/// immediately. Use [`Output`] to collect, deduplicate and validate directives before
+/// printing them.
 pub fn set_metadata(key: impl Display, value: impl Display) {
-    println!("cargo:{key}={value}");
+    print_directive(format!("metadata={key}={value}"));
 }
 
 /// Add an argument that cargo passes to the linker invocation for this package.
 pub fn add_link_arg(arg: impl Display) {
-    println!("cargo:rustc-link-arg={arg}");
+    print_directive(format!("rustc-link-arg={arg}"));
 }
 
 /// Rerun this build script if the file or directory has changed.
 pub fn track_file(file_or_dir: impl AsRef<Path>) {
-    println!(
-        "cargo:rerun-if-changed={}",
+    print_directive(format!(
+        "rerun-if-changed={}",
         file_or_dir.as_ref().try_to_str().unwrap()
-    )
+    ));
 }
 
 /// Rerun this build script if the environment variable has changed.
 pub fn track_env_var(env_var_name: impl Display) {
-    println!("cargo:rerun-if-env-changed={env_var_name}");
+    print_directive(format!("rerun-if-env-changed={env_var_name}"));
 }
 
 /// Set a cfg key value pair for this package wich may be used for conditional
 /// compilation.
 pub fn set_rustc_cfg(key: impl Display, value: impl AsRef<str>) {
     if value.as_ref().is_empty() {
-        println!("cargo:rustc-cfg={key}");
+        print_directive(format!("rustc-cfg={key}"));
     } else {
-        println!(
-            "cargo:rustc-cfg={}=\"{}\"",
+        print_directive(format!(
+            "rustc-cfg={}=\"{}\"",
             key,
-            value.as_ref().replace('\"', "\\\"")
-        );
+            value.as_ref().escape_debug()
+        ));
     }
 }
 
+/// Declare a cfg that may be set for this package, so that rustc doesn't warn about it
+/// as unexpected (e.g. `cfg(foo)` or `cfg(bar, values("a", "b"))`).
+pub fn set_rustc_check_cfg(check_cfg: impl Display) {
+    print_directive(format!("rustc-check-cfg={check_cfg}"));
+}
+
 /// Set an environment variable that is available during this packages compilation.
 pub fn set_rustc_env(key: impl Display, value: impl Display) {
-    println!("cargo:rustc-env={key}={value}");
+    print_directive(format!("rustc-env={key}={value}"));
 }
 
 /// Display a warning on the terminal.
+///
+/// Multi-line warnings are printed as one directive per line.
 pub fn print_warning(warning: impl Display) {
-    println!("cargo:warning={warning}");
+    for line in warning.to_string().lines() {
+        print_directive(format!("warning={line}"));
+    }
+}
+
+/// Report an error on the terminal, which makes the build fail once the build script
+/// exits.
+///
+/// Multi-line errors are printed as one directive per line. Cargo versions before 1.77
+/// don't support errors, they are printed as `error: `-prefixed warnings instead and
+/// [`check_errors`] must be used to fail the build script.
+pub fn print_error(error: impl Display) {
+    for line in error.to_string().lines() {
+        print_directive(format!("error={line}"));
+    }
+}
+
+/// Fail if any error was reported with [`print_error`] (or [`Output::error`]).
+///
+/// Call this at the end of a build script so that reported errors also fail the build
+/// with cargo versions before 1.77.
+pub fn check_errors() -> Result<()> {
+    if output::ERROR_PRINTED.load(std::sync::atomic::Ordering::Relaxed) {
+        anyhow::bail!("the build script reported errors");
+    }
+    Ok(())
+}
+
+/// Try to `cargo fmt` `file` using any of the current, `stable` and `nightly` toolchains.
+/// If all of them fail print a warning ([`print_warning`]).
+pub fn cargo_fmt_file(file: impl AsRef<Path>) {
+    let file = file.as_ref();
+    // Run rustfmt on the generated code separately, because custom toolchains often do not have rustfmt
+    // We try multiple rustfmt instances:
+    // - The one from the currently active toolchain
+    // - The one from stable
+    // - The one from nightly
+    if cmd!("rustfmt", file).run().is_err()
+        && cmd!("rustup", "run", "stable", "rustfmt", file)
+            .run()
+            .is_err()
+        && cmd!("rustup", "run", "nightly", "rustfmt", file)
+            .run()
+            .is_err()
+    {
+        print_warning(format!(
+            "rustfmt not found in the current toolchain, nor in stable or nightly. \
+             The generated file '{}' will not be properly formatted.",
+            file.display()
+        ));
+    }
 }
 
 /// While in a cargo build script, get the out directory of that crate.
@@ -407,17 +478,23 @@ where
 
 /// Try to get the path to crate workspace dir or [`None`] if unavailable.
 ///
-/// If the environment variable `CARGO_WORKSPACE_DIR` is set, it is returned.
-/// Otherwise, go up the directory tree of the current crate's [`out_dir`] until we're
-/// outside of the target directory.
+/// The workspace directory is the directory containing the `Cargo.lock` file and, by
+/// default, the target directory (the directory where all compilation artifacts are
+/// stored). The result is cached for the lifetime of the process.
 ///
-/// The workspace directory is the directory containing the `Cargo.lock` file and the
-/// target directory (the directory where all compilation artifacts are stored).
+/// The workspace directory is resolved in the following order:
+/// 1. The `CARGO_WORKSPACE_DIR` environment variable, if set.
+/// 2. While in a build script, the parent of the [`target_dir`] if it contains a
+///    `Cargo.lock` file. This also works for build scripts of dependencies (whose
+///    manifest is not part of the workspace), but not if a custom target directory
+///    outside of the workspace is used (e.g. with `cargo build --target-dir /tmp/x`).
+/// 3. The output of `cargo locate-project --workspace` executed in the
+///    `CARGO_MANIFEST_DIR` (or the current directory outside of build scripts), which
+///    also resolves the root of virtual workspaces.
 ///
 /// As there is currently no cargo provided way to get the workspace directory path (see
-/// issue rust-lang/cargo#3946), we try to guess it from the current out dir. If this
-/// approach results in the wrong directory or causes issues, the user can override it by
-/// setting the `CARGO_WORKSPACE_DIR` environment variable.
+/// issue rust-lang/cargo#3946), this may result in the wrong directory in which case the
+/// user can override it by setting the `CARGO_WORKSPACE_DIR` environment variable.
 ///
 /// A neat trick is to add the `CARGO_WORKSPACE_DIR` variable to the `[env]` section of
 /// the workspace's `.cargo/config.toml` file, like this:
@@ -426,23 +503,429 @@ where
 /// CARGO_WORKSPACE_DIR = { value = "", relative = true }
 /// ```
 pub fn workspace_dir() -> Option<PathBuf> {
+    static WORKSPACE_DIR: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+    WORKSPACE_DIR.get_or_init(find_workspace_dir).clone()
+}
+
+/// Resolve the [`workspace_dir`] without caching.
+fn find_workspace_dir() -> Option<PathBuf> {
     match env::var_os("CARGO_WORKSPACE_DIR") {
         Some(dir) if !dir.is_empty() => return Some(dir.into()),
         _ => (),
     };
 
-    // We pop the path to the out dir 6 times to get to the workspace root so the
-    // directory containing the `target` (build) directory. The directory containing the
-    // `target` directory is the the workspace root unless a different for the `target`
-    // directory has been set.
+    let workspace_dir = build_script_target_dir().and_then(|dir| dir.parent().map(Path::to_owned));
+    if let Some(dir) = workspace_dir.filter(|dir| dir.join("Cargo.lock").is_file()) {
+        return Some(dir);
+    }
 
-    // We have to pop one less if `$HOST == $TARGET` because then cargo will compile
-    // directly into the `debug` or `release` directory instead of having that directory
-    // inside of a `<target-triple>` directory.
-    let pop_count = if env::var_os("HOST")? == env::var_os("TARGET")? {
-        5
-    } else {
-        6
+    locate_workspace_manifest()
+        .map_err(|e| debug!("Could not locate the cargo workspace: {e:#}"))
+        .ok()
+        .and_then(|manifest| manifest.parent().map(Path::to_owned))
+}
+
+/// Try to get the path to the cargo target directory or [`None`] if unavailable.
+///
+/// The result is cached for the lifetime of the process.
+///
+/// While in a build script, the target directory is derived from the crate's
+/// [`out_dir`], which is always located inside of the target directory. This is correct
+/// for build scripts of dependencies and if a custom target directory is used (e.g. with
+/// `cargo build --target-dir /tmp/x` or the `CARGO_TARGET_DIR` environment variable).
+///
+/// Otherwise, the `target_directory` of `cargo metadata` executed in the
+/// `CARGO_MANIFEST_DIR` (or the current directory) is returned, which also honors the
+/// `build.target-dir` of the cargo configuration. If cargo can't be run, the
+/// `CARGO_TARGET_DIR` environment variable (relative to the current directory) is
+/// returned if set, or the `target` directory inside of the workspace directory.
+pub fn target_dir() -> Option<PathBuf> {
+    static TARGET_DIR: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+    TARGET_DIR.get_or_init(find_target_dir).clone()
+}
+
+/// Resolve the [`target_dir`] without caching.
+fn find_target_dir() -> Option<PathBuf> {
+    if let Some(dir) = build_script_target_dir() {
+        return Some(dir);
+    }
+
+    let dir = env::var_os("CARGO_MANIFEST_DIR").map_or_else(env::current_dir, |dir| Ok(dir.into()));
+    match dir.map_err(Into::into).and_then(metadata_target_dir_in) {
+        Ok(dir) => return Some(dir),
+        Err(e) => debug!("Could not get the target directory from `cargo metadata`: {e:#}"),
+    }
+
+    match env::var_os("CARGO_TARGET_DIR") {
+        Some(dir) if !dir.is_empty() => return PathBuf::from(dir).abspath().ok(),
+        _ => (),
     };
-    Some(PathBuf::from(env::var_os("OUT_DIR")?).pop_times(pop_count))
+
+    locate_workspace_manifest()
+        .ok()
+        .and_then(|manifest| manifest.parent().map(|dir| dir.join("target")))
+}
+
+/// Get the target directory derived from the `OUT_DIR` of a build script, or [`None`]
+/// if not in a build script or the `OUT_DIR` is not in the cargo layout.
+fn build_script_target_dir() -> Option<PathBuf> {
+    let out_dir = env::var_os("OUT_DIR")?;
+    let target = env::var("TARGET").ok();
+    target_dir_from_out_dir(Path::new(&out_dir), target.as_deref())
+}
+
+/// Get the `target_directory` of `cargo metadata` executed in `dir`.
+fn metadata_target_dir_in(dir: impl AsRef<Path>) -> Result<PathBuf> {
+    let cargo = env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+    let metadata = cmd!(
+        cargo, "metadata", "--format-version", "1", "--no-deps";
+        current_dir=(dir.as_ref())
+    )
+    .stdout()?;
+
+    json_string_field(&metadata, "target_directory")
+        .map(PathBuf::from)
+        .ok_or_else(|| anyhow::anyhow!("no `target_directory` in the output of `cargo metadata`"))
+}
+
+/// Get the value of the first string field `key` of the compact `json` (as printed by
+/// cargo).
+///
+/// Strings inside of the JSON can't match, as their quotes are escaped.
+fn json_string_field(json: &str, key: &str) -> Option<String> {
+    let start = json.find(&format!("\"{key}\":\""))? + key.len() + 4;
+
+    let mut value = String::new();
+    let mut chars = json[start..].chars();
+    loop {
+        match chars.next()? {
+            '"' => return Some(value),
+            '\\' => match chars.next()? {
+                'n' => value.push('\n'),
+                't' => value.push('\t'),
+                'r' => value.push('\r'),
+                'b' => value.push('\u{8}'),
+                'f' => value.push('\u{c}'),
+                'u' => {
+                    let code = chars.by_ref().take(4).collect::<String>();
+                    value.push(char::from_u32(u32::from_str_radix(&code, 16).ok()?)?);
+                }
+                c => value.push(c),
+            },
+            c => value.push(c),
+        }
+    }
+}
+
+/// Get the directory where the final artifacts (binaries, libraries) of the cargo
+/// `profile` are placed, for the target triple `target` if explicitly specified (using
+/// `--target` or `build.target`).
+///
+/// The `dev` and `test` profiles map to the `debug` directory and the `bench` profile to
+/// the `release` directory, all other profiles to the directory of the same name.
+///
+/// Returns [`None`] if the [`target_dir`] could not be determined.
+pub fn build_artifacts_dir(profile: &str, target: Option<&str>) -> Option<PathBuf> {
+    Some(artifacts_dir_in(&target_dir()?, profile, target))
+}
+
+fn artifacts_dir_in(target_dir: &Path, profile: &str, target: Option<&str>) -> PathBuf {
+    let profile_dir = match profile {
+        "dev" | "test" => "debug",
+        "bench" => "release",
+        profile => profile,
+    };
+
+    let mut dir = target_dir.to_owned();
+    if let Some(target) = target {
+        dir.push(target);
+    }
+    dir.push(profile_dir);
+    dir
+}
+
+/// Derive the target directory from the `out_dir` of a build script.
+///
+/// The out dir has the form `<target dir>/[<target triple>/]<profile>/build/<pkg>/out`,
+/// where the `<target triple>` directory only exists if a target was explicitly
+/// specified (which might be the same as the host).
+fn target_dir_from_out_dir(out_dir: &Path, target: Option<&str>) -> Option<PathBuf> {
+    let build_dir = out_dir.parent()?.parent()?;
+    if build_dir.file_name()? != "build" {
+        return None;
+    }
+
+    let dir = build_dir.parent()?.parent()?;
+    match (dir.file_name(), target) {
+        (Some(name), Some(target)) if name == target => dir.parent().map(Path::to_owned),
+        _ => Some(dir.to_owned()),
+    }
+}
+
+/// Create (if needed) and lock the scratch directory `name` of the current crate.
+///
+/// The scratch directory is located at `<target dir>/embuild/<crate name>/<name>` (see
+/// [`target_dir`]), so unlike the [`out_dir`] it is shared by all builds of the crate
+/// (e.g. with different features) and survives `cargo clean -p <crate>`.
+///
+/// The directory is locked until the returned [`ScratchDir`] is dropped, blocking if
+/// another build script (of the same crate) already holds the lock.
+pub fn scratch_dir(name: &str) -> Result<ScratchDir> {
+    let target_dir =
+        target_dir().ok_or_else(|| anyhow::anyhow!("could not determine the target directory"))?;
+    let crate_name = env::var("CARGO_PKG_NAME").map_err(|_| {
+        anyhow::anyhow!(
+            "`CARGO_PKG_NAME` env variable not set (maybe called outside of build script)"
+        )
+    })?;
+
+    ScratchDir::open(target_dir.join("embuild").join(crate_name).join(name))
+}
+
+/// A locked scratch directory (see [`scratch_dir`]).
+///
+/// The lock is released when dropped.
+#[derive(Debug)]
+pub struct ScratchDir {
+    path: PathBuf,
+    _lock: FileLock,
+}
+
+impl ScratchDir {
+    /// The name of the file in the scratch directory storing the manifest hash.
+    const MANIFEST_HASH_FILE: &'static str = ".embuild-manifest-hash";
+
+    /// Create (if needed) and lock the scratch directory `path`.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+
+        let mut lock_file = path.clone().into_os_string();
+        lock_file.push(".lock");
+        let lock = FileLock::acquire(lock_file)?;
+
+        fs::create_dir_all(&path)?;
+        Ok(Self { path, _lock: lock })
+    }
+
+    /// Get the path of the scratch directory.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Remove all contents of the scratch directory.
+    pub fn clean(&self) -> Result<()> {
+        fs::remove_dir_all(&self.path)?;
+        fs::create_dir_all(&self.path)?;
+        Ok(())
+    }
+
+    /// Get the manifest hash stored with [`ScratchDir::set_manifest_hash`], if any.
+    pub fn manifest_hash(&self) -> Option<String> {
+        fs::read_to_string(self.path.join(Self::MANIFEST_HASH_FILE)).ok()
+    }
+
+    /// Store the `hash` of the inputs the contents of the scratch directory were created
+    /// from.
+    pub fn set_manifest_hash(&self, hash: impl AsRef<str>) -> Result<()> {
+        Ok(fs::write(
+            self.path.join(Self::MANIFEST_HASH_FILE),
+            hash.as_ref(),
+        )?)
+    }
+
+    /// Clean the scratch directory if `is_stale` returns `true` when called with the
+    /// stored manifest hash (see [`ScratchDir::set_manifest_hash`]).
+    ///
+    /// Returns whether the directory was cleaned.
+    pub fn mark_stale_if(&self, is_stale: impl FnOnce(Option<&str>) -> bool) -> Result<bool> {
+        let stale = is_stale(self.manifest_hash().as_deref());
+        if stale {
+            debug!("Cleaning stale scratch directory '{}'", self.path.display());
+            self.clean()?;
+        }
+        Ok(stale)
+    }
+}
+
+/// Get the path to the workspace manifest of the crate in `CARGO_MANIFEST_DIR` or the
+/// current directory.
+fn locate_workspace_manifest() -> Result<PathBuf> {
+    match env::var_os("CARGO_MANIFEST_DIR") {
+        Some(dir) => locate_workspace_manifest_in(dir),
+        None => locate_workspace_manifest_in(env::current_dir()?),
+    }
+}
+
+/// Get the path to the workspace manifest of the crate in `dir` using `cargo
+/// locate-project --workspace`.
+fn locate_workspace_manifest_in(dir: impl AsRef<Path>) -> Result<PathBuf> {
+    let cargo = env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+    let manifest = cmd!(
+        cargo, "locate-project", "--workspace", "--message-format", "plain";
+        current_dir=(dir.as_ref())
+    )
+    .stdout()?;
+    Ok(PathBuf::from(manifest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_dir_from_out_dir() {
+        let out_dir = |s: &str| PathBuf::from(format!("{s}/build/foo-0123456789abcdef/out"));
+
+        // Default target dir, no explicit target.
+        assert_eq!(
+            target_dir_from_out_dir(
+                &out_dir("/ws/target/debug"),
+                Some("x86_64-unknown-linux-gnu")
+            ),
+            Some(PathBuf::from("/ws/target"))
+        );
+        // Explicit target.
+        assert_eq!(
+            target_dir_from_out_dir(
+                &out_dir("/ws/target/riscv32imc-esp-espidf/release"),
+                Some("riscv32imc-esp-espidf")
+            ),
+            Some(PathBuf::from("/ws/target"))
+        );
+        // `cargo build --target-dir /tmp/x` with a custom profile.
+        assert_eq!(
+            target_dir_from_out_dir(&out_dir("/tmp/x/release-lto"), None),
+            Some(PathBuf::from("/tmp/x"))
+        );
+        assert_eq!(target_dir_from_out_dir(Path::new("/tmp/out"), None), None);
+    }
+
+    #[test]
+    fn test_artifacts_dir() {
+        let target_dir = Path::new("/tmp/x");
+        assert_eq!(
+            artifacts_dir_in(target_dir, "dev", None),
+            PathBuf::from("/tmp/x/debug")
+        );
+        assert_eq!(
+            artifacts_dir_in(target_dir, "release", Some("xtensa-esp32-espidf")),
+            PathBuf::from("/tmp/x/xtensa-esp32-espidf/release")
+        );
+        assert_eq!(
+            artifacts_dir_in(target_dir, "bench", None),
+            PathBuf::from("/tmp/x/release")
+        );
+    }
+
+    #[test]
+    fn test_locate_virtual_workspace() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let member = dir.join("member");
+        fs::create_dir_all(member.join("src")).unwrap();
+        fs::write(
+            dir.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"member\"]\n",
+        )
+        .unwrap();
+        fs::write(
+            member.join("Cargo.toml"),
+            "[package]\nname = \"member\"\nversion = \"0.1.0\"\n",
+        )
+        .unwrap();
+        fs::write(member.join("src/lib.rs"), "").unwrap();
+
+        let manifest = locate_workspace_manifest_in(&member).unwrap();
+        assert_eq!(
+            manifest.canonicalize().unwrap(),
+            dir.join("Cargo.toml").canonicalize().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_json_string_field() {
+        let json = r#"{"packages":[{"description":"the \"target_directory\":\"x\""}],"target_directory":"C:\\ws\\tar\"get\u00e9","version":1}"#;
+        assert_eq!(
+            json_string_field(json, "target_directory").unwrap(),
+            "C:\\ws\\tar\"get\u{e9}"
+        );
+        assert_eq!(json_string_field(json, "version"), None);
+        assert_eq!(
+            json_string_field(r#"{"target_directory":"/ws"#, "target_directory"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_metadata_target_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::create_dir_all(dir.join(".cargo")).unwrap();
+        fs::write(
+            dir.join("Cargo.toml"),
+            "[package]\nname = \"app\"\nversion = \"0.1.0\"\n\n[workspace]\n",
+        )
+        .unwrap();
+        fs::write(dir.join("src/lib.rs"), "").unwrap();
+
+        // `CARGO_TARGET_DIR` overrides the configuration.
+        if env::var_os("CARGO_TARGET_DIR").is_some() {
+            return;
+        }
+        let target_dir = || metadata_target_dir_in(dir).unwrap();
+        assert_eq!(target_dir(), dir.canonicalize().unwrap().join("target"));
+
+        // `build.target-dir` of the cargo configuration.
+        fs::write(
+            dir.join(".cargo/config.toml"),
+            "[build]\ntarget-dir = \"custom\"\n",
+        )
+        .unwrap();
+        assert_eq!(target_dir(), dir.canonicalize().unwrap().join("custom"));
+    }
+
+    #[test]
+    fn test_scratch_dir_contention() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+        use std::{thread, time::Duration};
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let path = dir.join("scratch");
+        let in_use = Arc::new(AtomicBool::new(false));
+
+        let threads = (0..2)
+            .map(|i| {
+                let path = path.clone();
+                let in_use = in_use.clone();
+                thread::spawn(move || {
+                    let scratch = ScratchDir::open(&path).unwrap();
+                    assert!(!in_use.swap(true, Ordering::SeqCst));
+
+                    scratch
+                        .mark_stale_if(|hash| hash.is_some_and(|h| h != "v1"))
+                        .unwrap();
+                    fs::write(scratch.path().join(format!("{i}")), "").unwrap();
+                    scratch.set_manifest_hash("v1").unwrap();
+                    thread::sleep(Duration::from_millis(300));
+
+                    in_use.store(false, Ordering::SeqCst);
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let scratch = ScratchDir::open(&path).unwrap();
+        assert!(scratch.path().join("0").exists() && scratch.path().join("1").exists());
+        assert!(scratch.mark_stale_if(|hash| hash != Some("v2")).unwrap());
+        assert_eq!(fs::read_dir(scratch.path()).unwrap().count(), 0);
+        drop(scratch);
+    }
 }
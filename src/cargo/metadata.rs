@@ -0,0 +1,349 @@
+//! Typed access to the `[package.metadata.<namespace>]` tables of the current cargo
+//! workspace.
+//!
+//! Crates configured from their dependents' manifests (like `esp-idf-sys` with its
+//! `[package.metadata.esp-idf-sys]` table) read their configuration with
+//! [`CrateMetadata::load`] or [`CrateMetadata::load_as`], which merge the namespace
+//! tables of the following manifests, later ones overriding earlier ones:
+//! 1. `[package.metadata.<namespace>]` of the current package (i.e. the one whose build
+//!    script is running),
+//! 2. `[workspace.metadata.<namespace>]` of the workspace manifest,
+//! 3. `[package.metadata.<namespace>]` of the root package.
+//!
+//! Tables are merged recursively, all other values (including arrays) are replaced.
+//!
+//! The root package is the package of the workspace manifest, or the single default
+//! member of a virtual workspace; virtual workspaces with multiple default members have
+//! no root package.
+
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+
+use crate::{cargo, cmd};
+
+/// The output of `cargo metadata` for the current workspace.
+#[derive(Clone, Debug)]
+pub struct CrateMetadata {
+    json: Value,
+}
+
+/// The namespace table of a single manifest.
+#[derive(Clone, Debug)]
+struct Layer {
+    manifest: PathBuf,
+    key: String,
+    value: Map<String, Value>,
+}
+
+impl CrateMetadata {
+    /// Run `cargo metadata` for the current workspace.
+    ///
+    /// The result is cached for the lifetime of the process.
+    pub fn current() -> Result<&'static CrateMetadata> {
+        static METADATA: OnceLock<CrateMetadata> = OnceLock::new();
+
+        if let Some(metadata) = METADATA.get() {
+            return Ok(metadata);
+        }
+
+        let cargo = env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+        let mut cmd = cmd!(cargo, "metadata", "--format-version", "1");
+        if let Some(dir) = cargo::workspace_dir() {
+            cmd.args([
+                "--manifest-path".as_ref(),
+                dir.join("Cargo.toml").as_os_str(),
+            ]);
+        } else if let Some(dir) = env::var_os("CARGO_MANIFEST_DIR") {
+            cmd.current_dir(dir);
+        }
+
+        let output = cmd.stdout()?;
+        let json = serde_json::from_str(&output).context("could not parse `cargo metadata`")?;
+
+        Ok(METADATA.get_or_init(|| Self::from_json(json)))
+    }
+
+    /// Create from the JSON output of `cargo metadata --format-version 1`.
+    pub fn from_json(json: Value) -> Self {
+        Self { json }
+    }
+
+    /// Load the merged `namespace` tables of the current workspace (see the [module
+    /// documentation](self)).
+    ///
+    /// Rerun the build script if any of the involved manifests changed.
+    pub fn load(namespace: &str) -> Result<Value> {
+        let layers = Self::current()?.layers(namespace, current_manifest().as_deref())?;
+        track(&layers);
+
+        Ok(Value::Object(merge(&layers).0))
+    }
+
+    /// Load the merged `namespace` tables of the current workspace (see the [module
+    /// documentation](self)) and deserialize them as `T`.
+    ///
+    /// Rerun the build script if any of the involved manifests changed.
+    pub fn load_as<T: DeserializeOwned>(namespace: &str) -> Result<T> {
+        let layers = Self::current()?.layers(namespace, current_manifest().as_deref())?;
+        track(&layers);
+
+        deserialize(&layers, namespace)
+    }
+
+    /// Get the merged `namespace` tables with `current_manifest` as the manifest of the
+    /// current package.
+    pub fn namespace(&self, namespace: &str, current_manifest: Option<&Path>) -> Result<Value> {
+        let layers = self.layers(namespace, current_manifest)?;
+        Ok(Value::Object(merge(&layers).0))
+    }
+
+    /// Get the merged `namespace` tables with `current_manifest` as the manifest of the
+    /// current package and deserialize them as `T`.
+    pub fn namespace_as<T: DeserializeOwned>(
+        &self,
+        namespace: &str,
+        current_manifest: Option<&Path>,
+    ) -> Result<T> {
+        let layers = self.layers(namespace, current_manifest)?;
+        deserialize(&layers, namespace)
+    }
+
+    /// Get the workspace root directory.
+    pub fn workspace_root(&self) -> Option<&Path> {
+        self.json["workspace_root"].as_str().map(Path::new)
+    }
+
+    /// Get the `cargo metadata` package object of the root package, if any.
+    pub fn root_package(&self) -> Option<&Value> {
+        let root = match self.json["resolve"]["root"].as_str() {
+            Some(root) => root,
+            None => match self.json["workspace_default_members"].as_array() {
+                Some(members) if members.len() == 1 => members[0].as_str()?,
+                _ => return None,
+            },
+        };
+
+        self.packages().find(|p| p["id"].as_str() == Some(root))
+    }
+
+    /// Get the `cargo metadata` package object with the manifest `manifest`, if any.
+    pub fn package_by_manifest(&self, manifest: &Path) -> Option<&Value> {
+        self.packages()
+            .find(|p| p["manifest_path"].as_str().map(Path::new) == Some(manifest))
+    }
+
+    fn packages(&self) -> impl Iterator<Item = &Value> {
+        self.json["packages"].as_array().into_iter().flatten()
+    }
+
+    /// Collect the namespace tables in order of increasing precedence.
+    fn layers(&self, namespace: &str, current_manifest: Option<&Path>) -> Result<Vec<Layer>> {
+        let mut layers = Vec::new();
+        let mut push = |manifest: PathBuf, key: String, value: &Value| -> Result<()> {
+            match value {
+                Value::Null => (),
+                Value::Object(table) => layers.push(Layer {
+                    manifest,
+                    key,
+                    value: table.clone(),
+                }),
+                _ => bail!("`{key}` in '{}' is not a table", manifest.display()),
+            }
+            Ok(())
+        };
+
+        let root = self.root_package();
+        let current = current_manifest.and_then(|m| self.package_by_manifest(m));
+
+        if let Some(current) =
+            current.filter(|c| Some(c["id"].as_str()) != root.map(|r| r["id"].as_str()))
+        {
+            push(
+                package_manifest(current),
+                format!("package.metadata.{namespace}"),
+                &current["metadata"][namespace],
+            )?;
+        }
+
+        if let Some(workspace_root) = self.workspace_root() {
+            push(
+                workspace_root.join("Cargo.toml"),
+                format!("workspace.metadata.{namespace}"),
+                &self.json["metadata"][namespace],
+            )?;
+        }
+
+        if let Some(root) = root {
+            push(
+                package_manifest(root),
+                format!("package.metadata.{namespace}"),
+                &root["metadata"][namespace],
+            )?;
+        }
+
+        Ok(layers)
+    }
+}
+
+fn package_manifest(package: &Value) -> PathBuf {
+    PathBuf::from(package["manifest_path"].as_str().unwrap_or_default())
+}
+
+/// Get the manifest of the package whose build script is running.
+fn current_manifest() -> Option<PathBuf> {
+    env::var_os("CARGO_MANIFEST_DIR").map(|dir| PathBuf::from(dir).join("Cargo.toml"))
+}
+
+fn track(layers: &[Layer]) {
+    for layer in layers {
+        cargo::track_file(&layer.manifest);
+    }
+}
+
+/// Merge the `layers`, returning the merged table and for every top-level key the index
+/// of the layer its value (last) came from.
+fn merge(layers: &[Layer]) -> (Map<String, Value>, HashMap<String, usize>) {
+    let mut merged = Map::new();
+    let mut sources = HashMap::new();
+
+    for (index, layer) in layers.iter().enumerate() {
+        for (key, value) in &layer.value {
+            merge_value(merged.entry(key.clone()).or_insert(Value::Null), value);
+            sources.insert(key.clone(), index);
+        }
+    }
+
+    (merged, sources)
+}
+
+fn merge_value(target: &mut Value, value: &Value) {
+    match (target, value) {
+        (Value::Object(target), Value::Object(value)) => {
+            for (key, value) in value {
+                merge_value(target.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+        (target, value) => *target = value.clone(),
+    }
+}
+
+/// Deserialize the merged `layers` as `T`.
+///
+/// If this fails, try to find the top-level key responsible for the error by
+/// deserializing every key on its own, so that the error can point to the manifest that
+/// set it.
+fn deserialize<T: DeserializeOwned>(layers: &[Layer], namespace: &str) -> Result<T> {
+    let (merged, sources) = merge(layers);
+
+    let error = match serde_json::from_value(Value::Object(merged.clone())) {
+        Ok(value) => return Ok(value),
+        Err(e) => e,
+    };
+
+    for (key, value) in &merged {
+        let single = Map::from_iter([(key.clone(), value.clone())]);
+        match serde_json::from_value::<T>(Value::Object(single)) {
+            Err(e) if !e.to_string().starts_with("missing field") => {
+                let layer = &layers[sources[key]];
+                return Err(anyhow!(e).context(format!(
+                    "could not parse `{}.{key}` in '{}'",
+                    layer.key,
+                    layer.manifest.display()
+                )));
+            }
+            _ => (),
+        }
+    }
+
+    let manifests = layers
+        .iter()
+        .map(|l| format!("'{}'", l.manifest.display()))
+        .collect::<Vec<_>>();
+    Err(anyhow!(error).context(format!(
+        "could not parse the `{namespace}` metadata of {}",
+        if manifests.is_empty() {
+            "the workspace".to_owned()
+        } else {
+            manifests.join(", ")
+        }
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    fn metadata() -> CrateMetadata {
+        CrateMetadata::from_json(serde_json::json!({
+            "packages": [
+                {
+                    "id": "app 0.1.0 (path+file:///ws/app)",
+                    "manifest_path": "/ws/app/Cargo.toml",
+                    "metadata": {
+                        "ns": { "version": "v5.1", "nested": { "b": 2 } }
+                    }
+                },
+                {
+                    "id": "sys 0.1.0 (registry+https://github.com/rust-lang/crates.io-index)",
+                    "manifest_path": "/registry/sys/Cargo.toml",
+                    "metadata": {
+                        "ns": { "version": "v4.4", "tools": ["a"], "nested": { "a": 1, "b": 1 } }
+                    }
+                }
+            ],
+            "workspace_members": ["app 0.1.0 (path+file:///ws/app)"],
+            "workspace_default_members": ["app 0.1.0 (path+file:///ws/app)"],
+            "resolve": { "root": null },
+            "workspace_root": "/ws",
+            "metadata": {
+                "ns": { "version": "v5.0", "tools": ["b"] }
+            }
+        }))
+    }
+
+    #[test]
+    fn test_precedence() {
+        let metadata = metadata();
+
+        assert_eq!(
+            metadata
+                .namespace("ns", Some(Path::new("/registry/sys/Cargo.toml")))
+                .unwrap(),
+            serde_json::json!({
+                "version": "v5.1",
+                "tools": ["b"],
+                "nested": { "a": 1, "b": 2 }
+            })
+        );
+        assert_eq!(
+            metadata.namespace("other", None).unwrap(),
+            serde_json::json!({})
+        );
+    }
+
+    #[test]
+    fn test_parse_error() {
+        #[derive(Deserialize, Debug)]
+        #[allow(dead_code)]
+        struct Config {
+            version: String,
+            tools: Option<Vec<u32>>,
+        }
+
+        let error = metadata()
+            .namespace_as::<Config>("ns", Some(Path::new("/registry/sys/Cargo.toml")))
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "could not parse `workspace.metadata.ns.tools` in '/ws/Cargo.toml'"
+        );
+    }
+}
@@ -0,0 +1,411 @@
+//! Collecting, deduplicating and emitting build script output.
+//!
+//! Directives are printed with the `cargo::` syntax if the cargo running the build
+//! script supports it (cargo 1.77 or later) and with the old `cargo:` syntax otherwise.
+
+use std::collections::HashSet;
+use std::env;
+use std::fmt::Display;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+use anyhow::{bail, Result};
+
+use crate::cmd;
+use crate::utils::OsStrExt;
+
+/// A collector of cargo build script directives.
+///
+/// Directives are only printed when calling [`Output::emit`], in the order they were
+/// added. Repeated directives that have no further effect (`rerun-if-changed`,
+/// `rerun-if-env-changed`, `rustc-cfg`, `rustc-check-cfg` and `rustc-env`) are skipped,
+/// all others (e.g. link arguments such as `-u sym`, whose order and repetition matter)
+/// are kept. Invalid directives (e.g. a key containing a newline, which
+/// would corrupt the build script output) are reported by [`Output::emit`] without
+/// printing anything.
+#[derive(Debug, Default)]
+pub struct Output {
+    directives: Vec<String>,
+    seen: HashSet<String>,
+    errors: Vec<String>,
+}
+
+impl Output {
+    /// Create an empty collector.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Set an environment variable that is available during this package's compilation.
+    pub fn rustc_env(&mut self, key: impl Display, value: impl Display) -> &mut Self {
+        let (key, value) = (key.to_string(), value.to_string());
+        if key.contains('=') {
+            self.errors
+                .push(format!("environment variable name '{key}' contains a '='"));
+        }
+        self.push(format!("rustc-env={key}={value}"))
+    }
+
+    /// Link the native library `lib` (e.g. `static=foo` or `foo`).
+    pub fn rustc_link_lib(&mut self, lib: impl Display) -> &mut Self {
+        self.push(format!("rustc-link-lib={lib}"))
+    }
+
+    /// Add `dir` to the library search path, optionally with a `kind` (e.g. `native`).
+    pub fn rustc_link_search(&mut self, kind: Option<&str>, dir: impl AsRef<Path>) -> &mut Self {
+        match self.path(dir.as_ref()) {
+            Some(dir) => match kind {
+                Some(kind) => self.push(format!("rustc-link-search={kind}={dir}")),
+                None => self.push(format!("rustc-link-search={dir}")),
+            },
+            None => self,
+        }
+    }
+
+    /// Add an argument that cargo passes to the linker invocation for this package.
+    pub fn rustc_link_arg(&mut self, arg: impl Display) -> &mut Self {
+        self.push(format!("rustc-link-arg={arg}"))
+    }
+
+    /// Rerun this build script if the file or directory has changed.
+    pub fn rerun_if_changed(&mut self, file_or_dir: impl AsRef<Path>) -> &mut Self {
+        match self.path(file_or_dir.as_ref()) {
+            Some(path) => self.push(format!("rerun-if-changed={path}")),
+            None => self,
+        }
+    }
+
+    /// Rerun this build script if any file in `dir` (recursively) matching the glob
+    /// `filter` (e.g. `**/*.h`) has changed.
+    #[cfg(feature = "glob")]
+    pub fn rerun_if_changed_recursive(
+        &mut self,
+        dir: impl AsRef<Path>,
+        filter: impl AsRef<str>,
+    ) -> Result<&mut Self> {
+        let walker = globwalk::GlobWalkerBuilder::from_patterns(dir.as_ref(), &[filter])
+            .follow_links(true)
+            .file_type(globwalk::FileType::FILE)
+            .sort_by(|a, b| a.path().cmp(b.path()))
+            .build()?;
+
+        for entry in walker {
+            self.rerun_if_changed(entry?.path());
+        }
+
+        Ok(self)
+    }
+
+    /// Rerun this build script if the environment variable has changed.
+    pub fn rerun_if_env_changed(&mut self, var: impl Display) -> &mut Self {
+        self.push(format!("rerun-if-env-changed={var}"))
+    }
+
+    /// Set a cfg for this package.
+    pub fn rustc_cfg(&mut self, cfg: impl Display) -> &mut Self {
+        self.push(format!("rustc-cfg={cfg}"))
+    }
+
+    /// Declare a cfg that may be set for this package (see
+    /// [`set_rustc_check_cfg`](super::set_rustc_check_cfg)).
+    pub fn rustc_check_cfg(&mut self, check_cfg: impl Display) -> &mut Self {
+        self.push(format!("rustc-check-cfg={check_cfg}"))
+    }
+
+    /// Display a warning on the terminal, one directive per line.
+    pub fn warning(&mut self, warning: impl Display) -> &mut Self {
+        for line in warning.to_string().lines() {
+            self.push(format!("warning={line}"));
+        }
+        self
+    }
+
+    /// Report an error on the terminal, one directive per line (see
+    /// [`print_error`](super::print_error)).
+    pub fn error(&mut self, error: impl Display) -> &mut Self {
+        for line in error.to_string().lines() {
+            self.push(format!("error={line}"));
+        }
+        self
+    }
+
+    /// Set metadata that gets passed to all dependent's build scripts (see
+    /// [`set_metadata`](super::set_metadata)).
+    pub fn metadata(&mut self, key: impl Display, value: impl Display) -> &mut Self {
+        self.push(format!("metadata={key}={value}"))
+    }
+
+    /// Get all collected directives (without the `cargo::` prefix) in order.
+    pub fn directives(&self) -> impl Iterator<Item = &str> {
+        self.directives.iter().map(String::as_str)
+    }
+
+    /// Print all collected directives and clear this collector.
+    ///
+    /// Fails without printing anything if any of the directives is invalid.
+    pub fn emit(&mut self) -> Result<()> {
+        if !self.errors.is_empty() {
+            let errors = std::mem::take(&mut self.errors);
+            bail!("invalid build script output: {}", errors.join(", "));
+        }
+
+        for directive in self.directives.drain(..) {
+            print_directive(directive);
+        }
+        self.seen.clear();
+
+        Ok(())
+    }
+
+    fn path<'a>(&mut self, path: &'a Path) -> Option<&'a str> {
+        match path.as_os_str().try_to_str() {
+            Ok(path) => Some(path),
+            Err(e) => {
+                self.errors.push(format!("{e:#}"));
+                None
+            }
+        }
+    }
+
+    fn push(&mut self, directive: String) -> &mut Self {
+        if directive.contains(['\n', '\r']) {
+            self.errors
+                .push(format!("'{}' contains a newline", directive.escape_debug()));
+        } else if !is_idempotent(&directive) || self.seen.insert(directive.clone()) {
+            self.directives.push(directive);
+        }
+        self
+    }
+}
+
+/// Whether repeating the `directive` has no further effect, so that it can be skipped.
+fn is_idempotent(directive: &str) -> bool {
+    const KEYS: [&str; 5] = [
+        "rerun-if-changed=",
+        "rerun-if-env-changed=",
+        "rustc-cfg=",
+        "rustc-check-cfg=",
+        "rustc-env=",
+    ];
+
+    KEYS.iter().any(|key| directive.starts_with(key))
+}
+
+/// Print a single build script directive (without the `cargo::` prefix).
+pub(crate) fn print_directive(directive: String) {
+    if directive.starts_with("error=") {
+        ERROR_PRINTED.store(true, Ordering::Relaxed);
+    }
+
+    println!("{}", format_directive(&directive, new_syntax_supported()));
+}
+
+/// Whether an error was printed by this process.
+pub(crate) static ERROR_PRINTED: AtomicBool = AtomicBool::new(false);
+
+/// Format the `directive` (without prefix) with the new `cargo::` syntax if `new_syntax`
+/// is `true`, otherwise with the old `cargo:` syntax.
+///
+/// The old syntax has no `metadata=` directive (any unknown key is metadata) and no
+/// `error=` directive, for which a prefixed warning is used instead.
+fn format_directive(directive: &str, new_syntax: bool) -> String {
+    if new_syntax {
+        format!("cargo::{directive}")
+    } else if let Some(metadata) = directive.strip_prefix("metadata=") {
+        format!("cargo:{metadata}")
+    } else if let Some(error) = directive.strip_prefix("error=") {
+        format!("cargo:warning=error: {error}")
+    } else {
+        format!("cargo:{directive}")
+    }
+}
+
+/// Whether the `cargo::` directive syntax can be used: the cargo running the build script
+/// must be 1.77 or later, and the `rust-version` of the package (if set) must be at least
+/// 1.77, as cargo rejects the new syntax for packages with an older `rust-version`.
+///
+/// The version of the cargo in the `CARGO` environment variable is queried once and
+/// cached for the lifetime of the process. Outside of build scripts the old syntax is
+/// used.
+pub(crate) fn new_syntax_supported() -> bool {
+    static SUPPORTED: OnceLock<bool> = OnceLock::new();
+
+    *SUPPORTED.get_or_init(|| {
+        let Some(cargo) = env::var_os("CARGO") else {
+            return false;
+        };
+
+        let cargo_version = match cmd!(cargo, "--version").stdout() {
+            Ok(version) => parse_cargo_version(&version),
+            Err(e) => {
+                log::debug!("Could not get the cargo version: {e:#}");
+                None
+            }
+        };
+        new_syntax_allowed(
+            cargo_version,
+            env::var("CARGO_PKG_RUST_VERSION").ok().as_deref(),
+        )
+    })
+}
+
+/// Whether the `cargo::` syntax can be used with a cargo of `cargo_version` for a package
+/// with the `rust_version` (the `CARGO_PKG_RUST_VERSION`, empty if not set).
+fn new_syntax_allowed(cargo_version: Option<(u32, u32)>, rust_version: Option<&str>) -> bool {
+    const MIN_VERSION: (u32, u32) = (1, 77);
+
+    let rust_version_ok = match rust_version.map(str::trim).filter(|v| !v.is_empty()) {
+        Some(rust_version) => parse_version(rust_version).is_some_and(|v| v >= MIN_VERSION),
+        None => true,
+    };
+    rust_version_ok && cargo_version.is_some_and(|v| v >= MIN_VERSION)
+}
+
+/// Parse the `(major, minor)` version of the output of `cargo --version` (e.g. `cargo
+/// 1.77.0 (3fe68eabf 2024-02-29)`).
+fn parse_cargo_version(version: &str) -> Option<(u32, u32)> {
+    parse_version(version.trim().strip_prefix("cargo ")?)
+}
+
+/// Parse the `(major, minor)` version of `version` (e.g. `1.77`, `1.77.0` or `1.77.0
+/// (3fe68eabf 2024-02-29)`).
+fn parse_version(version: &str) -> Option<(u32, u32)> {
+    let mut parts = version.split(['.', ' ', '-']);
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dedup_and_order() {
+        let mut output = Output::new();
+        output
+            .rerun_if_changed("b.h")
+            .rustc_env("FOO", "1")
+            .rerun_if_changed("a.h")
+            .rerun_if_changed("b.h")
+            .rustc_link_search(Some("native"), "/lib")
+            .rustc_link_lib("static=foo")
+            .warning("first\nsecond")
+            .rustc_env("FOO", "1");
+
+        assert_eq!(
+            output.directives().collect::<Vec<_>>(),
+            [
+                "rerun-if-changed=b.h",
+                "rustc-env=FOO=1",
+                "rerun-if-changed=a.h",
+                "rustc-link-search=native=/lib",
+                "rustc-link-lib=static=foo",
+                "warning=first",
+                "warning=second",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_link_args_kept() {
+        let mut output = Output::new();
+        for arg in [
+            "-T", "a.ld", "-T", "b.ld", "-u", "x", "-u", "y", "-lc", "-lm", "-lc",
+        ] {
+            output.rustc_link_arg(arg);
+        }
+        output.rustc_link_lib("c").rustc_link_lib("c");
+
+        assert_eq!(
+            output.directives().collect::<Vec<_>>(),
+            [
+                "rustc-link-arg=-T",
+                "rustc-link-arg=a.ld",
+                "rustc-link-arg=-T",
+                "rustc-link-arg=b.ld",
+                "rustc-link-arg=-u",
+                "rustc-link-arg=x",
+                "rustc-link-arg=-u",
+                "rustc-link-arg=y",
+                "rustc-link-arg=-lc",
+                "rustc-link-arg=-lm",
+                "rustc-link-arg=-lc",
+                "rustc-link-lib=c",
+                "rustc-link-lib=c",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid() {
+        let mut output = Output::new();
+        output.rustc_env("FOO", "a\nb").rerun_if_changed("a.h");
+
+        assert!(output.emit().is_err());
+        assert_eq!(output.directives().count(), 1);
+    }
+
+    #[test]
+    fn test_format_directive() {
+        let mut output = Output::new();
+        output
+            .rustc_env("FOO", "1")
+            .metadata("root", "/sdk")
+            .error("failed\nbadly");
+
+        let format = |new_syntax| {
+            output
+                .directives()
+                .map(|d| format_directive(d, new_syntax))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            format(true),
+            [
+                "cargo::rustc-env=FOO=1",
+                "cargo::metadata=root=/sdk",
+                "cargo::error=failed",
+                "cargo::error=badly",
+            ]
+        );
+        assert_eq!(
+            format(false),
+            [
+                "cargo:rustc-env=FOO=1",
+                "cargo:root=/sdk",
+                "cargo:warning=error: failed",
+                "cargo:warning=error: badly",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_cargo_version() {
+        assert_eq!(
+            parse_cargo_version("cargo 1.77.0 (3fe68eabf 2024-02-29)\n"),
+            Some((1, 77))
+        );
+        assert_eq!(
+            parse_cargo_version("cargo 1.70.0-nightly (7bf43f028 2023-03-23)"),
+            Some((1, 70))
+        );
+        assert_eq!(parse_cargo_version("rustc 1.77.0"), None);
+    }
+
+    #[test]
+    fn test_new_syntax_allowed() {
+        assert!(new_syntax_allowed(Some((1, 80)), None));
+        assert!(new_syntax_allowed(Some((1, 80)), Some("")));
+        assert!(new_syntax_allowed(Some((1, 80)), Some("1.77")));
+        assert!(new_syntax_allowed(Some((1, 77)), Some("1.80.1")));
+        // Cargo rejects `cargo::` for packages with an older `rust-version`.
+        assert!(!new_syntax_allowed(Some((1, 80)), Some("1.70")));
+        assert!(!new_syntax_allowed(Some((1, 80)), Some("1.76.0")));
+        assert!(!new_syntax_allowed(Some((1, 80)), Some("invalid")));
+        assert!(!new_syntax_allowed(Some((1, 76)), None));
+        assert!(!new_syntax_allowed(None, Some("1.80")));
+    }
+}
@@ -2,8 +2,12 @@
 
 mod arg;
 mod parse_args;
+mod quote;
+mod response_file;
 mod separate_args;
 
 pub use arg::*;
 pub use parse_args::*;
+pub use quote::*;
+pub use response_file::*;
 pub use separate_args::*;
@@ -0,0 +1,131 @@
+use std::ffi::OsStr;
+use std::fmt::Write;
+
+/// Quote `arg` for a POSIX shell (using bash's `$'...'` quoting where needed) so that it
+/// can be copied and pasted unambiguously.
+///
+/// Arguments only containing safe characters are not quoted, other valid UTF-8
+/// arguments without control characters are single-quoted. Arguments with control
+/// characters or bytes that are not valid UTF-8 are `$'...'`-quoted with those bytes
+/// escaped as `\xNN` (or `\uNNNN` for unpaired surrogates on Windows).
+pub fn quote_for_shell(arg: impl AsRef<OsStr>) -> String {
+    let arg = arg.as_ref();
+
+    match arg.to_str() {
+        Some("") => "''".to_owned(),
+        Some(arg) if arg.chars().all(is_safe_char) => arg.to_owned(),
+        Some(arg) if !arg.chars().any(char::is_control) => {
+            format!("'{}'", arg.replace('\'', r"'\''"))
+        }
+        _ => ansi_c_quote(arg),
+    }
+}
+
+/// Quote all `args` with [`quote_for_shell`] and join them with spaces.
+pub fn join_for_shell(args: impl IntoIterator<Item = impl AsRef<OsStr>>) -> String {
+    args.into_iter()
+        .map(quote_for_shell)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn is_safe_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c)
+}
+
+fn push_escaped(quoted: &mut String, c: char) {
+    match c {
+        '\\' => quoted.push_str(r"\\"),
+        '\'' => quoted.push_str(r"\'"),
+        '\n' => quoted.push_str(r"\n"),
+        '\r' => quoted.push_str(r"\r"),
+        '\t' => quoted.push_str(r"\t"),
+        c if c.is_control() => {
+            let mut buf = [0; 4];
+            for b in c.encode_utf8(&mut buf).bytes() {
+                write!(quoted, "\\x{b:02x}").unwrap();
+            }
+        }
+        c => quoted.push(c),
+    }
+}
+
+#[cfg(unix)]
+fn ansi_c_quote(arg: &OsStr) -> String {
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut quoted = String::from("$'");
+    let mut bytes = arg.as_bytes();
+    while !bytes.is_empty() {
+        let (valid, invalid) = match std::str::from_utf8(bytes) {
+            Ok(valid) => (valid, &[][..]),
+            Err(e) => {
+                let (valid, rest) = bytes.split_at(e.valid_up_to());
+                let invalid_len = e.error_len().unwrap_or(rest.len());
+                (std::str::from_utf8(valid).unwrap(), &rest[..invalid_len])
+            }
+        };
+
+        valid.chars().for_each(|c| push_escaped(&mut quoted, c));
+        for b in invalid {
+            write!(quoted, "\\x{b:02x}").unwrap();
+        }
+        bytes = &bytes[valid.len() + invalid.len()..];
+    }
+    quoted.push('\'');
+    quoted
+}
+
+#[cfg(windows)]
+fn ansi_c_quote(arg: &OsStr) -> String {
+    use std::os::windows::ffi::OsStrExt;
+
+    let mut quoted = String::from("$'");
+    for c in char::decode_utf16(arg.encode_wide()) {
+        match c {
+            Ok(c) => push_escaped(&mut quoted, c),
+            Err(e) => write!(quoted, "\\u{:04x}", e.unpaired_surrogate()).unwrap(),
+        }
+    }
+    quoted.push('\'');
+    quoted
+}
+
+#[cfg(not(any(unix, windows)))]
+fn ansi_c_quote(arg: &OsStr) -> String {
+    let mut quoted = String::from("$'");
+    arg.to_string_lossy()
+        .chars()
+        .for_each(|c| push_escaped(&mut quoted, c));
+    quoted.push('\'');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote_for_shell() {
+        assert_eq!(quote_for_shell("--foo=bar/baz.c"), "--foo=bar/baz.c");
+        assert_eq!(quote_for_shell(""), "''");
+        assert_eq!(quote_for_shell("with space"), "'with space'");
+        assert_eq!(quote_for_shell(r#"it's "quoted""#), r#"'it'\''s "quoted"'"#);
+        assert_eq!(quote_for_shell("a\nb\\"), r"$'a\nb\\'");
+        assert_eq!(
+            join_for_shell(["gcc", "-DNAME=\"x y\"", "$HOME"]),
+            r#"gcc '-DNAME="x y"' '$HOME'"#
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_quote_non_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+
+        assert_eq!(
+            quote_for_shell(OsStr::from_bytes(b"a\xff'b")),
+            r"$'a\xff\'b'"
+        );
+    }
+}
@@ -0,0 +1,157 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use super::Syntax;
+
+/// The maximum depth of nested response files expanded by [`expand_response_files`].
+pub const MAX_RESPONSE_FILE_DEPTH: usize = 16;
+
+/// Replace all `@<file>` arguments in `args` with the arguments in the response file
+/// `<file>` (relative to `base_dir`), split with `syntax`.
+///
+/// Response files referenced by response files are expanded recursively, up to
+/// [`MAX_RESPONSE_FILE_DEPTH`] levels.
+pub fn expand_response_files(
+    args: impl IntoIterator<Item = impl Into<String>>,
+    base_dir: impl AsRef<Path>,
+    syntax: Syntax,
+) -> Result<Vec<String>> {
+    let mut expanded = Vec::new();
+    expand(
+        args.into_iter().map(Into::into),
+        base_dir.as_ref(),
+        syntax,
+        0,
+        &mut expanded,
+    )?;
+    Ok(expanded)
+}
+
+fn expand(
+    args: impl Iterator<Item = String>,
+    base_dir: &Path,
+    syntax: Syntax,
+    depth: usize,
+    expanded: &mut Vec<String>,
+) -> Result<()> {
+    for arg in args {
+        let Some(file) = arg.strip_prefix('@').filter(|f| !f.is_empty()) else {
+            expanded.push(arg);
+            continue;
+        };
+
+        if depth >= MAX_RESPONSE_FILE_DEPTH {
+            bail!("response files nested deeper than {MAX_RESPONSE_FILE_DEPTH} levels at '{file}'");
+        }
+
+        let path = base_dir.join(file);
+        let content = fs::read_to_string(&path)
+            .with_context(|| anyhow!("could not read response file '{}'", path.display()))?;
+
+        expand(
+            syntax.split(&content).into_iter(),
+            base_dir,
+            syntax,
+            depth + 1,
+            expanded,
+        )
+        .with_context(|| anyhow!("in response file '{}'", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Write `args` into the response file `path` if their joined length (with `syntax`)
+/// exceeds `threshold`.
+///
+/// Returns the arguments to pass instead of `args`, i.e. the `@<path>` argument if the
+/// arguments were written to the response file, and `args` otherwise.
+pub fn spill_response_file(
+    args: &[impl AsRef<str>],
+    path: impl AsRef<Path>,
+    threshold: usize,
+    syntax: Syntax,
+) -> Result<Vec<String>> {
+    let joined = syntax.join(args.iter().map(AsRef::as_ref));
+    if joined.len() <= threshold {
+        return Ok(args.iter().map(|a| a.as_ref().to_owned()).collect());
+    }
+
+    let path = path.as_ref();
+    fs::write(path, joined)
+        .with_context(|| anyhow!("could not write response file '{}'", path.display()))?;
+
+    let path = path
+        .to_str()
+        .ok_or_else(|| anyhow!("response file path '{}' is not valid utf-8", path.display()))?;
+    Ok(vec![format!("@{path}")])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_nested() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::write(dir.join("outer.rsp"), "-DA '-DB=b c' @inner.rsp -o out").unwrap();
+        fs::write(dir.join("inner.rsp"), "-Iinc\n-Iother\\ dir\n").unwrap();
+
+        let args = expand_response_files(["gcc", "@outer.rsp", "@", "main.c"], dir, Syntax::Posix)
+            .unwrap();
+        assert_eq!(
+            args,
+            [
+                "gcc",
+                "-DA",
+                "-DB=b c",
+                "-Iinc",
+                "-Iother dir",
+                "-o",
+                "out",
+                "@",
+                "main.c"
+            ]
+        );
+
+        fs::write(dir.join("loop.rsp"), "@loop.rsp").unwrap();
+        let error = expand_response_files(["@loop.rsp"], dir, Syntax::Posix).unwrap_err();
+        assert!(format!("{error:#}").contains("nested deeper than"));
+    }
+
+    #[test]
+    fn test_missing_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::write(dir.join("outer.rsp"), "@missing.rsp").unwrap();
+
+        let error = expand_response_files(["@outer.rsp"], dir, Syntax::Windows).unwrap_err();
+        let error = format!("{error:#}");
+        assert!(error.contains("could not read response file"), "{error}");
+        assert!(error.contains("missing.rsp"), "{error}");
+        assert!(error.contains("outer.rsp"), "{error}");
+    }
+
+    #[test]
+    fn test_spill() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let path = dir.join("args.rsp");
+        let args = ["-DNAME=\"a b\"", r"C:\Program Files\inc\"];
+
+        assert_eq!(
+            spill_response_file(&args, &path, 1000, Syntax::Windows).unwrap(),
+            args
+        );
+        assert!(!path.exists());
+
+        let spilled = spill_response_file(&args, &path, 10, Syntax::Windows).unwrap();
+        assert_eq!(spilled, [format!("@{}", path.display())]);
+        assert_eq!(
+            expand_response_files(spilled, dir, Syntax::Windows).unwrap(),
+            args
+        );
+    }
+}
@@ -1,5 +1,7 @@
 #![allow(deprecated)] // TODO: For now
 
+use std::borrow::Cow;
+
 /// An iterator that parses a command as windows command-line arguments and returns them
 /// as [`String`]s.
 ///
@@ -47,113 +49,159 @@ impl<'a> Iterator for WindowsCommandArgs<'a> {
     ///
     /// See the MSDN document "Parsing C Command-Line Arguments" at
     /// <https://docs.microsoft.com/en-us/cpp/c-language/parsing-c-command-line-arguments>
-    /// for rules of parsing the windows command line.
+    /// for rules of parsing the windows command line:
+    /// - `2n` backslashes followed by a `"` produce `n` backslashes and start or end a
+    ///   quoted region, `2n + 1` backslashes followed by a `"` produce `n` backslashes and
+    ///   a literal `"`; backslashes not followed by a `"` are literal.
+    /// - `""` inside of a quoted region produces a literal `"`.
+    /// - `""` outside of a quoted region produces an empty argument.
+    /// - In the program path (see [`WindowsCommandArgs::new_with_program`]) quotes only
+    ///   start or end a quoted region and backslashes are always literal.
     fn next(&mut self) -> Option<Self::Item> {
-        let Self {
-            command,
-            index,
-            in_first_argument,
-            ref mut arg,
-        } = *self;
-
-        if index > command.len() {
-            return None;
-        }
+        let rest = self.command.get(self.index..)?;
+        let start = rest.find(|c| c != ' ' && c != '\t')?;
 
+        let arg = &mut self.arg;
         arg.clear();
 
-        let mut last_char = ' ';
+        let mut chars = rest[start..].char_indices().peekable();
         let mut in_quotes = false;
-        let mut consecutive_quotes = 0;
-        let mut backslashes = 0_u32;
-
-        /// Append `backslashes` amount of `\` to the argument, or half the amount if
-        /// `half` is `true`. Set `backslashes` to zero.
-        fn push_backslashes(arg: &mut String, backslashes: &mut u32, half: bool) {
-            if *backslashes > 0 {
-                let n = (*backslashes >> (half as u32)) as usize;
-                arg.reserve(n);
-                for _ in 0..n {
-                    arg.push('\\');
-                }
-                *backslashes = 0;
-            }
-        }
-
-        fn push_quotes(arg: &mut String, quotes: &mut u32, in_quotes: &mut bool) {
-            if *quotes > 0 {
-                let n = (*quotes >> 1) as usize;
-                let is_even = *quotes & 1 == 0;
+        let mut end = rest.len();
 
-                arg.reserve(n);
-                for _ in 0..n {
-                    arg.push('"');
-                }
-                *quotes = 0;
-                *in_quotes = is_even;
-            }
-        }
-
-        for (index, c) in command[index..].char_indices() {
+        while let Some((index, c)) = chars.next() {
             match c {
-                '"' if in_first_argument => {
-                    in_quotes = !in_quotes;
-                }
-                '"' => {
-                    let is_backslash_escaped = backslashes % 2 == 1;
-                    push_backslashes(arg, &mut backslashes, true);
+                '"' if self.in_first_argument => in_quotes = !in_quotes,
+                '\\' if !self.in_first_argument => {
+                    let mut backslashes = 1;
+                    while chars.next_if(|&(_, c)| c == '\\').is_some() {
+                        backslashes += 1;
+                    }
 
-                    if is_backslash_escaped {
-                        arg.push(c);
-                    } else if !in_quotes {
-                        in_quotes = true;
+                    if chars.next_if(|&(_, c)| c == '"').is_some() {
+                        arg.extend(std::iter::repeat('\\').take(backslashes / 2));
+                        if backslashes % 2 == 1 {
+                            arg.push('"');
+                        } else {
+                            in_quotes = !in_quotes;
+                        }
                     } else {
-                        consecutive_quotes += 1;
+                        arg.extend(std::iter::repeat('\\').take(backslashes));
                     }
                 }
-                '\\' => {
-                    backslashes += 1;
-                    push_quotes(arg, &mut consecutive_quotes, &mut in_quotes);
-                }
-                // This filters empty arguments which doesn't really conform to the spec
-                // but we don't need them.
-                ' ' | '\t' if !in_quotes && matches!(last_char, ' ' | '\t') => {}
-                ' ' | '\t' => {
-                    push_backslashes(arg, &mut backslashes, false);
-                    push_quotes(arg, &mut consecutive_quotes, &mut in_quotes);
-
-                    if in_quotes {
-                        arg.push(c);
+                '"' => {
+                    if in_quotes && chars.next_if(|&(_, c)| c == '"').is_some() {
+                        arg.push('"');
                     } else {
-                        self.index += index + 1;
-                        self.in_first_argument = false;
-
-                        let mut result = String::with_capacity(arg.len());
-                        result.clone_from(arg);
-                        return Some(result);
+                        in_quotes = !in_quotes;
                     }
                 }
-                c => {
-                    push_backslashes(arg, &mut backslashes, false);
-                    push_quotes(arg, &mut consecutive_quotes, &mut in_quotes);
-                    arg.push(c);
+                ' ' | '\t' if !in_quotes => {
+                    end = start + index + 1;
+                    break;
                 }
+                c => arg.push(c),
             }
-            last_char = c;
         }
-        push_backslashes(arg, &mut backslashes, false);
-        push_quotes(arg, &mut consecutive_quotes, &mut in_quotes);
 
-        self.index = command.len() + 1;
-        if arg.is_empty() {
-            None
-        } else {
-            let mut result = std::mem::take(arg);
-            result.shrink_to_fit();
+        self.index += end;
+        self.in_first_argument = false;
+
+        Some(std::mem::take(arg))
+    }
+}
+
+/// Quote `arg` so that it is parsed as a single argument by [`WindowsCommandArgs`] (and
+/// the MSVC runtime).
+pub fn quote_windows_arg(arg: &str) -> Cow<'_, str> {
+    if !arg.is_empty() && !arg.contains([' ', '\t', '\n', '\x0b', '"']) {
+        return Cow::Borrowed(arg);
+    }
+
+    let mut quoted = String::with_capacity(arg.len() + 2);
+    quoted.push('"');
+
+    let mut chars = arg.chars().peekable();
+    loop {
+        let mut backslashes = 0;
+        while chars.next_if_eq(&'\\').is_some() {
+            backslashes += 1;
+        }
 
-            Some(result)
+        match chars.next() {
+            // Backslashes before the closing quote must be escaped.
+            None => {
+                quoted.extend(std::iter::repeat('\\').take(backslashes * 2));
+                break;
+            }
+            Some('"') => {
+                quoted.extend(std::iter::repeat('\\').take(backslashes * 2 + 1));
+                quoted.push('"');
+            }
+            Some(c) => {
+                quoted.extend(std::iter::repeat('\\').take(backslashes));
+                quoted.push(c);
+            }
         }
     }
+
+    quoted.push('"');
+    Cow::Owned(quoted)
+}
+
+/// Quote all `args` with [`quote_windows_arg`] and join them with spaces.
+pub fn join_windows_args<'a>(args: impl IntoIterator<Item = &'a str>) -> String {
+    args.into_iter()
+        .map(quote_windows_arg)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The syntax of a command line.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Syntax {
+    /// The MSVC runtime (`CommandLineToArgvW`) rules (see [`WindowsCommandArgs`]).
+    Windows,
+    /// The POSIX shell-like rules (see [`UnixCommandArgs`]).
+    Posix,
+}
+
+impl Syntax {
+    /// The syntax of the host platform.
+    pub const NATIVE: Syntax = if cfg!(windows) {
+        Syntax::Windows
+    } else {
+        Syntax::Posix
+    };
+
+    /// Split the command line `command` into separate arguments.
+    pub fn split(self, command: &str) -> Vec<String> {
+        match self {
+            Self::Windows => WindowsCommandArgs::new(command).collect(),
+            Self::Posix => UnixCommandArgs::new(command).collect(),
+        }
+    }
+
+    /// Quote `arg` so that it is split as a single argument by [`Syntax::split`].
+    pub fn quote(self, arg: &str) -> Cow<'_, str> {
+        match self {
+            Self::Windows => quote_windows_arg(arg),
+            Self::Posix => quote_unix_arg(arg),
+        }
+    }
+
+    /// Join `args` into a command line, so that [`Syntax::split`] returns the same
+    /// arguments.
+    pub fn join<'a>(self, args: impl IntoIterator<Item = &'a str>) -> String {
+        args.into_iter()
+            .map(|arg| self.quote(arg))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Join `args` into a command line with the `syntax` (see [`Syntax::join`]).
+pub fn join_args<'a>(args: impl IntoIterator<Item = &'a str>, syntax: Syntax) -> String {
+    syntax.join(args)
 }
 
 pub use shlex::join as join_unix_args;
@@ -191,4 +239,57 @@ mod test {
         assert_eq!(iter.next(), Some("rest a b   "));
         assert_eq!(iter.next(), None);
     }
+
+    #[test]
+    fn separate_windows_empty_args() {
+        let args = WindowsCommandArgs::new(r#" "" a "" "C:\Program Files\\" "C:\a\" b"#)
+            .collect::<Vec<_>>();
+        assert_eq!(args, ["", "a", "", r"C:\Program Files\", r#"C:\a" b"#]);
+    }
+
+    #[test]
+    fn join_round_trip() {
+        let nasty = [
+            "",
+            " ",
+            "plain",
+            r"C:\Program Files\esp\include",
+            r"C:\trailing\",
+            r"\\server\share\dir with space\",
+            r#"-DNAME="value with spaces""#,
+            r#"\"quoted\""#,
+            r#"a\\"b"#,
+            "\"",
+            "''",
+            "it's",
+            "tab\there",
+            "$HOME `cmd` ;&|",
+        ];
+
+        // A simple deterministic generator combining the nasty inputs and characters.
+        let mut seed = 0x2545_f491_u32;
+        let mut next = move |n: usize| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed as usize % n
+        };
+        let pieces = ["\\", "\"", "'", " ", "a", "\\\"", "\t", "C:", "é"];
+
+        for syntax in [Syntax::Windows, Syntax::Posix] {
+            assert_eq!(syntax.split(&syntax.join(nasty)), nasty);
+
+            for _ in 0..500 {
+                let args = (0..next(5) + 1)
+                    .map(|_| {
+                        (0..next(8))
+                            .map(|_| pieces[next(pieces.len())])
+                            .collect::<String>()
+                    })
+                    .collect::<Vec<_>>();
+                let joined = join_args(args.iter().map(String::as_str), syntax);
+                assert_eq!(syntax.split(&joined), args, "{syntax:?}: {joined}");
+            }
+        }
+    }
 }
@@ -16,8 +16,10 @@ use crate::cli::NativeCommandArgs;
 use crate::cmd;
 
 pub mod file_api;
+pub mod runner;
 pub use dep_cmake::*;
 pub use file_api::Query;
+pub use runner::Runner;
 
 /// An enum for parsing and passing to cmake the standard command-line generators.
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, EnumString, Display, EnumIter, IntoStaticStr)]
@@ -141,26 +143,28 @@ impl TryFrom<&file_api::codemodel::target::CompileGroup> for CInclArgs {
     type Error = Error;
 
     fn try_from(value: &file_api::codemodel::target::CompileGroup) -> Result<Self, Self::Error> {
-        let args = value
-            .defines
-            .iter()
-            .map(|d| format!("-D{}", d.define))
-            .chain(
-                value
-                    .includes
-                    .iter()
-                    .map(|i| format!("\"-isystem{}\"", i.path)),
-            )
-            .collect::<Vec<_>>()
-            .join(" ");
-
-        Ok(Self { args })
+        Ok(Self {
+            include_dirs: value
+                .includes
+                .iter()
+                .map(|i| i.path.clone().into())
+                .collect(),
+            defines: value
+                .defines
+                .iter()
+                .map(|d| match d.define.split_once('=') {
+                    Some((name, value)) => (name.to_owned(), Some(value.to_owned())),
+                    None => (d.define.clone(), None),
+                })
+                .collect(),
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
     use std::io::Write;
+    use std::path::Path;
 
     use super::*;
 
@@ -180,4 +184,73 @@ mod tests {
             .find(|&(k, _)| k == "VAR");
         assert_eq!(var, Some(("VAR", "some string")));
     }
+
+    #[test]
+    fn test_link_args() {
+        let link = std::fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/cmake/link.json"),
+        )
+        .unwrap();
+        let link: file_api::codemodel::target::Link = serde_json::from_str(&link).unwrap();
+
+        let args = LinkArgsBuilder::try_from(&link)
+            .unwrap()
+            .dedup_libs(true)
+            .link_group(["freertos", "esp_system"])
+            .build()
+            .unwrap();
+        assert_eq!(
+            args.args,
+            [
+                "-mlongcalls",
+                "-Wno-frame-address",
+                "-Wl,--cref",
+                "-Wl,--defsym=IDF_TARGET_ESP32=0",
+                "-Wl,--Map=/build/esp-idf.map",
+                "-Wl,--gc-sections",
+                "-L/build/esp-idf/esp_system/ld",
+                "esp-idf/main/libmain.a",
+                "-Wl,--start-group",
+                "esp-idf/esp_system/libesp_system.a",
+                "esp-idf/freertos/libfreertos.a",
+                "-Wl,--end-group",
+                "-Wl,--whole-archive",
+                "esp-idf/app_trace/libapp_trace.a",
+                "-Wl,--no-whole-archive",
+                "esp-idf/app_trace/libapp_trace.a",
+                "-L/sdk/components/esp_rom/esp32/ld",
+                "-L/sdk/components/soc/esp32/ld",
+                "-T",
+                "esp32.project.ld",
+                "-T",
+                "/sdk/components/esp_rom/esp32/ld/esp32.rom.ld",
+                "-Wl,-T,/sdk/components/esp_rom/esp32/ld/esp32.rom.api.ld",
+                "-T/sdk/components/soc/esp32/ld/esp32.peripherals.ld",
+                "-u",
+                "esp_app_desc",
+                "-lm",
+                "esp-idf/newlib/libnewlib.a",
+                "-lgcc",
+                "-lc",
+                "-lstdc++",
+            ]
+        );
+
+        // The output is unchanged without deduplication and grouping, except for the
+        // linker script directories.
+        let args = LinkArgsBuilder::try_from(&link).unwrap().build().unwrap();
+        let fragments = link
+            .command_fragments
+            .iter()
+            .flat_map(|f| NativeCommandArgs::new(&f.fragment))
+            .collect::<Vec<_>>();
+        assert_eq!(args.args.len(), fragments.len() + 2);
+        assert_eq!(
+            args.args
+                .iter()
+                .filter(|arg| !arg.starts_with("-L/sdk/"))
+                .collect::<Vec<_>>(),
+            fragments.iter().collect::<Vec<_>>()
+        );
+    }
 }
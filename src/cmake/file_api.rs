@@ -2,12 +2,14 @@
 //! API](https://cmake.org/cmake/help/git-stage/manual/cmake-file-api.7.html) used to get
 //! information about the build-system and build.
 
+use std::borrow::Cow;
 use std::fs;
 use std::path::{Path, PathBuf};
 
 use anyhow::Result;
 use serde::Deserialize;
 
+use crate::fs::write_if_changed;
 use crate::path_buf;
 
 /// An object or cmake version.
@@ -38,46 +40,235 @@ impl std::fmt::Display for Version {
     }
 }
 
+/// How a [`Query`] is written to the cmake-file-api query directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum QueryMode {
+    /// Client stateless query files (`query/client-<client>/<kind>-v<major>`).
+    #[default]
+    ClientStateless,
+    /// A client stateful query file (`query/client-<client>/query.json`) which
+    /// requests explicit object versions.
+    ClientStateful,
+    /// Shared stateless query files (`query/<kind>-v<major>`).
+    ///
+    /// These are shared with all other clients of the build tree, so if another tool
+    /// already queries the same objects no duplicate replies are generated.
+    Shared,
+}
+
 /// The query for the cmake-file-api.
 #[derive(Clone, Debug)]
 pub struct Query<'a> {
     api_dir: PathBuf,
     client_name: String,
-    kinds: &'a [ObjKind],
+    kinds: Cow<'a, [ObjKind]>,
+    mode: QueryMode,
 }
 
 impl Query<'_> {
-    /// Create a new query.
+    /// Create a new client stateless query.
+    ///
+    /// See [`QueryBuilder::write`].
     pub fn new(
         cmake_build_dir: impl AsRef<Path>,
         client_name: impl Into<String>,
         kinds: &[ObjKind],
-    ) -> Result<Query> {
-        let client_name = client_name.into();
-        let api_dir = path_buf![cmake_build_dir, ".cmake", "api", "v1"];
-
-        let client_dir = path_buf![&api_dir, "query", format!("client-{}", &client_name)];
-        fs::create_dir_all(&client_dir)?;
-
-        for kind in kinds {
-            fs::File::create(client_dir.join(format!(
-                "{}-v{}",
-                kind.as_str(),
-                kind.supported_version()
-            )))?;
+    ) -> Result<Query<'_>> {
+        let query = Query {
+            api_dir: path_buf![cmake_build_dir, ".cmake", "api", "v1"],
+            client_name: client_name.into(),
+            kinds: Cow::Borrowed(kinds),
+            mode: QueryMode::ClientStateless,
+        };
+        query.write()?;
+
+        Ok(query)
+    }
+
+    /// Create a builder for a query of client `client_name` for the cmake build
+    /// directory `cmake_build_dir`.
+    pub fn builder(
+        cmake_build_dir: impl AsRef<Path>,
+        client_name: impl Into<String>,
+    ) -> QueryBuilder {
+        QueryBuilder {
+            cmake_build_dir: cmake_build_dir.as_ref().to_owned(),
+            client_name: client_name.into(),
+            kinds: Vec::new(),
+            mode: QueryMode::default(),
         }
+    }
 
-        Ok(Query {
-            api_dir,
-            client_name,
-            kinds,
-        })
+    /// The mode in which this query was written.
+    pub fn mode(&self) -> QueryMode {
+        self.mode
+    }
+
+    /// The object kinds requested by this query.
+    pub fn kinds(&self) -> &[ObjKind] {
+        &self.kinds
     }
 
     /// Try to get all replies from this query.
     pub fn get_replies(&self) -> Result<Replies> {
         Replies::from_query(self)
     }
+
+    fn query_dir(&self) -> PathBuf {
+        self.api_dir.join("query")
+    }
+
+    fn marker_file(&self) -> PathBuf {
+        self.api_dir
+            .join(format!("embuild-query-client-{}.json", self.client_name))
+    }
+
+    /// Write the query files and remove all query files previously written for this
+    /// client that are no longer requested.
+    fn write(&self) -> Result<()> {
+        let query_dir = self.query_dir();
+        let client_dir = format!("client-{}", self.client_name);
+        fs::create_dir_all(&query_dir)?;
+
+        let marker_file = self.marker_file();
+        let previous: Vec<String> = match fs::read(&marker_file) {
+            Ok(content) => serde_json::from_slice(&content).unwrap_or_default(),
+            Err(_) => Vec::new(),
+        };
+
+        let mut written = Vec::new();
+        match self.mode {
+            QueryMode::Shared => {
+                for kind in self.kinds.iter() {
+                    let file = kind_file_name(*kind);
+
+                    // Don't claim shared queries that were written by someone else.
+                    if query_dir.join(&file).exists() && !previous.contains(&file) {
+                        log::debug!("Reusing existing cmake-file-api shared query '{file}'");
+                        continue;
+                    }
+
+                    write_if_changed(query_dir.join(&file), "")?;
+                    written.push(file);
+                }
+            }
+            QueryMode::ClientStateless => {
+                fs::create_dir_all(query_dir.join(&client_dir))?;
+                for kind in self.kinds.iter() {
+                    let file = format!("{client_dir}/{}", kind_file_name(*kind));
+                    write_if_changed(query_dir.join(&file), "")?;
+                    written.push(file);
+                }
+            }
+            QueryMode::ClientStateful => {
+                fs::create_dir_all(query_dir.join(&client_dir))?;
+                let requests = self
+                    .kinds
+                    .iter()
+                    .map(|k| {
+                        serde_json::json!({
+                            "kind": k.as_str(),
+                            "version": k.supported_version(),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+
+                let file = format!("{client_dir}/query.json");
+                write_if_changed(
+                    query_dir.join(&file),
+                    serde_json::to_vec_pretty(&serde_json::json!({ "requests": requests }))?,
+                )?;
+                written.push(file);
+            }
+        }
+
+        for file in previous.iter().filter(|f| !written.contains(f)) {
+            remove_file_if_exists(query_dir.join(file))?;
+        }
+
+        // The client directory is exclusively ours, also remove files that were
+        // written before the marker existed.
+        let client_dir_path = query_dir.join(&client_dir);
+        if client_dir_path.is_dir() {
+            for entry in fs::read_dir(&client_dir_path)? {
+                let entry = entry?;
+                let file = format!("{client_dir}/{}", entry.file_name().to_string_lossy());
+                if entry.file_type()?.is_file() && !written.contains(&file) {
+                    fs::remove_file(entry.path())?;
+                }
+            }
+
+            if fs::read_dir(&client_dir_path)?.next().is_none() {
+                fs::remove_dir(&client_dir_path)?;
+            }
+        }
+
+        write_if_changed(marker_file, serde_json::to_vec(&written)?)?;
+
+        Ok(())
+    }
+}
+
+/// A builder for a [`Query`].
+#[derive(Clone, Debug)]
+#[must_use]
+pub struct QueryBuilder {
+    cmake_build_dir: PathBuf,
+    client_name: String,
+    kinds: Vec<ObjKind>,
+    mode: QueryMode,
+}
+
+impl QueryBuilder {
+    /// Set the mode in which the query is written (defaults to
+    /// [`QueryMode::ClientStateless`]).
+    pub fn mode(mut self, mode: QueryMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Request the object kind `kind`.
+    pub fn kind(mut self, kind: ObjKind) -> Self {
+        if !self.kinds.contains(&kind) {
+            self.kinds.push(kind);
+        }
+        self
+    }
+
+    /// Request all object kinds `kinds`.
+    pub fn kinds(self, kinds: impl IntoIterator<Item = ObjKind>) -> Self {
+        kinds.into_iter().fold(self, Self::kind)
+    }
+
+    /// Write the query to the query directory of the cmake build directory.
+    ///
+    /// Shared query files that are already present (written by another tool) are
+    /// reused and left untouched. All query files previously written for this client
+    /// (tracked by a marker file in the cmake-file-api directory) which are no longer
+    /// requested are removed.
+    pub fn write(self) -> Result<Query<'static>> {
+        let query = Query {
+            api_dir: path_buf![self.cmake_build_dir, ".cmake", "api", "v1"],
+            client_name: self.client_name,
+            kinds: Cow::Owned(self.kinds),
+            mode: self.mode,
+        };
+        query.write()?;
+
+        Ok(query)
+    }
+}
+
+/// The name of the stateless query file for `kind`.
+fn kind_file_name(kind: ObjKind) -> String {
+    format!("{}-v{}", kind.as_str(), kind.supported_version())
+}
+
+fn remove_file_if_exists(path: impl AsRef<Path>) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
 }
 
 pub mod cache;
@@ -89,3 +280,72 @@ pub use cache::Cache;
 pub use codemodel::Codemodel;
 pub use index::*;
 pub use toolchains::Toolchains;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query_files(build_dir: &Path) -> Vec<String> {
+        let query_dir = path_buf![build_dir, ".cmake", "api", "v1", "query"];
+        let mut files = vec![];
+        for entry in fs::read_dir(&query_dir).unwrap() {
+            let entry = entry.unwrap();
+            if entry.file_type().unwrap().is_dir() {
+                for sub_entry in fs::read_dir(entry.path()).unwrap() {
+                    files.push(format!(
+                        "{}/{}",
+                        entry.file_name().to_string_lossy(),
+                        sub_entry.unwrap().file_name().to_string_lossy()
+                    ));
+                }
+            } else {
+                files.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn test_query_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let build_dir = dir.path();
+
+        Query::new(build_dir, "test", &[ObjKind::Codemodel, ObjKind::Cache]).unwrap();
+        assert_eq!(
+            query_files(build_dir),
+            ["client-test/cache-v2", "client-test/codemodel-v2"]
+        );
+
+        let query = Query::builder(build_dir, "test")
+            .mode(QueryMode::ClientStateful)
+            .kinds([ObjKind::Toolchains])
+            .write()
+            .unwrap();
+        assert_eq!(query.mode(), QueryMode::ClientStateful);
+        assert_eq!(query_files(build_dir), ["client-test/query.json"]);
+
+        // A shared query of another tool.
+        fs::File::create(path_buf![
+            build_dir, ".cmake", "api", "v1", "query", "cache-v2"
+        ])
+        .unwrap();
+
+        Query::builder(build_dir, "test")
+            .mode(QueryMode::Shared)
+            .kinds([ObjKind::Codemodel, ObjKind::Cache])
+            .write()
+            .unwrap();
+        assert_eq!(query_files(build_dir), ["cache-v2", "codemodel-v2"]);
+
+        Query::builder(build_dir, "test")
+            .mode(QueryMode::Shared)
+            .kinds([ObjKind::Toolchains])
+            .write()
+            .unwrap();
+        assert_eq!(query_files(build_dir), ["cache-v2", "toolchains-v1"]);
+
+        Query::new(build_dir, "test", &[ObjKind::Cache]).unwrap();
+        assert_eq!(query_files(build_dir), ["cache-v2", "client-test/cache-v2"]);
+    }
+}
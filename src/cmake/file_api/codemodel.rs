@@ -2,16 +2,18 @@
 //!
 //! The codemodel object kind describes the build system structure as modeled by CMake.
 
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fs;
-use std::path::PathBuf;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
 
-use anyhow::{anyhow, Context, Error, Result};
+use anyhow::{anyhow, bail, Context, Error, Result};
 use serde::Deserialize;
 
 use super::index::{self, ObjKind};
 use super::Version;
+use crate::utils::PathExt;
 
 /// The description of the build system structure as modeled by CMake.
 #[derive(Debug, Deserialize, Clone)]
@@ -76,6 +78,37 @@ impl Codemodel {
     pub fn dir_path(&self) -> &PathBuf {
         &self.codemodel_dir
     }
+
+    /// Get all direct and transitive dependencies of the target named `target_name` in
+    /// the first configuration (see [`Configuration::target_dependencies`]).
+    pub fn target_dependencies(&self, target_name: &str) -> Result<Vec<&target::Target>> {
+        self.first_conf()?.target_dependencies(target_name)
+    }
+
+    /// Get all direct and transitive dependencies of the target named `target_name` in
+    /// the first configuration which are of any of the `types`.
+    ///
+    /// See [`Configuration::target_dependencies_of_type`].
+    pub fn target_dependencies_of_type(
+        &self,
+        target_name: &str,
+        types: &[target::Type],
+    ) -> Result<Vec<&target::Target>> {
+        self.first_conf()?
+            .target_dependencies_of_type(target_name, types)
+    }
+
+    /// Get the install layout of the first configuration (see
+    /// [`Configuration::install_layout`]).
+    pub fn install_layout(&self) -> Result<InstallLayout> {
+        self.first_conf()?.install_layout()
+    }
+
+    fn first_conf(&self) -> Result<&Configuration> {
+        self.configurations
+            .first()
+            .ok_or_else(|| anyhow!("No configurations in cmake-file-api codemodel"))
+    }
 }
 
 /// Paths used by cmake.
@@ -101,6 +134,12 @@ pub struct Configuration {
     /// not generate any build rules).
     #[serde(rename = "targets")]
     pub target_refs: Vec<TargetRef>,
+    /// The build system directories, one for each source directory processed by cmake
+    /// (via `add_subdirectory()` for example).
+    #[serde(default, rename = "directories")]
+    pub directory_refs: Vec<DirectoryRef>,
+    #[serde(skip)]
+    loaded_targets: OnceLock<Vec<target::Target>>,
 }
 
 impl Configuration {
@@ -116,6 +155,316 @@ impl Configuration {
     pub fn targets(&self) -> impl Iterator<Item = Result<target::Target>> + '_ {
         self.target_refs.iter().map(move |t| t.load(self))
     }
+
+    /// Get all codemodel target objects, loading them only once.
+    ///
+    /// In contrast to [`targets`](Self::targets), the loaded targets are cached in this
+    /// configuration.
+    pub fn loaded_targets(&self) -> Result<&[target::Target]> {
+        if let Some(targets) = self.loaded_targets.get() {
+            return Ok(targets);
+        }
+
+        let targets = self.targets().collect::<Result<Vec<_>>>()?;
+        Ok(self.loaded_targets.get_or_init(|| targets))
+    }
+
+    /// Get all direct and transitive dependencies of the target named `target_name`.
+    ///
+    /// The dependencies are returned in a deterministic topological order where every
+    /// target comes before all of its own dependencies (the order in which static
+    /// libraries must be passed to the linker). The target itself is not included.
+    ///
+    /// Returns an error if a target with the name `target_name` doesn't exist, a
+    /// dependency id cannot be resolved, or the dependency graph contains a cycle.
+    pub fn target_dependencies(&self, target_name: &str) -> Result<Vec<&target::Target>> {
+        let targets = self.loaded_targets()?;
+        let by_id = targets
+            .iter()
+            .map(|t| (t.id.as_str(), t))
+            .collect::<HashMap<_, _>>();
+
+        let root = targets
+            .iter()
+            .find(|t| t.name == target_name)
+            .ok_or_else(|| {
+                anyhow!(
+                    "Target '{}' not found in cmake-file-api codemodel",
+                    target_name
+                )
+            })?;
+
+        #[derive(Clone, Copy, PartialEq, Eq)]
+        enum Mark {
+            Visiting,
+            Done,
+        }
+
+        fn visit<'a>(
+            target: &'a target::Target,
+            by_id: &HashMap<&str, &'a target::Target>,
+            marks: &mut HashMap<&'a str, Mark>,
+            path: &mut Vec<&'a str>,
+            order: &mut Vec<&'a target::Target>,
+        ) -> Result<()> {
+            match marks.get(target.id.as_str()) {
+                Some(Mark::Done) => return Ok(()),
+                Some(Mark::Visiting) => {
+                    let start = path.iter().position(|n| *n == target.name).unwrap_or(0);
+                    let mut cycle = path[start..].to_vec();
+                    cycle.push(&target.name);
+
+                    bail!(
+                        "Dependency cycle detected between cmake targets: {}",
+                        cycle.join(" -> ")
+                    );
+                }
+                None => (),
+            }
+
+            marks.insert(&target.id, Mark::Visiting);
+            path.push(&target.name);
+
+            // Visiting the dependencies in reverse preserves their declaration order in the
+            // reversed post-order below.
+            for dep in target.dependencies.iter().rev() {
+                let dep_target = by_id.get(dep.id.as_str()).ok_or_else(|| {
+                    anyhow!(
+                        "Dependency '{}' of cmake target '{}' not found in cmake-file-api codemodel",
+                        dep.id,
+                        target.name
+                    )
+                })?;
+                visit(dep_target, by_id, marks, path, order)?;
+            }
+
+            path.pop();
+            marks.insert(&target.id, Mark::Done);
+            order.push(target);
+
+            Ok(())
+        }
+
+        let mut marks = HashMap::new();
+        let mut order = Vec::new();
+        visit(root, &by_id, &mut marks, &mut Vec::new(), &mut order)?;
+
+        // `order` is a post-order (dependencies first) ending with the root target.
+        order.pop();
+        order.reverse();
+
+        Ok(order)
+    }
+
+    /// Get all direct and transitive dependencies of the target named `target_name`
+    /// which are of any of the `types`.
+    ///
+    /// See [`target_dependencies`](Self::target_dependencies).
+    pub fn target_dependencies_of_type(
+        &self,
+        target_name: &str,
+        types: &[target::Type],
+    ) -> Result<Vec<&target::Target>> {
+        Ok(self
+            .target_dependencies(target_name)?
+            .into_iter()
+            .filter(|t| types.contains(&t.target_type))
+            .collect())
+    }
+
+    /// Collect the install rules of all directories into an [`InstallLayout`].
+    ///
+    /// Projects without any install rules result in an empty layout.
+    pub fn install_layout(&self) -> Result<InstallLayout> {
+        let mut layout = InstallLayout::default();
+
+        for dir_ref in self.directory_refs.iter().filter(|d| d.has_install_rule) {
+            let Some(dir) = dir_ref.load(self).transpose()? else {
+                bail!(
+                    "Directory object for '{}' not available in cmake-file-api codemodel \
+                     (cmake version must be at least 3.19)",
+                    dir_ref.source.display()
+                );
+            };
+
+            for installer in dir.installers {
+                match installer.installer_type {
+                    directory::InstallerType::Target => {
+                        let Some(target_index) = installer.target_index else {
+                            continue;
+                        };
+                        let target_ref = self.target_refs.get(target_index).ok_or_else(|| {
+                            anyhow!("Invalid target index {} in install rule", target_index)
+                        })?;
+                        let target_type = self.loaded_targets()?[target_index].target_type;
+
+                        layout.targets.push(InstalledTarget {
+                            name: target_ref.name.clone(),
+                            target_type,
+                            destination: installer.destination.unwrap_or_default(),
+                            component: installer.component,
+                        });
+                    }
+                    directory::InstallerType::File
+                    | directory::InstallerType::Directory
+                    | directory::InstallerType::FileSet => {
+                        layout.files.push(InstalledFiles {
+                            installer_type: installer.installer_type,
+                            destination: installer.destination.unwrap_or_default(),
+                            paths: installer
+                                .paths
+                                .into_iter()
+                                .map(|p| p.from().to_owned())
+                                .collect(),
+                            component: installer.component,
+                        });
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        Ok(layout)
+    }
+}
+
+/// A reference to a codemodel directory object JSON file.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DirectoryRef {
+    /// The path to the source directory, relative to the top-level source directory if
+    /// inside of it, otherwise absolute.
+    pub source: PathBuf,
+    /// The path to the build directory, relative to the top-level build directory if
+    /// inside of it, otherwise absolute.
+    pub build: PathBuf,
+    /// An unsigned integer 0-based index into the main directories array indicating
+    /// the parent directory. Absent for the top-level directory.
+    pub parent_index: Option<usize>,
+    /// Unsigned integer 0-based indices into the [`Configuration::target_refs`] of the
+    /// targets defined in this directory.
+    #[serde(default)]
+    pub target_indexes: Vec<usize>,
+    /// Whether the directory or one of its subdirectories contains any `install()`
+    /// rules.
+    #[serde(default)]
+    pub has_install_rule: bool,
+    /// A path relative to the codemodel file to another JSON file containing a codemodel
+    /// `directory` object.
+    ///
+    /// Present since codemodel version 2.3 (cmake 3.19).
+    pub json_file: Option<String>,
+}
+
+impl DirectoryRef {
+    /// Load the directory object from the [`json_file`](Self::json_file), or [`None`]
+    /// if not available.
+    pub fn load(&self, cfg: &Configuration) -> Option<Result<directory::Directory>> {
+        self.json_file
+            .as_ref()
+            .map(|f| directory::Directory::from_file(cfg.codemodel_dir.join(f)))
+    }
+}
+
+/// The install locations declared by the `install()` rules of a cmake project.
+///
+/// All destinations are relative to the install prefix (`CMAKE_INSTALL_PREFIX`) unless
+/// they are absolute.
+#[derive(Debug, Clone, Default)]
+pub struct InstallLayout {
+    /// All installed targets.
+    pub targets: Vec<InstalledTarget>,
+    /// All installed files and directories.
+    pub files: Vec<InstalledFiles>,
+}
+
+impl InstallLayout {
+    /// Whether there are no install rules.
+    pub fn is_empty(&self) -> bool {
+        self.targets.is_empty() && self.files.is_empty()
+    }
+
+    /// Get the destinations of the target named `target_name`.
+    pub fn target_destinations(&self, target_name: &str) -> Vec<&Path> {
+        self.targets
+            .iter()
+            .filter(|t| t.name == target_name)
+            .map(|t| t.destination.as_path())
+            .collect()
+    }
+
+    /// Get the (deduplicated) absolute destination directories of all installed
+    /// static and shared libraries, suitable for `cargo:rustc-link-search`.
+    pub fn lib_dirs(&self, install_prefix: &Path) -> Vec<PathBuf> {
+        dedup_dirs(
+            self.targets
+                .iter()
+                .filter(|t| {
+                    matches!(
+                        t.target_type,
+                        target::Type::StaticLibrary | target::Type::SharedLibrary
+                    )
+                })
+                .map(|t| t.destination.abspath_relative_to(install_prefix)),
+        )
+    }
+
+    /// Get the (deduplicated) absolute destination directories of all installed
+    /// directories and files which are C/C++ headers.
+    pub fn include_dirs(&self, install_prefix: &Path) -> Vec<PathBuf> {
+        dedup_dirs(
+            self.files
+                .iter()
+                .filter(|f| {
+                    f.installer_type != directory::InstallerType::File
+                        || f.paths.iter().any(|p| {
+                            matches!(
+                                p.extension().and_then(|e| e.to_str()),
+                                Some("h" | "hh" | "hpp" | "hxx" | "inc")
+                            )
+                        })
+                })
+                .map(|f| f.destination.abspath_relative_to(install_prefix)),
+        )
+    }
+}
+
+fn dedup_dirs(dirs: impl Iterator<Item = PathBuf>) -> Vec<PathBuf> {
+    let mut result = Vec::new();
+    for dir in dirs {
+        if !result.contains(&dir) {
+            result.push(dir);
+        }
+    }
+    result
+}
+
+/// A target installed by an `install(TARGETS)` rule.
+#[derive(Debug, Clone)]
+pub struct InstalledTarget {
+    /// The name of the target.
+    pub name: String,
+    /// The type of the target.
+    pub target_type: target::Type,
+    /// The destination directory.
+    pub destination: PathBuf,
+    /// The installation component.
+    pub component: String,
+}
+
+/// Files or directories installed by an `install(FILES)`, `install(DIRECTORY)`, or
+/// `install(TARGETS ... FILE_SET)` rule.
+#[derive(Debug, Clone)]
+pub struct InstalledFiles {
+    /// The type of the install rule.
+    pub installer_type: directory::InstallerType,
+    /// The destination directory.
+    pub destination: PathBuf,
+    /// The paths of the installed files or directories, relative to the top-level
+    /// source directory unless absolute.
+    pub paths: Vec<PathBuf>,
+    /// The installation component.
+    pub component: String,
 }
 
 /// A reference to a codemodel target object JSON file.
@@ -124,6 +473,8 @@ impl Configuration {
 pub struct TargetRef {
     /// The target name.
     pub name: String,
+    /// An unique identifier for the target.
+    pub id: String,
     /// An unsigned integer 0-based index into the main directories array indicating the
     /// build system directory in which the target is defined.
     pub directory_index: usize,
@@ -170,6 +521,9 @@ pub mod target {
     use serde::Deserialize;
 
     use super::Language;
+    use crate::cargo;
+    use crate::cli::{expand_response_files, NativeCommandArgs, Syntax};
+    use crate::utils::PathExt;
 
     /// A type of cmake target.
     #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Hash)]
@@ -189,6 +543,14 @@ pub mod target {
     pub struct Target {
         /// The logical name of the target.
         pub name: String,
+        /// An unique identifier for the target.
+        ///
+        /// Matches the [`TargetRef::id`](super::TargetRef::id) of this target and is used
+        /// by [`Dependency::id`] of other targets.
+        pub id: String,
+        /// Other targets this target depends on at build time.
+        #[serde(default)]
+        pub dependencies: Vec<Dependency>,
         /// Link info of target.
         pub link: Option<Link>,
         /// Compile settings for source files.
@@ -197,6 +559,15 @@ pub mod target {
         /// The type of the target.
         #[serde(rename = "type")]
         pub target_type: Type,
+        /// The source files associated with the target.
+        #[serde(default)]
+        pub sources: Vec<Source>,
+        /// The main artifacts produced by the target (e.g. the `.a` or `.elf` file).
+        ///
+        /// Not present for targets that don't produce any artifacts (e.g. utility
+        /// targets).
+        #[serde(default)]
+        pub artifacts: Vec<Artifact>,
     }
 
     impl Target {
@@ -212,6 +583,88 @@ pub mod target {
 
             Ok(value)
         }
+
+        /// Get the absolute paths of all [`sources`](Self::sources) of this target.
+        ///
+        /// `source_dir` must be the top-level source directory (see
+        /// [`Paths::source`](super::Paths::source)).
+        pub fn source_paths(&self, source_dir: &Path) -> Vec<PathBuf> {
+            self.sources
+                .iter()
+                .map(|s| s.path.abspath_relative_to(source_dir))
+                .collect()
+        }
+
+        /// Get the absolute paths of all [`sources`](Self::sources) of this target which
+        /// were not generated by the build system.
+        ///
+        /// `source_dir` must be the top-level source directory (see
+        /// [`Paths::source`](super::Paths::source)).
+        pub fn non_generated_source_paths(&self, source_dir: &Path) -> Vec<PathBuf> {
+            self.sources
+                .iter()
+                .filter(|s| !s.is_generated)
+                .map(|s| s.path.abspath_relative_to(source_dir))
+                .collect()
+        }
+
+        /// Get the absolute paths of all [`artifacts`](Self::artifacts) of this target.
+        ///
+        /// `build_dir` must be the top-level build directory (see
+        /// [`Paths::build`](super::Paths::build)).
+        pub fn artifact_paths(&self, build_dir: &Path) -> Vec<PathBuf> {
+            self.artifacts
+                .iter()
+                .map(|a| a.path.abspath_relative_to(build_dir))
+                .collect()
+        }
+
+        /// Rerun the build script if any of the non-generated sources of this target
+        /// change.
+        ///
+        /// See [`non_generated_source_paths`](Self::non_generated_source_paths).
+        pub fn track_sources(&self, source_dir: &Path) {
+            for source in self.non_generated_source_paths(source_dir) {
+                cargo::track_file(source);
+            }
+        }
+    }
+
+    /// A source file of a target.
+    #[derive(Debug, Deserialize, Clone)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Source {
+        /// The path to the source file, represented with forward slashes.
+        ///
+        /// Relative to the top-level source directory if the file is inside of it,
+        /// otherwise absolute.
+        pub path: PathBuf,
+        /// An unsigned integer 0-based index into the
+        /// [`compile_groups`](Target::compile_groups) of the target, if the source is
+        /// compiled.
+        pub compile_group_index: Option<usize>,
+        /// Whether the source is generated by the build system.
+        #[serde(default)]
+        pub is_generated: bool,
+    }
+
+    /// A main artifact produced by a target.
+    #[derive(Debug, Deserialize, Clone)]
+    pub struct Artifact {
+        /// The path to the artifact, represented with forward slashes.
+        ///
+        /// Relative to the top-level build directory if the file is inside of it,
+        /// otherwise absolute.
+        pub path: PathBuf,
+    }
+
+    /// A build time dependency on another target.
+    #[derive(Debug, Deserialize, Clone)]
+    pub struct Dependency {
+        /// The unique identifier of the target on which this target depends.
+        pub id: String,
+        /// An unsigned integer 0-based index into the backtrace graph's `nodes` array.
+        pub backtrace: Option<usize>,
     }
 
     /// Compile settings for groups of sources using the same settings.
@@ -233,6 +686,52 @@ pub mod target {
         ///
         /// Present when the `CMAKE_SYSROOT_COMPILE` or `CMAKE_SYSROOT` variable is defined.
         pub sysroot: Option<Sysroot>,
+        /// The language standard.
+        ///
+        /// Present when the language standard is set explicitly (e.g. via
+        /// `CXX_STANDARD`) or implicitly by compile features.
+        pub language_standard: Option<LanguageStandard>,
+    }
+
+    impl CompileGroup {
+        /// Get all arguments of the [`compile_command_fragments`](Self::compile_command_fragments)
+        /// in order.
+        pub fn compile_args(&self) -> Vec<String> {
+            self.compile_command_fragments
+                .iter()
+                .flat_map(|f| NativeCommandArgs::new(&f.fragment))
+                .collect()
+        }
+
+        /// Get the [`compile_args`](Self::compile_args) with all `@<file>` response file
+        /// arguments replaced by their contents, relative to the `build_dir` (see
+        /// [`cli::expand_response_files`](crate::cli::expand_response_files)).
+        pub fn expanded_compile_args(&self, build_dir: impl AsRef<Path>) -> Result<Vec<String>> {
+            expand_response_files(self.compile_args(), build_dir, Syntax::NATIVE)
+        }
+
+        /// Get the `-std=` compiler argument corresponding to the
+        /// [`language_standard`](Self::language_standard), if any.
+        ///
+        /// Returns [`None`] if the language is neither C nor C++.
+        pub fn std_arg(&self) -> Option<String> {
+            let standard = &self.language_standard.as_ref()?.standard;
+            match self.language {
+                Language::C => Some(format!("-std=c{standard}")),
+                Language::Cpp => Some(format!("-std=c++{standard}")),
+                _ => None,
+            }
+        }
+    }
+
+    /// The language standard used to compile a [`CompileGroup`].
+    #[derive(Debug, Deserialize, Clone)]
+    pub struct LanguageStandard {
+        /// The language standard (e.g. `17`).
+        pub standard: String,
+        /// Unsigned integer 0-based indices into the backtrace graph's `nodes` array.
+        #[serde(default)]
+        pub backtraces: Vec<usize>,
     }
 
     /// A fragment of a compile command invocation.
@@ -279,6 +778,20 @@ pub mod target {
         pub sysroot: Option<Sysroot>,
     }
 
+    impl Link {
+        /// Get all arguments of the [`command_fragments`](Self::command_fragments) in
+        /// order, with all `@<file>` response file arguments replaced by their contents,
+        /// relative to the `build_dir` (see
+        /// [`cli::expand_response_files`](crate::cli::expand_response_files)).
+        pub fn expanded_args(&self, build_dir: impl AsRef<Path>) -> Result<Vec<String>> {
+            let args = self
+                .command_fragments
+                .iter()
+                .flat_map(|f| NativeCommandArgs::new(&f.fragment));
+            expand_response_files(args, build_dir, Syntax::NATIVE)
+        }
+    }
+
     /// A link command linke fragment.
     #[derive(Debug, Deserialize, Clone)]
     pub struct CommandFragment {
@@ -310,3 +823,318 @@ pub mod target {
         pub path: PathBuf,
     }
 }
+
+/// Codemodel directory cmake file API object.
+pub mod directory {
+    use std::path::{Path, PathBuf};
+
+    use anyhow::{anyhow, Context, Result};
+    use serde::Deserialize;
+
+    use super::Paths;
+
+    /// A build system directory with its `install()` rules.
+    #[derive(Debug, Deserialize, Clone)]
+    pub struct Directory {
+        /// The source and build directory paths.
+        pub paths: Paths,
+        /// The `install()` rules of this directory.
+        #[serde(default)]
+        pub installers: Vec<Installer>,
+    }
+
+    impl Directory {
+        /// Deserialize the codemodel directory object JSON file from `file_path`.
+        pub fn from_file(file_path: impl AsRef<Path>) -> Result<Directory> {
+            let file = std::fs::File::open(&file_path)?;
+            let value: Directory = serde_json::from_reader(file).with_context(|| {
+                anyhow!(
+                    "Failed to parse the cmake-file-api directory file '{}'",
+                    file_path.as_ref().display()
+                )
+            })?;
+
+            Ok(value)
+        }
+    }
+
+    /// The type of an `install()` rule.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Hash)]
+    #[serde(rename_all = "camelCase")]
+    pub enum InstallerType {
+        File,
+        Directory,
+        Target,
+        Export,
+        Script,
+        Code,
+        ImportedRuntimeArtifacts,
+        RuntimeDependencySet,
+        FileSet,
+        CxxModuleBmi,
+        #[serde(other)]
+        Unknown,
+    }
+
+    /// An `install()` rule.
+    #[derive(Debug, Deserialize, Clone)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Installer {
+        /// The installation component.
+        pub component: String,
+        /// The install destination, relative to the install prefix unless absolute.
+        pub destination: Option<PathBuf>,
+        /// The paths (files or directories) to be installed.
+        #[serde(default)]
+        pub paths: Vec<InstallPath>,
+        /// The type of the rule.
+        #[serde(rename = "type")]
+        pub installer_type: InstallerType,
+        /// An unsigned integer 0-based index into the
+        /// [`Configuration::target_refs`](super::Configuration::target_refs) of the
+        /// installed target, if [`installer_type`](Self::installer_type) is
+        /// [`InstallerType::Target`].
+        pub target_index: Option<usize>,
+        /// Whether the rule has the `EXCLUDE_FROM_ALL` option.
+        #[serde(default)]
+        pub is_exclude_from_all: bool,
+        /// Whether the rule has the `OPTIONAL` option.
+        #[serde(default)]
+        pub is_optional: bool,
+    }
+
+    /// A path to be installed.
+    #[derive(Debug, Deserialize, Clone)]
+    #[serde(untagged)]
+    pub enum InstallPath {
+        /// A path, relative to the top-level source directory unless absolute, that is
+        /// installed under the same name.
+        Path(PathBuf),
+        /// A path `from`, relative to the top-level source directory unless absolute,
+        /// that is installed as `to`, relative to the destination.
+        FromTo { from: PathBuf, to: PathBuf },
+    }
+
+    impl InstallPath {
+        /// The path of the file or directory to install.
+        pub fn from(&self) -> &Path {
+            match self {
+                Self::Path(path) => path,
+                Self::FromTo { from, .. } => from,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    fn write_target(dir: &Path, name: &str, target_type: &str, deps: &[&str]) {
+        let deps = deps
+            .iter()
+            .map(|d| format!(r#"{{ "id": "{d}::@1", "backtrace": 1 }}"#))
+            .collect::<Vec<_>>()
+            .join(", ");
+        fs::write(
+            dir.join(format!("target-{name}.json")),
+            format!(
+                r#"{{ "name": "{name}", "id": "{name}::@1", "type": "{target_type}", "dependencies": [{deps}] }}"#
+            ),
+        )
+        .unwrap();
+    }
+
+    fn diamond_codemodel(dir: &Path) -> Codemodel {
+        codemodel_with_directories(dir, "[]")
+    }
+
+    fn codemodel_with_directories(dir: &Path, directories: &str) -> Codemodel {
+        let targets = [
+            ("app", "EXECUTABLE", &["liba", "libb"][..]),
+            ("liba", "STATIC_LIBRARY", &["base"][..]),
+            ("libb", "OBJECT_LIBRARY", &["base"][..]),
+            ("base", "STATIC_LIBRARY", &[][..]),
+        ];
+
+        let mut target_refs = vec![];
+        for (name, target_type, deps) in targets {
+            write_target(dir, name, target_type, deps);
+            target_refs.push(format!(
+                r#"{{ "name": "{name}", "id": "{name}::@1", "directoryIndex": 0, "projectIndex": 0, "jsonFile": "target-{name}.json" }}"#
+            ));
+        }
+
+        let json_file = dir.join("codemodel-v2.json");
+        fs::write(
+            &json_file,
+            format!(
+                r#"{{
+                    "version": {{ "major": 2, "minor": 3 }},
+                    "paths": {{ "source": "/src", "build": "/build" }},
+                    "configurations": [{{
+                        "name": "Debug",
+                        "targets": [{}],
+                        "directories": {directories}
+                    }}]
+                }}"#,
+                target_refs.join(", ")
+            ),
+        )
+        .unwrap();
+
+        Codemodel::try_from(&index::Reply {
+            json_file,
+            kind: ObjKind::Codemodel,
+            version: Version {
+                major: 2,
+                ..Default::default()
+            },
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_target_dependencies_diamond() {
+        let dir = tempfile::tempdir().unwrap();
+        let codemodel = diamond_codemodel(dir.path());
+
+        let names = |targets: Vec<&target::Target>| {
+            targets
+                .into_iter()
+                .map(|t| t.name.clone())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            names(codemodel.target_dependencies("app").unwrap()),
+            ["liba", "libb", "base"]
+        );
+        assert_eq!(
+            names(codemodel.target_dependencies("libb").unwrap()),
+            ["base"]
+        );
+        assert_eq!(
+            names(
+                codemodel
+                    .target_dependencies_of_type("app", &[target::Type::StaticLibrary])
+                    .unwrap()
+            ),
+            ["liba", "base"]
+        );
+        assert!(codemodel.target_dependencies("missing").is_err());
+    }
+
+    #[test]
+    fn test_target_sources_and_artifacts() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("target.json");
+        fs::write(
+            &file,
+            r#"{
+                "name": "main",
+                "id": "main::@1",
+                "type": "STATIC_LIBRARY",
+                "sources": [
+                    { "path": "main/main.c", "compileGroupIndex": 0 },
+                    { "path": "/build/config/gen.c", "compileGroupIndex": 0, "isGenerated": true },
+                    { "path": "main/main.h" }
+                ],
+                "artifacts": [{ "path": "esp-idf/main/libmain.a" }],
+                "compileGroups": [{
+                    "language": "C",
+                    "languageStandard": { "backtraces": [1], "standard": "17" },
+                    "compileCommandFragments": [
+                        { "fragment": "-mlongcalls -Wno-frame-address" },
+                        { "fragment": "-std=gnu17" }
+                    ],
+                    "includes": [{ "path": "/src/main/include" }],
+                    "defines": [{ "define": "ESP_PLATFORM" }]
+                }]
+            }"#,
+        )
+        .unwrap();
+
+        let target = target::Target::from_file(&file).unwrap();
+
+        assert_eq!(
+            target.source_paths(Path::new("/src")),
+            [
+                Path::new("/src/main/main.c"),
+                Path::new("/build/config/gen.c"),
+                Path::new("/src/main/main.h")
+            ]
+        );
+        assert_eq!(
+            target.non_generated_source_paths(Path::new("/src")),
+            [Path::new("/src/main/main.c"), Path::new("/src/main/main.h")]
+        );
+        assert_eq!(target.sources[2].compile_group_index, None);
+        assert_eq!(
+            target.artifact_paths(Path::new("/build")),
+            [Path::new("/build/esp-idf/main/libmain.a")]
+        );
+
+        let compile_group = &target.compile_groups[0];
+        assert_eq!(compile_group.std_arg().as_deref(), Some("-std=c17"));
+        assert_eq!(
+            compile_group.compile_args(),
+            ["-mlongcalls", "-Wno-frame-address", "-std=gnu17"]
+        );
+    }
+
+    #[test]
+    fn test_install_layout() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let codemodel = codemodel_with_directories(
+            dir.path(),
+            r#"[{ "source": ".", "build": ".", "hasInstallRule": false }]"#,
+        );
+        assert!(codemodel.install_layout().unwrap().is_empty());
+
+        fs::write(
+            dir.path().join("directory-.json"),
+            r#"{
+                "paths": { "source": "/src", "build": "/build" },
+                "installers": [
+                    { "component": "Unspecified", "destination": "lib", "paths": ["libbase.a"], "type": "target", "targetIndex": 3 },
+                    { "component": "Unspecified", "destination": "bin", "paths": ["app"], "type": "target", "targetIndex": 0 },
+                    { "component": "Unspecified", "destination": "include", "paths": ["base/base.h"], "type": "file" },
+                    { "component": "Unspecified", "destination": "share", "paths": [{ "from": "README", "to": "README.md" }], "type": "file" },
+                    { "component": "Unspecified", "type": "code" }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let codemodel = codemodel_with_directories(
+            dir.path(),
+            r#"[{ "source": ".", "build": ".", "hasInstallRule": true, "jsonFile": "directory-.json" }]"#,
+        );
+        let layout = codemodel.install_layout().unwrap();
+
+        assert_eq!(layout.target_destinations("base"), [Path::new("lib")]);
+        assert_eq!(
+            layout.lib_dirs(Path::new("/prefix")),
+            [Path::new("/prefix/lib")]
+        );
+        assert_eq!(
+            layout.include_dirs(Path::new("/prefix")),
+            [Path::new("/prefix/include")]
+        );
+        assert_eq!(layout.files[1].paths, [Path::new("README")]);
+    }
+
+    #[test]
+    fn test_target_dependencies_cycle() {
+        let dir = tempfile::tempdir().unwrap();
+        let codemodel = diamond_codemodel(dir.path());
+        write_target(dir.path(), "base", "STATIC_LIBRARY", &["liba"]);
+
+        let err = codemodel.target_dependencies("app").unwrap_err();
+        assert!(err.to_string().contains("base -> liba -> base"), "{err}");
+    }
+}
@@ -10,7 +10,7 @@ use serde_json::Value;
 use super::cache::Cache;
 use super::codemodel::Codemodel;
 use super::toolchains::Toolchains;
-use super::{Query, Version};
+use super::{kind_file_name, Query, QueryMode, Version};
 
 /// CMake tool kind for [`CMake::paths`].
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
@@ -188,7 +188,7 @@ impl Replies {
         let Index { cmake, reply } =
             serde_json::from_reader(&fs::File::open(&index_file)?).with_context(base_error)?;
 
-        for kind in query.kinds {
+        for kind in query.kinds.iter() {
             let min_cmake_version = kind.min_cmake_version();
             if cmake.version.major < min_cmake_version.major
                 || cmake.version.minor < min_cmake_version.minor
@@ -203,13 +203,6 @@ impl Replies {
             }
         }
 
-        let client = format!("client-{}", &query.client_name);
-        let (_, reply) = reply
-            .into_iter()
-            .find(|(k, _)| k == &client)
-            .ok_or_else(|| anyhow!("Reply for client '{}' not found", &query.client_name))
-            .with_context(base_error)?;
-
         #[derive(Deserialize)]
         #[serde(untagged)]
         enum ReplyOrError {
@@ -217,27 +210,82 @@ impl Replies {
             Error { error: String },
         }
 
-        let mut errors = vec![];
-        let replies: HashMap<ObjKind, Reply> =
-            serde_json::from_value::<HashMap<String, ReplyOrError>>(reply)
-                .with_context(base_error)?
-                .into_iter()
-                .filter_map(|(_, v)| match v {
-                    ReplyOrError::Reply(mut r) => {
-                        if let Err(err) = r.kind.check_version_supported(r.version.major) {
-                            errors.push(err.to_string());
-                            None
-                        } else {
-                            r.json_file = reply_dir.join(r.json_file);
-                            Some((r.kind, r))
-                        }
+        let client_reply = || {
+            let client = format!("client-{}", &query.client_name);
+            reply
+                .get(&client)
+                .cloned()
+                .ok_or_else(|| anyhow!("Reply for client '{}' not found", &query.client_name))
+                .with_context(base_error)
+        };
+
+        let objects: Vec<ReplyOrError> = match query.mode {
+            QueryMode::ClientStateless => {
+                serde_json::from_value::<HashMap<String, ReplyOrError>>(client_reply()?)
+                    .with_context(base_error)?
+                    .into_values()
+                    .collect()
+            }
+            QueryMode::ClientStateful => {
+                #[derive(Deserialize)]
+                #[serde(untagged)]
+                enum StatefulReply {
+                    Responses { responses: Vec<ReplyOrError> },
+                    Error { error: String },
+                }
+
+                let stateful =
+                    serde_json::from_value::<HashMap<String, StatefulReply>>(client_reply()?)
+                        .with_context(base_error)?
+                        .remove("query.json")
+                        .ok_or_else(|| {
+                            anyhow!(
+                                "Reply for stateful query of client '{}' not found",
+                                &query.client_name
+                            )
+                        })
+                        .with_context(base_error)?;
+
+                match stateful {
+                    StatefulReply::Responses { responses } => responses,
+                    StatefulReply::Error { error } => {
+                        return Err(anyhow!("{}", error)
+                            .context(format!(
+                                "Stateful query of client '{}' failed",
+                                &query.client_name
+                            ))
+                            .context(base_error()))
                     }
-                    ReplyOrError::Error { error } => {
-                        errors.push(error);
+                }
+            }
+            QueryMode::Shared => query
+                .kinds
+                .iter()
+                .filter_map(|k| reply.get(&kind_file_name(*k)))
+                .map(|v| serde_json::from_value::<ReplyOrError>(v.clone()))
+                .collect::<Result<_, _>>()
+                .with_context(base_error)?,
+        };
+
+        let mut errors = vec![];
+        let replies: HashMap<ObjKind, Reply> = objects
+            .into_iter()
+            .filter_map(|v| match v {
+                ReplyOrError::Reply(mut r) => {
+                    if let Err(err) = r.kind.check_version_supported(r.version.major) {
+                        errors.push(err.to_string());
                         None
+                    } else {
+                        r.json_file = reply_dir.join(r.json_file);
+                        Some((r.kind, r))
                     }
-                })
-                .collect();
+                }
+                ReplyOrError::Error { error } => {
+                    errors.push(error);
+                    None
+                }
+            })
+            .collect();
 
         let not_found = query
             .kinds
@@ -0,0 +1,466 @@
+//! A runner for configuring and building cmake projects.
+
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::{env, fs};
+
+use anyhow::{anyhow, Context, Result};
+
+use super::file_api::{Codemodel, ObjKind, PathsKey, Query, QueryMode, Replies};
+use super::{cmake, Generator};
+use crate::artifacts::{self, ArtifactKind};
+use crate::cache::{self, CacheKey};
+use crate::cmd::{Cmd, CmdError, OutputClassifier};
+use crate::path_buf;
+use crate::utils::StableHasher;
+use crate::{cmd, logging};
+
+/// The file in the build directory where the hash of the configure inputs is stored.
+const CONFIGURE_STAMP_FILE: &str = "embuild-configure.stamp";
+
+/// The environment variables cmake reads when configuring a project (the compilers,
+/// their flags and the defaults of the command line options), whose changes make
+/// [`Runner::configure`] configure the project again.
+const CONFIGURE_ENV_VARS: &[&str] = &[
+    "CC",
+    "CXX",
+    "ASM",
+    "CFLAGS",
+    "CXXFLAGS",
+    "ASMFLAGS",
+    "CPPFLAGS",
+    "LDFLAGS",
+    "CMAKE_GENERATOR",
+    "CMAKE_GENERATOR_PLATFORM",
+    "CMAKE_GENERATOR_TOOLSET",
+    "CMAKE_TOOLCHAIN_FILE",
+    "CMAKE_BUILD_TYPE",
+    "CMAKE_PREFIX_PATH",
+    "CMAKE_C_COMPILER_LAUNCHER",
+    "CMAKE_CXX_COMPILER_LAUNCHER",
+];
+
+/// The cache schema of the build directory (see [`CacheKey`]).
+const BUILD_DIR_SCHEMA: &str = "cmake-build/1";
+
+/// The default client name used for the cmake file-api query.
+pub const DEFAULT_CLIENT_NAME: &str = "embuild";
+
+/// A builder for configuring and building a cmake project.
+///
+/// Before configuring, a cmake file-api query for the [`query_kinds`](Self::query_kinds)
+/// is written to the build directory, so that the replies can be loaded from the
+/// returned [`Build`] afterwards.
+#[derive(Debug, Clone)]
+#[must_use]
+pub struct Runner {
+    source_dir: PathBuf,
+    build_dir: PathBuf,
+    generator: Option<Generator>,
+    toolchain_file: Option<PathBuf>,
+    defines: BTreeMap<String, String>,
+    env: Vec<(OsString, Option<OsString>)>,
+    build_target: Option<String>,
+    client_name: String,
+    query_kinds: Vec<ObjKind>,
+    query_mode: QueryMode,
+    output_classifier: OutputClassifier,
+}
+
+impl Runner {
+    /// Create a new runner for the cmake project in `source_dir` which is built in
+    /// `build_dir`.
+    pub fn new(source_dir: impl Into<PathBuf>, build_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            source_dir: source_dir.into(),
+            build_dir: build_dir.into(),
+            generator: None,
+            toolchain_file: None,
+            defines: BTreeMap::new(),
+            env: Vec::new(),
+            build_target: None,
+            client_name: DEFAULT_CLIENT_NAME.into(),
+            query_kinds: vec![ObjKind::Codemodel, ObjKind::Cache, ObjKind::Toolchains],
+            query_mode: QueryMode::default(),
+            output_classifier: OutputClassifier::default(),
+        }
+    }
+
+    /// The cmake generator to use (the `-G` argument).
+    ///
+    /// If not set, cmake uses its default generator.
+    pub fn generator(mut self, generator: Generator) -> Self {
+        self.generator = Some(generator);
+        self
+    }
+
+    /// The toolchain file to use (the `CMAKE_TOOLCHAIN_FILE` variable).
+    pub fn toolchain_file(mut self, toolchain_file: impl Into<PathBuf>) -> Self {
+        self.toolchain_file = Some(toolchain_file.into());
+        self
+    }
+
+    /// Define the cache variable `name` with `value` (`-D<name>=<value>`).
+    pub fn define(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.defines.insert(name.into(), value.into());
+        self
+    }
+
+    /// Define all cache variables in `defines` (see [`define`](Self::define)).
+    pub fn defines(
+        mut self,
+        defines: impl IntoIterator<Item = (impl Into<String>, impl Into<String>)>,
+    ) -> Self {
+        self.defines
+            .extend(defines.into_iter().map(|(k, v)| (k.into(), v.into())));
+        self
+    }
+
+    /// Set the environment variable `key` to `value` when running cmake.
+    pub fn env(mut self, key: impl AsRef<OsStr>, value: impl AsRef<OsStr>) -> Self {
+        self.env
+            .push((key.as_ref().to_owned(), Some(value.as_ref().to_owned())));
+        self
+    }
+
+    /// Remove the environment variable `key` when running cmake.
+    pub fn env_remove(mut self, key: impl AsRef<OsStr>) -> Self {
+        self.env.push((key.as_ref().to_owned(), None));
+        self
+    }
+
+    /// Only build the target `target` (the `--target` argument).
+    ///
+    /// If not set, the default target is built.
+    pub fn build_target(mut self, target: impl Into<String>) -> Self {
+        self.build_target = Some(target.into());
+        self
+    }
+
+    /// The client name of the cmake file-api query.
+    ///
+    /// Defaults to [`DEFAULT_CLIENT_NAME`].
+    pub fn client_name(mut self, client_name: impl Into<String>) -> Self {
+        self.client_name = client_name.into();
+        self
+    }
+
+    /// The cmake file-api object kinds to query.
+    ///
+    /// Defaults to [`ObjKind::Codemodel`], [`ObjKind::Cache`] and
+    /// [`ObjKind::Toolchains`].
+    pub fn query_kinds(mut self, kinds: impl IntoIterator<Item = ObjKind>) -> Self {
+        self.query_kinds = kinds.into_iter().collect();
+        self
+    }
+
+    /// The mode in which the cmake file-api query is written.
+    ///
+    /// Defaults to [`QueryMode::ClientStateless`].
+    pub fn query_mode(mut self, mode: QueryMode) -> Self {
+        self.query_mode = mode;
+        self
+    }
+
+    /// The classifier of the cmake and build tool output, whose errors and warnings are
+    /// printed as cargo warnings in build scripts.
+    ///
+    /// Defaults to [`OutputClassifier::default`].
+    pub fn output_classifier(mut self, classifier: OutputClassifier) -> Self {
+        self.output_classifier = classifier;
+        self
+    }
+
+    /// Configure the project if needed and build it.
+    pub fn run(self) -> Result<Build> {
+        let build = self.configure()?;
+        build.build()?;
+
+        Ok(build)
+    }
+
+    /// Only configure the project (if needed) without building it.
+    ///
+    /// Configuring is skipped if the build directory was already configured with the
+    /// same inputs (the source directory, generator, toolchain file and its contents,
+    /// defines, and file-api query).
+    pub fn configure(self) -> Result<Build> {
+        self.try_configure().map_err(crate::diag::record_error)
+    }
+
+    fn try_configure(self) -> Result<Build> {
+        cache::validate_or_clean(&self.build_dir, &self.cache_key()).with_context(|| {
+            anyhow!(
+                "Could not create cmake build directory '{}'",
+                self.build_dir.display()
+            )
+        })?;
+
+        self.write_query()?;
+
+        let stamp = self.configure_stamp()?;
+        let stamp_file = self.build_dir.join(CONFIGURE_STAMP_FILE);
+
+        let configured = self.build_dir.join("CMakeCache.txt").is_file()
+            && fs::read_to_string(&stamp_file).ok().as_deref() == Some(stamp.as_str());
+
+        if configured {
+            log::info!(
+                "cmake project unchanged, skipping reconfigure of build directory '{}'",
+                self.build_dir.display()
+            );
+        } else {
+            // Remove the stamp first so that a failed configure is retried.
+            let _ = fs::remove_file(&stamp_file);
+
+            let mut cmd = self.command();
+            cmd.arg("-S")
+                .arg(&self.source_dir)
+                .arg("-B")
+                .arg(&self.build_dir);
+
+            if let Some(generator) = self.generator {
+                cmd.arg("-G").arg(generator.name());
+            }
+            if let Some(toolchain_file) = &self.toolchain_file {
+                cmd.arg(format!(
+                    "-DCMAKE_TOOLCHAIN_FILE={}",
+                    toolchain_file.display()
+                ));
+            }
+            cmd.args(self.defines.iter().map(|(k, v)| format!("-D{k}={v}")));
+
+            let step = logging::scoped_step(format!(
+                "Configuring cmake project '{}'",
+                self.source_dir.display()
+            ));
+            run_streaming(cmd, &self.output_classifier)
+                .context("Configuring cmake project failed")?;
+            step.finish();
+
+            crate::fs::write_if_changed(&stamp_file, &stamp)?;
+        }
+
+        artifacts::record(ArtifactKind::ProjectDir, &self.source_dir)?;
+        artifacts::record(ArtifactKind::BuildDir, &self.build_dir)?;
+
+        Ok(Build { runner: self })
+    }
+
+    fn write_query(&self) -> Result<Query<'static>> {
+        Query::builder(&self.build_dir, &self.client_name)
+            .mode(self.query_mode)
+            .kinds(self.query_kinds.iter().copied())
+            .write()
+    }
+
+    fn command(&self) -> Command {
+        let mut cmd = Command::new(cmake());
+        for (key, value) in &self.env {
+            match value {
+                Some(value) => cmd.env(key, value),
+                None => cmd.env_remove(key),
+            };
+        }
+        cmd
+    }
+
+    /// The cache key of the build directory, which is cleaned when it was configured by
+    /// another version of embuild or cmake.
+    fn cache_key(&self) -> CacheKey {
+        let key = CacheKey::new(BUILD_DIR_SCHEMA);
+        // If cmake can't be run, configuring fails with a better error.
+        match cmd!(cmake(), "--version").stdout() {
+            Ok(version) => key.tool("cmake", version.lines().next().unwrap_or_default()),
+            Err(_) => key,
+        }
+    }
+
+    /// Compute a hash of all inputs that influence the configure step.
+    fn configure_stamp(&self) -> Result<String> {
+        let mut hasher = StableHasher::new();
+
+        self.source_dir.to_string_lossy().hash(&mut hasher);
+        self.generator
+            .as_ref()
+            .map(Generator::name)
+            .hash(&mut hasher);
+        self.defines.hash(&mut hasher);
+        self.query_kinds.hash(&mut hasher);
+        self.query_mode.hash(&mut hasher);
+        self.client_name.hash(&mut hasher);
+
+        if let Some(toolchain_file) = &self.toolchain_file {
+            toolchain_file.to_string_lossy().hash(&mut hasher);
+            fs::read(toolchain_file)
+                .with_context(|| {
+                    anyhow!(
+                        "Could not read cmake toolchain file '{}'",
+                        toolchain_file.display()
+                    )
+                })?
+                .hash(&mut hasher);
+        }
+
+        // The environment of cmake: the variables set for it, and the variables of this
+        // process it reads when configuring.
+        for (key, value) in &self.env {
+            key.to_string_lossy().hash(&mut hasher);
+            value
+                .as_ref()
+                .map(|v| v.to_string_lossy())
+                .hash(&mut hasher);
+        }
+        for var in CONFIGURE_ENV_VARS {
+            var.hash(&mut hasher);
+            env::var_os(var)
+                .as_ref()
+                .map(|v| v.to_string_lossy())
+                .hash(&mut hasher);
+        }
+
+        Ok(hasher.finish_hex())
+    }
+}
+
+/// A configured cmake build directory as returned by [`Runner::run`] or
+/// [`Runner::configure`].
+#[derive(Debug, Clone)]
+pub struct Build {
+    runner: Runner,
+}
+
+impl Build {
+    /// The source directory of the cmake project.
+    pub fn source_dir(&self) -> &Path {
+        &self.runner.source_dir
+    }
+
+    /// The build directory of the cmake project.
+    pub fn build_dir(&self) -> &Path {
+        &self.runner.build_dir
+    }
+
+    /// The environment variables set when running cmake (see [`Runner::env`]).
+    pub fn env(&self) -> impl Iterator<Item = (&OsStr, &OsStr)> {
+        self.runner
+            .env
+            .iter()
+            .filter_map(|(key, value)| Some((key.as_os_str(), value.as_deref()?)))
+    }
+
+    /// Build the project (again).
+    pub fn build(&self) -> Result<()> {
+        let mut cmd = self.runner.command();
+        cmd.arg("--build").arg(&self.runner.build_dir);
+
+        if let Some(target) = &self.runner.build_target {
+            cmd.arg("--target").arg(target);
+        }
+
+        let step = logging::scoped_step(format!(
+            "Building cmake project '{}'",
+            self.runner.source_dir.display()
+        ));
+        run_streaming(cmd, &self.runner.output_classifier)
+            .context("Building cmake project failed")
+            .map_err(crate::diag::record_error)?;
+        step.finish();
+
+        // The partition table of esp-idf projects.
+        let partition_table = path_buf![
+            &self.runner.build_dir,
+            "partition_table",
+            "partition-table.bin"
+        ];
+        if partition_table.is_file() {
+            artifacts::record(ArtifactKind::PartitionTable, partition_table)?;
+        }
+        Ok(())
+    }
+
+    /// Load the replies of the cmake file-api query.
+    pub fn replies(&self) -> Result<Replies> {
+        self.runner.write_query()?.get_replies()
+    }
+
+    /// Load the codemodel object of the cmake file-api query.
+    ///
+    /// Convenience function for `replies()?.get_codemodel()`.
+    pub fn codemodel(&self) -> Result<Codemodel> {
+        self.replies()?.get_codemodel()
+    }
+}
+
+impl crate::build::ManifestSource for Build {
+    /// Cmake and the compilers of the toolchains (if queried, see
+    /// [`Runner::query_kinds`]).
+    fn manifest_entries(&self) -> Result<Vec<crate::build::ManifestEntry>> {
+        use crate::build::ManifestEntry;
+
+        let replies = self.replies()?;
+        let mut cmake = ManifestEntry::new("cmake").version(replies.cmake.version.to_string());
+        if let Some(path) = replies.cmake.paths.get(&PathsKey::CMake) {
+            cmake = cmake.path(path);
+        }
+
+        let mut entries = vec![cmake];
+        if replies.replies.contains_key(&ObjKind::Toolchains) {
+            for toolchain in replies.get_toolchains()?.toolchains {
+                let compiler = toolchain.compiler;
+                let mut entry = ManifestEntry::new(format!("{:?} compiler", toolchain.language));
+                entry.version = compiler.version;
+                entry.path = compiler.path;
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+}
+
+/// Run `cmd` to completion with [`Cmd::tee`], reporting the lines classified by
+/// `classifier`.
+fn run_streaming(cmd: Command, classifier: &OutputClassifier) -> Result<(), CmdError> {
+    Cmd::from(cmd)
+        .output_classifier(classifier.clone())
+        .tee(None)
+        .map(drop)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_configure_stamp() {
+        let dir = tempfile::tempdir().unwrap();
+        let toolchain_file = dir.path().join("toolchain.cmake");
+        fs::write(&toolchain_file, "set(CMAKE_C_COMPILER gcc)").unwrap();
+
+        let runner = Runner::new("src", "build")
+            .generator(Generator::Ninja)
+            .toolchain_file(&toolchain_file)
+            .define("FOO", "1");
+        let stamp = runner.configure_stamp().unwrap();
+
+        assert_eq!(stamp, runner.clone().configure_stamp().unwrap());
+        assert_ne!(
+            stamp,
+            runner.clone().define("FOO", "2").configure_stamp().unwrap()
+        );
+        assert_ne!(
+            stamp,
+            runner
+                .clone()
+                .env("CFLAGS", "-Os")
+                .configure_stamp()
+                .unwrap()
+        );
+
+        fs::write(&toolchain_file, "set(CMAKE_C_COMPILER clang)").unwrap();
+        assert_ne!(stamp, runner.configure_stamp().unwrap());
+    }
+}
@@ -1,11 +1,108 @@
 //! Command building and running utilities.
 
-use std::ffi::OsStr;
-use std::io;
-use std::process::{self, Command, ExitStatus};
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::fmt::Write as _;
+use std::io::{self, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::process::{self, Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::{env, fs};
+
+use crate::logging::warning;
+use crate::utils::OsStrExt;
+
+#[cfg(feature = "regex")]
+mod classify;
+#[cfg(feature = "regex")]
+pub use classify::*;
+
+/// The interval in which a command with a timeout is polled for completion.
+const TIMEOUT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long the output of a killed command is still collected.
+const KILLED_OUTPUT_GRACE: Duration = Duration::from_secs(1);
+
+/// The number of last output lines included in the error of a failed [`Cmd::tee`].
+const TEE_TAIL_LINES: usize = 50;
+
+/// The environment variable controlling the logging of all commands.
+///
+/// - `debug`: print every command line (see [`Cmd::command_line`]) to stderr before
+///   running it.
+/// - `dry-run`: print every command line to stderr and don't run any command (see
+///   [`Cmd::dry_run`]), pretending that it succeeded.
+pub const CMD_LOG_VAR: &str = "EMBUILD_CMD_LOG";
+
+/// The command logging mode set with [`CMD_LOG_VAR`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum CmdLog {
+    Default,
+    Debug,
+    DryRun,
+}
+
+impl CmdLog {
+    fn get() -> Self {
+        static CMD_LOG: OnceLock<CmdLog> = OnceLock::new();
+
+        *CMD_LOG.get_or_init(|| match env::var(CMD_LOG_VAR).as_deref() {
+            Ok("debug") => CmdLog::Debug,
+            Ok("dry-run") => CmdLog::DryRun,
+            Ok(other) if !other.is_empty() => {
+                warning!("Ignoring unknown value '{other}' of `{CMD_LOG_VAR}`");
+                CmdLog::Default
+            }
+            _ => CmdLog::Default,
+        })
+    }
+}
+
+/// The replacement of secrets registered with [`redact`].
+const REDACTED: &str = "***";
+
+/// All secrets registered with [`redact`], longest first.
+static SECRETS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Hide `secret` in all command lines logged from now on (see [`Cmd::command_line`]) and
+/// in the messages of all [`CmdError`]s, by replacing it with `***`.
+///
+/// Use this for credentials passed to commands in arguments or environment variables.
+pub fn redact(secret: impl Into<String>) {
+    let secret = secret.into();
+    if secret.is_empty() {
+        return;
+    }
+
+    let mut secrets = SECRETS.lock().unwrap();
+    if !secrets.contains(&secret) {
+        secrets.push(secret);
+        secrets.sort_by_key(|s| std::cmp::Reverse(s.len()));
+    }
+}
+
+/// Replace all secrets registered with [`redact`] in `text` with `***`.
+pub fn redacted(text: &str) -> String {
+    SECRETS
+        .lock()
+        .unwrap()
+        .iter()
+        .fold(text.to_owned(), |text, secret| {
+            text.replace(secret.as_str(), REDACTED)
+        })
+}
+
+/// Format `cmd` for a [`CmdError`], with all secrets redacted.
+pub(crate) fn error_cmd(cmd: &process::Command) -> String {
+    redacted(&format!("{cmd:?}"))
+}
 
 /// Error when trying to execute a command.
 #[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
 pub enum CmdError {
     /// The command failed to start.
     #[error("command '{0}' failed to start")]
@@ -16,12 +113,23 @@ pub enum CmdError {
     /// The command was terminated unexpectedly.
     #[error("command '{0}' was terminated unexpectedly")]
     Terminated(String),
+    /// The command did not finish within its timeout and was killed.
+    #[error("command '{cmd}' timed out after {duration:?}")]
+    TimedOut {
+        cmd: String,
+        duration: Duration,
+        captured_output: Option<String>,
+    },
+    /// The command exited successfully but printed lines classified as errors (see
+    /// [`OutputClassifier::fail_on_error`]).
+    #[error("command '{cmd}' reported errors:\n{}", errors.join("\n"))]
+    ErrorOutput { cmd: String, errors: Vec<String> },
 }
 
 impl CmdError {
     /// Create a [`CmdError::NoRun`].
     pub fn no_run(cmd: &process::Command, error: io::Error) -> Self {
-        CmdError::NoRun(format!("{cmd:?}"), error)
+        CmdError::NoRun(error_cmd(cmd), error)
     }
 
     /// Convert a [`process::ExitStatus`] into a `Result<(), CmdError>`.
@@ -34,12 +142,12 @@ impl CmdError {
             Ok(())
         } else if let Some(code) = status.code() {
             Err(CmdError::Unsuccessful(
-                format!("{cmd:?}"),
+                error_cmd(cmd),
                 code,
-                cmd_output().map(anyhow::Error::msg),
+                cmd_output().map(|output| anyhow::Error::msg(redacted(&output))),
             ))
         } else {
-            Err(CmdError::Terminated(format!("{cmd:?}")))
+            Err(CmdError::Terminated(error_cmd(cmd)))
         }
     }
 }
@@ -50,6 +158,29 @@ pub struct Cmd {
     /// The actual [`std::process::Command`] wrapped.
     pub cmd: std::process::Command,
     ignore_exitcode: bool,
+    timeout: Option<Duration>,
+    kill_on_drop: bool,
+    dry_run: Option<bool>,
+    env_cleared: bool,
+    response_file_threshold: Option<usize>,
+    retry: Option<(u32, Duration)>,
+    retry_if: Option<RetryPredicate>,
+    cancel: Option<Arc<AtomicBool>>,
+    quiet: bool,
+    #[cfg(feature = "regex")]
+    classifier: Option<Arc<OutputClassifier>>,
+}
+
+/// A predicate deciding whether a failed command is retried (see [`Cmd::retry_if`]).
+#[derive(Clone)]
+struct RetryPredicate(Arc<RetryFn>);
+
+type RetryFn = dyn Fn(Option<i32>, Option<&str>) -> bool + Send + Sync;
+
+impl std::fmt::Debug for RetryPredicate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("RetryPredicate(..)")
+    }
 }
 
 impl std::ops::Deref for Cmd {
@@ -71,6 +202,17 @@ impl From<std::process::Command> for Cmd {
         Cmd {
             cmd,
             ignore_exitcode: false,
+            timeout: None,
+            kill_on_drop: false,
+            dry_run: None,
+            env_cleared: false,
+            response_file_threshold: None,
+            retry: None,
+            retry_if: None,
+            cancel: None,
+            quiet: false,
+            #[cfg(feature = "regex")]
+            classifier: None,
         }
     }
 }
@@ -88,6 +230,17 @@ impl Cmd {
         Self {
             cmd: Command::new(program),
             ignore_exitcode: false,
+            timeout: None,
+            kill_on_drop: false,
+            dry_run: None,
+            env_cleared: false,
+            response_file_threshold: None,
+            retry: None,
+            retry_if: None,
+            cancel: None,
+            quiet: false,
+            #[cfg(feature = "regex")]
+            classifier: None,
         }
     }
 
@@ -103,31 +256,474 @@ impl Cmd {
         self
     }
 
+    /// Don't stream the output of [`Cmd::tee`] to stderr, only capture (and classify)
+    /// it.
+    pub fn quiet(&mut self) -> &mut Self {
+        self.quiet = true;
+        self
+    }
+
+    /// Kill the command (including all processes it started) if it did not finish after
+    /// `timeout`, failing with [`CmdError::TimedOut`].
+    ///
+    /// On Unix the command is run in its own process group, so that the whole group can
+    /// be killed. On Windows the process tree is killed with `taskkill`.
+    ///
+    /// Applies to:
+    /// - [`Cmd::run`]
+    /// - [`Cmd::status`]
+    /// - [`Cmd::output`]
+    /// - [`Cmd::stdout`]
+    /// - [`Cmd::stderr`]
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Kill the command (including all processes it started) when the [`CmdChild`]
+    /// returned by [`Cmd::spawn`] is dropped before the command finished.
+    pub fn kill_on_drop(&mut self, kill_on_drop: bool) -> &mut Self {
+        self.kill_on_drop = kill_on_drop;
+        self
+    }
+
+    /// Run the command up to `attempts` times if it fails with a transient error, waiting
+    /// `backoff` before the first retry and doubling the wait before every further one.
+    ///
+    /// Whether a failure is transient is decided by the predicate set with
+    /// [`Cmd::retry_if`], by default with [`is_network_error`] on the captured output.
+    /// If all attempts fail, the outputs of all attempts are included in the error.
+    ///
+    /// Applies to:
+    /// - [`Cmd::run`], which doesn't capture the output: with the default predicate,
+    ///   only commands that failed to start are retried. Use [`Cmd::tee`] to retry on
+    ///   network errors while still printing the output, or [`Cmd::retry_if`] to
+    ///   retry on exit codes.
+    /// - [`Cmd::output`]
+    /// - [`Cmd::stdout`]
+    /// - [`Cmd::stderr`]
+    /// - [`Cmd::tee`]
+    pub fn retry(&mut self, attempts: u32, backoff: Duration) -> &mut Self {
+        self.retry = Some((attempts, backoff));
+        self
+    }
+
+    /// Only retry a failed command (see [`Cmd::retry`]) if `predicate` returns `true` for
+    /// its exit code (`None` if it was terminated or timed out) and its captured output
+    /// (`None` if the output was not captured, e.g. with [`Cmd::run`]).
+    ///
+    /// The default predicate retries if the captured output contains a typical network
+    /// error (see [`is_network_error`]). Commands that failed to start (or whose status
+    /// could not be read) are only retried with the default predicate, unless the program
+    /// was not found.
+    pub fn retry_if(
+        &mut self,
+        predicate: impl Fn(Option<i32>, Option<&str>) -> bool + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.retry_if = Some(RetryPredicate(Arc::new(predicate)));
+        self
+    }
+
+    /// Run `f` with the retries configured with [`Cmd::retry`].
+    fn retried<T>(
+        &mut self,
+        mut f: impl FnMut(&mut Self) -> Result<T, CmdError>,
+    ) -> Result<T, CmdError> {
+        let Some((attempts, backoff)) = self.retry else {
+            return f(self);
+        };
+        let predicate = self.retry_if.clone();
+        let cancel = self.cancel.clone();
+        let is_transient = |error: &CmdError| {
+            if cancel.as_ref().is_some_and(|c| c.load(Ordering::Relaxed)) {
+                return false;
+            }
+            let (code, output) = match error {
+                CmdError::NoRun(_, e) => {
+                    return predicate.is_none() && e.kind() != io::ErrorKind::NotFound
+                }
+                CmdError::ErrorOutput { .. } => return false,
+                CmdError::Unsuccessful(_, code, output) => {
+                    (Some(*code), output.as_ref().map(|o| o.to_string()))
+                }
+                CmdError::Terminated(_) => (None, None),
+                CmdError::TimedOut {
+                    captured_output, ..
+                } => (None, captured_output.clone()),
+            };
+            match &predicate {
+                Some(predicate) => (predicate.0)(code, output.as_deref()),
+                None => output.is_some_and(|output| is_network_error(&output)),
+            }
+        };
+
+        retrying(attempts, backoff, is_transient, |_| f(self)).map_err(|error| {
+            let count = error.errors.len();
+            let mut outputs = String::new();
+            for (attempt, error) in error.errors.iter().enumerate() {
+                let output = match error {
+                    CmdError::Unsuccessful(_, _, Some(output)) => output.to_string(),
+                    CmdError::TimedOut {
+                        captured_output: Some(output),
+                        ..
+                    } => output.clone(),
+                    _ => String::new(),
+                };
+                write!(
+                    outputs,
+                    "\n--- attempt {}/{count}: {error}\n{output}",
+                    attempt + 1
+                )
+                .unwrap();
+            }
+
+            let mut last = error.into_last();
+            if count > 1 {
+                let outputs = outputs.trim().to_owned();
+                match &mut last {
+                    CmdError::Unsuccessful(_, _, output) => {
+                        *output = Some(anyhow::Error::msg(outputs))
+                    }
+                    CmdError::TimedOut {
+                        captured_output, ..
+                    } => *captured_output = Some(outputs),
+                    _ => (),
+                }
+            }
+            last
+        })
+    }
+
+    /// Remove the environment variable `key` from the environment of the command.
+    pub fn env_remove(&mut self, key: impl AsRef<OsStr>) -> &mut Self {
+        self.cmd.env_remove(key);
+        self
+    }
+
+    /// Clear the environment of the command, so that it doesn't inherit any variables.
+    pub fn env_clear(&mut self) -> &mut Self {
+        self.cmd.env_clear();
+        self.env_cleared = true;
+        self
+    }
+
+    /// Prepend `dirs` to the `PATH` of the command (see [`EnvSet::path_prepend`]).
+    pub fn env_path_prepend(&mut self, dirs: impl IntoIterator<Item = PathBuf>) -> &mut Self {
+        self.envs_from(&EnvSet::new().path_prepend(dirs))
+    }
+
+    /// Apply the environment changes `env` to the command.
+    pub fn envs_from(&mut self, env: &EnvSet) -> &mut Self {
+        self.env_cleared = env.apply_cleared(&mut self.cmd, self.env_cleared);
+        self
+    }
+
+    /// Pass the arguments in a response file (as a single `@<file>` argument, see
+    /// [`cli::spill_response_file`](crate::cli::spill_response_file)) if their joined
+    /// length exceeds `threshold` (e.g. the 32k command line limit of Windows).
+    ///
+    /// The response file is written to the `OUT_DIR` in build scripts and to the
+    /// temporary directory otherwise, and removed once the command finished (or, with
+    /// [`Cmd::spawn`], once the [`CmdChild`] is dropped). As the underlying
+    /// [`std::process::Command`] is recreated with the program, the environment and
+    /// the working directory for the run, any stdio configuration of it is reset.
+    pub fn auto_response_file(&mut self, threshold: usize) -> &mut Self {
+        self.response_file_threshold = Some(threshold);
+        self
+    }
+
+    /// Classify the output lines of [`Cmd::tee`] with `classifier`, printing the errors
+    /// and warnings as cargo warnings in build scripts.
+    ///
+    /// With [`OutputClassifier::fail_on_error`], a command that exited successfully
+    /// fails with [`CmdError::ErrorOutput`] if any line was classified as an error.
+    #[cfg(feature = "regex")]
+    pub fn output_classifier(&mut self, classifier: OutputClassifier) -> &mut Self {
+        self.classifier = Some(Arc::new(classifier));
+        self
+    }
+
+    /// Run `f` with the arguments replaced by a response file if enabled and needed (see
+    /// [`Cmd::auto_response_file`]), restoring the arguments and removing the response
+    /// file afterwards.
+    fn with_response_file<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T, CmdError>,
+    ) -> Result<T, CmdError> {
+        let Some((cmd, file)) = self.spilled_command()? else {
+            return f(self);
+        };
+
+        let original = std::mem::replace(&mut self.cmd, cmd);
+        let result = f(self);
+        self.cmd = original;
+        drop(file);
+        result
+    }
+
+    /// Get the command with the arguments replaced by a response file, if enabled and
+    /// needed, and the response file.
+    fn spilled_command(&self) -> Result<Option<(Command, ResponseFile)>, CmdError> {
+        use crate::cli::{spill_response_file, Syntax};
+
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let Some(threshold) = self.response_file_threshold else {
+            return Ok(None);
+        };
+        let error = |e: anyhow::Error| {
+            CmdError::no_run(
+                &self.cmd,
+                io::Error::new(io::ErrorKind::Other, format!("{e:#}")),
+            )
+        };
+
+        let args = self
+            .cmd
+            .get_args()
+            .map(|arg| {
+                arg.to_str()
+                    .ok_or_else(|| anyhow::anyhow!("argument {arg:?} is not valid utf-8"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()
+            .map_err(error)?;
+
+        let dir = env::var_os("OUT_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(env::temp_dir);
+        let file = dir.join(format!(
+            "embuild-args-{}-{}.rsp",
+            process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let spilled =
+            spill_response_file(&args, &file, threshold, Syntax::NATIVE).map_err(error)?;
+        if spilled == args {
+            return Ok(None);
+        }
+        let file = ResponseFile(file);
+
+        let mut cmd = Command::new(self.cmd.get_program());
+        cmd.args(spilled);
+        if self.env_cleared {
+            cmd.env_clear();
+        }
+        for (key, value) in self.cmd.get_envs() {
+            match value {
+                Some(value) => cmd.env(key, value),
+                None => cmd.env_remove(key),
+            };
+        }
+        if let Some(dir) = self.cmd.get_current_dir() {
+            cmd.current_dir(dir);
+        }
+
+        Ok(Some((cmd, file)))
+    }
+
+    /// Don't run the command but only log its command line (see [`Cmd::command_line`]),
+    /// pretending that it exited successfully if `success` is `true` and with exit code
+    /// `1` otherwise.
+    ///
+    /// The output of a dry-run command is empty. [`Cmd::spawn`] fails in dry-run mode.
+    ///
+    /// All commands are dry-run if the [`CMD_LOG_VAR`] environment variable is set to
+    /// `dry-run`.
+    pub fn dry_run(&mut self, success: bool) -> &mut Self {
+        self.dry_run = Some(success);
+        self
+    }
+
+    /// Get the full command line of this command for logging.
+    ///
+    /// The command line includes the working directory and the modified environment
+    /// variables, all quoted (with [`cli::quote_for_shell`](crate::cli::quote_for_shell))
+    /// so that it can be copied and pasted into a POSIX shell, e.g.:
+    /// ```text
+    /// cd /project && env -u PYTHONHOME IDF_PATH=/esp-idf cmake -B build '-DNAME=a b'
+    /// ```
+    ///
+    /// Secrets registered with [`redact`] are replaced with `***`.
+    pub fn command_line(&self) -> String {
+        use crate::cli::{join_for_shell, quote_for_shell};
+
+        let mut line = String::new();
+        if let Some(dir) = self.cmd.get_current_dir() {
+            write!(line, "cd {} && ", quote_for_shell(dir)).unwrap();
+        }
+
+        let mut envs = self.cmd.get_envs().collect::<Vec<_>>();
+        envs.sort_by_key(|(key, value)| (value.is_some(), *key));
+        if !envs.is_empty() {
+            line.push_str("env ");
+            for (key, value) in envs {
+                match value {
+                    Some(value) => {
+                        let mut var = key.to_owned();
+                        var.push("=");
+                        var.push(value);
+                        write!(line, "{} ", quote_for_shell(var)).unwrap();
+                    }
+                    None => write!(line, "-u {} ", quote_for_shell(key)).unwrap(),
+                }
+            }
+        }
+
+        line.push_str(&join_for_shell(
+            std::iter::once(self.cmd.get_program()).chain(self.cmd.get_args()),
+        ));
+        redacted(&line)
+    }
+
+    /// Log the command line and get the pretended exit status if this is a dry run.
+    fn log_and_dry_run(&self) -> Option<ExitStatus> {
+        let cmd_log = CmdLog::get();
+        let dry_run = self.dry_run.or((cmd_log == CmdLog::DryRun).then_some(true));
+
+        let line = self.command_line();
+        if dry_run.is_some() {
+            log::info!("Dry run: {line}");
+        } else {
+            log::debug!("Running: {line}");
+        }
+        if cmd_log != CmdLog::Default {
+            let _ = writeln!(
+                io::stderr(),
+                "{}{line}",
+                if dry_run.is_some() { "[dry run] " } else { "" }
+            );
+        }
+
+        dry_run.map(|success| exit_status(if success { 0 } else { 1 }))
+    }
+
+    /// Start the command without waiting for it to finish.
+    ///
+    /// See [`Cmd::kill_on_drop`]. A response file (see [`Cmd::auto_response_file`]) is
+    /// removed when the returned [`CmdChild`] is dropped.
+    pub fn spawn(&mut self) -> Result<CmdChild, CmdError> {
+        // The response file is removed with the child.
+        let spilled = self.spilled_command()?;
+        let (original, response_file) = match spilled {
+            Some((cmd, file)) => (Some(std::mem::replace(&mut self.cmd, cmd)), Some(file)),
+            None => (None, None),
+        };
+        let child = self.spawn_spilled();
+        if let Some(original) = original {
+            self.cmd = original;
+        }
+
+        let mut child = child?;
+        child.response_file = response_file;
+        Ok(child)
+    }
+
+    fn spawn_spilled(&mut self) -> Result<CmdChild, CmdError> {
+        if self.log_and_dry_run().is_some() {
+            return Err(CmdError::no_run(
+                &self.cmd,
+                io::Error::new(
+                    io::ErrorKind::Other,
+                    "cannot spawn a command in dry-run mode",
+                ),
+            ));
+        }
+
+        let child = self.spawn_child()?;
+        Ok(CmdChild {
+            child,
+            kill_on_drop: self.kill_on_drop,
+            response_file: None,
+        })
+    }
+
+    fn spawn_child(&mut self) -> Result<Child, CmdError> {
+        #[cfg(unix)]
+        if self.timeout.is_some() || self.kill_on_drop || self.cancel.is_some() {
+            use std::os::unix::process::CommandExt;
+            self.cmd.process_group(0);
+        }
+
+        self.cmd.spawn().map_err(|e| CmdError::no_run(&self.cmd, e))
+    }
+
+    /// Wait for the `child` until the timeout or the cancellation (see [`run_parallel_ext`]),
+    /// killing it if either happens.
+    fn wait(&self, child: &mut Child) -> Result<ExitStatus, CmdError> {
+        if self.timeout.is_none() && self.cancel.is_none() {
+            return child.wait().map_err(|e| CmdError::no_run(&self.cmd, e));
+        }
+
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            match child.try_wait() {
+                Ok(Some(status)) => break Ok(status),
+                Ok(None) if deadline.is_some_and(|deadline| Instant::now() >= deadline) => {
+                    kill_tree(child);
+
+                    break Err(CmdError::TimedOut {
+                        cmd: error_cmd(&self.cmd),
+                        duration: self.timeout.unwrap_or_default(),
+                        captured_output: None,
+                    });
+                }
+                Ok(None)
+                    if self
+                        .cancel
+                        .as_ref()
+                        .is_some_and(|cancel| cancel.load(Ordering::Relaxed)) =>
+                {
+                    kill_tree(child);
+
+                    break Err(CmdError::Terminated(error_cmd(&self.cmd)));
+                }
+                Ok(None) => thread::sleep(TIMEOUT_POLL_INTERVAL),
+                Err(e) => break Err(CmdError::no_run(&self.cmd, e)),
+            }
+        }
+    }
+
     /// Run the command to completion.
     ///
     /// If [`Cmd::ignore_exitcode`] has been called a program that exited with an error
     /// will also return [`Ok`], otherwise it will return [`Err`].
     /// A program that failed to start will always return an [`Err`].
     ///
+    /// The output is not captured, so with [`Cmd::retry`] the default predicate doesn't
+    /// retry on network errors (see [`Cmd::retry`]).
+    ///
     /// [`std::process::Command::status`] is used internally.
     pub fn run(&mut self) -> Result<(), CmdError> {
-        self.cmd
-            .status()
-            .map_err(|e| CmdError::no_run(&self.cmd, e))
-            .and_then(|v| {
-                if self.ignore_exitcode {
+        self.retried(|cmd| {
+            cmd.status().and_then(|v| {
+                if cmd.ignore_exitcode {
                     Ok(())
                 } else {
-                    CmdError::status_into_result(v, &self.cmd, || None)
+                    CmdError::status_into_result(v, &cmd.cmd, || None)
                 }
             })
+        })
     }
 
     /// Run the command and get its [`ExitStatus`].
     pub fn status(&mut self) -> Result<ExitStatus, CmdError> {
-        self.cmd
-            .status()
-            .map_err(|e| CmdError::no_run(&self.cmd, e))
+        self.with_response_file(Self::status_spilled)
+    }
+
+    fn status_spilled(&mut self) -> Result<ExitStatus, CmdError> {
+        if let Some(status) = self.log_and_dry_run() {
+            return Ok(status);
+        }
+
+        if self.timeout.is_none() && self.cancel.is_none() {
+            return self
+                .cmd
+                .status()
+                .map_err(|e| CmdError::no_run(&self.cmd, e));
+        }
+
+        let mut child = self.spawn_child()?;
+        self.wait(&mut child)
     }
 
     fn print_output(&self, output: &std::process::Output) {
@@ -148,8 +744,13 @@ impl Cmd {
         &mut self,
         func: impl FnOnce(std::process::Output) -> T,
     ) -> Result<T, CmdError> {
-        match self.cmd.output() {
-            Err(err) => Err(CmdError::no_run(&self.cmd, err)),
+        self.retried(Self::checked_output).map(func)
+    }
+
+    /// Run the command to completion and check its exit status.
+    fn checked_output(&mut self) -> Result<process::Output, CmdError> {
+        match self.output_with_timeout() {
+            Err(err) => Err(err),
             Ok(result) => if self.ignore_exitcode {
                 self.print_output(&result);
                 Ok(())
@@ -166,7 +767,73 @@ impl Cmd {
                 self.print_output(&result);
                 e
             })
-            .map(|_| func(result)),
+            .map(|_| result),
+        }
+    }
+
+    fn output_with_timeout(&mut self) -> Result<process::Output, CmdError> {
+        self.with_response_file(Self::output_spilled)
+    }
+
+    fn output_spilled(&mut self) -> Result<process::Output, CmdError> {
+        if let Some(status) = self.log_and_dry_run() {
+            return Ok(process::Output {
+                status,
+                stdout: Vec::new(),
+                stderr: Vec::new(),
+            });
+        }
+
+        if self.timeout.is_none() && self.cancel.is_none() {
+            return self
+                .cmd
+                .output()
+                .map_err(|e| CmdError::no_run(&self.cmd, e));
+        }
+
+        self.cmd
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let mut child = self.spawn_child()?;
+
+        // The pipes are read on separate threads so that the child can't block on a
+        // full pipe. They are only waited on for a short time after a timeout, as the
+        // pipes might still be held open by processes the child spawned.
+        let read = |mut pipe: Box<dyn Read + Send>| {
+            let (sender, receiver) = mpsc::channel();
+            thread::spawn(move || {
+                let mut buf = Vec::new();
+                let _ = pipe.read_to_end(&mut buf);
+                let _ = sender.send(buf);
+            });
+            receiver
+        };
+        let stdout = read(Box::new(child.stdout.take().unwrap()));
+        let stderr = read(Box::new(child.stderr.take().unwrap()));
+
+        match self.wait(&mut child) {
+            Ok(status) => Ok(process::Output {
+                status,
+                stdout: stdout.recv().unwrap_or_default(),
+                stderr: stderr.recv().unwrap_or_default(),
+            }),
+            Err(CmdError::TimedOut { cmd, duration, .. }) => {
+                let mut output = String::new();
+                for receiver in [stdout, stderr] {
+                    let buf = receiver
+                        .recv_timeout(KILLED_OUTPUT_GRACE)
+                        .unwrap_or_default();
+                    output.push_str(&String::from_utf8_lossy(&buf));
+                }
+
+                Err(CmdError::TimedOut {
+                    cmd,
+                    duration,
+                    captured_output: Some(redacted(output.trim_end())),
+                })
+            }
+            Err(e) => Err(e),
         }
     }
 
@@ -192,12 +859,583 @@ impl Cmd {
         })
     }
 
+    /// Run the command to completion while streaming its stdout and stderr line by line
+    /// to stderr, prefixed with `prefix` (e.g. `[cmake] `), and get its captured output.
+    ///
+    /// The lines of stdout and stderr are captured in the order they arrived, a lone `\r`
+    /// (e.g. of progress output) also ends a line. If the command fails, the last 50
+    /// lines of its output are included in the error.
+    ///
+    /// If [`Cmd::ignore_exitcode`] has been called a program that exited with an error
+    /// will also return [`Ok`], otherwise it will return [`Err`].
+    /// A program that failed to start will always return an [`Err`].
+    pub fn tee(&mut self, prefix: Option<&str>) -> Result<String, CmdError> {
+        self.retried(|cmd| cmd.tee_once(prefix))
+    }
+
+    fn tee_once(&mut self, prefix: Option<&str>) -> Result<String, CmdError> {
+        self.with_response_file(|cmd| cmd.tee_spilled(prefix))
+    }
+
+    fn tee_spilled(&mut self, prefix: Option<&str>) -> Result<String, CmdError> {
+        if let Some(status) = self.log_and_dry_run() {
+            return if self.ignore_exitcode {
+                Ok(String::new())
+            } else {
+                CmdError::status_into_result(status, &self.cmd, || None).map(|_| String::new())
+            };
+        }
+
+        self.cmd
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let mut child = self.spawn_child()?;
+
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        #[cfg(feature = "regex")]
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let prefix = prefix.unwrap_or_default().to_owned();
+        let quiet = self.quiet;
+
+        let stream = |pipe: Box<dyn Read + Send>| {
+            let (sender, receiver) = mpsc::channel();
+            let lines = lines.clone();
+            let prefix = prefix.clone();
+            #[cfg(feature = "regex")]
+            let (classifier, errors) = (self.classifier.clone(), errors.clone());
+            thread::spawn(move || {
+                let emit = |line: &mut Vec<u8>| {
+                    let line = String::from_utf8_lossy(&std::mem::take(line)).into_owned();
+                    if !quiet {
+                        let _ = writeln!(io::stderr(), "{prefix}{line}");
+                    }
+                    #[cfg(feature = "regex")]
+                    if let Some(error) = classifier.as_ref().and_then(|c| c.report(&line)) {
+                        errors.lock().unwrap().push(error);
+                    }
+                    lines.lock().unwrap().push(line);
+                };
+
+                // A lone `\r` (as used by progress output) also ends a line.
+                let mut line = Vec::new();
+                let mut after_cr = false;
+                for byte in BufReader::new(pipe).bytes() {
+                    let Ok(byte) = byte else { break };
+
+                    match byte {
+                        b'\n' if after_cr => (),
+                        b'\n' | b'\r' => emit(&mut line),
+                        byte => line.push(byte),
+                    }
+                    after_cr = byte == b'\r';
+                }
+                if !line.is_empty() {
+                    emit(&mut line);
+                }
+                let _ = sender.send(());
+            });
+            receiver
+        };
+        let stdout = stream(Box::new(child.stdout.take().unwrap()));
+        let stderr = stream(Box::new(child.stderr.take().unwrap()));
+
+        let status = self.wait(&mut child);
+        let grace = match status {
+            Ok(_) => None,
+            Err(_) => Some(KILLED_OUTPUT_GRACE),
+        };
+        for receiver in [stdout, stderr] {
+            let _ = match grace {
+                Some(grace) => receiver.recv_timeout(grace).ok(),
+                None => receiver.recv().ok(),
+            };
+        }
+
+        let lines = std::mem::take(&mut *lines.lock().unwrap());
+        let tail = || lines[lines.len().saturating_sub(TEE_TAIL_LINES)..].join("\n");
+
+        match status {
+            Ok(status) if !self.ignore_exitcode => {
+                CmdError::status_into_result(status, &self.cmd, || Some(tail()))?
+            }
+            Ok(_) => (),
+            Err(CmdError::TimedOut { cmd, duration, .. }) => {
+                return Err(CmdError::TimedOut {
+                    cmd,
+                    duration,
+                    captured_output: Some(redacted(&tail())),
+                })
+            }
+            Err(e) => return Err(e),
+        }
+
+        #[cfg(feature = "regex")]
+        if self.classifier.as_ref().is_some_and(|c| c.fails_on_error()) {
+            let errors = std::mem::take(&mut *errors.lock().unwrap());
+            if !errors.is_empty() && !self.ignore_exitcode {
+                return Err(CmdError::ErrorOutput {
+                    cmd: error_cmd(&self.cmd),
+                    errors,
+                });
+            }
+        }
+
+        Ok(lines.join("\n"))
+    }
+
     /// Turn this [`Cmd`] into its underlying [`std::process::Command`].
     pub fn into_inner(self) -> std::process::Command {
         self.cmd
     }
 }
 
+/// Typical (lowercase) messages of transient network errors of `git`, `pip`, `curl` and
+/// download code.
+const NETWORK_ERRORS: &[&str] = &[
+    "could not resolve host",
+    "temporary failure in name resolution",
+    "name or service not known",
+    "dns failed",
+    "connection refused",
+    "connection reset",
+    "connection failed",
+    "failed to connect",
+    "network is unreachable",
+    "network error",
+    "timed out",
+    "early eof",
+    "rpc failed",
+    "unexpected disconnect",
+    "the remote end hung up unexpectedly",
+    "gnutls_handshake",
+    "tls handshake",
+    "max retries exceeded",
+    "returned error: 502",
+    "returned error: 503",
+    "returned error: 504",
+    "status code 502",
+    "status code 503",
+    "status code 504",
+];
+
+/// Whether `output` (e.g. the stderr of a command or an error message) contains a
+/// typical transient network error message, like `Could not resolve host` or
+/// `Connection reset by peer`.
+pub fn is_network_error(output: &str) -> bool {
+    let output = output.to_lowercase();
+    NETWORK_ERRORS.iter().any(|e| output.contains(e))
+}
+
+/// Error of [`retrying`] with the errors of all attempts.
+#[derive(Debug)]
+pub struct RetryError<E> {
+    /// The errors of all attempts, in order.
+    pub errors: Vec<E>,
+}
+
+impl<E> RetryError<E> {
+    /// Get the error of the last attempt.
+    pub fn into_last(mut self) -> E {
+        self.errors.pop().expect("at least one attempt")
+    }
+}
+
+impl<E: std::fmt::Display> std::fmt::Display for RetryError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let [error] = &self.errors[..] {
+            return write!(f, "{error:#}");
+        }
+
+        write!(f, "all {} attempts failed", self.errors.len())?;
+        for (attempt, error) in self.errors.iter().enumerate() {
+            write!(f, "\n - attempt {}: {error:#}", attempt + 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl<E: std::fmt::Display + std::fmt::Debug> std::error::Error for RetryError<E> {}
+
+/// Call `f` with the attempt number (starting at `1`) up to `attempts` times until it
+/// succeeds, waiting `backoff` before the first retry and doubling the wait before every
+/// further one.
+///
+/// Errors for which `is_transient` returns `false` are not retried. Every retry is logged
+/// as a warning.
+pub fn retrying<T, E: std::fmt::Display>(
+    attempts: u32,
+    backoff: Duration,
+    is_transient: impl Fn(&E) -> bool,
+    mut f: impl FnMut(u32) -> Result<T, E>,
+) -> Result<T, RetryError<E>> {
+    let attempts = attempts.max(1);
+    let mut backoff = backoff;
+    let mut errors = Vec::new();
+
+    for attempt in 1..=attempts {
+        match f(attempt) {
+            Ok(value) => return Ok(value),
+            Err(e) => {
+                let retry = attempt < attempts && is_transient(&e);
+                if retry {
+                    warning!("Attempt {attempt}/{attempts} failed, retrying in {backoff:?}: {e:#}");
+                }
+                errors.push(e);
+                if !retry {
+                    break;
+                }
+            }
+        }
+
+        thread::sleep(backoff);
+        backoff *= 2;
+    }
+
+    Err(RetryError { errors })
+}
+
+/// What happens to the commands still running when a command run by
+/// [`run_parallel_ext`] fails.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum InFlight {
+    /// Let them run to completion.
+    #[default]
+    Finish,
+    /// Kill them (including all processes they started).
+    Kill,
+}
+
+/// Run the `commands` in parallel, at most `max_concurrency` at a time, and get their
+/// outputs in the order of `commands`.
+///
+/// See [`run_parallel_ext`], the commands still running when one fails are run to
+/// completion.
+pub fn run_parallel(
+    commands: Vec<Cmd>,
+    max_concurrency: usize,
+) -> Result<Vec<process::Output>, CmdError> {
+    run_parallel_ext(commands, max_concurrency, InFlight::Finish)
+}
+
+/// Run the `commands` in parallel, at most `max_concurrency` at a time, and get their
+/// outputs in the order of `commands`.
+///
+/// The output of every command is captured separately (see [`Cmd::output`]) and every
+/// finished command is logged as `[2/6] done: <command line>`. As soon as a command
+/// fails, no further commands are started and the commands still running are handled
+/// according to `in_flight`; the error of the first failed command is returned.
+pub fn run_parallel_ext(
+    commands: Vec<Cmd>,
+    max_concurrency: usize,
+    in_flight: InFlight,
+) -> Result<Vec<process::Output>, CmdError> {
+    let cancel = Arc::new(AtomicBool::new(false));
+
+    parallel(
+        commands,
+        max_concurrency,
+        &cancel,
+        |cmd| cmd.command_line(),
+        |mut cmd| {
+            if in_flight == InFlight::Kill {
+                cmd.cancel = Some(cancel.clone());
+            }
+            cmd.retried(Cmd::checked_output)
+        },
+    )
+}
+
+/// Call `f` for all `items` in parallel on at most `max_concurrency` threads and get the
+/// results in the order of `items`.
+///
+/// Every finished item is logged as `[2/6] done: <label>`. As soon as `f` fails for an
+/// item, no further items are started and the first error is returned once the running
+/// calls have finished.
+pub fn map_parallel<T: Send, R: Send, E: Send>(
+    items: Vec<T>,
+    max_concurrency: usize,
+    label: impl Fn(&T) -> String + Sync,
+    f: impl Fn(T) -> Result<R, E> + Sync,
+) -> Result<Vec<R>, E> {
+    parallel(items, max_concurrency, &AtomicBool::new(false), label, f)
+}
+
+/// Implementation of [`map_parallel`], setting `cancel` as soon as `f` fails.
+fn parallel<T: Send, R: Send, E: Send>(
+    items: Vec<T>,
+    max_concurrency: usize,
+    cancel: &AtomicBool,
+    label: impl Fn(&T) -> String + Sync,
+    f: impl Fn(T) -> Result<R, E> + Sync,
+) -> Result<Vec<R>, E> {
+    let total = items.len();
+    let queue = Mutex::new(items.into_iter().enumerate());
+    let results = Mutex::new((0..total).map(|_| None).collect::<Vec<_>>());
+    let error = Mutex::new(None);
+    let done = AtomicUsize::new(0);
+
+    thread::scope(|scope| {
+        for _ in 0..max_concurrency.clamp(1, total.max(1)) {
+            scope.spawn(|| loop {
+                if cancel.load(Ordering::Relaxed) {
+                    break;
+                }
+                let Some((index, item)) = queue.lock().unwrap().next() else {
+                    break;
+                };
+
+                let label = label(&item);
+                match f(item) {
+                    Ok(result) => {
+                        results.lock().unwrap()[index] = Some(result);
+                        let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+                        log::info!("[{done}/{total}] done: {label}");
+                    }
+                    Err(e) => {
+                        error.lock().unwrap().get_or_insert(e);
+                        cancel.store(true, Ordering::Relaxed);
+                        break;
+                    }
+                }
+            });
+        }
+    });
+
+    if let Some(error) = error.into_inner().unwrap() {
+        return Err(error);
+    }
+    Ok(results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.expect("all items processed"))
+        .collect())
+}
+
+/// A set of environment changes that can be created once and applied to many commands
+/// (see [`Cmd::envs_from`] and [`EnvSet::apply`]).
+///
+/// The changes are applied in the order they were added.
+#[derive(Clone, Debug, Default)]
+#[must_use]
+pub struct EnvSet {
+    ops: Vec<EnvOp>,
+}
+
+#[derive(Clone, Debug)]
+enum EnvOp {
+    Set(OsString, OsString),
+    Remove(OsString),
+    Clear,
+    PathPrepend(Vec<PathBuf>),
+}
+
+impl EnvSet {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Set the environment variable `key` to `value`.
+    pub fn set(mut self, key: impl Into<OsString>, value: impl Into<OsString>) -> Self {
+        self.ops.push(EnvOp::Set(key.into(), value.into()));
+        self
+    }
+
+    /// Remove the environment variable `key`.
+    pub fn remove(mut self, key: impl Into<OsString>) -> Self {
+        self.ops.push(EnvOp::Remove(key.into()));
+        self
+    }
+
+    /// Clear the environment, so that no variables are inherited.
+    pub fn clear(mut self) -> Self {
+        self.ops.push(EnvOp::Clear);
+        self
+    }
+
+    /// Prepend `dirs` to the `PATH` environment variable.
+    ///
+    /// The `dirs` are joined with the platform's path separator and prepended to the
+    /// `PATH` already set on the command, or to the `PATH` of the current process if
+    /// none was set. On Windows, the `PATH` variable is looked up case-insensitively.
+    pub fn path_prepend(mut self, dirs: impl IntoIterator<Item = PathBuf>) -> Self {
+        self.ops
+            .push(EnvOp::PathPrepend(dirs.into_iter().collect()));
+        self
+    }
+
+    /// Apply all environment changes to `cmd`.
+    pub fn apply(&self, cmd: &mut Command) {
+        self.apply_cleared(cmd, false);
+    }
+
+    /// Apply all environment changes to `cmd`, whose environment was already cleared if
+    /// `cleared` is `true`, and return whether it is cleared afterwards.
+    fn apply_cleared(&self, cmd: &mut Command, mut cleared: bool) -> bool {
+        for op in &self.ops {
+            match op {
+                EnvOp::Set(key, value) => {
+                    cmd.env(key, value);
+                }
+                EnvOp::Remove(key) => {
+                    cmd.env_remove(key);
+                }
+                EnvOp::Clear => {
+                    cmd.env_clear();
+                    cleared = true;
+                }
+                EnvOp::PathPrepend(dirs) => {
+                    let (key, path) = match path_var(cmd.get_envs()) {
+                        Some((key, path)) => (key, path.unwrap_or_default()),
+                        None if cleared => ("PATH".into(), OsString::new()),
+                        None => path_var(env::vars_os().map(|(k, v)| (k, Some(v))))
+                            .map(|(key, path)| (key, path.unwrap_or_default()))
+                            .unwrap_or_else(|| ("PATH".into(), OsString::new())),
+                    };
+
+                    let paths = dirs
+                        .iter()
+                        .cloned()
+                        .chain(path.split_paths_ext())
+                        .collect::<Vec<_>>();
+                    match env::join_paths(paths) {
+                        Ok(path) => {
+                            cmd.env(key, path);
+                        }
+                        Err(e) => warning!("Could not prepend {dirs:?} to `PATH`: {e}"),
+                    }
+                }
+            }
+        }
+        cleared
+    }
+}
+
+impl<K: Into<OsString>, V: Into<OsString>> FromIterator<(K, V)> for EnvSet {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        iter.into_iter()
+            .fold(EnvSet::new(), |env, (key, value)| env.set(key, value))
+    }
+}
+
+impl<K: Into<OsString>, V: Into<OsString>> From<HashMap<K, V>> for EnvSet {
+    fn from(map: HashMap<K, V>) -> Self {
+        map.into_iter().collect()
+    }
+}
+
+impl<K: Into<OsString>, V: Into<OsString>> From<BTreeMap<K, V>> for EnvSet {
+    fn from(map: BTreeMap<K, V>) -> Self {
+        map.into_iter().collect()
+    }
+}
+
+/// Find the `PATH` variable in `vars` (case-insensitively on Windows), returning its
+/// actual name and value.
+fn path_var<K: AsRef<OsStr>, V: AsRef<OsStr>>(
+    vars: impl Iterator<Item = (K, Option<V>)>,
+) -> Option<(OsString, Option<OsString>)> {
+    vars.filter(|(key, _)| {
+        if cfg!(windows) {
+            key.as_ref().eq_ignore_ascii_case("PATH")
+        } else {
+            key.as_ref() == "PATH"
+        }
+    })
+    .last()
+    .map(|(key, value)| {
+        (
+            key.as_ref().to_owned(),
+            value.map(|v| v.as_ref().to_owned()),
+        )
+    })
+}
+
+/// A running command started with [`Cmd::spawn`].
+#[derive(Debug)]
+pub struct CmdChild {
+    /// The actual [`std::process::Child`] wrapped.
+    pub child: Child,
+    kill_on_drop: bool,
+    response_file: Option<ResponseFile>,
+}
+
+/// A response file written by [`Cmd::auto_response_file`], removed when dropped.
+#[derive(Debug)]
+struct ResponseFile(PathBuf);
+
+impl Drop for ResponseFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.0) {
+            log::debug!("Could not remove '{}': {e}", self.0.display());
+        }
+    }
+}
+
+impl std::ops::Deref for CmdChild {
+    type Target = Child;
+
+    fn deref(&self) -> &Self::Target {
+        &self.child
+    }
+}
+
+impl std::ops::DerefMut for CmdChild {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.child
+    }
+}
+
+impl Drop for CmdChild {
+    fn drop(&mut self) {
+        if self.kill_on_drop && matches!(self.child.try_wait(), Ok(None)) {
+            kill_tree(&mut self.child);
+        }
+    }
+}
+
+/// Create an [`ExitStatus`] with the exit `code`.
+fn exit_status(code: i32) -> ExitStatus {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        ExitStatus::from_raw(code << 8)
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::ExitStatusExt;
+        ExitStatus::from_raw(code as u32)
+    }
+}
+
+/// Kill the `child` and all processes it started, and wait for it to exit.
+fn kill_tree(child: &mut Child) {
+    let pid = child.id().to_string();
+
+    #[cfg(unix)]
+    let killer = Command::new("kill")
+        .args(["-KILL", "--", &format!("-{pid}")])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    #[cfg(windows)]
+    let killer = Command::new("taskkill")
+        .args(["/F", "/T", "/PID", &pid])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    #[cfg(not(any(unix, windows)))]
+    let killer: io::Result<ExitStatus> = Err(io::ErrorKind::Unsupported.into());
+
+    if let Err(e) = killer {
+        log::debug!("Could not kill the process tree of {pid}: {e}");
+    }
+
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
 /// Build a command using a given [`std::process::Command`] or [`Cmd`] and return it.
 ///
 /// The first argument is expected to be a [`std::process::Command`] or [`Cmd`] instance.
@@ -264,3 +1502,334 @@ macro_rules! cmd {
         $crate::cmd_build!(cmd $(, $(@$cmdargs,)* $cmdarg)* $(; $($k = $v),* )?)
     }};
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn test_timeout_kills() {
+        let start = Instant::now();
+        let result = cmd!("sh", "-c", "echo started; sleep 30")
+            .timeout(Duration::from_millis(500))
+            .stdout();
+
+        match result {
+            Err(CmdError::TimedOut {
+                captured_output, ..
+            }) => assert_eq!(captured_output.as_deref(), Some("started")),
+            other => panic!("unexpected result {other:?}"),
+        }
+        assert!(start.elapsed() < Duration::from_secs(10));
+
+        let result = cmd!("sleep", "30")
+            .timeout(Duration::from_millis(200))
+            .run();
+        assert!(matches!(result, Err(CmdError::TimedOut { .. })));
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_tee() {
+        let output = cmd!("sh", "-c", "echo out; echo err >&2; echo out2")
+            .tee(Some("[test] "))
+            .unwrap();
+        let mut lines = output.lines().collect::<Vec<_>>();
+        lines.sort();
+        assert_eq!(lines, ["err", "out", "out2"]);
+
+        let output = cmd!("sh", "-c", "echo out; echo err >&2")
+            .quiet()
+            .tee(None)
+            .unwrap();
+        assert_eq!(output.lines().count(), 2);
+
+        // Fill both pipes way beyond their capacity.
+        let output = cmd!(
+            "sh",
+            "-c",
+            "i=0; while [ $i -lt 100 ]; do printf '%01000d\\n' $i; printf '%01000d\\n' $i >&2; i=$((i+1)); done"
+        )
+        .tee(None)
+        .unwrap();
+        assert_eq!(output.lines().count(), 200);
+
+        let result = cmd!(
+            "sh",
+            "-c",
+            "i=0; while [ $i -lt 100 ]; do echo line$i; i=$((i+1)); done; exit 3"
+        )
+        .tee(None);
+        match result {
+            Err(CmdError::Unsuccessful(_, 3, Some(tail))) => {
+                let tail = tail.to_string();
+                assert_eq!(tail.lines().count(), TEE_TAIL_LINES);
+                assert_eq!(tail.lines().last(), Some("line99"));
+            }
+            other => panic!("unexpected result {other:?}"),
+        }
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_tee_classified() {
+        let script = "echo 'main.c:1:1: warning: w'; echo 'ERROR: failed' >&2; exit 0";
+        cmd!("sh", "-c", script)
+            .output_classifier(OutputClassifier::default())
+            .tee(None)
+            .unwrap();
+
+        let result = cmd!("sh", "-c", script)
+            .output_classifier(OutputClassifier::default().fail_on_error(true))
+            .tee(None);
+        match result {
+            Err(CmdError::ErrorOutput { errors, .. }) => assert_eq!(errors, ["ERROR: failed"]),
+            other => panic!("unexpected result {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_dry_run() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let mut cmd = cmd!("touch", "a b", "it's", OsStr::from_bytes(b"\xff");
+            current_dir=("/tmp/some dir"), env=("FOO", "1"), env_remove=("PYTHONHOME"));
+        assert_eq!(
+            cmd.command_line(),
+            r"cd '/tmp/some dir' && env -u PYTHONHOME FOO=1 touch 'a b' 'it'\''s' $'\xff'"
+        );
+
+        assert!(cmd.dry_run(true).run().is_ok());
+        assert_eq!(cmd.dry_run(true).stdout().unwrap(), "");
+        assert!(matches!(
+            cmd.dry_run(false).run(),
+            Err(CmdError::Unsuccessful(_, 1, _))
+        ));
+        assert!(cmd.dry_run(true).spawn().is_err());
+    }
+
+    #[test]
+    fn test_redact() {
+        redact("s3cr3t-cmd-test");
+
+        let mut cmd = cmd!("sh", "-c", "echo s3cr3t-cmd-test >&2; exit 2", "-c", "x=s3cr3t-cmd-test";
+            env=("TOKEN", "s3cr3t-cmd-test"));
+        assert_eq!(
+            cmd.command_line(),
+            "env TOKEN=*** sh -c 'echo *** >&2; exit 2' -c x=***"
+        );
+
+        let error = cmd.stdout().unwrap_err();
+        let message = format!("{:#}", anyhow::Error::new(error));
+        assert!(!message.contains("s3cr3t"), "{message}");
+        assert!(message.contains("echo *** >&2"), "{message}");
+        assert!(message.contains("status code 2"), "{message}");
+    }
+
+    #[test]
+    fn test_env_set() {
+        let env_set = EnvSet::new()
+            .set("EMBUILD_TEST_A", "a")
+            .remove("PYTHONHOME")
+            .path_prepend([
+                PathBuf::from("/opt/tools/bin"),
+                PathBuf::from("/opt/venv/bin"),
+            ]);
+
+        let child_env = |cmd: &mut Cmd| {
+            cmd.stdout()
+                .unwrap()
+                .lines()
+                .filter_map(|l| l.split_once('='))
+                .map(|(k, v)| (k.to_owned(), v.to_owned()))
+                .collect::<HashMap<_, _>>()
+        };
+
+        let vars = child_env(cmd!("/usr/bin/env"; env=("PYTHONHOME", "/py")).envs_from(&env_set));
+        assert_eq!(vars["EMBUILD_TEST_A"], "a");
+        assert!(!vars.contains_key("PYTHONHOME"));
+        let path = env::var("PATH").unwrap();
+        assert_eq!(vars["PATH"], format!("/opt/tools/bin:/opt/venv/bin:{path}"));
+
+        let vars = child_env(
+            cmd!("/usr/bin/env")
+                .env_clear()
+                .envs_from(&EnvSet::from(HashMap::from([("B", "b")])))
+                .env_path_prepend([PathBuf::from("/a")])
+                .env_path_prepend([PathBuf::from("/b")]),
+        );
+        assert_eq!(
+            vars,
+            HashMap::from([
+                ("B".to_owned(), "b".to_owned()),
+                ("PATH".to_owned(), "/b:/a".to_owned())
+            ])
+        );
+    }
+
+    #[test]
+    fn test_auto_response_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let script = tmp.path().join("args.sh");
+        fs::write(&script, "#!/bin/sh\necho \"$1\"\ncat \"${1#@}\"\n").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        let args = (0..100).map(|i| format!("arg {i}")).collect::<Vec<_>>();
+
+        let mut cmd = cmd!(&script; args=(&args), env=("FOO", "bar"));
+        cmd.auto_response_file(100);
+        for _ in 0..2 {
+            let output = cmd.stdout().unwrap();
+            let (file, contents) = output.split_once('\n').unwrap();
+            let joined = crate::cli::Syntax::NATIVE.join(args.iter().map(String::as_str));
+            assert_eq!(contents, joined.trim_end());
+            // The response file is removed after the command finished.
+            assert!(!Path::new(&file[1..]).exists());
+        }
+
+        let output = cmd!("echo", "short")
+            .auto_response_file(100)
+            .stdout()
+            .unwrap();
+        assert_eq!(output, "short");
+    }
+
+    #[test]
+    fn test_kill_on_drop() {
+        let child = cmd!("sleep", "30").kill_on_drop(true).spawn().unwrap();
+        let pid = child.id();
+        drop(child);
+
+        if Path::new("/proc/self").exists() {
+            assert!(!Path::new("/proc").join(pid.to_string()).exists());
+        }
+    }
+
+    #[test]
+    fn test_retry() {
+        let dir = tempfile::tempdir().unwrap();
+        let counter = dir.path().join("counter");
+        let script = format!(
+            "n=$(cat '{0}' 2>/dev/null || echo 0); n=$((n+1)); echo $n > '{0}'; \
+             [ $n -ge 3 ] && echo done && exit 0; \
+             echo \"fatal: unable to access: Could not resolve host: example.com ($n)\" >&2; exit 128",
+            counter.display()
+        );
+
+        let output = cmd!("sh", "-c", &script)
+            .retry(3, Duration::from_millis(10))
+            .stdout()
+            .unwrap();
+        assert_eq!(output, "done");
+
+        std::fs::remove_file(&counter).unwrap();
+        let error = cmd!("sh", "-c", &script)
+            .retry(2, Duration::from_millis(10))
+            .stdout()
+            .unwrap_err();
+        let error = format!("{:#}", anyhow::Error::new(error));
+        assert!(
+            error.contains("attempt 1/2") && error.contains("(1)"),
+            "{error}"
+        );
+        assert!(
+            error.contains("attempt 2/2") && error.contains("(2)"),
+            "{error}"
+        );
+        std::fs::remove_file(&counter).unwrap();
+
+        let error = cmd!("sh", "-c", &script)
+            .retry(3, Duration::from_millis(10))
+            .retry_if(|code, _| code != Some(128))
+            .stdout()
+            .unwrap_err();
+        assert!(matches!(error, CmdError::Unsuccessful(_, 128, _)));
+        assert_eq!(std::fs::read_to_string(&counter).unwrap().trim(), "1");
+        std::fs::remove_file(&counter).unwrap();
+
+        // Failures without captured output are not retried by default.
+        cmd!("sh", "-c", &script)
+            .retry(3, Duration::from_millis(10))
+            .run()
+            .unwrap_err();
+        assert_eq!(std::fs::read_to_string(&counter).unwrap().trim(), "1");
+        std::fs::remove_file(&counter).unwrap();
+    }
+
+    #[test]
+    fn test_retrying() {
+        let result = retrying(
+            5,
+            Duration::ZERO,
+            |e: &String| is_network_error(e),
+            |attempt| match attempt {
+                1 => Err("Connection reset by peer".to_owned()),
+                2 => Err("checksum mismatch".to_owned()),
+                _ => Ok(()),
+            },
+        );
+        assert_eq!(result.unwrap_err().errors.len(), 2);
+    }
+
+    #[test]
+    fn test_run_parallel() {
+        let commands = (0..6)
+            .map(|i| cmd!("sh", "-c", format!("sleep 0.{}; echo {i}", 6 - i)))
+            .collect();
+        let outputs = run_parallel(commands, 3).unwrap();
+        let stdouts = outputs
+            .iter()
+            .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(stdouts, ["0", "1", "2", "3", "4", "5"]);
+
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("marker");
+        let commands = vec![
+            cmd!("sh", "-c", "echo broken >&2; exit 3"),
+            cmd!("sh", "-c", "sleep 5"),
+            cmd!("touch", &marker),
+        ];
+        let start = Instant::now();
+        let error = run_parallel_ext(commands, 2, InFlight::Kill).unwrap_err();
+        assert!(
+            matches!(error, CmdError::Unsuccessful(_, 3, _)),
+            "{error:?}"
+        );
+        assert!(start.elapsed() < Duration::from_secs(4));
+        assert!(!marker.exists());
+    }
+
+    #[test]
+    fn test_map_parallel() {
+        let squares = map_parallel(
+            (0..20).collect(),
+            4,
+            |i| i.to_string(),
+            |i: u32| Ok::<_, ()>(i * i),
+        );
+        assert_eq!(squares.unwrap(), (0..20).map(|i| i * i).collect::<Vec<_>>());
+
+        let started = AtomicUsize::new(0);
+        let result = map_parallel(
+            (0..20).collect(),
+            1,
+            |i| i.to_string(),
+            |i: u32| {
+                started.fetch_add(1, Ordering::Relaxed);
+                if i == 2 {
+                    Err(i)
+                } else {
+                    Ok(i)
+                }
+            },
+        );
+        assert_eq!(result, Err(2));
+        assert_eq!(started.load(Ordering::Relaxed), 3);
+    }
+}
@@ -0,0 +1,351 @@
+use std::borrow::Cow;
+use std::env;
+
+use anyhow::{anyhow, Context, Result};
+use regex::Regex;
+
+use crate::cargo;
+
+/// The default rules of [`OutputClassifier`], in order.
+///
+/// These recognize the diagnostics of gcc, clang and binutils (including the xtensa
+/// toolchains), cmake, ninja, make, scons, PlatformIO, `idf.py` and `idf_tools.py`, and
+/// ignore some benign warnings.
+pub const DEFAULT_RULES: &[(&str, OutputClass)] = &[
+    // Benign noise.
+    (
+        r"^Warning! Ignore unknown configuration option",
+        OutputClass::Ignore,
+    ),
+    (
+        r"^CMake (?:Warning \(dev\)|Deprecation Warning)",
+        OutputClass::Ignore,
+    ),
+    (r"^WARNING: You are using pip version", OutputClass::Ignore),
+    // gcc, clang and the assembler with a location.
+    (
+        r"^\S.*?:[0-9]+(?::[0-9]+)?: (?:fatal )?(?:error|Error): ",
+        OutputClass::Error,
+    ),
+    (
+        r"^\S.*?:[0-9]+(?::[0-9]+)?: (?:warning|Warning): ",
+        OutputClass::Warning,
+    ),
+    (r"^\S.*?:[0-9]+(?::[0-9]+)?: note: ", OutputClass::Info),
+    // The compiler drivers and the linker without a location.
+    (
+        r"^(?:\S*[/\\])?(?:\S+-)?(?:gcc|g\+\+|cc1|cc1plus|clang|clang\+\+|ld|ld\.bfd|ld\.lld)(?:\.exe)?: (?:fatal )?error: ",
+        OutputClass::Error,
+    ),
+    (
+        r"^(?:\S*[/\\])?(?:\S+-)?(?:gcc|g\+\+|cc1|cc1plus|clang|clang\+\+|ld|ld\.bfd|ld\.lld)(?:\.exe)?: warning: ",
+        OutputClass::Warning,
+    ),
+    // Linker errors.
+    (
+        r"undefined reference to|multiple definition of|dangerous relocation|cannot find -l|region `[^']*' overflowed",
+        OutputClass::Error,
+    ),
+    // cmake.
+    (r"^CMake Error", OutputClass::Error),
+    (r"^CMake Warning", OutputClass::Warning),
+    // ninja, make and scons.
+    (r"^FAILED: ", OutputClass::Error),
+    (r"^make(?:\[[0-9]+\])?: \*\*\* ", OutputClass::Error),
+    (r"^\*\*\* \[.*\] Error [0-9]+", OutputClass::Error),
+    // PlatformIO, idf.py and idf_tools.py.
+    (r"^Error: ", OutputClass::Error),
+    (r"^ERROR: ", OutputClass::Error),
+    (r"^WARNING: ", OutputClass::Warning),
+];
+
+/// The class of a line of tool output, see [`OutputClassifier`].
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum OutputClass {
+    /// An error, printed as a cargo warning in build scripts.
+    Error,
+    /// A warning, printed as a cargo warning in build scripts.
+    Warning,
+    /// An informational line, logged with [`log::info`].
+    Info,
+    /// A line that is not reported.
+    Ignore,
+}
+
+/// A rule of an [`OutputClassifier`].
+#[derive(Clone, Debug)]
+pub struct ClassifierRule {
+    regex: Regex,
+    class: OutputClass,
+    format: Option<String>,
+}
+
+impl ClassifierRule {
+    /// Create a rule classifying the lines matching `regex` as `class`.
+    pub fn new(regex: &str, class: OutputClass) -> Result<Self> {
+        Ok(Self {
+            regex: Regex::new(regex)
+                .with_context(|| anyhow!("invalid output classifier rule '{regex}'"))?,
+            class,
+            format: None,
+        })
+    }
+
+    /// Report the matched lines as `format` instead, in which `$1` or `${name}` are
+    /// replaced with the groups captured by the regex (see
+    /// [`regex::Captures::expand`]).
+    pub fn format(mut self, format: impl Into<String>) -> Self {
+        self.format = Some(format.into());
+        self
+    }
+}
+
+/// Classifies the lines of the output of a tool (e.g. a compiler) with ordered regex
+/// rules, of which the first matching one wins.
+///
+/// The [`Default`] classifier has the [`DEFAULT_RULES`]. It is used by the cmake
+/// [`Runner`](crate::cmake::Runner), [`Pio::build`](crate::pio::Pio::build), the esp-idf
+/// [`Installer`](crate::espidf::Installer) and [`Cmd::tee`](super::Cmd::tee) (see
+/// [`Cmd::output_classifier`](super::Cmd::output_classifier)), which print the errors
+/// and warnings as cargo warnings in build scripts.
+///
+/// # Examples
+/// ```
+/// # use embuild::cmd::{ClassifierRule, OutputClass, OutputClassifier};
+/// let classifier = OutputClassifier::default()
+///     .prepend(ClassifierRule::new(r"^Note: ", OutputClass::Ignore)?)
+///     .push(
+///         ClassifierRule::new(r"^E \([0-9]+\) (.*)$", OutputClass::Error)?.format("esp-idf: $1"),
+///     )
+///     .fail_on_error(true);
+///
+/// assert_eq!(
+///     classifier.classify("E (123) boot failed"),
+///     Some((OutputClass::Error, "esp-idf: boot failed".into()))
+/// );
+/// # anyhow::Ok(())
+/// ```
+#[derive(Clone, Debug)]
+#[must_use]
+pub struct OutputClassifier {
+    rules: Vec<ClassifierRule>,
+    fail_on_error: bool,
+}
+
+impl Default for OutputClassifier {
+    fn default() -> Self {
+        Self {
+            rules: DEFAULT_RULES
+                .iter()
+                .map(|(regex, class)| ClassifierRule::new(regex, *class).unwrap())
+                .collect(),
+            fail_on_error: false,
+        }
+    }
+}
+
+impl OutputClassifier {
+    /// Create a classifier without any rules.
+    pub fn empty() -> Self {
+        Self {
+            rules: Vec::new(),
+            fail_on_error: false,
+        }
+    }
+
+    /// Add `rule` after all rules.
+    pub fn push(mut self, rule: ClassifierRule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Add `rule` before all rules, so that it takes precedence over them.
+    pub fn prepend(mut self, rule: ClassifierRule) -> Self {
+        self.rules.insert(0, rule);
+        self
+    }
+
+    /// Fail the command if a line is classified as [`OutputClass::Error`], even if it
+    /// exited successfully (as some tools do after fatal errors).
+    pub fn fail_on_error(mut self, fail_on_error: bool) -> Self {
+        self.fail_on_error = fail_on_error;
+        self
+    }
+
+    /// Whether lines classified as [`OutputClass::Error`] fail the command, see
+    /// [`OutputClassifier::fail_on_error`].
+    pub fn fails_on_error(&self) -> bool {
+        self.fail_on_error
+    }
+
+    /// Get the class and the (reformatted) message of `line`, or [`None`] if no rule
+    /// matches it.
+    pub fn classify<'a>(&self, line: &'a str) -> Option<(OutputClass, Cow<'a, str>)> {
+        self.rules.iter().find_map(|rule| {
+            let captures = rule.regex.captures(line)?;
+            let message = match &rule.format {
+                Some(format) => {
+                    let mut message = String::new();
+                    captures.expand(format, &mut message);
+                    Cow::Owned(message)
+                }
+                None => Cow::Borrowed(line),
+            };
+            Some((rule.class, message))
+        })
+    }
+
+    /// Classify `line` and report it (see [`OutputClass`]), returning the message if it
+    /// is an [`OutputClass::Error`].
+    pub(crate) fn report(&self, line: &str) -> Option<String> {
+        let (class, message) = self.classify(line)?;
+        match class {
+            OutputClass::Error | OutputClass::Warning => {
+                if env::var_os("OUT_DIR").is_some() {
+                    cargo::print_warning(&message);
+                }
+            }
+            OutputClass::Info => log::info!("{message}"),
+            OutputClass::Ignore => (),
+        }
+        (class == OutputClass::Error).then(|| message.into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_rules() {
+        use OutputClass::*;
+
+        let classifier = OutputClassifier::default();
+        let table = [
+            // gcc and clang
+            (
+                "src/main.c:12:5: warning: unused variable 'x' [-Wunused-variable]",
+                Some(Warning),
+            ),
+            (
+                "src/main.c:3:10: fatal error: foo.h: No such file or directory",
+                Some(Error),
+            ),
+            (
+                r"C:\proj\src\main.c:7:1: error: expected ';' before '}' token",
+                Some(Error),
+            ),
+            ("src/main.c:5:6: note: declared here", Some(Info)),
+            (
+                "xtensa-esp32-elf-gcc: error: unrecognized command-line option '-mfoo'",
+                Some(Error),
+            ),
+            (
+                "cc1: warning: command-line option '-std=c++17' is valid for C++",
+                Some(Warning),
+            ),
+            (
+                "clang: error: no such file or directory: 'main.c'",
+                Some(Error),
+            ),
+            // Assembler and linker
+            (
+                "src/start.S:3: Error: unknown opcode or format name 'foo'",
+                Some(Error),
+            ),
+            (
+                "xtensa-esp32-elf/bin/ld: warning: section `.bss' type changed to PROGBITS",
+                Some(Warning),
+            ),
+            (
+                "main.c:(.text.app_main+0x8): undefined reference to `foo'",
+                Some(Error),
+            ),
+            (
+                "main.o:(.literal.app_main+0x0): dangerous relocation: l32r: literal placed after use: .literal.foo",
+                Some(Error),
+            ),
+            (
+                "riscv32-esp-elf/bin/ld: region `iram0_0_seg' overflowed by 24 bytes",
+                Some(Error),
+            ),
+            // cmake
+            (
+                "CMake Error at CMakeLists.txt:3 (project):",
+                Some(Error),
+            ),
+            ("CMake Warning at main/CMakeLists.txt:5:", Some(Warning)),
+            ("CMake Warning (dev) at CMakeLists.txt:1:", Some(Ignore)),
+            ("CMake Deprecation Warning at CMakeLists.txt:1:", Some(Ignore)),
+            ("-- Configuring done", None),
+            // ninja, make and scons
+            ("FAILED: esp-idf/main/CMakeFiles/main.dir/main.c.obj", Some(Error)),
+            ("make[2]: *** [Makefile:12: main.o] Error 1", Some(Error)),
+            (
+                "*** [.pio/build/debug/src/main.o] Error 1",
+                Some(Error),
+            ),
+            // PlatformIO
+            (
+                "Warning! Ignore unknown configuration option `foo` in section [env:debug]",
+                Some(Ignore),
+            ),
+            ("Error: Unknown board ID 'foo'", Some(Error)),
+            ("Compiling .pio/build/debug/src/main.o", None),
+            ("Linking .pio/build/debug/firmware.elf", None),
+            // idf.py, idf_tools.py and pip
+            (
+                "WARNING: The following Python requirements are not satisfied:",
+                Some(Warning),
+            ),
+            (
+                "ERROR: tool xtensa-esp-elf has no installed versions.",
+                Some(Error),
+            ),
+            (
+                "WARNING: You are using pip version 21.2.4; however, version 23.0 is available.",
+                Some(Ignore),
+            ),
+            ("Installing esp-idf tools", None),
+        ];
+
+        for (line, class) in table {
+            assert_eq!(
+                classifier.classify(line).map(|(class, _)| class),
+                class,
+                "{line}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_rules() {
+        let classifier = OutputClassifier::default()
+            .prepend(ClassifierRule::new("^WARNING: benign", OutputClass::Ignore).unwrap())
+            .push(
+                ClassifierRule::new(
+                    r"^(?P<level>[EW]) \(([0-9]+)\) (?P<tag>\w+): (.*)$",
+                    OutputClass::Warning,
+                )
+                .unwrap()
+                .format("${tag}: $4 (at $2 ms)"),
+            );
+
+        assert_eq!(
+            classifier.classify("WARNING: benign thing"),
+            Some((OutputClass::Ignore, "WARNING: benign thing".into()))
+        );
+        assert_eq!(
+            classifier.classify("W (312) wifi: no ap found"),
+            Some((OutputClass::Warning, "wifi: no ap found (at 312 ms)".into()))
+        );
+        assert_eq!(
+            classifier.report("ERROR: failed"),
+            Some("ERROR: failed".to_owned())
+        );
+        assert_eq!(classifier.report("WARNING: careful"), None);
+
+        assert!(ClassifierRule::new("(", OutputClass::Error).is_err());
+        assert_eq!(OutputClassifier::empty().classify("error: x"), None);
+    }
+}
@@ -0,0 +1,350 @@
+//! Generating `compile_commands.json` compilation databases.
+//!
+//! clangd and other C tooling read the compile flags of each source file from a
+//! [compilation database](https://clang.llvm.org/docs/JSONCompilationDatabase.html).
+//! cmake can write one itself, but the PlatformIO and the bindgen-only builds can't;
+//! [`CompileCommands`] writes one from the flags embuild knows about, e.g. the entries
+//! of a cmake target ([`entries_from_cmake`]) or of the headers bindgen is run on
+//! ([`entries_from_bindgen`]).
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Component, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::cargo;
+use crate::logging::warning;
+use crate::utils::PathExt;
+
+/// The default file name of the compilation database.
+pub const COMPILE_COMMANDS_FILE: &str = "compile_commands.json";
+
+/// The compiler of the entries created by [`entries_from_bindgen`].
+#[cfg(feature = "bindgen")]
+pub const CLANG: &str = "clang";
+
+/// An entry of a compilation database: how one source file is compiled.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    /// The working directory of the compilation, which relative paths of the
+    /// [`file`](Self::file) and [`arguments`](Self::arguments) are relative to.
+    pub directory: PathBuf,
+    /// The source file.
+    pub file: PathBuf,
+    /// The compile command (starting with the compiler) as a list of arguments.
+    ///
+    /// Arguments are not quoted: an argument containing spaces is a single element.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub arguments: Vec<String>,
+    /// The output of the compilation, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<PathBuf>,
+    /// The shell-quoted compile command of entries written by other tools (e.g. cmake),
+    /// which is kept when merging.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    command: Option<String>,
+}
+
+impl Entry {
+    /// Create the entry of `file` compiled with `arguments` in `directory`.
+    pub fn new<S: Into<String>>(
+        file: impl Into<PathBuf>,
+        directory: impl Into<PathBuf>,
+        arguments: impl IntoIterator<Item = S>,
+    ) -> Self {
+        Self {
+            directory: directory.into(),
+            file: file.into(),
+            arguments: arguments.into_iter().map(Into::into).collect(),
+            output: None,
+            command: None,
+        }
+    }
+
+    /// Set the output of the compilation.
+    pub fn with_output(mut self, output: impl Into<PathBuf>) -> Self {
+        self.output = Some(output.into());
+        self
+    }
+
+    /// The absolute path of the source file, with `.` and `..` components resolved
+    /// lexically.
+    pub fn file_path(&self) -> PathBuf {
+        let mut path = PathBuf::new();
+        for component in self.file.abspath_relative_to(&self.directory).components() {
+            match component {
+                Component::CurDir => (),
+                Component::ParentDir => {
+                    path.pop();
+                }
+                component => path.push(component),
+            }
+        }
+        path
+    }
+}
+
+/// A builder for a `compile_commands.json` file.
+#[derive(Clone, Debug, Default)]
+#[must_use]
+pub struct CompileCommands {
+    entries: Vec<Entry>,
+    output: Option<PathBuf>,
+}
+
+impl CompileCommands {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add an entry, replacing an entry added before for the same source file.
+    pub fn entry(self, entry: Entry) -> Self {
+        self.entries([entry])
+    }
+
+    /// Add `entries`, replacing entries added before for the same source files.
+    pub fn entries(mut self, entries: impl IntoIterator<Item = Entry>) -> Self {
+        for entry in entries {
+            let file = entry.file_path();
+            self.entries.retain(|e| e.file_path() != file);
+            self.entries.push(entry);
+        }
+        self
+    }
+
+    /// Set the file the compilation database is written to.
+    ///
+    /// Defaults to [`COMPILE_COMMANDS_FILE`] in the cargo workspace directory (see
+    /// [`cargo::workspace_dir`]).
+    pub fn output(mut self, file: impl Into<PathBuf>) -> Self {
+        self.output = Some(file.into());
+        self
+    }
+
+    /// The entries added so far.
+    pub fn get_entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// The file the compilation database is written to.
+    pub fn output_file(&self) -> Result<PathBuf> {
+        match &self.output {
+            Some(file) => Ok(file.clone()),
+            None => cargo::workspace_dir()
+                .map(|dir| dir.join(COMPILE_COMMANDS_FILE))
+                .ok_or_else(|| {
+                    anyhow!("Could not determine the cargo workspace directory to write `{COMPILE_COMMANDS_FILE}` to; set the output file explicitly")
+                }),
+        }
+    }
+
+    /// Write the compilation database to the [`output_file`](Self::output_file) and
+    /// return its path.
+    ///
+    /// The entries of an existing file are kept, except for the source files of the
+    /// added entries, which are replaced. The file is only written if its contents
+    /// change.
+    pub fn write(&self) -> Result<PathBuf> {
+        let file = self.output_file()?;
+
+        let existing = match fs::read(&file) {
+            Ok(content) => serde_json::from_slice::<Vec<Entry>>(&content).unwrap_or_else(|e| {
+                warning!(
+                    "Replacing the invalid compilation database '{}': {e}",
+                    file.display()
+                );
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+
+        let files = self
+            .entries
+            .iter()
+            .map(Entry::file_path)
+            .collect::<HashSet<_>>();
+        let entries = existing
+            .into_iter()
+            .filter(|e| !files.contains(&e.file_path()))
+            .chain(self.entries.iter().cloned())
+            .collect::<Vec<_>>();
+
+        if let Some(dir) = file.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        crate::fs::write_if_changed(&file, serde_json::to_string_pretty(&entries)?).with_context(
+            || anyhow!("Could not write compilation database '{}'", file.display()),
+        )?;
+
+        Ok(file)
+    }
+}
+
+/// Create the entries of all compiled sources of the cmake `target`.
+///
+/// `paths` are the top-level source and build directories of the codemodel. The
+/// compilers are taken from the `toolchains` if available, `cc` and `c++` are used
+/// otherwise.
+#[cfg(feature = "cmake")]
+pub fn entries_from_cmake(
+    target: &crate::cmake::file_api::codemodel::target::Target,
+    paths: &crate::cmake::file_api::codemodel::Paths,
+    toolchains: Option<&crate::cmake::file_api::Toolchains>,
+) -> Result<Vec<Entry>> {
+    use crate::cmake::file_api::codemodel::Language;
+
+    let mut entries = Vec::new();
+    for source in &target.sources {
+        let Some(index) = source.compile_group_index else {
+            continue;
+        };
+        let group = target.compile_groups.get(index).ok_or_else(|| {
+            anyhow!(
+                "Invalid compile group index {index} of source '{}' in cmake target '{}'",
+                source.path.display(),
+                target.name
+            )
+        })?;
+
+        let compiler = toolchains
+            .and_then(|t| t.get(group.language))
+            .and_then(|t| t.compiler.path.clone())
+            .unwrap_or_else(|| match group.language {
+                Language::Cpp => "c++".into(),
+                _ => "cc".into(),
+            });
+        let file = source.path.abspath_relative_to(&paths.source);
+
+        let arguments = [compiler.to_string_lossy().into_owned()]
+            .into_iter()
+            .chain(group.defines.iter().map(|d| format!("-D{}", d.define)))
+            .chain(group.includes.iter().flat_map(|i| {
+                if i.is_system {
+                    vec!["-isystem".to_owned(), i.path.clone()]
+                } else {
+                    vec![format!("-I{}", i.path)]
+                }
+            }))
+            .chain(
+                group
+                    .sysroot
+                    .iter()
+                    .map(|s| format!("--sysroot={}", s.path.display())),
+            )
+            .chain(group.std_arg())
+            .chain(group.expanded_compile_args(&paths.build)?)
+            .chain(["-c".to_owned(), file.to_string_lossy().into_owned()]);
+
+        entries.push(Entry::new(file, &paths.build, arguments));
+    }
+    Ok(entries)
+}
+
+/// Create the entries of the `headers` with the clang args bindgen is run with (see
+/// [`Factory::resolve`](crate::bindgen::Factory::resolve)), so that the headers passed
+/// to [`BindgenExt::headers`](crate::bindgen::BindgenExt::headers) are understood by
+/// clangd like by bindgen.
+///
+/// Relative header paths are relative to the current directory.
+#[cfg(feature = "bindgen")]
+pub fn entries_from_bindgen(
+    factory: crate::bindgen::Factory,
+    cpp: bool,
+    headers: impl IntoIterator<Item = impl AsRef<std::path::Path>>,
+) -> Result<Vec<Entry>> {
+    let resolved = factory.resolve(cpp)?;
+    let directory = std::env::current_dir()?;
+
+    Ok(headers
+        .into_iter()
+        .map(|header| {
+            let file = header.as_ref().abspath_relative_to(&directory);
+            let arguments = [CLANG.to_owned()]
+                .into_iter()
+                .chain(resolved.clang_args.iter().cloned())
+                .chain([file.to_string_lossy().into_owned()]);
+            Entry::new(file, &directory, arguments)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn test_write_merges() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let file = dir.join("db").join(COMPILE_COMMANDS_FILE);
+
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        fs::write(
+            &file,
+            r#"[
+  { "directory": "/build", "file": "/src/a.c", "command": "cc -c /src/a.c" },
+  { "directory": "/build", "file": "../src/b.c", "arguments": ["cc", "-c", "../src/b.c"] }
+]"#,
+        )
+        .unwrap();
+
+        let written = CompileCommands::new()
+            .output(&file)
+            .entry(Entry::new(
+                "/src/b.c",
+                "/",
+                ["cc", "-DOLD", "-c", "/src/b.c"],
+            ))
+            .entry(Entry::new(
+                "/src/b.c",
+                "/",
+                [
+                    "cc",
+                    "-DNAME=\"with spaces\"",
+                    "-I/my include",
+                    "-c",
+                    "/src/b.c",
+                ],
+            ))
+            .entry(Entry::new("c.c", "/src", ["cc", "-c", "c.c"]).with_output("c.o"))
+            .write()
+            .unwrap();
+        assert_eq!(written, file);
+
+        let content = fs::read_to_string(&file).unwrap();
+        let entries = serde_json::from_str::<Vec<Entry>>(&content).unwrap();
+        assert_eq!(entries.len(), 3);
+        // Entries of other tools are kept as is.
+        assert_eq!(entries[0].command.as_deref(), Some("cc -c /src/a.c"));
+        assert!(!content.contains("\"arguments\": []"));
+        // Replaced, not duplicated.
+        assert_eq!(
+            entries[1].arguments,
+            [
+                "cc",
+                "-DNAME=\"with spaces\"",
+                "-I/my include",
+                "-c",
+                "/src/b.c"
+            ]
+        );
+        assert!(content.contains(r#""-DNAME=\"with spaces\"""#));
+        assert!(content.contains(r#""-I/my include""#));
+        assert!(!content.contains("\"command\": \"cc -DNAME"));
+        assert_eq!(entries[2].output.as_deref(), Some(Path::new("c.o")));
+
+        // Writing again replaces the entries instead of duplicating them.
+        CompileCommands::new()
+            .output(&file)
+            .entry(Entry::new("/src/c.c", "/", ["cc", "-c", "/src/c.c"]))
+            .write()
+            .unwrap();
+        let entries = serde_json::from_slice::<Vec<Entry>>(&fs::read(&file).unwrap()).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[2].directory, Path::new("/"));
+    }
+}
@@ -9,27 +9,34 @@
 //!
 //! - **`~/.espressif`**, if `install_dir` is None
 
+use std::collections::HashMap;
 use std::ffi::{OsStr, OsString};
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::sync::Arc;
+use std::time::Duration;
 use std::{env, fs};
 
 use anyhow::{anyhow, Context, Error, Result};
 use serde::{Deserialize, Serialize};
 
-use crate::python::PYTHON;
+use crate::artifacts::{self, ArtifactKind};
+use crate::logging::{self, warning};
+use crate::utils::{sha256_file, Download, OsStrExt, PathExt};
 use crate::{cmd, git, path_buf, python};
 
 use self::tools_schema::{
     PlatformDownloadInfo, PlatformOverrideInfoPlatformsItem, ToolInfo, VersionInfo,
 };
 
+pub mod partitions;
+pub mod sdkconfig;
 #[cfg(feature = "elf")]
 pub mod ulp_fsm;
 
 mod tools_schema;
+mod verify;
 
 pub const DEFAULT_ESP_IDF_REPOSITORY: &str = "https://github.com/espressif/esp-idf.git";
 pub const MANAGED_ESP_IDF_REPOS_DIR_BASE: &str = "esp-idf";
@@ -41,9 +48,66 @@ pub const IDF_TOOLS_PATH_VAR: &str = "IDF_TOOLS_PATH";
 
 const IDF_PYTHON_ENV_PATH_VAR: &str = "IDF_PYTHON_ENV_PATH";
 
+/// Environment variable containing the path to the directory with all artifacts needed
+/// for an offline installation (see [`Installer::offline`]).
+pub const OFFLINE_DIR_VAR: &str = "EMBUILD_ESP_IDF_OFFLINE_DIR";
+
+/// Environment variable overriding the git URL of a [`EspIdfOrigin::Managed`] esp-idf
+/// without a custom [`repo_url`](EspIdfRemote#structfield.repo_url).
+pub const REPO_URL_VAR: &str = "EMBUILD_IDF_REPO_URL";
+
+/// Environment variable with `;`-separated download mirror base URLs for the tools (see
+/// [`Installer::mirrors`]).
+pub const MIRRORS_VAR: &str = "EMBUILD_IDF_MIRRORS";
+
+/// Environment variable forcing the [`Installer`] to install a managed esp-idf even if an
+/// activated esp-idf environment exists (see [`Installer::reuse_activated_env`]).
+pub const FORCE_MANAGED_VAR: &str = "EMBUILD_ESP_IDF_FORCE_MANAGED";
+
+/// Environment variable disabling the verification of the installed tools (see
+/// [`Installer::verify_tools`]).
+pub const SKIP_TOOL_VERIFICATION_VAR: &str = "EMBUILD_ESP_IDF_SKIP_TOOL_VERIFICATION";
+
+/// The file in the install dir recording the successfully verified tools (see
+/// [`Installer::verify_tools`]).
+pub const TOOLS_MANIFEST_FILE: &str = ".embuild-tools.json";
+
+/// The default timeout of a single tool download attempt.
+pub const DEFAULT_DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// An unreachable proxy used for all subprocesses of the [`Installer`] when network
+/// access is denied (see [`Installer::deny_network`]).
+const NO_NETWORK_PROXY: &str = "http://127.0.0.1:9";
+
 /// The global install dir of the esp-idf and its tools, relative to the user home dir.
 pub const GLOBAL_INSTALL_DIR: &str = ".espressif";
 
+/// The install dir of the esp-idf and its tools for [`InstallLocation::Workspace`],
+/// relative to the cargo target directory.
+pub const WORKSPACE_INSTALL_DIR: &str = ".embuild/espressif";
+
+/// The lock file in the install dir held while installing (see [`crate::fs::FileLock`]).
+const INSTALL_LOCK_FILE: &str = ".embuild-install.lock";
+
+/// The esp-idf submodules only needed for specific chip targets, with the targets
+/// needing them.
+///
+/// See [`excluded_submodules`].
+pub const TARGET_SPECIFIC_SUBMODULES: &[(&str, &[&str])] = &[
+    ("components/bt/controller/lib_esp32", &["esp32"]),
+    ("components/bt/controller/lib_esp32c2", &["esp32c2"]),
+    (
+        "components/bt/controller/lib_esp32c3_family",
+        &["esp32c3", "esp32s3"],
+    ),
+    ("components/bt/controller/lib_esp32c5", &["esp32c5"]),
+    (
+        "components/bt/controller/lib_esp32c6",
+        &["esp32c6", "esp32c61"],
+    ),
+    ("components/bt/controller/lib_esp32h2", &["esp32h2"]),
+];
+
 /// Default filename for the file that contains [`EspIdfBuildInfo`].
 pub const BUILD_INFO_FILENAME: &str = "esp-idf-build.json";
 
@@ -55,7 +119,15 @@ pub struct Tools {
     /// This file is passed to the `idf_tools.py` python script.
     pub index: Option<PathBuf>,
     /// All names of the tools that should be installed.
+    ///
+    /// If empty and [`Tools::targets`] is not, all tools required for these targets
+    /// are installed.
     pub tools: Vec<String>,
+    /// The chip targets (e.g. `esp32`, `esp32c3`) the tools are needed for.
+    ///
+    /// Tools whose `supported_targets` in the tools index do not include any of these
+    /// targets are not installed. If empty, no tools are filtered out.
+    pub targets: Vec<String>,
     _tempfile: Option<Arc<tempfile::TempPath>>,
 }
 
@@ -65,10 +137,19 @@ impl Tools {
         Tools {
             index: None,
             tools: tools.into_iter().map(|s| s.as_ref().to_owned()).collect(),
+            targets: Vec::new(),
             _tempfile: None,
         }
     }
 
+    /// Create a tools descriptor for all tools of the default tools index that are
+    /// required for the chip targets `targets`.
+    ///
+    /// Required tools are the ones marked with `"install": "always"` in the tools index.
+    pub fn for_targets(targets: impl IntoIterator<Item = impl AsRef<str>>) -> Tools {
+        Self::new(std::iter::empty::<&str>()).targets(targets)
+    }
+
     /// Create a tools descriptor for tool names `tools` with the path to the tools index
     /// `tools_json`.
     pub fn new_with_index(
@@ -78,6 +159,7 @@ impl Tools {
         Tools {
             index: Some(tools_json.as_ref().into()),
             tools: iter.into_iter().map(|s| s.as_ref().to_owned()).collect(),
+            targets: Vec::new(),
             _tempfile: None,
         }
     }
@@ -96,6 +178,7 @@ impl Tools {
         Ok(Tools {
             index: Some(temp.to_path_buf()),
             tools,
+            targets: Vec::new(),
             _tempfile: Some(Arc::new(temp)),
         })
     }
@@ -107,10 +190,92 @@ impl Tools {
             include_str!("espidf/resources/cmake.json"),
         )
     }
+
+    /// Restrict the tools to the ones supporting any of the chip targets `targets`.
+    #[must_use]
+    pub fn targets(mut self, targets: impl IntoIterator<Item = impl AsRef<str>>) -> Self {
+        self.targets = targets.into_iter().map(|s| s.as_ref().to_owned()).collect();
+        self
+    }
+
+    /// Select the tools of `tools_info` described by this instance.
+    fn select<'a>(&self, tools_info: &'a ToolsInfo) -> Result<Vec<&'a ToolInfo>> {
+        let platform = host_platform();
+        let supports_targets = |tool_info: &ToolInfo| {
+            if self.targets.is_empty() {
+                return true;
+            }
+
+            let supported_targets = platform
+                .and_then(|p| {
+                    tool_info
+                        .platform_overrides
+                        .iter()
+                        .filter(|info| info.platforms.contains(&p))
+                        .find_map(|info| info.supported_targets.as_ref())
+                })
+                .or(tool_info.supported_targets.as_ref());
+
+            match supported_targets {
+                None => true,
+                Some(supported) => supported
+                    .iter()
+                    .any(|t| t == "all" || self.targets.contains(t)),
+            }
+        };
+
+        if self.tools.is_empty() {
+            let required = |tool_info: &ToolInfo| {
+                let install = platform
+                    .and_then(|p| {
+                        tool_info
+                            .platform_overrides
+                            .iter()
+                            .filter(|info| info.platforms.contains(&p))
+                            .find_map(|info| info.install)
+                    })
+                    .unwrap_or(tool_info.install);
+                install == tools_schema::InstallRequirementInfo::Always
+            };
+
+            return Ok(if self.targets.is_empty() {
+                Vec::new()
+            } else {
+                tools_info
+                    .tools
+                    .iter()
+                    .filter(|tool_info| tool_info.name.is_some())
+                    .filter(|tool_info| required(tool_info) && supports_targets(tool_info))
+                    .collect()
+            });
+        }
+
+        self.tools
+            .iter()
+            .map(|name| {
+                tools_info
+                    .tools
+                    .iter()
+                    .find(|tool_info| tool_info.name.as_deref() == Some(name.as_str()))
+                    .ok_or_else(|| anyhow!("tool '{name}' not found in the tools index"))
+            })
+            .filter(|tool_info| match tool_info {
+                Ok(tool_info) if !supports_targets(tool_info) => {
+                    log::debug!(
+                        "Skipping tool '{}' as it does not support any of the targets {:?}",
+                        tool_info.name.as_deref().unwrap_or_default(),
+                        self.targets
+                    );
+                    false
+                }
+                _ => true,
+            })
+            .collect()
+    }
 }
 
 /// A tool instance describing its properties.
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default)]
 struct Tool {
     name: String,
     /// url to obtain the Tool as an compressed binary
@@ -131,6 +296,8 @@ struct Tool {
     version_cmd_args: Vec<String>,
     /// regex to extract the version returned by the version_cmd
     version_regex: String,
+    /// Whether the version is not the match of [`Tool::version_regex`] but a replacement
+    version_regex_replace: bool,
 }
 
 impl Tool {
@@ -147,12 +314,16 @@ impl Tool {
             self.test_command(),
         );
 
-        let output = self.test_command().output().unwrap_or_else(|e| {
-            panic!(
-                "Failed to run command: {:?}; error: {e:?}",
-                self.test_command()
-            )
-        });
+        let output = match self.test_command().output() {
+            Ok(output) => output,
+            Err(e) => {
+                log::debug!(
+                    "Failed to run command: {:?}; error: {e:?}",
+                    self.test_command()
+                );
+                return false;
+            }
+        };
 
         let regex = regex::Regex::new(&self.version_regex).expect("Invalid regex pattern provided");
 
@@ -166,6 +337,11 @@ impl Tool {
         false
     }
 
+    /// The file name of the tool's archive as downloaded from [`Tool::url`].
+    fn archive_name(&self) -> &str {
+        self.url.rsplit('/').next().unwrap_or_default()
+    }
+
     /// get the absolute PATH
     fn abs_export_path(&self) -> PathBuf {
         self.install_dir.join(self.export_path.as_path())
@@ -185,35 +361,76 @@ impl Tool {
     }
 }
 
+/// An esp-idf tool installed by the [`Installer`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InstalledTool {
+    /// The name of the tool as in the tools index.
+    pub name: String,
+    /// The installed version of the tool.
+    pub version: String,
+    /// The absolute directory of the tool's executables that should be added to `PATH`.
+    pub export_path: PathBuf,
+}
+
+impl From<&Tool> for InstalledTool {
+    fn from(tool: &Tool) -> Self {
+        Self {
+            name: tool.name.clone(),
+            version: tool.version.clone(),
+            export_path: tool.abs_export_path(),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 struct ToolsInfo {
     tools: Vec<ToolInfo>,
     version: u32,
 }
 
-fn parse_tools(
-    tools_wanted: Vec<&str>,
-    tools_json_file: PathBuf,
-    install_dir: PathBuf,
-) -> anyhow::Result<Vec<Tool>> {
-    let mut tools_string = String::new();
-    let mut tools_file = std::fs::File::open(tools_json_file)?;
+impl ToolsInfo {
+    fn from_file(tools_json_file: &Path) -> Result<ToolsInfo> {
+        let mut tools_string = String::new();
+        let mut tools_file = std::fs::File::open(tools_json_file).with_context(|| {
+            anyhow!("could not open tools index '{}'", tools_json_file.display())
+        })?;
 
-    tools_file.read_to_string(&mut tools_string)?;
+        tools_file.read_to_string(&mut tools_string)?;
 
-    let tools_info = serde_json::from_str::<ToolsInfo>(&tools_string)?;
+        Ok(serde_json::from_str::<ToolsInfo>(&tools_string)?)
+    }
+}
 
-    let tools = tools_info.tools;
+/// Map OS and ARCH to platform names in esp-idf.
+///
+/// Unfortunately, the Rust std lib doesn't differentiate between armel
+/// and armhf for 32-bit ARM platforms. This code defaults to armel for
+/// maximum compatibility
+fn host_platform() -> Option<PlatformOverrideInfoPlatformsItem> {
+    match (std::env::consts::OS, std::env::consts::ARCH) {
+        ("linux", "x86") => Some(PlatformOverrideInfoPlatformsItem::LinuxI686),
+        ("linux", "x86_64") => Some(PlatformOverrideInfoPlatformsItem::LinuxAmd64),
+        ("linux", "arm") => Some(PlatformOverrideInfoPlatformsItem::LinuxArmel),
+        ("linux", "aarch64") => Some(PlatformOverrideInfoPlatformsItem::LinuxArm64),
+        ("macos", "x86_64") => Some(PlatformOverrideInfoPlatformsItem::Macos),
+        ("macos", "aarch64") => Some(PlatformOverrideInfoPlatformsItem::MacosArm64),
+        ("windows", "x86") => Some(PlatformOverrideInfoPlatformsItem::Win32),
+        ("windows", "x86_64") => Some(PlatformOverrideInfoPlatformsItem::Win64),
+        _ => None,
+    }
+}
 
-    let tools = tools.iter().filter(|tool_info|{
-        // tools_json schema contract marks name not as required ;(
-        tools_wanted.contains(&tool_info.name.as_ref().unwrap().as_str())
-    }).map(|tool_info| {
+fn parse_tools<'a>(
+    tool_infos: impl IntoIterator<Item = &'a ToolInfo>,
+    install_dir: &Path,
+) -> Vec<Tool> {
+    let tools = tool_infos.into_iter().map(|tool_info| {
         let mut tool = Tool {
             name: tool_info.name.as_ref().unwrap().clone(),
-            install_dir: install_dir.clone(),
+            install_dir: install_dir.to_owned(),
             version_cmd_args: tool_info.version_cmd.to_vec(),
             version_regex: tool_info.version_regex.to_string(),
+            version_regex_replace: tool_info.version_regex_replace.is_some(),
             ..Default::default()
         };
 
@@ -268,25 +485,10 @@ fn parse_tools(
             }
         });
 
-        // Map OS and ARCH to platform names in esp-idf.
-        // Unfortunately, the Rust std lib doesn't differentiate between armel
-        // and armhf for 32-bit ARM platforms. This code defaults to armel for
-        // maximum compatibility
-        let platform = match (std::env::consts::OS, std::env::consts::ARCH) {
-            ("linux", "x86") => Some(PlatformOverrideInfoPlatformsItem::LinuxI686),
-            ("linux", "x86_64") => Some(PlatformOverrideInfoPlatformsItem::LinuxAmd64),
-            ("linux", "arm") => Some(PlatformOverrideInfoPlatformsItem::LinuxArmel),
-            ("linux", "aarch64") => Some(PlatformOverrideInfoPlatformsItem::LinuxArm64),
-            ("macos", "x86_64") => Some(PlatformOverrideInfoPlatformsItem::Macos),
-            ("macos", "aarch64") => Some(PlatformOverrideInfoPlatformsItem::MacosArm64),
-            ("windows", "x86") => Some(PlatformOverrideInfoPlatformsItem::Win32),
-            ("windows", "x86_64") => Some(PlatformOverrideInfoPlatformsItem::Win64),
-            _ => None,
-        };
         // Process any overrides that match the detected platform.
         // If additional fields from `tool_info` are used in the future, their
         // corresponding overrides need to be processed here as well
-        if let Some(p) = platform {
+        if let Some(p) = host_platform() {
             tool_info.platform_overrides
                 .iter()
                 .filter(|info| info.platforms.contains(&p))
@@ -315,7 +517,189 @@ fn parse_tools(
     }
     ).collect();
 
-    Ok(tools)
+    tools
+}
+
+/// A file that must be provided in the offline directory (see [`Installer::offline`]).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OfflineArtifact {
+    /// The expected file name.
+    pub file_name: String,
+    /// The expected sha256 hash of the file.
+    pub sha256: String,
+    /// The expected size of the file in bytes.
+    pub size: i64,
+}
+
+/// The error returned by [`Installer::install`] if artifacts required for an offline
+/// installation are missing (or don't match their expected checksum).
+#[derive(Debug)]
+pub struct MissingOfflineArtifacts {
+    /// The offline directory the artifacts are missing from.
+    pub offline_dir: PathBuf,
+    /// All missing artifacts.
+    pub artifacts: Vec<OfflineArtifact>,
+}
+
+impl std::fmt::Display for MissingOfflineArtifacts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "missing artifacts in offline directory '{}':",
+            self.offline_dir.display()
+        )?;
+        for artifact in &self.artifacts {
+            write!(
+                f,
+                "\n - {} (sha256: {}, size: {})",
+                artifact.file_name, artifact.sha256, artifact.size
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for MissingOfflineArtifacts {}
+
+/// Get the URLs of `url` on all `mirrors`.
+///
+/// The scheme and host of `url` are replaced with the mirror base URL, e.g.
+/// `https://github.com/espressif/x/y.tar.gz` on the mirror
+/// `https://dl.espressif.com/github_assets` is
+/// `https://dl.espressif.com/github_assets/espressif/x/y.tar.gz`.
+fn mirror_urls(url: &str, mirrors: &[String]) -> Vec<String> {
+    let path = url
+        .split_once("://")
+        .map(|(_, rest)| rest)
+        .unwrap_or(url)
+        .split_once('/')
+        .map(|(_, path)| path)
+        .unwrap_or_default();
+
+    mirrors
+        .iter()
+        .map(|mirror| format!("{}/{path}", mirror.trim_end_matches('/')))
+        .collect()
+}
+
+/// How often downloading a tool from a single mirror is tried on network errors.
+const DOWNLOAD_ATTEMPTS: u32 = 3;
+
+/// The wait before the first retry of a failed download.
+const DOWNLOAD_BACKOFF: Duration = Duration::from_secs(1);
+
+/// How many tools are downloaded in parallel.
+const DOWNLOAD_CONCURRENCY: usize = 4;
+
+/// Download the archives of `tools` into the `dist` directory of `install_dir`, trying
+/// the URLs on all `mirrors` in order and finally the original URL.
+///
+/// Every download is verified against the sha256 hash of the tools index.
+fn download_from_mirrors<'a>(
+    mirrors: &[String],
+    install_dir: &Path,
+    tools: impl IntoIterator<Item = &'a Tool>,
+    timeout: Duration,
+) -> Result<()> {
+    let dist_dir = install_dir.join("dist");
+    fs::create_dir_all(&dist_dir)
+        .with_context(|| anyhow!("could not create '{}'", dist_dir.display()))?;
+
+    let agent = ureq::AgentBuilder::new().timeout(timeout).build();
+
+    let download_tool = |tool: &Tool| -> Result<()> {
+        let archive = dist_dir.join(tool.archive_name());
+        if archive.is_file() && sha256_file(&archive)?.eq_ignore_ascii_case(&tool.sha256) {
+            return Ok(());
+        }
+
+        let mut failures = Vec::new();
+        for url in mirror_urls(&tool.url, mirrors)
+            .into_iter()
+            .chain(std::iter::once(tool.url.clone()))
+        {
+            log::debug!("Downloading '{url}'");
+
+            let download = Download::new(&url)
+                .sha256(&tool.sha256)
+                .agent(agent.clone());
+
+            let result = cmd::retrying(
+                DOWNLOAD_ATTEMPTS,
+                DOWNLOAD_BACKOFF,
+                |e: &anyhow::Error| cmd::is_network_error(&format!("{e:#}")),
+                |_| download.to_file(&archive),
+            );
+            match result {
+                Ok(()) => break,
+                Err(e) => failures.push(format!("{url}: {e:#}")),
+            }
+        }
+
+        if !archive.is_file() {
+            return Err(anyhow!(
+                "could not download tool '{}' from any mirror:\n - {}",
+                tool.name,
+                failures.join("\n - ")
+            ));
+        }
+
+        Ok(())
+    };
+
+    cmd::map_parallel(
+        tools.into_iter().collect(),
+        DOWNLOAD_CONCURRENCY,
+        |tool| tool.name.clone(),
+        download_tool,
+    )?;
+
+    Ok(())
+}
+
+/// Copy the archives of `tools` from `offline_dir` into the `dist` directory of
+/// `install_dir`, where `idf_tools.py` picks them up instead of downloading them.
+fn stage_offline_archives<'a>(
+    offline_dir: &Path,
+    install_dir: &Path,
+    tools: impl IntoIterator<Item = &'a Tool>,
+) -> Result<()> {
+    let dist_dir = install_dir.join("dist");
+    fs::create_dir_all(&dist_dir)
+        .with_context(|| anyhow!("could not create '{}'", dist_dir.display()))?;
+
+    let mut missing = Vec::new();
+    for tool in tools {
+        let archive = offline_dir.join(tool.archive_name());
+        if archive.is_file() {
+            let sha256 = sha256_file(&archive)?;
+            if sha256.eq_ignore_ascii_case(&tool.sha256) {
+                crate::fs::copy_file_if_different(&archive, &dist_dir)?;
+                continue;
+            }
+            warning!(
+                "Checksum mismatch of '{}': expected sha256 {}, got {sha256}",
+                archive.display(),
+                tool.sha256
+            );
+        }
+
+        missing.push(OfflineArtifact {
+            file_name: tool.archive_name().to_owned(),
+            sha256: tool.sha256.clone(),
+            size: tool.size,
+        });
+    }
+
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(MissingOfflineArtifacts {
+            offline_dir: offline_dir.to_owned(),
+            artifacts: missing,
+        }
+        .into())
+    }
 }
 
 /// The error returned by [`EspIdf::try_from_env`].
@@ -344,6 +728,13 @@ pub struct EspIdf {
     pub exported_path: OsString,
     /// The path to the python executable to be used by the esp-idf.
     pub venv_python: PathBuf,
+    /// The directory the tools (and python environment) are installed in.
+    pub install_dir: PathBuf,
+    /// All tools installed by the [`Installer`], in the order their export paths appear
+    /// in [`EspIdf::exported_path`].
+    ///
+    /// Empty if the esp-idf environment was not set up by the [`Installer`].
+    pub tools: Vec<InstalledTool>,
     /// The version of the esp-idf or [`Err`] if it could not be detected.
     pub version: Result<EspIdfVersion>,
     /// Whether [`EspIdf::repository`] is installed and managed by [`Installer`] and
@@ -371,7 +762,9 @@ impl EspIdf {
         // get idf.py from $PATH
         // Special case for windows (see issue https://github.com/harryfei/which-rs/issues/56)
         let idf_py = if cfg!(windows) {
-            env::split_paths(&path_var)
+            path_var
+                .split_paths_ext()
+                .into_iter()
                 .find_map(|p| {
                     let file_path = Path::new(&p).join("idf.py");
                     if file_path.is_file() {
@@ -413,22 +806,139 @@ impl EspIdf {
             .with_context(|| anyhow!("failed to check python dependencies"))
             .map_err(not_activated)?;
 
+        let install_dir = env::var_os(IDF_TOOLS_PATH_VAR)
+            .map(PathBuf::from)
+            .unwrap_or_else(Installer::global_install_dir);
+
         Ok(EspIdf {
             version: EspIdfVersion::try_from(&repo),
             repository: repo,
             exported_path: path_var,
             venv_python: python,
+            install_dir,
+            tools: Vec::new(),
             is_managed_espidf: true,
         })
     }
+
+    /// Get the environment of an activated esp-idf, as set up by its `export.sh` script.
+    ///
+    /// This contains `PATH` (see [`EspIdf::exported_path`]), `IDF_PATH`, `IDF_TOOLS_PATH`
+    /// and `IDF_PYTHON_ENV_PATH`.
+    pub fn env(&self) -> Vec<(OsString, OsString)> {
+        vec![
+            ("PATH".into(), self.exported_path.clone()),
+            (
+                IDF_PATH_VAR.into(),
+                self.repository.worktree().as_os_str().to_owned(),
+            ),
+            (
+                IDF_TOOLS_PATH_VAR.into(),
+                self.install_dir.as_os_str().to_owned(),
+            ),
+            (
+                IDF_PYTHON_ENV_PATH_VAR.into(),
+                self.venv_python.pop_times(2).into_os_string(),
+            ),
+        ]
+    }
+
+    /// Create a command running `idf.py` in the environment of this esp-idf (see
+    /// [`EspIdf::env`]).
+    ///
+    /// `idf.py` is run with the python of the esp-idf python environment, so this works
+    /// the same on all platforms (no `idf.py.exe` or `.bat` wrappers are needed on
+    /// windows). Stdio is inherited by default so interactive subcommands like `monitor`
+    /// work.
+    pub fn idf_py(&self) -> cmd::Cmd {
+        let idf_py = path_buf![self.repository.worktree(), "tools", "idf.py"];
+
+        cmd!(&self.venv_python, idf_py; envs_from=(&self.env_set()))
+    }
+
+    /// Get the environment of this esp-idf (see [`EspIdf::env`]) as an [`cmd::EnvSet`]
+    /// applicable to commands, which also removes variables interfering with the esp-idf
+    /// python environment (`PYTHONHOME`, `PYTHONPATH`) and `idf.py` (`MSYSTEM`).
+    pub fn env_set(&self) -> cmd::EnvSet {
+        self.env()
+            .into_iter()
+            .collect::<cmd::EnvSet>()
+            .remove("PYTHONHOME")
+            .remove("PYTHONPATH")
+            .remove("MSYSTEM")
+    }
+
+    /// Run `idf.py` with `args` (see [`EspIdf::idf_py`]) to completion.
+    pub fn run_idf_py(&self, args: impl IntoIterator<Item = impl AsRef<OsStr>>) -> Result<()> {
+        let args = args
+            .into_iter()
+            .map(|arg| arg.as_ref().to_owned())
+            .collect::<Vec<_>>();
+
+        let mut cmd = self.idf_py();
+        cmd.args(&args);
+        cmd.run().with_context(|| {
+            let subcommand = args
+                .iter()
+                .map(|arg| arg.to_string_lossy())
+                .find(|arg| !arg.starts_with('-'))
+                .unwrap_or_default();
+            anyhow!("`idf.py {subcommand}` failed")
+        })
+    }
+}
+
+impl crate::build::ManifestSource for EspIdf {
+    /// The esp-idf (with its version, remote url and commit) and all installed tools.
+    fn manifest_entries(&self) -> Result<Vec<crate::build::ManifestEntry>> {
+        use crate::build::ManifestEntry;
+
+        let mut esp_idf = ManifestEntry::new("esp-idf").path(self.repository.worktree());
+        if let Ok(version) = &self.version {
+            esp_idf = esp_idf.version(version.to_string());
+        }
+        let remotes = self.repository.get_remotes()?;
+        let remote = remotes
+            .iter()
+            .find(|(name, _)| Some(name) == self.repository.origin())
+            .or_else(|| remotes.first());
+        if let Some((_, url)) = remote {
+            esp_idf = esp_idf.source(url);
+        }
+        esp_idf = esp_idf.commit(self.repository.head_commit()?);
+
+        Ok(std::iter::once(esp_idf)
+            .chain(self.tools.iter().map(|tool| {
+                ManifestEntry::new(&tool.name)
+                    .version(&tool.version)
+                    .path(&tool.export_path)
+            }))
+            .collect())
+    }
 }
 
 /// The version of an esp-idf repository.
-#[derive(Clone, Debug)]
+///
+/// Can be parsed from the output of `git describe` or the `IDF_VER` string of the
+/// esp-idf, i.e. `[v]<major>.<minor>[.<patch>][-<pre-release>][-<commits>-g<commit>][-dirty]`,
+/// e.g. `v5.1-rc1-12-g1a2b3c4-dirty`.
+///
+/// Versions are ordered like esp-idf versions: pre-releases (`dev` < `beta` < `rc`) come
+/// before the corresponding release, and commits on top of a version come after it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
 pub struct EspIdfVersion {
     pub major: u64,
     pub minor: u64,
     pub patch: u64,
+    /// The pre-release identifier, e.g. `dev`, `beta1` or `rc2`.
+    pub pre_release: Option<String>,
+    /// The number of commits on top of the version's tag.
+    pub commits: u64,
+    /// The abbreviated hash of the current commit, if there are [`commits`](Self::commits)
+    /// on top of the version's tag.
+    pub commit: Option<String>,
+    /// Whether the worktree has uncommitted changes.
+    pub dirty: bool,
 }
 
 impl EspIdfVersion {
@@ -470,6 +980,7 @@ impl EspIdfVersion {
                 major,
                 minor,
                 patch,
+                ..Default::default()
             })
         } else {
             Err(anyhow!("parsing failed").context(base_err()))
@@ -483,11 +994,178 @@ impl EspIdfVersion {
             Err(_) => "(unknown version)".to_string(),
         }
     }
+
+    /// The sort key of a pre-release identifier.
+    fn pre_release_key(pre_release: &str) -> (u8, u64, &str) {
+        let (rank, rest) = if let Some(rest) = pre_release.strip_prefix("dev") {
+            (0, rest)
+        } else if let Some(rest) = pre_release.strip_prefix("alpha") {
+            (1, rest)
+        } else if let Some(rest) = pre_release.strip_prefix("beta") {
+            (2, rest)
+        } else if let Some(rest) = pre_release.strip_prefix("rc") {
+            (3, rest)
+        } else {
+            (1, pre_release)
+        };
+
+        match rest.trim_start_matches('.').parse::<u64>() {
+            Ok(number) => (rank, number, ""),
+            Err(_) => (rank, 0, rest),
+        }
+    }
 }
 
 impl std::fmt::Display for EspIdfVersion {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        if let Some(pre_release) = &self.pre_release {
+            write!(f, "-{pre_release}")?;
+        }
+        if let Some(commit) = &self.commit {
+            write!(f, "-{}-g{commit}", self.commits)?;
+        }
+        if self.dirty {
+            write!(f, "-dirty")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for EspIdfVersion {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let base_err = || anyhow!("invalid esp-idf version '{s}'");
+
+        let mut version = Self::default();
+
+        let mut rest = s.trim();
+        if let Some(r) = rest.strip_suffix("-dirty") {
+            version.dirty = true;
+            rest = r;
+        }
+
+        // `<commits>-g<commit>` as appended by `git describe`
+        if let Some((r, commit)) = rest.rsplit_once("-g") {
+            if let Some((r, commits)) = r.rsplit_once('-') {
+                if let (Ok(commits), true) = (
+                    commits.parse::<u64>(),
+                    !commit.is_empty() && commit.chars().all(|c| c.is_ascii_hexdigit()),
+                ) {
+                    version.commits = commits;
+                    version.commit = Some(commit.to_owned());
+                    rest = r;
+                }
+            }
+        }
+
+        let (numbers, pre_release) = match rest.split_once('-') {
+            Some((numbers, pre_release)) => (numbers, Some(pre_release)),
+            None => (rest, None),
+        };
+        if let Some(pre_release) = pre_release {
+            if pre_release.is_empty() {
+                return Err(base_err());
+            }
+            version.pre_release = Some(pre_release.to_owned());
+        }
+
+        let numbers = numbers.strip_prefix('v').unwrap_or(numbers);
+        let numbers = numbers
+            .split('.')
+            .map(|n| n.parse::<u64>().map_err(|_| base_err()))
+            .collect::<Result<Vec<_>>>()?;
+        match numbers[..] {
+            [major, minor] => {
+                version.major = major;
+                version.minor = minor;
+            }
+            [major, minor, patch] => {
+                version.major = major;
+                version.minor = minor;
+                version.patch = patch;
+            }
+            _ => return Err(base_err()),
+        }
+
+        Ok(version)
+    }
+}
+
+impl PartialOrd for EspIdfVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for EspIdfVersion {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        use std::cmp::Ordering;
+
+        (self.major, self.minor, self.patch)
+            .cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| match (&self.pre_release, &other.pre_release) {
+                (None, None) => Ordering::Equal,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(_), None) => Ordering::Less,
+                (Some(a), Some(b)) => Self::pre_release_key(a).cmp(&Self::pre_release_key(b)),
+            })
+            .then_with(|| self.commits.cmp(&other.commits))
+            .then_with(|| self.commit.cmp(&other.commit))
+            .then_with(|| self.dirty.cmp(&other.dirty))
+    }
+}
+
+/// The revision of an esp-idf repository as described by `git describe` or `IDF_VER`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum EspIdfRevision {
+    /// A (pre-)release version, possibly with commits on top of it.
+    Version(EspIdfVersion),
+    /// A release branch `release/v<major>.<minor>`.
+    ReleaseBranch { major: u64, minor: u64 },
+    /// A string that is not a version, e.g. a bare commit hash (as described by
+    /// `git describe` if no tag is reachable in a shallow clone).
+    Unknown(String),
+}
+
+impl EspIdfRevision {
+    /// Parse a revision, falling back to [`EspIdfRevision::Unknown`] for unknown formats.
+    pub fn parse(s: &str) -> Self {
+        let s = s.trim();
+        if let Ok(version) = s.parse::<EspIdfVersion>() {
+            return Self::Version(version);
+        }
+
+        s.strip_prefix("release/v")
+            .and_then(|v| v.split_once('.'))
+            .and_then(|(major, minor)| Some((major.parse().ok()?, minor.parse().ok()?)))
+            .map(|(major, minor)| Self::ReleaseBranch { major, minor })
+            .unwrap_or_else(|| Self::Unknown(s.to_owned()))
+    }
+
+    /// Describe the checked out revision of the esp-idf repository `repo` with `git
+    /// describe`.
+    pub fn from_repository(repo: &git::Repository) -> Result<Self> {
+        Ok(Self::parse(&repo.describe()?))
+    }
+
+    /// Get the version if this is a [`EspIdfRevision::Version`].
+    pub fn version(&self) -> Option<&EspIdfVersion> {
+        match self {
+            Self::Version(version) => Some(version),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for EspIdfRevision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Version(version) => write!(f, "v{version}"),
+            Self::ReleaseBranch { major, minor } => write!(f, "release/v{major}.{minor}"),
+            Self::Unknown(s) => write!(f, "{s}"),
+        }
     }
 }
 
@@ -495,9 +1173,9 @@ impl std::fmt::Display for EspIdfVersion {
 ///
 /// Two variations exist:
 /// - Managed
-///     The esp-idf source is installed automatically.
+///   The esp-idf source is installed automatically.
 /// - Custom
-///     A user-provided local clone the esp-idf repository.
+///   A user-provided local clone the esp-idf repository.
 ///
 /// In both cases the [`Installer`] will install all required tools.
 ///
@@ -512,13 +1190,54 @@ pub type EspIdfOrigin = git::sdk::SdkOrigin;
 /// A distinct version of the esp-idf repository to be installed.
 pub type EspIdfRemote = git::sdk::RemoteSdk;
 
+/// Where the [`Installer`] installs the esp-idf source and tools.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum InstallLocation {
+    /// The global install dir `~/.espressif` (see [`GLOBAL_INSTALL_DIR`]), shared by all
+    /// workspaces.
+    #[default]
+    Global,
+    /// The [`WORKSPACE_INSTALL_DIR`] in the cargo target directory of the current
+    /// workspace.
+    ///
+    /// The esp-idf repository and tools already installed in the global install dir are
+    /// reused (without modifying them) instead of being installed again.
+    Workspace,
+    /// A custom directory.
+    Custom(PathBuf),
+}
+
+impl InstallLocation {
+    /// Get the install directory of this location.
+    pub fn dir(&self) -> Result<PathBuf> {
+        match self {
+            Self::Global => Ok(Installer::global_install_dir()),
+            Self::Workspace => {
+                let target_dir = crate::cargo::target_dir()
+                    .ok_or_else(|| anyhow!("could not determine the cargo target directory"))?;
+                Ok(target_dir.join(WORKSPACE_INSTALL_DIR))
+            }
+            Self::Custom(dir) => Ok(dir.clone()),
+        }
+    }
+}
+
 /// Installer for the esp-idf source and tools.
 pub struct Installer {
     esp_idf_origin: EspIdfOrigin,
-    custom_install_dir: Option<PathBuf>,
+    install_location: InstallLocation,
+    clone_options: git::CloneOptions,
     #[allow(clippy::type_complexity)]
     tools_provider:
         Option<Box<dyn FnOnce(&git::Repository, &Result<EspIdfVersion>) -> Result<Vec<Tools>>>>,
+    offline_dir: Option<PathBuf>,
+    deny_network: bool,
+    mirrors: Vec<String>,
+    download_timeout: Duration,
+    reuse_activated_env: bool,
+    output_classifier: cmd::OutputClassifier,
+    verify_tools: bool,
+    tool_version_args: HashMap<String, Vec<String>>,
 }
 
 impl Installer {
@@ -527,7 +1246,16 @@ impl Installer {
         Self {
             esp_idf_origin,
             tools_provider: None,
-            custom_install_dir: None,
+            install_location: InstallLocation::Global,
+            clone_options: git::CloneOptions::new().depth(1),
+            offline_dir: None,
+            deny_network: false,
+            mirrors: Vec::new(),
+            download_timeout: DEFAULT_DOWNLOAD_TIMEOUT,
+            reuse_activated_env: true,
+            output_classifier: cmd::OutputClassifier::default(),
+            verify_tools: true,
+            tool_version_args: HashMap::new(),
         }
     }
 
@@ -546,35 +1274,402 @@ impl Installer {
     /// If [`None`] use the default (see [`GLOBAL_INSTALL_DIR`]).
     #[must_use]
     pub fn install_dir(mut self, install_dir: Option<PathBuf>) -> Self {
-        self.custom_install_dir = install_dir;
+        self.install_location = install_dir
+            .map(InstallLocation::Custom)
+            .unwrap_or(InstallLocation::Global);
         self
     }
 
+    /// Set the options for cloning a [`EspIdfOrigin::Managed`] esp-idf.
+    ///
+    /// Defaults to a shallow clone with depth 1 (including the submodules). Use
+    /// [`excluded_submodules`] to skip submodules not needed for the chip targets built
+    /// for. The [`force_ref`](git::CloneOptions::force_ref) is always set to the ref of
+    /// the managed esp-idf.
+    #[must_use]
+    pub fn clone_options(mut self, clone_options: git::CloneOptions) -> Self {
+        self.clone_options = clone_options;
+        self
+    }
+
+    /// Set the install location to `install_location`.
+    #[must_use]
+    pub fn install_location(mut self, install_location: InstallLocation) -> Self {
+        self.install_location = install_location;
+        self
+    }
+
+    /// Install from the local directory `offline_dir` instead of the network.
+    ///
+    /// If [`None`] the directory given by the [`OFFLINE_DIR_VAR`] environment variable is
+    /// used, if set. The offline directory must contain:
+    /// - `esp-idf`: a checkout of the esp-idf (including its submodules) at the ref of the
+    ///   [`EspIdfOrigin::Managed`] origin, e.g. extracted from an esp-idf release archive;
+    ///   it is used in-place.
+    /// - The archives of all tools to install, named as the last segment of their download
+    ///   URL in the tools index. Their sha256 hashes are verified against the tools index.
+    /// - `wheels`: a directory with all python wheels needed by the esp-idf python
+    ///   environment.
+    /// - Optionally the `espidf.constraints.v<major>.<minor>.txt` python constraints file.
+    ///
+    /// If tool archives are missing, [`install`](Self::install) fails with
+    /// [`MissingOfflineArtifacts`].
+    #[must_use]
+    pub fn offline(mut self, offline_dir: Option<PathBuf>) -> Self {
+        self.offline_dir = offline_dir;
+        self
+    }
+
+    /// Fail any network access of the installation.
+    ///
+    /// This requires an [`offline`](Self::offline) directory, and makes all network
+    /// accesses of the spawned `idf_tools.py` and `pip` processes fail.
+    #[must_use]
+    pub fn deny_network(mut self, deny_network: bool) -> Self {
+        self.deny_network = deny_network;
+        self
+    }
+
+    /// Download the tools from the mirror base URLs `mirrors`, trying them in order before
+    /// the original download URL.
+    ///
+    /// The scheme and host of the download URLs in the tools index are replaced with the
+    /// mirror base URL (like `IDF_GITHUB_ASSETS` of the esp-idf), e.g. a mirror
+    /// `https://dl.espressif.com/github_assets` for tools hosted on github. The downloads
+    /// are verified against the sha256 hashes of the tools index regardless of the
+    /// mirror.
+    ///
+    /// If no mirrors are set, the mirrors in the [`MIRRORS_VAR`] environment variable
+    /// are used.
+    #[must_use]
+    pub fn mirrors(mut self, mirrors: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.mirrors = mirrors.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Set the timeout of a single download attempt from a mirror (see
+    /// [`mirrors`](Self::mirrors)).
+    ///
+    /// Defaults to [`DEFAULT_DOWNLOAD_TIMEOUT`].
+    #[must_use]
+    pub fn download_timeout(mut self, download_timeout: Duration) -> Self {
+        self.download_timeout = download_timeout;
+        self
+    }
+
+    /// The classifier of the `idf_tools.py` output, whose errors and warnings are printed
+    /// as cargo warnings in build scripts.
+    ///
+    /// Defaults to [`OutputClassifier::default`](cmd::OutputClassifier::default).
+    #[must_use]
+    pub fn output_classifier(mut self, classifier: cmd::OutputClassifier) -> Self {
+        self.output_classifier = classifier;
+        self
+    }
+
+    /// Whether an already activated esp-idf environment (see [`EspIdf::try_from_env`])
+    /// should be used instead of installing the esp-idf and its tools.
+    ///
+    /// Enabled by default, unless the [`FORCE_MANAGED_VAR`] environment variable is set.
+    /// See [`install`](Self::install) for the requirements on the activated environment.
+    #[must_use]
+    pub fn reuse_activated_env(mut self, reuse_activated_env: bool) -> Self {
+        self.reuse_activated_env = reuse_activated_env;
+        self
+    }
+
+    /// Whether the installed tools should be verified before they are used.
+    ///
+    /// Each tool is run with the version command of the tools index (or the arguments
+    /// set with [`tool_version_args`](Self::tool_version_args)), and the installation
+    /// fails if it doesn't run or reports another version than the tools index. The
+    /// error names the architecture of the tool if it doesn't match the host's.
+    /// Successfully verified tools are recorded in the [`TOOLS_MANIFEST_FILE`] of the
+    /// install dir and only verified again when their executable changes.
+    ///
+    /// Enabled by default, unless the [`SKIP_TOOL_VERIFICATION_VAR`] environment
+    /// variable is set. Disable it for tools installed for another machine; tools are
+    /// then considered installed if their directory exists.
+    #[must_use]
+    pub fn verify_tools(mut self, verify_tools: bool) -> Self {
+        self.verify_tools = verify_tools;
+        self
+    }
+
+    /// Run the tool named `tool` with `args` instead of the arguments of its version
+    /// command in the tools index when verifying it (see
+    /// [`verify_tools`](Self::verify_tools)).
+    ///
+    /// Tools without arguments in the tools index are run with `--version`.
+    #[must_use]
+    pub fn tool_version_args(
+        mut self,
+        tool: impl Into<String>,
+        args: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.tool_version_args
+            .insert(tool.into(), args.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Check that the activated `esp_idf` matches the esp-idf origin.
+    fn check_activated(&self, esp_idf: &EspIdf) -> Result<()> {
+        let activated = esp_idf.repository.worktree();
+        let hint = || {
+            format!(
+                "deactivate the esp-idf environment (unset `{IDF_PATH_VAR}`) or set \
+                 `{FORCE_MANAGED_VAR}=1` to install the required esp-idf instead"
+            )
+        };
+
+        match &self.esp_idf_origin {
+            EspIdfOrigin::Custom(repository) => {
+                let same = match (
+                    activated.canonicalize(),
+                    repository.worktree().canonicalize(),
+                ) {
+                    (Ok(a), Ok(b)) => a == b,
+                    _ => activated == repository.worktree(),
+                };
+                if !same {
+                    return Err(anyhow!(
+                        "the activated esp-idf '{}' is not the required esp-idf '{}'; {}",
+                        activated.display(),
+                        repository.worktree().display(),
+                        hint()
+                    ));
+                }
+            }
+            EspIdfOrigin::Managed(managed) => {
+                if esp_idf.repository.is_ref(&managed.git_ref) {
+                    return Ok(());
+                }
+
+                let required = match &managed.git_ref {
+                    git::Ref::Tag(s) | git::Ref::Branch(s) => EspIdfRevision::parse(s),
+                    git::Ref::Commit(s) => EspIdfRevision::Unknown(s.clone()),
+                };
+                let matches = match (&required, &esp_idf.version) {
+                    (EspIdfRevision::Version(required), Ok(version)) => {
+                        (required.major, required.minor, required.patch)
+                            == (version.major, version.minor, version.patch)
+                    }
+                    (EspIdfRevision::ReleaseBranch { major, minor }, Ok(version)) => {
+                        (*major, *minor) == (version.major, version.minor)
+                    }
+                    _ => false,
+                };
+
+                if !matches {
+                    return Err(anyhow!(
+                        "the activated esp-idf '{}' ({}) does not match the required esp-idf {}; {}",
+                        activated.display(),
+                        EspIdfVersion::format(&esp_idf.version),
+                        managed.git_ref,
+                        hint()
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resolve all `tools` in the `PATH` of the activated `esp_idf`, checking their
+    /// versions.
+    fn activated_tools(esp_idf: &EspIdf, tools: &[Tools]) -> Result<Vec<InstalledTool>> {
+        let default_tools_json = esp_idf.repository.worktree().join("tools/tools.json");
+
+        let mut installed = Vec::new();
+        let mut errors = Vec::new();
+        for tool_set in tools {
+            let tools_json = tool_set.index.as_deref().unwrap_or(&default_tools_json);
+            let tools_info = ToolsInfo::from_file(tools_json)?;
+
+            for tool_info in tool_set.select(&tools_info)? {
+                let tool = parse_tools([tool_info], &esp_idf.install_dir)
+                    .pop()
+                    .unwrap();
+
+                let Some(program) = tool.version_cmd_args.first() else {
+                    continue;
+                };
+                let Ok(path) = which::which_in(program, Some(&esp_idf.exported_path), "") else {
+                    errors.push(format!("tool '{}' not found in $PATH", tool.name));
+                    continue;
+                };
+
+                let output = cmd!(&path; args=(&tool.version_cmd_args[1..]))
+                    .output(|output| {
+                        let mut out = String::from_utf8_lossy(&output.stdout).into_owned();
+                        out.push_str(&String::from_utf8_lossy(&output.stderr));
+                        out
+                    })
+                    .unwrap_or_default();
+                let version = regex::Regex::new(&tool.version_regex)
+                    .ok()
+                    .and_then(|r| r.captures(&output))
+                    .and_then(|c| c.get(1).or_else(|| c.get(0)))
+                    .map(|m| m.as_str().to_owned());
+
+                match version {
+                    Some(version)
+                        if tool_info.version_regex_replace.is_some() || version == tool.version => {
+                    }
+                    version => {
+                        errors.push(format!(
+                            "tool '{}' ('{}') has version {}, but version {} is required",
+                            tool.name,
+                            path.display(),
+                            version.as_deref().unwrap_or("(unknown)"),
+                            tool.version
+                        ));
+                        continue;
+                    }
+                }
+
+                installed.push(InstalledTool {
+                    name: tool.name.clone(),
+                    version: tool.version.clone(),
+                    export_path: path.parent().map(Path::to_owned).unwrap_or_default(),
+                });
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(installed)
+        } else {
+            Err(anyhow!(
+                "the activated esp-idf environment does not provide the required tools \
+                 (set `{FORCE_MANAGED_VAR}=1` to install them instead):\n - {}",
+                errors.join("\n - ")
+            ))
+        }
+    }
+
+    /// The environment for all subprocesses of the installation.
+    fn network_env(&self) -> Vec<(&'static str, &'static str)> {
+        if self.deny_network {
+            vec![
+                ("HTTP_PROXY", NO_NETWORK_PROXY),
+                ("HTTPS_PROXY", NO_NETWORK_PROXY),
+                ("http_proxy", NO_NETWORK_PROXY),
+                ("https_proxy", NO_NETWORK_PROXY),
+                ("NO_PROXY", ""),
+                ("no_proxy", ""),
+                ("PIP_NO_INDEX", "1"),
+                ("GIT_ALLOW_PROTOCOL", "file"),
+            ]
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Open the esp-idf repository of the offline directory `offline_dir`.
+    fn open_offline_repository(
+        offline_dir: &Path,
+        managed: &EspIdfRemote,
+    ) -> Result<git::Repository> {
+        let repo_dir = offline_dir.join(MANAGED_ESP_IDF_REPOS_DIR_BASE);
+        let repository = git::Repository::open(&repo_dir).with_context(|| {
+            anyhow!(
+                "missing esp-idf checkout '{}' in offline directory",
+                repo_dir.display()
+            )
+        })?;
+
+        if !repository.is_ref(&managed.git_ref) {
+            return Err(anyhow!(
+                "esp-idf checkout '{}' in offline directory is not at {}",
+                repo_dir.display(),
+                managed.git_ref
+            ));
+        }
+
+        Ok(repository)
+    }
+
     /// Install the esp-idf source if a managed ESP-IDF reference was supplied by the user and then install all tools added with [`with_tools`](Self::with_tools).
     ///
     /// The install directory, where the esp-idf source and tools are installed into, is
-    /// determined by
+    /// determined by the [`install_location`](Self::install_location):
     /// 1. The directory given to [`install_dir`](Self::install_dir) if it is [`Some`],
-    /// 2. or the global install directory `~/.espressif` (where `~` stands for the user
+    /// 2. the [`WORKSPACE_INSTALL_DIR`] in the cargo target directory for
+    ///    [`InstallLocation::Workspace`],
+    /// 3. or the global install directory `~/.espressif` (where `~` stands for the user
     ///    home directory) otherwise.
     ///
+    /// The install directory is locked for the whole installation, so concurrent
+    /// installations into the same directory wait for each other.
+    ///
+    /// If an activated esp-idf environment is detected (see
+    /// [`reuse_activated_env`](Self::reuse_activated_env)), it is used instead and nothing
+    /// is installed. The activated esp-idf must be the [`EspIdfOrigin::Custom`] repository,
+    /// or match the version of the [`EspIdfOrigin::Managed`] ref, and all tools must be
+    /// found in `PATH` with their required versions; otherwise an error is returned.
+    ///
     /// Installation will do the following things in order:
     /// 1. If a [`EspIdfOrigin::Managed`] is provided, try to find an installed esp-idf
     ///    matching the specified remote repo. If not found, clone it into `<install
     ///    directory>/esp-idf[-<esp-idf-git-url-hash>]/<esp-idf version string>` where
     ///    `esp-idf version string` is the branch name, tag name, or the hash of the
-    ///    commit, if a specific commit was used. Otherwise if it is a
+    ///    commit, if a specific commit was used. In [`offline`](Self::offline) mode the
+    ///    esp-idf checkout of the offline directory is used instead. Otherwise if it is a
     ///    [`EspIdfOrigin::Custom`] use that esp-idf repository instead.
     /// 2. Create a python virtual env using the system `python` and `idf_tools.py
     ///    install-python-env` in the install directory.
     /// 3. Install all tools with `idf_tools.py --tools-json <tools_json> install
     ///    <tools...>` per [`Tools`] instance added with [`with_tools`](Self::with_tools).
     ///    `tools_json` is the optional [`Tools::index`] path, if [`None`] the `tools.json`
-    ///    of the esp-idf is used.
+    ///    of the esp-idf is used. Only the tools selected by the [`Tools`] instance (see
+    ///    [`Tools::targets`]) which are not already installed are passed to
+    ///    `idf_tools.py`.
     pub fn install(self) -> Result<EspIdf> {
-        let install_dir = self
-            .custom_install_dir
-            .unwrap_or_else(Self::global_install_dir);
+        let step = logging::scoped_step("Installing the esp-idf");
+        let esp_idf = self.try_install().map_err(crate::diag::record_error)?;
+        step.finish();
+        artifacts::record(ArtifactKind::EspIdfDir, esp_idf.repository.worktree())?;
+        Ok(esp_idf)
+    }
+
+    fn try_install(self) -> Result<EspIdf> {
+        if self.reuse_activated_env && env::var_os(FORCE_MANAGED_VAR).is_none() {
+            match EspIdf::try_from_env() {
+                Ok(mut esp_idf) => {
+                    self.check_activated(&esp_idf)?;
+
+                    let tools = self
+                        .tools_provider
+                        .map(|p| p(&esp_idf.repository, &esp_idf.version))
+                        .unwrap_or(Ok(Vec::new()))?;
+                    esp_idf.tools = Self::activated_tools(&esp_idf, &tools)?;
+                    esp_idf.is_managed_espidf = false;
+
+                    log::info!(
+                        "Using the activated esp-idf environment of '{}' ({}) instead of \
+                         installing the esp-idf (set `{FORCE_MANAGED_VAR}=1` to disable)",
+                        esp_idf.repository.worktree().display(),
+                        EspIdfVersion::format(&esp_idf.version)
+                    );
+                    return Ok(esp_idf);
+                }
+                Err(FromEnvError::NoRepo(_)) => (),
+                Err(e) => log::debug!("Not using the esp-idf environment: {e:#}"),
+            }
+        }
+
+        let network_env = self.network_env();
+        let offline_dir = self
+            .offline_dir
+            .or_else(|| env::var_os(OFFLINE_DIR_VAR).map(PathBuf::from));
+
+        if self.deny_network && offline_dir.is_none() {
+            return Err(anyhow!(
+                "network access is denied but no offline directory was given (see `{OFFLINE_DIR_VAR}`)"
+            ));
+        }
+
+        let install_dir = self.install_location.dir()?;
 
         std::fs::create_dir_all(&install_dir).with_context(|| {
             format!(
@@ -583,16 +1678,71 @@ impl Installer {
             )
         })?;
 
-        let (repository, managed_repo) = match self.esp_idf_origin {
-            EspIdfOrigin::Managed(managed) => (
-                managed.open_or_clone(
-                    &install_dir,
-                    git::CloneOptions::new().depth(1),
-                    DEFAULT_ESP_IDF_REPOSITORY,
-                    MANAGED_ESP_IDF_REPOS_DIR_BASE,
-                )?,
-                true,
+        // The lock is released by the OS if an installation is interrupted, so waiting
+        // for it only waits for concurrent installations.
+        let lock_file = install_dir.join(INSTALL_LOCK_FILE);
+        let _lock = match crate::fs::FileLock::try_acquire(&lock_file)? {
+            Some(lock) => lock,
+            None => {
+                log::info!(
+                    "Waiting for another build installing the esp-idf into '{}'",
+                    install_dir.display()
+                );
+                crate::fs::FileLock::acquire(&lock_file)?
+            }
+        };
+
+        // Tools of an existing global installation are reused for workspace installations.
+        let global_install_dir = Some(Self::global_install_dir())
+            .filter(|_| self.install_location == InstallLocation::Workspace)
+            .filter(|dir| dir.exists());
+
+        let mirrors = if self.mirrors.is_empty() {
+            env::var(MIRRORS_VAR)
+                .unwrap_or_default()
+                .split(';')
+                .map(str::trim)
+                .filter(|m| !m.is_empty())
+                .map(str::to_owned)
+                .collect()
+        } else {
+            self.mirrors
+        };
+
+        let esp_idf_origin = match self.esp_idf_origin {
+            EspIdfOrigin::Managed(mut managed) if managed.repo_url.is_none() => {
+                managed.repo_url = env::var(REPO_URL_VAR).ok().filter(|url| !url.is_empty());
+                EspIdfOrigin::Managed(managed)
+            }
+            origin => origin,
+        };
+
+        let (repository, managed_repo) = match esp_idf_origin {
+            EspIdfOrigin::Managed(managed) if offline_dir.is_some() => (
+                Self::open_offline_repository(offline_dir.as_deref().unwrap(), &managed)?,
+                false,
             ),
+            EspIdfOrigin::Managed(managed) => match global_install_dir
+                .as_deref()
+                .and_then(|dir| managed.open(dir, MANAGED_ESP_IDF_REPOS_DIR_BASE))
+            {
+                Some(repository) => {
+                    log::debug!(
+                        "Reusing esp-idf repository '{}' of the global install dir",
+                        repository.worktree().display()
+                    );
+                    (repository, false)
+                }
+                None => (
+                    managed.open_or_clone(
+                        &install_dir,
+                        self.clone_options,
+                        DEFAULT_ESP_IDF_REPOSITORY,
+                        MANAGED_ESP_IDF_REPOS_DIR_BASE,
+                    )?,
+                    true,
+                ),
+            },
             EspIdfOrigin::Custom(repository) => (repository, false),
         };
 
@@ -602,10 +1752,10 @@ impl Installer {
         // Create python virtualenv or use a previously installed one.
 
         // The systems minimal python version for bootstrepping the virtuelenv
-        // - By "system python" we refer to the current python executable that is provided to this processs that is
-        //   first found in the env PATH
+        // - By "system python" we refer to the first python executable found in the env PATH that is at least
+        //   3.6, unless overridden with `EMBUILD_PYTHON`/`PYTHON` (see `python::find_interpreter`)
         // - This will also be the python version used inside the virtualenv
-        let python_version = python::check_python_at_least(3, 6)?;
+        let python = python::find_interpreter(&python::VersionReq::at_least(3, 6))?;
 
         // Using the idf_tools.py script version that comes with the esp-idf git repository
         let idf_tools_py = path_buf![repository.worktree(), "tools", "idf_tools.py"];
@@ -613,10 +1763,36 @@ impl Installer {
         // TODO: add virtual_env check to skip install-python-env
         // running the command cost 2-3 seconds but always makes sure that everything is installed correctly and is up-to-date
 
+        // in offline mode the wheels (and constraints) are taken from the offline directory
+        let mut python_env_args: Vec<OsString> = Vec::new();
+        if let Some(offline_dir) = &offline_dir {
+            let constraints = format!(
+                "espidf.constraints.v{}.{}.txt",
+                esp_version.major, esp_version.minor
+            );
+            let offline_constraints = offline_dir.join(&constraints);
+            if offline_constraints.is_file() {
+                crate::fs::copy_file_if_different(
+                    &offline_constraints,
+                    install_dir.join(&constraints),
+                )?;
+            } else {
+                python_env_args.push("--no-constraints".into());
+            }
+
+            python_env_args.push("--no-index".into());
+            python_env_args.push("--extra-wheels-dir".into());
+            python_env_args.push(offline_dir.join("wheels").into());
+        }
+
         // assumes that the command can be run repeatedly
         // whenalready installed -> checks for updates and a working state
-        cmd!(PYTHON, &idf_tools_py, "--idf-path", repository.worktree(), "--non-interactive", "install-python-env";
-        env=(IDF_TOOLS_PATH_VAR, &install_dir), env_remove=("MSYSTEM"), env_remove=(IDF_PYTHON_ENV_PATH_VAR)).run()?;
+        let step = logging::scoped_step("Installing the esp-idf python environment");
+        cmd!(&python.path, &idf_tools_py, "--idf-path", repository.worktree(), "--non-interactive", "install-python-env";
+        args=(python_env_args), env=(IDF_TOOLS_PATH_VAR, &install_dir), envs=(network_env.iter().copied()),
+        env_remove=("MSYSTEM"), env_remove=(IDF_PYTHON_ENV_PATH_VAR), output_classifier=(self.output_classifier.clone()))
+        .tee(Some("[idf_tools] "))?;
+        step.finish();
 
         // since the above command exited sucessfully -> there should be a virt_env dir
 
@@ -625,7 +1801,7 @@ impl Installer {
 
         // with above knowladge -> construct the python_env_dir implicitly
         let idf_major_minor = format!("{}.{}", esp_version.major, esp_version.minor);
-        let python_major_minor = format!("{}.{}", python_version.major, python_version.minor);
+        let python_major_minor = format!("{}.{}", python.version.major, python.version.minor);
 
         let python_env_dir_template = format!("idf{idf_major_minor}_py{python_major_minor}_env");
 
@@ -649,52 +1825,127 @@ impl Installer {
             .map(|p| p(&repository, &esp_version))
             .unwrap_or(Ok(Vec::new()))?;
 
-        let tools_wanted = tools.clone();
-        let tools_wanted: Vec<&str> = tools_wanted
-            .iter()
-            .flat_map(|tool| tool.tools.iter().map(|s| s.as_str()))
-            .collect();
+        let default_tools_json = repository.worktree().join("tools/tools.json");
 
-        let tools_json = repository.worktree().join("tools/tools.json");
+        let mut tool_sets: Vec<(PathBuf, Vec<Tool>)> = Vec::new();
+        for tool_set in &tools {
+            let tools_json = tool_set
+                .index
+                .clone()
+                .unwrap_or_else(|| default_tools_json.clone());
+            let tools_info = ToolsInfo::from_file(&tools_json)?;
 
-        let tools_vec = parse_tools(
-            tools_wanted.clone(),
-            tools_json.clone(),
-            install_dir.clone(),
-        )
-        .unwrap();
+            let tools_vec = parse_tools(
+                tool_set
+                    .select(&tools_info)?
+                    .into_iter()
+                    .filter(|tool_info| {
+                        !tool_sets
+                            .iter()
+                            .flat_map(|(_, tools)| tools)
+                            .any(|t| Some(&t.name) == tool_info.name.as_ref())
+                    }),
+                &install_dir,
+            );
+
+            tool_sets.push((tools_json, tools_vec));
+        }
 
-        //let tools_vec = parse_into_tools(tools_wanted, tools_json, install_dir.clone())?;
+        let mut verifier = (self.verify_tools && env::var_os(SKIP_TOOL_VERIFICATION_VAR).is_none())
+            .then(|| verify::ToolVerifier::new(&install_dir, self.tool_version_args));
+        let is_installed = |verifier: &Option<verify::ToolVerifier>, tool: &Tool| match verifier {
+            Some(verifier) => verifier.is_verified(tool) || tool.test(),
+            None => tool.abs_export_path().exists(),
+        };
 
-        let all_tools_installed = tools_vec.iter().all(|tool| tool.test());
+        if let Some(global_install_dir) = &global_install_dir {
+            for tool in tool_sets.iter_mut().flat_map(|(_, tools)| tools) {
+                let global_tool = Tool {
+                    install_dir: global_install_dir.clone(),
+                    ..tool.clone()
+                };
+                if !is_installed(&verifier, tool) && is_installed(&verifier, &global_tool) {
+                    log::debug!(
+                        "Reusing tool '{}' of the global install dir '{}'",
+                        tool.name,
+                        global_install_dir.display()
+                    );
+                    *tool = global_tool;
+                }
+            }
+        }
 
-        if !all_tools_installed {
-            for tool_set in tools {
-                let tools_json = tool_set
-                    .index
-                    .as_ref()
-                    .map(|tools_json| {
-                        [OsStr::new("--tools-json"), tools_json.as_os_str()].into_iter()
-                    })
-                    .into_iter()
-                    .flatten();
+        let missing_tools = tool_sets
+            .iter()
+            .map(|(tools_json, tools_vec)| {
+                let missing = tools_vec
+                    .iter()
+                    .filter(|tool| !is_installed(&verifier, tool))
+                    .collect();
+                (tools_json, missing)
+            })
+            .collect::<Vec<(_, Vec<_>)>>();
+
+        if let Some(offline_dir) = &offline_dir {
+            stage_offline_archives(
+                offline_dir,
+                &install_dir,
+                missing_tools
+                    .iter()
+                    .flat_map(|(_, missing)| missing.iter().copied()),
+            )?;
+        } else if !mirrors.is_empty() {
+            download_from_mirrors(
+                &mirrors,
+                &install_dir,
+                missing_tools
+                    .iter()
+                    .flat_map(|(_, missing)| missing.iter().copied()),
+                self.download_timeout,
+            )?;
+        }
 
-                cmd!(&venv_python, &idf_tools_py, "--idf-path", repository.worktree(), @tools_json.clone(), "install"; 
-                     env=(IDF_TOOLS_PATH_VAR, &install_dir), args=(tool_set.tools)).run()?;
+        for (tools_json, missing) in missing_tools {
+            if missing.is_empty() {
+                continue;
             }
 
+            let names = missing.iter().map(|tool| &tool.name).collect::<Vec<_>>();
+            let step = logging::scoped_step(format!("Installing esp-idf tools {names:?}"));
+
+            cmd!(&venv_python, &idf_tools_py, "--idf-path", repository.worktree(), "--tools-json", tools_json, "install";
+                 env=(IDF_TOOLS_PATH_VAR, &install_dir), envs=(network_env.iter().copied()), args=(names),
+                 output_classifier=(self.output_classifier.clone())).tee(Some("[idf_tools] "))?;
+            step.finish();
+
             // Test again if all tools are now installed correctly
-            let all_tools_installed = tools_vec.iter().all(|tool| tool.test());
-            if !all_tools_installed {
-                return Err(anyhow::Error::msg("Could not install all requested Tools"));
+            if let Some(tool) = missing.iter().find(|tool| !tool.abs_export_path().exists()) {
+                return Err(anyhow!(
+                    "Could not install tool '{}' (version {})",
+                    tool.name,
+                    tool.version
+                ));
             }
         }
 
+        let installed_tools: Vec<Tool> =
+            tool_sets.into_iter().flat_map(|(_, tools)| tools).collect();
+
+        if let Some(verifier) = &mut verifier {
+            let step = logging::scoped_step("Verifying the esp-idf tools");
+            let result = installed_tools
+                .iter()
+                .try_for_each(|tool| verifier.verify(tool));
+            verifier.store()?;
+            result?;
+            step.finish();
+        }
+
         // End Tools install
         // Create PATH
 
         // All tools are installed -> infer there PATH variable by using the information out of tools.json
-        let mut tools_path: Vec<PathBuf> = tools_vec
+        let mut tools_path: Vec<PathBuf> = installed_tools
             .iter()
             .map(|tool| tool.abs_export_path())
             .collect();
@@ -707,8 +1958,7 @@ impl Installer {
         let paths = env::join_paths(
             tools_path
                 .into_iter()
-                .map(PathBuf::from)
-                .chain(env::split_paths(&env::var_os("PATH").unwrap_or_default())),
+                .chain(env::var_os("PATH").unwrap_or_default().split_paths_ext()),
         )?;
 
         log::debug!("Using PATH='{}'", &paths.to_string_lossy());
@@ -717,6 +1967,8 @@ impl Installer {
             repository,
             exported_path: paths,
             venv_python,
+            install_dir,
+            tools: installed_tools.iter().map(InstalledTool::from).collect(),
             version: esp_version,
             is_managed_espidf: managed_repo,
         })
@@ -732,11 +1984,29 @@ impl Installer {
     }
 }
 
+/// Get the esp-idf submodules that are not needed when building for the chip targets
+/// `targets` (see [`TARGET_SPECIFIC_SUBMODULES`]).
+///
+/// Pass them to [`git::CloneOptions::exclude_submodules`] for
+/// [`Installer::clone_options`] to skip cloning them.
+pub fn excluded_submodules(targets: impl IntoIterator<Item = impl AsRef<str>>) -> Vec<String> {
+    let targets = targets
+        .into_iter()
+        .map(|t| t.as_ref().to_owned())
+        .collect::<Vec<_>>();
+
+    TARGET_SPECIFIC_SUBMODULES
+        .iter()
+        .filter(|(_, needed_by)| !needed_by.iter().any(|t| targets.iter().any(|x| x == t)))
+        .map(|(path, _)| (*path).to_owned())
+        .collect()
+}
+
 /// Parse a [`git::Ref`] from an esp-idf version string.
 ///
 /// The version string can have the following format:
 /// - `commit:<hash>`: Uses the commit `<hash>` of the `esp-idf` repository. Note that
-///                    this will clone the whole `esp-idf` not just one commit.
+///   this will clone the whole `esp-idf` not just one commit.
 /// - `tag:<tag>`: Uses the tag `<tag>` of the `esp-idf` repository.
 /// - `branch:<branch>`: Uses the branch `<branch>` of the `esp-idf` repository.
 /// - `v<major>.<minor>` or `<major>.<minor>`: Uses the tag `v<major>.<minor>` of the `esp-idf` repository.
@@ -807,6 +2077,7 @@ pub mod sysenv {
     use crate::{
         build::{CInclArgs, CfgArgs, LinkArgs},
         cargo,
+        logging::warning,
     };
 
     const CRATES_LINKS_LIBS: [&str; 3] = ["ESP_IDF_SVC", "ESP_IDF_HAL", "ESP_IDF"];
@@ -821,7 +2092,7 @@ pub mod sysenv {
     pub fn cincl_args() -> Option<CInclArgs> {
         CRATES_LINKS_LIBS
             .iter()
-            .filter_map(|lib| CInclArgs::try_from_env(lib).ok())
+            .filter_map(|lib| CInclArgs::from_dep(lib).ok())
             .next()
     }
 
@@ -839,6 +2110,19 @@ pub mod sysenv {
             .next()
     }
 
+    pub fn tools_install_dir() -> Option<String> {
+        CRATES_LINKS_LIBS
+            .iter()
+            .filter_map(|lib| {
+                env::var(format!(
+                    "DEP_{lib}_{}",
+                    crate::build::ESP_IDF_TOOLS_INSTALL_DIR_VAR
+                ))
+                .ok()
+            })
+            .next()
+    }
+
     pub fn idf_path() -> Option<String> {
         CRATES_LINKS_LIBS
             .iter()
@@ -854,7 +2138,9 @@ pub mod sysenv {
             args.propagate()
         }
         if let Some(args) = cincl_args() {
-            args.propagate()
+            if let Err(e) = args.propagate() {
+                warning!("Could not propagate the C include args: {e:#}");
+            }
         }
         if let Some(args) = link_args() {
             args.propagate()
@@ -865,6 +2151,9 @@ pub mod sysenv {
         if let Some(path) = idf_path() {
             cargo::set_metadata(crate::build::ESP_IDF_PATH_VAR, path)
         }
+        if let Some(path) = tools_install_dir() {
+            cargo::set_metadata(crate::build::ESP_IDF_TOOLS_INSTALL_DIR_VAR, path)
+        }
     }
 
     pub fn output() {
@@ -876,3 +2165,179 @@ pub mod sysenv {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tools_info() -> ToolsInfo {
+        let tool = |name: &str, install: &str, targets: &[&str]| {
+            serde_json::json!({
+                "description": name,
+                "export_paths": [["bin"]],
+                "info_url": "",
+                "install": install,
+                "license": "",
+                "name": name,
+                "supported_targets": targets,
+                "version_cmd": [name, "--version"],
+                "version_regex": "([0-9.]+)",
+                "versions": [],
+            })
+        };
+
+        serde_json::from_value(serde_json::json!({
+            "version": 1,
+            "tools": [
+                tool("xtensa-esp-elf", "always", &["esp32", "esp32s3"]),
+                tool("riscv32-esp-elf", "always", &["esp32c3", "esp32c6"]),
+                tool("cmake", "on_request", &["all"]),
+                tool("esp-rom-elfs", "always", &["all"]),
+            ],
+        }))
+        .unwrap()
+    }
+
+    fn names(tools: Vec<&ToolInfo>) -> Vec<&str> {
+        tools
+            .into_iter()
+            .map(|t| t.name.as_deref().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_select_for_targets() {
+        let info = tools_info();
+
+        let selected = Tools::for_targets(["esp32c3"]).select(&info).unwrap();
+        assert_eq!(names(selected), ["riscv32-esp-elf", "esp-rom-elfs"]);
+
+        let selected = Tools::new(["xtensa-esp-elf", "riscv32-esp-elf", "cmake"])
+            .targets(["esp32"])
+            .select(&info)
+            .unwrap();
+        assert_eq!(names(selected), ["xtensa-esp-elf", "cmake"]);
+
+        assert!(Tools::new(["unknown"]).select(&info).is_err());
+    }
+
+    #[test]
+    fn test_parse_version() {
+        let version: EspIdfVersion = "v5.1-rc1-12-g1a2b3c4-dirty".parse().unwrap();
+        assert_eq!(
+            version,
+            EspIdfVersion {
+                major: 5,
+                minor: 1,
+                patch: 0,
+                pre_release: Some("rc1".into()),
+                commits: 12,
+                commit: Some("1a2b3c4".into()),
+                dirty: true,
+            }
+        );
+        assert_eq!(version.to_string(), "5.1.0-rc1-12-g1a2b3c4-dirty");
+        assert_eq!(
+            version.to_string().parse::<EspIdfVersion>().unwrap(),
+            version
+        );
+
+        assert!("5".parse::<EspIdfVersion>().is_err());
+        assert!("1a2b3c4".parse::<EspIdfVersion>().is_err());
+    }
+
+    #[test]
+    fn test_version_ordering() {
+        let versions = [
+            "v5.0.4",
+            "v5.1-dev-1234-gabcdef0",
+            "v5.1-beta1",
+            "v5.1-rc1",
+            "v5.1-rc2",
+            "v5.1",
+            "v5.1-3-g1a2b3c4",
+            "v5.1.1",
+            "v5.1.10",
+        ]
+        .map(|v| v.parse::<EspIdfVersion>().unwrap());
+
+        assert!(versions.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn test_parse_revision() {
+        for s in ["v5.1.2", "release/v5.1", "1a2b3c4d"] {
+            assert_eq!(EspIdfRevision::parse(s).to_string(), s);
+        }
+        assert_eq!(
+            EspIdfRevision::parse("release/v5.1"),
+            EspIdfRevision::ReleaseBranch { major: 5, minor: 1 }
+        );
+        assert_eq!(
+            EspIdfRevision::parse("1a2b3c4d"),
+            EspIdfRevision::Unknown("1a2b3c4d".into())
+        );
+    }
+
+    #[test]
+    fn test_excluded_submodules() {
+        let excluded = excluded_submodules(["esp32c3"]);
+        assert!(!excluded.contains(&"components/bt/controller/lib_esp32c3_family".to_owned()));
+        assert!(excluded.contains(&"components/bt/controller/lib_esp32".to_owned()));
+        assert!(excluded.contains(&"components/bt/controller/lib_esp32h2".to_owned()));
+    }
+
+    #[test]
+    fn test_mirror_urls() {
+        assert_eq!(
+            mirror_urls(
+                "https://github.com/espressif/crosstool-NG/releases/download/x/gcc.tar.xz",
+                &[
+                    "https://dl.espressif.com/github_assets/".into(),
+                    "https://mirror.example.com".into()
+                ]
+            ),
+            [
+                "https://dl.espressif.com/github_assets/espressif/crosstool-NG/releases/download/x/gcc.tar.xz",
+                "https://mirror.example.com/espressif/crosstool-NG/releases/download/x/gcc.tar.xz",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stage_offline_archives() {
+        let offline_dir = tempfile::tempdir().unwrap();
+        let install_dir = tempfile::tempdir().unwrap();
+
+        fs::write(offline_dir.path().join("present.tar.gz"), b"archive").unwrap();
+        let present = Tool {
+            name: "present".into(),
+            url: "https://dl.espressif.com/present.tar.gz".into(),
+            sha256: sha256_file(offline_dir.path().join("present.tar.gz")).unwrap(),
+            ..Default::default()
+        };
+        let missing = Tool {
+            name: "missing".into(),
+            url: "https://dl.espressif.com/missing.zip".into(),
+            sha256: "abcd".into(),
+            size: 4,
+            ..Default::default()
+        };
+
+        stage_offline_archives(offline_dir.path(), install_dir.path(), [&present]).unwrap();
+        assert!(install_dir.path().join("dist/present.tar.gz").is_file());
+
+        let err =
+            stage_offline_archives(offline_dir.path(), install_dir.path(), [&present, &missing])
+                .unwrap_err();
+        let err = err.downcast_ref::<MissingOfflineArtifacts>().unwrap();
+        assert_eq!(
+            err.artifacts,
+            [OfflineArtifact {
+                file_name: "missing.zip".into(),
+                sha256: "abcd".into(),
+                size: 4,
+            }]
+        );
+    }
+}
@@ -0,0 +1,900 @@
+//! esp-idf partition tables.
+//!
+//! Parses the CSV format of the partition tables of esp-idf projects (usually
+//! `partitions.csv`) into a [`PartitionTable`], validates it like esp-idf's
+//! `gen_esp32part.py`, and writes it back as CSV, as the binary flashed at
+//! [`PARTITION_TABLE_OFFSET`], or as a rust module with the offsets and sizes of the
+//! partitions (see [`PartitionTable::write_rust_constants`]).
+
+use std::fmt::{self, Display, Write as _};
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use bitflags::bitflags;
+
+use crate::cargo;
+use crate::logging::warning;
+
+/// The default offset of the partition table in flash (`CONFIG_PARTITION_TABLE_OFFSET`).
+pub const PARTITION_TABLE_OFFSET: u32 = 0x8000;
+
+/// The size of the flash sector reserved for the partition table.
+pub const PARTITION_TABLE_SIZE: u32 = 0x1000;
+
+/// The maximum length of the binary partition table, including its MD5 entry.
+pub const MAX_PARTITION_TABLE_LENGTH: usize = 0xC00;
+
+/// The maximum length of a partition name in bytes.
+pub const MAX_NAME_LENGTH: usize = 16;
+
+/// The length of an entry of the binary partition table.
+const ENTRY_LENGTH: usize = 32;
+
+/// The magic bytes of a partition entry of the binary partition table.
+const ENTRY_MAGIC: [u8; 2] = [0xAA, 0x50];
+
+/// The magic bytes of the MD5 entry of the binary partition table.
+const MD5_MAGIC: [u8; 2] = [0xEB, 0xEB];
+
+/// The subtypes of [`PartitionType::App`] partitions (besides `ota_0` to `ota_15`).
+pub const APP_SUBTYPES: &[(&str, u8)] = &[("factory", 0x00), ("test", 0x20)];
+
+/// The first subtype of OTA app partitions (`ota_0`).
+pub const APP_OTA_MIN: u8 = 0x10;
+
+/// The number of OTA app subtypes (`ota_0` to `ota_15`).
+pub const APP_OTA_COUNT: u8 = 16;
+
+/// The subtypes of [`PartitionType::Data`] partitions.
+pub const DATA_SUBTYPES: &[(&str, u8)] = &[
+    ("ota", 0x00),
+    ("phy", 0x01),
+    ("nvs", 0x02),
+    ("coredump", 0x03),
+    ("nvs_keys", 0x04),
+    ("efuse", 0x05),
+    ("undefined", 0x06),
+    ("esphttpd", 0x80),
+    ("fat", 0x81),
+    ("spiffs", 0x82),
+    ("littlefs", 0x83),
+];
+
+/// The type of a partition.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PartitionType {
+    App,
+    Data,
+    /// A custom type (usually `0x40` to `0xFE`).
+    Custom(u8),
+}
+
+impl PartitionType {
+    /// Get the type of its binary `value`.
+    pub fn from_value(value: u8) -> Self {
+        match value {
+            0x00 => Self::App,
+            0x01 => Self::Data,
+            value => Self::Custom(value),
+        }
+    }
+
+    /// The binary value of this type.
+    pub fn value(self) -> u8 {
+        match self {
+            Self::App => 0x00,
+            Self::Data => 0x01,
+            Self::Custom(value) => value,
+        }
+    }
+
+    /// The alignment of the offset of partitions of this type.
+    pub fn alignment(self) -> u32 {
+        match self {
+            Self::App => 0x10000,
+            _ => 0x1000,
+        }
+    }
+
+    /// The name and value of the known subtypes of this type.
+    fn subtypes(self) -> Vec<(String, u8)> {
+        let named = match self {
+            Self::App => APP_SUBTYPES,
+            Self::Data => DATA_SUBTYPES,
+            Self::Custom(_) => &[],
+        };
+        let ota = (0..APP_OTA_COUNT)
+            .filter(|_| self == Self::App)
+            .map(|i| (format!("ota_{i}"), APP_OTA_MIN + i));
+
+        named
+            .iter()
+            .map(|(name, value)| (name.to_string(), *value))
+            .chain(ota)
+            .collect()
+    }
+
+    /// The name of the `subtype` of this type, if it is known.
+    pub fn subtype_name(self, subtype: u8) -> Option<String> {
+        self.subtypes()
+            .into_iter()
+            .find(|(_, value)| *value == subtype)
+            .map(|(name, _)| name)
+    }
+}
+
+impl Display for PartitionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::App => f.write_str("app"),
+            Self::Data => f.write_str("data"),
+            Self::Custom(value) => write!(f, "{value:#x}"),
+        }
+    }
+}
+
+bitflags! {
+    /// The flags of a partition.
+    pub struct Flags: u32 {
+        /// The partition is encrypted with flash encryption.
+        const ENCRYPTED = (1 << 0);
+        /// The partition is read-only.
+        const READONLY = (1 << 1);
+    }
+}
+
+impl Flags {
+    /// The names of the flags in the CSV format.
+    const NAMES: &'static [(&'static str, Flags)] = &[
+        ("encrypted", Flags::ENCRYPTED),
+        ("readonly", Flags::READONLY),
+    ];
+}
+
+/// A partition of a [`PartitionTable`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Partition {
+    /// The name (at most [`MAX_NAME_LENGTH`] bytes).
+    pub name: String,
+    pub partition_type: PartitionType,
+    /// The subtype, whose meaning depends on the type (see
+    /// [`PartitionType::subtype_name`]).
+    pub subtype: u8,
+    /// The offset in flash.
+    pub offset: u32,
+    /// The size in bytes.
+    pub size: u32,
+    pub flags: Flags,
+}
+
+impl Partition {
+    /// The name of the subtype, if it is known.
+    pub fn subtype_name(&self) -> Option<String> {
+        self.partition_type.subtype_name(self.subtype)
+    }
+
+    /// The end of the partition (exclusive).
+    pub fn end(&self) -> u64 {
+        u64::from(self.offset) + u64::from(self.size)
+    }
+
+    fn validate(&self) -> Result<()> {
+        ensure!(
+            self.name.len() <= MAX_NAME_LENGTH,
+            "Partition name '{}' is longer than {MAX_NAME_LENGTH} bytes",
+            self.name
+        );
+        let alignment = self.partition_type.alignment();
+        ensure!(
+            self.offset % alignment == 0,
+            "Partition '{}': Offset {:#x} is not aligned to {alignment:#x}",
+            self.name,
+            self.offset
+        );
+        if self.partition_type == PartitionType::App {
+            ensure!(
+                self.size % 0x1000 == 0,
+                "Partition '{}': Size {:#x} is not aligned to {:#x}",
+                self.name,
+                self.size,
+                0x1000
+            );
+        }
+
+        if let Some(partition_type) = parse_type_name(&self.name) {
+            if partition_type != self.partition_type {
+                warning!(
+                    "Partition has name '{}' which is a partition type, but does not match this partition's type ({}). Mistake in partition table?",
+                    self.name,
+                    self.partition_type
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Parse the CSV line `line` (without comment) whose partition follows the one ending
+    /// at `last_end`, or the partition table at `table_offset` if it is the first one.
+    fn from_csv_line(line: &str, last_end: u64, table_offset: Option<u32>) -> Result<Self> {
+        // Missing trailing fields are empty.
+        let mut fields = line.split(',').map(str::trim).chain(std::iter::repeat(""));
+        let mut field = || fields.next().unwrap_or_default();
+        let (name, partition_type, subtype, offset, size, flags) =
+            (field(), field(), field(), field(), field(), field());
+
+        ensure!(!name.is_empty(), "Field 'name' can't be left empty");
+        ensure!(
+            !partition_type.is_empty(),
+            "Field 'type' can't be left empty"
+        );
+        let partition_type = match parse_type_name(partition_type) {
+            Some(partition_type) => partition_type,
+            None => PartitionType::from_value(
+                u8::try_from(parse_int(partition_type)?)
+                    .map_err(|_| anyhow!("Invalid partition type '{partition_type}'"))?,
+            ),
+        };
+
+        let subtype = if subtype.is_empty() {
+            ensure!(
+                partition_type != PartitionType::App,
+                "App partition cannot have an empty subtype"
+            );
+            0x06
+        } else {
+            match partition_type
+                .subtypes()
+                .into_iter()
+                .find(|(name, _)| name == subtype)
+            {
+                Some((_, value)) => value,
+                None => u8::try_from(parse_int(subtype)?)
+                    .map_err(|_| anyhow!("Invalid partition subtype '{subtype}'"))?,
+            }
+        };
+
+        let offset = if offset.is_empty() {
+            let alignment = u64::from(partition_type.alignment());
+            (last_end + alignment - 1) / alignment * alignment
+        } else {
+            let offset = parse_int(offset)?;
+            if offset < last_end {
+                match table_offset {
+                    Some(table_offset) => bail!(
+                        "Partitions overlap. Partition sets offset {offset:#x}. But partition table occupies the whole sector {table_offset:#x}. Use a free offset {last_end:#x} or higher."
+                    ),
+                    None => bail!(
+                        "Partitions overlap. Partition sets offset {offset:#x}. Previous partition ends {last_end:#x}"
+                    ),
+                }
+            }
+            offset
+        };
+
+        ensure!(!size.is_empty(), "Size field can't be empty");
+        let size = parse_int(size)?;
+
+        let mut parsed_flags = Flags::empty();
+        for flag in flags.split(':').map(str::trim).filter(|f| !f.is_empty()) {
+            let (_, value) = Flags::NAMES
+                .iter()
+                .find(|(name, _)| *name == flag)
+                .ok_or_else(|| anyhow!("CSV flag column contains unknown flag '{flag}'"))?;
+            parsed_flags |= *value;
+        }
+
+        Ok(Self {
+            name: name.to_owned(),
+            partition_type,
+            subtype,
+            offset: u32::try_from(offset)
+                .map_err(|_| anyhow!("Offset {offset:#x} is too large"))?,
+            size: u32::try_from(size).map_err(|_| anyhow!("Size {size:#x} is too large"))?,
+            flags: parsed_flags,
+        })
+    }
+
+    fn to_csv_line(&self) -> String {
+        let subtype = self
+            .subtype_name()
+            .unwrap_or_else(|| format!("{:#x}", self.subtype));
+        let flags = Flags::NAMES
+            .iter()
+            .filter(|(_, flag)| self.flags.contains(*flag))
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(":");
+
+        format!(
+            "{},{},{subtype},{:#x},{},{flags}",
+            self.name,
+            self.partition_type,
+            self.offset,
+            format_size(self.size)
+        )
+    }
+
+    fn to_binary(&self) -> [u8; ENTRY_LENGTH] {
+        let mut entry = [0; ENTRY_LENGTH];
+        entry[..2].copy_from_slice(&ENTRY_MAGIC);
+        entry[2] = self.partition_type.value();
+        entry[3] = self.subtype;
+        entry[4..8].copy_from_slice(&self.offset.to_le_bytes());
+        entry[8..12].copy_from_slice(&self.size.to_le_bytes());
+        entry[12..12 + self.name.len()].copy_from_slice(self.name.as_bytes());
+        entry[28..].copy_from_slice(&self.flags.bits().to_le_bytes());
+        entry
+    }
+
+    fn from_binary(entry: &[u8]) -> Result<Self> {
+        let u32_at = |i: usize| u32::from_le_bytes(entry[i..i + 4].try_into().unwrap());
+        let name = &entry[12..28];
+        let name = &name[..name.iter().position(|b| *b == 0).unwrap_or(name.len())];
+
+        Ok(Self {
+            name: String::from_utf8(name.to_vec())
+                .map_err(|_| anyhow!("Invalid partition name {name:?}"))?,
+            partition_type: PartitionType::from_value(entry[2]),
+            subtype: entry[3],
+            offset: u32_at(4),
+            size: u32_at(8),
+            flags: Flags::from_bits_truncate(u32_at(28)),
+        })
+    }
+}
+
+/// A partition table.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PartitionTable {
+    pub partitions: Vec<Partition>,
+}
+
+impl PartitionTable {
+    /// Parse and validate the partition table `csv`, placing partitions without an offset
+    /// after the partition table at the default [`PARTITION_TABLE_OFFSET`].
+    pub fn from_csv(csv: &str) -> Result<Self> {
+        Self::from_csv_with_offset(csv, PARTITION_TABLE_OFFSET)
+    }
+
+    /// Parse and validate the partition table `csv`, placing partitions without an offset
+    /// after the partition table at `table_offset`.
+    pub fn from_csv_with_offset(csv: &str, table_offset: u32) -> Result<Self> {
+        let table_end = u64::from(table_offset) + u64::from(PARTITION_TABLE_SIZE);
+
+        let mut partitions = Vec::<Partition>::new();
+        for (line_no, line) in csv.lines().enumerate().map(|(i, l)| (i + 1, l.trim())) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let partition = match partitions.last() {
+                Some(last) => Partition::from_csv_line(line, last.end(), None),
+                None => Partition::from_csv_line(line, table_end, Some(table_offset)),
+            }
+            .with_context(|| anyhow!("CSV Error at line {line_no}"))?;
+            partitions.push(partition);
+        }
+
+        let table = Self { partitions };
+        table.validate()?;
+        Ok(table)
+    }
+
+    /// Read, parse and validate the partition table CSV file `path` (see
+    /// [`from_csv`](Self::from_csv)).
+    pub fn from_csv_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let csv = fs::read_to_string(path)
+            .with_context(|| anyhow!("Could not read partition table '{}'", path.display()))?;
+        Self::from_csv(&csv)
+            .with_context(|| anyhow!("Invalid partition table '{}'", path.display()))
+    }
+
+    /// Parse and validate the binary partition table `data`, verifying its MD5 checksum
+    /// if present.
+    pub fn from_binary(data: &[u8]) -> Result<Self> {
+        ensure!(
+            data.len() % ENTRY_LENGTH == 0,
+            "Partition table length must be a multiple of {ENTRY_LENGTH} bytes"
+        );
+
+        let mut partitions = Vec::new();
+        for (i, entry) in data.chunks(ENTRY_LENGTH).enumerate() {
+            if entry.iter().all(|b| *b == 0xFF) {
+                break;
+            }
+            match [entry[0], entry[1]] {
+                MD5_MAGIC => {
+                    let computed = md5(&data[..i * ENTRY_LENGTH]);
+                    ensure!(
+                        entry[16..] == computed,
+                        "MD5 checksums don't match! (computed: 0x{}, parsed: 0x{})",
+                        hex(&computed),
+                        hex(&entry[16..])
+                    );
+                }
+                ENTRY_MAGIC => partitions.push(Partition::from_binary(entry)?),
+                magic => bail!("Invalid magic bytes ({magic:02x?}) for partition definition"),
+            }
+        }
+
+        let table = Self { partitions };
+        table.validate()?;
+        Ok(table)
+    }
+
+    /// Validate the partitions like `gen_esp32part.py`: their offsets (and for apps also
+    /// their sizes) must be aligned, names unique, partitions must not overlap, and an
+    /// `otadata` partition must be unique and `0x2000` bytes large.
+    pub fn validate(&self) -> Result<()> {
+        for partition in &self.partitions {
+            partition.validate()?;
+        }
+
+        let mut names = self
+            .partitions
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect::<Vec<_>>();
+        names.sort_unstable();
+        let duplicates = names
+            .windows(2)
+            .filter(|w| w[0] == w[1])
+            .map(|w| w[0])
+            .collect::<Vec<_>>();
+        ensure!(
+            duplicates.is_empty(),
+            "Partition names must be unique (duplicates: {})",
+            duplicates.join(", ")
+        );
+
+        let mut sorted = self.partitions.iter().collect::<Vec<_>>();
+        sorted.sort_by_key(|p| p.offset);
+        for pair in sorted.windows(2) {
+            let (last, partition) = (pair[0], pair[1]);
+            ensure!(
+                u64::from(partition.offset) >= last.end(),
+                "Partition at {:#x} overlaps {:#x}-{:#x}",
+                partition.offset,
+                last.offset,
+                last.end() - 1
+            );
+        }
+
+        let otadata = self
+            .partitions
+            .iter()
+            .filter(|p| p.partition_type == PartitionType::Data && p.subtype == 0x00)
+            .collect::<Vec<_>>();
+        ensure!(otadata.len() <= 1, "Found multiple otadata partitions");
+        if let Some(otadata) = otadata.first() {
+            ensure!(
+                otadata.size == 0x2000,
+                "otadata partition must have size = 0x2000"
+            );
+        }
+
+        let length = (self.partitions.len() + 1) * ENTRY_LENGTH;
+        ensure!(
+            length <= MAX_PARTITION_TABLE_LENGTH,
+            "Partition table length must be no more than {MAX_PARTITION_TABLE_LENGTH:#x} bytes"
+        );
+        Ok(())
+    }
+
+    /// Get the partition `name`.
+    pub fn get(&self, name: &str) -> Option<&Partition> {
+        self.partitions.iter().find(|p| p.name == name)
+    }
+
+    /// Serialize the partition table to CSV.
+    pub fn to_csv(&self) -> String {
+        let mut csv =
+            "# ESP-IDF Partition Table\n# Name, Type, SubType, Offset, Size, Flags\n".to_owned();
+        for partition in &self.partitions {
+            csv.push_str(&partition.to_csv_line());
+            csv.push('\n');
+        }
+        csv
+    }
+
+    /// Serialize the partition table to the binary format flashed at the partition table
+    /// offset, with an MD5 checksum and padded to [`MAX_PARTITION_TABLE_LENGTH`].
+    pub fn to_binary(&self) -> Result<Vec<u8>> {
+        self.validate()?;
+
+        let mut data = self
+            .partitions
+            .iter()
+            .flat_map(Partition::to_binary)
+            .collect::<Vec<_>>();
+        let checksum = md5(&data);
+        data.extend(MD5_MAGIC);
+        data.extend([0xFF; 14]);
+        data.extend(checksum);
+        data.resize(MAX_PARTITION_TABLE_LENGTH, 0xFF);
+        Ok(data)
+    }
+
+    /// Generate a rust module with the `<NAME>_OFFSET` and `<NAME>_SIZE` constants of all
+    /// partitions, where `<NAME>` is the uppercase partition name with all characters
+    /// that are not alphanumeric replaced by `_`.
+    pub fn to_rust_constants(&self) -> Result<String> {
+        let mut code = "// Generated by embuild from the partition table.\n".to_owned();
+        let mut idents = Vec::new();
+        for partition in &self.partitions {
+            let mut ident = partition
+                .name
+                .chars()
+                .map(|c| match c.is_ascii_alphanumeric() {
+                    true => c.to_ascii_uppercase(),
+                    false => '_',
+                })
+                .collect::<String>();
+            if ident.starts_with(|c: char| c.is_ascii_digit()) {
+                ident.insert(0, '_');
+            }
+            if idents.contains(&ident) {
+                bail!(
+                    "The names of the partitions '{}' and '{}' result in the same constant names",
+                    self.partitions[idents.iter().position(|i| *i == ident).unwrap()].name,
+                    partition.name
+                );
+            }
+
+            let kind = match partition.subtype_name() {
+                Some(subtype) => format!("{}, {subtype}", partition.partition_type),
+                None => format!("{}, {:#x}", partition.partition_type, partition.subtype),
+            };
+            writeln!(
+                code,
+                "\n/// The offset of the partition `{}` ({kind}).\npub const {ident}_OFFSET: u32 = {:#x};",
+                partition.name, partition.offset
+            )?;
+            writeln!(
+                code,
+                "/// The size of the partition `{}` ({kind}).\npub const {ident}_SIZE: u32 = {:#x};",
+                partition.name, partition.size
+            )?;
+            idents.push(ident);
+        }
+        Ok(code)
+    }
+
+    /// Write the [`to_rust_constants`](Self::to_rust_constants) module to `file` and format
+    /// it like the bindings (see [`cargo::cargo_fmt_file`]).
+    pub fn write_rust_constants(&self, file: impl AsRef<Path>) -> Result<()> {
+        let file = file.as_ref();
+        fs::write(file, self.to_rust_constants()?)
+            .with_context(|| anyhow!("Could not write '{}'", file.display()))?;
+        cargo::cargo_fmt_file(file);
+        Ok(())
+    }
+}
+
+/// Parse the type name `name`.
+fn parse_type_name(name: &str) -> Option<PartitionType> {
+    match name {
+        "app" => Some(PartitionType::App),
+        "data" => Some(PartitionType::Data),
+        _ => None,
+    }
+}
+
+/// Parse the integer `value` like `gen_esp32part.py`: decimal, or hexadecimal, octal or
+/// binary with a `0x`, `0o` or `0b` prefix, optionally followed by a `K` or `M` suffix.
+fn parse_int(value: &str) -> Result<u64> {
+    let invalid = || anyhow!("Invalid field value '{value}'");
+
+    let lower = value.to_ascii_lowercase();
+    let (number, multiplier) = match lower.strip_suffix('k') {
+        Some(number) => (number, 1024),
+        None => match lower.strip_suffix('m') {
+            Some(number) => (number, 1024 * 1024),
+            None => (lower.as_str(), 1),
+        },
+    };
+
+    let number = number.trim();
+    let parsed = if let Some(hex) = number.strip_prefix("0x") {
+        u64::from_str_radix(hex, 16)
+    } else if let Some(oct) = number.strip_prefix("0o") {
+        u64::from_str_radix(oct, 8)
+    } else if let Some(bin) = number.strip_prefix("0b") {
+        u64::from_str_radix(bin, 2)
+    } else {
+        number.parse()
+    };
+    parsed
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(invalid)
+}
+
+/// Format `size` with an `M` or `K` suffix if possible, like `gen_esp32part.py`.
+fn format_size(size: u32) -> String {
+    match size {
+        size if size != 0 && size % 0x100000 == 0 => format!("{}M", size / 0x100000),
+        size if size != 0 && size % 0x400 == 0 => format!("{}K", size / 0x400),
+        size => format!("{size:#x}"),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// The MD5 digest of `data` (RFC 1321).
+fn md5(data: &[u8]) -> [u8; 16] {
+    const SHIFTS: [u32; 64] = [
+        7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 5, 9, 14, 20, 5, 9, 14, 20, 5,
+        9, 14, 20, 5, 9, 14, 20, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 6, 10,
+        15, 21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+    ];
+    let constants: Vec<u32> = (0..64)
+        .map(|i| ((i as f64 + 1.0).sin().abs() * 4294967296.0) as u32)
+        .collect();
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend(((data.len() as u64).wrapping_mul(8)).to_le_bytes());
+
+    let mut state: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+    for block in message.chunks(64) {
+        let words = block
+            .chunks(4)
+            .map(|w| u32::from_le_bytes(w.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        let [mut a, mut b, mut c, mut d] = state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let f = f
+                .wrapping_add(a)
+                .wrapping_add(constants[i])
+                .wrapping_add(words[g]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(f.rotate_left(SHIFTS[i]));
+        }
+        for (s, v) in state.iter_mut().zip([a, b, c, d]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+
+    let mut digest = [0; 16];
+    for (chunk, s) in digest.chunks_mut(4).zip(state) {
+        chunk.copy_from_slice(&s.to_le_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `partitions_singleapp.csv` of the esp-idf.
+    const SINGLE_APP: &str = "\
+# Name,   Type, SubType, Offset,  Size, Flags
+# Note: if you have increased the bootloader size, make sure to update the offsets to avoid overlap
+nvs,      data, nvs,     ,        0x6000,
+phy_init, data, phy,     ,        0x1000,
+factory,  app,  factory, ,        1M,
+";
+
+    /// `partitions_two_ota.csv` of the esp-idf.
+    const TWO_OTA: &str = "\
+# Name,   Type, SubType, Offset,   Size, Flags
+# Note: if you have increased the bootloader size, make sure to update the offsets to avoid overlap
+nvs,      data, nvs,     ,        0x4000,
+otadata,  data, ota,     ,        0x2000,
+phy_init, data, phy,     ,        0x1000,
+factory,  app,  factory, ,        1M,
+ota_0,    app,  ota_0,   ,        1M,
+ota_1,    app,  ota_1,   ,        1M,
+";
+
+    /// `partitions_singleapp_coredump.csv` of the esp-idf.
+    const SINGLE_APP_COREDUMP: &str = "\
+# Name,   Type, SubType,  Offset,  Size, Flags
+# Note: if you have increased the bootloader size, make sure to update the offsets to avoid overlap
+nvs,      data, nvs,      ,        0x6000,
+phy_init, data, phy,      ,        0x1000,
+factory,  app,  factory,  ,        1M,
+coredump, data, coredump, ,        64K,
+";
+
+    fn layout(table: &PartitionTable) -> Vec<(&str, u32, u32)> {
+        table
+            .partitions
+            .iter()
+            .map(|p| (p.name.as_str(), p.offset, p.size))
+            .collect()
+    }
+
+    #[test]
+    fn test_stock_tables() {
+        let table = PartitionTable::from_csv(SINGLE_APP).unwrap();
+        assert_eq!(
+            layout(&table),
+            [
+                ("nvs", 0x9000, 0x6000),
+                ("phy_init", 0xf000, 0x1000),
+                ("factory", 0x10000, 0x100000)
+            ]
+        );
+
+        let table = PartitionTable::from_csv(TWO_OTA).unwrap();
+        assert_eq!(
+            layout(&table),
+            [
+                ("nvs", 0x9000, 0x4000),
+                ("otadata", 0xd000, 0x2000),
+                ("phy_init", 0xf000, 0x1000),
+                ("factory", 0x10000, 0x100000),
+                ("ota_0", 0x110000, 0x100000),
+                ("ota_1", 0x210000, 0x100000)
+            ]
+        );
+        let ota_1 = table.get("ota_1").unwrap();
+        assert_eq!(ota_1.partition_type, PartitionType::App);
+        assert_eq!(ota_1.subtype, 0x11);
+        assert_eq!(ota_1.subtype_name().as_deref(), Some("ota_1"));
+
+        for csv in [SINGLE_APP, TWO_OTA, SINGLE_APP_COREDUMP] {
+            let table = PartitionTable::from_csv(csv).unwrap();
+            assert_eq!(PartitionTable::from_csv(&table.to_csv()).unwrap(), table);
+
+            let binary = table.to_binary().unwrap();
+            assert_eq!(binary.len(), MAX_PARTITION_TABLE_LENGTH);
+            assert_eq!(PartitionTable::from_binary(&binary).unwrap(), table);
+        }
+    }
+
+    #[test]
+    fn test_csv_format() {
+        let table = PartitionTable::from_csv(
+            "nvs, data, nvs, 0x9000, 24K\n\
+             keys, data, nvs_keys, , 4K, encrypted\n\
+             factory, app, factory, 0x10000, 0x1F0000\n\
+             storage, 0x40, 0x01, , 0x10000, encrypted:readonly\n\
+             blob, 0x41, , 0x300000, 1M\n",
+        )
+        .unwrap();
+        assert_eq!(table.get("keys").unwrap().offset, 0xf000);
+        assert_eq!(table.get("keys").unwrap().flags, Flags::ENCRYPTED);
+        let storage = table.get("storage").unwrap();
+        assert_eq!(storage.partition_type, PartitionType::Custom(0x40));
+        assert_eq!(storage.offset, 0x200000);
+        assert_eq!(storage.flags, Flags::ENCRYPTED | Flags::READONLY);
+        assert_eq!(table.get("blob").unwrap().subtype, 0x06);
+
+        assert_eq!(
+            table.to_csv(),
+            "# ESP-IDF Partition Table\n\
+             # Name, Type, SubType, Offset, Size, Flags\n\
+             nvs,data,nvs,0x9000,24K,\n\
+             keys,data,nvs_keys,0xf000,4K,encrypted\n\
+             factory,app,factory,0x10000,1984K,\n\
+             storage,0x40,0x1,0x200000,64K,encrypted:readonly\n\
+             blob,0x41,0x6,0x300000,1M,\n"
+        );
+        assert_eq!(PartitionTable::from_csv(&table.to_csv()).unwrap(), table);
+        assert_eq!(
+            PartitionTable::from_binary(&table.to_binary().unwrap()).unwrap(),
+            table
+        );
+
+        let error = |csv: &str| format!("{:#}", PartitionTable::from_csv(csv).unwrap_err());
+        assert_eq!(
+            error("nvs, data, nvs, , 24K\nfactory, app, , , 1M\n"),
+            "CSV Error at line 2: App partition cannot have an empty subtype"
+        );
+        assert_eq!(
+            error("nvs, data, nvs, , 24K, secret\n"),
+            "CSV Error at line 1: CSV flag column contains unknown flag 'secret'"
+        );
+        assert_eq!(
+            error("nvs, data, nvs, 0x8000, 24K\n"),
+            "CSV Error at line 1: Partitions overlap. Partition sets offset 0x8000. But partition table occupies the whole sector 0x8000. Use a free offset 0x9000 or higher."
+        );
+        assert_eq!(
+            error("factory, app, factory, 0x11000, 1M\n"),
+            "Partition 'factory': Offset 0x11000 is not aligned to 0x10000"
+        );
+        assert_eq!(
+            error("nvs, data, nvs, , 0x6000\nnvs, data, nvs, , 0x6000\n"),
+            "Partition names must be unique (duplicates: nvs)"
+        );
+        assert_eq!(
+            error("otadata, data, ota, , 0x1000\n"),
+            "otadata partition must have size = 0x2000"
+        );
+    }
+
+    #[test]
+    fn test_overlapping_table() {
+        // The offsets of the CSV are checked while parsing...
+        let error = PartitionTable::from_csv(
+            "nvs, data, nvs, 0x9000, 0x6000\n\
+             phy_init, data, phy, 0xe000, 0x1000\n",
+        )
+        .unwrap_err();
+        assert_eq!(
+            format!("{error:#}"),
+            "CSV Error at line 2: Partitions overlap. Partition sets offset 0xe000. Previous partition ends 0xf000"
+        );
+
+        // ...and of all tables when validating.
+        let mut table = PartitionTable::from_csv(TWO_OTA).unwrap();
+        table.partitions[4].offset = 0x100000;
+        assert_eq!(
+            table.validate().unwrap_err().to_string(),
+            "Partition at 0x100000 overlaps 0x10000-0x10ffff"
+        );
+        assert!(table.to_binary().is_err());
+    }
+
+    #[test]
+    fn test_binary() {
+        let table = PartitionTable::from_csv(SINGLE_APP).unwrap();
+        let mut binary = table.to_binary().unwrap();
+        assert_eq!(
+            binary[..32],
+            [
+                0xAA, 0x50, 0x01, 0x02, 0x00, 0x90, 0x00, 0x00, 0x00, 0x60, 0x00, 0x00, b'n', b'v',
+                b's', 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0
+            ]
+        );
+        assert_eq!(binary[96..98], MD5_MAGIC);
+        assert_eq!(binary[112..128], md5(&binary[..96]));
+        assert!(binary[128..].iter().all(|b| *b == 0xFF));
+
+        binary[20] = b'x';
+        assert!(PartitionTable::from_binary(&binary)
+            .unwrap_err()
+            .to_string()
+            .starts_with("MD5 checksums don't match!"));
+    }
+
+    #[test]
+    fn test_md5() {
+        assert_eq!(hex(&md5(b"")), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(hex(&md5(b"abc")), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(hex(&md5(&[b'a'; 1000])), "cabe45dcc9ae5b66ba86600cca6b8ba8");
+    }
+
+    #[test]
+    fn test_rust_constants() {
+        let table = PartitionTable::from_csv(
+            "nvs, data, nvs, , 0x6000\n\
+             factory, app, factory, , 1M\n\
+             3d-models, 0x40, 0x01, , 64K\n",
+        )
+        .unwrap();
+        let code = table.to_rust_constants().unwrap();
+        assert!(code.contains(
+            "/// The offset of the partition `nvs` (data, nvs).\npub const NVS_OFFSET: u32 = 0x9000;"
+        ));
+        assert!(code.contains("pub const FACTORY_SIZE: u32 = 0x100000;"));
+        assert!(code.contains(
+            "/// The size of the partition `3d-models` (0x40, 0x1).\npub const _3D_MODELS_SIZE: u32 = 0x10000;"
+        ));
+
+        let table = PartitionTable::from_csv(
+            "my-data, data, fat, , 64K\n\
+             my_data, data, fat, , 64K\n",
+        )
+        .unwrap();
+        assert!(table.to_rust_constants().is_err());
+    }
+}
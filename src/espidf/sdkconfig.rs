@@ -0,0 +1,340 @@
+//! Layered `sdkconfig.defaults` files.
+//!
+//! The esp-idf accepts multiple sdkconfig defaults files (as the `;`-separated
+//! `SDKCONFIG_DEFAULTS` cmake variable), where options of later files override the ones
+//! of earlier files. [`SdkconfigDefaults`] resolves such a list from base files plus their
+//! cargo profile and chip target specific variants, and merges them for backends that
+//! only support a single defaults file.
+//!
+//! [`SdkconfigDefaults::check_drift`] detects options of the defaults that didn't take
+//! effect in the generated `sdkconfig`.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::cargo;
+use crate::kconfig::{self, Change, Parsed};
+use crate::logging::warning;
+
+/// The name of the cmake variable containing the sdkconfig defaults files.
+pub const SDKCONFIG_DEFAULTS_VAR: &str = "SDKCONFIG_DEFAULTS";
+
+/// An ordered list of sdkconfig defaults files, later files overriding earlier ones.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SdkconfigDefaults {
+    files: Vec<PathBuf>,
+}
+
+impl SdkconfigDefaults {
+    /// Create defaults from the ordered list of `files`.
+    pub fn new(files: impl IntoIterator<Item = impl Into<PathBuf>>) -> Self {
+        Self {
+            files: files.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    /// Resolve the defaults from the base files `bases` for the cargo `profile` (e.g.
+    /// `debug` or `release`) and the chip target `mcu` (e.g. `esp32c3`).
+    ///
+    /// For each base file `<base>` the following files are used in this order, if they
+    /// exist:
+    /// 1. `<base>`
+    /// 2. `<base>.<profile>`
+    /// 3. `<base>.<mcu>`
+    /// 4. `<base>.<profile>.<mcu>`
+    pub fn resolve(
+        bases: impl IntoIterator<Item = impl AsRef<Path>>,
+        profile: Option<&str>,
+        mcu: Option<&str>,
+    ) -> Self {
+        let suffixes = [
+            profile.map(str::to_owned),
+            mcu.map(str::to_owned),
+            profile
+                .zip(mcu)
+                .map(|(profile, mcu)| format!("{profile}.{mcu}")),
+        ];
+
+        let files = bases
+            .into_iter()
+            .flat_map(|base| {
+                let base = base.as_ref().to_owned();
+                let variants = suffixes
+                    .iter()
+                    .flatten()
+                    .map(|suffix| {
+                        let mut file = base.clone().into_os_string();
+                        file.push(".");
+                        file.push(suffix);
+                        PathBuf::from(file)
+                    })
+                    .collect::<Vec<_>>();
+
+                std::iter::once(base).chain(variants)
+            })
+            .filter(|file| file.is_file())
+            .collect();
+
+        Self { files }
+    }
+
+    /// Resolve the defaults like [`SdkconfigDefaults::resolve`], using the `PROFILE`
+    /// environment variable of cargo build scripts as the profile.
+    pub fn resolve_for_build(
+        bases: impl IntoIterator<Item = impl AsRef<Path>>,
+        mcu: Option<&str>,
+    ) -> Self {
+        let profile = std::env::var("PROFILE").ok();
+        Self::resolve(bases, profile.as_deref(), mcu)
+    }
+
+    /// Get all defaults files in order.
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// Rerun the build script if any of the defaults files changed.
+    pub fn track(&self) {
+        for file in &self.files {
+            cargo::track_file(file);
+        }
+    }
+
+    /// Get the value of the `SDKCONFIG_DEFAULTS` cmake variable (see
+    /// [`SDKCONFIG_DEFAULTS_VAR`]), i.e. all files separated by `;`.
+    pub fn to_cmake_value(&self) -> Result<String> {
+        let files = self
+            .files
+            .iter()
+            .map(|f| {
+                f.to_str()
+                    .ok_or_else(|| anyhow!("'{}' is not valid utf-8", f.display()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(files.join(";"))
+    }
+
+    /// Merge all options of the defaults files, later files overriding the options of
+    /// earlier files.
+    ///
+    /// Returns the options in the order they first appeared as `(name, value)` pairs,
+    /// where `value` is [`None`] for options explicitly unset with `# CONFIG_<name> is
+    /// not set`.
+    pub fn merge(&self) -> Result<Vec<(String, Option<String>)>> {
+        let mut options: Vec<(String, Option<String>)> = Vec::new();
+        let mut sources: HashMap<String, (usize, &Path)> = HashMap::new();
+
+        for file in &self.files {
+            let content = fs::read_to_string(file)
+                .with_context(|| anyhow!("could not read '{}'", file.display()))?;
+
+            for (name, value) in content.lines().filter_map(parse_line) {
+                if let Some((index, source)) = sources.get(name).copied() {
+                    if options[index].1.as_deref() != value {
+                        log::debug!(
+                            "sdkconfig option '{name}' of '{}' overridden by '{}'",
+                            source.display(),
+                            file.display()
+                        );
+                    }
+                    options[index].1 = value.map(str::to_owned);
+                    sources.insert(name.to_owned(), (index, file));
+                } else {
+                    sources.insert(name.to_owned(), (options.len(), file));
+                    options.push((name.to_owned(), value.map(str::to_owned)));
+                }
+            }
+        }
+
+        Ok(options)
+    }
+
+    /// Merge all defaults files (see [`SdkconfigDefaults::merge`]) into the single file
+    /// `path`.
+    ///
+    /// This is needed for PlatformIO, which only reads the `sdkconfig.defaults` file of
+    /// the project directory. The file is only written if its contents changed (see
+    /// [`write_if_changed`](crate::fs::write_if_changed)).
+    pub fn write_merged(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let content = self
+            .merge()?
+            .into_iter()
+            .map(|(name, value)| match value {
+                Some(value) => format!("{name}={value}\n"),
+                None => format!("# {name} is not set\n"),
+            })
+            .collect::<String>();
+
+        crate::fs::write_if_changed(path, content)?;
+        Ok(())
+    }
+}
+
+impl SdkconfigDefaults {
+    /// Parse and merge all defaults files (later files overriding the options of earlier
+    /// files) into a single kconfig file.
+    pub fn parse(&self) -> Result<Parsed> {
+        let mut merged = Parsed::default();
+        for file in &self.files {
+            for (name, value) in Parsed::from_file(file)?.into_entries() {
+                merged.set(name, value);
+            }
+        }
+        Ok(merged)
+    }
+
+    /// Compare the options of the defaults files with the effective `sdkconfig` file
+    /// generated from them, and get the options whose value didn't take effect
+    /// (usually because their dependencies are not met, or because a stale `sdkconfig`
+    /// was not regenerated).
+    ///
+    /// Every such option is printed as a cargo warning, or if `deny` is `true`, this
+    /// fails listing them.
+    pub fn check_drift(&self, sdkconfig: impl AsRef<Path>, deny: bool) -> Result<Vec<Change>> {
+        let sdkconfig = sdkconfig.as_ref();
+        let drift = kconfig::diff(&self.parse()?, &Parsed::from_file(sdkconfig)?)
+            .into_iter()
+            .filter(|change| match change {
+                Change::Added { .. } => false,
+                // Options with unmet dependencies aren't written, i.e. they are disabled.
+                Change::Removed { value, .. } => value.as_bool() != Some(false),
+                Change::Changed { .. } => true,
+            })
+            .collect::<Vec<_>>();
+        if drift.is_empty() {
+            return Ok(drift);
+        }
+
+        let message = format!(
+            "{} option(s) of the sdkconfig defaults did not take effect in '{}' \
+             (are their dependencies met?):\n{}",
+            drift.len(),
+            sdkconfig.display(),
+            drift
+                .iter()
+                .map(|change| match change {
+                    Change::Removed { name, value } =>
+                        format!("  {name}: requested {value}, but it is not set"),
+                    Change::Changed { name, old, new } =>
+                        format!("  {name}: requested {old}, but it is {new}"),
+                    Change::Added { .. } => unreachable!(),
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+        if deny {
+            bail!(message);
+        }
+        warning!("{message}");
+        Ok(drift)
+    }
+}
+
+/// Parse a `CONFIG_<name>=<value>` or `# CONFIG_<name> is not set` line.
+fn parse_line(line: &str) -> Option<(&str, Option<&str>)> {
+    let line = line.trim();
+    if let Some(comment) = line.strip_prefix('#') {
+        let name = comment.trim().strip_suffix("is not set")?.trim();
+        name.starts_with("CONFIG_").then_some((name, None))
+    } else {
+        let (name, value) = line.split_once('=')?;
+        let name = name.trim();
+        name.starts_with("CONFIG_")
+            .then_some((name, Some(value.trim())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_and_merge() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("sdkconfig.defaults");
+        fs::write(&base, "CONFIG_A=y\nCONFIG_B=1\n# CONFIG_C is not set\n").unwrap();
+        fs::write(
+            dir.path().join("sdkconfig.defaults.release"),
+            "CONFIG_B=2\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("sdkconfig.defaults.esp32c3"),
+            "# CONFIG_A is not set\nCONFIG_D=\"d\"\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("sdkconfig.defaults.esp32"), "CONFIG_B=3\n").unwrap();
+
+        let defaults = SdkconfigDefaults::resolve([&base], Some("release"), Some("esp32c3"));
+        assert_eq!(
+            defaults.files(),
+            [
+                base.clone(),
+                dir.path().join("sdkconfig.defaults.release"),
+                dir.path().join("sdkconfig.defaults.esp32c3"),
+            ]
+        );
+
+        assert_eq!(
+            defaults.merge().unwrap(),
+            [
+                ("CONFIG_A".to_owned(), None),
+                ("CONFIG_B".to_owned(), Some("2".to_owned())),
+                ("CONFIG_C".to_owned(), None),
+                ("CONFIG_D".to_owned(), Some("\"d\"".to_owned())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_drift() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("sdkconfig.defaults");
+        fs::write(
+            &base,
+            "CONFIG_BT_ENABLED=y\nCONFIG_HZ=100\n# CONFIG_LOG is not set\nCONFIG_OFFSET=0x8000\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("sdkconfig.defaults.release"),
+            "CONFIG_HZ=1000\n",
+        )
+        .unwrap();
+        let sdkconfig = dir.path().join("sdkconfig");
+        fs::write(
+            &sdkconfig,
+            "CONFIG_HZ=100\nCONFIG_OFFSET=32768\nCONFIG_OTHER=y\n",
+        )
+        .unwrap();
+
+        let defaults = SdkconfigDefaults::resolve([&base], Some("release"), None);
+        let drift = defaults.check_drift(&sdkconfig, false).unwrap();
+        assert_eq!(
+            drift.iter().map(Change::name).collect::<Vec<_>>(),
+            ["CONFIG_BT_ENABLED", "CONFIG_HZ"]
+        );
+
+        let error = defaults.check_drift(&sdkconfig, true).unwrap_err();
+        let error = error.to_string();
+        assert!(
+            error.contains("CONFIG_BT_ENABLED: requested y, but it is not set"),
+            "{error}"
+        );
+        assert!(
+            error.contains("CONFIG_HZ: requested 1000, but it is 100"),
+            "{error}"
+        );
+
+        fs::write(
+            &sdkconfig,
+            "CONFIG_BT_ENABLED=y\nCONFIG_HZ=1000\nCONFIG_OFFSET=0x8000\n",
+        )
+        .unwrap();
+        assert_eq!(defaults.check_drift(&sdkconfig, true).unwrap(), []);
+    }
+}
@@ -36,7 +36,7 @@ impl Builder {
 
         Ok(Self {
             esp_idf: PathBuf::from(env::var(format!("DEP_{library}_EMBUILD_ESP_IDF_PATH"))?),
-            sys_includes: SystemIncludes::CInclArgs(build::CInclArgs::try_from_env(library)?),
+            sys_includes: SystemIncludes::CInclArgs(build::CInclArgs::from_dep(library)?),
             add_includes: add_includes.into(),
             gcc: None,
             env_path: env::var_os("DEP_ESP_IDF_EMBUILD_ENV_PATH"),
@@ -221,9 +221,9 @@ impl Builder {
                 .cloned()
                 .chain(
                     include_args
-                        .args
-                        .split_ascii_whitespace()
-                        .filter_map(Self::unescape),
+                        .include_dirs
+                        .iter()
+                        .map(|dir| format!("-isystem{}", dir.display())),
                 )
                 .collect::<Vec<_>>(),
             SystemIncludes::MCU(ref mcu) => self
@@ -239,20 +239,6 @@ impl Builder {
         }
     }
 
-    fn unescape(arg: &str) -> Option<String> {
-        let unescaped = if arg.starts_with('\"') && arg.ends_with('\"') {
-            arg[1..arg.len() - 1].replace("\\\"", "\"")
-        } else {
-            arg.to_owned()
-        };
-
-        if unescaped.starts_with("-isystem") || unescaped.starts_with("-I") {
-            Some(unescaped)
-        } else {
-            None
-        }
-    }
-
     fn resuffix(path: &Path, out_dir: &Path, suffix: &str) -> anyhow::Result<PathBuf> {
         let resuffixed = path_buf![
             &out_dir,
@@ -0,0 +1,465 @@
+//! Verification of the installed esp-idf tools.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use super::{Tool, SKIP_TOOL_VERIFICATION_VAR, TOOLS_MANIFEST_FILE};
+use crate::cmd;
+
+/// The version argument used for tools without a version command in the tools index.
+const DEFAULT_VERSION_ARG: &str = "--version";
+
+/// The tools which were verified successfully, stored in the [`TOOLS_MANIFEST_FILE`]
+/// of the install dir.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Manifest {
+    #[serde(default)]
+    verified: BTreeMap<String, VerifiedTool>,
+}
+
+/// A successfully verified tool, which is not verified again as long as its executable
+/// doesn't change.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+struct VerifiedTool {
+    version: String,
+    path: PathBuf,
+    args: Vec<String>,
+    size: u64,
+    modified: u64,
+}
+
+/// Runs the installed tools with their version arguments to check that they work on
+/// this host, caching the results in the install dir.
+pub(super) struct ToolVerifier {
+    manifest_path: PathBuf,
+    manifest: Manifest,
+    changed: bool,
+    version_args: HashMap<String, Vec<String>>,
+}
+
+impl ToolVerifier {
+    /// Create a verifier using the [`TOOLS_MANIFEST_FILE`] in `install_dir`.
+    ///
+    /// `version_args` overrides the version arguments of the tools index by tool name.
+    pub fn new(install_dir: &Path, version_args: HashMap<String, Vec<String>>) -> Self {
+        let manifest_path = install_dir.join(TOOLS_MANIFEST_FILE);
+        let manifest = fs::read_to_string(&manifest_path)
+            .ok()
+            .and_then(|manifest| match serde_json::from_str(&manifest) {
+                Ok(manifest) => Some(manifest),
+                Err(e) => {
+                    log::debug!(
+                        "Ignoring invalid tools manifest '{}': {e}",
+                        manifest_path.display()
+                    );
+                    None
+                }
+            })
+            .unwrap_or_default();
+
+        Self {
+            manifest_path,
+            manifest,
+            changed: false,
+            version_args,
+        }
+    }
+
+    /// The executable and arguments of the version command of `tool`, or [`None`] if the
+    /// tool has no executable.
+    fn version_command(&self, tool: &Tool) -> Option<(PathBuf, Vec<String>)> {
+        let program = tool.version_cmd_args.first().filter(|p| !p.is_empty())?;
+        let export_path = tool.abs_export_path();
+        let path = which::which_in(program, Some(&export_path), &export_path)
+            .unwrap_or_else(|_| export_path.join(program));
+
+        let args = match self.version_args.get(&tool.name) {
+            Some(args) => args.clone(),
+            None if tool.version_cmd_args.len() > 1 => tool.version_cmd_args[1..].to_vec(),
+            None => vec![DEFAULT_VERSION_ARG.to_owned()],
+        };
+        Some((path, args))
+    }
+
+    /// The cache entry of `tool` with version command `path` and `args` in its current
+    /// state, or [`None`] if the executable doesn't exist.
+    fn entry(tool: &Tool, path: &Path, args: &[String]) -> Option<VerifiedTool> {
+        let metadata = fs::metadata(path).ok()?;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default();
+
+        Some(VerifiedTool {
+            version: tool.version.clone(),
+            path: path.to_owned(),
+            args: args.to_vec(),
+            size: metadata.len(),
+            modified,
+        })
+    }
+
+    /// Whether `tool` was verified before and its executable didn't change since.
+    pub fn is_verified(&self, tool: &Tool) -> bool {
+        let Some((path, args)) = self.version_command(tool) else {
+            return false;
+        };
+        Self::entry(tool, &path, &args)
+            .is_some_and(|entry| self.manifest.verified.get(&tool.name) == Some(&entry))
+    }
+
+    /// Verify that `tool` runs on this host and reports the version of the tools index.
+    ///
+    /// Tools verified before whose executable didn't change since are not run again.
+    pub fn verify(&mut self, tool: &Tool) -> Result<()> {
+        let Some((path, args)) = self.version_command(tool) else {
+            return Ok(());
+        };
+        let entry = Self::entry(tool, &path, &args);
+        if entry.is_some() && self.manifest.verified.get(&tool.name) == entry.as_ref() {
+            return Ok(());
+        }
+
+        if self.manifest.verified.remove(&tool.name).is_some() {
+            self.changed = true;
+        }
+        if entry.is_none() {
+            return Err(verification_error(
+                tool,
+                &path,
+                "the executable does not exist".into(),
+            ));
+        }
+
+        log::debug!("Verifying tool '{}' with {path:?} {args:?}", tool.name);
+        let output = cmd!(&path; args=(&args))
+            .into_inner()
+            .output()
+            .map_err(|e| verification_error(tool, &path, format!("it could not be run: {e}")))?;
+
+        let mut out = String::from_utf8_lossy(&output.stdout).into_owned();
+        out.push_str(&String::from_utf8_lossy(&output.stderr));
+        if !output.status.success() {
+            return Err(verification_error(
+                tool,
+                &path,
+                format!(
+                    "running it with {args:?} failed with {}:\n{}",
+                    output.status,
+                    out.trim()
+                ),
+            ));
+        }
+
+        if !tool.version_regex.is_empty() && !tool.version_regex_replace {
+            let version = regex::Regex::new(&tool.version_regex)
+                .ok()
+                .and_then(|r| r.captures(&out))
+                .and_then(|c| c.get(1).or_else(|| c.get(0)))
+                .map(|m| m.as_str().to_owned());
+            if version.as_deref() != Some(&tool.version) {
+                return Err(verification_error(
+                    tool,
+                    &path,
+                    format!(
+                        "it reports version {}, but version {} is required",
+                        version.as_deref().unwrap_or("(unknown)"),
+                        tool.version
+                    ),
+                ));
+            }
+        }
+
+        self.manifest
+            .verified
+            .insert(tool.name.clone(), entry.unwrap());
+        self.changed = true;
+        Ok(())
+    }
+
+    /// Write the verification results to the install dir if they changed.
+    pub fn store(&self) -> Result<()> {
+        if self.changed {
+            crate::fs::write_if_changed(
+                &self.manifest_path,
+                serde_json::to_string_pretty(&self.manifest)?,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn verification_error(tool: &Tool, path: &Path, reason: String) -> anyhow::Error {
+    let mut msg = format!(
+        "The esp-idf tool '{}' (version {}) at '{}' does not work: {reason}",
+        tool.name,
+        tool.version,
+        path.display()
+    );
+    if let Some(mismatch) = BinaryInfo::read(path).and_then(|info| info.host_mismatch()) {
+        msg.push_str(&format!("\n{mismatch}"));
+    }
+    msg.push_str(&format!(
+        "\nRemove '{}' (or the whole install dir '{}') to reinstall it, or set \
+         `{SKIP_TOOL_VERIFICATION_VAR}=1` if the tools are installed for another machine",
+        tool.install_dir
+            .join("tools")
+            .join(&tool.name)
+            .join(&tool.version)
+            .display(),
+        tool.install_dir.display()
+    ));
+    anyhow!(msg)
+}
+
+/// The executable format and architectures of a binary, as read from its header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct BinaryInfo {
+    /// The executable format, e.g. `ELF`.
+    format: &'static str,
+    /// The OS the format is used by, as in [`std::env::consts::OS`].
+    os: &'static str,
+    /// The architectures of the binary, as in [`std::env::consts::ARCH`] if known.
+    archs: Vec<String>,
+}
+
+impl BinaryInfo {
+    /// Read the header of the binary at `path`, or [`None`] if it isn't an ELF, Mach-O or
+    /// PE binary.
+    fn read(path: &Path) -> Option<BinaryInfo> {
+        let mut header = Vec::new();
+        fs::File::open(path)
+            .ok()?
+            .take(4096)
+            .read_to_end(&mut header)
+            .ok()?;
+        Self::parse(&header)
+    }
+
+    fn parse(header: &[u8]) -> Option<BinaryInfo> {
+        let u16_at = |offset: usize, le: bool| -> Option<u16> {
+            let bytes = header.get(offset..offset + 2)?.try_into().ok()?;
+            Some(if le {
+                u16::from_le_bytes(bytes)
+            } else {
+                u16::from_be_bytes(bytes)
+            })
+        };
+        let u32_at = |offset: usize, le: bool| -> Option<u32> {
+            let bytes = header.get(offset..offset + 4)?.try_into().ok()?;
+            Some(if le {
+                u32::from_le_bytes(bytes)
+            } else {
+                u32::from_be_bytes(bytes)
+            })
+        };
+
+        match header.get(..4)? {
+            [0x7f, b'E', b'L', b'F'] => {
+                let is_64 = *header.get(4)? == 2;
+                let machine = u16_at(18, *header.get(5)? == 1)?;
+                let arch = match machine {
+                    0x03 => "x86".into(),
+                    0x08 => "mips".into(),
+                    0x28 => "arm".into(),
+                    0x3e => "x86_64".into(),
+                    0x5e => "xtensa".into(),
+                    0xb7 => "aarch64".into(),
+                    0xf3 if is_64 => "riscv64".into(),
+                    0xf3 => "riscv32".into(),
+                    machine => format!("machine {machine:#x}"),
+                };
+                Some(BinaryInfo {
+                    format: "ELF",
+                    os: "linux",
+                    archs: vec![arch],
+                })
+            }
+            [0xce | 0xcf, 0xfa, 0xed, 0xfe] => Some(BinaryInfo {
+                format: "Mach-O",
+                os: "macos",
+                archs: vec![macho_arch(u32_at(4, true)?)],
+            }),
+            [0xca, 0xfe, 0xba, 0xbe] => {
+                // Universal binaries share their magic with java class files, which have
+                // far more than the few architectures of universal binaries here.
+                let count = u32_at(4, false)?;
+                if count == 0 || count > 16 {
+                    return None;
+                }
+                let archs = (0..count as usize)
+                    .map(|i| u32_at(8 + 20 * i, false).map(macho_arch))
+                    .collect::<Option<_>>()?;
+                Some(BinaryInfo {
+                    format: "universal Mach-O",
+                    os: "macos",
+                    archs,
+                })
+            }
+            [b'M', b'Z', ..] => {
+                let pe = u32_at(0x3c, true)? as usize;
+                if header.get(pe..pe + 4)? != b"PE\0\0" {
+                    return None;
+                }
+                let arch = match u16_at(pe + 4, true)? {
+                    0x014c => "x86".into(),
+                    0x01c0 | 0x01c4 => "arm".into(),
+                    0x8664 => "x86_64".into(),
+                    0xaa64 => "aarch64".into(),
+                    machine => format!("machine {machine:#x}"),
+                };
+                Some(BinaryInfo {
+                    format: "PE",
+                    os: "windows",
+                    archs: vec![arch],
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// A description of why the binary can't run on this host, or [`None`] if its format
+    /// and architecture match the host.
+    fn host_mismatch(&self) -> Option<String> {
+        self.mismatch(std::env::consts::OS, std::env::consts::ARCH)
+    }
+
+    fn mismatch(&self, os: &str, arch: &str) -> Option<String> {
+        // Other unix-like OSes may run ELF binaries as well.
+        let os_matches =
+            self.os == os || (self.format == "ELF" && os != "macos" && os != "windows");
+        if os_matches && self.archs.iter().any(|a| a == arch) {
+            return None;
+        }
+        Some(format!(
+            "It is a {} executable for {}, but this host is {os} {arch}",
+            self.format,
+            self.archs.join(", ")
+        ))
+    }
+}
+
+fn macho_arch(cpu_type: u32) -> String {
+    match cpu_type {
+        0x0000_0007 => "x86".into(),
+        0x0000_000c => "arm".into(),
+        0x0100_0007 => "x86_64".into(),
+        0x0100_000c => "aarch64".into(),
+        cpu_type => format!("cpu type {cpu_type:#x}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_binary_info() {
+        let mut elf = vec![0u8; 64];
+        elf[..6].copy_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1]);
+        elf[18..20].copy_from_slice(&0xb7u16.to_le_bytes());
+        let info = BinaryInfo::parse(&elf).unwrap();
+        assert_eq!(info.archs, ["aarch64"]);
+        assert_eq!(info.mismatch("linux", "aarch64"), None);
+        assert_eq!(
+            info.mismatch("linux", "x86_64").unwrap(),
+            "It is a ELF executable for aarch64, but this host is linux x86_64"
+        );
+        assert!(info.mismatch("macos", "aarch64").is_some());
+
+        let mut macho = vec![0xcf, 0xfa, 0xed, 0xfe];
+        macho.extend(0x0100_0007u32.to_le_bytes());
+        let info = BinaryInfo::parse(&macho).unwrap();
+        assert_eq!((info.format, info.os), ("Mach-O", "macos"));
+        assert_eq!(info.archs, ["x86_64"]);
+
+        let mut fat = vec![0xca, 0xfe, 0xba, 0xbe, 0, 0, 0, 2];
+        for cpu_type in [0x0100_0007u32, 0x0100_000c] {
+            fat.extend(cpu_type.to_be_bytes());
+            fat.extend([0; 16]);
+        }
+        let info = BinaryInfo::parse(&fat).unwrap();
+        assert_eq!(info.archs, ["x86_64", "aarch64"]);
+        assert_eq!(info.mismatch("macos", "aarch64"), None);
+
+        let mut pe = vec![0u8; 0x90];
+        pe[..2].copy_from_slice(b"MZ");
+        pe[0x3c..0x40].copy_from_slice(&0x80u32.to_le_bytes());
+        pe[0x80..0x84].copy_from_slice(b"PE\0\0");
+        pe[0x84..0x86].copy_from_slice(&0x8664u16.to_le_bytes());
+        let info = BinaryInfo::parse(&pe).unwrap();
+        assert_eq!((info.format, info.archs[0].as_str()), ("PE", "x86_64"));
+
+        assert_eq!(BinaryInfo::parse(b"#!/bin/sh\n"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_verify_tool() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let install_dir = tmp.path();
+        let export_path = install_dir.join("tools/fake-gcc/1.2.3/bin");
+        fs::create_dir_all(&export_path).unwrap();
+        let exe = export_path.join("fake-gcc");
+        let write_exe = |script: &str| {
+            fs::write(&exe, script).unwrap();
+            fs::set_permissions(&exe, fs::Permissions::from_mode(0o755)).unwrap();
+        };
+        write_exe("#!/bin/sh\necho \"fake-gcc version 1.2.3 $1\"\n");
+
+        let tool = Tool {
+            name: "fake-gcc".into(),
+            version: "1.2.3".into(),
+            install_dir: install_dir.to_owned(),
+            export_path: "tools/fake-gcc/1.2.3/bin".into(),
+            version_cmd_args: vec!["fake-gcc".into(), "--version".into()],
+            version_regex: r"version ([0-9.]+)".into(),
+            ..Default::default()
+        };
+
+        let mut verifier = ToolVerifier::new(install_dir, HashMap::new());
+        assert!(!verifier.is_verified(&tool));
+        verifier.verify(&tool).unwrap();
+        verifier.store().unwrap();
+        assert!(install_dir.join(TOOLS_MANIFEST_FILE).is_file());
+
+        // The verification is cached across installer runs.
+        let verifier = ToolVerifier::new(install_dir, HashMap::new());
+        assert!(verifier.is_verified(&tool));
+
+        // Overriding the version arguments verifies the tool again.
+        let mut verifier = ToolVerifier::new(
+            install_dir,
+            HashMap::from([("fake-gcc".into(), vec!["-v".into()])]),
+        );
+        assert!(!verifier.is_verified(&tool));
+        verifier.verify(&tool).unwrap();
+
+        // A changed executable is verified again.
+        write_exe("#!/bin/sh\necho \"fake-gcc version 1.2.4\"\n");
+        let err = verifier.verify(&tool).unwrap_err().to_string();
+        assert!(
+            err.contains("it reports version 1.2.4, but version 1.2.3 is required"),
+            "{err}"
+        );
+        assert!(err.contains(&format!(
+            "Remove '{}'",
+            install_dir.join("tools/fake-gcc/1.2.3").display()
+        )));
+
+        write_exe("#!/bin/sh\necho broken >&2\nexit 3\n");
+        let err = verifier.verify(&tool).unwrap_err().to_string();
+        assert!(err.contains("failed with exit status: 3:\nbroken"), "{err}");
+        assert!(!verifier.is_verified(&tool));
+    }
+}
@@ -2,9 +2,15 @@
 
 use std::fs::{self, File};
 use std::io::{self, Read};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::process;
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
+
+#[cfg(feature = "extract")]
+mod extract;
+#[cfg(feature = "extract")]
+pub use extract::*;
 
 /// Copy `src_file` to `dest_file_or_dir` if `src_file` is different or the destination
 /// file doesn't exist.
@@ -101,3 +107,195 @@ pub fn copy_with_metadata(src_file: impl AsRef<Path>, dest_file: impl AsRef<Path
 
     Ok(())
 }
+
+/// Write `contents` to the file `path` only if its contents differ, and get whether the
+/// file was written.
+///
+/// Unchanged files keep their modification time, so that generated project files don't
+/// trigger a reconfigure of the build system. Changed files are written to a temporary
+/// file next to `path` and renamed to `path`, so that `path` is never partially written.
+pub fn write_if_changed(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> Result<bool> {
+    let path = path.as_ref();
+    let contents = contents.as_ref();
+
+    if fs::read(path).ok().as_deref() == Some(contents) {
+        return Ok(false);
+    }
+
+    write_atomically(path, contents, None)
+        .with_context(|| anyhow!("Could not write '{}'", path.display()))?;
+    Ok(true)
+}
+
+/// Copy the file `src_file` to `dest_file` only if their contents differ (or
+/// `dest_file` doesn't exist), and get whether `dest_file` was written.
+///
+/// Like [`write_if_changed`], an unchanged `dest_file` keeps its modification time and
+/// a changed one is replaced atomically; its permissions are copied from `src_file`.
+pub fn copy_if_changed(src_file: impl AsRef<Path>, dest_file: impl AsRef<Path>) -> Result<bool> {
+    let (src_file, dest_file) = (src_file.as_ref(), dest_file.as_ref());
+
+    let contents =
+        fs::read(src_file).with_context(|| anyhow!("Could not read '{}'", src_file.display()))?;
+    if fs::read(dest_file).ok().as_ref() == Some(&contents) {
+        return Ok(false);
+    }
+
+    let permissions = fs::metadata(src_file)?.permissions();
+    write_atomically(dest_file, &contents, Some(permissions)).with_context(|| {
+        anyhow!(
+            "Could not copy '{}' to '{}'",
+            src_file.display(),
+            dest_file.display()
+        )
+    })?;
+    Ok(true)
+}
+
+/// Write `contents` to a temporary file in the directory of `path` and rename it to
+/// `path`.
+fn write_atomically(
+    path: &Path,
+    contents: &[u8],
+    permissions: Option<fs::Permissions>,
+) -> io::Result<()> {
+    let mut temp_name = path.file_name().unwrap_or_default().to_owned();
+    temp_name.push(format!(".{}.tmp", process::id()));
+    let temp_file = path.with_file_name(temp_name);
+
+    let result = fs::write(&temp_file, contents)
+        .and_then(|_| match permissions {
+            Some(permissions) => fs::set_permissions(&temp_file, permissions),
+            None => Ok(()),
+        })
+        .and_then(|_| fs::rename(&temp_file, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_file);
+    }
+    result
+}
+
+/// An exclusive, inter-process lock backed by a lock file.
+///
+/// The lock is an advisory lock of the operating system on the lock file (`flock` on unix,
+/// `LockFileEx` on windows), which is released when the [`FileLock`] is dropped or the
+/// process holding it exits in any way, so a crashed or interrupted process never leaves
+/// a stale lock behind. The lock file itself is kept.
+#[derive(Debug)]
+pub struct FileLock {
+    path: PathBuf,
+    /// The locked file, whose lock is released when it is closed.
+    _file: fd_lock::RwLock<File>,
+}
+
+impl FileLock {
+    /// Acquire the lock `path`, blocking until it is released by any other process.
+    pub fn acquire(path: impl AsRef<Path>) -> Result<FileLock> {
+        let path = path.as_ref();
+        if let Some(lock) = Self::try_acquire(path)? {
+            return Ok(lock);
+        }
+
+        log::info!("Waiting for lock '{}'", path.display());
+        let mut file = Self::open(path)?;
+        std::mem::forget(
+            file.write()
+                .with_context(|| anyhow!("could not lock '{}'", path.display()))?,
+        );
+        Ok(FileLock {
+            path: path.to_owned(),
+            _file: file,
+        })
+    }
+
+    /// Try to acquire the lock `path` without blocking, returning [`None`] if it is held
+    /// by another process.
+    pub fn try_acquire(path: impl AsRef<Path>) -> Result<Option<FileLock>> {
+        let path = path.as_ref();
+        let mut file = Self::open(path)?;
+        match file.try_write() {
+            // The lock is released when the file is closed.
+            Ok(guard) => std::mem::forget(guard),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+            Err(e) => {
+                return Err(
+                    anyhow::Error::new(e).context(format!("could not lock '{}'", path.display()))
+                )
+            }
+        }
+        Ok(Some(FileLock {
+            path: path.to_owned(),
+            _file: file,
+        }))
+    }
+
+    /// Get the path of the lock file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn open(path: &Path) -> Result<fd_lock::RwLock<File>> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| anyhow!("could not create '{}'", parent.display()))?;
+        }
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .with_context(|| anyhow!("could not open lock '{}'", path.display()))?;
+        Ok(fd_lock::RwLock::new(file))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_if_changed() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let (file, copy) = (dir.join("file.txt"), dir.join("copy.txt"));
+        let old = filetime::FileTime::from_unix_time(1_000_000_000, 0);
+        let mtime = |path: &Path| {
+            filetime::FileTime::from_last_modification_time(&fs::metadata(path).unwrap())
+        };
+
+        assert!(write_if_changed(&file, "a").unwrap());
+        assert!(copy_if_changed(&file, &copy).unwrap());
+        filetime::set_file_mtime(&file, old).unwrap();
+        filetime::set_file_mtime(&copy, old).unwrap();
+
+        // Unchanged files are not touched.
+        assert!(!write_if_changed(&file, "a").unwrap());
+        assert!(!copy_if_changed(&file, &copy).unwrap());
+        assert_eq!(mtime(&file), old);
+        assert_eq!(mtime(&copy), old);
+
+        assert!(write_if_changed(&file, "b").unwrap());
+        assert!(copy_if_changed(&file, &copy).unwrap());
+        assert_ne!(mtime(&file), old);
+        assert_eq!(fs::read_to_string(&copy).unwrap(), "b");
+        assert_eq!(fs::read_dir(dir).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_file_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sub/test.lock");
+
+        let lock = FileLock::try_acquire(&path).unwrap().unwrap();
+        assert!(FileLock::try_acquire(&path).unwrap().is_none());
+        drop(lock);
+        assert!(FileLock::try_acquire(&path).unwrap().is_some());
+
+        // A lock left behind by a process that exited is released by the OS: the lock
+        // file alone doesn't lock.
+        assert!(path.exists());
+        let lock = FileLock::acquire(&path).unwrap();
+        assert_eq!(lock.path(), path);
+    }
+}
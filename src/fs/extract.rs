@@ -0,0 +1,516 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use anyhow::{anyhow, bail, Context, Result};
+
+mod tar;
+mod zip;
+
+/// Extract the `.tar`, `.tar.gz`, `.tar.xz` or `.zip` `archive` to the directory
+/// `dest`, replacing it if it exists (see [`Extract`]).
+pub fn extract(archive: impl AsRef<Path>, dest: impl AsRef<Path>) -> Result<()> {
+    Extract::new().run(archive, dest)
+}
+
+/// The extraction of an archive.
+///
+/// The archive format is detected from the magic bytes of the archive (or its extension
+/// if they are ambiguous). The archive is extracted into a temporary sibling of the
+/// destination directory, which is only renamed to the destination once the extraction
+/// succeeded, so that an interrupted extraction never leaves a partially populated
+/// destination.
+///
+/// Unix permissions of the entries are preserved. Symlinks in the archive are created
+/// after all other entries and are copies of their targets on Windows if creating
+/// symlinks is not permitted. Entries (and symlink targets) outside of the destination
+/// (e.g. `../../evil`) fail the extraction.
+#[derive(Debug, Clone, Default)]
+pub struct Extract {
+    strip_leading_dir: bool,
+}
+
+impl Extract {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Strip the single top-level directory all entries of the archive are in (e.g. the
+    /// `tool-1.2.3/` of tool archives).
+    ///
+    /// The extraction fails if the archive has any other top-level entry.
+    pub fn strip_leading_dir(mut self, strip: bool) -> Self {
+        self.strip_leading_dir = strip;
+        self
+    }
+
+    /// Extract `archive` to the directory `dest`, replacing it if it exists.
+    pub fn run(&self, archive: impl AsRef<Path>, dest: impl AsRef<Path>) -> Result<()> {
+        let (archive, dest) = (archive.as_ref(), dest.as_ref());
+        self.extract(archive, dest).with_context(|| {
+            anyhow!(
+                "could not extract '{}' to '{}'",
+                archive.display(),
+                dest.display()
+            )
+        })
+    }
+
+    fn extract(&self, archive: &Path, dest: &Path) -> Result<()> {
+        let format = Format::detect(archive)?;
+
+        let temp = sibling(dest, "extract")?;
+        if let Some(parent) = temp.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        remove_if_exists(&temp)?;
+        fs::create_dir(&temp)?;
+
+        let mut target = Target {
+            root: temp.clone(),
+            symlinks: Vec::new(),
+            dir_modes: Vec::new(),
+        };
+        let result = format
+            .unpack(archive, &mut target)
+            .and_then(|_| target.finish())
+            .and_then(|_| self.replace(&temp, dest));
+        if result.is_err() {
+            let _ = remove_if_exists(&temp);
+        }
+
+        result
+    }
+
+    /// Move the extracted `temp` directory to `dest`.
+    fn replace(&self, temp: &Path, dest: &Path) -> Result<()> {
+        let extracted = if self.strip_leading_dir {
+            let entries = fs::read_dir(temp)?.collect::<Result<Vec<_>, _>>()?;
+            match &entries[..] {
+                [entry] if entry.file_type()?.is_dir() => entry.path(),
+                _ => bail!(
+                    "cannot strip the leading directory: the archive has {} top-level entries",
+                    entries.len()
+                ),
+            }
+        } else {
+            temp.to_owned()
+        };
+
+        if fs::symlink_metadata(dest).is_ok() {
+            let old = sibling(dest, "old")?;
+            remove_if_exists(&old)?;
+            fs::rename(dest, &old)?;
+            fs::rename(&extracted, dest)?;
+            remove_if_exists(&old)?;
+        } else {
+            fs::rename(&extracted, dest)?;
+        }
+
+        if extracted != temp {
+            fs::remove_dir(temp)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Get the hidden sibling `.<name>.<suffix>-<pid>` of `path`.
+fn sibling(path: &Path, suffix: &str) -> Result<PathBuf> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("'{}' has no file name", path.display()))?;
+    Ok(path.with_file_name(format!(
+        ".{}.{suffix}-{}",
+        name.to_string_lossy(),
+        process::id()
+    )))
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(path)?,
+        Ok(_) => fs::remove_file(path)?,
+        Err(_) => (),
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Tar,
+    TarGz,
+    TarXz,
+    Zip,
+}
+
+impl Format {
+    fn detect(archive: &Path) -> Result<Self> {
+        let mut magic = Vec::with_capacity(262);
+        File::open(archive)
+            .with_context(|| anyhow!("could not open '{}'", archive.display()))?
+            .take(262)
+            .read_to_end(&mut magic)?;
+
+        let format = if magic.starts_with(&[0x1F, 0x8B]) {
+            Self::TarGz
+        } else if magic.starts_with(&[0xFD, b'7', b'z', b'X', b'Z', 0x00]) {
+            Self::TarXz
+        } else if magic.starts_with(b"PK\x03\x04") || magic.starts_with(b"PK\x05\x06") {
+            Self::Zip
+        } else if magic.get(257..262) == Some(b"ustar") {
+            Self::Tar
+        } else {
+            let name = archive
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_lowercase();
+            if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+                Self::TarGz
+            } else if name.ends_with(".tar.xz") || name.ends_with(".txz") {
+                Self::TarXz
+            } else if name.ends_with(".zip") {
+                Self::Zip
+            } else if name.ends_with(".tar") {
+                Self::Tar
+            } else {
+                bail!("unsupported archive format");
+            }
+        };
+
+        Ok(format)
+    }
+
+    fn unpack(self, archive: &Path, target: &mut Target) -> Result<()> {
+        let file = File::open(archive)?;
+        match self {
+            Self::Tar => tar::unpack(BufReader::new(file), target),
+            Self::TarGz => tar::unpack(
+                flate2::bufread::MultiGzDecoder::new(BufReader::new(file)),
+                target,
+            ),
+            Self::TarXz => tar::unpack(xz2::read::XzDecoder::new_multi_decoder(file), target),
+            Self::Zip => zip::unpack(file, target),
+        }
+    }
+}
+
+/// The path separators of archive entries.
+const SEPARATORS: &[char] = if cfg!(windows) { &['/', '\\'] } else { &['/'] };
+
+/// The directory an archive is unpacked into.
+struct Target {
+    root: PathBuf,
+    /// The symlinks and their targets, which are created last so that no entry is
+    /// written through a symlink.
+    symlinks: Vec<(PathBuf, String)>,
+    /// The modes of directories, which are set last so that read-only directories can
+    /// be populated.
+    dir_modes: Vec<(PathBuf, u32)>,
+}
+
+impl Target {
+    /// Get the path of the archive entry `name`, or [`None`] for the root itself.
+    fn path(&self, name: &str) -> Result<Option<PathBuf>> {
+        let mut path = self.root.clone();
+        for component in name.split(SEPARATORS) {
+            match component {
+                "" | "." => (),
+                ".." => bail!("archive entry '{name}' is outside of the destination"),
+                _ if cfg!(windows) && component.contains(':') => {
+                    bail!("archive entry '{name}' is not a valid relative path")
+                }
+                _ => path.push(component),
+            }
+        }
+
+        Ok((path != self.root).then_some(path))
+    }
+
+    fn create_parent(path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| anyhow!("could not create '{}'", parent.display()))?;
+        }
+        Ok(())
+    }
+
+    fn dir(&mut self, name: &str, mode: Option<u32>) -> Result<()> {
+        let Some(path) = self.path(name)? else {
+            return Ok(());
+        };
+
+        fs::create_dir_all(&path)
+            .with_context(|| anyhow!("could not create '{}'", path.display()))?;
+        if let Some(mode) = mode {
+            self.dir_modes.push((path, mode));
+        }
+        Ok(())
+    }
+
+    fn file(&mut self, name: &str, mode: Option<u32>, data: &mut impl Read) -> Result<()> {
+        let Some(path) = self.path(name)? else {
+            bail!("archive entry '{name}' is not a valid file name");
+        };
+
+        Self::create_parent(&path)?;
+        // A later entry of the same path replaces the earlier one.
+        let _ = fs::remove_file(&path);
+        let mut file = File::create(&path)
+            .with_context(|| anyhow!("could not create '{}'", path.display()))?;
+        io::copy(data, &mut file)?;
+        drop(file);
+
+        set_mode(&path, mode)
+    }
+
+    fn symlink(&mut self, name: &str, link: &str) -> Result<()> {
+        let Some(path) = self.path(name)? else {
+            bail!("archive entry '{name}' is not a valid symlink");
+        };
+
+        let outside =
+            || anyhow!("target '{link}' of symlink '{name}' is outside of the destination");
+        if link.starts_with(SEPARATORS) || cfg!(windows) && link.contains(':') {
+            return Err(outside());
+        }
+
+        // Resolve the link lexically from the directory of the symlink.
+        let mut depth = path.strip_prefix(&self.root)?.components().count() - 1;
+        for component in link.split(SEPARATORS) {
+            match component {
+                "" | "." => (),
+                ".." => depth = depth.checked_sub(1).ok_or_else(outside)?,
+                _ => depth += 1,
+            }
+        }
+
+        self.symlinks.push((path, link.to_owned()));
+        Ok(())
+    }
+
+    /// Create the symlinks and set the directory modes.
+    fn finish(&mut self) -> Result<()> {
+        let mut pending = std::mem::take(&mut self.symlinks);
+        // On Windows symlinks may be copies of other symlinks, which must be created first.
+        loop {
+            let count = pending.len();
+            let mut remaining = Vec::new();
+            for (path, link) in pending {
+                if !create_symlink(&path, &link)? {
+                    remaining.push((path, link));
+                }
+            }
+            pending = remaining;
+
+            if pending.is_empty() || pending.len() == count {
+                break;
+            }
+        }
+        for (path, link) in pending {
+            log::debug!(
+                "Skipping symlink '{}' to '{link}' whose target does not exist",
+                path.display()
+            );
+        }
+
+        for (path, mode) in self.dir_modes.drain(..).rev() {
+            set_mode(&path, Some(mode))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Set the unix permissions of `path` to the permission bits of `mode`.
+///
+/// A mode without any permission bits (as written by some zip tools) is ignored.
+fn set_mode(path: &Path, mode: Option<u32>) -> Result<()> {
+    #[cfg(unix)]
+    if let Some(mode) = mode.map(|mode| mode & 0o777).filter(|&mode| mode != 0) {
+        use std::os::unix::fs::PermissionsExt;
+
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))
+            .with_context(|| anyhow!("could not set the permissions of '{}'", path.display()))?;
+    }
+    #[cfg(not(unix))]
+    let _ = (path, mode);
+
+    Ok(())
+}
+
+/// Create the symlink `path` to `link`, returning `false` if it has to wait for its
+/// target to be created.
+#[cfg(unix)]
+fn create_symlink(path: &Path, link: &str) -> Result<bool> {
+    Target::create_parent(path)?;
+    let _ = fs::remove_file(path);
+    std::os::unix::fs::symlink(link, path)
+        .with_context(|| anyhow!("could not create symlink '{}'", path.display()))?;
+    Ok(true)
+}
+
+/// Create the symlink `path` to `link`, returning `false` if it has to wait for its
+/// target to be created.
+///
+/// If symlinks cannot be created (e.g. on Windows without developer mode) the target is
+/// copied instead.
+#[cfg(not(unix))]
+fn create_symlink(path: &Path, link: &str) -> Result<bool> {
+    let original = path.parent().unwrap_or(Path::new("")).join(link);
+    let Ok(meta) = fs::metadata(&original) else {
+        return Ok(false);
+    };
+
+    Target::create_parent(path)?;
+    #[cfg(windows)]
+    let result = if meta.is_dir() {
+        std::os::windows::fs::symlink_dir(link, path)
+    } else {
+        std::os::windows::fs::symlink_file(link, path)
+    };
+    #[cfg(not(windows))]
+    let result = Err::<(), _>(io::Error::from(io::ErrorKind::Unsupported));
+
+    if let Err(e) = result {
+        log::debug!(
+            "Could not create symlink '{}' ({e}), copying its target '{link}' instead",
+            path.display()
+        );
+        if meta.is_dir() {
+            copy_dir(&original, path)?;
+        } else {
+            fs::copy(&original, path)
+                .with_context(|| anyhow!("could not copy '{}'", original.display()))?;
+        }
+    }
+
+    Ok(true)
+}
+
+#[cfg(not(unix))]
+fn copy_dir(src: &Path, dest: &Path) -> Result<()> {
+    fs::create_dir_all(dest)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let dest = dest.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &dest)?;
+        } else {
+            fs::copy(entry.path(), dest)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/extract")
+            .join(name)
+    }
+
+    #[test]
+    fn test_extract() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let data = (0..3000)
+            .map(|i| format!("line {i}: {}\n", i * i % 9973))
+            .collect::<String>();
+
+        for archive in ["tool.tar.gz", "tool.tar.xz", "tool.zip"] {
+            let dest = dir.join(archive);
+            Extract::new()
+                .strip_leading_dir(true)
+                .run(fixture(archive), &dest)
+                .unwrap();
+
+            let read = |path: &str| fs::read(dest.join(path)).unwrap();
+            assert_eq!(read("bin/tool"), b"#!/bin/sh\necho tool\n", "{archive}");
+            assert_eq!(read("share/doc/readme.txt"), b"tool 1.2.3\n");
+            assert_eq!(read("share/data.txt"), data.as_bytes());
+            assert_eq!(
+                read("share/random.bin"),
+                read("../tool.tar.gz/share/random.bin")
+            );
+            assert_eq!(
+                read(&format!(
+                    "share/{}end.txt",
+                    "a-very-long-file-name-".repeat(5)
+                )),
+                b"long\n"
+            );
+            assert_eq!(read("share/bin/tool-link"), read("bin/tool"));
+
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+
+                let mode = |path: &str| {
+                    fs::metadata(dest.join(path)).unwrap().permissions().mode() & 0o777
+                };
+                assert_eq!(mode("bin/tool"), 0o755, "{archive}");
+                assert_eq!(mode("share/random.bin"), 0o600, "{archive}");
+                assert_eq!(
+                    fs::read_link(dest.join("share/bin")).unwrap(),
+                    Path::new("../bin")
+                );
+            }
+        }
+
+        assert_eq!(
+            fs::read(dir.join("tool.tar.xz/bin/tool-hardlink")).unwrap(),
+            b"#!/bin/sh\necho tool\n"
+        );
+
+        // Without stripping and replacing an existing directory.
+        let dest = dir.join("tool.zip");
+        extract(fixture("tool.zip"), &dest).unwrap();
+        assert!(dest.join("tool-1.2.3/bin/tool").is_file());
+        assert!(!dest.join("bin").exists());
+
+        // A plain tar archive without a single top-level directory.
+        let error = Extract::new()
+            .strip_leading_dir(true)
+            .run(fixture("flat.tar"), dir.join("flat"))
+            .unwrap_err();
+        assert!(
+            format!("{error:#}").contains("2 top-level entries"),
+            "{error:#}"
+        );
+        extract(fixture("flat.tar"), dir.join("flat")).unwrap();
+        assert_eq!(fs::read(dir.join("flat/b.txt")).unwrap(), b"b\n");
+
+        // No temporary directories are left behind.
+        let mut entries = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        entries.sort();
+        assert_eq!(entries, ["flat", "tool.tar.gz", "tool.tar.xz", "tool.zip"]);
+    }
+
+    #[test]
+    fn test_traversal() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let dest = dir.join("a/b/dest");
+
+        for archive in ["evil.tar.gz", "evil.zip", "evil-link.tar.gz"] {
+            let error = format!("{:#}", extract(fixture(archive), &dest).unwrap_err());
+            assert!(error.contains("outside of the destination"), "{error}");
+            assert!(!dest.exists());
+        }
+
+        assert!(!dir.join("evil").exists());
+        assert!(!dir.join("a/evil").exists());
+        assert_eq!(fs::read_dir(dir.join("a/b")).unwrap().count(), 0);
+    }
+}
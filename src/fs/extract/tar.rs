@@ -0,0 +1,46 @@
+//! Unpacking of tar archives with the `tar` crate.
+
+use std::io::Read;
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use super::Target;
+
+/// Unpack the tar archive read from `reader` into `target`.
+///
+/// Files and hard links are unpacked by the `tar` crate, which refuses entries outside of
+/// the destination; directories and symlinks go through `target`, so that directory
+/// modes and symlinks are applied after all files were written.
+pub fn unpack(reader: impl Read, target: &mut Target) -> Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    archive.set_preserve_permissions(true);
+    archive.set_overwrite(true);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let kind = entry.header().entry_type();
+
+        if kind.is_dir() {
+            target.dir(&name, entry.header().mode().ok())?;
+        } else if kind.is_symlink() {
+            let link = entry
+                .link_name()?
+                .ok_or_else(|| anyhow!("symlink '{name}' has no target"))?;
+            target.symlink(&name, &link.to_string_lossy())?;
+        } else if kind.is_file() || kind.is_hard_link() || kind.is_contiguous() {
+            // Validate the path like all other entries before unpacking it.
+            target.path(&name)?;
+            let unpacked = entry
+                .unpack_in(&target.root)
+                .with_context(|| anyhow!("could not unpack archive entry '{name}'"))?;
+            if !unpacked {
+                bail!("archive entry '{name}' is outside of the destination");
+            }
+        } else if !kind.is_pax_global_extensions() {
+            log::debug!("Skipping archive entry '{name}' of unsupported type {kind:?}");
+        }
+    }
+
+    Ok(())
+}
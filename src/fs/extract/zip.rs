@@ -0,0 +1,42 @@
+//! Unpacking of zip archives with the `zip` crate.
+
+use std::fs::File;
+use std::io::Read;
+
+use anyhow::{bail, Result};
+
+use super::Target;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+
+/// Unpack the zip archive `file` into `target`.
+pub fn unpack(file: File, target: &mut Target) -> Result<()> {
+    let mut archive = zip::ZipArchive::new(file)?;
+
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        let Some(name) = entry
+            .enclosed_name()
+            .map(|name| name.to_string_lossy().into_owned())
+        else {
+            bail!(
+                "archive entry '{}' is outside of the destination",
+                entry.name()
+            );
+        };
+        let mode = entry.unix_mode();
+
+        if entry.is_dir() {
+            target.dir(&name, mode)?;
+        } else if mode.is_some_and(|mode| mode & S_IFMT == S_IFLNK) {
+            let mut link = String::new();
+            entry.read_to_string(&mut link)?;
+            target.symlink(&name, &link)?;
+        } else {
+            target.file(&name, mode, &mut entry)?;
+        }
+    }
+
+    Ok(())
+}
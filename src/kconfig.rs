@@ -1,15 +1,35 @@
-//! A quick and dirty parser for the .config files generated by kconfig systems (e.g. used
-//! in the esp-idf).
+//! Parsers for the .config files generated by kconfig systems (e.g. the `sdkconfig` of
+//! the esp-idf).
+//!
+//! [`Parsed`] keeps the type of every value and writes the file back unchanged, while
+//! [`try_from_config`] and [`try_from_json`] are quick and dirty parsers only yielding
+//! tristate and string values.
 
 use std::collections::HashMap;
+use std::fmt::{self, Display};
 use std::fs;
 use std::io::{self, BufRead, Read};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
+
+use crate::build::{DepVars, DepVarsWriter};
+use crate::cargo;
+use crate::logging::warning;
+
+/// The environment variable name containing the file path of the module generated by
+/// [`generate_module`] into [`default_module_file`].
+pub const VAR_KCONFIG_FILE: &str = "EMBUILD_GENERATED_KCONFIG_FILE";
+
+/// The `links` metadata key of the file written by [`CfgArgs::propagate`].
+const KCONFIG_CFGS_FILE_VAR: &str = "EMBUILD_KCONFIG_CFGS_FILE";
+
+/// The version of the file format of [`CfgArgs::write_to`], incremented on every
+/// incompatible change.
+const CFG_ARGS_SCHEMA_VERSION: u32 = 1;
 
 /// A tristate kconfig configuration item.
-#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, serde::Serialize, serde::Deserialize)]
 pub enum Tristate {
     /// The item is enabled, compiled, true.
     True,
@@ -25,15 +45,73 @@ pub enum Tristate {
 }
 
 /// Value of a kconfig configuration item.
-#[derive(Clone, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug, serde::Serialize, serde::Deserialize)]
 pub enum Value {
+    /// A bool value (`y`, or `n` and `# ... is not set`).
+    Bool(bool),
     /// A [`Tristate`] value.
     Tristate(Tristate),
+    /// A decimal integer value.
+    Int(i64),
+    /// A hexadecimal integer value (`0x...`).
+    Hex(u64),
     /// A [`String`] value.
     String(String),
 }
 
 impl Value {
+    /// Get the value of a bool or tristate item, [`Tristate::Module`] counting as
+    /// enabled.
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            Value::Tristate(t) => Some(matches!(t, Tristate::True | Tristate::Module)),
+            _ => None,
+        }
+    }
+
+    /// Get the value of a decimal or hexadecimal item, [`None`] if it doesn't fit in an
+    /// `i64`.
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Value::Int(i) => Some(*i),
+            Value::Hex(h) => i64::try_from(*h).ok(),
+            _ => None,
+        }
+    }
+
+    /// Get the value of a hexadecimal or (non-negative) decimal item.
+    pub fn as_hex(&self) -> Option<u64> {
+        match self {
+            Value::Hex(h) => Some(*h),
+            Value::Int(i) => u64::try_from(*i).ok(),
+            _ => None,
+        }
+    }
+
+    /// Get the value of a string item.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Whether this value has the same meaning as `other` (see [`diff`]).
+    fn same_as(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Bool(_) | Value::Tristate(_), Value::Bool(_) | Value::Tristate(_)) => {
+                self.as_bool() == other.as_bool()
+                    && matches!(self, Value::Tristate(Tristate::Module))
+                        == matches!(other, Value::Tristate(Tristate::Module))
+            }
+            (Value::Int(_) | Value::Hex(_), Value::Int(_) | Value::Hex(_)) => {
+                (self.as_int(), self.as_hex()) == (other.as_int(), other.as_hex())
+            }
+            _ => self == other,
+        }
+    }
+
     /// Turn a configuration value of an item named `key` into a valid rust cfg.
     ///
     /// Only the following cfgs will be generated:
@@ -45,7 +123,7 @@ impl Value {
     /// Both `prefix` and `key` are lowercased.
     pub fn to_rustc_cfg(&self, prefix: impl AsRef<str>, key: impl AsRef<str>) -> Option<String> {
         match self {
-            Value::Tristate(Tristate::True) => Some(""),
+            Value::Tristate(Tristate::True) | Value::Bool(true) => Some(""),
             Value::String(s) => Some(s.as_str()),
             _ => None,
         }
@@ -138,3 +216,1040 @@ fn parse_config_value(str: impl AsRef<str>) -> Option<Value> {
         return None;
     })
 }
+
+impl Display for Value {
+    /// Format the value like in a .config file, with `n` for unset items.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&format_value(self))
+    }
+}
+
+/// A kconfig .config file parsed into typed values by [`Parsed::parse`].
+///
+/// All lines are kept, so that the file is written back byte-identically by its
+/// [`Display`] implementation if no value was changed.
+#[derive(Clone, Debug, Default)]
+pub struct Parsed {
+    lines: Vec<Line>,
+    entries: Vec<(String, Value)>,
+    warnings: Vec<Warning>,
+}
+
+/// A line of a [`Parsed`] file.
+#[derive(Clone, Debug)]
+enum Line {
+    /// A comment, empty or malformed line, including its line terminator.
+    Other(String),
+    /// The item `entries[index]`, with the text it was parsed from (including all
+    /// continuation lines and the line terminator) and the value parsed from it.
+    Entry {
+        index: usize,
+        original: Option<(String, Value)>,
+    },
+}
+
+/// A malformed line found by [`Parsed::parse`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Warning {
+    /// The line number (starting at 1).
+    pub line: usize,
+    /// What is wrong with the line.
+    pub message: String,
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Parsed {
+    /// Parse the kconfig .config file `text`.
+    ///
+    /// Supports:
+    /// - `NAME=y`, `NAME=n`, `NAME=m`, decimal and `0x` hexadecimal integers, and quoted
+    ///   strings with `\` escapes;
+    /// - `# NAME is not set` comments (as [`Value::Bool(false)`](Value::Bool));
+    /// - lines ending with a `\` continued on the next line.
+    ///
+    /// Malformed lines are kept as is and reported as [`Warning`]s (see
+    /// [`Parsed::warnings`]), which are also logged.
+    pub fn parse(text: &str) -> Self {
+        let mut parsed = Parsed::default();
+        let mut lines = text.split_inclusive('\n').enumerate().peekable();
+
+        while let Some((index, first)) = lines.next() {
+            let line_number = index + 1;
+            let mut raw = first.to_owned();
+            let mut content = line_content(first).to_owned();
+            while has_continuation(&content) {
+                let Some((_, next)) = lines.next() else {
+                    break;
+                };
+                content.pop();
+                content.push_str(line_content(next));
+                raw.push_str(next);
+            }
+
+            match parse_line(&content) {
+                Ok(Some((name, value))) => parsed.push_entry(name, value, Some(raw)),
+                Ok(None) => parsed.lines.push(Line::Other(raw)),
+                Err(message) => {
+                    let warning = Warning {
+                        line: line_number,
+                        message,
+                    };
+                    warning!("Ignoring malformed kconfig {warning}");
+                    parsed.warnings.push(warning);
+                    parsed.lines.push(Line::Other(raw));
+                }
+            }
+        }
+
+        parsed
+    }
+
+    /// Read and parse the kconfig .config file `path` (see [`Parsed::parse`]).
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("could not read '{}'", path.display()))?;
+        Ok(Self::parse(&text))
+    }
+
+    /// Write this file to `path` (see the [`Display`] implementation).
+    pub fn write_to_file(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        crate::fs::write_if_changed(path, self.to_string())?;
+        Ok(())
+    }
+
+    /// Get all items in the order of the file.
+    pub fn entries(&self) -> &[(String, Value)] {
+        &self.entries
+    }
+
+    /// Get all items in the order of the file.
+    pub fn into_entries(self) -> Vec<(String, Value)> {
+        self.entries
+    }
+
+    /// Get the malformed lines of the parsed file.
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    /// Get the value of the item `name` (including any `CONFIG_` prefix).
+    ///
+    /// If the item is set multiple times, the last value is returned (like kconfig does).
+    pub fn get(&self, name: impl AsRef<str>) -> Option<&Value> {
+        let name = name.as_ref();
+        self.entries
+            .iter()
+            .rev()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Get the value of the bool or tristate item `name` (see [`Value::as_bool`]).
+    pub fn get_bool(&self, name: impl AsRef<str>) -> Option<bool> {
+        self.get(name).and_then(Value::as_bool)
+    }
+
+    /// Get the value of the integer item `name` (see [`Value::as_int`]).
+    pub fn get_int(&self, name: impl AsRef<str>) -> Option<i64> {
+        self.get(name).and_then(Value::as_int)
+    }
+
+    /// Get the value of the integer item `name` (see [`Value::as_hex`]).
+    pub fn get_hex(&self, name: impl AsRef<str>) -> Option<u64> {
+        self.get(name).and_then(Value::as_hex)
+    }
+
+    /// Get the value of the string item `name`.
+    pub fn get_str(&self, name: impl AsRef<str>) -> Option<&str> {
+        self.get(name).and_then(Value::as_str)
+    }
+
+    /// Set the item `name` to `value`, appending it if it isn't set yet.
+    ///
+    /// All values of an item set multiple times are changed.
+    pub fn set(&mut self, name: impl Into<String>, value: Value) {
+        let name = name.into();
+        let mut found = false;
+        for (_, v) in self.entries.iter_mut().filter(|(n, _)| *n == name) {
+            *v = value.clone();
+            found = true;
+        }
+        if !found {
+            self.push_entry(name, value, None);
+        }
+    }
+
+    fn push_entry(&mut self, name: String, value: Value, raw: Option<String>) {
+        self.lines.push(Line::Entry {
+            index: self.entries.len(),
+            original: raw.map(|raw| (raw, value.clone())),
+        });
+        self.entries.push((name, value));
+    }
+}
+
+impl Display for Parsed {
+    /// Write the file in the kconfig .config format, with all unchanged items as they
+    /// were parsed.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Whether a changed item must be put on a new line, because the last line had no
+        // line terminator.
+        let mut unterminated = false;
+        for line in &self.lines {
+            let raw = match line {
+                Line::Other(raw) => raw,
+                Line::Entry { index, original } => {
+                    let (name, value) = &self.entries[*index];
+                    match original {
+                        Some((raw, original)) if original == value => raw,
+                        _ => {
+                            if unterminated {
+                                writeln!(f)?;
+                            }
+                            writeln!(f, "{}", format_entry(name, value))?;
+                            unterminated = false;
+                            continue;
+                        }
+                    }
+                }
+            };
+            f.write_str(raw)?;
+            unterminated = !raw.ends_with('\n');
+        }
+        Ok(())
+    }
+}
+
+/// A difference between two kconfig files found by [`diff`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Change {
+    /// The item is only set in the new file.
+    Added { name: String, value: Value },
+    /// The item is only set in the old file.
+    Removed { name: String, value: Value },
+    /// The item has different values.
+    Changed {
+        name: String,
+        old: Value,
+        new: Value,
+    },
+}
+
+impl Change {
+    /// Get the name of the changed item.
+    pub fn name(&self) -> &str {
+        match self {
+            Change::Added { name, .. }
+            | Change::Removed { name, .. }
+            | Change::Changed { name, .. } => name,
+        }
+    }
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added { name, value } => write!(f, "+ {}", format_entry(name, value)),
+            Change::Removed { name, value } => write!(f, "- {}", format_entry(name, value)),
+            Change::Changed { name, old, new } => write!(
+                f,
+                "~ {} (was {})",
+                format_entry(name, new),
+                format_value(old)
+            ),
+        }
+    }
+}
+
+/// Compare the (last) values of all items of `old` and `new`.
+///
+/// Values with the same meaning are equal, e.g. `Int(4096)` and `Hex(0x1000)`, or
+/// `Bool(true)` and `Tristate(Tristate::True)`. The changes are in the order of the items
+/// in `old`, followed by the items added in `new`.
+pub fn diff(old: &Parsed, new: &Parsed) -> Vec<Change> {
+    let mut seen = std::collections::HashSet::new();
+    let mut changes = Vec::new();
+
+    for (name, _) in old.entries() {
+        if !seen.insert(name.as_str()) {
+            continue;
+        }
+        let old_value = old.get(name).unwrap();
+        match new.get(name) {
+            None => changes.push(Change::Removed {
+                name: name.clone(),
+                value: old_value.clone(),
+            }),
+            Some(new_value) if !old_value.same_as(new_value) => changes.push(Change::Changed {
+                name: name.clone(),
+                old: old_value.clone(),
+                new: new_value.clone(),
+            }),
+            Some(_) => (),
+        }
+    }
+    for (name, _) in new.entries() {
+        if seen.insert(name.as_str()) {
+            changes.push(Change::Added {
+                name: name.clone(),
+                value: new.get(name).unwrap().clone(),
+            });
+        }
+    }
+
+    changes
+}
+
+/// Get `line` without its line terminator.
+fn line_content(line: &str) -> &str {
+    line.strip_suffix('\n')
+        .map(|l| l.strip_suffix('\r').unwrap_or(l))
+        .unwrap_or(line)
+}
+
+/// Whether `content` ends with an unescaped `\`.
+fn has_continuation(content: &str) -> bool {
+    content.bytes().rev().take_while(|b| *b == b'\\').count() % 2 == 1
+}
+
+/// Parse a (continued) line, returning [`None`] for comments and empty lines and a
+/// message for malformed lines.
+fn parse_line(content: &str) -> Result<Option<(String, Value)>, String> {
+    let line = content.trim();
+    if line.is_empty() {
+        return Ok(None);
+    }
+    if let Some(comment) = line.strip_prefix('#') {
+        return Ok(comment
+            .trim()
+            .strip_suffix(" is not set")
+            .filter(|name| is_valid_name(name))
+            .map(|name| (name.to_owned(), Value::Bool(false))));
+    }
+
+    let Some((name, value)) = line.split_once('=') else {
+        return Err(format!("expected 'NAME=value', found '{line}'"));
+    };
+    let name = name.trim();
+    if !is_valid_name(name) {
+        return Err(format!("invalid item name '{name}'"));
+    }
+
+    parse_value(value.trim())
+        .map(|value| Some((name.to_owned(), value)))
+        .map_err(|e| format!("invalid value of '{name}': {e}"))
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_value(value: &str) -> Result<Value, String> {
+    if let Some(quoted) = value.strip_prefix('"') {
+        return unescape(quoted).map(Value::String);
+    }
+
+    Ok(match value {
+        "y" => Value::Bool(true),
+        "n" => Value::Bool(false),
+        "m" => Value::Tristate(Tristate::Module),
+        _ => {
+            if let Some(hex) = value
+                .strip_prefix("0x")
+                .or_else(|| value.strip_prefix("0X"))
+            {
+                Value::Hex(u64::from_str_radix(hex, 16).map_err(|e| format!("'{value}': {e}"))?)
+            } else if let Ok(int) = value.parse() {
+                Value::Int(int)
+            } else {
+                return Err(format!("'{value}' is not a bool, integer or quoted string"));
+            }
+        }
+    })
+}
+
+/// Unescape the string after its opening quote up to its closing quote.
+fn unescape(quoted: &str) -> Result<String, String> {
+    let mut value = String::new();
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => value.push(chars.next().ok_or("unterminated escape sequence")?),
+            '"' => {
+                let rest = chars.as_str().trim();
+                return if rest.is_empty() {
+                    Ok(value)
+                } else {
+                    Err(format!("unexpected '{rest}' after the closing quote"))
+                };
+            }
+            c => value.push(c),
+        }
+    }
+    Err("missing closing quote".into())
+}
+
+/// Format the item `name` with `value` as a line of a .config file (without line
+/// terminator).
+fn format_entry(name: &str, value: &Value) -> String {
+    match value {
+        Value::Bool(false) | Value::Tristate(Tristate::False | Tristate::NotSet) => {
+            format!("# {name} is not set")
+        }
+        _ => format!("{name}={}", format_value(value)),
+    }
+}
+
+/// Format `value` like in a .config file (with `n` for unset items).
+fn format_value(value: &Value) -> String {
+    match value {
+        Value::Bool(true) | Value::Tristate(Tristate::True) => "y".into(),
+        Value::Bool(false) | Value::Tristate(Tristate::False | Tristate::NotSet) => "n".into(),
+        Value::Tristate(Tristate::Module) => "m".into(),
+        Value::Int(i) => i.to_string(),
+        Value::Hex(h) => format!("{h:#x}"),
+        Value::String(s) => {
+            let mut quoted = String::from("\"");
+            for c in s.chars() {
+                if matches!(c, '"' | '\\') {
+                    quoted.push('\\');
+                }
+                quoted.push(c);
+            }
+            quoted.push('"');
+            quoted
+        }
+    }
+}
+
+/// The rustc cfgs of kconfig items, for conditional compilation with e.g.
+/// `#[cfg(esp_idf_bt_enabled)]` or `#[cfg(esp_idf_idf_target = "esp32c3")]`.
+///
+/// Every item is named with [`cfg_name`] and set as follows:
+/// - an enabled bool or tristate item as `<name>`, disabled ones are not set;
+/// - an integer or string item as `<name>="<value>"` (hexadecimal integers as
+///   `"0x..."`).
+///
+/// All items (including disabled ones) are also declared with `rustc-check-cfg`, so that
+/// rustc doesn't warn about unexpected cfgs.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CfgArgs {
+    items: Vec<(String, Value)>,
+}
+
+impl CfgArgs {
+    /// Get the cfgs of the kconfig `items` (e.g. [`Parsed::entries`]), named with
+    /// `prefix` (see [`cfg_name`]).
+    ///
+    /// Items set multiple times keep their last value. Fails if different items map to
+    /// the same cfg name.
+    pub fn new(
+        prefix: impl AsRef<str>,
+        items: impl IntoIterator<Item = (impl AsRef<str>, Value)>,
+    ) -> Result<Self> {
+        let prefix = prefix.as_ref();
+        let mut names = HashMap::<String, String>::new();
+        let mut cfgs = Self::default();
+
+        for (item, value) in items {
+            let item = item.as_ref();
+            let name = cfg_name(prefix, item);
+            match names.get(&name) {
+                Some(other) if other != item => {
+                    bail!("kconfig items '{other}' and '{item}' both map to the cfg '{name}'")
+                }
+                Some(_) => {
+                    if let Some(entry) = cfgs.items.iter_mut().find(|(n, _)| *n == name) {
+                        entry.1 = value;
+                    }
+                }
+                None => {
+                    names.insert(name.clone(), item.to_owned());
+                    cfgs.items.push((name, value));
+                }
+            }
+        }
+
+        Ok(cfgs)
+    }
+
+    /// Get the cfg names and values of all items.
+    pub fn items(&self) -> &[(String, Value)] {
+        &self.items
+    }
+
+    /// Get the value of the cfg `name`.
+    pub fn get(&self, name: impl AsRef<str>) -> Option<&Value> {
+        let name = name.as_ref();
+        self.items
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Get the cfgs to set (the arguments of `--cfg`).
+    pub fn rustc_cfgs(&self) -> Vec<String> {
+        self.items
+            .iter()
+            .filter_map(|(name, value)| {
+                let value = match value {
+                    Value::Bool(_) | Value::Tristate(_) => {
+                        return value.as_bool().unwrap_or(false).then(|| name.clone())
+                    }
+                    Value::Int(i) => i.to_string(),
+                    Value::Hex(h) => format!("{h:#x}"),
+                    Value::String(s) => s.clone(),
+                };
+                Some(format!("{name}=\"{}\"", value.escape_debug()))
+            })
+            .collect()
+    }
+
+    /// Get the declarations of all cfgs (the arguments of `--check-cfg`).
+    pub fn check_cfgs(&self) -> Vec<String> {
+        self.items
+            .iter()
+            .map(|(name, value)| match value {
+                Value::Bool(_) | Value::Tristate(_) => format!("cfg({name})"),
+                _ => format!("cfg({name}, values(any()))"),
+            })
+            .collect()
+    }
+
+    /// Add the cfgs and their declarations to `output`.
+    pub fn output(&self, output: &mut cargo::Output) {
+        for cfg in self.rustc_cfgs() {
+            output.rustc_cfg(cfg);
+        }
+        for check_cfg in self.check_cfgs() {
+            output.rustc_check_cfg(check_cfg);
+        }
+    }
+
+    /// Set the cfgs and declare them for this package.
+    pub fn emit(&self) -> Result<()> {
+        let mut output = cargo::Output::new();
+        self.output(&mut output);
+        output.emit()
+    }
+
+    /// Write the items (with their value types) as JSON to `path`.
+    ///
+    /// The file contains a schema version, so that [`CfgArgs::read_from`] of another
+    /// embuild version with an incompatible format fails instead of misreading it.
+    pub fn write_to(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let json = serde_json::to_string_pretty(&serde_json::json!({
+            "version": CFG_ARGS_SCHEMA_VERSION,
+            "items": self.items,
+        }))?;
+        crate::fs::write_if_changed(path, json)?;
+        Ok(())
+    }
+
+    /// Read the items written by [`CfgArgs::write_to`] from `path`.
+    pub fn read_from(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let context = || format!("could not read the kconfig cfgs of '{}'", path.display());
+
+        let json = fs::read_to_string(path).with_context(context)?;
+        let mut json: serde_json::Value = serde_json::from_str(&json).with_context(context)?;
+        match json.get("version").and_then(serde_json::Value::as_u64) {
+            Some(version) if version == u64::from(CFG_ARGS_SCHEMA_VERSION) => (),
+            version => {
+                return Err(anyhow::anyhow!(
+                    "the file has schema version {}, but this embuild version only \
+                     supports version {CFG_ARGS_SCHEMA_VERSION} (are different embuild \
+                     versions used in the dependency graph?)",
+                    version.map_or("<none>".into(), |v| v.to_string())
+                ))
+                .with_context(context)
+            }
+        }
+
+        let items = serde_json::from_value(json["items"].take()).with_context(context)?;
+        Ok(Self { items })
+    }
+
+    /// Propagate the cfgs (with their values) to all dependents of this crate, by
+    /// writing them to a file in `OUT_DIR` (see [`CfgArgs::write_to`]) and exporting its
+    /// path.
+    ///
+    /// ### **Important**
+    /// Calling this method in a dependency doesn't do anything on itself. All dependents
+    /// that want to have these cfgs set must call [`CfgArgs::from_dep`] (and
+    /// [`CfgArgs::emit`]) in their build script with the value of this crate's `links`
+    /// property (specified in `Cargo.toml`).
+    pub fn propagate(&self) -> Result<()> {
+        let file = cargo::out_dir().join("kconfig-cfgs.json");
+        self.write_to(&file)?;
+        DepVarsWriter::new().path(KCONFIG_CFGS_FILE_VAR, &file)?;
+        Ok(())
+    }
+
+    /// Read the cfgs propagated by the dependency with the `links` property value
+    /// `links` using [`propagate`](CfgArgs::propagate), and rerun the build script if
+    /// they change.
+    pub fn from_dep(links: impl AsRef<str>) -> Result<Self> {
+        let file = DepVars::from_links(links).path(KCONFIG_CFGS_FILE_VAR)?;
+        cargo::track_file(&file);
+        Self::read_from(file)
+    }
+
+    /// Set and declare the cfgs propagated by the dependency with the `links` property
+    /// value `links` (see [`CfgArgs::from_dep`]).
+    pub fn output_propagated(links: impl AsRef<str>) -> Result<()> {
+        Self::from_dep(links)?.emit()
+    }
+}
+
+/// Get the cfg name of the kconfig item `item` with `prefix`: `<prefix>_<item>`, with
+/// the `CONFIG_` prefix of `item` removed, lowercased and with all characters that
+/// aren't valid in identifiers replaced with `_`.
+///
+/// For example, `CONFIG_BT_ENABLED` with the prefix `esp_idf` is `esp_idf_bt_enabled`.
+pub fn cfg_name(prefix: &str, item: &str) -> String {
+    let item = item.strip_prefix("CONFIG_").unwrap_or(item);
+    let name = if prefix.is_empty() {
+        item.to_owned()
+    } else {
+        format!("{prefix}_{item}")
+    };
+
+    let mut name = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        name.insert(0, '_');
+    }
+    name
+}
+
+/// Options for the constants generated by [`generate_module`].
+#[derive(Clone, Debug)]
+#[must_use]
+pub struct ModuleOptions {
+    source: Option<PathBuf>,
+    strip_prefix: bool,
+    keep_case: bool,
+}
+
+impl Default for ModuleOptions {
+    fn default() -> Self {
+        Self {
+            source: None,
+            strip_prefix: true,
+            keep_case: true,
+        }
+    }
+}
+
+impl ModuleOptions {
+    /// Name the constants like the kconfig items without their `CONFIG_` prefix.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the kconfig file `path` the values were parsed from (and the hash of its
+    /// content) in the header of the module.
+    pub fn source(mut self, path: impl Into<PathBuf>) -> Self {
+        self.source = Some(path.into());
+        self
+    }
+
+    /// Whether the `CONFIG_` prefix of the items is removed from the constant names
+    /// (`true` by default).
+    pub fn strip_prefix(mut self, strip_prefix: bool) -> Self {
+        self.strip_prefix = strip_prefix;
+        self
+    }
+
+    /// Whether the constant names keep the case of the items, or are uppercased (`true`
+    /// by default).
+    pub fn keep_case(mut self, keep_case: bool) -> Self {
+        self.keep_case = keep_case;
+        self
+    }
+
+    /// Get the constant name of the kconfig item `item`, with all characters that aren't
+    /// valid in identifiers replaced with `_`.
+    fn const_name(&self, item: &str) -> String {
+        let item = match item.strip_prefix("CONFIG_") {
+            Some(stripped) if self.strip_prefix => stripped,
+            _ => item,
+        };
+
+        let mut name = item
+            .chars()
+            .map(|c| match c {
+                c if c.is_ascii_alphanumeric() || c == '_' => c,
+                _ => '_',
+            })
+            .collect::<String>();
+        if !self.keep_case {
+            name.make_ascii_uppercase();
+        }
+        if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+            name.insert(0, '_');
+        }
+        name
+    }
+}
+
+/// Get the default file of the module generated by [`generate_module`] and set the
+/// environment variable named [`VAR_KCONFIG_FILE`] that is available during crate
+/// compilation to that path.
+///
+/// The module can then be included with
+/// `include!(env!("EMBUILD_GENERATED_KCONFIG_FILE"))`.
+pub fn default_module_file() -> Result<PathBuf> {
+    let module_file = cargo::out_dir().join("kconfig.rs");
+    cargo::set_rustc_env(
+        VAR_KCONFIG_FILE,
+        crate::utils::OsStrExt::try_to_str(module_file.as_os_str())?,
+    );
+    Ok(module_file)
+}
+
+/// Generate a rust module with a constant for every kconfig item in `items` into
+/// `out_file` and run `cargo fmt` over that file (see [`cargo::cargo_fmt_file`]).
+///
+/// The constants have the types:
+/// - `bool` for bool and tristate items ([`Tristate::Module`] is `true`);
+/// - `u32` for integers that fit into it and `i64` (or `u64` for hexadecimal items)
+///   otherwise, with hexadecimal items as hexadecimal literals;
+/// - `&str` for strings.
+///
+/// Items set multiple times keep their last value. Fails if different items map to the
+/// same constant name.
+pub fn generate_module(
+    items: impl IntoIterator<Item = (impl AsRef<str>, Value)>,
+    out_file: impl AsRef<Path>,
+    options: &ModuleOptions,
+) -> Result<()> {
+    use std::hash::Hasher;
+
+    let out_file = out_file.as_ref();
+
+    let mut names = HashMap::<String, String>::new();
+    let mut consts = Vec::<(String, Value)>::new();
+    for (item, value) in items {
+        let item = item.as_ref();
+        let name = options.const_name(item);
+        match names.get(&name) {
+            Some(other) if other != item => {
+                bail!("kconfig items '{other}' and '{item}' both map to the constant '{name}'")
+            }
+            Some(_) => {
+                if let Some(entry) = consts.iter_mut().find(|(n, _)| *n == name) {
+                    entry.1 = value;
+                }
+            }
+            None => {
+                names.insert(name.clone(), item.to_owned());
+                consts.push((name, value));
+            }
+        }
+    }
+
+    let mut module = String::new();
+    match &options.source {
+        Some(source) => {
+            let content = fs::read(source)
+                .with_context(|| format!("could not read '{}'", source.display()))?;
+            let mut hasher = crate::utils::StableHasher::new();
+            hasher.write(&content);
+            module.push_str(&format!(
+                "// Generated by embuild from the kconfig file '{}' (hash {}).\n",
+                source.display().to_string().escape_debug(),
+                hasher.finish_hex()
+            ));
+        }
+        None => module.push_str("// Generated by embuild from kconfig items.\n"),
+    }
+    module.push_str("// Do not edit, changes will be overwritten.\n\n");
+
+    for (name, value) in &consts {
+        let (ty, literal) = match value {
+            Value::Bool(_) | Value::Tristate(_) => {
+                ("bool", value.as_bool().unwrap_or(false).to_string())
+            }
+            Value::Int(i) if u32::try_from(*i).is_ok() => ("u32", i.to_string()),
+            Value::Int(i) => ("i64", i.to_string()),
+            Value::Hex(h) if u32::try_from(*h).is_ok() => ("u32", format!("{h:#x}")),
+            Value::Hex(h) => ("u64", format!("{h:#x}")),
+            Value::String(s) => ("&str", format!("\"{}\"", s.escape_debug())),
+        };
+        if name.chars().any(|c| c.is_ascii_lowercase()) {
+            module.push_str("#[allow(non_upper_case_globals)]\n");
+        }
+        module.push_str(&format!("pub const {name}: {ty} = {literal};\n"));
+    }
+
+    fs::write(out_file, module)
+        .with_context(|| format!("could not write '{}'", out_file.display()))?;
+    cargo::cargo_fmt_file(out_file);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SDKCONFIG: &str = "#\r\n\
+        # Automatically generated file. DO NOT EDIT.\r\n\
+        #\r\n\
+        CONFIG_IDF_TARGET=\"esp32c3\"\r\n\
+        CONFIG_FREERTOS_HZ=1000\n\
+        CONFIG_PARTITION_TABLE_OFFSET=0x8000\n\
+        CONFIG_APP_NAME=\"say \\\"hi\\\" \\\\ \\\n\
+        bye\"\n\
+        # CONFIG_BT_ENABLED is not set\n\
+        CONFIG_ESP_CONSOLE_UART=y\n\
+        CONFIG_DRIVER=m\n\
+        CONFIG_OFFSET=-12\n\
+        CONFIG_BROKEN\n\
+        CONFIG_UNQUOTED=abc\n\
+        CONFIG_FREERTOS_HZ=100";
+
+    #[test]
+    fn test_parse() {
+        let parsed = Parsed::parse(SDKCONFIG);
+
+        assert_eq!(
+            parsed.entries(),
+            [
+                ("CONFIG_IDF_TARGET".into(), Value::String("esp32c3".into())),
+                ("CONFIG_FREERTOS_HZ".into(), Value::Int(1000)),
+                ("CONFIG_PARTITION_TABLE_OFFSET".into(), Value::Hex(0x8000)),
+                (
+                    "CONFIG_APP_NAME".into(),
+                    Value::String(r#"say "hi" \ bye"#.into())
+                ),
+                ("CONFIG_BT_ENABLED".into(), Value::Bool(false)),
+                ("CONFIG_ESP_CONSOLE_UART".into(), Value::Bool(true)),
+                ("CONFIG_DRIVER".into(), Value::Tristate(Tristate::Module)),
+                ("CONFIG_OFFSET".into(), Value::Int(-12)),
+                ("CONFIG_FREERTOS_HZ".into(), Value::Int(100)),
+            ]
+        );
+        assert_eq!(
+            parsed.warnings().iter().map(|w| w.line).collect::<Vec<_>>(),
+            [13, 14]
+        );
+        assert!(parsed.warnings()[1].to_string().contains("CONFIG_UNQUOTED"));
+
+        assert_eq!(parsed.get_int("CONFIG_FREERTOS_HZ"), Some(100));
+        assert_eq!(
+            parsed.get_hex("CONFIG_PARTITION_TABLE_OFFSET"),
+            Some(0x8000)
+        );
+        assert_eq!(
+            parsed.get_int("CONFIG_PARTITION_TABLE_OFFSET"),
+            Some(0x8000)
+        );
+        assert_eq!(parsed.get_bool("CONFIG_BT_ENABLED"), Some(false));
+        assert_eq!(parsed.get_bool("CONFIG_DRIVER"), Some(true));
+        assert_eq!(parsed.get_str("CONFIG_IDF_TARGET"), Some("esp32c3"));
+        assert_eq!(parsed.get_str("CONFIG_FREERTOS_HZ"), None);
+        assert_eq!(parsed.get("CONFIG_MISSING"), None);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut parsed = Parsed::parse(SDKCONFIG);
+        assert_eq!(parsed.to_string(), SDKCONFIG);
+
+        parsed.set("CONFIG_BT_ENABLED", Value::Bool(true));
+        parsed.set("CONFIG_APP_NAME", Value::String(r#"a "b" \c"#.into()));
+        parsed.set("CONFIG_NEW_OFFSET", Value::Hex(0x10000));
+        let text = parsed.to_string();
+        assert!(text.contains("CONFIG_BT_ENABLED=y\n"));
+        assert!(text.contains("CONFIG_APP_NAME=\"a \\\"b\\\" \\\\c\"\n"));
+        assert!(text.ends_with("CONFIG_FREERTOS_HZ=100\nCONFIG_NEW_OFFSET=0x10000\n"));
+        assert!(text.starts_with("#\r\n# Automatically generated file."));
+
+        let reparsed = Parsed::parse(&text);
+        assert_eq!(reparsed.get_str("CONFIG_APP_NAME"), Some(r#"a "b" \c"#));
+        assert_eq!(reparsed.get_bool("CONFIG_BT_ENABLED"), Some(true));
+    }
+
+    #[test]
+    fn test_cfg_args() {
+        let parsed = Parsed::parse(SDKCONFIG);
+        let cfgs = CfgArgs::new("esp_idf", parsed.entries().iter().cloned()).unwrap();
+
+        assert_eq!(
+            cfgs.rustc_cfgs(),
+            [
+                r#"esp_idf_idf_target="esp32c3""#,
+                r#"esp_idf_freertos_hz="100""#,
+                r#"esp_idf_partition_table_offset="0x8000""#,
+                r#"esp_idf_app_name="say \"hi\" \\ bye""#,
+                "esp_idf_esp_console_uart",
+                "esp_idf_driver",
+                r#"esp_idf_offset="-12""#,
+            ]
+        );
+        assert_eq!(
+            cfgs.check_cfgs()[..5],
+            [
+                "cfg(esp_idf_idf_target, values(any()))",
+                "cfg(esp_idf_freertos_hz, values(any()))",
+                "cfg(esp_idf_partition_table_offset, values(any()))",
+                "cfg(esp_idf_app_name, values(any()))",
+                "cfg(esp_idf_bt_enabled)",
+            ]
+        );
+        assert_eq!(cfgs.get("esp_idf_freertos_hz"), Some(&Value::Int(100)));
+
+        assert_eq!(cfg_name("", "CONFIG_9LIVES"), "_9lives");
+        assert_eq!(cfg_name("Esp-Idf", "A.B"), "esp_idf_a_b");
+
+        let error = CfgArgs::new(
+            "p",
+            [
+                ("CONFIG_A_B", Value::Bool(true)),
+                ("CONFIG_A-B", Value::Bool(true)),
+            ],
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("'CONFIG_A_B' and 'CONFIG_A-B'"));
+    }
+
+    #[test]
+    fn test_generate_module() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let sdkconfig = dir.join("sdkconfig");
+        fs::write(
+            &sdkconfig,
+            "CONFIG_FREERTOS_HZ=1000\n\
+             CONFIG_PARTITION_TABLE_OFFSET=0x8000\n\
+             CONFIG_FLASH_SIZE=0x100000000\n\
+             CONFIG_OFFSET=-12\n\
+             CONFIG_BT_ENABLED=y\n\
+             # CONFIG_LOG_COLORS is not set\n\
+             CONFIG_APP_NAME=\"say \\\"grüß dich\\\" \\\\ 🦀\"\n\
+             CONFIG_lower=\"x\"\n",
+        )
+        .unwrap();
+        let out_file = dir.join("kconfig.rs");
+
+        let parsed = Parsed::from_file(&sdkconfig).unwrap();
+        generate_module(
+            parsed.entries().iter().cloned(),
+            &out_file,
+            &ModuleOptions::new().source(&sdkconfig),
+        )
+        .unwrap();
+
+        let module = fs::read_to_string(&out_file).unwrap();
+        assert!(module.starts_with(&format!(
+            "// Generated by embuild from the kconfig file '{}' (hash ",
+            sdkconfig.display()
+        )));
+        for line in [
+            "pub const FREERTOS_HZ: u32 = 1000;",
+            "pub const PARTITION_TABLE_OFFSET: u32 = 0x8000;",
+            "pub const FLASH_SIZE: u64 = 0x100000000;",
+            "pub const OFFSET: i64 = -12;",
+            "pub const BT_ENABLED: bool = true;",
+            "pub const LOG_COLORS: bool = false;",
+            r#"pub const APP_NAME: &str = "say \"grüß dich\" \\ 🦀";"#,
+            "#[allow(non_upper_case_globals)]\npub const lower: &str = \"x\";",
+        ] {
+            assert!(module.contains(line), "'{line}' missing in:\n{module}");
+        }
+
+        generate_module(
+            parsed.entries().iter().cloned(),
+            &out_file,
+            &ModuleOptions::new().strip_prefix(false).keep_case(false),
+        )
+        .unwrap();
+        let module = fs::read_to_string(&out_file).unwrap();
+        assert!(module.contains("pub const CONFIG_LOWER: &str = \"x\";"));
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = Parsed::parse(
+            "CONFIG_A=y\nCONFIG_B=4096\nCONFIG_C=\"c\"\n# CONFIG_D is not set\nCONFIG_E=1\nCONFIG_E=2\n",
+        );
+        let new =
+            Parsed::parse("CONFIG_F=m\nCONFIG_E=2\nCONFIG_C=\"x\"\nCONFIG_B=0x1000\nCONFIG_A=y\n");
+
+        let changes = diff(&old, &new);
+        assert_eq!(
+            changes,
+            [
+                Change::Changed {
+                    name: "CONFIG_C".into(),
+                    old: Value::String("c".into()),
+                    new: Value::String("x".into())
+                },
+                Change::Removed {
+                    name: "CONFIG_D".into(),
+                    value: Value::Bool(false)
+                },
+                Change::Added {
+                    name: "CONFIG_F".into(),
+                    value: Value::Tristate(Tristate::Module)
+                },
+            ]
+        );
+        assert_eq!(
+            changes.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            [
+                "~ CONFIG_C=\"x\" (was \"c\")",
+                "- # CONFIG_D is not set",
+                "+ CONFIG_F=m"
+            ]
+        );
+        assert_eq!(diff(&new, &new), []);
+    }
+
+    #[test]
+    fn test_cfg_args_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let file = dir.join("cfgs.json");
+
+        let cfgs = CfgArgs::new("esp_idf", Parsed::parse(SDKCONFIG).into_entries()).unwrap();
+        cfgs.write_to(&file).unwrap();
+        assert_eq!(CfgArgs::read_from(&file).unwrap(), cfgs);
+        assert_eq!(
+            CfgArgs::read_from(&file)
+                .unwrap()
+                .get("esp_idf_partition_table_offset"),
+            Some(&Value::Hex(0x8000))
+        );
+
+        let json = fs::read_to_string(&file)
+            .unwrap()
+            .replace("\"version\": 1", "\"version\": 2");
+        fs::write(&file, json).unwrap();
+        let error = format!("{:#}", CfgArgs::read_from(&file).unwrap_err());
+        assert!(error.contains("schema version 2"), "{error}");
+
+        fs::write(&file, r#"{"items": []}"#).unwrap();
+        let error = format!("{:#}", CfgArgs::read_from(&file).unwrap_err());
+        assert!(error.contains("schema version <none>"), "{error}");
+    }
+}
@@ -35,10 +35,32 @@ pub mod symgen;
 #[cfg(feature = "elf")]
 pub mod bingen;
 
+#[cfg(feature = "diag")]
+pub mod diag;
+
+#[cfg(feature = "artifacts")]
+pub mod artifacts;
+
+#[cfg(feature = "cache")]
+pub mod cache;
+
+#[cfg(feature = "compile-commands")]
+pub mod compile_commands;
+
+#[cfg(feature = "size")]
+pub mod size;
+
+#[cfg(any(feature = "pio", feature = "cmake"))]
+pub mod builder;
+
+#[cfg(feature = "test-support")]
+pub mod test_support;
+
 pub mod build;
 pub mod cargo;
 pub mod cli;
 pub mod cmd;
 pub mod fs;
+pub mod logging;
 pub mod python;
 pub mod utils;
@@ -0,0 +1,275 @@
+//! Leveled logging.
+//!
+//! All modules of embuild log with the [`log`] crate, with their module path (e.g.
+//! `embuild::espidf`) as the target. Build scripts may set any logger (e.g. with
+//! `env_logger`), or call [`init`] to install a minimal stderr logger whose levels are
+//! set with [`LOG_VAR`]. embuild never installs a logger itself.
+//!
+//! Independent of the logger, the warnings of embuild are also printed as cargo
+//! warnings in build scripts, each distinct warning only once (see
+//! [`cargo_warning_once`]).
+
+use std::collections::HashSet;
+use std::env;
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+use crate::cargo;
+
+/// The environment variable setting the log levels of the logger installed by
+/// [`init`].
+///
+/// It is a comma-separated list of levels (`off`, `error`, `warn`, `info`, `debug` or
+/// `trace`), optionally prefixed with a target and `=`:
+/// - `debug` logs all records of embuild up to the debug level.
+/// - `embuild::pio=trace,warn` logs all records of the `embuild::pio` module (and its
+///   submodules) and only warnings and errors of the other modules.
+/// - `embuild::step=off` hides the progress of the build steps (see [`scoped_step`]).
+///
+/// Defaults to [`DEFAULT_LEVEL`] for embuild and to `warn` for the records of other
+/// crates.
+pub const LOG_VAR: &str = "EMBUILD_LOG";
+
+/// The log level of embuild if it is not set with [`LOG_VAR`].
+pub const DEFAULT_LEVEL: LevelFilter = LevelFilter::Info;
+
+/// The target of the records of [`scoped_step`].
+pub const STEP_TARGET: &str = "embuild::step";
+
+/// Log a warning with [`log::warn!`] and print it as a cargo warning once (see
+/// [`cargo_warning_once`]).
+macro_rules! warning {
+    ($($arg:tt)+) => {{
+        let message = format!($($arg)+);
+        ::log::warn!("{message}");
+        $crate::logging::cargo_warning_once(&message);
+    }};
+}
+pub(crate) use warning;
+
+/// Install the embuild stderr logger configured with [`LOG_VAR`], unless a logger is
+/// already set.
+///
+/// This is only done when called by the build script (e.g. first thing in `main`),
+/// so that it can set another logger instead.
+///
+/// Calling this more than once has no effect.
+pub fn init() {
+    static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+    let mut invalid = None;
+    let logger = LOGGER.get_or_init(|| {
+        let (filters, errors) = Filters::parse(&env::var(LOG_VAR).unwrap_or_default());
+        invalid = Some(errors);
+        Logger { filters }
+    });
+    // Only the first call initializes the logger.
+    let Some(invalid) = invalid else {
+        return;
+    };
+
+    if log::set_logger(logger).is_ok() {
+        log::set_max_level(logger.filters.max_level());
+    }
+    for directive in invalid {
+        warning!("Ignoring invalid directive '{directive}' of `{LOG_VAR}`");
+    }
+}
+
+/// Print `warning` as a cargo warning if this is a build script and it wasn't printed
+/// before, and get whether it was printed.
+pub fn cargo_warning_once(warning: impl Display) -> bool {
+    if env::var_os("OUT_DIR").is_none() {
+        return false;
+    }
+
+    let warning = warning.to_string();
+    let first = first_occurrence(&warning);
+    if first {
+        cargo::print_warning(warning);
+    }
+    first
+}
+
+/// Whether `warning` is seen for the first time.
+fn first_occurrence(warning: &str) -> bool {
+    static SEEN: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+
+    SEEN.lock()
+        .unwrap()
+        .get_or_insert_with(HashSet::new)
+        .insert(warning.to_owned())
+}
+
+/// Log the start of the step `name` of the build (e.g. `"Installing esp-idf tools"`),
+/// and its end with the elapsed time once the returned [`Step`] is finished or dropped.
+///
+/// The records have the [`STEP_TARGET`] and the info level.
+pub fn scoped_step(name: impl Into<String>) -> Step {
+    let name = name.into();
+    log::info!(target: STEP_TARGET, "{name}...");
+    Step {
+        name,
+        start: Instant::now(),
+        finished: false,
+    }
+}
+
+/// A step of the build started with [`scoped_step`].
+///
+/// Dropping it without [`Step::finish`] (e.g. because of an error) logs that the step
+/// failed.
+#[derive(Debug)]
+#[must_use = "the step ends when it is dropped"]
+pub struct Step {
+    name: String,
+    start: Instant,
+    finished: bool,
+}
+
+impl Step {
+    /// Log that the step finished successfully.
+    pub fn finish(mut self) {
+        self.finished = true;
+    }
+}
+
+impl Drop for Step {
+    fn drop(&mut self) {
+        let (name, elapsed) = (&self.name, self.start.elapsed());
+        if self.finished {
+            log::info!(target: STEP_TARGET, "{name} finished in {elapsed:.1?}");
+        } else {
+            log::info!(target: STEP_TARGET, "{name} failed after {elapsed:.1?}");
+        }
+    }
+}
+
+/// The log levels parsed from [`LOG_VAR`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Filters {
+    /// The targets with their levels, the most specific one first.
+    targets: Vec<(String, LevelFilter)>,
+    /// The level of all other targets.
+    default: LevelFilter,
+}
+
+impl Filters {
+    /// Parse the directives of `spec`, and get the invalid ones.
+    fn parse(spec: &str) -> (Self, Vec<String>) {
+        let mut embuild = DEFAULT_LEVEL;
+        let mut targets = Vec::new();
+        let mut invalid = Vec::new();
+
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let (target, level) = match directive.split_once('=') {
+                Some((target, level)) => (Some(target.trim()), level.trim()),
+                None => (None, directive),
+            };
+            match (target, level.parse::<LevelFilter>()) {
+                (Some(target), Ok(level)) if !target.is_empty() => {
+                    targets.push((target.to_owned(), level))
+                }
+                (None, Ok(level)) => embuild = level,
+                _ => invalid.push(directive.to_owned()),
+            }
+        }
+
+        if !targets.iter().any(|(target, _)| target == "embuild") {
+            targets.push(("embuild".to_owned(), embuild));
+        }
+        // Later directives for the same target win.
+        targets.reverse();
+        targets.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        targets.dedup_by(|(a, _), (b, _)| a == b);
+
+        let filters = Self {
+            targets,
+            default: LevelFilter::Warn,
+        };
+        (filters, invalid)
+    }
+
+    /// The level of `target`.
+    fn level(&self, target: &str) -> LevelFilter {
+        self.targets
+            .iter()
+            .find(|(prefix, _)| {
+                target
+                    .strip_prefix(prefix.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    /// The most verbose level of all targets.
+    fn max_level(&self) -> LevelFilter {
+        self.targets
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, Ord::max)
+    }
+}
+
+/// The stderr logger installed by [`init`].
+struct Logger {
+    filters: Filters,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filters.level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("[{} {}] {}", record.level(), record.target(), record.args());
+        }
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filters() {
+        let (filters, invalid) = Filters::parse("");
+        assert!(invalid.is_empty());
+        assert_eq!(filters.level("embuild"), DEFAULT_LEVEL);
+        assert_eq!(filters.level("embuild::espidf"), DEFAULT_LEVEL);
+        assert_eq!(filters.level("embuildx"), LevelFilter::Warn);
+        assert_eq!(filters.level("bindgen"), LevelFilter::Warn);
+        assert_eq!(filters.max_level(), DEFAULT_LEVEL);
+
+        let (filters, invalid) = Filters::parse(
+            "debug, embuild::pio=trace, embuild::step=off, bindgen=error, loud, =info, embuild::pio=info",
+        );
+        assert_eq!(invalid, ["loud", "=info"]);
+        assert_eq!(filters.level("embuild::cmake"), LevelFilter::Debug);
+        assert_eq!(filters.level("embuild::pio"), LevelFilter::Info);
+        assert_eq!(filters.level("embuild::pio::project"), LevelFilter::Info);
+        assert_eq!(filters.level(STEP_TARGET), LevelFilter::Off);
+        assert_eq!(filters.level("bindgen::ir"), LevelFilter::Error);
+        assert_eq!(filters.max_level(), LevelFilter::Debug);
+
+        let (filters, _) = Filters::parse("embuild=trace,off");
+        assert_eq!(filters.level("embuild::git"), LevelFilter::Trace);
+        assert_eq!(filters.max_level(), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn test_first_occurrence() {
+        assert!(first_occurrence("test warning"));
+        assert!(!first_occurrence("test warning"));
+        assert!(first_occurrence("test warning 2"));
+    }
+}
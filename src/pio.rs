@@ -3,6 +3,7 @@
 
 pub mod project;
 
+use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet};
 use std::convert::{TryFrom, TryInto};
 use std::ffi::OsStr;
@@ -10,33 +11,55 @@ use std::fs::{self, File};
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::process::{Command, Output, Stdio};
+use std::str::FromStr;
+use std::time::Duration;
+use std::{env, fmt};
 
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use log::*;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use tempfile::*;
 
+use crate::artifacts::{self, ArtifactKind};
+use crate::cmd::{Cmd, CmdError, OutputClassifier};
+use crate::logging::{self, warning};
 use crate::python::{check_python_at_least, PYTHON};
-use crate::utils;
+use crate::{cmd, path_buf, utils};
 
 const INSTALLER_URL: &str = "https://raw.githubusercontent.com/platformio/platformio-core-installer/master/get-platformio.py";
 const INSTALLER_BLOB: &[u8] = include_bytes!("pio/resources/get-platformio.py.resource");
 
+/// How often downloading `get-platformio.py` is tried on network errors.
+const INSTALLER_DOWNLOAD_ATTEMPTS: u32 = 3;
+
+/// The wait before the first retry of a failed `get-platformio.py` download.
+const INSTALLER_DOWNLOAD_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The timeout of a single `get-platformio.py` download.
+const INSTALLER_DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The environment variable overriding the requested PlatformIO Core version (see
+/// [`PioInstaller::version`]).
+pub const VAR_PIO_VERSION: &str = "PIO_VERSION";
+
+/// The timeout of PlatformIO commands queried for JSON output.
+///
+/// Generous, as e.g. `pio boards` might first need to fetch the package index.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// The maximum number of suggestions listed for an unknown board.
+const MAX_SUGGESTIONS: usize = 5;
+
 /// The logging verbosity level when executing platformio.
-#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
 pub enum LogLevel {
     Quiet,
+    #[default]
     Standard,
     Verbose,
 }
 
-impl Default for LogLevel {
-    fn default() -> Self {
-        Self::Standard
-    }
-}
-
 /// A platformio platform defintion.
 #[deprecated(
     since = "0.31.2",
@@ -120,6 +143,7 @@ pub struct LibraryFrameworkOrPlatformRef {
     pub title: String,
 }
 
+/// A board definition, as returned by `pio boards --json-output`.
 #[derive(Serialize, Deserialize, Default, Clone, Debug)]
 pub struct Board {
     pub id: String,
@@ -151,6 +175,93 @@ pub struct FrameworkFromBoards {
     pub platforms: Vec<String>,
 }
 
+/// A PlatformIO Core version, e.g. `6.1.15` or `6.1.16rc1`.
+///
+/// Versions are ordered by their numeric components; a pre-release (e.g. `6.1.16rc1`)
+/// is ordered before the release with the same numeric components.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PioVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+    /// The pre-release or development suffix (e.g. `rc1`, `a2` or `dev3`), if any.
+    pub pre: Option<String>,
+}
+
+impl PioVersion {
+    pub fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+            pre: None,
+        }
+    }
+}
+
+impl FromStr for PioVersion {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let numeric_len = s
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(s.len());
+        let (numeric, pre) = s.split_at(numeric_len);
+        let numeric = numeric.trim_end_matches('.');
+
+        let components = numeric
+            .split('.')
+            .map(u32::from_str)
+            .collect::<Result<Vec<_>, _>>()
+            .ok()
+            .filter(|c| (1..=3).contains(&c.len()))
+            .ok_or_else(|| {
+                anyhow!("Invalid PlatformIO version '{s}'; expected '<major>[.<minor>[.<patch>]][<pre>]'")
+            })?;
+
+        let pre = pre.trim_start_matches(['.', '-']);
+
+        Ok(Self {
+            major: components[0],
+            minor: components.get(1).copied().unwrap_or_default(),
+            patch: components.get(2).copied().unwrap_or_default(),
+            pre: (!pre.is_empty()).then(|| pre.to_owned()),
+        })
+    }
+}
+
+impl fmt::Display for PioVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+
+        if let Some(pre) = &self.pre {
+            write!(f, "{pre}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Ord for PioVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.major, self.minor, self.patch)
+            .cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| match (&self.pre, &other.pre) {
+                (None, None) => Ordering::Equal,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(_), None) => Ordering::Less,
+                (Some(pre), Some(other_pre)) => pre.cmp(other_pre),
+            })
+    }
+}
+
+impl PartialOrd for PioVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct PioInstallerInfo {
     pub is_develop_core: bool,
@@ -201,6 +312,13 @@ pub struct Pio {
     pub log_level: LogLevel,
 }
 
+impl PioInstallerInfo {
+    /// The parsed [`core_version`](Self::core_version).
+    pub fn parsed_core_version(&self) -> Result<PioVersion> {
+        self.core_version.parse()
+    }
+}
+
 impl From<PioInstallerInfo> for Pio {
     fn from(pi: PioInstallerInfo) -> Self {
         Self {
@@ -226,6 +344,20 @@ impl Pio {
         pio_dir: Option<impl AsRef<Path>>,
         log_level: LogLevel,
         download: bool,
+    ) -> Result<Self> {
+        Self::install_version(pio_dir, log_level, download, None)
+    }
+
+    /// Install PlatformIO, pinning the PlatformIO Core to `version` (unless overridden
+    /// with the [`VAR_PIO_VERSION`] environment variable).
+    ///
+    /// Fails if an existing installation has a different core version; use
+    /// [`PioInstaller::allow_upgrade`] to replace it instead.
+    pub fn install_version(
+        pio_dir: Option<impl AsRef<Path>>,
+        log_level: LogLevel,
+        download: bool,
+        version: Option<&str>,
     ) -> Result<Self> {
         let mut pio_installer = if download {
             PioInstaller::new_download()?
@@ -247,7 +379,11 @@ impl Pio {
             pio_installer.pio(pio_dir);
         }
 
-        pio_installer.update().map(Into::into)
+        if let Some(version) = version {
+            pio_installer.version(version.parse()?);
+        }
+
+        pio_installer.update()
     }
 
     pub fn install_default() -> Result<Self> {
@@ -291,13 +427,36 @@ impl Pio {
     }
 
     pub fn try_from_env() -> Option<Self> {
-        let mut cmd = Command::new("platformio");
-
-        Self::json::<PioInfo>(cmd.arg("system").arg("info"))
+        Self::query::<PioInfo>(cmd!("platformio", "system", "info"))
             .map(Pio::from)
             .ok()
     }
 
+    /// The version of the PlatformIO Core, as reported by `pio --version`.
+    pub fn version(&self) -> Result<PioVersion> {
+        let mut cmd = self.cmd();
+        cmd.arg("--version");
+
+        debug!("Running PlatformIO command {:?}", cmd);
+
+        let output = cmd.output()?;
+        Self::check(&output)?;
+
+        // E.g. `PlatformIO Core, version 6.1.15`
+        let stdout = String::from_utf8(output.stdout)?;
+        stdout
+            .split_whitespace()
+            .last()
+            .unwrap_or_default()
+            .parse()
+            .with_context(|| {
+                anyhow!(
+                    "Unexpected output from `pio --version`: '{}'",
+                    stdout.trim()
+                )
+            })
+    }
+
     #[must_use]
     pub fn log_level(mut self, log_level: LogLevel) -> Self {
         self.log_level = log_level;
@@ -317,6 +476,19 @@ impl Pio {
         Ok(())
     }
 
+    fn query_cmd(&self) -> Cmd {
+        cmd!(&self.platformio_exe; env=("PLATFORMIO_CORE_DIR", &self.core_dir))
+    }
+
+    /// Run `cmd` with `--json-output` and [`QUERY_TIMEOUT`], and deserialize its output.
+    fn query<T: DeserializeOwned>(mut cmd: Cmd) -> Result<T> {
+        cmd.arg("--json-output");
+        cmd.timeout(QUERY_TIMEOUT);
+        debug!("Running PlatformIO command {:?}", cmd.cmd);
+
+        Ok(serde_json::from_str::<T>(&cmd.stdout()?)?)
+    }
+
     pub fn cmd(&self) -> Command {
         let mut command = Command::new(&self.platformio_exe);
 
@@ -343,15 +515,123 @@ impl Pio {
         cmd
     }
 
+    /// Build the project at `project_path`, classifying its output with the default
+    /// [`OutputClassifier`].
+    ///
+    /// See [`Pio::build_with_classifier`].
     pub fn build(&self, project_path: impl AsRef<Path>, release: bool) -> Result<()> {
+        self.build_with_classifier(project_path, release, &OutputClassifier::default())
+    }
+
+    /// Build the project at `project_path`, streaming the output line by line to stderr
+    /// (unless the log level is [`LogLevel::Quiet`]).
+    ///
+    /// When running in a build script, the lines classified as errors or warnings by
+    /// `classifier` are also printed as cargo warnings. If the build fails (or reported
+    /// errors with [`OutputClassifier::fail_on_error`]), the error includes the last
+    /// lines of the output.
+    pub fn build_with_classifier(
+        &self,
+        project_path: impl AsRef<Path>,
+        release: bool,
+        classifier: &OutputClassifier,
+    ) -> Result<()> {
+        let project_path = project_path.as_ref();
+        let cmd = self.build_cmd(project_path, release);
+
+        let step = logging::scoped_step(format!(
+            "Building PlatformIO project '{}'",
+            project_path.display()
+        ));
+        self.exec_tee(cmd, classifier)
+            .context("PlatformIO build failed")
+            .map_err(crate::diag::record_error)?;
+        step.finish();
+
+        Self::record_artifacts(project_path, release)
+    }
+
+    /// Record the firmware and partition table built in the project at `project_path`
+    /// (see [`artifacts::record`]).
+    fn record_artifacts(project_path: &Path, release: bool) -> Result<()> {
+        let build_dir = path_buf![
+            project_path,
+            ".pio",
+            "build",
+            if release { "release" } else { "debug" }
+        ];
+        for (kind, file) in [
+            (ArtifactKind::Elf, "firmware.elf"),
+            (ArtifactKind::PartitionTable, "partitions.bin"),
+        ] {
+            let file = build_dir.join(file);
+            if file.is_file() {
+                artifacts::record(kind, file)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn build_cmd(&self, project_path: impl AsRef<Path>, release: bool) -> Command {
         let mut cmd = self.run_cmd();
 
         cmd.arg("-d")
             .arg(project_path.as_ref())
             .arg("-e")
             .arg(if release { "release" } else { "debug" });
+        cmd
+    }
+
+    /// Run the PlatformIO target `target` (e.g. `menuconfig`) of the `environment` (or
+    /// the default environments) of the project at `project_path`.
+    ///
+    /// If `interactive`, the command inherits the terminal of this process, as needed by
+    /// e.g. `menuconfig`; otherwise its output is handled like the output of
+    /// [`Pio::build`].
+    pub fn exec_target(
+        &self,
+        project_path: impl AsRef<Path>,
+        environment: Option<&str>,
+        target: &str,
+        interactive: bool,
+    ) -> Result<()> {
+        let mut cmd = self.run_cmd();
+
+        cmd.arg("-d")
+            .arg(project_path.as_ref())
+            .arg("-t")
+            .arg(target);
+        if let Some(environment) = environment {
+            cmd.arg("-e").arg(environment);
+        }
+
+        if interactive {
+            debug!("Running PlatformIO command: {:?}", cmd);
+
+            let status = cmd
+                .stdin(Stdio::inherit())
+                .stdout(Stdio::inherit())
+                .stderr(Stdio::inherit())
+                .status()
+                .map_err(|e| CmdError::no_run(&cmd, e))?;
 
-        self.exec(&mut cmd)
+            CmdError::status_into_result(status, &cmd, || None)
+                .with_context(|| anyhow!("Running PlatformIO target '{target}' failed"))
+        } else {
+            self.exec_tee(cmd, &OutputClassifier::default())
+                .with_context(|| anyhow!("Running PlatformIO target '{target}' failed"))
+        }
+    }
+
+    /// Run `cmd` with [`Cmd::tee`], reporting the lines classified by `classifier` and
+    /// streaming the output to stderr unless the log level is [`LogLevel::Quiet`].
+    fn exec_tee(&self, cmd: Command, classifier: &OutputClassifier) -> Result<(), CmdError> {
+        let mut cmd = Cmd::from(cmd);
+        cmd.output_classifier(classifier.clone());
+        if self.log_level == LogLevel::Quiet {
+            cmd.quiet();
+        }
+        cmd.tee(None).map(drop)
     }
 
     pub fn exec_with_args(&self, args: &[impl AsRef<OsStr>]) -> Result<()> {
@@ -376,7 +656,8 @@ impl Pio {
             cmd.stdout(Stdio::null());
         }
 
-        cmd.status()?;
+        let status = cmd.status().map_err(|e| CmdError::no_run(cmd, e))?;
+        CmdError::status_into_result(status, cmd, || None).context("PlatformIO command failed")?;
 
         Ok(())
     }
@@ -393,7 +674,7 @@ impl Pio {
     }
 
     pub fn boards(&self, id: Option<impl AsRef<str>>) -> Result<Vec<Board>> {
-        let mut cmd = self.cmd();
+        let mut cmd = self.query_cmd();
 
         cmd.arg("boards");
 
@@ -401,7 +682,7 @@ impl Pio {
             cmd.arg(search_str.as_ref());
         }
 
-        let result = Self::json::<Vec<Board>>(&mut cmd);
+        let result = Self::query::<Vec<Board>>(cmd);
 
         if let Some(search_str) = id {
             Ok(result?
@@ -413,6 +694,16 @@ impl Pio {
         }
     }
 
+    /// Get the board with the id `id`.
+    ///
+    /// If no such board is known to PlatformIO, the error lists the boards with the
+    /// most similar ids.
+    pub fn resolve_board(&self, id: &str) -> Result<Board> {
+        let boards = self.boards(Option::<&str>::None)?;
+
+        find_board(boards, id)
+    }
+
     pub fn frameworks_from_boards(&self) -> Result<Vec<FrameworkFromBoards>> {
         let mut frameworks = HashMap::new();
 
@@ -426,11 +717,7 @@ impl Pio {
                             platforms: vec![board.platform.clone()],
                         });
 
-                if !framework
-                    .platforms
-                    .iter()
-                    .any(|platform| *platform == board.platform)
-                {
+                if !framework.platforms.contains(&board.platform) {
                     framework.platforms.push(board.platform.clone());
                 }
             }
@@ -534,21 +821,43 @@ impl Pio {
     }
 }
 
+impl crate::build::ManifestSource for Pio {
+    /// The PlatformIO Core.
+    fn manifest_entries(&self) -> Result<Vec<crate::build::ManifestEntry>> {
+        Ok(vec![crate::build::ManifestEntry::new("platformio-core")
+            .version(self.version()?.to_string())
+            .path(&self.platformio_exe)])
+    }
+}
+
 #[derive(Debug)]
 pub struct PioInstaller {
     installer_location: PathBuf,
     _installer_temp: Option<TempPath>,
     pio_location: Option<PathBuf>,
+    version: Option<PioVersion>,
+    allow_upgrade: bool,
     silent: bool,
 }
 
 impl PioInstaller {
     pub fn new() -> Result<Self> {
-        Self::create(false)
+        Self::create(None)
     }
 
+    /// Create an installer with the latest `get-platformio.py` downloaded from the
+    /// PlatformIO Core installer repository.
     pub fn new_download() -> Result<Self> {
-        Self::create(true)
+        Self::create(Some(None))
+    }
+
+    /// Create an installer with the `get-platformio.py` downloaded from the PlatformIO
+    /// Core installer repository, verified against its hex `sha256` hash.
+    ///
+    /// The verified installer is kept in the temporary directory and reused by later
+    /// installers with the same hash.
+    pub fn new_download_sha256(sha256: &str) -> Result<Self> {
+        Self::create(Some(Some(sha256)))
     }
 
     pub fn new_location(installer_location: impl Into<PathBuf>) -> Result<Self> {
@@ -558,6 +867,8 @@ impl PioInstaller {
             installer_location: installer_location.into(),
             _installer_temp: None,
             pio_location: None,
+            version: None,
+            allow_upgrade: false,
             silent: false,
         })
     }
@@ -568,30 +879,83 @@ impl PioInstaller {
         self
     }
 
-    fn create(download: bool) -> Result<Self> {
-        check_python_at_least(3, 6)?;
+    /// Pin the PlatformIO Core to `version`.
+    ///
+    /// The [`VAR_PIO_VERSION`] environment variable, if set, overrides this version.
+    pub fn version(&mut self, version: PioVersion) -> &mut Self {
+        self.version = Some(version);
+        self
+    }
 
-        let mut file = NamedTempFile::new()?;
-        if download {
-            debug!("Downloading get-platformio.py from {}", INSTALLER_URL);
+    /// Allow [`update`](Self::update) to replace the PlatformIO Core of an existing
+    /// installation when it does not match the pinned [`version`](Self::version).
+    pub fn allow_upgrade(&mut self) -> &mut Self {
+        self.allow_upgrade = true;
+        self
+    }
 
-            utils::download_file_to(INSTALLER_URL, &mut file)?;
-        } else {
-            debug!("Using built-in get-platformio.py");
+    /// Create an installer with the built-in `get-platformio.py`, or the downloaded one
+    /// (optionally verified against its sha256 hash) if `download` is [`Some`].
+    fn create(download: Option<Option<&str>>) -> Result<Self> {
+        check_python_at_least(3, 6)?;
 
-            file.write_all(INSTALLER_BLOB)?;
-        }
+        let (installer_location, installer_temp) = match download {
+            Some(Some(sha256)) => {
+                let file = env::temp_dir().join(format!(
+                    "embuild-get-platformio-{}.py",
+                    &sha256[..sha256.len().min(16)]
+                ));
+                Self::download(&file, Some(sha256))?;
+                (file, None)
+            }
+            Some(None) => {
+                let temp_path = NamedTempFile::new()?.into_temp_path();
+                Self::download(&temp_path, None)?;
+                (temp_path.to_path_buf(), Some(temp_path))
+            }
+            None => {
+                debug!("Using built-in get-platformio.py");
 
-        let temp_path = file.into_temp_path();
+                let mut file = NamedTempFile::new()?;
+                file.write_all(INSTALLER_BLOB)?;
+                let temp_path = file.into_temp_path();
+                (temp_path.to_path_buf(), Some(temp_path))
+            }
+        };
 
         Ok(Self {
-            installer_location: temp_path.to_path_buf(),
-            _installer_temp: Some(temp_path),
+            installer_location,
+            _installer_temp: installer_temp,
             pio_location: None,
+            version: None,
+            allow_upgrade: false,
             silent: false,
         })
     }
 
+    /// Download `get-platformio.py` to `file`, retrying on network errors.
+    fn download(file: &Path, sha256: Option<&str>) -> Result<()> {
+        debug!("Downloading get-platformio.py from {}", INSTALLER_URL);
+
+        let agent = ureq::AgentBuilder::new()
+            .timeout(INSTALLER_DOWNLOAD_TIMEOUT)
+            .build();
+        let mut download = utils::Download::new(INSTALLER_URL).agent(agent);
+        if let Some(sha256) = sha256 {
+            download = download.sha256(sha256);
+        }
+
+        cmd::retrying(
+            INSTALLER_DOWNLOAD_ATTEMPTS,
+            INSTALLER_DOWNLOAD_BACKOFF,
+            |e: &anyhow::Error| cmd::is_network_error(&format!("{e:#}")),
+            |_| download.to_file(file),
+        )
+        .context("Downloading get-platformio.py failed")?;
+
+        Ok(())
+    }
+
     pub fn pio(&mut self, pio_location: impl Into<PathBuf>) -> &mut Self {
         let pio_location = pio_location.into();
 
@@ -602,16 +966,63 @@ impl PioInstaller {
     }
 
     pub fn update(&self) -> Result<Pio> {
-        if let Ok(pii) = self.check() {
-            info!("PlatformIO is up-to-date");
+        let version = self.requested_version()?;
+
+        let (pii, upgrade) = if let Ok(pii) = self.check() {
+            match &version {
+                Some(version) if pii.parsed_core_version()? != *version => {
+                    if !self.allow_upgrade {
+                        bail!(
+                            "PlatformIO Core {} is installed in '{}', but version {} was requested; allow upgrading to replace it",
+                            pii.core_version,
+                            pii.penv_dir.display(),
+                            version
+                        );
+                    }
+
+                    info!(
+                        "Replacing PlatformIO Core {} with the requested version {}",
+                        pii.core_version, version
+                    );
 
-            Ok(pii.into())
+                    (pii, true)
+                }
+                _ => {
+                    info!("PlatformIO is up-to-date");
+                    (pii, false)
+                }
+            }
         } else {
             info!("PlatformIO needs to be installed or updated");
 
             self.install()?;
-            Ok(self.check()?.into())
+            let pii = self.check()?;
+            let upgrade = match &version {
+                Some(version) => pii.parsed_core_version()? != *version,
+                None => false,
+            };
+
+            (pii, upgrade)
+        };
+
+        let pio = Pio::from(pii.clone());
+
+        if let Some(version) = version {
+            if upgrade {
+                self.install_core(&pii, &version)?;
+            }
+
+            let installed = pio.version()?;
+            if installed != version {
+                bail!(
+                    "Installed PlatformIO Core version {} does not match the requested version {}",
+                    installed,
+                    version
+                );
+            }
         }
+
+        Ok(pio)
     }
 
     pub fn install(&self) -> Result<()> {
@@ -650,6 +1061,46 @@ impl PioInstaller {
         Ok(serde_json::from_reader::<File, PioInstallerInfo>(file)?)
     }
 
+    /// The pinned version, with the [`VAR_PIO_VERSION`] environment variable taking
+    /// precedence.
+    fn requested_version(&self) -> Result<Option<PioVersion>> {
+        match env::var(VAR_PIO_VERSION) {
+            Ok(version) if !version.trim().is_empty() => {
+                Ok(Some(version.parse().with_context(|| {
+                    anyhow!("Invalid value of `{VAR_PIO_VERSION}`")
+                })?))
+            }
+            _ => Ok(self.version.clone()),
+        }
+    }
+
+    /// Install the PlatformIO Core `version` into the virtual environment of `pii`.
+    fn install_core(&self, pii: &PioInstallerInfo, version: &PioVersion) -> Result<()> {
+        let mut cmd = Command::new(&pii.python_exe);
+        cmd.arg("-m")
+            .arg("pip")
+            .arg("install")
+            .arg(format!("platformio=={version}"));
+
+        debug!("Running command {:?}", cmd);
+
+        if self.silent {
+            cmd.stdout(Stdio::null());
+            cmd.stderr(Stdio::null());
+        }
+
+        let status = cmd.status()?;
+        if !status.success() {
+            bail!(
+                "Installing PlatformIO Core {} failed with status {:?}",
+                version,
+                status.code()
+            );
+        }
+
+        Ok(())
+    }
+
     fn command(&self) -> Command {
         let mut command = Command::new(PYTHON);
         if let Some(pio_location) = self.pio_location.as_ref() {
@@ -763,6 +1214,11 @@ impl Resolver {
     }
 
     pub fn resolve(&self, mandatory_target_resolution: bool) -> Result<Resolution> {
+        self.try_resolve(mandatory_target_resolution)
+            .map_err(crate::diag::record_error)
+    }
+
+    fn try_resolve(&self, mandatory_target_resolution: bool) -> Result<Resolution> {
         debug!("Resolving {:?}", self);
 
         let resolution = if self.params.board.is_some() {
@@ -787,15 +1243,15 @@ impl Resolver {
 
         let board_id = params.board.as_ref().unwrap().as_str();
 
-        let mut boards: Vec<Board> = self
-            .pio
-            .boards(None as Option<String>)?
-            .into_iter()
+        let all_boards = self.pio.boards(None as Option<String>)?;
+        let mut boards: Vec<Board> = all_boards
+            .iter()
             .filter(|b| b.id == board_id)
+            .cloned()
             .collect::<Vec<_>>();
 
         if boards.is_empty() {
-            bail!("Configured board '{}' is not known to PIO", board_id);
+            return Err(unknown_board(&all_boards, board_id).context("Invalid configured board"));
         }
 
         let target_pmf = self.get_default_platform_mcu_frameworks();
@@ -883,7 +1339,7 @@ impl Resolver {
             }
 
             if params.mcu.is_none() {
-                warn!(
+                warning!(
                     "Configuring first supported MCU '{}' derived from the build target '{}' supporting MCUs [{}].\nExplicitly specify a board or an MCU to resolve this ambiguity",
                     target_pmf.mcus[0], target, target_pmf.mcus.join(", ")
                 );
@@ -1004,7 +1460,7 @@ impl Resolver {
                         target);
                 }
             } else {
-                warn!(
+                warning!(
                     "Configuring first supported MCU '{}' derived from the build target '{}' supporting MCUs [{}].\nExplicitly specify a board or an MCU to resolve this ambiguity",
                     target_pmf.mcus[0], target, target_pmf.mcus.join(", ")
                 );
@@ -1165,17 +1621,17 @@ impl Resolver {
                 params.platform.as_ref().unwrap(),
                 params.frameworks.join(", "));
         } else {
-            if params.mcu.is_some() {
+            if let Some(mcu) = &params.mcu {
                 boards = boards
                     .into_iter()
-                    .filter(|b| b.mcu == *params.mcu.as_ref().unwrap())
+                    .filter(|b| b.mcu == *mcu)
                     .collect::<Vec<_>>();
 
                 if boards.is_empty() {
                     bail!(
                         "Configured platform '{}', MCU '{}' and frameworks [{}] do not have any matching board defined in PIO",
                         params.platform.as_ref().unwrap(),
-                        params.mcu.as_ref().unwrap(),
+                        mcu,
                         params.frameworks.join(", "));
                 }
             } else {
@@ -1337,3 +1793,116 @@ impl Resolver {
         })
     }
 }
+
+/// Find the board `id` in `boards`, erroring with the closest matches if not found.
+fn find_board(boards: Vec<Board>, id: &str) -> Result<Board> {
+    match boards.iter().position(|b| b.id == id) {
+        Some(index) => Ok(boards.into_iter().nth(index).unwrap()),
+        None => Err(unknown_board(&boards, id)),
+    }
+}
+
+/// The error for the unknown board `id`, listing the closest matches in `boards`.
+fn unknown_board(boards: &[Board], id: &str) -> anyhow::Error {
+    let mut suggestions = boards
+        .iter()
+        .map(|b| (edit_distance(&b.id, id), b.id.as_str()))
+        .filter(|(distance, board_id)| *distance <= (id.len() / 3).max(2) || board_id.contains(id))
+        .collect::<Vec<_>>();
+    suggestions.sort();
+
+    if suggestions.is_empty() {
+        anyhow!("Board '{}' is not known to PIO", id)
+    } else {
+        anyhow!(
+            "Board '{}' is not known to PIO; did you mean one of [{}]?",
+            id,
+            suggestions
+                .iter()
+                .take(MAX_SUGGESTIONS)
+                .map(|(_, board_id)| *board_id)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    }
+}
+
+/// The Levenshtein distance between `a` and `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_board() {
+        let boards: Vec<Board> = serde_json::from_str(
+            r#"[
+                {"id": "esp32dev", "name": "Espressif ESP32 Dev Module", "platform": "espressif32",
+                 "mcu": "ESP32", "fcpu": 240000000, "ram": 327680, "rom": 4194304,
+                 "frameworks": ["arduino", "espidf"], "vendor": "Espressif",
+                 "url": "https://en.wikipedia.org/wiki/ESP32"},
+                {"id": "esp32-c3-devkitm-1", "name": "Espressif ESP32-C3-DevKitM-1",
+                 "platform": "espressif32", "mcu": "ESP32C3", "fcpu": 160000000, "ram": 327680,
+                 "rom": 4194304, "frameworks": ["arduino", "espidf"], "vendor": "Espressif",
+                 "url": "https://docs.espressif.com"},
+                {"id": "uno", "name": "Arduino Uno", "platform": "atmelavr", "mcu": "ATMEGA328P",
+                 "fcpu": 16000000, "ram": 2048, "rom": 32256, "frameworks": ["arduino"],
+                 "vendor": "Arduino", "url": "https://www.arduino.cc"}
+            ]"#,
+        )
+        .unwrap();
+
+        let board = find_board(boards.clone(), "esp32dev").unwrap();
+        assert_eq!(board.mcu, "ESP32");
+        assert_eq!(board.frameworks, ["arduino", "espidf"]);
+
+        let err = find_board(boards.clone(), "esp32-dev").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Board 'esp32-dev' is not known to PIO; did you mean one of [esp32dev]?"
+        );
+
+        let err = find_board(boards, "nucleo_f401re").unwrap_err();
+        assert_eq!(err.to_string(), "Board 'nucleo_f401re' is not known to PIO");
+    }
+
+    #[test]
+    fn test_pio_version() {
+        let version = "6.1.15".parse::<PioVersion>().unwrap();
+        assert_eq!(version, PioVersion::new(6, 1, 15));
+        assert_eq!(version.to_string(), "6.1.15");
+
+        let rc = "6.1.16rc1".parse::<PioVersion>().unwrap();
+        assert_eq!(rc.pre.as_deref(), Some("rc1"));
+        assert_eq!(rc.to_string(), "6.1.16rc1");
+
+        assert_eq!(
+            "6.1".parse::<PioVersion>().unwrap(),
+            PioVersion::new(6, 1, 0)
+        );
+        assert!("six".parse::<PioVersion>().is_err());
+        assert!("6.1.2.3".parse::<PioVersion>().is_err());
+
+        assert!(version >= PioVersion::new(6, 1, 0));
+        assert!(version < rc);
+        assert!(rc < PioVersion::new(6, 1, 16));
+        assert!(PioVersion::new(5, 2, 5) < PioVersion::new(6, 0, 0));
+    }
+}
@@ -1,21 +1,38 @@
 use std::convert::TryFrom;
 use std::env;
-use std::fs::{self, OpenOptions};
-use std::io::Write;
+use std::fs;
+use std::hash::Hash;
 use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Context, Result};
 use log::*;
 use serde::{Deserialize, Serialize};
 
-use super::Resolution;
+use super::{Pio, PioVersion, Resolution};
+use crate::artifacts::{self, ArtifactKind};
+use crate::cache::{self, CacheKey};
 use crate::cargo::CargoCmd;
-use crate::utils::OsStrExt;
+use crate::cli::Syntax;
+use crate::cmd::CmdError;
+use crate::utils::{OsStrExt, StableHasher};
 use crate::{build, cargo};
 
+mod ini;
+mod libdeps;
+
+use self::ini::Ini;
+pub use self::libdeps::{LibraryDep, LibraryDeps};
+
 pub const OPTION_QUICK_DUMP: &str = "quick_dump";
 pub const OPTION_TERMINATE_AFTER_DUMP: &str = "terminate_after_dump";
 
+/// The environment variable which, if set to `1`, forces
+/// [`SconsVariables::from_project`] to dump the scons variables again.
+pub const VAR_SKIP_CACHE: &str = "EMBUILD_PIO_SKIP_CACHE";
+
+pub(crate) const SCONS_DUMP_FILE: &str = "__pio_scons_dump.json";
+const SCONS_DUMP_CACHE_FILE: &str = "__pio_scons_dump.cache.json";
+
 const VAR_BUILD_ACTIVE: &str = "CARGO_PIO_BUILD_ACTIVE";
 const VAR_BUILD_RELEASE: &str = "CARGO_PIO_BUILD_RELEASE_BUILD";
 //const VAR_BUILD_BINDGEN_RUN: &str = "CARGO_PIO_BUILD_BINDGEN_RUN";
@@ -25,6 +42,10 @@ const VAR_BUILD_INC_FLAGS: &str = "CARGO_PIO_BUILD_INC_FLAGS";
 const VAR_BUILD_LIB_FLAGS: &str = "CARGO_PIO_BUILD_LIB_FLAGS";
 const VAR_BUILD_LIB_DIR_FLAGS: &str = "CARGO_PIO_BUILD_LIB_DIR_FLAGS";
 const VAR_BUILD_LIBS: &str = "CARGO_PIO_BUILD_LIBS";
+const VAR_BUILD_DEF_FLAGS: &str = "CARGO_PIO_BUILD_DEF_FLAGS";
+const VAR_BUILD_CC_FLAGS: &str = "CARGO_PIO_BUILD_CC_FLAGS";
+const VAR_BUILD_C_FLAGS: &str = "CARGO_PIO_BUILD_C_FLAGS";
+const VAR_BUILD_CXX_FLAGS: &str = "CARGO_PIO_BUILD_CXX_FLAGS";
 const VAR_BUILD_LINK_FLAGS: &str = "CARGO_PIO_BUILD_LINK_FLAGS";
 const VAR_BUILD_LINK: &str = "CARGO_PIO_BUILD_LINK";
 const VAR_BUILD_LINKCOM: &str = "CARGO_PIO_BUILD_LINKCOM";
@@ -38,6 +59,14 @@ const PLATFORMIO_PATCH_PY: &[u8] = include_bytes!("resources/platformio.patch.py
 const PLATFORMIO_DUMP_PY: &[u8] = include_bytes!("resources/platformio.dump.py.resource");
 const PLATFORMIO_CARGO_PY: &[u8] = include_bytes!("resources/platformio.cargo.py.resource");
 
+/// The names of the scripts generated by the [`Builder`] itself.
+const GENERATED_SCRIPTS: &[&str] = &[
+    "platformio.git.py",
+    "platformio.patch.py",
+    "platformio.dump.py",
+    "platformio.cargo.py",
+];
+
 const LIB_RS: &[u8] = include_bytes!("resources/lib.rs.resource");
 const LIB_ESPIDF_RS: &[u8] = include_bytes!("resources/lib_espidf.rs.resource");
 const LIB_ARDUINO_RS: &[u8] = include_bytes!("resources/lib_arduino.rs.resource");
@@ -58,6 +87,18 @@ pub struct SconsVariables {
     pub libflags: String,
     pub libdirflags: String,
     pub libs: String,
+    /// The preprocessor defines (`$_CPPDEFFLAGS`), i.e. the expanded `CPPDEFINES`.
+    #[serde(default)]
+    pub defflags: String,
+    /// The flags common to the C and C++ compiler (`$CCFLAGS`).
+    #[serde(default)]
+    pub ccflags: String,
+    /// The flags only passed to the C compiler (`$CFLAGS`).
+    #[serde(default)]
+    pub cflags: String,
+    /// The flags only passed to the C++ compiler (`$CXXFLAGS`).
+    #[serde(default)]
+    pub cxxflags: String,
     pub linkflags: String,
     pub link: String,
     pub linkcom: String,
@@ -80,6 +121,10 @@ impl SconsVariables {
                 libflags: env::var(VAR_BUILD_LIB_FLAGS).ok()?,
                 libdirflags: env::var(VAR_BUILD_LIB_DIR_FLAGS).ok()?,
                 libs: env::var(VAR_BUILD_LIBS).ok()?,
+                defflags: env::var(VAR_BUILD_DEF_FLAGS).unwrap_or_default(),
+                ccflags: env::var(VAR_BUILD_CC_FLAGS).unwrap_or_default(),
+                cflags: env::var(VAR_BUILD_C_FLAGS).unwrap_or_default(),
+                cxxflags: env::var(VAR_BUILD_CXX_FLAGS).unwrap_or_default(),
                 linkflags: env::var(VAR_BUILD_LINK_FLAGS).ok()?,
                 link: env::var(VAR_BUILD_LINK).ok()?,
                 linkcom: env::var(VAR_BUILD_LINKCOM).ok()?,
@@ -96,10 +141,115 @@ impl SconsVariables {
 
     pub fn from_dump(project_path: impl AsRef<Path>) -> Result<Self> {
         Ok(serde_json::from_reader(fs::File::open(
-            project_path.as_ref().join("__pio_scons_dump.json"),
+            project_path.as_ref().join(SCONS_DUMP_FILE),
         )?)?)
     }
 
+    /// Get the scons variables of the project at `project_dir` (generated with
+    /// [`Builder::enable_scons_dump`]) by building its debug or `release` environment.
+    ///
+    /// The variables are cached in the project directory and only dumped again when
+    /// `platformio.ini`, the environment, the PlatformIO Core version or the installed
+    /// packages change, or when [`VAR_SKIP_CACHE`] is set to `1`.
+    pub fn from_project(pio: &Pio, project_dir: impl AsRef<Path>, release: bool) -> Result<Self> {
+        let project_dir = project_dir.as_ref();
+        let environment = if release { "release" } else { "debug" };
+
+        let version = pio.version()?;
+        cache::validate_or_remove(
+            project_dir.join(SCONS_DUMP_CACHE_FILE),
+            &CacheKey::new("pio-scons-dump/1").tool("platformio", version.to_string()),
+        )?;
+        let key = Self::cache_key(pio, &version, project_dir, environment)?;
+
+        if env::var(VAR_SKIP_CACHE).as_deref() != Ok("1") {
+            if let Some(vars) = Self::load_cached(project_dir, &key) {
+                info!(
+                    "PlatformIO project {} unchanged, skipping reconfigure (using the cached scons variables)",
+                    project_dir.display()
+                );
+                return Ok(vars);
+            }
+        }
+
+        let vars = Self::dump(pio, project_dir, release)?;
+
+        Self::store_cached(project_dir, &key, &vars)?;
+
+        Ok(vars)
+    }
+
+    /// Dump the scons variables of the project at `project_dir` (generated with
+    /// [`Builder::enable_scons_dump`]) by building its debug or `release` environment,
+    /// without caching them.
+    ///
+    /// The build is only needed for the dump, so a build exiting unsuccessfully after
+    /// the variables were dumped (e.g. when linking the firmware) is not an error.
+    pub fn dump(pio: &Pio, project_dir: impl AsRef<Path>, release: bool) -> Result<Self> {
+        let project_dir = project_dir.as_ref();
+        let dump_file = project_dir.join(SCONS_DUMP_FILE);
+        if dump_file.exists() {
+            fs::remove_file(&dump_file)?;
+        }
+
+        match pio.build(project_dir, release) {
+            Ok(()) => (),
+            Err(err) if dump_file.exists() && is_unsuccessful(&err) => {
+                debug!("PlatformIO build failed after dumping the scons variables: {err:#}");
+            }
+            Err(err) => return Err(err),
+        }
+
+        Self::from_dump(project_dir)
+    }
+
+    fn cache_key(
+        pio: &Pio,
+        version: &PioVersion,
+        project_dir: &Path,
+        environment: &str,
+    ) -> Result<String> {
+        let mut hasher = StableHasher::new();
+
+        fs::read(project_dir.join("platformio.ini"))?.hash(&mut hasher);
+        environment.hash(&mut hasher);
+        version.hash(&mut hasher);
+
+        // The installed platform, tool and library packages
+        let mut cmd = pio.query_cmd();
+        cmd.arg("pkg")
+            .arg("list")
+            .arg("-d")
+            .arg(project_dir)
+            .arg("-e")
+            .arg(environment);
+        cmd.timeout(super::QUERY_TIMEOUT);
+        cmd.stdout().ok().hash(&mut hasher);
+
+        Ok(hasher.finish_hex())
+    }
+
+    fn load_cached(project_dir: &Path, key: &str) -> Option<Self> {
+        let cache = fs::read(project_dir.join(SCONS_DUMP_CACHE_FILE)).ok()?;
+        let cache = serde_json::from_slice::<SconsVariablesCache>(&cache).ok()?;
+
+        (cache.key == key).then_some(cache.vars)
+    }
+
+    fn store_cached(project_dir: &Path, key: &str, vars: &Self) -> Result<()> {
+        let cache = SconsVariablesCache {
+            key: key.to_owned(),
+            vars: vars.clone(),
+        };
+
+        fs::write(
+            project_dir.join(SCONS_DUMP_CACHE_FILE),
+            serde_json::to_vec(&cache)?,
+        )?;
+
+        Ok(())
+    }
+
     pub fn full_path(&self, executable: impl AsRef<str>) -> Result<PathBuf> {
         Ok(which::which_in(
             executable.as_ref(),
@@ -107,28 +257,141 @@ impl SconsVariables {
             env::current_dir()?,
         )?)
     }
+
+    /// The arguments passed to the C compiler, i.e. the parsed `CCFLAGS` followed by
+    /// the parsed `CFLAGS`.
+    pub fn cflags_args(&self) -> Vec<String> {
+        let mut args = Syntax::NATIVE.split(&self.ccflags);
+        args.extend(Syntax::NATIVE.split(&self.cflags));
+        args
+    }
+
+    /// The arguments passed to the C++ compiler, i.e. the parsed `CCFLAGS` followed by
+    /// the parsed `CXXFLAGS`.
+    pub fn cxxflags_args(&self) -> Vec<String> {
+        let mut args = Syntax::NATIVE.split(&self.ccflags);
+        args.extend(Syntax::NATIVE.split(&self.cxxflags));
+        args
+    }
+
+    /// The parsed `LINKFLAGS`.
+    pub fn linkflags_args(&self) -> Vec<String> {
+        Syntax::NATIVE.split(&self.linkflags)
+    }
+
+    /// All preprocessor macros defined with `-D` in the `CPPDEFINES`, `CCFLAGS` and
+    /// `CFLAGS` as `(name, value)` pairs, in the order they were defined.
+    ///
+    /// The value is [`None`] for macros defined without one (`-DNAME`).
+    pub fn defines(&self) -> Vec<(String, Option<String>)> {
+        let mut args = Syntax::NATIVE
+            .split(&self.defflags)
+            .into_iter()
+            .chain(self.cflags_args());
+        let mut defines = Vec::new();
+
+        while let Some(arg) = args.next() {
+            let define = match arg.strip_prefix("-D") {
+                Some("") => args.next(),
+                Some(define) => Some(define.to_owned()),
+                None => None,
+            };
+
+            if let Some(define) = define {
+                defines.push(match define.split_once('=') {
+                    Some((name, value)) => (name.to_owned(), Some(value.to_owned())),
+                    None => (define, None),
+                });
+            }
+        }
+
+        defines
+    }
+}
+
+impl crate::build::ManifestSource for SconsVariables {
+    /// The PlatformIO platform and framework, as described by their package manifests
+    /// (`platform.json` or `package.json`).
+    fn manifest_entries(&self) -> Result<Vec<crate::build::ManifestEntry>> {
+        [&self.pio_platform_dir, &self.pio_framework_dir]
+            .into_iter()
+            .filter(|dir| !dir.is_empty())
+            .map(|dir| package_manifest_entry(Path::new(dir)))
+            .collect()
+    }
+}
+
+/// Get the manifest entry of the PlatformIO package (or platform) in `dir`.
+fn package_manifest_entry(dir: &Path) -> Result<crate::build::ManifestEntry> {
+    #[derive(Deserialize)]
+    struct Repository {
+        url: Option<String>,
+    }
+
+    #[derive(Deserialize)]
+    struct Package {
+        name: String,
+        version: Option<String>,
+        repository: Option<Repository>,
+    }
+
+    let file = ["platform.json", "package.json"]
+        .iter()
+        .map(|file| dir.join(file))
+        .find(|file| file.is_file())
+        .ok_or_else(|| anyhow!("no package manifest found in '{}'", dir.display()))?;
+    let package: Package = serde_json::from_str(&fs::read_to_string(&file)?)
+        .with_context(|| anyhow!("invalid package manifest '{}'", file.display()))?;
+
+    let mut entry = crate::build::ManifestEntry::new(package.name).path(dir);
+    entry.version = package.version;
+    entry.source = package.repository.and_then(|r| r.url);
+    Ok(entry)
+}
+
+/// Whether `err` is caused by a command that exited with a non-zero status.
+fn is_unsuccessful(err: &anyhow::Error) -> bool {
+    err.chain()
+        .any(|e| matches!(e.downcast_ref(), Some(CmdError::Unsuccessful(..))))
+}
+
+/// The cached scons variables, with the key of the inputs they were dumped from.
+#[derive(Serialize, Deserialize)]
+struct SconsVariablesCache {
+    key: String,
+    vars: SconsVariables,
 }
 
 pub struct Builder {
     project_dir: PathBuf,
-    options: Vec<(String, String)>,
+    options: Ini,
     git_repos_enabled: bool,
     git_repos: Vec<(String, PathBuf)>,
     files: Vec<(PathBuf, PathBuf)>,
-    platform_packages: Vec<(String, PathBuf)>,
+    platform_packages: Vec<String>,
     platform_packages_patches_enabled: bool,
     platform_packages_patches: Vec<(PathBuf, PathBuf)>,
     cargo_cmd: Option<CargoCmd>,
     cargo_options: Vec<String>,
     scons_dump_enabled: bool,
     c_entry_points_enabled: bool,
+    extra_scripts: Vec<ExtraScript>,
+}
+
+/// A user provided script in the `extra_scripts` of a generated project.
+#[derive(Clone, Debug)]
+struct ExtraScript {
+    /// The `extra_scripts` entry, i.e. the file name with the optional stage prefix.
+    entry: String,
+    file_name: String,
+    contents: Vec<u8>,
 }
 
 impl Builder {
     pub fn new(project_dir: impl AsRef<Path>) -> Self {
         Self {
             project_dir: project_dir.as_ref().to_owned(),
-            options: Vec::new(),
+            options: Ini::default(),
             git_repos_enabled: false,
             git_repos: Vec::new(),
             files: Vec::new(),
@@ -139,6 +402,7 @@ impl Builder {
             cargo_options: Vec::new(),
             scons_dump_enabled: false,
             c_entry_points_enabled: false,
+            extra_scripts: Vec::new(),
         }
     }
 
@@ -146,23 +410,59 @@ impl Builder {
         &self.project_dir
     }
 
-    pub fn option(&mut self, name: impl AsRef<str>, value: impl AsRef<str>) -> &mut Self {
+    /// Set the option `key` in the `section` of the generated `platformio.ini` to
+    /// `value` (e.g. `option("env", "monitor_speed", "115200")`).
+    ///
+    /// User options win over the defaults generated by the builder, but must not
+    /// override the options the builder needs for the integration with Rust (e.g.
+    /// `rust_lib` or `extra_scripts`); such conflicts fail [`generate`](Self::generate).
+    pub fn option(
+        &mut self,
+        section: impl AsRef<str>,
+        key: impl AsRef<str>,
+        value: impl AsRef<str>,
+    ) -> &mut Self {
         self.options
-            .push((name.as_ref().to_owned(), value.as_ref().to_owned()));
+            .set(section.as_ref(), key.as_ref(), value.as_ref());
         self
     }
 
-    pub fn options<S>(&mut self, options: impl Iterator<Item = (S, S)>) -> &mut Self
+    /// Set all `options` in the `section` of the generated `platformio.ini` (see
+    /// [`option`](Self::option)).
+    pub fn options<S>(
+        &mut self,
+        section: impl AsRef<str>,
+        options: impl Iterator<Item = (S, S)>,
+    ) -> &mut Self
     where
         S: AsRef<str>,
     {
-        for (name, value) in options {
-            self.option(name, value);
+        for (key, value) in options {
+            self.option(section.as_ref(), key, value);
         }
 
         self
     }
 
+    /// Set all options of the ini file (fragment) at `path` in the generated
+    /// `platformio.ini` (see [`option`](Self::option)).
+    pub fn options_from_ini(&mut self, path: impl AsRef<Path>) -> Result<&mut Self> {
+        let path = path.as_ref();
+        let ini = fs::read_to_string(path)
+            .map_err(anyhow::Error::from)
+            .and_then(|content| Ini::parse(&content))
+            .with_context(|| {
+                anyhow!(
+                    "Failed to load PlatformIO options from '{}'",
+                    path.display()
+                )
+            })?;
+
+        self.options.merge(&ini);
+
+        Ok(self)
+    }
+
     pub fn cargo_option(&mut self, option: impl AsRef<str>) -> &mut Self {
         self.cargo_options.push(option.as_ref().to_owned());
         self
@@ -213,8 +513,22 @@ impl Builder {
         package: impl AsRef<str>,
         location: impl AsRef<Path>,
     ) -> &mut Self {
-        self.platform_packages
-            .push((package.as_ref().to_owned(), location.as_ref().to_owned()));
+        self.platform_package_spec(format!(
+            "{}@{}",
+            package.as_ref(),
+            location.as_ref().display()
+        ))
+    }
+
+    /// Add the `platform_packages` entry `spec` (e.g. `toolchain-xtensa-esp32@~8.4.0`)
+    /// to the generated `platformio.ini`.
+    ///
+    /// Adding different specs for the same package fails [`generate`](Self::generate).
+    pub fn platform_package_spec(&mut self, spec: impl AsRef<str>) -> &mut Self {
+        let spec = spec.as_ref().trim().to_owned();
+        if !self.platform_packages.contains(&spec) {
+            self.platform_packages.push(spec);
+        }
         self
     }
 
@@ -249,19 +563,119 @@ impl Builder {
         self
     }
 
+    /// Add the PlatformIO extra script `name` with `contents` to the generated project.
+    ///
+    /// `name` is the file name of the script in the project directory, optionally
+    /// prefixed with `pre:` or `post:` to select when PlatformIO runs it (see the
+    /// `extra_scripts` option). The script is only rewritten when its contents change.
+    pub fn extra_script(
+        &mut self,
+        name: impl AsRef<str>,
+        contents: impl AsRef<[u8]>,
+    ) -> Result<&mut Self> {
+        let entry = name.as_ref().trim();
+        let file_name = entry
+            .strip_prefix("pre:")
+            .or_else(|| entry.strip_prefix("post:"))
+            .unwrap_or(entry);
+
+        if file_name.is_empty()
+            || file_name.starts_with('.')
+            || !file_name.ends_with(".py")
+            || !file_name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        {
+            bail!("Invalid PlatformIO extra script name '{entry}'; expected a '<name>.py' file name optionally prefixed with 'pre:' or 'post:'");
+        }
+
+        if GENERATED_SCRIPTS.contains(&file_name) {
+            bail!("The PlatformIO extra script '{file_name}' is generated by embuild");
+        }
+
+        if self.extra_scripts.iter().any(|s| s.file_name == file_name) {
+            bail!("Duplicate PlatformIO extra script '{file_name}'");
+        }
+
+        self.extra_scripts.push(ExtraScript {
+            entry: entry.to_owned(),
+            file_name: file_name.to_owned(),
+            contents: contents.as_ref().to_owned(),
+        });
+
+        Ok(self)
+    }
+
+    /// Add the PlatformIO extra script `name` with the contents of the file `source` to
+    /// the generated project (see [`extra_script`](Self::extra_script)).
+    pub fn extra_script_file(
+        &mut self,
+        name: impl AsRef<str>,
+        source: impl AsRef<Path>,
+    ) -> Result<&mut Self> {
+        let source = source.as_ref();
+        let contents = fs::read(source).with_context(|| {
+            anyhow!(
+                "Failed to read PlatformIO extra script '{}'",
+                source.display()
+            )
+        })?;
+
+        self.extra_script(name, contents)
+    }
+
+    /// The paths of the extra scripts added with [`extra_script`](Self::extra_script) in
+    /// the generated project.
+    pub fn extra_script_paths(&self) -> impl Iterator<Item = PathBuf> + '_ {
+        self.extra_scripts
+            .iter()
+            .map(|s| self.project_dir.join(&s.file_name))
+    }
+
     pub fn generate(&self, resolution: &Resolution) -> Result<PathBuf> {
+        self.regenerate(resolution)?;
+
+        Ok(self.project_dir.clone())
+    }
+
+    /// Generate the project like [`generate`](Self::generate), and get whether any of
+    /// its files changed.
+    ///
+    /// Only the files whose contents changed are written (see
+    /// [`write_if_changed`](crate::fs::write_if_changed)), so that an unchanged project
+    /// does not trigger a reconfigure of PlatformIO.
+    pub fn regenerate(&self, resolution: &Resolution) -> Result<bool> {
+        self.check_managed_options()?;
+
         let mut options = vec![
             ("board".into(), resolution.board.clone()),
             ("platform".into(), resolution.platform.clone()),
             ("framework".into(), resolution.frameworks.join(", ")),
         ];
 
-        self.generate_with_options(resolution, &mut options)?;
+        let mut changed = self.generate_with_options(resolution, &mut options)?;
 
-        options.extend(self.options.iter().cloned());
-        self.create_platformio_ini(&options)?;
+        let mut ini = Ini::default();
+        ini.set("platformio", "default_envs", "debug");
+        for (key, value) in &options {
+            ini.set("env", key, value);
+        }
+        ini.set("env:debug", "build_type", "debug");
+        ini.set("env:release", "build_type", "release");
 
-        Ok(self.project_dir.clone())
+        ini.merge(&self.options);
+
+        changed |= self.create_platformio_ini(&ini)?;
+
+        artifacts::record(ArtifactKind::ProjectDir, &self.project_dir)?;
+
+        if !changed {
+            debug!(
+                "PlatformIO project {} unchanged",
+                self.project_dir.display()
+            );
+        }
+        Ok(changed)
     }
 
     pub fn update(&self) -> Result<PathBuf> {
@@ -281,6 +695,8 @@ impl Builder {
             self.create_file("platformio.dump.py", PLATFORMIO_DUMP_PY)?;
         }
 
+        self.create_extra_scripts()?;
+
         Ok(self.project_dir.clone())
     }
 
@@ -288,7 +704,8 @@ impl Builder {
         &self,
         resolution: &Resolution,
         options: &mut Vec<(String, String)>,
-    ) -> Result<()> {
+    ) -> Result<bool> {
+        let mut changed = false;
         let mut extra_scripts = Vec::new();
 
         let arduino = resolution
@@ -312,19 +729,23 @@ impl Builder {
                             .chain(self.cargo_options.iter().map(|s| &s[..])),
                     )?;
 
+                    changed = true;
+
                     let rust_lib = cargo_crate.set_library_type(["staticlib"])?;
                     cargo_crate.create_config_toml(Some(resolution.target.clone()), build_std)?;
 
                     if arduino {
-                        self.create_file(PathBuf::from("src").join("lib.rs"), LIB_ARDUINO_RS)?;
-                        self.create_file(
+                        changed |=
+                            self.create_file(PathBuf::from("src").join("lib.rs"), LIB_ARDUINO_RS)?;
+                        changed |= self.create_file(
                             PathBuf::from("src").join("main.cpp"),
                             MAIN_ARDUINO_RUST_CPP,
                         )?;
                     } else if espidf {
-                        self.create_file(PathBuf::from("src").join("lib.rs"), LIB_ESPIDF_RS)?;
+                        changed |=
+                            self.create_file(PathBuf::from("src").join("lib.rs"), LIB_ESPIDF_RS)?;
                     } else {
-                        self.create_file(PathBuf::from("src").join("lib.rs"), LIB_RS)?;
+                        changed |= self.create_file(PathBuf::from("src").join("lib.rs"), LIB_RS)?;
                     }
 
                     rust_lib
@@ -332,25 +753,26 @@ impl Builder {
                 _ => cargo_crate.check_staticlib()?,
             };
 
-            self.create_file("platformio.cargo.py", PLATFORMIO_CARGO_PY)?;
-            self.create_file(PathBuf::from("src").join("dummy.c"), DUMMY_C)?;
+            changed |= self.create_file("platformio.cargo.py", PLATFORMIO_CARGO_PY)?;
+            changed |= self.create_file(PathBuf::from("src").join("dummy.c"), DUMMY_C)?;
 
             options.push(("rust_lib".to_owned(), rust_lib));
             options.push(("rust_target".to_owned(), resolution.target.clone()));
         } else if self.c_entry_points_enabled {
             if arduino {
-                self.create_file(PathBuf::from("src").join("main.cpp"), MAIN_ARDUINO_CPP)?;
+                changed |=
+                    self.create_file(PathBuf::from("src").join("main.cpp"), MAIN_ARDUINO_CPP)?;
             } else if espidf {
-                self.create_file(PathBuf::from("src").join("main.c"), MAIN_ESPIDF_C)?;
+                changed |= self.create_file(PathBuf::from("src").join("main.c"), MAIN_ESPIDF_C)?;
             } else {
-                self.create_file(PathBuf::from("src").join("main.c"), MAIN_C)?;
+                changed |= self.create_file(PathBuf::from("src").join("main.c"), MAIN_C)?;
             }
         }
 
-        self.copy_files()?;
+        changed |= self.copy_files()?;
 
         if self.git_repos_enabled {
-            self.create_file("platformio.git.py", PLATFORMIO_GIT_PY)?;
+            changed |= self.create_file("platformio.git.py", PLATFORMIO_GIT_PY)?;
             extra_scripts.push("pre:platformio.git.py");
 
             if let Some(option) = self.get_git_repos_option()? {
@@ -363,7 +785,7 @@ impl Builder {
         }
 
         if self.platform_packages_patches_enabled {
-            self.create_file("platformio.patch.py", PLATFORMIO_PATCH_PY)?;
+            changed |= self.create_file("platformio.patch.py", PLATFORMIO_PATCH_PY)?;
             extra_scripts.push("pre:platformio.patch.py");
 
             if let Some(option) = self.get_platform_packages_patches_option()? {
@@ -376,15 +798,74 @@ impl Builder {
         }
 
         if self.scons_dump_enabled {
-            self.create_file("platformio.dump.py", PLATFORMIO_DUMP_PY)?;
+            changed |= self.create_file("platformio.dump.py", PLATFORMIO_DUMP_PY)?;
             extra_scripts.push("platformio.dump.py");
         }
 
+        changed |= self.create_extra_scripts()?;
+        extra_scripts.extend(self.extra_scripts.iter().map(|s| s.entry.as_str()));
+
         if !extra_scripts.is_empty() {
             options.insert(0, ("extra_scripts".to_owned(), extra_scripts.join(", ")));
         }
 
-        self.update_gitignore()?;
+        changed |= self.update_gitignore()?;
+
+        Ok(changed)
+    }
+
+    /// The `[env]` options generated for the integration with Rust, which the user
+    /// options must not override.
+    fn managed_options(&self) -> Vec<&'static str> {
+        let mut managed = Vec::new();
+
+        if self.cargo_cmd.is_some() {
+            managed.extend(["rust_lib", "rust_target"]);
+        }
+
+        if self.cargo_cmd.is_some()
+            || self.git_repos_enabled
+            || self.platform_packages_patches_enabled
+            || self.scons_dump_enabled
+            || !self.extra_scripts.is_empty()
+        {
+            managed.push("extra_scripts");
+        }
+
+        if self.git_repos_enabled && !self.git_repos.is_empty() {
+            managed.push("git_repos");
+        }
+
+        if !self.platform_packages.is_empty() {
+            managed.push("platform_packages");
+        }
+
+        if self.platform_packages_patches_enabled && !self.platform_packages_patches.is_empty() {
+            managed.push("patches");
+        }
+
+        managed
+    }
+
+    fn check_managed_options(&self) -> Result<()> {
+        let managed = self.managed_options();
+
+        let mut conflicts = self
+            .options
+            .iter()
+            .filter(|(section, key, _)| {
+                (*section == "env" || section.starts_with("env:")) && managed.contains(key)
+            })
+            .map(|(section, key, _)| format!("[{section}] {key}"))
+            .collect::<Vec<_>>();
+        conflicts.dedup();
+
+        if !conflicts.is_empty() {
+            bail!(
+                "The PlatformIO project options {} are managed by embuild and cannot be overridden",
+                conflicts.join(", ")
+            );
+        }
 
         Ok(())
     }
@@ -408,6 +889,22 @@ impl Builder {
     }
 
     fn get_platform_packages_option(&self) -> Result<Option<(String, String)>> {
+        for (index, spec) in self.platform_packages.iter().enumerate() {
+            let package = platform_package_name(spec);
+
+            if let Some(conflict) = self.platform_packages[..index]
+                .iter()
+                .find(|other| platform_package_name(other) == package)
+            {
+                bail!(
+                    "Conflicting platform packages '{}' and '{}' for package '{}'",
+                    conflict,
+                    spec,
+                    package
+                );
+            }
+        }
+
         Ok(if !self.platform_packages.is_empty() {
             Some((
                 "platform_packages".into(),
@@ -415,7 +912,7 @@ impl Builder {
                     "\n{}",
                     self.platform_packages
                         .iter()
-                        .map(|package| format!("  {}@{}", package.0, package.1.display()))
+                        .map(|spec| format!("  {spec}"))
                         .collect::<Vec<_>>()
                         .join("\n"),
                 ),
@@ -446,78 +943,92 @@ impl Builder {
         })
     }
 
-    fn create_platformio_ini(&self, options: &[(impl AsRef<str>, impl AsRef<str>)]) -> Result<()> {
+    fn create_platformio_ini(&self, ini: &Ini) -> Result<bool> {
         let platformio_ini_path = self.project_dir.join("platformio.ini");
 
         debug!("Creating file {}", platformio_ini_path.display());
 
-        fs::write(
+        crate::fs::write_if_changed(
             platformio_ini_path,
             format!(
-                r#"
-; PlatformIO Project Configuration File
+                r#"; PlatformIO Project Configuration File
 ;
 ; Please visit documentation for options and examples
 ; https://docs.platformio.org/page/projectconf.html
-[platformio]
-default_envs = debug
-
-[env]
-{}
-
-[env:debug]
-build_type = debug
-
-[env:release]
-build_type = release
-"#,
-                options
-                    .iter()
-                    .map(|(key, value)| format!("{} = {}", key.as_ref(), value.as_ref()))
-                    .collect::<Vec<_>>()
-                    .join("\n")
+{ini}"#
             ),
-        )?;
-
-        Ok(())
+        )
     }
 
-    fn update_gitignore(&self) -> Result<()> {
-        // TODO: Only do this if not done already
-        debug!("Adding \".pio\" and \"CMakeFiles\" directories to .gitignore");
+    fn update_gitignore(&self) -> Result<bool> {
+        let path = self.project_dir.join(".gitignore");
+        let mut gitignore = fs::read_to_string(&path).unwrap_or_default();
 
-        let mut file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(self.project_dir.join(".gitignore"))?;
+        let missing = [".pio", "CMakeFiles/"]
+            .into_iter()
+            .filter(|entry| !gitignore.lines().any(|line| line.trim() == *entry))
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            return Ok(false);
+        }
 
-        writeln!(file, ".pio\nCMakeFiles/")?;
+        debug!("Adding {missing:?} to .gitignore");
 
-        Ok(())
+        if !gitignore.is_empty() && !gitignore.ends_with('\n') {
+            gitignore.push('\n');
+        }
+        for entry in missing {
+            gitignore.push_str(entry);
+            gitignore.push('\n');
+        }
+
+        crate::fs::write_if_changed(path, gitignore)
     }
 
-    fn copy_files(&self) -> Result<()> {
+    fn copy_files(&self) -> Result<bool> {
+        let mut changed = false;
         for file_pair in &self.files {
             let dest_file = self.project_dir.join(&file_pair.1);
 
             debug!("Creating/updating {}", dest_file.display());
 
             fs::create_dir_all(dest_file.parent().unwrap())?;
-            fs::copy(&file_pair.0, dest_file)?;
+            changed |= crate::fs::copy_if_changed(&file_pair.0, dest_file)?;
         }
 
-        Ok(())
+        Ok(changed)
     }
 
-    fn create_file(&self, path: impl AsRef<Path>, data: &[u8]) -> Result<()> {
+    fn create_extra_scripts(&self) -> Result<bool> {
+        let mut changed = false;
+        for script in &self.extra_scripts {
+            changed |= self.create_file(&script.file_name, &script.contents)?;
+        }
+
+        Ok(changed)
+    }
+
+    fn create_file(&self, path: impl AsRef<Path>, data: &[u8]) -> Result<bool> {
         let dest_file = self.project_dir.join(path.as_ref());
 
         debug!("Creating/updating {}", dest_file.display());
 
         fs::create_dir_all(dest_file.parent().unwrap())?;
-        fs::write(dest_file, data)?;
+        crate::fs::write_if_changed(dest_file, data)
+    }
+}
 
-        Ok(())
+/// The name of the package of the `platform_packages` entry `spec`, i.e. the part before
+/// the `@` without the owner (`platformio/tool-openocd @ ~2.1` -> `tool-openocd`).
+///
+/// Specs without a name (e.g. a plain repository url) are their own name.
+fn platform_package_name(spec: &str) -> &str {
+    match spec.split_once('@') {
+        Some((name, _)) if !name.contains(':') => {
+            let name = name.trim();
+            name.rsplit_once('/').map_or(name, |(_, name)| name)
+        }
+        _ => spec,
     }
 }
 
@@ -525,9 +1036,7 @@ impl TryFrom<&SconsVariables> for build::CInclArgs {
     type Error = anyhow::Error;
 
     fn try_from(scons: &SconsVariables) -> Result<Self> {
-        Ok(Self {
-            args: scons.incflags.clone(),
-        })
+        Ok(Self::from_args(Syntax::NATIVE.split(&scons.incflags)))
     }
 }
 
@@ -575,3 +1084,245 @@ impl TryFrom<&SconsVariables> for build::LinkArgsBuilder {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags_args() {
+        let scons = SconsVariables {
+            defflags: "-DESP_PLATFORM -DIDF_VER=\\\"v4.4\\\"".into(),
+            ccflags: "-Os -DMSG=\"hello world\" -D CONFIG_FOO=1".into(),
+            cflags: "-std=gnu99 \"-DPATH=/my dir/x\"".into(),
+            cxxflags: "-std=gnu++11 -fno-rtti".into(),
+            linkflags: "-nostdlib \"-Wl,-Map,my file.map\"".into(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            scons.cflags_args(),
+            [
+                "-Os",
+                "-DMSG=hello world",
+                "-D",
+                "CONFIG_FOO=1",
+                "-std=gnu99",
+                "-DPATH=/my dir/x"
+            ]
+        );
+        assert_eq!(
+            scons.cxxflags_args(),
+            [
+                "-Os",
+                "-DMSG=hello world",
+                "-D",
+                "CONFIG_FOO=1",
+                "-std=gnu++11",
+                "-fno-rtti"
+            ]
+        );
+        assert_eq!(
+            scons.linkflags_args(),
+            ["-nostdlib", "-Wl,-Map,my file.map"]
+        );
+        assert_eq!(
+            scons.defines(),
+            [
+                ("ESP_PLATFORM".to_owned(), None),
+                ("IDF_VER".to_owned(), Some("\"v4.4\"".to_owned())),
+                ("MSG".to_owned(), Some("hello world".to_owned())),
+                ("CONFIG_FOO".to_owned(), Some("1".to_owned())),
+                ("PATH".to_owned(), Some("/my dir/x".to_owned())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scons_vars_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let vars = SconsVariables {
+            incflags: "-Iinclude".into(),
+            ..Default::default()
+        };
+
+        assert!(SconsVariables::load_cached(dir.path(), "key").is_none());
+
+        SconsVariables::store_cached(dir.path(), "key", &vars).unwrap();
+
+        let cached = SconsVariables::load_cached(dir.path(), "key").unwrap();
+        assert_eq!(cached.incflags, "-Iinclude");
+        assert!(SconsVariables::load_cached(dir.path(), "other key").is_none());
+    }
+
+    #[test]
+    fn test_platform_packages() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut builder = Builder::new(dir.path());
+        builder
+            .platform_package_spec("toolchain-xtensa-esp32@~8.4.0")
+            .platform_package_spec("platformio/tool-openocd @ ~2.1")
+            .platform_package_spec("toolchain-xtensa-esp32@~8.4.0")
+            .platform_package_spec("https://github.com/espressif/esp-idf.git")
+            .platform_package("framework-espidf", "/path/to/esp-idf");
+        builder.generate(&resolution()).unwrap();
+
+        let ini = fs::read_to_string(dir.path().join("platformio.ini")).unwrap();
+        assert!(ini.contains(
+            "platform_packages = \n  toolchain-xtensa-esp32@~8.4.0\n  platformio/tool-openocd @ ~2.1\n  https://github.com/espressif/esp-idf.git\n  framework-espidf@/path/to/esp-idf\n"
+        ));
+
+        builder.platform_package_spec("espressif/toolchain-xtensa-esp32@8.4.0+2021r2");
+        let err = builder.generate(&resolution()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Conflicting platform packages 'toolchain-xtensa-esp32@~8.4.0' and 'espressif/toolchain-xtensa-esp32@8.4.0+2021r2' for package 'toolchain-xtensa-esp32'"
+        );
+    }
+
+    #[test]
+    fn test_extra_scripts() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut builder = Builder::new(dir.path());
+        builder
+            .extra_script("pre:version.py", "Import('env')\n")
+            .unwrap()
+            .extra_script("post_elf.py", "Import('env')\n")
+            .unwrap()
+            .enable_scons_dump();
+
+        assert!(builder.extra_script("../escape.py", "").is_err());
+        assert!(builder.extra_script("pre:script.sh", "").is_err());
+        assert!(builder.extra_script("post:platformio.dump.py", "").is_err());
+        assert!(builder.extra_script("version.py", "").is_err());
+
+        builder.generate(&resolution()).unwrap();
+
+        let ini = fs::read_to_string(dir.path().join("platformio.ini")).unwrap();
+        assert!(ini.contains("extra_scripts = platformio.dump.py, pre:version.py, post_elf.py\n"));
+
+        let paths = builder.extra_script_paths().collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                dir.path().join("version.py"),
+                dir.path().join("post_elf.py")
+            ]
+        );
+        assert_eq!(fs::read_to_string(&paths[0]).unwrap(), "Import('env')\n");
+    }
+
+    fn resolution() -> Resolution {
+        Resolution {
+            board: "esp32dev".into(),
+            mcu: "ESP32".into(),
+            platform: "espressif32".into(),
+            frameworks: vec!["espidf".into()],
+            target: "xtensa-esp32-espidf".into(),
+        }
+    }
+
+    #[test]
+    fn test_options_from_ini() {
+        let dir = tempfile::tempdir().unwrap();
+        let fragment = dir.path().join("fragment.ini");
+        fs::write(
+            &fragment,
+            "; User options\n[env]\nmonitor_speed = 9600\nboard = esp32-s2-saola-1\n\n\
+             [env:release]\nbuild_flags = -DNDEBUG\n\n\
+             [env]\nmonitor_speed = 115200\nbuild_flags =\n  -DFOO=1\n  -DBAR\n",
+        )
+        .unwrap();
+
+        let mut builder = Builder::new(dir.path());
+        builder
+            .option("env", "board_build.partitions", "partitions.csv")
+            .options_from_ini(&fragment)
+            .unwrap()
+            .enable_scons_dump();
+        builder.generate(&resolution()).unwrap();
+
+        let ini = fs::read_to_string(dir.path().join("platformio.ini")).unwrap();
+        let ini = ini
+            .lines()
+            .skip_while(|l| l.starts_with(';'))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            ini,
+            [
+                "[platformio]",
+                "default_envs = debug",
+                "",
+                "[env]",
+                "extra_scripts = platformio.dump.py",
+                "board = esp32-s2-saola-1",
+                "platform = espressif32",
+                "framework = espidf",
+                "board_build.partitions = partitions.csv",
+                "monitor_speed = 115200",
+                "build_flags = ",
+                "  -DFOO=1",
+                "  -DBAR",
+                "",
+                "[env:debug]",
+                "build_type = debug",
+                "",
+                "[env:release]",
+                "build_type = release",
+                "build_flags = -DNDEBUG",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_regenerate_unchanged() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut builder = Builder::new(dir.path());
+        builder
+            .enable_scons_dump()
+            .extra_script("post:post_elf.py", "Import('env')\n")
+            .unwrap();
+        assert!(builder.regenerate(&resolution()).unwrap());
+
+        let old = filetime::FileTime::from_unix_time(1_000_000_000, 0);
+        let files = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        for file in &files {
+            filetime::set_file_mtime(file, old).unwrap();
+        }
+
+        assert!(!builder.regenerate(&resolution()).unwrap());
+        for file in &files {
+            let mtime =
+                filetime::FileTime::from_last_modification_time(&fs::metadata(file).unwrap());
+            assert_eq!(mtime, old, "{}", file.display());
+        }
+        assert_eq!(
+            fs::read_to_string(dir.path().join(".gitignore")).unwrap(),
+            ".pio\nCMakeFiles/\n"
+        );
+
+        builder.option("env", "monitor_speed", "115200");
+        assert!(builder.regenerate(&resolution()).unwrap());
+    }
+
+    #[test]
+    fn test_managed_option_conflict() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut builder = Builder::new(dir.path());
+        builder
+            .option("env:debug", "extra_scripts", "my_script.py")
+            .option("env", "monitor_speed", "115200")
+            .enable_scons_dump();
+
+        let err = builder.generate(&resolution()).unwrap_err();
+        assert!(err.to_string().contains("[env:debug] extra_scripts"));
+        assert!(!dir.path().join("platformio.ini").exists());
+    }
+}
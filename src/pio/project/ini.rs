@@ -0,0 +1,168 @@
+//! A minimal, order-preserving model of the `platformio.ini` project configuration file.
+
+use std::fmt;
+
+use anyhow::{bail, Result};
+
+/// An order-preserving `platformio.ini` file.
+///
+/// Sections and the options within a section are kept in the order they were first
+/// defined, so that merging options produces readable diffs. Defining a section or an
+/// option again updates it in place.
+///
+/// Only full-line comments (starting with `;` or `#`) are supported and they are not
+/// preserved.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Ini {
+    sections: Vec<Section>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Section {
+    name: String,
+    options: Vec<(String, String)>,
+}
+
+impl Ini {
+    /// Parse the contents of a `platformio.ini` file.
+    ///
+    /// Multi-line values (continuation lines indented with whitespace) are kept as is,
+    /// i.e. as the first line followed by the indented continuation lines.
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut ini = Self::default();
+        let mut section: Option<String> = None;
+        let mut last_key: Option<String> = None;
+
+        for (index, line) in content.lines().enumerate() {
+            let trimmed = line.trim();
+
+            if trimmed.is_empty() || trimmed.starts_with(';') || trimmed.starts_with('#') {
+                last_key = None;
+                continue;
+            }
+
+            if line.starts_with(char::is_whitespace) {
+                if let (Some(section), Some(key)) = (&section, &last_key) {
+                    let value = ini.get_mut(section, key).unwrap();
+                    value.push('\n');
+                    value.push_str(line.trim_end());
+                    continue;
+                }
+            }
+
+            if let Some(name) = trimmed.strip_prefix('[') {
+                let Some(name) = name.strip_suffix(']') else {
+                    bail!("Malformed section header '{trimmed}' in line {}", index + 1);
+                };
+
+                let name = name.trim().to_owned();
+                ini.section_mut(&name);
+                section = Some(name);
+                last_key = None;
+            } else {
+                let Some((key, value)) = trimmed.split_once('=') else {
+                    bail!("Malformed option '{trimmed}' in line {}", index + 1);
+                };
+                let Some(section) = &section else {
+                    bail!(
+                        "Option '{trimmed}' outside of a section in line {}",
+                        index + 1
+                    );
+                };
+
+                let key = key.trim().to_owned();
+                ini.set(section, &key, value.trim());
+                last_key = Some(key);
+            }
+        }
+
+        Ok(ini)
+    }
+
+    /// Set the option `key` in `section` to `value`, creating the section if needed.
+    pub fn set(&mut self, section: &str, key: &str, value: impl Into<String>) {
+        let value = value.into();
+        let options = &mut self.section_mut(section).options;
+
+        if let Some((_, existing)) = options.iter_mut().find(|(k, _)| k == key) {
+            *existing = value;
+        } else {
+            options.push((key.to_owned(), value));
+        }
+    }
+
+    /// Iterate over all options as `(section, key, value)` in order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str, &str)> {
+        self.sections.iter().flat_map(|s| {
+            s.options
+                .iter()
+                .map(move |(k, v)| (s.name.as_str(), k.as_str(), v.as_str()))
+        })
+    }
+
+    /// Merge all options of `other` into this file, with the options of `other` winning.
+    pub fn merge(&mut self, other: &Ini) {
+        for (section, key, value) in other.iter() {
+            self.set(section, key, value);
+        }
+    }
+
+    fn get_mut(&mut self, section: &str, key: &str) -> Option<&mut String> {
+        self.sections
+            .iter_mut()
+            .find(|s| s.name == section)?
+            .options
+            .iter_mut()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v)
+    }
+
+    fn section_mut(&mut self, name: &str) -> &mut Section {
+        let index = match self.sections.iter().position(|s| s.name == name) {
+            Some(index) => index,
+            None => {
+                self.sections.push(Section {
+                    name: name.to_owned(),
+                    options: Vec::new(),
+                });
+                self.sections.len() - 1
+            }
+        };
+
+        &mut self.sections[index]
+    }
+}
+
+impl fmt::Display for Ini {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, section) in self.sections.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+
+            writeln!(f, "[{}]", section.name)?;
+            for (key, value) in &section.options {
+                writeln!(f, "{key} = {value}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_round_trip() {
+        let content = "[env]\nboard = esp32dev\nlib_deps =\n  foo\n  bar\n\n[env:debug]\nbuild_type = debug\n";
+        let ini = Ini::parse(content).unwrap();
+
+        assert_eq!(
+            ini.to_string(),
+            "[env]\nboard = esp32dev\nlib_deps = \n  foo\n  bar\n\n[env:debug]\nbuild_type = debug\n"
+        );
+        assert_eq!(Ini::parse(&ini.to_string()).unwrap(), ini);
+    }
+}
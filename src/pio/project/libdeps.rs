@@ -0,0 +1,256 @@
+//! Resolution of the `lib_deps` library dependencies of a PlatformIO project.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::pio::Pio;
+
+/// A library dependency of a PlatformIO project.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct LibraryDep {
+    pub name: String,
+    pub version: Option<String>,
+    /// The directory the library is installed in.
+    pub dir: PathBuf,
+    /// The include directories of the library.
+    pub include_dirs: Vec<PathBuf>,
+    /// Whether the library is installed in the global library storage of PlatformIO
+    /// instead of the project.
+    pub global: bool,
+}
+
+/// The resolved library dependencies of a PlatformIO project environment.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct LibraryDeps {
+    pub libraries: Vec<LibraryDep>,
+}
+
+/// The `.piopm` package manifest PlatformIO writes into installed packages.
+#[derive(Deserialize)]
+struct PackageManifest {
+    name: String,
+    #[serde(default)]
+    version: Option<String>,
+}
+
+/// The relevant parts of the output of `pio project metadata --json-output`.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct EnvMetadata {
+    includes: EnvMetadataIncludes,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct EnvMetadataIncludes {
+    build: Vec<PathBuf>,
+    compatlib: Vec<PathBuf>,
+}
+
+impl LibraryDeps {
+    /// Install the `lib_deps` of the debug or `release` environment of the project at
+    /// `project_dir` and resolve their include directories.
+    ///
+    /// Both the libraries installed in the project and in the global library storage of
+    /// PlatformIO are considered.
+    pub fn resolve(pio: &Pio, project_dir: impl AsRef<Path>, release: bool) -> Result<Self> {
+        let project_dir = project_dir.as_ref();
+        let environment = if release { "release" } else { "debug" };
+
+        let mut cmd = pio.query_cmd();
+        cmd.arg("pkg")
+            .arg("install")
+            .arg("-d")
+            .arg(project_dir)
+            .arg("-e")
+            .arg(environment);
+        cmd.timeout(crate::pio::QUERY_TIMEOUT);
+
+        debug!("Running PlatformIO command {:?}", cmd.cmd);
+        cmd.run()
+            .context("Installing the PlatformIO library dependencies failed")?;
+
+        let mut cmd = pio.query_cmd();
+        cmd.arg("project")
+            .arg("metadata")
+            .arg("-d")
+            .arg(project_dir)
+            .arg("-e")
+            .arg(environment);
+
+        let mut metadata = Pio::query::<HashMap<String, EnvMetadata>>(cmd)?;
+        let metadata = metadata.remove(environment).ok_or_else(|| {
+            anyhow!("No PlatformIO project metadata for environment '{environment}'")
+        })?;
+
+        let lib_dirs = [
+            (
+                project_dir.join(".pio").join("libdeps").join(environment),
+                false,
+            ),
+            (pio.core_dir.join("lib"), true),
+        ];
+
+        Self::from_metadata(&metadata, &lib_dirs)
+    }
+
+    /// Collect the libraries installed in `lib_dirs` (with whether they are global).
+    fn from_metadata(metadata: &EnvMetadata, lib_dirs: &[(PathBuf, bool)]) -> Result<Self> {
+        let includes = metadata
+            .includes
+            .compatlib
+            .iter()
+            .chain(&metadata.includes.build)
+            .collect::<Vec<_>>();
+
+        let mut libraries = Vec::new();
+
+        for (lib_dir, global) in lib_dirs {
+            if !lib_dir.is_dir() {
+                continue;
+            }
+
+            let mut dirs = fs::read_dir(lib_dir)?
+                .map(|entry| Ok(entry?.path()))
+                .collect::<Result<Vec<_>>>()?;
+            dirs.sort();
+
+            for dir in dirs {
+                let manifest_file = dir.join(".piopm");
+                if !manifest_file.is_file() {
+                    continue;
+                }
+
+                let manifest: PackageManifest = serde_json::from_slice(&fs::read(&manifest_file)?)
+                    .with_context(|| {
+                        anyhow!("Invalid package manifest '{}'", manifest_file.display())
+                    })?;
+
+                let mut include_dirs = Vec::new();
+                for include in includes.iter().filter(|include| include.starts_with(&dir)) {
+                    if !include_dirs.contains(*include) {
+                        include_dirs.push((*include).clone());
+                    }
+                }
+
+                if include_dirs.is_empty() {
+                    // Not (yet) known to the build, assume the usual library layout
+                    let src_dir = dir.join("src");
+                    include_dirs.push(if src_dir.is_dir() {
+                        src_dir
+                    } else {
+                        dir.clone()
+                    });
+                }
+
+                libraries.push(LibraryDep {
+                    name: manifest.name,
+                    version: manifest.version,
+                    dir,
+                    include_dirs,
+                    global: *global,
+                });
+            }
+        }
+
+        Ok(Self { libraries })
+    }
+
+    /// The include directories of all libraries, without duplicates.
+    pub fn include_dirs(&self) -> Vec<&Path> {
+        let mut include_dirs = Vec::new();
+
+        for include_dir in self.libraries.iter().flat_map(|l| &l.include_dirs) {
+            if !include_dirs.contains(&include_dir.as_path()) {
+                include_dirs.push(include_dir.as_path());
+            }
+        }
+
+        include_dirs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let local = dir
+            .path()
+            .join("proj")
+            .join(".pio")
+            .join("libdeps")
+            .join("debug");
+        let global = dir.path().join("core").join("lib");
+
+        let json_lib = local.join("ArduinoJson");
+        fs::create_dir_all(json_lib.join("src")).unwrap();
+        fs::write(
+            json_lib.join(".piopm"),
+            r#"{"type": "library", "name": "ArduinoJson", "version": "6.21.3", "spec": {"owner": "bblanchon", "id": 64, "name": "ArduinoJson", "requirements": null, "uri": null}}"#,
+        )
+        .unwrap();
+
+        let other_lib = global.join("Other");
+        fs::create_dir_all(&other_lib).unwrap();
+        fs::write(
+            other_lib.join(".piopm"),
+            r#"{"type": "library", "name": "Other"}"#,
+        )
+        .unwrap();
+
+        let metadata: HashMap<String, EnvMetadata> = serde_json::from_value(serde_json::json!({
+            "debug": {
+                "env_name": "debug",
+                "includes": {
+                    "build": [dir.path().join("proj").join("include"), json_lib.join("src")],
+                    "compatlib": [json_lib.join("src")],
+                    "toolchain": []
+                },
+                "defines": ["PLATFORMIO=60115"]
+            }
+        }))
+        .unwrap();
+
+        let deps = LibraryDeps::from_metadata(
+            &metadata["debug"],
+            &[
+                (local, false),
+                (global, true),
+                (dir.path().join("missing"), true),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(
+            deps.libraries,
+            [
+                LibraryDep {
+                    name: "ArduinoJson".into(),
+                    version: Some("6.21.3".into()),
+                    dir: json_lib.clone(),
+                    include_dirs: vec![json_lib.join("src")],
+                    global: false,
+                },
+                LibraryDep {
+                    name: "Other".into(),
+                    version: None,
+                    dir: other_lib.clone(),
+                    include_dirs: vec![other_lib.clone()],
+                    global: true,
+                },
+            ]
+        );
+        assert_eq!(
+            deps.include_dirs(),
+            [json_lib.join("src").as_path(), other_lib.as_path()]
+        );
+    }
+}
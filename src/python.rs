@@ -1,9 +1,17 @@
 //! Python utilities.
 
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
 use anyhow::{anyhow, Context, Result};
 
 use crate::cmd;
 
+mod interpreter;
+mod venv;
+pub use interpreter::*;
+pub use venv::*;
+
 /// Python 3 executable name.
 ///
 /// `python` for Window, `python3` otherwise.
@@ -17,9 +25,59 @@ pub const PYTHON: &str = {
 };
 
 /// The Version of a Python Binary
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct PythonVersion {
     pub major: u32,
     pub minor: u32,
+    pub patch: u32,
+}
+
+impl PythonVersion {
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+}
+
+impl FromStr for PythonVersion {
+    type Err = anyhow::Error;
+
+    /// Parse a `<major>[.<minor>[.<patch>]]` version; trailing qualifiers of the patch
+    /// version (e.g. `3.13.0rc1`) are ignored.
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || anyhow!("Invalid python version '{s}'");
+        let number = |part: Option<&str>| -> Result<u32> {
+            match part {
+                None => Ok(0),
+                Some(part) => {
+                    let digits = part
+                        .find(|c: char| !c.is_ascii_digit())
+                        .unwrap_or(part.len());
+                    part[..digits].parse().map_err(|_| invalid())
+                }
+            }
+        };
+
+        let mut parts = s.trim().splitn(3, '.');
+        let major = parts.next().unwrap().parse().map_err(|_| invalid())?;
+        let minor = parts
+            .next()
+            .map(str::parse)
+            .transpose()
+            .map_err(|_| invalid())?;
+        let patch = number(parts.next())?;
+
+        Ok(Self::new(major, minor.unwrap_or(0), patch))
+    }
+}
+
+impl Display for PythonVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
 }
 
 /// Check that python is at least `major.minor`.
@@ -48,8 +106,9 @@ pub fn check_python_at_least(major: u32, minor: u32) -> Result<PythonVersion> {
 
     let python_major = version[0].unwrap();
     let python_minor = version[1].unwrap();
+    let python_patch = version.get(2).copied().flatten().unwrap_or(0);
 
-    if python_major < major || python_minor < minor {
+    if (python_major, python_minor) < (major, minor) {
         Err(anyhow!(
             "Invalid python version '{}'; expected at least {}.{}",
             version_str,
@@ -58,9 +117,23 @@ pub fn check_python_at_least(major: u32, minor: u32) -> Result<PythonVersion> {
         )
         .context(format!("When running '{PYTHON} --version'")))
     } else {
-        Ok(PythonVersion {
-            major: python_major,
-            minor: python_minor,
-        })
+        Ok(PythonVersion::new(python_major, python_minor, python_patch))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        let parse = |s: &str| s.parse::<PythonVersion>().ok();
+        assert_eq!(parse("3.12.1"), Some(PythonVersion::new(3, 12, 1)));
+        assert_eq!(parse("3.13.0rc1\n"), Some(PythonVersion::new(3, 13, 0)));
+        assert_eq!(parse("3.8"), Some(PythonVersion::new(3, 8, 0)));
+        assert_eq!(parse("3"), Some(PythonVersion::new(3, 0, 0)));
+        assert_eq!(parse("Python 3.8"), None);
+        assert_eq!(parse("3.x"), None);
+        assert!(PythonVersion::new(3, 10, 0) > PythonVersion::new(3, 9, 18));
     }
 }
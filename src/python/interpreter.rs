@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsString;
+use std::fmt::{self, Display};
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use anyhow::{anyhow, bail, Result};
+
+use super::PythonVersion;
+use crate::cmd;
+use crate::cmd::CmdError;
+use crate::utils::OsStrJoinExt;
+
+/// The environment variable overriding the python interpreter found by
+/// [`find_interpreter`].
+pub const VAR_EMBUILD_PYTHON: &str = "EMBUILD_PYTHON";
+
+/// The environment variable overriding the python interpreter found by
+/// [`find_interpreter`] if [`VAR_EMBUILD_PYTHON`] is not set.
+pub const VAR_PYTHON: &str = "PYTHON";
+
+/// The newest python 3 minor version probed by [`find_interpreter`] with a versioned
+/// name (e.g. `python3.14`).
+const NEWEST_MINOR: u32 = 14;
+
+/// The oldest python 3 minor version probed by [`find_interpreter`] with a versioned
+/// name (e.g. `python3.8`).
+const OLDEST_MINOR: u32 = 8;
+
+/// A snippet printing the path and the version of the interpreter, compatible with
+/// python 2.
+const PROBE: &str =
+    "import sys; sys.stdout.write('%s\\n%d.%d.%d\\n' % ((sys.executable,) + tuple(sys.version_info[:3])))";
+
+/// The constraint and override of a [`find_interpreter`] call.
+type CacheKey = (VersionReq, Option<OsString>);
+
+/// A python interpreter found by [`find_interpreter`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PythonInterpreter {
+    /// The absolute path of the interpreter.
+    pub path: PathBuf,
+    /// The version of the interpreter.
+    pub version: PythonVersion,
+}
+
+/// A constraint on a python version of comma-separated comparisons, e.g. `>=3.8` or
+/// `>=3.8, <3.13`.
+///
+/// The supported operators are `==`, `!=`, `>=`, `>`, `<=` and `<`; omitted minor and
+/// patch versions are zero.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct VersionReq {
+    comparators: Vec<(Op, PythonVersion)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Op {
+    Eq,
+    Ne,
+    Ge,
+    Gt,
+    Le,
+    Lt,
+}
+
+impl Op {
+    const ALL: [(Op, &'static str); 6] = [
+        (Op::Eq, "=="),
+        (Op::Ne, "!="),
+        (Op::Ge, ">="),
+        (Op::Gt, ">"),
+        (Op::Le, "<="),
+        (Op::Lt, "<"),
+    ];
+
+    fn as_str(self) -> &'static str {
+        Self::ALL.iter().find(|(op, _)| *op == self).unwrap().1
+    }
+}
+
+impl VersionReq {
+    /// Require at least python `major.minor`.
+    pub fn at_least(major: u32, minor: u32) -> Self {
+        Self {
+            comparators: vec![(Op::Ge, PythonVersion::new(major, minor, 0))],
+        }
+    }
+
+    /// Whether `version` satisfies all comparisons of this constraint.
+    pub fn matches(&self, version: &PythonVersion) -> bool {
+        self.comparators.iter().all(|(op, other)| match op {
+            Op::Eq => version == other,
+            Op::Ne => version != other,
+            Op::Ge => version >= other,
+            Op::Gt => version > other,
+            Op::Le => version <= other,
+            Op::Lt => version < other,
+        })
+    }
+}
+
+impl FromStr for VersionReq {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let comparators = s
+            .split(',')
+            .map(|comparator| {
+                let comparator = comparator.trim();
+                let (op, version) = Op::ALL
+                    .iter()
+                    .find_map(|(op, prefix)| Some((*op, comparator.strip_prefix(prefix)?)))
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "Invalid python version constraint '{s}'; expected e.g. '>=3.8, <3.13'"
+                        )
+                    })?;
+                Ok((op, version.trim().parse()?))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self { comparators })
+    }
+}
+
+impl Display for VersionReq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (op, version)) in self.comparators.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}{version}", op.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+/// Find a python interpreter whose version satisfies `constraint`.
+///
+/// If the [`VAR_EMBUILD_PYTHON`] or [`VAR_PYTHON`] environment variable is set, only
+/// the interpreter it names is considered. Otherwise `python3`, `python`, `py -3` (on
+/// Windows) and the versioned names `python3.14` down to `python3.8` are probed in
+/// that order, and the first one satisfying the constraint is returned. The error if
+/// none does lists all probed interpreters and their versions.
+///
+/// The result is cached for the lifetime of the process.
+pub fn find_interpreter(constraint: &VersionReq) -> Result<PythonInterpreter> {
+    static CACHE: Mutex<Option<HashMap<CacheKey, PythonInterpreter>>> = Mutex::new(None);
+
+    let over = env::var_os(VAR_EMBUILD_PYTHON).or_else(|| env::var_os(VAR_PYTHON));
+    let key = (constraint.clone(), over.clone());
+    if let Some(interpreter) = CACHE
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .get(&key)
+    {
+        return Ok(interpreter.clone());
+    }
+
+    let candidates = match over {
+        Some(python) => vec![vec![python]],
+        None => default_candidates(),
+    };
+    let interpreter = find_in(constraint, &candidates)?;
+
+    CACHE
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .insert(key, interpreter.clone());
+    Ok(interpreter)
+}
+
+/// Get the commands (program and arguments) of all probed interpreters.
+fn default_candidates() -> Vec<Vec<OsString>> {
+    let mut candidates = vec![vec!["python3".into()], vec!["python".into()]];
+    if cfg!(windows) {
+        candidates.push(vec!["py".into(), "-3".into()]);
+    }
+    candidates.extend(
+        (OLDEST_MINOR..=NEWEST_MINOR)
+            .rev()
+            .map(|minor| vec![format!("python3.{minor}").into()]),
+    );
+    candidates
+}
+
+/// Find the first interpreter of `candidates` satisfying `constraint`.
+fn find_in(constraint: &VersionReq, candidates: &[Vec<OsString>]) -> Result<PythonInterpreter> {
+    let mut probed = Vec::new();
+    for candidate in candidates {
+        let name = candidate.iter().join_os(" ").to_string_lossy().into_owned();
+
+        match probe(candidate) {
+            Ok(interpreter) if constraint.matches(&interpreter.version) => {
+                log::debug!(
+                    "Using python {} at '{}' ({name})",
+                    interpreter.version,
+                    interpreter.path.display()
+                );
+                return Ok(interpreter);
+            }
+            Ok(interpreter) => probed.push(format!("{name}: {}", interpreter.version)),
+            Err(e) => probed.push(format!("{name}: {e}")),
+        }
+    }
+
+    bail!(
+        "Could not find a python interpreter matching '{constraint}' (set `{VAR_EMBUILD_PYTHON}` to use a specific one); probed:\n - {}",
+        probed.join("\n - ")
+    )
+}
+
+/// Run the interpreter `command` to get its path and version.
+fn probe(command: &[OsString]) -> Result<PythonInterpreter> {
+    let output = cmd!(&command[0], @&command[1..], "-c", PROBE)
+        .stdout()
+        .map_err(|e| match e {
+            CmdError::NoRun(_, e) if e.kind() == io::ErrorKind::NotFound => anyhow!("not found"),
+            CmdError::NoRun(_, e) => anyhow!("failed to start: {e}"),
+            CmdError::Unsuccessful(_, code, _) => anyhow!("exited with status code {code}"),
+            e => anyhow!("failed to run: {e}"),
+        })?;
+
+    let mut lines = output.lines();
+    let (Some(path), Some(version)) = (lines.next(), lines.next()) else {
+        bail!("unexpected output '{output}'");
+    };
+
+    Ok(PythonInterpreter {
+        path: PathBuf::from(path),
+        version: version.parse()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::python::PYTHON;
+
+    #[test]
+    fn test_version_req() {
+        let req: VersionReq = ">=3.8, <3.13".parse().unwrap();
+        assert_eq!(req.to_string(), ">=3.8.0, <3.13.0");
+        assert!(req.matches(&PythonVersion::new(3, 8, 0)));
+        assert!(req.matches(&PythonVersion::new(3, 12, 9)));
+        assert!(!req.matches(&PythonVersion::new(3, 13, 0)));
+        assert!(!req.matches(&PythonVersion::new(2, 7, 18)));
+
+        let req: VersionReq = "==3.11.4".parse().unwrap();
+        assert!(req.matches(&PythonVersion::new(3, 11, 4)));
+        assert!(!req.matches(&PythonVersion::new(3, 11, 5)));
+
+        assert!(VersionReq::at_least(3, 8).matches(&PythonVersion::new(4, 0, 0)));
+        assert!("3.8".parse::<VersionReq>().is_err());
+        assert!(">=three".parse::<VersionReq>().is_err());
+    }
+
+    #[test]
+    fn test_find_in() {
+        let candidates = [
+            vec!["embuild-nonexistent-python".into()],
+            vec![PYTHON.into()],
+        ];
+        let Ok(found) = find_in(&VersionReq::at_least(3, 0), &candidates) else {
+            eprintln!("Skipping test: no python found");
+            return;
+        };
+        assert!(found.path.is_absolute());
+
+        let error = find_in(&VersionReq::at_least(99, 0), &candidates).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Could not find a python interpreter matching '>=99.0.0' (set `EMBUILD_PYTHON` to use a specific one); probed:\n - embuild-nonexistent-python: not found\n - {PYTHON}: {}",
+                found.version
+            )
+        );
+    }
+}
@@ -0,0 +1,256 @@
+use std::ffi::OsStr;
+use std::fmt::{self, Display};
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::cargo::ScratchDir;
+use crate::cmd;
+use crate::cmd::{Cmd, EnvSet};
+use crate::logging::warning;
+use crate::utils::StableHasher;
+
+/// A pip requirement of a [`VirtualEnv`], optionally pinned to a version.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Requirement {
+    name: String,
+    version: Option<String>,
+}
+
+impl Requirement {
+    /// Require the package `name` in any version.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            version: None,
+        }
+    }
+
+    /// Pin the requirement to exactly `version`.
+    pub fn version(mut self, version: impl Into<String>) -> Self {
+        self.version = Some(version.into());
+        self
+    }
+}
+
+impl Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.version {
+            Some(version) => write!(f, "{}=={version}", self.name),
+            None => f.write_str(&self.name),
+        }
+    }
+}
+
+/// A python virtual environment with installed [`Requirement`]s.
+#[derive(Debug, Clone)]
+pub struct VirtualEnv {
+    dir: PathBuf,
+}
+
+impl VirtualEnv {
+    /// Create the virtual environment `dir` with the base interpreter `python` (e.g.
+    /// [`PYTHON`](super::PYTHON)) if it doesn't exist yet, and install the
+    /// `requirements` into it.
+    ///
+    /// The requirements are only installed if they changed since the last call, as
+    /// recorded by a hash of them stored in the virtual environment. A virtual
+    /// environment whose base interpreter was removed (or, with the `cache` feature,
+    /// upgraded) is recreated. The directory is
+    /// locked (see [`ScratchDir`]) so that multiple build scripts can ensure the same
+    /// virtual environment concurrently.
+    pub fn ensure(
+        dir: impl AsRef<Path>,
+        python: impl AsRef<OsStr>,
+        requirements: &[Requirement],
+    ) -> Result<VirtualEnv> {
+        let venv = VirtualEnv {
+            dir: dir.as_ref().to_owned(),
+        };
+        let scratch = ScratchDir::open(&venv.dir)?;
+
+        let exists = venv.dir.join("pyvenv.cfg").is_file();
+        let intact = venv.is_intact();
+        if exists && !intact {
+            warning!(
+                "Recreating the python virtual environment '{}', as it is broken (was its base interpreter removed?)",
+                venv.dir.display()
+            );
+        }
+        if !intact {
+            scratch.clean()?;
+        }
+        // Upgrading the base interpreter in place breaks the installed packages.
+        #[cfg(feature = "cache")]
+        let intact = !crate::cache::validate_or_clean(
+            &venv.dir,
+            &crate::cache::CacheKey::new("python-venv/1")
+                .any_embuild_version()
+                .tool("python", cmd!(python.as_ref(), "--version").stdout()?),
+        )? && intact;
+
+        if !intact {
+            cmd!(python.as_ref(), "-m", "venv", &venv.dir)
+                .run()
+                .with_context(|| {
+                    anyhow!(
+                        "could not create the python virtual environment '{}'",
+                        venv.dir.display()
+                    )
+                })?;
+        }
+
+        let hash = requirements_hash(requirements);
+        if scratch.manifest_hash().as_deref() != Some(hash.as_str()) {
+            if !requirements.is_empty() {
+                log::debug!(
+                    "Installing python requirements into '{}'",
+                    venv.dir.display()
+                );
+                let mut pip = venv.command("python");
+                pip.args(["-m", "pip", "install", "--disable-pip-version-check"])
+                    .args(requirements.iter().map(Requirement::to_string));
+                pip.run()
+                    .context("could not install the python requirements")?;
+            }
+            scratch.set_manifest_hash(hash)?;
+        }
+
+        Ok(venv)
+    }
+
+    /// Get the directory of the virtual environment.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Get the directory of the executables of the virtual environment (`Scripts` on
+    /// Windows, `bin` otherwise).
+    pub fn bin_dir(&self) -> PathBuf {
+        self.dir.join(if cfg!(windows) { "Scripts" } else { "bin" })
+    }
+
+    /// Get the path of the python interpreter of the virtual environment.
+    pub fn python_path(&self) -> PathBuf {
+        self.bin_dir()
+            .join(format!("python{}", std::env::consts::EXE_SUFFIX))
+    }
+
+    /// Get the environment of the activated virtual environment.
+    pub fn env(&self) -> EnvSet {
+        EnvSet::new()
+            .set("VIRTUAL_ENV", &self.dir)
+            .remove("PYTHONHOME")
+            .path_prepend([self.bin_dir()])
+    }
+
+    /// Create a command running `program` in the virtual environment.
+    ///
+    /// `program` is taken from the [`bin_dir`](VirtualEnv::bin_dir) if it exists there.
+    pub fn command(&self, program: impl AsRef<OsStr>) -> Cmd {
+        let mut exe = program.as_ref().to_owned();
+        exe.push(std::env::consts::EXE_SUFFIX);
+        let in_venv = self.bin_dir().join(exe);
+
+        let mut cmd = if in_venv.is_file() {
+            Cmd::new(in_venv)
+        } else {
+            Cmd::new(program)
+        };
+        cmd.envs_from(&self.env());
+        cmd
+    }
+
+    /// Whether the interpreter of the virtual environment and its base interpreter
+    /// exist.
+    fn is_intact(&self) -> bool {
+        let Ok(cfg) = std::fs::read_to_string(self.dir.join("pyvenv.cfg")) else {
+            return false;
+        };
+        let value = |key: &str| {
+            cfg.lines().find_map(|line| {
+                let (k, v) = line.split_once('=')?;
+                (k.trim() == key).then(|| PathBuf::from(v.trim()))
+            })
+        };
+
+        // `python_path` is a symlink to the base interpreter on unix.
+        self.python_path().is_file()
+            && value("home").map_or(true, |home| home.is_dir())
+            && value("executable").map_or(true, |exe| exe.is_file())
+    }
+}
+
+fn requirements_hash(requirements: &[Requirement]) -> String {
+    let mut hasher = StableHasher::new();
+    requirements.hash(&mut hasher);
+    hasher.finish_hex()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::python::PYTHON;
+
+    #[test]
+    fn test_requirements() {
+        let requirements = [
+            Requirement::new("pyparsing").version("3.0.9"),
+            Requirement::new("kconfiglib"),
+        ];
+        assert_eq!(
+            requirements
+                .iter()
+                .map(|r| r.to_string())
+                .collect::<Vec<_>>(),
+            ["pyparsing==3.0.9", "kconfiglib"]
+        );
+        assert_ne!(
+            requirements_hash(&requirements),
+            requirements_hash(&requirements[..1])
+        );
+    }
+
+    #[test]
+    fn test_virtual_env() {
+        if cmd!(PYTHON, "-m", "venv", "--help").stdout().is_err() {
+            eprintln!("Skipping test: no python with the venv module");
+            return;
+        }
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let venv = VirtualEnv::ensure(dir, PYTHON, &[]).unwrap();
+        assert!(venv.python_path().is_file());
+        let mut python = venv.command("python");
+        python.args([
+            "-c",
+            "import os, sys; print(sys.prefix == os.environ['VIRTUAL_ENV'])",
+        ]);
+        assert_eq!(python.stdout().unwrap(), "True");
+
+        // An intact virtual environment is reused.
+        let marker = dir.join("marker");
+        std::fs::write(&marker, "").unwrap();
+        VirtualEnv::ensure(dir, PYTHON, &[]).unwrap();
+        assert!(marker.exists());
+
+        // A virtual environment whose base interpreter is gone is recreated.
+        let cfg = std::fs::read_to_string(dir.join("pyvenv.cfg"))
+            .unwrap()
+            .lines()
+            .map(|line| match line.split_once(" = ") {
+                Some(("home" | "executable", _)) => {
+                    format!("{} = /nonexistent", &line[..line.find(' ').unwrap()])
+                }
+                _ => line.to_owned(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        std::fs::write(dir.join("pyvenv.cfg"), cfg).unwrap();
+        let venv = VirtualEnv::ensure(dir, PYTHON, &[]).unwrap();
+        assert!(!marker.exists());
+        assert!(venv.is_intact());
+    }
+}
@@ -0,0 +1,613 @@
+//! Firmware size reports and budgets.
+//!
+//! A [`SizeReport`] has the IRAM, DRAM, flash code and flash rodata usage of a firmware
+//! ELF, parsed from the output of the GNU `size` tool or of esp-idf's `idf.py size
+//! --format json`, optionally attributed to the archives (and object files) they come
+//! from with the linker map. [`SizeReport::check`] fails with all budgets the usage
+//! exceeds, so that build scripts or xtasks can enforce them, and the report can be
+//! serialized to JSON to compare sizes between commits in CI.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fmt::{self, Display, Write as _};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::artifacts::Artifacts;
+use crate::cmd;
+
+/// A memory region whose usage is reported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Region {
+    /// Code and data in the instruction RAM (`.iram0.*` sections).
+    Iram,
+    /// Data in the data RAM (`.dram0.*` sections).
+    Dram,
+    /// Code executed from flash (`.flash.text`).
+    FlashCode,
+    /// Read-only data in flash (`.flash.rodata` and `.flash.appdesc`).
+    FlashRodata,
+}
+
+impl Region {
+    pub const ALL: [Region; 4] = [
+        Region::Iram,
+        Region::Dram,
+        Region::FlashCode,
+        Region::FlashRodata,
+    ];
+
+    /// Get the region of the ELF output section `section`, if it is one of the reported
+    /// regions.
+    pub fn of_section(section: &str) -> Option<Region> {
+        if section.starts_with(".iram") {
+            Some(Region::Iram)
+        } else if section.starts_with(".dram") {
+            Some(Region::Dram)
+        } else if section == ".flash.text" {
+            Some(Region::FlashCode)
+        } else if section == ".flash.rodata" || section == ".flash.appdesc" {
+            Some(Region::FlashRodata)
+        } else {
+            None
+        }
+    }
+}
+
+impl Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Iram => "IRAM",
+            Self::Dram => "DRAM",
+            Self::FlashCode => "flash code",
+            Self::FlashRodata => "flash rodata",
+        })
+    }
+}
+
+/// The usage of the reported memory regions by an archive (see
+/// [`SizeReport::per_archive`]).
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveSize {
+    pub iram: u64,
+    pub dram: u64,
+    pub flash_code: u64,
+    pub flash_rodata: u64,
+}
+
+impl ArchiveSize {
+    /// The usage of `region`.
+    pub fn get(&self, region: Region) -> u64 {
+        match region {
+            Region::Iram => self.iram,
+            Region::Dram => self.dram,
+            Region::FlashCode => self.flash_code,
+            Region::FlashRodata => self.flash_rodata,
+        }
+    }
+
+    fn get_mut(&mut self, region: Region) -> &mut u64 {
+        match region {
+            Region::Iram => &mut self.iram,
+            Region::Dram => &mut self.dram,
+            Region::FlashCode => &mut self.flash_code,
+            Region::FlashRodata => &mut self.flash_rodata,
+        }
+    }
+
+    /// The usage of all regions.
+    pub fn total(&self) -> u64 {
+        Region::ALL.iter().map(|r| self.get(*r)).sum()
+    }
+}
+
+/// The memory usage of a firmware.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SizeReport {
+    /// The used IRAM in bytes.
+    pub iram: u64,
+    /// The used DRAM in bytes.
+    pub dram: u64,
+    /// The code in flash in bytes.
+    pub flash_code: u64,
+    /// The read-only data in flash in bytes.
+    pub flash_rodata: u64,
+    /// The usage by archive (or object file if it is not part of an archive) file name,
+    /// if a linker map was parsed (see [`SizeReport::with_map`]).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub per_archive: BTreeMap<String, ArchiveSize>,
+}
+
+impl SizeReport {
+    /// The usage of `region`.
+    pub fn get(&self, region: Region) -> u64 {
+        match region {
+            Region::Iram => self.iram,
+            Region::Dram => self.dram,
+            Region::FlashCode => self.flash_code,
+            Region::FlashRodata => self.flash_rodata,
+        }
+    }
+
+    fn add(&mut self, region: Region, size: u64) {
+        *match region {
+            Region::Iram => &mut self.iram,
+            Region::Dram => &mut self.dram,
+            Region::FlashCode => &mut self.flash_code,
+            Region::FlashRodata => &mut self.flash_rodata,
+        } += size;
+    }
+
+    /// Parse the output of the GNU `size` tool in the SysV format (`size -A`), or in the
+    /// default Berkeley format.
+    ///
+    /// The Berkeley format only has the `text`, `data` and `bss` totals, which are
+    /// reported as flash code (`text`) and DRAM (`data` and `bss`).
+    pub fn from_size_output(output: &str) -> Result<Self> {
+        let lines = output.lines().map(str::trim).filter(|l| !l.is_empty());
+
+        let mut report = Self::default();
+        if let Some(header) = lines.clone().find(|l| l.starts_with("text")) {
+            let values = lines
+                .skip_while(|l| *l != header)
+                .nth(1)
+                .ok_or_else(|| anyhow!("No sizes after the header of the `size` output"))?
+                .split_whitespace()
+                .take(3)
+                .map(str::parse::<u64>)
+                .collect::<Result<Vec<_>, _>>()
+                .context("Invalid `size` output")?;
+            let [text, data, bss] = values[..] else {
+                bail!("Invalid `size` output: expected the text, data and bss sizes");
+            };
+            report.flash_code = text;
+            report.dram = data + bss;
+            return Ok(report);
+        }
+
+        let mut sections = 0;
+        for line in lines {
+            let mut fields = line.split_whitespace();
+            let (Some(section), Some(size)) = (fields.next(), fields.next()) else {
+                continue;
+            };
+            let (Some(region), Ok(size)) = (Region::of_section(section), size.parse::<u64>())
+            else {
+                continue;
+            };
+            report.add(region, size);
+            sections += 1;
+        }
+        if sections == 0 {
+            bail!("No sections found in the `size` output");
+        }
+        Ok(report)
+    }
+
+    /// Parse the output of `idf.py size --format json` (or of `idf_size.py` or
+    /// `esp_idf_size` with the JSON formats).
+    pub fn from_idf_json(json: &str) -> Result<Self> {
+        let value: serde_json::Value =
+            serde_json::from_str(json).context("Invalid esp-idf size JSON")?;
+
+        let mut report = Self::default();
+        // The `json2` format of `esp_idf_size` lists the sections of each memory type,
+        // with some sections listed for multiple memory types.
+        if let Some(memory_types) = value.get("memory_types").and_then(|v| v.as_object()) {
+            let sections = memory_types
+                .values()
+                .filter_map(|m| m.get("sections")?.as_object())
+                .flatten()
+                .filter_map(|(name, s)| Some((name.as_str(), s.get("size")?.as_u64()?)))
+                .collect::<BTreeMap<_, _>>();
+            for (section, size) in sections {
+                if let Some(region) = Region::of_section(section) {
+                    report.add(region, size);
+                }
+            }
+            return Ok(report);
+        }
+
+        let get = |key: &str| value.get(key).and_then(|v| v.as_u64());
+        let sum = |keys: &[&str]| keys.iter().filter_map(|k| get(k)).sum::<u64>();
+        if get("flash_code").is_none() {
+            bail!("Unknown esp-idf size JSON format: no `flash_code` or `memory_types`");
+        }
+
+        report.iram = get("used_iram")
+            .unwrap_or_else(|| sum(&["iram_vectors", "iram_text", "iram_other"]))
+            + sum(&["diram_text", "diram_vectors"]);
+        report.dram = get("used_dram")
+            .unwrap_or_else(|| sum(&["dram_data", "dram_bss", "dram_rodata", "dram_other"]))
+            + sum(&["diram_data", "diram_bss", "diram_rodata"]);
+        report.flash_code = sum(&["flash_code"]);
+        report.flash_rodata = sum(&["flash_rodata"]);
+        Ok(report)
+    }
+
+    /// Attribute the usage to archives (and object files) with the GNU linker map `map`
+    /// (see [`per_archive`](Self::per_archive)).
+    pub fn with_map(mut self, map: &str) -> Self {
+        self.per_archive = parse_map(map);
+        self
+    }
+
+    /// Run the GNU `size` tool `size_tool` on the ELF file `elf` and parse its output,
+    /// attributing the usage to archives with the linker map next to the ELF (with the
+    /// `.map` extension) if it exists.
+    pub fn from_elf(size_tool: impl AsRef<OsStr>, elf: impl AsRef<Path>) -> Result<Self> {
+        let elf = elf.as_ref();
+        let output = cmd!(size_tool.as_ref(), "-A", elf).stdout()?;
+        let report = Self::from_size_output(&output)
+            .with_context(|| anyhow!("Could not get the size of '{}'", elf.display()))?;
+
+        match fs::read_to_string(elf.with_extension("map")) {
+            Ok(map) => Ok(report.with_map(&map)),
+            Err(_) => Ok(report),
+        }
+    }
+
+    /// Get the size report of the firmware ELF of `artifacts` (see [`Artifacts::elf`]) for
+    /// the rust `target` (e.g. `xtensa-esp32-espidf`), with the `size` tool of its
+    /// toolchain (see [`find_size_tool`]).
+    pub fn from_artifacts(
+        artifacts: &Artifacts,
+        target: &str,
+        path: Option<&OsStr>,
+    ) -> Result<Self> {
+        let elf = artifacts.elf()?;
+        Self::from_elf(find_size_tool(target, path)?, elf)
+    }
+
+    /// Check the usage against the `budgets`.
+    ///
+    /// Fails with an error listing all regions whose usage exceeds their budget.
+    pub fn check(&self, budgets: &Budgets) -> Result<()> {
+        let mut exceeded = String::new();
+        for (region, budget) in &budgets.0 {
+            let used = self.get(*region);
+            if used > *budget {
+                write!(
+                    exceeded,
+                    "\n- {region}: {used} bytes used, budget is {budget} bytes ({} bytes over)",
+                    used - budget
+                )?;
+                let mut archives = self
+                    .per_archive
+                    .iter()
+                    .map(|(name, size)| (size.get(*region), name))
+                    .filter(|(size, _)| *size > 0)
+                    .collect::<Vec<_>>();
+                archives.sort_by(|a, b| b.cmp(a));
+                if !archives.is_empty() {
+                    let largest = archives
+                        .iter()
+                        .take(3)
+                        .map(|(size, name)| format!("{name} ({size} bytes)"))
+                        .collect::<Vec<_>>();
+                    write!(exceeded, "; largest: {}", largest.join(", "))?;
+                }
+            }
+        }
+        if !exceeded.is_empty() {
+            bail!("The firmware exceeds its size budgets:{exceeded}");
+        }
+        Ok(())
+    }
+}
+
+/// The maximum usage of memory regions (see [`SizeReport::check`]).
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Budgets(BTreeMap<Region, u64>);
+
+impl Budgets {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Limit the usage of `region` to `bytes`.
+    #[must_use]
+    pub fn limit(mut self, region: Region, bytes: u64) -> Self {
+        self.0.insert(region, bytes);
+        self
+    }
+
+    /// The budget of `region`, if any.
+    pub fn get(&self, region: Region) -> Option<u64> {
+        self.0.get(&region).copied()
+    }
+}
+
+/// Get the names of the GNU `size` tool of the esp-idf toolchains for the rust `target`
+/// (e.g. `xtensa-esp32-espidf` or `riscv32imc-esp-espidf`), the preferred one first.
+pub fn size_tool_names(target: &str) -> Vec<String> {
+    let (arch, rest) = target.split_once('-').unwrap_or((target, ""));
+    if arch == "xtensa" {
+        let mcu = rest.split('-').next().unwrap_or_default();
+        // The toolchains of all xtensa chips were merged in esp-idf v5.2.
+        vec![
+            "xtensa-esp-elf-size".to_owned(),
+            format!("xtensa-{mcu}-elf-size"),
+        ]
+    } else if arch.starts_with("riscv32") {
+        vec!["riscv32-esp-elf-size".to_owned()]
+    } else {
+        vec!["size".to_owned()]
+    }
+}
+
+/// Find the GNU `size` tool for the rust `target` (see [`size_tool_names`]) in `path`
+/// (e.g. the `PATH` of the activated esp-idf), or in the `PATH` if [`None`].
+pub fn find_size_tool(target: &str, path: Option<&OsStr>) -> Result<PathBuf> {
+    let path = path.map(OsString::from).or_else(|| env::var_os("PATH"));
+    let names = size_tool_names(target);
+    names
+        .iter()
+        .find_map(|name| which::which_in(name, path.as_ref(), env::current_dir().ok()?).ok())
+        .ok_or_else(|| {
+            anyhow!(
+                "Could not find the size tool of the toolchain for target '{target}' (tried {})",
+                names.join(", ")
+            )
+        })
+}
+
+/// Parse the usage of all archives (and object files) from the GNU linker map `map`.
+fn parse_map(map: &str) -> BTreeMap<String, ArchiveSize> {
+    let mut archives = BTreeMap::<String, ArchiveSize>::new();
+    let mut attribute = |region: Region, size: &str, file: &[&str]| {
+        let (Some(size), false) = (parse_hex(size), file.is_empty()) else {
+            return;
+        };
+        if size == 0 {
+            return;
+        }
+        let file = file.join(" ");
+        let file = file
+            .split_once('(')
+            .map_or(file.as_str(), |(archive, _)| archive);
+        let name = file.rsplit(['/', '\\']).next().unwrap_or(file);
+        *archives.entry(name.to_owned()).or_default().get_mut(region) += size;
+    };
+
+    let mut region = None;
+    // Whether the address, size and file of an input section are on the next line.
+    let mut pending = false;
+    for line in map
+        .lines()
+        .skip_while(|l| !l.starts_with("Linker script and memory map"))
+        .skip(1)
+    {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if !line.starts_with(' ') {
+            // An output section (or a linker script directive).
+            region = line.split_whitespace().next().and_then(Region::of_section);
+            pending = false;
+            continue;
+        }
+        let Some(region) = region else {
+            continue;
+        };
+
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        if !line.starts_with("  ") {
+            // An input section with its address, size and file, or only its name if it
+            // is too long.
+            pending = false;
+            match fields[..] {
+                [name] => pending = name.starts_with('.') || name == "COMMON",
+                [_, address, size, ref file @ ..] if parse_hex(address).is_some() => {
+                    attribute(region, size, file)
+                }
+                _ => (),
+            }
+        } else if pending {
+            pending = false;
+            if let [address, size, ref file @ ..] = fields[..] {
+                if parse_hex(address).is_some() {
+                    attribute(region, size, file);
+                }
+            }
+        }
+    }
+    archives
+}
+
+fn parse_hex(value: &str) -> Option<u64> {
+    u64::from_str_radix(value.strip_prefix("0x")?, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE_SYSV: &str = "\
+app.elf  :
+section                size         addr
+.rtc.text                 0   1074528256
+.iram0.vectors         1027   1074266112
+.iram0.text           54321   1074267140
+.dram0.data            1234   1073405952
+.dram0.bss             5678   1073407188
+.flash.appdesc          256   1061158912
+.flash.rodata         45678   1061159168
+.flash.text          123456   1074593824
+.debug_info         9999999            0
+Total               10230649
+";
+
+    const MAP: &str = "\
+Archive member included to satisfy reference by file (symbol)
+
+/esp/libfreertos.a(port.c.obj)
+                              (vPortYield)
+
+Discarded input sections
+
+ .text          0x00000000        0x0 /esp/libmain.a(main.c.obj)
+
+Linker script and memory map
+
+LOAD /esp/libmain.a
+.iram0.text     0x40080400      0x100
+ *(.iram1 .iram1.*)
+ .iram1.5       0x40080400       0x44 /esp/libfreertos.a(port.c.obj)
+                0x40080400                vPortYield
+ .iram1.a_very_long_input_section_name
+                0x40080444       0x3c /esp/libfreertos.a(tasks.c.obj)
+ *fill*         0x40080480        0x4
+ .iram1.1       0x40080484       0x7c C:\\esp\\build\\libesp_system.a(cpu_start.c.obj)
+
+.dram0.bss      0x3ffb0000       0x20
+ COMMON         0x3ffb0000       0x20 /esp/libmain.a(main.c.obj)
+
+.flash.text     0x400d0020     0x1000
+ .text.app_main
+                0x400d0020      0x800 /esp/libmain.a(main.c.obj)
+ .text.main     0x400d0820      0x800 /target/debug/deps/libapp-1234.rlib(app-1234.app.cgu.0.rcgu.o)
+
+.debug_info     0x00000000    0x10000
+ .debug_info    0x00000000     0x8000 /esp/libmain.a(main.c.obj)
+";
+
+    #[test]
+    fn test_parse() {
+        let report = SizeReport::from_size_output(SIZE_SYSV).unwrap();
+        assert_eq!(report.iram, 55348);
+        assert_eq!(report.dram, 6912);
+        assert_eq!(report.flash_code, 123456);
+        assert_eq!(report.flash_rodata, 45934);
+
+        let report = SizeReport::from_size_output(
+            "   text\t   data\t    bss\t    dec\t    hex\tfilename\n 123456\t   1234\t   5678\t 130368\t  1fd40\tapp.elf\n",
+        )
+        .unwrap();
+        assert_eq!((report.flash_code, report.dram), (123456, 6912));
+        assert!(SizeReport::from_size_output("size: 'app.elf': No such file").is_err());
+
+        let legacy = r#"{
+            "dram_data": 9324, "dram_bss": 8296, "dram_rodata": 0, "dram_other": 0,
+            "used_dram": 17620, "dram_total": 180736, "used_dram_ratio": 0.097,
+            "iram_vectors": 1024, "iram_text": 37908, "iram_other": 0, "used_iram": 38932,
+            "flash_code": 80290, "flash_rodata": 39300, "total_size": 175186
+        }"#;
+        let report = SizeReport::from_idf_json(legacy).unwrap();
+        assert_eq!(
+            (
+                report.iram,
+                report.dram,
+                report.flash_code,
+                report.flash_rodata
+            ),
+            (38932, 17620, 80290, 39300)
+        );
+
+        let diram = r#"{
+            "used_dram": 0, "used_iram": 0, "diram_data": 100, "diram_bss": 200,
+            "diram_text": 300, "diram_vectors": 0, "flash_code": 1, "flash_rodata": 2
+        }"#;
+        let report = SizeReport::from_idf_json(diram).unwrap();
+        assert_eq!((report.iram, report.dram), (300, 300));
+
+        let json2 = r#"{
+            "version": "1.1",
+            "target": "esp32c3",
+            "memory_types": {
+                "DIRAM": { "size": 321296, "used": 1300, "sections": {
+                    ".dram0.data": { "abbrev_name": ".data", "size": 1000 },
+                    ".iram0.text": { "abbrev_name": ".text", "size": 300 }
+                }},
+                "IRAM": { "size": 16384, "used": 300, "sections": {
+                    ".iram0.text": { "abbrev_name": ".text", "size": 300 }
+                }},
+                "Flash Code": { "size": 8388576, "used": 5000, "sections": {
+                    ".flash.text": { "abbrev_name": ".text", "size": 5000 }
+                }},
+                "Flash Data": { "size": 8388576, "used": 2000, "sections": {
+                    ".flash.rodata": { "abbrev_name": ".rodata", "size": 1744 },
+                    ".flash.appdesc": { "abbrev_name": ".appdesc", "size": 256 }
+                }}
+            }
+        }"#;
+        let report = SizeReport::from_idf_json(json2).unwrap();
+        assert_eq!(
+            (
+                report.iram,
+                report.dram,
+                report.flash_code,
+                report.flash_rodata
+            ),
+            (300, 1000, 5000, 2000)
+        );
+
+        let report = report.with_map(MAP);
+        let archives = report
+            .per_archive
+            .iter()
+            .map(|(name, size)| (name.as_str(), size.clone()))
+            .collect::<Vec<_>>();
+        let size = |iram, dram, flash_code| ArchiveSize {
+            iram,
+            dram,
+            flash_code,
+            flash_rodata: 0,
+        };
+        assert_eq!(
+            archives,
+            [
+                ("libapp-1234.rlib", size(0, 0, 0x800)),
+                ("libesp_system.a", size(0x7c, 0, 0)),
+                ("libfreertos.a", size(0x44 + 0x3c, 0, 0)),
+                ("libmain.a", size(0, 0x20, 0x800)),
+            ]
+        );
+
+        let json = serde_json::to_string(&report).unwrap();
+        assert_eq!(serde_json::from_str::<SizeReport>(&json).unwrap(), report);
+    }
+
+    #[test]
+    fn test_check() {
+        let report = SizeReport::from_size_output(SIZE_SYSV)
+            .unwrap()
+            .with_map(MAP);
+
+        report
+            .check(&Budgets::new().limit(Region::Iram, 55348))
+            .unwrap();
+
+        let error = report
+            .check(
+                &Budgets::new()
+                    .limit(Region::Iram, 50000)
+                    .limit(Region::Dram, 10000)
+                    .limit(Region::FlashCode, 100000),
+            )
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The firmware exceeds its size budgets:\n\
+             - IRAM: 55348 bytes used, budget is 50000 bytes (5348 bytes over); largest: libfreertos.a (128 bytes), libesp_system.a (124 bytes)\n\
+             - flash code: 123456 bytes used, budget is 100000 bytes (23456 bytes over); largest: libmain.a (2048 bytes), libapp-1234.rlib (2048 bytes)"
+        );
+    }
+
+    #[test]
+    fn test_size_tool_names() {
+        assert_eq!(
+            size_tool_names("xtensa-esp32s3-espidf"),
+            ["xtensa-esp-elf-size", "xtensa-esp32s3-elf-size"]
+        );
+        assert_eq!(
+            size_tool_names("riscv32imac-esp-espidf"),
+            ["riscv32-esp-elf-size"]
+        );
+    }
+}
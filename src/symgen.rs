@@ -1,21 +1,179 @@
+use std::collections::HashSet;
 use std::fs::{self, File};
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::{env, fmt};
 
-use anyhow::{Error, Result};
+use anyhow::{Context, Error, Result};
+use regex::Regex;
 use xmas_elf::sections::{SectionData, ShType};
 use xmas_elf::symbol_table::{Binding, Visibility};
 use xmas_elf::{symbol_table, ElfFile};
 
+use crate::utils;
+
+#[cfg(feature = "demangle")]
+mod demangle;
+
 pub const VAR_SYMBOLS_FILE: &str = "EMBUILD_GENERATED_SYMBOLS_FILE";
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct Symbol<'a> {
     name: &'a str,
     section_name: Option<&'a str>,
     visible: bool,
     global: bool,
+    r#type: SymbolType,
+    address: u64,
+    #[cfg(feature = "demangle")]
+    demangled: Option<&'a str>,
+}
+
+/// The type of an ELF symbol.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SymbolType {
+    /// A symbol without type (`NOTYPE`), e.g. a linker script symbol.
+    NoType,
+    /// A data object (`OBJECT`).
+    Object,
+    /// A function (`FUNC`).
+    Func,
+}
+
+impl SymbolType {
+    fn from_elf(r#type: symbol_table::Type) -> Option<Self> {
+        match r#type {
+            symbol_table::Type::NoType => Some(Self::NoType),
+            symbol_table::Type::Object => Some(Self::Object),
+            symbol_table::Type::Func => Some(Self::Func),
+            _ => None,
+        }
+    }
+}
+
+/// A filter of the symbols processed by [`Symgen`].
+///
+/// A symbol passes the filter if its type is one of the [types](SymbolFilter::types),
+/// it is in one of the [sections](SymbolFilter::section) (if any are given), its name
+/// matches one of the [include](SymbolFilter::include) patterns (if any are given) and
+/// none of the [exclude](SymbolFilter::exclude) patterns.
+///
+/// The default filter passes all `OBJECT` and `NOTYPE` symbols.
+#[derive(Debug, Clone)]
+pub struct SymbolFilter {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+    sections: Vec<String>,
+    types: Vec<SymbolType>,
+}
+
+impl Default for SymbolFilter {
+    fn default() -> Self {
+        Self {
+            include: Vec::new(),
+            exclude: Vec::new(),
+            sections: Vec::new(),
+            types: vec![SymbolType::Object, SymbolType::NoType],
+        }
+    }
+}
+
+impl SymbolFilter {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Only pass symbols whose name matches one of the regexes added with this method.
+    pub fn include(mut self, pattern: impl AsRef<str>) -> Result<Self> {
+        self.include.push(Self::regex(pattern.as_ref())?);
+        Ok(self)
+    }
+
+    /// Don't pass symbols whose name matches the regex `pattern`.
+    pub fn exclude(mut self, pattern: impl AsRef<str>) -> Result<Self> {
+        self.exclude.push(Self::regex(pattern.as_ref())?);
+        Ok(self)
+    }
+
+    /// Only pass symbols in one of the sections (e.g. `.iram0.text`) added with this
+    /// method.
+    pub fn section(mut self, name: impl Into<String>) -> Self {
+        self.sections.push(name.into());
+        self
+    }
+
+    /// Only pass symbols of one of the `types`.
+    pub fn types(mut self, types: impl IntoIterator<Item = SymbolType>) -> Self {
+        self.types = types.into_iter().collect();
+        self
+    }
+
+    fn regex(pattern: &str) -> Result<Regex> {
+        Regex::new(pattern).with_context(|| format!("invalid symbol pattern '{pattern}'"))
+    }
+
+    fn matches_type(&self, r#type: SymbolType) -> bool {
+        self.types.contains(&r#type)
+    }
+
+    fn matches_section(&self, section_name: Option<&str>) -> bool {
+        self.sections.is_empty()
+            || section_name.is_some_and(|name| self.sections.iter().any(|s| s == name))
+    }
+
+    fn matches_name(&self, name: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|r| r.is_match(name)))
+            && !self.exclude.iter().any(|r| r.is_match(name))
+    }
+}
+
+/// The format of the Rust items generated by [`Symgen`].
+#[derive(Debug, Clone, Default)]
+pub enum OutputFormat {
+    /// Write the pointers created by the pointer generator of [`Symgen`] (e.g.
+    /// `pub const NAME: *mut core::ffi::c_void = 0x... as *mut core::ffi::c_void;`).
+    #[default]
+    Pointers,
+    /// Write the address of every symbol as `pub const NAME: usize = 0x...;`, with
+    /// `strip_prefix` removed from the start of the names.
+    Consts { strip_prefix: Option<String> },
+}
+
+/// How [`Symgen`] demangles C++ and Rust symbol names.
+#[cfg(feature = "demangle")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Demangle {
+    /// Keep the mangled names.
+    #[default]
+    Off,
+    /// Replace the mangled names with identifiers made from the demangled names (e.g.
+    /// `foo_bar` for `_ZN3foo3barEv`).
+    ///
+    /// If several symbols would get the same identifier, the identifiers of the
+    /// demangled ones get their mangled name as suffix (e.g. `foo_bar__ZN3foo3barEi`).
+    Replace,
+    /// Keep the mangled names and document the items with the demangled names.
+    Alongside,
+}
+
+/// Make an identifier of the demangled `name` by replacing all runs of characters that
+/// are not allowed in identifiers with a `_`.
+#[cfg(feature = "demangle")]
+fn to_identifier(name: &str) -> String {
+    let mut ident = String::new();
+    let mut separate = false;
+    for c in name.chars() {
+        if c == '_' || c.is_ascii_alphanumeric() {
+            if separate && !ident.is_empty() {
+                ident.push('_');
+            }
+            separate = false;
+            ident.push(c);
+        } else {
+            separate = true;
+        }
+    }
+    ident
 }
 
 #[derive(Debug)]
@@ -64,19 +222,34 @@ impl<'a> Symbol<'a> {
         self.global
     }
 
+    /// Get the symbol's type.
+    pub fn r#type(&self) -> SymbolType {
+        self.r#type
+    }
+
+    /// Get the symbol's address (including the start address of [`Symgen`]).
+    pub fn address(&self) -> u64 {
+        self.address
+    }
+
+    /// Get the symbol's demangled name (e.g. `foo::bar(int)`), if demangling is
+    /// enabled and the name is a C++ or Rust mangled name.
+    #[cfg(feature = "demangle")]
+    pub fn demangled(&self) -> Option<&'a str> {
+        self.demangled
+    }
+
     pub fn default_pointer_gen(&self) -> Option<RustPointer> {
-        if self.section_name().is_some() && self.global() && self.visible() {
-            let valid_identifier = self.name().char_indices().all(|(index, ch)| {
-                ch == '_' || index == 0 && ch.is_alphabetic() || index > 0 && ch.is_alphanumeric()
+        if self.section_name().is_some()
+            && self.global()
+            && self.visible()
+            && is_identifier(self.name())
+        {
+            return Some(RustPointer {
+                name: self.name().to_owned(),
+                mutable: true,
+                r#type: None,
             });
-
-            if valid_identifier {
-                return Some(RustPointer {
-                    name: self.name().to_owned(),
-                    mutable: true,
-                    r#type: None,
-                });
-            }
         }
 
         None
@@ -112,11 +285,22 @@ pub struct RustPointer {
     pub r#type: Option<String>,
 }
 
+fn is_identifier(name: &str) -> bool {
+    !name.is_empty()
+        && name.char_indices().all(|(index, ch)| {
+            ch == '_' || index == 0 && ch.is_alphabetic() || index > 0 && ch.is_alphanumeric()
+        })
+}
+
 #[allow(clippy::type_complexity)]
 pub struct Symgen {
     elf: PathBuf,
     start_addr: u64,
     rust_pointer_gen: Box<dyn for<'a> Fn(&Symbol<'a>) -> Option<RustPointer>>,
+    filter: SymbolFilter,
+    format: OutputFormat,
+    #[cfg(feature = "demangle")]
+    demangle: Demangle,
 }
 
 impl Symgen {
@@ -133,9 +317,34 @@ impl Symgen {
             elf: elf.into(),
             start_addr,
             rust_pointer_gen: Box::new(rust_pointer_gen),
+            filter: SymbolFilter::default(),
+            format: OutputFormat::default(),
+            #[cfg(feature = "demangle")]
+            demangle: Demangle::default(),
         }
     }
 
+    /// Only process the symbols passing `filter`.
+    pub fn with_filter(mut self, filter: SymbolFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Write the symbols in the `format`.
+    pub fn with_format(mut self, format: OutputFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Demangle C++ and Rust symbol names as specified by `demangle`.
+    ///
+    /// The [filter](Symgen::with_filter) is still applied to the mangled names.
+    #[cfg(feature = "demangle")]
+    pub fn with_demangle(mut self, demangle: Demangle) -> Self {
+        self.demangle = demangle;
+        self
+    }
+
     pub fn run(&self) -> Result<PathBuf> {
         let output_file = PathBuf::from(env::var("OUT_DIR")?).join("symbols.rs");
 
@@ -153,83 +362,206 @@ impl Symgen {
     pub fn run_for_file(&self, output_file: impl AsRef<Path>) -> Result<()> {
         let output_file = output_file.as_ref();
 
-        eprintln!("Output: {output_file:?}");
+        log::info!("Output: {}", utils::shortest_display(output_file, "."));
 
         self.write(&mut File::create(output_file)?)
     }
 
+    /// Write the symbols passing the filter, sorted by address and name.
     pub fn write(&self, output: &mut impl Write) -> Result<()> {
-        eprintln!("Input: {:?}", self.elf);
+        log::info!("Input: {}", utils::shortest_display(&self.elf, "."));
 
         let elf_data = fs::read(&self.elf)?;
         let elf = ElfFile::new(&elf_data).map_err(Error::msg)?;
 
+        let mut symbols = Vec::new();
         for symtable in self.get_symtables(&elf) {
             match symtable.1 {
-                SectionData::SymbolTable32(entries) => {
-                    self.write_symbols(&elf, symtable.0, entries.iter().enumerate(), output)?
-                }
-                SectionData::SymbolTable64(entries) => {
-                    self.write_symbols(&elf, symtable.0, entries.iter().enumerate(), output)?
-                }
+                SectionData::SymbolTable32(entries) => self.collect_symbols(
+                    &elf,
+                    symtable.0,
+                    entries.iter().enumerate(),
+                    &mut symbols,
+                )?,
+                SectionData::SymbolTable64(entries) => self.collect_symbols(
+                    &elf,
+                    symtable.0,
+                    entries.iter().enumerate(),
+                    &mut symbols,
+                )?,
                 _ => unimplemented!(),
             }
         }
 
+        #[cfg(feature = "demangle")]
+        let names = self.demangle_names(&symbols);
+        #[cfg(feature = "demangle")]
+        let symbols = symbols
+            .iter()
+            .zip(&names)
+            .map(|(symbol, (name, demangled))| Symbol {
+                name: name.as_deref().unwrap_or(symbol.name),
+                demangled: demangled.as_deref(),
+                ..*symbol
+            })
+            .collect::<Vec<_>>();
+
+        let mut items = symbols
+            .iter()
+            .filter_map(|symbol| {
+                let item = self.format_symbol(symbol);
+                if item.is_none() {
+                    log::debug!("Skipping symbol: {} [{symbol:?}]", symbol.name);
+                }
+                item.map(|(name, item)| (symbol.address, name, item))
+            })
+            .collect::<Vec<_>>();
+        items.sort();
+
+        let mut names = HashSet::new();
+        for (_address, name, item) in items {
+            if names.insert(name.clone()) {
+                output.write_all(item.as_bytes())?;
+            } else {
+                log::debug!("Skipping duplicate symbol: {name}");
+            }
+        }
+
         Ok(())
     }
 
-    fn write_symbols<'a, W: Write>(
+    /// Collect the symbols passing the filter into `symbols`.
+    fn collect_symbols<'a>(
         &self,
         elf: &'a ElfFile<'a>,
         symtable_index: usize,
         symbols: impl Iterator<Item = (usize, &'a (impl symbol_table::Entry + fmt::Debug + 'a))>,
-        output: &mut W,
+        collected: &mut Vec<Symbol<'a>>,
     ) -> Result<()> {
         for (_index, sym) in symbols {
-            eprintln!("Found symbol: {sym:?}");
-
-            let sym_type = sym.get_type().map_err(Error::msg)?;
-
-            if sym_type == symbol_table::Type::Object || sym_type == symbol_table::Type::NoType {
-                let name = sym.get_name(elf).map_err(Error::msg)?;
-
-                let section_name = sym
-                    .get_section_header(elf, symtable_index)
-                    .and_then(|sh| sh.get_name(elf))
-                    .ok();
-
-                let global = sym.get_binding().map_err(Error::msg)? == Binding::Global;
-                let visible = matches!(sym.get_other(), Visibility::Default);
-
-                let symbol = Symbol {
-                    name,
-                    section_name,
-                    global,
-                    visible,
-                };
-
-                let pointer = (self.rust_pointer_gen)(&symbol);
-
-                if let Some(pointer) = pointer {
-                    eprintln!("Writing symbol: {name} [{symbol:?}] as [{pointer:?}]");
-                    write!(
-                        output,
-                        "#[allow(dead_code, non_upper_case_globals)]\npub const {name}: *{mutable} {typ} = 0x{addr:x} as *{mutable} {typ};\n",
-                        name = pointer.name,
-                        mutable = if pointer.mutable { "mut" } else {"const" },
-                        typ = pointer.r#type.unwrap_or_else(|| "core::ffi::c_void".to_owned()),
-                        addr = self.start_addr + sym.value()
-                    )?;
-                } else {
-                    eprintln!("Skipping symbol: {name} [{sym:?}]");
-                }
+            let Some(sym_type) = SymbolType::from_elf(sym.get_type().map_err(Error::msg)?) else {
+                continue;
+            };
+            if !self.filter.matches_type(sym_type) {
+                continue;
+            }
+
+            let section_name = sym
+                .get_section_header(elf, symtable_index)
+                .and_then(|sh| sh.get_name(elf))
+                .ok();
+            if !self.filter.matches_section(section_name) {
+                continue;
+            }
+
+            let name = sym.get_name(elf).map_err(Error::msg)?;
+            if !self.filter.matches_name(name) {
+                continue;
             }
+
+            log::trace!("Found symbol: {sym:?}");
+
+            collected.push(Symbol {
+                name,
+                section_name,
+                global: sym.get_binding().map_err(Error::msg)? == Binding::Global,
+                visible: matches!(sym.get_other(), Visibility::Default),
+                r#type: sym_type,
+                address: self.start_addr + sym.value(),
+                #[cfg(feature = "demangle")]
+                demangled: None,
+            });
         }
 
         Ok(())
     }
 
+    /// Get the replaced name (if any) and the demangled name of all `symbols`.
+    #[cfg(feature = "demangle")]
+    fn demangle_names(&self, symbols: &[Symbol]) -> Vec<(Option<String>, Option<String>)> {
+        if self.demangle == Demangle::Off {
+            return vec![(None, None); symbols.len()];
+        }
+
+        let demangled = symbols
+            .iter()
+            .map(|symbol| demangle::demangle(symbol.name))
+            .collect::<Vec<_>>();
+        if self.demangle == Demangle::Alongside {
+            return demangled
+                .into_iter()
+                .map(|demangled| (None, demangled.map(|d| d.full)))
+                .collect();
+        }
+
+        let idents = symbols
+            .iter()
+            .zip(&demangled)
+            .map(|(symbol, demangled)| {
+                demangled
+                    .as_ref()
+                    .map_or(symbol.name.to_owned(), |d| to_identifier(&d.name))
+            })
+            .collect::<Vec<_>>();
+        let mut counts = std::collections::HashMap::<&str, usize>::new();
+        for ident in &idents {
+            *counts.entry(ident).or_default() += 1;
+        }
+
+        symbols
+            .iter()
+            .zip(demangled)
+            .zip(&idents)
+            .map(|((symbol, demangled), ident)| match demangled {
+                Some(demangled) if counts[ident.as_str()] > 1 => (
+                    Some(format!("{ident}_{}", symbol.name)),
+                    Some(demangled.full),
+                ),
+                Some(demangled) => (Some(ident.clone()), Some(demangled.full)),
+                None => (None, None),
+            })
+            .collect()
+    }
+
+    /// Format the `symbol` as Rust item, returning its name and the item.
+    fn format_symbol(&self, symbol: &Symbol) -> Option<(String, String)> {
+        #[allow(unused_mut)]
+        let mut doc = String::new();
+        #[cfg(feature = "demangle")]
+        if let (Demangle::Alongside, Some(demangled)) = (self.demangle, symbol.demangled) {
+            doc = format!("/// `{demangled}`\n");
+        }
+
+        match &self.format {
+            OutputFormat::Pointers => (self.rust_pointer_gen)(symbol).map(|pointer| {
+                log::trace!("Writing symbol: {} [{symbol:?}] as [{pointer:?}]", symbol.name);
+                let item = format!(
+                    "{doc}#[allow(dead_code, non_upper_case_globals)]\npub const {name}: *{mutable} {typ} = 0x{addr:x} as *{mutable} {typ};\n",
+                    name = pointer.name,
+                    mutable = if pointer.mutable { "mut" } else {"const" },
+                    typ = pointer.r#type.unwrap_or_else(|| "core::ffi::c_void".to_owned()),
+                    addr = symbol.address,
+                );
+                (pointer.name, item)
+            }),
+            OutputFormat::Consts { strip_prefix } => {
+                let name = strip_prefix
+                    .as_deref()
+                    .and_then(|prefix| symbol.name.strip_prefix(prefix))
+                    .unwrap_or(symbol.name);
+
+                is_identifier(name).then(|| {
+                    log::trace!("Writing symbol: {name} [{symbol:?}]");
+                    let item = format!(
+                        "{doc}#[allow(dead_code, non_upper_case_globals)]\npub const {name}: usize = 0x{addr:x};\n",
+                        addr = symbol.address,
+                    );
+                    (name.to_owned(), item)
+                })
+            }
+        }
+    }
+
     fn get_symtables<'a, 'b>(
         &self,
         elf: &'b ElfFile<'a>,
@@ -240,3 +572,140 @@ impl Symgen {
             .map(move |(index, header)| (index, header.get_data(elf).unwrap()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/symgen.elf")
+    }
+
+    fn generate(symgen: Symgen) -> String {
+        let mut output = Vec::new();
+        symgen.write(&mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_pointers() {
+        let output = generate(Symgen::new(fixture(), 0));
+        assert_eq!(
+            output,
+            "#[allow(dead_code, non_upper_case_globals)]\n\
+             pub const counter: *mut core::ffi::c_void = 0x3ffb0000 as *mut core::ffi::c_void;\n\
+             #[allow(dead_code, non_upper_case_globals)]\n\
+             pub const __bss_start: *mut core::ffi::c_void = 0x3ffb0004 as *mut core::ffi::c_void;\n\
+             #[allow(dead_code, non_upper_case_globals)]\n\
+             pub const _edata: *mut core::ffi::c_void = 0x3ffb0004 as *mut core::ffi::c_void;\n\
+             #[allow(dead_code, non_upper_case_globals)]\n\
+             pub const zeroed: *mut core::ffi::c_void = 0x3ffb0004 as *mut core::ffi::c_void;\n\
+             #[allow(dead_code, non_upper_case_globals)]\n\
+             pub const _end: *mut core::ffi::c_void = 0x3ffb0008 as *mut core::ffi::c_void;\n"
+        );
+
+        let filter = SymbolFilter::new().exclude("^_").unwrap().section(".bss");
+        let output = generate(Symgen::new(fixture(), 0x1000).with_filter(filter));
+        assert_eq!(
+            output,
+            "#[allow(dead_code, non_upper_case_globals)]\n\
+             pub const zeroed: *mut core::ffi::c_void = 0x3ffb1004 as *mut core::ffi::c_void;\n"
+        );
+    }
+
+    #[test]
+    fn test_consts() {
+        let format = OutputFormat::Consts {
+            strip_prefix: Some("esp_rom_".into()),
+        };
+
+        let filter = SymbolFilter::new().include("^esp_rom_").unwrap();
+        let output = generate(
+            Symgen::new(fixture(), 0)
+                .with_filter(filter)
+                .with_format(format.clone()),
+        );
+        assert_eq!(
+            output,
+            "#[allow(dead_code, non_upper_case_globals)]\n\
+             pub const printf: usize = 0x40007d54;\n\
+             #[allow(dead_code, non_upper_case_globals)]\n\
+             pub const delay_us: usize = 0x40008534;\n"
+        );
+
+        let filter = SymbolFilter::new()
+            .types([SymbolType::Func])
+            .section(".text")
+            .section(".iram0.text")
+            .include("handler$")
+            .unwrap();
+        let output = generate(
+            Symgen::new(fixture(), 0)
+                .with_filter(filter)
+                .with_format(format),
+        );
+        assert_eq!(
+            output,
+            "#[allow(dead_code, non_upper_case_globals)]\n\
+             pub const slow_handler: usize = 0x40080000;\n\
+             #[allow(dead_code, non_upper_case_globals)]\n\
+             pub const fast_handler: usize = 0x4008002d;\n"
+        );
+
+        assert!(SymbolFilter::new().include("(").is_err());
+    }
+
+    #[cfg(feature = "demangle")]
+    #[test]
+    fn test_demangle() {
+        let symgen = || {
+            let filter = SymbolFilter::new()
+                .include("^_[ZR]")
+                .unwrap()
+                .include("^esp_rom_printf$")
+                .unwrap();
+            Symgen::new(fixture(), 0)
+                .with_filter(filter)
+                .with_format(OutputFormat::Consts { strip_prefix: None })
+        };
+
+        let output = generate(symgen().with_demangle(Demangle::Replace));
+        assert_eq!(
+            output,
+            "#[allow(dead_code, non_upper_case_globals)]\n\
+             pub const esp_rom_printf: usize = 0x40007d54;\n\
+             #[allow(dead_code, non_upper_case_globals)]\n\
+             pub const foo_bar__ZN3foo3barEv: usize = 0x40090000;\n\
+             #[allow(dead_code, non_upper_case_globals)]\n\
+             pub const foo_bar__ZN3foo3barEi: usize = 0x40090010;\n\
+             #[allow(dead_code, non_upper_case_globals)]\n\
+             pub const foo_baz: usize = 0x40090020;\n\
+             #[allow(dead_code, non_upper_case_globals)]\n\
+             pub const core_fmt_write: usize = 0x40090030;\n\
+             #[allow(dead_code, non_upper_case_globals)]\n\
+             pub const mycrate_main: usize = 0x40090040;\n"
+        );
+
+        let output = generate(symgen().with_demangle(Demangle::Alongside));
+        assert_eq!(
+            output,
+            "#[allow(dead_code, non_upper_case_globals)]\n\
+             pub const esp_rom_printf: usize = 0x40007d54;\n\
+             /// `foo::bar()`\n\
+             #[allow(dead_code, non_upper_case_globals)]\n\
+             pub const _ZN3foo3barEv: usize = 0x40090000;\n\
+             /// `foo::bar(int)`\n\
+             #[allow(dead_code, non_upper_case_globals)]\n\
+             pub const _ZN3foo3barEi: usize = 0x40090010;\n\
+             /// `foo::baz(char const*) const`\n\
+             #[allow(dead_code, non_upper_case_globals)]\n\
+             pub const _ZNK3foo3bazEPKc: usize = 0x40090020;\n\
+             /// `core::fmt::write`\n\
+             #[allow(dead_code, non_upper_case_globals)]\n\
+             pub const _ZN4core3fmt5write17h0123456789abcdefE: usize = 0x40090030;\n\
+             /// `mycrate::main`\n\
+             #[allow(dead_code, non_upper_case_globals)]\n\
+             pub const _RNvCs1234_7mycrate4main: usize = 0x40090040;\n"
+        );
+    }
+}
@@ -0,0 +1,117 @@
+//! Demangling of C++ (Itanium C++ ABI) and Rust symbol names.
+
+use cpp_demangle::DemangleOptions;
+
+/// A demangled symbol name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Demangled {
+    /// The qualified name without parameters (e.g. `foo::bar`).
+    pub name: String,
+    /// The full readable form (e.g. `foo::bar(int) const`).
+    pub full: String,
+}
+
+/// Demangle the C++ or Rust symbol name `symbol`.
+///
+/// Returns [`None`] if `symbol` is not mangled or could not be demangled.
+pub fn demangle(symbol: &str) -> Option<Demangled> {
+    if is_rust(symbol) {
+        let name = format!("{:#}", rustc_demangle::try_demangle(symbol).ok()?);
+        return Some(Demangled {
+            full: name.clone(),
+            name,
+        });
+    }
+
+    if !symbol.starts_with("_Z") {
+        return None;
+    }
+    let parsed = cpp_demangle::Symbol::new(symbol).ok()?;
+    let full = parsed
+        .demangle(&DemangleOptions::new().no_return_type())
+        .ok()?;
+    let (mangled, _) = symbol.split_once('.').unwrap_or((symbol, ""));
+    let name = cpp_demangle::Symbol::new(mangled)
+        .ok()?
+        .demangle(&DemangleOptions::new().no_params().no_return_type())
+        .ok()?;
+
+    Some(Demangled { name, full })
+}
+
+/// Whether `symbol` is a Rust v0 or legacy mangled name (the latter only differs from
+/// C++ names by its trailing hash, e.g. `_ZN3foo3bar17h0123456789abcdefE`).
+fn is_rust(symbol: &str) -> bool {
+    if symbol.starts_with("_R") {
+        return true;
+    }
+
+    let symbol = symbol.split('.').next().unwrap_or_default();
+    symbol.starts_with("_ZN")
+        && symbol.len() > 20
+        && symbol.ends_with('E')
+        && symbol[symbol.len() - 20..].starts_with("17h")
+        && symbol[symbol.len() - 17..symbol.len() - 1]
+            .bytes()
+            .all(|b| b.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_demangle_cpp() {
+        for (mangled, full) in [
+            ("_ZN3foo3barEv", "foo::bar()"),
+            ("_ZNK3foo3bazEPKci", "foo::baz(char const*, int) const"),
+            (
+                "_ZNSt6vectorIiSaIiEE9push_backERKi",
+                "std::vector<int, std::allocator<int> >::push_back(int const&)",
+            ),
+            ("_ZN3FooC2Ev", "Foo::Foo()"),
+            ("_ZN3FooD1Ev", "Foo::~Foo()"),
+            ("_ZN12_GLOBAL__N_13runEv", "(anonymous namespace)::run()"),
+            ("_ZL6helperv", "helper()"),
+            ("_Z3maxIiET_S0_S0_", "max<int>(int, int)"),
+            ("_ZN3foo3barB5cxx11Ev", "foo::bar[abi:cxx11]()"),
+            ("_ZZ4mainE5count", "main::count"),
+            (
+                "_Z5apply3FooIjLj3EE",
+                "apply(Foo<unsigned int, (unsigned int)3>)",
+            ),
+            ("_ZN3fooeqERKS_", "foo::operator==(foo const&)"),
+            ("_Z4findRKSs", "find(std::string const&)"),
+            (
+                "_ZN3foo3barEv.constprop.0",
+                "foo::bar() [clone .constprop.0]",
+            ),
+            ("_ZplRK3VecS1_", "operator+(Vec const&, Vec const&)"),
+            ("_ZN4ns_13Cls6methodEz", "ns_1::Cls::method(...)"),
+            ("_ZN3foo5valueE", "foo::value"),
+            ("_Z3fooPFvvE", "foo(void (*)())"),
+        ] {
+            assert_eq!(
+                demangle(mangled).map(|d| d.full).as_deref(),
+                Some(full),
+                "{mangled}"
+            );
+        }
+
+        assert_eq!(demangle("_ZN3foo3barEv").unwrap().name, "foo::bar");
+
+        for invalid in ["main", "_Z", "_ZN3foo", "_ZN3foo3barEvX", "_Z9short"] {
+            assert_eq!(demangle(invalid), None, "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_demangle_rust() {
+        let legacy = demangle("_ZN4core3fmt5write17h0123456789abcdefE").unwrap();
+        assert_eq!(legacy.name, "core::fmt::write");
+        assert_eq!(legacy.full, "core::fmt::write");
+
+        let v0 = demangle("_RNvCs1234_7mycrate4main").unwrap();
+        assert_eq!(v0.name, "mycrate::main");
+    }
+}
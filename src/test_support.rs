@@ -0,0 +1,573 @@
+//! Fake toolchains and canned fixtures for testing build logic without the real tools.
+//!
+//! A [`FakeToolchain`] is a temporary directory of fake executables (e.g.
+//! `xtensa-esp32-elf-gcc`, `ld`, `cmake` or `pio`) which print canned outputs for the
+//! arguments they are called with and record all their calls. They are shell scripts
+//! (batch files on Windows), so no build of a helper binary is needed.
+//!
+//! [`CmakeReplies`] writes a canned cmake-file-api reply tree and [`write_scons_dump`] a
+//! PlatformIO scons dump, as if the real tools had run.
+//!
+//! # Examples
+//! ```
+//! # use embuild::test_support::{FakeTool, FakeToolchain};
+//! let toolchain = FakeToolchain::new()?;
+//! let sysroot = toolchain.sysroot("xtensa-esp32-elf", &["12.2.0"])?;
+//! let gcc = toolchain.gcc("xtensa-esp32-elf", &sysroot)?;
+//! let cmake = toolchain.tool(
+//!     "cmake",
+//!     FakeTool::new().on(&["--version"], "cmake version 3.24.0\n"),
+//! )?;
+//! # let _ = (gcc, cmake);
+//! # anyhow::Ok(())
+//! ```
+
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{anyhow, Context, Result};
+use serde_json::json;
+
+/// The extension of the fake executables created by [`FakeToolchain::tool`].
+pub const FAKE_TOOL_EXTENSION: &str = if cfg!(windows) { "bat" } else { "" };
+
+/// The output and exit code of a [`FakeTool`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Response {
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_code: i32,
+}
+
+impl Response {
+    /// A successful response printing `stdout`.
+    pub fn stdout(stdout: impl Into<String>) -> Self {
+        Self {
+            stdout: stdout.into(),
+            ..Default::default()
+        }
+    }
+
+    /// A failed response with `exit_code` printing `stderr`.
+    pub fn failure(exit_code: i32, stderr: impl Into<String>) -> Self {
+        Self {
+            stderr: stderr.into(),
+            exit_code,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&str> for Response {
+    fn from(stdout: &str) -> Self {
+        Self::stdout(stdout)
+    }
+}
+
+/// The behavior of a fake executable of a [`FakeToolchain`].
+///
+/// The arguments of a call are joined with spaces and compared with the arguments of
+/// each [`FakeTool::on`] in order; the first one that is equal gives the response.
+/// Calls with other arguments get the [`FakeTool::otherwise`] response, which succeeds
+/// without output by default.
+#[derive(Clone, Debug, Default)]
+#[must_use]
+pub struct FakeTool {
+    responses: Vec<(String, Response)>,
+    otherwise: Response,
+}
+
+impl FakeTool {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Respond with `response` when called with exactly `args`.
+    pub fn on(mut self, args: &[&str], response: impl Into<Response>) -> Self {
+        self.responses.push((args.join(" "), response.into()));
+        self
+    }
+
+    /// Respond with `response` when called with any other args.
+    pub fn otherwise(mut self, response: impl Into<Response>) -> Self {
+        self.otherwise = response.into();
+        self
+    }
+}
+
+/// A temporary directory of fake executables, see the [module docs](self).
+///
+/// The directory is removed when this is dropped.
+#[derive(Debug)]
+pub struct FakeToolchain {
+    dir: tempfile::TempDir,
+}
+
+impl FakeToolchain {
+    /// Create an empty fake toolchain in a new temporary directory.
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            dir: tempfile::Builder::new()
+                .prefix("embuild-fake-toolchain")
+                .tempdir()?,
+        })
+    }
+
+    /// The directory of the fake executables.
+    pub fn dir(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Create the fake executable `name` behaving like `tool`, and get its path.
+    ///
+    /// On Windows the executable has the extension [`FAKE_TOOL_EXTENSION`].
+    pub fn tool(&self, name: &str, tool: FakeTool) -> Result<PathBuf> {
+        let responses_dir = self.dir().join(".responses");
+        fs::create_dir_all(&responses_dir)?;
+        fs::create_dir_all(self.dir().join(".calls"))?;
+
+        // The outputs are stored in files so that they don't need to be escaped.
+        let response_files = |index: String, response: &Response| -> Result<_> {
+            let stdout = responses_dir.join(format!("{name}.{index}.stdout"));
+            let stderr = responses_dir.join(format!("{name}.{index}.stderr"));
+            fs::write(&stdout, &response.stdout)?;
+            fs::write(&stderr, &response.stderr)?;
+            Ok((stdout, stderr, response.exit_code))
+        };
+        let mut cases = Vec::new();
+        for (index, (args, response)) in tool.responses.iter().enumerate() {
+            cases.push((args.as_str(), response_files(index.to_string(), response)?));
+        }
+        let otherwise = response_files("otherwise".into(), &tool.otherwise)?;
+
+        let path = match FAKE_TOOL_EXTENSION {
+            "" => self.dir().join(name),
+            extension => self.dir().join(format!("{name}.{extension}")),
+        };
+        fs::write(&path, script(&self.calls_file(name), &cases, &otherwise))
+            .with_context(|| anyhow!("Could not write fake tool '{}'", path.display()))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+        }
+        Ok(path)
+    }
+
+    /// Create the fake gcc `<prefix>-gcc` and linker `<prefix>-ld` of a cross toolchain
+    /// with `sysroot`, and get the path of gcc.
+    ///
+    /// Like the real cross toolchains, only the linker prints the sysroot with
+    /// `--print-sysroot`, gcc prints an empty line.
+    pub fn gcc(&self, prefix: &str, sysroot: &Path) -> Result<PathBuf> {
+        self.tool(
+            &format!("{prefix}-ld"),
+            FakeTool::new().on(
+                &["--print-sysroot"],
+                format!("{}\n", sysroot.display()).as_str(),
+            ),
+        )?;
+        self.tool(
+            &format!("{prefix}-gcc"),
+            FakeTool::new().on(&["--print-sysroot"], "\n"),
+        )
+    }
+
+    /// Create the sysroot directory `name` with an `include` directory and the C++
+    /// include directories `include/c++/<version>` of all `cpp_versions`, and get its
+    /// path.
+    pub fn sysroot(&self, name: &str, cpp_versions: &[&str]) -> Result<PathBuf> {
+        let sysroot = self.dir().join(name);
+        fs::create_dir_all(sysroot.join("include"))?;
+        for version in cpp_versions {
+            fs::create_dir_all(sysroot.join("include").join("c++").join(version))?;
+        }
+        Ok(sysroot)
+    }
+
+    /// The arguments of all calls of the fake executable `name`, in order, each joined
+    /// with spaces.
+    pub fn calls(&self, name: &str) -> Vec<String> {
+        fs::read_to_string(self.calls_file(name))
+            .unwrap_or_default()
+            .lines()
+            .map(|line| line.trim_end().to_owned())
+            .collect()
+    }
+
+    /// The `PATH` with the directory of the fake executables prepended.
+    pub fn path_var(&self) -> Result<OsString> {
+        let path = env::var_os("PATH").unwrap_or_default();
+        Ok(env::join_paths(
+            [self.dir().to_owned()]
+                .into_iter()
+                .chain(env::split_paths(&path)),
+        )?)
+    }
+
+    /// Create a [`Command`] running `program` with the [`path_var`](Self::path_var) as
+    /// its `PATH`, so that it (and the processes it starts) find the fake executables.
+    pub fn command(&self, program: impl AsRef<OsStr>) -> Result<Command> {
+        let mut cmd = Command::new(program);
+        cmd.env("PATH", self.path_var()?);
+        Ok(cmd)
+    }
+
+    fn calls_file(&self, name: &str) -> PathBuf {
+        self.dir().join(".calls").join(name)
+    }
+}
+
+type ResponseFiles = (PathBuf, PathBuf, i32);
+
+/// The script of a fake tool recording its calls in `calls_file` and responding with
+/// the `cases`.
+#[cfg(not(windows))]
+fn script(calls_file: &Path, cases: &[(&str, ResponseFiles)], otherwise: &ResponseFiles) -> String {
+    fn quote(s: impl AsRef<OsStr>) -> String {
+        format!("'{}'", s.as_ref().to_string_lossy().replace('\'', r"'\''"))
+    }
+    let respond = |(stdout, stderr, exit_code): &ResponseFiles| {
+        format!(
+            "  cat {}; cat {} >&2; exit {exit_code}\n",
+            quote(stdout),
+            quote(stderr)
+        )
+    };
+
+    let mut script = format!(
+        "#!/bin/sh\nprintf '%s\\n' \"$*\" >> {}\n",
+        quote(calls_file)
+    );
+    for (args, response) in cases {
+        script += &format!("if [ \"$*\" = {} ]; then\n", quote(args));
+        script += &respond(response);
+        script += "fi\n";
+    }
+    script + &respond(otherwise)
+}
+
+/// The batch file of a fake tool recording its calls in `calls_file` and responding
+/// with the `cases`.
+///
+/// Arguments containing double quotes are not supported.
+#[cfg(windows)]
+fn script(calls_file: &Path, cases: &[(&str, ResponseFiles)], otherwise: &ResponseFiles) -> String {
+    let respond = |(stdout, stderr, exit_code): &ResponseFiles| {
+        format!(
+            "  type \"{}\"\r\n  type \"{}\" 1>&2\r\n  exit /b {exit_code}\r\n",
+            stdout.display(),
+            stderr.display()
+        )
+    };
+
+    let mut script = format!("@echo off\r\n>>\"{}\" echo(%*\r\n", calls_file.display());
+    for (args, response) in cases {
+        script += &format!("if \"%*\"==\"{args}\" (\r\n");
+        script += &respond(response);
+        script += ")\r\n";
+    }
+    script + "(\r\n" + &respond(otherwise) + ")\r\n"
+}
+
+/// A canned cmake-file-api reply tree of a configured project, as written by cmake for
+/// a query of the codemodel and toolchains objects (see
+/// [`cmake::file_api::Query`](crate::cmake::file_api::Query)).
+///
+/// The codemodel has the executable target `app` depending on the static library
+/// target `main`, whose C compile group has the includes, defines, compile args and
+/// sysroot set here.
+#[derive(Clone, Debug)]
+#[must_use]
+pub struct CmakeReplies {
+    source_dir: PathBuf,
+    build_dir: PathBuf,
+    cmake_version: (u32, u32, u32),
+    compiler: PathBuf,
+    includes: Vec<PathBuf>,
+    defines: Vec<String>,
+    compile_args: Vec<String>,
+    sysroot: Option<PathBuf>,
+}
+
+impl CmakeReplies {
+    /// Create the replies of the project `source_dir` configured in `build_dir`.
+    pub fn new(source_dir: impl Into<PathBuf>, build_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            source_dir: source_dir.into(),
+            build_dir: build_dir.into(),
+            cmake_version: (3, 24, 0),
+            compiler: PathBuf::from("xtensa-esp32-elf-gcc"),
+            includes: Vec::new(),
+            defines: Vec::new(),
+            compile_args: Vec::new(),
+            sysroot: None,
+        }
+    }
+
+    /// The version of the fake cmake (defaults to 3.24.0).
+    pub fn cmake_version(mut self, major: u32, minor: u32, patch: u32) -> Self {
+        self.cmake_version = (major, minor, patch);
+        self
+    }
+
+    /// The C compiler of the toolchains object (defaults to `xtensa-esp32-elf-gcc`).
+    pub fn compiler(mut self, compiler: impl Into<PathBuf>) -> Self {
+        self.compiler = compiler.into();
+        self
+    }
+
+    /// Add an include directory.
+    pub fn include(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.includes.push(dir.into());
+        self
+    }
+
+    /// Add a define (`NAME` or `NAME=value`).
+    pub fn define(mut self, define: impl Into<String>) -> Self {
+        self.defines.push(define.into());
+        self
+    }
+
+    /// Add a compile arg (e.g. `-mlongcalls`).
+    pub fn compile_arg(mut self, arg: impl Into<String>) -> Self {
+        self.compile_args.push(arg.into());
+        self
+    }
+
+    /// The sysroot of the compile group.
+    pub fn sysroot(mut self, sysroot: impl Into<PathBuf>) -> Self {
+        self.sysroot = Some(sysroot.into());
+        self
+    }
+
+    /// Write the replies for the client stateless query of client `client_name`.
+    pub fn write(&self, client_name: &str) -> Result<()> {
+        let reply_dir = self
+            .build_dir
+            .join(".cmake")
+            .join("api")
+            .join("v1")
+            .join("reply");
+        fs::create_dir_all(&reply_dir)?;
+        let write = |file: &str, value: serde_json::Value| -> Result<()> {
+            Ok(fs::write(
+                reply_dir.join(file),
+                serde_json::to_vec_pretty(&value)?,
+            )?)
+        };
+
+        let (major, minor, patch) = self.cmake_version;
+        let target_ref = |name: &str, index: usize| {
+            json!({
+                "name": name,
+                "id": format!("{name}::@6890427a1f51a3e7e1df"),
+                "directoryIndex": 0,
+                "projectIndex": 0,
+                "jsonFile": format!("target-{name}-{index}.json"),
+            })
+        };
+        write(
+            "codemodel-v2-0.json",
+            json!({
+                "version": { "major": 2, "minor": 4 },
+                "paths": { "source": self.source_dir, "build": self.build_dir },
+                "configurations": [{
+                    "name": "",
+                    "targets": [target_ref("app", 0), target_ref("main", 1)],
+                }],
+            }),
+        )?;
+        write(
+            "target-app-0.json",
+            json!({
+                "name": "app",
+                "id": "app::@6890427a1f51a3e7e1df",
+                "type": "EXECUTABLE",
+                "dependencies": [{ "id": "main::@6890427a1f51a3e7e1df" }],
+                "artifacts": [{ "path": "app.elf" }],
+                "sources": [],
+            }),
+        )?;
+
+        let mut compile_group = json!({
+            "language": "C",
+            "compileCommandFragments": self
+                .compile_args
+                .iter()
+                .map(|arg| json!({ "fragment": arg }))
+                .collect::<Vec<_>>(),
+            "includes": self
+                .includes
+                .iter()
+                .map(|dir| json!({ "path": dir }))
+                .collect::<Vec<_>>(),
+            "defines": self
+                .defines
+                .iter()
+                .map(|define| json!({ "define": define }))
+                .collect::<Vec<_>>(),
+        });
+        if let Some(sysroot) = &self.sysroot {
+            compile_group["sysroot"] = json!({ "path": sysroot });
+        }
+        write(
+            "target-main-1.json",
+            json!({
+                "name": "main",
+                "id": "main::@6890427a1f51a3e7e1df",
+                "type": "STATIC_LIBRARY",
+                "artifacts": [{ "path": "libmain.a" }],
+                "sources": [{ "path": "main.c", "compileGroupIndex": 0 }],
+                "compileGroups": [compile_group],
+            }),
+        )?;
+        write(
+            "toolchains-v1-0.json",
+            json!({
+                "version": { "major": 1, "minor": 0 },
+                "toolchains": [{
+                    "language": "C",
+                    "compiler": {
+                        "path": self.compiler,
+                        "id": "GNU",
+                        "version": "12.2.0",
+                        "implicit": {},
+                    },
+                }],
+            }),
+        )?;
+
+        let reply = |kind: &str, file: &str, major: u32| {
+            json!({
+                "kind": kind,
+                "version": { "major": major, "minor": 0 },
+                "jsonFile": file,
+            })
+        };
+        write(
+            "index-2024-01-01T00-00-00-0000.json",
+            json!({
+                "cmake": {
+                    "version": {
+                        "major": major,
+                        "minor": minor,
+                        "patch": patch,
+                        "string": format!("{major}.{minor}.{patch}"),
+                        "suffix": "",
+                        "isDirty": false,
+                    },
+                    "paths": { "cmake": "cmake", "ctest": "ctest", "cpack": "cpack", "root": "." },
+                    "generator": { "multiConfig": false, "name": "Ninja" },
+                },
+                "objects": [],
+                "reply": {
+                    format!("client-{client_name}"): {
+                        "codemodel-v2": reply("codemodel", "codemodel-v2-0.json", 2),
+                        "toolchains-v1": reply("toolchains", "toolchains-v1-0.json", 1),
+                    },
+                },
+            }),
+        )
+    }
+}
+
+/// Write `vars` as the scons dump of the PlatformIO project `project_dir`, as read by
+/// [`SconsVariables::from_dump`](crate::pio::project::SconsVariables::from_dump).
+#[cfg(feature = "pio")]
+pub fn write_scons_dump(
+    project_dir: &Path,
+    vars: &crate::pio::project::SconsVariables,
+) -> Result<()> {
+    fs::write(
+        project_dir.join(crate::pio::project::SCONS_DUMP_FILE),
+        serde_json::to_vec_pretty(vars)?,
+    )?;
+    Ok(())
+}
+
+/// Canned scons variables of an esp32 PlatformIO project in `project_dir` whose
+/// toolchain (linking with `xtensa-esp32-elf-gcc`) is found in `path`, e.g. the
+/// [`FakeToolchain::path_var`].
+#[cfg(feature = "pio")]
+pub fn esp32_scons_variables(
+    project_dir: &Path,
+    path: &OsStr,
+) -> crate::pio::project::SconsVariables {
+    let framework_dir = project_dir.join("framework-espidf");
+    crate::pio::project::SconsVariables {
+        project_dir: project_dir.to_owned(),
+        release_build: false,
+        path: path.to_string_lossy().into_owned(),
+        incflags: format!(
+            "-I{} -I{}",
+            project_dir.join("include").display(),
+            framework_dir
+                .join("components")
+                .join("esp_common")
+                .join("include")
+                .display()
+        ),
+        libflags: "-lmain".into(),
+        libdirflags: format!("-L{}", project_dir.join(".pio").join("build").display()),
+        libs: "main".into(),
+        defflags: "-DESP_PLATFORM -DIDF_VER=\\\"v5.1\\\" -DPLATFORMIO=60110".into(),
+        ccflags: "-mlongcalls -Wno-frame-address -ffunction-sections".into(),
+        cflags: "-std=gnu17".into(),
+        cxxflags: "-std=gnu++2b -fno-rtti".into(),
+        linkflags: "-mlongcalls -Wl,--gc-sections".into(),
+        link: "xtensa-esp32-elf-gcc".into(),
+        linkcom: "$LINK -o $TARGET $LINKFLAGS $__RPATH $SOURCES $_LIBDIRFLAGS $_LIBFLAGS".into(),
+        mcu: "esp32".into(),
+        clangargs: None,
+        pio_platform_dir: project_dir
+            .join("platform-espressif32")
+            .display()
+            .to_string(),
+        pio_framework_dir: framework_dir.display().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fake_tool() {
+        let toolchain = FakeToolchain::new().unwrap();
+        let tool = toolchain
+            .tool(
+                "fake-tool",
+                FakeTool::new()
+                    .on(&["--version"], "fake 1.0\n")
+                    .on(&["fail", "now"], Response::failure(3, "failed\n"))
+                    .otherwise(Response::failure(1, "unknown args\n")),
+            )
+            .unwrap();
+
+        let output = toolchain
+            .command(&tool)
+            .unwrap()
+            .arg("--version")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "fake 1.0");
+
+        let output = Command::new(&tool).args(["fail", "now"]).output().unwrap();
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(String::from_utf8_lossy(&output.stderr).trim(), "failed");
+
+        let output = Command::new(&tool).arg("other").output().unwrap();
+        assert_eq!(output.status.code(), Some(1));
+
+        assert_eq!(
+            toolchain.calls("fake-tool"),
+            ["--version", "fail now", "other"]
+        );
+        assert!(toolchain.calls("other-tool").is_empty());
+    }
+}
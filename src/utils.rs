@@ -1,11 +1,20 @@
 //! Miscellaneous utilities.
 
-use std::ffi::OsStr;
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::ffi::{OsStr, OsString};
+use std::fmt::Display;
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 use std::{env, io};
 
 use anyhow::Result;
 
+#[cfg(all(feature = "ureq", feature = "ring"))]
+mod download;
+#[cfg(all(feature = "ureq", feature = "ring"))]
+pub use download::*;
+
 /// Build a [`PathBuf`].
 ///
 /// # Examples
@@ -64,21 +73,319 @@ pub trait PathExt: AsRef<Path> {
 impl PathExt for Path {}
 impl PathExt for PathBuf {}
 
-/// Error when converting from [`OsStr`] to [`String`] fails.
+/// Get `path` relative to the directory `base` (e.g. `../b/c` for `/a/b/c` relative to
+/// `/a/d`).
+///
+/// Both paths are made absolute (relative to [`env::current_dir`]) and resolved as far
+/// as they exist, following all symlinks of `base` but not a final symlink of `path`.
+/// If no relative path exists (e.g. on different drives or UNC shares on Windows) the
+/// resolved absolute `path` is returned.
+pub fn path_relative_to(path: impl AsRef<Path>, base: impl AsRef<Path>) -> PathBuf {
+    let (absolute, relative) = relative_and_absolute(path.as_ref(), base.as_ref());
+    relative.unwrap_or(absolute)
+}
+
+/// Get the shorter of `path` relative to `base` (see [`path_relative_to`]) and the
+/// absolute `path` for displaying it, preferring the relative path if both are equally
+/// long.
+pub fn shortest_display(path: impl AsRef<Path>, base: impl AsRef<Path>) -> String {
+    let (absolute, relative) = relative_and_absolute(path.as_ref(), base.as_ref());
+    let absolute = absolute.display().to_string();
+    match relative.map(|relative| relative.display().to_string()) {
+        Some(relative) if relative.len() <= absolute.len() => relative,
+        _ => absolute,
+    }
+}
+
+/// Get the resolved absolute `path`, and `path` relative to `base` if possible.
+fn relative_and_absolute(path: &Path, base: &Path) -> (PathBuf, Option<PathBuf>) {
+    let path = resolve_existing(path, false);
+    let base = resolve_existing(base, true);
+    match (path.to_str(), base.to_str()) {
+        (Some(path), Some(base)) => {
+            let (absolute, relative) = lexical_relative(path, base, PathStyle::native());
+            (absolute.into(), relative.map(PathBuf::from))
+        }
+        _ => (path, None),
+    }
+}
+
+/// Make `path` absolute and canonicalize its longest existing ancestor (or the whole
+/// path if `follow_last` is `true`), keeping the rest of the path as is.
+fn resolve_existing(path: &Path, follow_last: bool) -> PathBuf {
+    let path = path.abspath().unwrap_or_else(|_| path.to_owned());
+    let start = if follow_last {
+        Some(path.as_path())
+    } else {
+        path.parent()
+    };
+
+    for ancestor in start.into_iter().flat_map(Path::ancestors) {
+        if let Ok(canonical) = ancestor.canonicalize() {
+            return match path.strip_prefix(ancestor) {
+                Ok(rest) if !rest.as_os_str().is_empty() => canonical.join(rest),
+                _ => canonical,
+            };
+        }
+    }
+    path
+}
+
+/// The path syntax used by [`lexical_relative`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum PathStyle {
+    Unix,
+    /// Windows paths, with `\` or `/` separators, case-insensitive components and
+    /// drive, UNC and verbatim (`\\?\`) prefixes.
+    Windows,
+}
+
+impl PathStyle {
+    pub(crate) fn native() -> Self {
+        if cfg!(windows) {
+            Self::Windows
+        } else {
+            Self::Unix
+        }
+    }
+
+    fn separator(self) -> &'static str {
+        match self {
+            Self::Unix => "/",
+            Self::Windows => "\\",
+        }
+    }
+
+    fn eq(self, a: &str, b: &str) -> bool {
+        match self {
+            Self::Unix => a == b,
+            Self::Windows => a.eq_ignore_ascii_case(b),
+        }
+    }
+
+    /// Split `path` into its prefix (the root `/`, a drive like `C:` or a UNC share like
+    /// `\\server\share`) and its lexically normalized components.
+    fn parse(self, path: &str) -> (String, Vec<&str>) {
+        let (prefix, rest) = match self {
+            Self::Unix => match path.strip_prefix('/') {
+                Some(rest) => ("/".to_owned(), rest),
+                None => (String::new(), path),
+            },
+            Self::Windows => Self::windows_prefix(path),
+        };
+
+        let separators: &[char] = match self {
+            Self::Unix => &['/'],
+            Self::Windows => &['\\', '/'],
+        };
+        let mut components = Vec::new();
+        for component in rest.split(separators) {
+            match component {
+                "" | "." => (),
+                ".." if components.last().is_some_and(|c| *c != "..") => {
+                    components.pop();
+                }
+                ".." if !prefix.is_empty() => (),
+                component => components.push(component),
+            }
+        }
+        (prefix, components)
+    }
+
+    fn windows_prefix(path: &str) -> (String, &str) {
+        let is_separator = |c: char| c == '\\' || c == '/';
+        let strip = |path: &'_ str, prefix: &str| -> Option<String> {
+            let head = path.get(..prefix.len())?;
+            (head.replace('/', "\\") == prefix).then(|| path[prefix.len()..].to_owned())
+        };
+        let unc = |rest: &str| {
+            let mut parts = rest.splitn(3, is_separator);
+            let server = parts.next().unwrap_or_default();
+            let share = parts.next().unwrap_or_default();
+            let rest_start = (server.len() + share.len() + 2).min(rest.len());
+            (format!("\\\\{server}\\{share}"), rest_start)
+        };
+
+        if let Some(rest) = strip(path, r"\\?\UNC\") {
+            let (prefix, start) = unc(&rest);
+            return (prefix, &path[path.len() - rest.len() + start..]);
+        }
+        let path = match strip(path, r"\\?\") {
+            Some(rest) => &path[path.len() - rest.len()..],
+            None => path,
+        };
+        if let Some(rest) = strip(path, r"\\") {
+            let (prefix, start) = unc(&rest);
+            return (prefix, &path[2 + start..]);
+        }
+
+        let bytes = path.as_bytes();
+        if bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' {
+            return (path[..2].to_ascii_uppercase(), &path[2..]);
+        }
+        (String::new(), path)
+    }
+
+    fn format(self, prefix: &str, components: &[&str]) -> String {
+        let joined = components.join(self.separator());
+        match (self, prefix) {
+            (_, "") => joined,
+            (Self::Unix, _) => format!("{prefix}{joined}"),
+            (Self::Windows, _) => format!("{prefix}\\{joined}"),
+        }
+    }
+}
+
+/// Get the lexically normalized `path`, and `path` relative to `base` if both have the
+/// same prefix.
+fn lexical_relative(path: &str, base: &str, style: PathStyle) -> (String, Option<String>) {
+    let (prefix, components) = style.parse(path);
+    let (base_prefix, base_components) = style.parse(base);
+    let normalized = style.format(&prefix, &components);
+
+    if !style.eq(&prefix, &base_prefix) || base_components.contains(&"..") {
+        return (normalized, None);
+    }
+
+    let common = components
+        .iter()
+        .zip(&base_components)
+        .take_while(|(a, b)| style.eq(a, b))
+        .count();
+    let mut relative = vec![".."; base_components.len() - common];
+    relative.extend(&components[common..]);
+
+    let relative = if relative.is_empty() {
+        ".".to_owned()
+    } else {
+        relative.join(style.separator())
+    };
+    (normalized, Some(relative))
+}
+
+/// Get a regex matching the absolute directory `dir` and all paths below it.
 ///
-/// The contained [`String`] is is the lossy conversion of the original.
+/// Windows paths are matched ASCII case-insensitively and with `\` or `/` separators.
+/// This doesn't use `(?i)`, which requires the `unicode-case` feature of `regex`.
+#[cfg(feature = "bindgen")]
+pub(crate) fn path_prefix_regex(dir: &str, style: PathStyle) -> String {
+    let escape = |s: &str| {
+        s.chars().fold(String::new(), |mut escaped, c| {
+            if "\\.+*?()|[]{}^$#&-~".contains(c) {
+                escaped.push('\\');
+                escaped.push(c);
+            } else if style == PathStyle::Windows && c.is_ascii_alphabetic() {
+                escaped.extend(['[', c.to_ascii_lowercase(), c.to_ascii_uppercase(), ']']);
+            } else {
+                escaped.push(c);
+            }
+            escaped
+        })
+    };
+
+    let (prefix, components) = style.parse(dir);
+    let separator = match style {
+        PathStyle::Unix => "/",
+        PathStyle::Windows => r"[\\/]",
+    };
+    let prefix = match style {
+        PathStyle::Unix => escape(&prefix),
+        // The separators of UNC prefixes (`\\server\share`).
+        PathStyle::Windows => prefix
+            .split('\\')
+            .map(escape)
+            .collect::<Vec<_>>()
+            .join(separator),
+    };
+    let components = components
+        .iter()
+        .map(|c| escape(c))
+        .collect::<Vec<_>>()
+        .join(separator);
+
+    match (style, components.is_empty()) {
+        (_, true) => format!("{prefix}.*"),
+        (PathStyle::Unix, false) => format!("{prefix}{components}(?:/.*)?"),
+        (PathStyle::Windows, false) => {
+            format!("{prefix}{separator}{components}(?:{separator}.*)?")
+        }
+    }
+}
+
+/// Error when converting from [`OsStr`] to [`String`] fails.
 #[derive(Debug, thiserror::Error)]
-#[error("failed to convert OsStr '{0}' to String, invalid utf-8")]
-pub struct Utf8ConvError(pub String);
+pub struct Utf8ConvError {
+    /// The lossy conversion of the original.
+    pub value: String,
+    /// What the original is (e.g. the name of an environment variable), if known.
+    pub context: Option<String>,
+}
+
+impl Display for Utf8ConvError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.context {
+            Some(context) => write!(
+                f,
+                "failed to convert {context} '{}' to String, invalid utf-8",
+                self.value
+            ),
+            None => write!(
+                f,
+                "failed to convert OsStr '{}' to String, invalid utf-8",
+                self.value
+            ),
+        }
+    }
+}
 
 /// Extension trait for fallibly converting [`OsStr`] to [`str`].
 pub trait OsStrExt: AsRef<OsStr> {
     /// Try to convert this [`OsStr`] into a string.
     fn try_to_str(&self) -> Result<&str, Utf8ConvError> {
-        match self.as_ref().to_str() {
-            Some(s) => Ok(s),
-            _ => Err(Utf8ConvError(self.as_ref().to_string_lossy().to_string())),
+        self.as_ref().to_str().ok_or_else(|| Utf8ConvError {
+            value: self.as_ref().to_string_lossy().into_owned(),
+            context: None,
+        })
+    }
+
+    /// Try to convert this [`OsStr`] into a string, describing it as `context` (e.g.
+    /// `` "variable `PATH`" ``) in the error.
+    fn try_to_str_with_context(&self, context: impl Display) -> Result<&str, Utf8ConvError> {
+        self.try_to_str().map_err(|e| Utf8ConvError {
+            context: Some(context.to_string()),
+            ..e
+        })
+    }
+
+    /// Convert this [`OsStr`] into a string, replacing invalid utf-8 with
+    /// [`U+FFFD`](std::char::REPLACEMENT_CHARACTER).
+    ///
+    /// A warning is logged (and printed as a cargo warning in build scripts) the first
+    /// time a distinct value is converted lossily.
+    fn to_str_lossy_warn(&self) -> Cow<'_, str> {
+        static WARNED: Mutex<Option<HashSet<OsString>>> = Mutex::new(None);
+
+        let value = self.as_ref().to_string_lossy();
+        if let Cow::Owned(_) = value {
+            let mut warned = WARNED.lock().unwrap();
+            if warned
+                .get_or_insert_with(HashSet::new)
+                .insert(self.as_ref().to_owned())
+            {
+                crate::logging::warning!(
+                    "'{value}' is not valid utf-8, replaced the invalid parts"
+                );
+            }
         }
+        value
+    }
+
+    /// Split this [`OsStr`] at the platform's path separator (like the `PATH`
+    /// environment variable, see [`env::split_paths`]), skipping empty paths.
+    fn split_paths_ext(&self) -> Vec<PathBuf> {
+        env::split_paths(self.as_ref())
+            .filter(|path| !path.as_os_str().is_empty())
+            .collect()
     }
 }
 
@@ -87,6 +394,29 @@ impl OsStrExt for std::ffi::OsString {}
 impl OsStrExt for Path {}
 impl OsStrExt for PathBuf {}
 
+/// Extension trait for joining iterators of [`OsStr`]s.
+pub trait OsStrJoinExt: Iterator
+where
+    Self::Item: AsRef<OsStr>,
+{
+    /// Join all items with `separator` in between.
+    fn join_os(self, separator: impl AsRef<OsStr>) -> OsString
+    where
+        Self: Sized,
+    {
+        let mut joined = OsString::new();
+        for (i, item) in self.enumerate() {
+            if i > 0 {
+                joined.push(separator.as_ref());
+            }
+            joined.push(item);
+        }
+        joined
+    }
+}
+
+impl<I: Iterator> OsStrJoinExt for I where I::Item: AsRef<OsStr> {}
+
 /// Download the file at `url` to `writer`.
 ///
 /// Fails if the response status is not `200` (`OK`).
@@ -106,3 +436,307 @@ pub fn download_file_to(url: &str, writer: &mut impl std::io::Write) -> Result<(
     std::io::copy(&mut reader, writer)?;
     Ok(())
 }
+
+/// A 64-bit FNV-1a [`Hasher`](std::hash::Hasher) whose hashes, unlike the ones of
+/// [`DefaultHasher`](std::collections::hash_map::DefaultHasher), don't change between
+/// Rust versions, so that they can be persisted (e.g. in file names).
+///
+/// Integers are hashed in little-endian byte order, so the hashes are also the same on
+/// all platforms. Hash paths as strings, as the [`Hash`](std::hash::Hash) impl of
+/// [`Path`] is not stable.
+#[derive(Clone, Debug)]
+pub struct StableHasher(u64);
+
+impl StableHasher {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    pub fn new() -> Self {
+        Self(Self::OFFSET_BASIS)
+    }
+
+    /// Get the hash as 16 lowercase hex digits.
+    pub fn finish_hex(&self) -> String {
+        use std::hash::Hasher;
+
+        format!("{:016x}", self.finish())
+    }
+}
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::hash::Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(Self::PRIME);
+        }
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_i16(&mut self, i: i16) {
+        self.write_u16(i as u16);
+    }
+
+    fn write_i32(&mut self, i: i32) {
+        self.write_u32(i as u32);
+    }
+
+    fn write_i64(&mut self, i: i64) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_i128(&mut self, i: i128) {
+        self.write_u128(i as u128);
+    }
+
+    fn write_isize(&mut self, i: isize) {
+        self.write_u64(i as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An [`OsString`] of `prefix` followed by an invalid code unit.
+    #[cfg(any(unix, windows))]
+    fn invalid_os_string(prefix: &str) -> OsString {
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStringExt;
+            let mut bytes = prefix.as_bytes().to_vec();
+            bytes.push(0xFF);
+            OsString::from_vec(bytes)
+        }
+        #[cfg(windows)]
+        {
+            use std::os::windows::ffi::OsStringExt;
+            let mut wide = prefix.encode_utf16().collect::<Vec<_>>();
+            wide.push(0xD800);
+            OsString::from_wide(&wide)
+        }
+    }
+
+    #[test]
+    #[cfg(any(unix, windows))]
+    fn test_try_to_str() {
+        let invalid = invalid_os_string("foo");
+        assert_eq!(OsStr::new("foo").try_to_str().unwrap(), "foo");
+        assert_eq!(
+            invalid.try_to_str().unwrap_err().to_string(),
+            "failed to convert OsStr 'foo\u{FFFD}' to String, invalid utf-8"
+        );
+        assert_eq!(
+            invalid
+                .try_to_str_with_context("variable `FOO`")
+                .unwrap_err()
+                .to_string(),
+            "failed to convert variable `FOO` 'foo\u{FFFD}' to String, invalid utf-8"
+        );
+
+        assert_eq!(OsStr::new("foo").to_str_lossy_warn(), "foo");
+        assert_eq!(invalid.to_str_lossy_warn(), "foo\u{FFFD}");
+    }
+
+    #[test]
+    #[cfg(any(unix, windows))]
+    fn test_split_paths_ext() {
+        let sep = if cfg!(windows) { ";" } else { ":" };
+        let mut paths = OsString::from(format!("/a{sep}{sep}/b c{sep}"));
+        paths.push(invalid_os_string("/d"));
+        assert_eq!(
+            paths.split_paths_ext(),
+            [
+                PathBuf::from("/a"),
+                PathBuf::from("/b c"),
+                PathBuf::from(invalid_os_string("/d"))
+            ]
+        );
+        assert!(OsStr::new("").split_paths_ext().is_empty());
+    }
+
+    #[test]
+    #[cfg(any(unix, windows))]
+    fn test_join_os() {
+        let joined = [OsString::from("a"), invalid_os_string("b")]
+            .iter()
+            .join_os(" ");
+        let mut expected = OsString::from("a ");
+        expected.push(invalid_os_string("b"));
+        assert_eq!(joined, expected);
+        assert_eq!(std::iter::empty::<&OsStr>().join_os(","), OsString::new());
+    }
+
+    fn relative(path: &str, base: &str, style: PathStyle) -> Option<String> {
+        lexical_relative(path, base, style).1
+    }
+
+    #[test]
+    fn test_lexical_relative_unix() {
+        let unix = PathStyle::Unix;
+        assert_eq!(relative("/a/b/c", "/a/d", unix).as_deref(), Some("../b/c"));
+        assert_eq!(relative("/a/b", "/a/b/", unix).as_deref(), Some("."));
+        assert_eq!(relative("/a/./b/../c", "/a", unix).as_deref(), Some("c"));
+        assert_eq!(relative("/a", "/a/b/c", unix).as_deref(), Some("../.."));
+        assert_eq!(relative("/A/b", "/a", unix).as_deref(), Some("../A/b"));
+        assert_eq!(relative("/a\\b", "/a", unix).as_deref(), Some("../a\\b"));
+        assert_eq!(relative("/../a", "/", unix).as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn test_lexical_relative_windows() {
+        let windows = PathStyle::Windows;
+
+        // Different drives.
+        assert_eq!(
+            lexical_relative(r"C:\a\b", r"D:\a", windows),
+            (r"C:\a\b".to_owned(), None)
+        );
+        // Case-insensitive drives and components.
+        assert_eq!(
+            relative(r"c:\Users\x\proj\src\main.rs", r"C:\users\X\proj", windows).as_deref(),
+            Some(r"src\main.rs")
+        );
+        // Verbatim prefixes and mixed separators.
+        assert_eq!(
+            relative(r"\\?\C:\a\b", "C:/a/c", windows).as_deref(),
+            Some(r"..\b")
+        );
+        assert_eq!(
+            relative(r"C:/a\b/./c\..", r"\\?\c:\a", windows).as_deref(),
+            Some("b")
+        );
+        // UNC shares.
+        assert_eq!(
+            relative(r"\\server\share\a\b", r"\\?\UNC\server\share\a", windows).as_deref(),
+            Some("b")
+        );
+        assert_eq!(
+            relative("//server/share/a", r"\\SERVER\Share", windows).as_deref(),
+            Some("a")
+        );
+        assert_eq!(
+            lexical_relative(r"\\?\UNC\server\share\a\b", r"\\server\other\a", windows),
+            (r"\\server\share\a\b".to_owned(), None)
+        );
+        assert_eq!(relative(r"\\server\share\a", r"C:\a", windows), None);
+    }
+
+    #[test]
+    #[cfg(feature = "bindgen")]
+    fn test_path_prefix_regex() {
+        let unix = PathStyle::Unix;
+        assert_eq!(
+            path_prefix_regex("/opt/sdk-1.0/include/", unix),
+            r"/opt/sdk\-1\.0/include(?:/.*)?"
+        );
+        assert_eq!(path_prefix_regex("/", unix), "/.*");
+
+        let windows = PathStyle::Windows;
+        assert_eq!(
+            path_prefix_regex(r"\\?\c:\Sdk (x86)\v1", windows),
+            r"[cC]:[\\/][sS][dD][kK] \([xX]86\)[\\/][vV]1(?:[\\/].*)?"
+        );
+        assert_eq!(
+            path_prefix_regex(r"\\srv\c$\a", windows),
+            r"[\\/][\\/][sS][rR][vV][\\/][cC]\$[\\/][aA](?:[\\/].*)?"
+        );
+        assert_eq!(path_prefix_regex("D:/", windows), "[dD]:.*");
+
+        // Anchored like the regexes of bindgen.
+        #[cfg(feature = "regex")]
+        {
+            let matches = |regex: &str, path: &str| {
+                regex::Regex::new(&format!("^({regex})$"))
+                    .unwrap()
+                    .is_match(path)
+            };
+
+            let regex = path_prefix_regex("/opt/sdk-1.0/include", unix);
+            assert!(matches(&regex, "/opt/sdk-1.0/include"));
+            assert!(matches(&regex, "/opt/sdk-1.0/include/sys/types.h"));
+            assert!(!matches(&regex, "/opt/sdk-1.0/include2/a.h"));
+            assert!(!matches(&regex, "/opt/sdk-1x0/include/a.h"));
+
+            let regex = path_prefix_regex(r"C:\Program Files (x86)\sdk", windows);
+            assert!(matches(&regex, r"C:\Program Files (x86)\sdk\include\a.h"));
+            assert!(matches(&regex, "c:/program files (x86)/SDK/include/a.h"));
+            assert!(!matches(&regex, r"C:\Program Files (x86)\sdk2\a.h"));
+            assert!(!matches(&regex, r"D:\Program Files (x86)\sdk\a.h"));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_path_relative_to() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir.join("real")).unwrap();
+        std::fs::write(dir.join("real/target.txt"), "").unwrap();
+        std::os::unix::fs::symlink(dir.join("real"), dir.join("dir-link")).unwrap();
+        std::os::unix::fs::symlink(dir.join("real/target.txt"), dir.join("file-link")).unwrap();
+
+        assert_eq!(
+            path_relative_to(dir.join("file-link"), dir),
+            Path::new("file-link")
+        );
+        assert_eq!(
+            path_relative_to(dir.join("real/target.txt"), dir.join("dir-link")),
+            Path::new("target.txt")
+        );
+        assert_eq!(
+            path_relative_to(dir.join("dir-link/../missing/x"), dir.join("real")),
+            Path::new("../missing/x")
+        );
+        assert_eq!(path_relative_to(dir, dir), Path::new("."));
+
+        assert_eq!(shortest_display(dir.join("real"), dir), "real");
+        assert_eq!(shortest_display("/", dir), "/");
+    }
+
+    #[test]
+    fn test_stable_hasher() {
+        use std::hash::{Hash, Hasher};
+
+        let hash = |bytes: &[u8]| {
+            let mut hasher = StableHasher::new();
+            hasher.write(bytes);
+            hasher.finish()
+        };
+        // The test vectors of FNV-1a.
+        assert_eq!(hash(b""), 0xcbf29ce484222325);
+        assert_eq!(hash(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(hash(b"foobar"), 0x85944171f73967e8);
+
+        let mut hasher = StableHasher::new();
+        ("embuild", 1u32, vec![2usize]).hash(&mut hasher);
+        assert_eq!(hasher.finish_hex(), "72c8459e7925d4f4");
+    }
+}
@@ -0,0 +1,311 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+
+/// How often the progress of a download is logged.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A download of a file, optionally verified against its sha256 hash.
+///
+/// The file is downloaded to a `.part` file next to the destination, which is only
+/// renamed to the destination once it is complete (and its hash matches). If the
+/// download is interrupted, the next download of the same file resumes the `.part` file
+/// with an HTTP range request if the server supports it.
+#[derive(Clone)]
+pub struct Download {
+    url: String,
+    sha256: Option<String>,
+    agent: Option<ureq::Agent>,
+}
+
+impl Download {
+    /// Download the file at `url`.
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            sha256: None,
+            agent: None,
+        }
+    }
+
+    /// Verify the downloaded file against the hex `sha256` hash.
+    pub fn sha256(mut self, sha256: impl Into<String>) -> Self {
+        self.sha256 = Some(sha256.into());
+        self
+    }
+
+    /// Use `agent` (e.g. with a timeout) for the requests.
+    pub fn agent(mut self, agent: ureq::Agent) -> Self {
+        self.agent = Some(agent);
+        self
+    }
+
+    /// Download the file to `dest`.
+    ///
+    /// If `dest` already exists and matches the [hash](Download::sha256) it is used
+    /// without downloading it again. A `.part` file whose hash doesn't match after
+    /// downloading is deleted.
+    pub fn to_file(&self, dest: impl AsRef<Path>) -> Result<()> {
+        let dest = dest.as_ref();
+        if let Some(sha256) = &self.sha256 {
+            if dest.is_file() && sha256_file(dest)?.eq_ignore_ascii_case(sha256) {
+                log::debug!("Using already downloaded '{}'", dest.display());
+                return Ok(());
+            }
+        }
+
+        let part = part_file(dest);
+        self.download_part(&part)?;
+
+        if let Some(sha256) = &self.sha256 {
+            let actual = sha256_file(&part)?;
+            if !actual.eq_ignore_ascii_case(sha256) {
+                let _ = fs::remove_file(&part);
+                bail!(
+                    "checksum mismatch of '{}': expected sha256 {sha256}, got {actual}",
+                    self.url
+                );
+            }
+        }
+
+        fs::rename(&part, dest).with_context(|| {
+            anyhow!(
+                "could not rename '{}' to '{}'",
+                part.display(),
+                dest.display()
+            )
+        })
+    }
+
+    /// Download (or resume downloading) the file to `part`.
+    fn download_part(&self, part: &Path) -> Result<()> {
+        let agent = self.agent.clone().unwrap_or_else(ureq::agent);
+        let offset = fs::metadata(part).map(|m| m.len()).unwrap_or(0);
+
+        let mut request = agent.get(&self.url);
+        if offset > 0 {
+            log::debug!("Resuming download of '{}' at byte {offset}", self.url);
+            request = request.set("Range", &format!("bytes={offset}-"));
+        }
+
+        let response = match request.call() {
+            Ok(response) => response,
+            // The `.part` file is already complete (or bogus, which the hash reveals).
+            Err(ureq::Error::Status(416, _)) if offset > 0 => return Ok(()),
+            Err(e) => return Err(e).with_context(|| anyhow!("could not download '{}'", self.url)),
+        };
+
+        let (mut file, offset) = match response.status() {
+            206 if offset > 0 => (fs::OpenOptions::new().append(true).open(part)?, offset),
+            200 => (fs::File::create(part)?, 0),
+            status => bail!(
+                "server at url '{}' returned unexpected status {status}: {}",
+                self.url,
+                response.status_text()
+            ),
+        };
+        let total = response
+            .header("Content-Length")
+            .and_then(|len| len.parse::<u64>().ok())
+            .map(|len| len + offset);
+
+        let mut reader = response.into_reader();
+        let mut buf = vec![0; 64 * 1024];
+        let mut received = offset;
+        let mut last_progress = Instant::now();
+        loop {
+            let read = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    return Err(e).with_context(|| {
+                        anyhow!(
+                            "network error while downloading '{}' after {received} bytes",
+                            self.url
+                        )
+                    })
+                }
+            };
+            file.write_all(&buf[..read])?;
+            received += read as u64;
+
+            if last_progress.elapsed() >= PROGRESS_INTERVAL {
+                last_progress = Instant::now();
+                match total {
+                    Some(total) => {
+                        log::info!("Downloading '{}': {received}/{total} bytes", self.url)
+                    }
+                    None => log::info!("Downloading '{}': {received} bytes", self.url),
+                }
+            }
+        }
+
+        match total {
+            Some(total) if received < total => bail!(
+                "download of '{}' ended early (early eof): received {received} of {total} bytes",
+                self.url
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Get the `.part` file of `dest`.
+fn part_file(dest: &Path) -> PathBuf {
+    let mut file_name = dest.file_name().unwrap_or_default().to_owned();
+    file_name.push(".part");
+    dest.with_file_name(file_name)
+}
+
+/// Compute the lowercase hex sha256 hash of the file at `path`.
+pub fn sha256_file(path: impl AsRef<Path>) -> Result<String> {
+    let path = path.as_ref();
+    let mut file =
+        fs::File::open(path).with_context(|| anyhow!("could not open '{}'", path.display()))?;
+    let mut context = ring::digest::Context::new(&ring::digest::SHA256);
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        context.update(&buf[..read]);
+    }
+
+    Ok(context
+        .finish()
+        .as_ref()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufRead;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    const BODY: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+    const BODY_SHA256: &str = "74e7e5bb9d22d6db26bf76946d40fff3ea9f0346b884fd0694920fccfad15e33";
+
+    /// Serve `BODY` (with range requests) to every connection, recording the `Range`
+    /// header of every request and sending only the first `truncate` bytes of the body
+    /// if it is set.
+    fn serve(truncate: Option<usize>) -> (String, Arc<Mutex<Vec<Option<String>>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/file.bin", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let recorded = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut range = None;
+                for line in std::io::BufReader::new(&stream).lines() {
+                    let line = line.unwrap();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(": ") {
+                        if name.eq_ignore_ascii_case("range") {
+                            range = Some(value.to_owned());
+                        }
+                    }
+                }
+                recorded.lock().unwrap().push(range.clone());
+
+                let start = range
+                    .as_deref()
+                    .and_then(|r| r.strip_prefix("bytes="))
+                    .and_then(|r| r.strip_suffix('-'))
+                    .map(|r| r.parse::<usize>().unwrap());
+                let (status, body) = match start {
+                    Some(start) if start >= BODY.len() => ("416 Range Not Satisfiable", &[][..]),
+                    Some(start) => ("206 Partial Content", &BODY[start..]),
+                    None => ("200 OK", BODY),
+                };
+                let sent = &body[..truncate.unwrap_or(body.len()).min(body.len())];
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                );
+                let _ = stream.write_all(sent);
+            }
+        });
+
+        (url, requests)
+    }
+
+    #[test]
+    fn test_download() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let dest = dir.join("file.bin");
+        let (url, requests) = serve(None);
+
+        Download::new(&url)
+            .sha256(BODY_SHA256)
+            .to_file(&dest)
+            .unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), BODY);
+        assert!(!part_file(&dest).exists());
+
+        // Reused without downloading it again.
+        Download::new(&url)
+            .sha256(BODY_SHA256.to_uppercase())
+            .to_file(&dest)
+            .unwrap();
+        assert_eq!(requests.lock().unwrap().len(), 1);
+
+        let error = Download::new(&url)
+            .sha256("00")
+            .to_file(dir.join("other.bin"))
+            .unwrap_err();
+        let error = format!("{error:#}");
+        assert!(error.contains(&url), "{error}");
+        assert!(error.contains("expected sha256 00"), "{error}");
+        assert!(error.contains(BODY_SHA256), "{error}");
+        assert!(!dir.join("other.bin").exists());
+        assert!(!dir.join("other.bin.part").exists());
+    }
+
+    #[test]
+    fn test_resume() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let dest = dir.join("file.bin");
+
+        let (url, _) = serve(Some(10));
+        let error = Download::new(&url).to_file(&dest).unwrap_err();
+        assert!(
+            crate::cmd::is_network_error(&format!("{error:#}")),
+            "{error:#}"
+        );
+        assert_eq!(fs::read(part_file(&dest)).unwrap(), &BODY[..10]);
+
+        let (url, requests) = serve(None);
+        Download::new(&url)
+            .sha256(BODY_SHA256)
+            .to_file(&dest)
+            .unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), BODY);
+        assert_eq!(*requests.lock().unwrap(), [Some("bytes=10-".to_owned())]);
+
+        // A complete `.part` file is only verified.
+        fs::remove_file(&dest).unwrap();
+        fs::write(part_file(&dest), BODY).unwrap();
+        Download::new(&url)
+            .sha256(BODY_SHA256)
+            .to_file(&dest)
+            .unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), BODY);
+    }
+}
@@ -0,0 +1,98 @@
+//! Builds a crate whose build script uses pregenerated bindings and generates a
+//! PlatformIO project, and finds them with [`embuild::artifacts::Artifacts::discover`].
+
+#![cfg(all(feature = "bindgen", feature = "pio"))]
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use embuild::artifacts::Artifacts;
+
+const APP_BUILD_RS: &str = r##"
+fn main() -> anyhow::Result<()> {
+    embuild::bindgen::run_or_use_pregenerated(
+        bindgen::Builder::default(),
+        "pregenerated.rs",
+        embuild::bindgen::PregenMode::Use,
+    )?;
+
+    let project_dir = std::path::PathBuf::from(std::env::var("OUT_DIR")?).join("project");
+    std::fs::create_dir_all(&project_dir)?;
+    embuild::pio::project::Builder::new(project_dir).regenerate(
+        &embuild::pio::Resolution {
+            board: "esp32dev".into(),
+            mcu: "ESP32".into(),
+            platform: "espressif32".into(),
+            frameworks: vec!["espidf".into()],
+            target: "xtensa-esp32-espidf".into(),
+        },
+    )?;
+    Ok(())
+}
+"##;
+
+fn write(path: impl AsRef<Path>, content: &str) {
+    let path = path.as_ref();
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, content).unwrap();
+}
+
+#[test]
+fn test_discover() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("artifacts-discovery");
+    let _ = fs::remove_dir_all(&dir);
+
+    let embuild = Path::new(env!("CARGO_MANIFEST_DIR")).display();
+    write(
+        dir.join("Cargo.toml"),
+        &format!(
+            r#"[package]
+name = "app"
+version = "0.1.0"
+edition = "2021"
+
+[build-dependencies]
+anyhow = "1"
+bindgen = "0.69"
+embuild = {{ path = '{embuild}', features = ["bindgen", "pio"] }}
+
+[workspace]
+"#
+        ),
+    );
+    write(dir.join("build.rs"), APP_BUILD_RS);
+    write(dir.join("pregenerated.rs"), "pub const VALUE: u32 = 42;\n");
+    write(dir.join("src/main.rs"), "fn main() {}\n");
+    // Resolve the same dependency versions as embuild itself, which are available
+    // offline.
+    let lock = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.lock");
+    if lock.exists() {
+        fs::copy(lock, dir.join("Cargo.lock")).unwrap();
+    }
+
+    let target_dir = dir.join("target");
+    let output = Command::new(env!("CARGO"))
+        .args(["build", "--offline", "--quiet"])
+        .current_dir(&dir)
+        .env("CARGO_TARGET_DIR", &target_dir)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let artifacts = Artifacts::discover(&target_dir, "app", "dev").unwrap();
+    assert_eq!(
+        artifacts.bindings_file().unwrap(),
+        dir.join("pregenerated.rs").canonicalize().unwrap()
+    );
+    let project_dir = artifacts.project_dir().unwrap();
+    assert!(project_dir.ends_with("out/project"));
+    assert!(project_dir.join("platformio.ini").is_file());
+    assert_eq!(artifacts.elf().unwrap(), target_dir.join("debug/app"));
+    assert!(artifacts.partition_table().is_err());
+    assert!(Artifacts::discover(&target_dir, "app", "release").is_err());
+}
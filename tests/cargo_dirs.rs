@@ -0,0 +1,88 @@
+//! Builds a member of a virtual workspace whose build script resolves the workspace and
+//! target directories with [`embuild::cargo::workspace_dir`] and
+//! [`embuild::cargo::target_dir`], with the default and a custom target directory.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const BUILD_RS: &str = r##"
+fn main() {
+    let dirs = format!(
+        "{}\n{}\n",
+        embuild::cargo::workspace_dir().unwrap().display(),
+        embuild::cargo::target_dir().unwrap().display(),
+    );
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    std::fs::write(std::path::Path::new(&manifest_dir).join("dirs.txt"), dirs).unwrap();
+}
+"##;
+
+fn write(path: impl AsRef<Path>, content: &str) {
+    let path = path.as_ref();
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, content).unwrap();
+}
+
+/// Build the workspace in `ws` with the extra cargo `args` and return the workspace and
+/// target directories resolved by the build script.
+fn build(ws: &Path, args: &[&str]) -> (PathBuf, PathBuf) {
+    let output = Command::new(env!("CARGO"))
+        .args(["build", "--offline", "--quiet"])
+        .args(args)
+        .current_dir(ws.join("app"))
+        .env_remove("CARGO_TARGET_DIR")
+        .env_remove("CARGO_WORKSPACE_DIR")
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let dirs = fs::read_to_string(ws.join("app/dirs.txt")).unwrap();
+    let mut lines = dirs.lines().map(PathBuf::from);
+    (lines.next().unwrap(), lines.next().unwrap())
+}
+
+#[test]
+fn test_workspace_and_target_dir() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("cargo-dirs");
+    let _ = fs::remove_dir_all(&dir);
+    let ws = dir.join("ws");
+
+    let embuild = Path::new(env!("CARGO_MANIFEST_DIR")).display();
+    write(ws.join("Cargo.toml"), "[workspace]\nmembers = [\"app\"]\n");
+    write(
+        ws.join("app/Cargo.toml"),
+        &format!(
+            r#"[package]
+name = "app"
+version = "0.1.0"
+edition = "2021"
+
+[build-dependencies]
+embuild = {{ path = '{embuild}' }}
+"#
+        ),
+    );
+    write(ws.join("app/build.rs"), BUILD_RS);
+    write(ws.join("app/src/main.rs"), "fn main() {}\n");
+    // Resolve the same dependency versions as embuild itself, which are available
+    // offline.
+    let lock = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.lock");
+    if lock.exists() {
+        fs::copy(lock, ws.join("Cargo.lock")).unwrap();
+    }
+
+    let ws = ws.canonicalize().unwrap();
+    assert_eq!(build(&ws, &[]), (ws.clone(), ws.join("target")));
+
+    // A target directory outside of the workspace.
+    let target_dir = dir.canonicalize().unwrap().join("x");
+    assert_eq!(
+        build(&ws, &["--target-dir", target_dir.to_str().unwrap()]),
+        (ws, target_dir)
+    );
+}
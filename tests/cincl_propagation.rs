@@ -0,0 +1,141 @@
+//! Builds a dependency that propagates its C include args and a dependent that
+//! generates bindings for an extra header with them using
+//! [`embuild::build::CInclArgs::from_dep`].
+
+#![cfg(feature = "bindgen")]
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+const DEP_BUILD_RS: &str = r##"
+fn main() -> anyhow::Result<()> {
+    let include_dir = std::path::Path::new(&std::env::var("OUT_DIR")?).join("my include");
+    std::fs::create_dir_all(&include_dir)?;
+    std::fs::write(
+        include_dir.join("extra.h"),
+        "#ifdef EXTRA_ENABLED\n#define EXTRA_VALUE EXTRA_BASE + 1\n#endif\n",
+    )?;
+
+    embuild::build::CInclArgs {
+        include_dirs: vec![include_dir],
+        defines: vec![
+            ("EXTRA_ENABLED".into(), None),
+            ("EXTRA_BASE".into(), Some("41".into())),
+        ],
+    }
+    .propagate()
+}
+"##;
+
+const APP_BUILD_RS: &str = r##"
+fn main() -> anyhow::Result<()> {
+    let args = embuild::build::CInclArgs::from_dep("incl_dep")?;
+    let out_dir = std::path::PathBuf::from(std::env::var("OUT_DIR")?);
+    let factory = args.extend_factory(embuild::bindgen::Factory::new().with_sysroot(&out_dir));
+
+    let builder = factory
+        .builder()?
+        .header_contents("app.h", "#include <extra.h>\nenum app { APP_VALUE = EXTRA_VALUE };\n");
+    std::fs::write(
+        out_dir.join("flags.txt"),
+        builder.command_line_flags().join("\n"),
+    )?;
+    let bindings = builder.generate()?;
+    bindings.write_to_file(out_dir.join("bindings.rs"))?;
+    Ok(())
+}
+"##;
+
+const APP_MAIN_RS: &str = r#"
+#![allow(non_camel_case_types, non_upper_case_globals)]
+include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
+
+fn main() {}
+"#;
+
+fn write(path: impl AsRef<Path>, content: &str) {
+    let path = path.as_ref();
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, content).unwrap();
+}
+
+fn manifest(name: &str, links: Option<&str>, deps: &str) -> String {
+    let embuild = Path::new(env!("CARGO_MANIFEST_DIR")).display();
+    let links = links.map_or(String::new(), |l| format!("links = \"{l}\"\n"));
+    format!(
+        r#"[package]
+name = "{name}"
+version = "0.1.0"
+edition = "2021"
+{links}
+[dependencies]
+{deps}
+
+[build-dependencies]
+anyhow = "1"
+embuild = {{ path = '{embuild}', features = ["bindgen"] }}
+"#
+    )
+}
+
+#[test]
+fn test_propagation() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("cincl-propagation");
+    let _ = fs::remove_dir_all(&dir);
+
+    write(
+        dir.join("Cargo.toml"),
+        "[workspace]\nmembers = [\"dep\", \"app\"]\nresolver = \"2\"\n",
+    );
+    write(
+        dir.join("dep/Cargo.toml"),
+        &manifest("dep", Some("incl_dep"), ""),
+    );
+    write(dir.join("dep/build.rs"), DEP_BUILD_RS);
+    write(dir.join("dep/src/lib.rs"), "");
+    write(
+        dir.join("app/Cargo.toml"),
+        &manifest("app", None, "dep = { path = \"../dep\" }"),
+    );
+    write(dir.join("app/build.rs"), APP_BUILD_RS);
+    write(dir.join("app/src/main.rs"), APP_MAIN_RS);
+    // Resolve the same dependency versions as embuild itself, which are available
+    // offline.
+    let lock = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.lock");
+    if lock.exists() {
+        fs::copy(lock, dir.join("Cargo.lock")).unwrap();
+    }
+
+    let output = Command::new(env!("CARGO"))
+        .args(["build", "--offline", "--quiet"])
+        .current_dir(&dir)
+        .env("CARGO_TARGET_DIR", dir.join("target"))
+        .output()
+        .unwrap();
+    let out_dir = |file: &str| {
+        fs::read_dir(dir.join("target/debug/build"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path().join("out").join(file))
+            .find(|file| file.exists())
+    };
+
+    // The propagated args arrive unchanged even if bindgen can't run.
+    let flags = fs::read_to_string(out_dir("flags.txt").unwrap()).unwrap();
+    let flags = flags.lines().collect::<Vec<_>>();
+    assert!(flags.contains(&"-DEXTRA_ENABLED"));
+    assert!(flags.contains(&"-DEXTRA_BASE=41"));
+    assert!(flags
+        .iter()
+        .any(|flag| flag.starts_with("-isystem") && flag.ends_with("my include")));
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if stderr.contains("Unable to find libclang") {
+        eprintln!("Skipping test: libclang not found");
+        return;
+    }
+    assert!(output.status.success(), "{stderr}");
+
+    let bindings = fs::read_to_string(out_dir("bindings.rs").unwrap()).unwrap();
+    assert!(bindings.contains("pub const app_APP_VALUE: app = 42;"));
+}
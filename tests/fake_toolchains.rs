@@ -0,0 +1,347 @@
+//! Exercises the toolchain probing of embuild against the fake toolchains and fixtures
+//! of [`embuild::test_support`].
+
+#![cfg(all(feature = "test-support", feature = "bindgen"))]
+
+use std::path::Path;
+
+use embuild::bindgen::{Factory, Preflight, PreflightCheck};
+use embuild::test_support::{FakeTool, FakeToolchain, Response};
+
+/// The resolved clang args of `factory` (for C++ if `cpp`) for the host.
+fn clang_args(factory: Factory, cpp: bool) -> Vec<String> {
+    factory.with_host_target().resolve(cpp).unwrap().clang_args
+}
+
+fn include_arg(dir: &Path) -> String {
+    format!("-I{}", dir.display())
+}
+
+#[test]
+fn test_sysroot_from_gcc_linker() {
+    let toolchain = FakeToolchain::new().unwrap();
+    let sysroot = toolchain.sysroot("xtensa-esp32-elf", &[]).unwrap();
+    let gcc = toolchain.gcc("xtensa-esp32-elf", &sysroot).unwrap();
+
+    let resolved = Factory::new()
+        .with_linker(&gcc)
+        .with_host_target()
+        .resolve(false)
+        .unwrap();
+    assert_eq!(resolved.sysroot, sysroot);
+    assert!(resolved
+        .clang_args
+        .contains(&format!("--sysroot={}", sysroot.display())));
+
+    // gcc doesn't print the sysroot, so the linker is asked instead.
+    assert!(toolchain.calls("xtensa-esp32-elf-gcc").is_empty());
+    assert_eq!(toolchain.calls("xtensa-esp32-elf-ld"), ["--print-sysroot"]);
+}
+
+#[test]
+fn test_sysroot_from_failing_linker() {
+    let toolchain = FakeToolchain::new().unwrap();
+    let ld = toolchain
+        .tool(
+            "riscv32-esp-elf-ld",
+            FakeTool::new().otherwise(Response::failure(1, "ld: unrecognized option\n")),
+        )
+        .unwrap();
+
+    let report = Factory::new()
+        .with_linker(&ld)
+        .run_preflight(false, &Preflight::new().skip(PreflightCheck::ClangVersion));
+    assert_eq!(report.sysroot, None);
+    assert_eq!(report.problems.len(), 1);
+    assert_eq!(report.problems[0].check, PreflightCheck::Sysroot);
+    assert!(report.problems[0]
+        .message
+        .starts_with("Could not determine sysroot from linker"));
+}
+
+#[test]
+fn test_cpp_includes_newest_version() {
+    let toolchain = FakeToolchain::new().unwrap();
+    let sysroot = toolchain
+        .sysroot("xtensa-esp32-elf", &["8.4.0", "12.2.0", "11.1.0"])
+        .unwrap();
+
+    let args = clang_args(Factory::new().with_sysroot(&sysroot), true);
+    let cpp_dir = sysroot.join("include").join("c++").join("12.2.0");
+    let cpp_args = args
+        .iter()
+        .filter(|arg| arg.starts_with("-I") && arg.contains("c++"))
+        .cloned()
+        .collect::<Vec<_>>();
+    assert_eq!(
+        cpp_args,
+        [
+            include_arg(&cpp_dir),
+            include_arg(&cpp_dir.join("backward")),
+            include_arg(&cpp_dir.join("xtensa-esp32-elf")),
+        ]
+    );
+
+    // No C++ includes for C bindings.
+    let args = clang_args(Factory::new().with_sysroot(&sysroot), false);
+    assert!(!args
+        .iter()
+        .any(|arg| arg.starts_with("-I") && arg.contains("c++")));
+}
+
+#[cfg(feature = "cmake")]
+#[test]
+fn test_cmake_file_api_replies() {
+    use embuild::cmake::file_api::codemodel::target::Type;
+    use embuild::cmake::file_api::codemodel::Language;
+    use embuild::cmake::file_api::{ObjKind, Query};
+    use embuild::test_support::CmakeReplies;
+
+    let toolchain = FakeToolchain::new().unwrap();
+    let sysroot = toolchain.sysroot("xtensa-esp32-elf", &[]).unwrap();
+    let gcc = toolchain.gcc("xtensa-esp32-elf", &sysroot).unwrap();
+    let source_dir = toolchain.dir().join("project");
+    let build_dir = source_dir.join("build");
+
+    let query = Query::builder(&build_dir, "embuild")
+        .kinds([ObjKind::Codemodel, ObjKind::Toolchains])
+        .write()
+        .unwrap();
+    CmakeReplies::new(&source_dir, &build_dir)
+        .compiler(&gcc)
+        .include(source_dir.join("main"))
+        .define("ESP_PLATFORM")
+        .define("IDF_VER=\"v5.1\"")
+        .compile_arg("-mlongcalls")
+        .compile_arg("-std=gnu17")
+        .sysroot(&sysroot)
+        .write("embuild")
+        .unwrap();
+
+    let replies = query.get_replies().unwrap();
+    assert_eq!(replies.cmake.version.to_string(), "3.24.0");
+
+    let codemodel = replies
+        .get_kind(ObjKind::Codemodel)
+        .unwrap()
+        .codemodel()
+        .unwrap();
+    assert_eq!(codemodel.paths.build, build_dir);
+    let dependencies = codemodel.target_dependencies("app").unwrap();
+    assert_eq!(dependencies.len(), 1);
+    assert_eq!(dependencies[0].name, "main");
+    assert_eq!(dependencies[0].target_type, Type::StaticLibrary);
+
+    let toolchains = replies
+        .get_kind(ObjKind::Toolchains)
+        .unwrap()
+        .toolchains()
+        .unwrap();
+    assert_eq!(
+        toolchains
+            .get(Language::C)
+            .unwrap()
+            .compiler
+            .path
+            .as_deref(),
+        Some(gcc.as_path())
+    );
+
+    let compile_group = &dependencies[0].compile_groups[0];
+    let args = clang_args(Factory::from_cmake(compile_group).unwrap(), false);
+    assert!(args.contains(&"-DESP_PLATFORM".to_owned()));
+    assert!(args.contains(&"-DIDF_VER=\"v5.1\"".to_owned()));
+    assert!(args.contains(&include_arg(&source_dir.join("main"))));
+    // Only the compile args understood by clang are passed on.
+    assert!(args.contains(&"-std=gnu17".to_owned()));
+    assert!(!args.contains(&"-mlongcalls".to_owned()));
+    assert!(args.contains(&format!("--sysroot={}", sysroot.display())));
+    // The sysroot of the compile group is used, the linker is not asked.
+    assert!(toolchain.calls("xtensa-esp32-elf-ld").is_empty());
+}
+
+#[cfg(feature = "pio")]
+#[test]
+fn test_scons_dump() {
+    use embuild::pio::project::SconsVariables;
+    use embuild::test_support::{esp32_scons_variables, write_scons_dump};
+
+    let toolchain = FakeToolchain::new().unwrap();
+    let sysroot = toolchain.sysroot("xtensa-esp32-elf", &[]).unwrap();
+    toolchain.gcc("xtensa-esp32-elf", &sysroot).unwrap();
+    let project_dir = toolchain.dir().join("project");
+    std::fs::create_dir_all(&project_dir).unwrap();
+
+    write_scons_dump(
+        &project_dir,
+        &esp32_scons_variables(&project_dir, &toolchain.path_var().unwrap()),
+    )
+    .unwrap();
+    let vars = SconsVariables::from_dump(&project_dir).unwrap();
+    assert_eq!(vars.mcu, "esp32");
+    assert_eq!(
+        vars.defines(),
+        [
+            ("ESP_PLATFORM".to_owned(), None),
+            ("IDF_VER".to_owned(), Some("\"v5.1\"".to_owned())),
+            ("PLATFORMIO".to_owned(), Some("60110".to_owned())),
+        ]
+    );
+
+    // The linker is found in the `PATH` of the scons variables.
+    let resolved = Factory::from_scons_vars(&vars)
+        .unwrap()
+        .with_host_target()
+        .resolve(false)
+        .unwrap();
+    assert_eq!(resolved.sysroot, sysroot);
+    assert!(resolved
+        .clang_args
+        .contains(&include_arg(&project_dir.join("include"))));
+    assert_eq!(toolchain.calls("xtensa-esp32-elf-ld"), ["--print-sysroot"]);
+}
+
+#[cfg(all(feature = "cmake", feature = "compile-commands"))]
+#[test]
+fn test_compile_commands_from_cmake() {
+    use embuild::cmake::file_api::{ObjKind, Query};
+    use embuild::compile_commands::{entries_from_cmake, CompileCommands};
+    use embuild::test_support::CmakeReplies;
+
+    let toolchain = FakeToolchain::new().unwrap();
+    let sysroot = toolchain.sysroot("xtensa-esp32-elf", &[]).unwrap();
+    let gcc = toolchain.gcc("xtensa-esp32-elf", &sysroot).unwrap();
+    let source_dir = toolchain.dir().join("my project");
+    let build_dir = source_dir.join("build");
+
+    let query = Query::builder(&build_dir, "embuild")
+        .kinds([ObjKind::Codemodel, ObjKind::Toolchains])
+        .write()
+        .unwrap();
+    CmakeReplies::new(&source_dir, &build_dir)
+        .compiler(&gcc)
+        .include(source_dir.join("main"))
+        .define("IDF_VER=\"v5.1 dirty\"")
+        .compile_arg("-mlongcalls")
+        .sysroot(&sysroot)
+        .write("embuild")
+        .unwrap();
+    let replies = query.get_replies().unwrap();
+    let codemodel = replies
+        .get_kind(ObjKind::Codemodel)
+        .unwrap()
+        .codemodel()
+        .unwrap();
+    let toolchains = replies
+        .get_kind(ObjKind::Toolchains)
+        .unwrap()
+        .toolchains()
+        .unwrap();
+    let main = codemodel.target_dependencies("app").unwrap()[0];
+
+    let entries = entries_from_cmake(main, &codemodel.paths, Some(&toolchains)).unwrap();
+    let source = source_dir.join("main.c");
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].file, source);
+    assert_eq!(entries[0].directory, build_dir);
+    assert_eq!(
+        entries[0].arguments,
+        [
+            gcc.display().to_string(),
+            "-DIDF_VER=\"v5.1 dirty\"".to_owned(),
+            include_arg(&source_dir.join("main")),
+            format!("--sysroot={}", sysroot.display()),
+            "-mlongcalls".to_owned(),
+            "-c".to_owned(),
+            source.display().to_string(),
+        ]
+    );
+
+    // Writing twice doesn't duplicate the entry.
+    let file = toolchain.dir().join("compile_commands.json");
+    for _ in 0..2 {
+        CompileCommands::new()
+            .output(&file)
+            .entries(entries.clone())
+            .write()
+            .unwrap();
+    }
+    let written: Vec<serde_json::Value> =
+        serde_json::from_slice(&std::fs::read(&file).unwrap()).unwrap();
+    assert_eq!(written.len(), 1);
+    assert_eq!(written[0]["arguments"][1], "-DIDF_VER=\"v5.1 dirty\"");
+}
+
+#[cfg(feature = "compile-commands")]
+#[test]
+fn test_compile_commands_from_bindgen() {
+    use embuild::compile_commands::{entries_from_bindgen, CLANG};
+
+    let toolchain = FakeToolchain::new().unwrap();
+    let sysroot = toolchain.sysroot("xtensa-esp32-elf", &[]).unwrap();
+    let headers = [toolchain.dir().join("a.h"), toolchain.dir().join("b.h")];
+
+    let factory = || {
+        Factory::new()
+            .with_sysroot(&sysroot)
+            .with_clang_args(["-DFOO"])
+            .with_host_target()
+    };
+    let entries = entries_from_bindgen(factory(), false, &headers).unwrap();
+    let clang_args = clang_args(factory(), false);
+    assert_eq!(entries.len(), 2);
+    for (entry, header) in entries.iter().zip(&headers) {
+        assert_eq!(&entry.file, header);
+        assert_eq!(entry.arguments[0], CLANG);
+        assert_eq!(entry.arguments[1..=clang_args.len()], clang_args);
+        assert_eq!(entry.arguments.last(), Some(&header.display().to_string()));
+    }
+}
+
+#[cfg(feature = "size")]
+#[test]
+fn test_size_report_from_elf() {
+    use embuild::size::{find_size_tool, Budgets, Region, SizeReport};
+    use embuild::test_support::Response;
+
+    let toolchain = FakeToolchain::new().unwrap();
+    let elf = toolchain.dir().join("app.elf");
+    std::fs::write(&elf, "").unwrap();
+    let size = toolchain
+        .tool(
+            "xtensa-esp32-elf-size",
+            FakeTool::new().otherwise(Response::stdout(
+                "app.elf  :\nsection        size   addr\n.iram0.text    1000   0\n.dram0.bss     2000   0\n.flash.text    3000   0\nTotal          6000\n",
+            )),
+        )
+        .unwrap();
+    std::fs::write(
+        elf.with_extension("map"),
+        "Linker script and memory map\n\n.flash.text 0x400d0020 0xbb8\n .text.app_main 0x400d0020 0xbb8 /esp/libmain.a(main.c.obj)\n",
+    )
+    .unwrap();
+
+    let path = toolchain.path_var().unwrap();
+    assert_eq!(
+        find_size_tool("xtensa-esp32-espidf", Some(&path)).unwrap(),
+        size
+    );
+
+    let report = SizeReport::from_elf(&size, &elf).unwrap();
+    assert_eq!(
+        toolchain.calls("xtensa-esp32-elf-size"),
+        [format!("-A {}", elf.display())]
+    );
+    assert_eq!(
+        (report.iram, report.dram, report.flash_code),
+        (1000, 2000, 3000)
+    );
+    assert_eq!(report.per_archive["libmain.a"].flash_code, 3000);
+
+    let error = report
+        .check(&Budgets::new().limit(Region::Dram, 1500))
+        .unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("DRAM: 2000 bytes used, budget is 1500 bytes (500 bytes over)"));
+}
@@ -0,0 +1,12 @@
+#pragma once
+
+typedef enum {
+    ESP_LOG_NONE,
+    ESP_LOG_ERROR,
+    ESP_LOG_WARN,
+    ESP_LOG_INFO,
+} esp_log_level_t;
+
+extern esp_log_level_t esp_log_default_level;
+
+void esp_log_write(esp_log_level_t level, const char *tag, const char *format, ...);
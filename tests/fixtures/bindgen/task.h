@@ -0,0 +1,13 @@
+#pragma once
+
+#include "esp_err.h"
+
+struct tskTaskControlBlock;
+/** A task handle */
+typedef struct tskTaskControlBlock *TaskHandle_t;
+typedef void (*TaskFunction_t)(void *arg);
+
+/** Create a task */
+esp_err_t xTaskCreate(TaskFunction_t task, const char *name, unsigned int stack_depth,
+                      void *arg, unsigned int priority, TaskHandle_t *handle);
+void vTaskDelay(unsigned int ticks);
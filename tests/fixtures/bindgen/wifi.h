@@ -0,0 +1,33 @@
+#pragma once
+
+#include "esp_err.h"
+
+#define WIFI_MAX_SSID_LEN 32
+
+typedef enum {
+    WIFI_MODE_NULL,
+    WIFI_MODE_STA,
+    WIFI_MODE_AP,
+} wifi_mode_t;
+
+typedef union {
+    unsigned int ip4;
+    unsigned int ip6[4];
+} esp_ip_addr_t;
+
+typedef struct {
+    unsigned char ssid[WIFI_MAX_SSID_LEN];
+    wifi_mode_t mode;
+    unsigned int channel : 4;
+    unsigned int hidden : 1;
+    union {
+        esp_ip_addr_t addr;
+        unsigned char raw[16];
+    };
+    void (*on_event)(int event, void *data);
+} wifi_config_t;
+
+extern int wifi_channel;
+
+esp_err_t esp_wifi_set_mode(wifi_mode_t mode);
+esp_err_t esp_wifi_set_config(const wifi_config_t *config);
@@ -0,0 +1,9 @@
+// Generated by embuild's bingen, do not edit.
+
+/// The length of `FIRMWARE` in bytes.
+pub const FIRMWARE_LEN: usize = 20;
+
+pub static FIRMWARE: [u8; FIRMWARE_LEN] = [
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0xff, 0xff, 0xff, 0xff, 0x10, 0x11, 0x12, 0x13,
+    0x14, 0x15, 0x16, 0x17,
+];
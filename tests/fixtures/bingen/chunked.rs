@@ -0,0 +1,29 @@
+// Generated by embuild's bingen, do not edit.
+
+#[repr(C, align(4))]
+pub struct Aligned<T: ?Sized>(pub T);
+
+/// The length of `FIRMWARE` in bytes.
+pub const FIRMWARE_LEN: usize = 20;
+
+/// The length of the chunks of `FIRMWARE` (except the last one) in bytes.
+pub const FIRMWARE_CHUNK_SIZE: usize = 8;
+
+static FIRMWARE_CHUNK_0: Aligned<[u8; 8]> = Aligned([
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07,
+]);
+
+static FIRMWARE_CHUNK_1: Aligned<[u8; 8]> = Aligned([
+    0xff, 0xff, 0xff, 0xff, 0x10, 0x11, 0x12, 0x13,
+]);
+
+static FIRMWARE_CHUNK_2: Aligned<[u8; 4]> = Aligned([
+    0x14, 0x15, 0x16, 0x17,
+]);
+
+/// The chunks of `FIRMWARE` in order.
+pub static FIRMWARE_CHUNKS: [&[u8]; 3] = [
+    &FIRMWARE_CHUNK_0.0,
+    &FIRMWARE_CHUNK_1.0,
+    &FIRMWARE_CHUNK_2.0,
+];
@@ -0,0 +1,9 @@
+// Generated by embuild's bingen, do not edit.
+
+#[repr(C, align(16))]
+pub struct Aligned<T: ?Sized>(pub T);
+
+/// The length of `FIRMWARE` in bytes.
+pub const FIRMWARE_LEN: usize = 20;
+
+pub static FIRMWARE: Aligned<[u8; FIRMWARE_LEN]> = Aligned(*include_bytes!("include_bytes.bin"));
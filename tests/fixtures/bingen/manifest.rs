@@ -0,0 +1,25 @@
+// Generated by embuild's bingen, do not edit.
+
+#[repr(C, align(4))]
+pub struct Aligned<T: ?Sized>(pub T);
+
+/// The length of `_1ST_STAGE` in bytes.
+pub const _1ST_STAGE_LEN: usize = 20;
+
+pub static _1ST_STAGE: Aligned<[u8; _1ST_STAGE_LEN]> = Aligned([
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x00, 0x00, 0x00, 0x00, 0x10, 0x11, 0x12, 0x13,
+    0x14, 0x15, 0x16, 0x17,
+]);
+
+/// The length of `WEB_INDEX_HTML` in bytes.
+pub const WEB_INDEX_HTML_LEN: usize = 13;
+
+pub static WEB_INDEX_HTML: Aligned<[u8; WEB_INDEX_HTML_LEN]> = Aligned([
+    0x3c, 0x68, 0x74, 0x6d, 0x6c, 0x3e, 0x3c, 0x2f, 0x68, 0x74, 0x6d, 0x6c, 0x3e,
+]);
+
+/// The names and contents of all binaries, sorted by name.
+pub static MANIFEST: &[(&str, &[u8])] = &[
+    ("1st-stage", &_1ST_STAGE.0),
+    ("web/index.html", &WEB_INDEX_HTML.0),
+];
@@ -0,0 +1,96 @@
+#!/usr/bin/env python3
+"""Generate the archive fixtures of the `fs::extract` tests.
+
+Run in this directory: `python3 generate.py`.
+"""
+
+import io
+import random
+import stat
+import tarfile
+import zipfile
+
+ROOT = "tool-1.2.3"
+LONG_NAME = "share/" + "a-very-long-file-name-" * 5 + "end.txt"
+
+FILES = [
+    ("bin/tool", 0o755, b"#!/bin/sh\necho tool\n"),
+    ("share/doc/readme.txt", 0o644, b"tool 1.2.3\n"),
+    ("share/data.txt", 0o644, "".join(f"line {i}: {i * i % 9973}\n" for i in range(3000)).encode()),
+    ("share/random.bin", 0o600, random.Random(0).randbytes(3000)),
+    (LONG_NAME, 0o644, b"long\n"),
+]
+DIRS = ["bin", "share", "share/doc"]
+SYMLINKS = [("bin/tool-link", "tool"), ("share/bin", "../bin")]
+
+
+def tar_info(name, type, mode, size=0, linkname=""):
+    info = tarfile.TarInfo(name)
+    info.type = type
+    info.mode = mode
+    info.size = size
+    info.linkname = linkname
+    info.mtime = 1700000000
+    return info
+
+
+def write_tar(path, mode, format):
+    with tarfile.open(path, mode, format=format) as tar:
+        tar.addfile(tar_info(ROOT, tarfile.DIRTYPE, 0o755))
+        for dir in DIRS:
+            tar.addfile(tar_info(f"{ROOT}/{dir}", tarfile.DIRTYPE, 0o755))
+        for name, file_mode, data in FILES:
+            info = tar_info(f"{ROOT}/{name}", tarfile.REGTYPE, file_mode, len(data))
+            tar.addfile(info, io.BytesIO(data))
+        for name, target in SYMLINKS:
+            tar.addfile(tar_info(f"{ROOT}/{name}", tarfile.SYMTYPE, 0o777, linkname=target))
+        tar.addfile(
+            tar_info(f"{ROOT}/bin/tool-hardlink", tarfile.LNKTYPE, 0o755, linkname=f"{ROOT}/bin/tool")
+        )
+
+
+def zip_info(name, mode):
+    info = zipfile.ZipInfo(name, (2023, 11, 14, 22, 13, 20))
+    info.create_system = 3
+    info.external_attr = mode << 16
+    info.compress_type = zipfile.ZIP_DEFLATED
+    return info
+
+
+def write_zip(path):
+    with zipfile.ZipFile(path, "w", zipfile.ZIP_DEFLATED) as zip:
+        zip.writestr(zip_info(f"{ROOT}/", stat.S_IFDIR | 0o755), b"")
+        for dir in DIRS:
+            zip.writestr(zip_info(f"{ROOT}/{dir}/", stat.S_IFDIR | 0o755), b"")
+        for name, file_mode, data in FILES:
+            zip.writestr(zip_info(f"{ROOT}/{name}", stat.S_IFREG | file_mode), data)
+        for name, target in SYMLINKS:
+            info = zip_info(f"{ROOT}/{name}", stat.S_IFLNK | 0o777)
+            info.compress_type = zipfile.ZIP_STORED
+            zip.writestr(info, target.encode())
+
+
+def write_flat():
+    with tarfile.open("flat.tar", "w", format=tarfile.USTAR_FORMAT) as tar:
+        for name in ["a.txt", "b.txt"]:
+            data = name[0].encode() + b"\n"
+            tar.addfile(tar_info(name, tarfile.REGTYPE, 0o644, len(data)), io.BytesIO(data))
+
+
+def write_evil():
+    with tarfile.open("evil.tar.gz", "w:gz", format=tarfile.GNU_FORMAT) as tar:
+        tar.addfile(tar_info("ok.txt", tarfile.REGTYPE, 0o644, 3), io.BytesIO(b"ok\n"))
+        tar.addfile(tar_info("../../evil", tarfile.REGTYPE, 0o644, 5), io.BytesIO(b"evil\n"))
+    with tarfile.open("evil-link.tar.gz", "w:gz", format=tarfile.GNU_FORMAT) as tar:
+        tar.addfile(tar_info("dir", tarfile.DIRTYPE, 0o755))
+        tar.addfile(tar_info("dir/escape", tarfile.SYMTYPE, 0o777, linkname="../../outside"))
+    with zipfile.ZipFile("evil.zip", "w") as zip:
+        zip.writestr(zip_info("ok.txt", stat.S_IFREG | 0o644), b"ok\n")
+        zip.writestr(zip_info("../evil", stat.S_IFREG | 0o644), b"evil\n")
+
+
+write_tar("tool.tar.gz", "w:gz", tarfile.GNU_FORMAT)
+write_tar("tool.tar.xz", "w:xz", tarfile.PAX_FORMAT)
+write_zip("tool.zip")
+write_flat()
+write_evil()
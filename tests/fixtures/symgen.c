@@ -0,0 +1,30 @@
+// Source of `symgen.elf`, the fixture of the `symgen` tests. Rebuild with:
+//
+//   gcc -Os -nostdlib -static -no-pie -fno-asynchronous-unwind-tables \
+//       -Wl,--build-id=none -Wl,-Ttext=0x40080000 -Wl,-Tdata=0x3ffb0000 \
+//       -Wl,--defsym=esp_rom_printf=0x40007d54 \
+//       -Wl,--defsym=esp_rom_delay_us=0x40008534 \
+//       -Wl,--defsym=_ZN3foo3barEv=0x40090000 \
+//       -Wl,--defsym=_ZN3foo3barEi=0x40090010 \
+//       -Wl,--defsym=_ZNK3foo3bazEPKc=0x40090020 \
+//       -Wl,--defsym=_ZN4core3fmt5write17h0123456789abcdefE=0x40090030 \
+//       -Wl,--defsym=_RNvCs1234_7mycrate4main=0x40090040 \
+//       -o symgen.elf symgen.c && strip --strip-debug symgen.elf
+
+int counter = 1;
+int zeroed;
+const int table[4] = {1, 2, 3, 4};
+
+__attribute__((section(".iram0.text"), noinline)) int fast_handler(int x) {
+    return x + counter;
+}
+
+__attribute__((noinline)) int slow_handler(int x) {
+    return x * table[x & 3];
+}
+
+void _start(void) {
+    zeroed = fast_handler(slow_handler(counter));
+    for (;;) {
+    }
+}
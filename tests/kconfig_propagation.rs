@@ -0,0 +1,96 @@
+//! Builds a dependency that propagates kconfig cfgs and a dependent that consumes them
+//! with [`embuild::kconfig::CfgArgs::from_dep`].
+
+#![cfg(feature = "kconfig")]
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+const DEP_BUILD_RS: &str = r#"
+fn main() -> anyhow::Result<()> {
+    let sdkconfig = embuild::kconfig::Parsed::parse(
+        "CONFIG_BT_ENABLED=y\nCONFIG_FREERTOS_HZ=100\n# CONFIG_SPIRAM is not set\n",
+    );
+    embuild::kconfig::CfgArgs::new("dep", sdkconfig.into_entries())?.propagate()
+}
+"#;
+
+const APP_BUILD_RS: &str = r#"
+fn main() -> anyhow::Result<()> {
+    embuild::kconfig::CfgArgs::from_dep("kconfig_dep")?.emit()
+}
+"#;
+
+const APP_MAIN_RS: &str = r#"
+#![deny(unexpected_cfgs)]
+
+#[cfg(all(dep_bt_enabled, dep_freertos_hz = "100", not(dep_spiram)))]
+fn main() {}
+"#;
+
+fn write(path: impl AsRef<Path>, content: &str) {
+    let path = path.as_ref();
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, content).unwrap();
+}
+
+fn manifest(name: &str, links: Option<&str>, deps: &str) -> String {
+    let embuild = Path::new(env!("CARGO_MANIFEST_DIR")).display();
+    let links = links.map_or(String::new(), |l| format!("links = \"{l}\"\n"));
+    format!(
+        r#"[package]
+name = "{name}"
+version = "0.1.0"
+edition = "2021"
+{links}
+[dependencies]
+{deps}
+
+[build-dependencies]
+anyhow = "1"
+embuild = {{ path = '{embuild}', features = ["kconfig"] }}
+"#
+    )
+}
+
+#[test]
+fn test_propagation() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("kconfig-propagation");
+    let _ = fs::remove_dir_all(&dir);
+
+    write(
+        dir.join("Cargo.toml"),
+        "[workspace]\nmembers = [\"dep\", \"app\"]\nresolver = \"2\"\n",
+    );
+    write(
+        dir.join("dep/Cargo.toml"),
+        &manifest("dep", Some("kconfig_dep"), ""),
+    );
+    write(dir.join("dep/build.rs"), DEP_BUILD_RS);
+    write(dir.join("dep/src/lib.rs"), "");
+    write(
+        dir.join("app/Cargo.toml"),
+        &manifest("app", None, "dep = { path = \"../dep\" }"),
+    );
+    write(dir.join("app/build.rs"), APP_BUILD_RS);
+    write(dir.join("app/src/main.rs"), APP_MAIN_RS);
+    // Resolve the same dependency versions as embuild itself, which are available
+    // offline.
+    let lock = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.lock");
+    if lock.exists() {
+        fs::copy(lock, dir.join("Cargo.lock")).unwrap();
+    }
+
+    let output = Command::new(env!("CARGO"))
+        .args(["build", "--offline", "--quiet"])
+        .current_dir(&dir)
+        .env("CARGO_TARGET_DIR", dir.join("target"))
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}